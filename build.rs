@@ -65,6 +65,69 @@ fn compile_qml(dir: &str, qt_include_path: &str, qt_library_path: &str) {
     println!("cargo:rustc-link-lib=static:+whole-archive=qmlcache");
 }
 
+// Same as src/qt_gpu/compiled/compile_shaders.sh. The .qsb files are embedded in the resources, so they are rebuilt
+// whenever the GLSL sources change. `QSB` can point to the tool, otherwise it's taken from the Qt installation or PATH.
+// Each output has a `.hash` file next to it with the hash of the sources it was compiled from. Without qsb the checked in
+// files are used, but only if that hash still matches, otherwise the build fails
+fn compile_shaders(qt_library_path: &str) {
+    const MODELS: [&str; 5] = ["opencv_fisheye", "opencv_standard", "poly3", "poly5", "ptlens"];
+    let models_dir = "src/core/stabilization/distortion_models";
+    let compiled_dir = Path::new("src/qt_gpu/compiled");
+    println!("cargo:rerun-if-changed=src/qt_gpu/undistort.frag");
+    println!("cargo:rerun-if-changed=src/qt_gpu/texture.vert");
+    println!("cargo:rerun-if-changed={}", models_dir);
+    println!("cargo:rerun-if-env-changed=QSB");
+
+    // FNV-1a, stable across toolchains unlike DefaultHasher
+    let hash = |data: &[u8]| data.iter().fold(0xcbf29ce484222325u64, |h, x| (h ^ *x as u64).wrapping_mul(0x100000001b3));
+    let mut outputs = Vec::new(); // (output, sources)
+    for model in MODELS {
+        outputs.push((format!("undistort_{}.frag.qsb", model), vec![
+            format!("{}/gopro_superview.glsl", models_dir),
+            format!("{}/{}.glsl", models_dir, model),
+            "src/qt_gpu/undistort.frag".to_string()
+        ]));
+    }
+    outputs.push(("texture.vert.qsb".to_string(), vec!["src/qt_gpu/texture.vert".to_string()]));
+
+    let qt_bin = Path::new(qt_library_path).parent().unwrap().join("bin");
+    let qsb = env::var("QSB").ok()
+        .or_else(|| ["qsb", "qsb.exe"].iter().map(|x| qt_bin.join(x)).find(|x| x.exists()).map(|x| x.to_string_lossy().to_string()))
+        .unwrap_or_else(|| "qsb".to_string());
+
+    let out_dir = env::var("OUT_DIR").unwrap();
+    for (out, sources) in outputs {
+        let mut contents = Vec::new();
+        for x in &sources { contents.extend(std::fs::read(x).unwrap().into_iter().filter(|x| *x != b'\r')); }
+        let sources_hash = format!("{:016x}", hash(&contents));
+        let hash_file = compiled_dir.join(format!("{}.hash", out));
+
+        let input = if out.ends_with(".frag.qsb") {
+            let mut source = "#version 420\n".to_string();
+            for x in &sources { source.push_str(&std::fs::read_to_string(x).unwrap()); }
+            let tmp = Path::new(&out_dir).join(out.replace(".qsb", ""));
+            std::fs::write(&tmp, source).unwrap();
+            tmp.to_string_lossy().to_string()
+        } else {
+            sources[0].clone()
+        };
+        let status = Command::new(&qsb)
+            .args(&["--glsl", "100 es,200 es,300 es,330,400,120,100", "--hlsl", "50", "--msl", "12", "-o"])
+            .arg(compiled_dir.join(&out))
+            .arg(&input)
+            .status();
+        match status {
+            Ok(x) if x.success() => { std::fs::write(&hash_file, &sources_hash).unwrap(); },
+            Ok(_) => panic!("Failed to compile {}", out),
+            Err(_) => {
+                if std::fs::read_to_string(&hash_file).ok().as_deref().map(str::trim) != Some(sources_hash.as_str()) {
+                    panic!("{} is out of date with its sources and {} wasn't found. Set QSB to the path of the qsb tool", out, qsb);
+                }
+            }
+        }
+    }
+}
+
 fn main() {
     let qt_include_path = env::var("DEP_QT_INCLUDE_PATH").unwrap();
     let qt_library_path = env::var("DEP_QT_LIBRARY_PATH").unwrap();
@@ -77,6 +140,8 @@ fn main() {
         }
     }
 
+    compile_shaders(&qt_library_path);
+
     let mut config = cpp_build::Config::new();
 
     for f in env::var("DEP_QT_COMPILE_FLAGS").unwrap().split_terminator(';') {
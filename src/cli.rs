@@ -13,6 +13,11 @@ use crate::rendering;
 use crate::rendering::render_queue::*;
 use indicatif::{ProgressBar, MultiProgress, ProgressState, ProgressStyle};
 
+// Exit codes of the console mode
+const EXIT_OK: i32 = 0;
+const EXIT_FAILED: i32 = 1; // At least one of the renders failed
+const EXIT_INVALID_ARGS: i32 = 2;
+
 cpp! {{
     struct TraitObject2 { void *data; void *vtable; };
     #include <QCoreApplication>
@@ -36,6 +41,10 @@ struct Opts {
     #[argh(positional)]
     input: Vec<String>,
 
+    /// video or project file to render, same as the positional input files. Can be repeated
+    #[argh(option)]
+    render: Vec<String>,
+
     /// overwrite if output file exists, default: false
     #[argh(switch, short = 'f')]
     overwrite: bool,
@@ -67,6 +76,22 @@ struct Opts {
     /// watch folder for automated processing
     #[argh(option)]
     watch: Option<String>,
+
+    /// compare the GPU and CPU stabilization kernels with the reference implementation on synthetic inputs
+    #[argh(switch)]
+    conformance: bool,
+
+    /// measure the speed of telemetry parsing, integration, smoothing, zooming and frame warping and print a JSON report. Uses the gyro data from the input file if provided
+    #[argh(switch)]
+    benchmark: bool,
+
+    /// print a JSON with the compiled features, installed SDKs, GPU backends and available encoders and decoders
+    #[argh(switch)]
+    capabilities: bool,
+
+    /// reduce memory usage at the cost of speed (smaller caches and decoder queues, bounded render memory)
+    #[argh(switch)]
+    low_memory: bool,
 }
 
 pub fn will_run_in_console() -> bool {
@@ -82,9 +107,35 @@ pub fn will_run_in_console() -> bool {
     false
 }
 
-pub fn run(open_file: &mut String) -> bool {
+// Returns the exit code if the app ran in the console mode, None if the GUI should be started.
+// Exits with 0 when all renders succeeded, 1 when any of them failed and 2 for invalid arguments
+pub fn run(open_file: &mut String) -> Option<i32> {
     if std::env::args().len() > 1 {
-        let opts: Opts = argh::from_env();
+        let mut opts: Opts = argh::from_env();
+        opts.input.append(&mut opts.render);
+
+        if opts.low_memory {
+            gyroflow_core::low_memory::set_enabled(true);
+        }
+        if opts.conformance {
+            let results = gyroflow_core::stabilization::reference::run_conformance();
+            let failed = results.iter().filter(|x| !x.passed).count();
+            if failed > 0 {
+                log::error!("{} of {} conformance checks failed", failed, results.len());
+                return Some(EXIT_FAILED);
+            }
+            log::info!("All {} conformance checks passed", results.len());
+            return Some(EXIT_OK);
+        }
+        if opts.capabilities {
+            println!("{}", serde_json::to_string_pretty(&crate::util::get_capabilities()).unwrap_or_default());
+            return Some(EXIT_OK);
+        }
+        if opts.benchmark {
+            let report = gyroflow_core::benchmark::run_benchmark(opts.input.first().map(|x| x.as_str()));
+            println!("{}", serde_json::to_string_pretty(&report).unwrap_or_default());
+            return Some(EXIT_OK);
+        }
 
         let (videos, mut lens_profiles, mut presets) = detect_types(&opts.input);
         if let Some(mut preset) = opts.preset {
@@ -97,14 +148,14 @@ pub fn run(open_file: &mut String) -> bool {
         if let Some(open) = opts.open {
             if !open.is_empty() {
                 *open_file = open;
-                return false;
+                return None;
             }
         }
 
         for file in videos.iter().chain(lens_profiles.iter()) {
             if !std::path::Path::new(&file).exists() {
                 log::error!("File {} doesn't exist.", file);
-                return true;
+                return Some(EXIT_INVALID_ARGS);
             }
         }
         let mut watching = opts.watch.as_ref().map(|x| !x.is_empty()).unwrap_or_default();
@@ -112,11 +163,11 @@ pub fn run(open_file: &mut String) -> bool {
         if !watching {
             if lens_profiles.len() > 1 {
                 log::error!("More than one lens profile!");
-                return true;
+                return Some(EXIT_INVALID_ARGS);
             }
             if videos.is_empty() {
                 log::error!("No videos provided!");
-                return true;
+                return Some(EXIT_INVALID_ARGS);
             }
 
             log::info!("Videos: {:?}", videos);
@@ -159,8 +210,17 @@ pub fn run(open_file: &mut String) -> bool {
 
         if let Some(mut outp) = opts.out_params {
             outp = outp.replace('\'', "\"");
-            gyroflow_core::util::merge_json(additional_data.get_mut("output").unwrap(), &serde_json::from_str(&outp).expect("Invalid json"));
+            match serde_json::from_str(&outp) {
+                Ok(outp) => gyroflow_core::util::merge_json(additional_data.get_mut("output").unwrap(), &outp),
+                Err(e) => {
+                    log::error!("Invalid output parameters {}: {:?}", outp, e);
+                    return Some(EXIT_INVALID_ARGS);
+                }
+            }
         }
+        // Without a terminal (eg. in scripts) the progress bars aren't drawn, so the progress is printed as lines instead
+        let plain_progress = indicatif::ProgressDrawTarget::stdout().is_hidden();
+        let mut printed_progress = HashMap::<u32, u64>::new();
 
         queue.set_parallel_renders(opts.parallel_renders.max(1));
         queue.set_when_done(opts.when_done);
@@ -242,6 +302,14 @@ pub fn run(open_file: &mut String) -> bool {
 
                 pb.set_length(*total_frames as u64);
                 pb.set_position(*current_frame as u64);
+
+                if plain_progress && *total_frames > 0 {
+                    let percent = (*current_frame * 100 / *total_frames) as u64;
+                    if printed_progress.get(job_id) != Some(&percent) {
+                        printed_progress.insert(*job_id, percent);
+                        println!("[{:08x}] {:3}% {}/{} {}", job_id, percent, current_frame, total_frames, queue.get_job_output_path(*job_id));
+                    }
+                }
             });
             connect!(queue_ptr, q, processing_progress, |job_id: &u32, progress: &f64| {
                 let mut any_other_in_progress = false;
@@ -272,14 +340,17 @@ pub fn run(open_file: &mut String) -> bool {
             connect!(queue_ptr, q, convert_format, |job_id: &u32, format: &QString, supported: &QString| {
                 log::error!("[{:08x}] Pixel format {} is not supported. Supported are: {}", job_id, format.to_string(), supported.to_string());
             });
-            connect!(queue_ptr, q, error, |job_id: &u32, text: &QString, arg: &QString, _callback: &QString| {
-                if opts.overwrite && text.to_string().starts_with("file_exists:") {
+            connect!(queue_ptr, q, error, |job_id: &u32, error_json: &QString| {
+                let err: serde_json::Value = serde_json::from_str(&error_json.to_string()).unwrap_or_default();
+                let kind = err.get("kind").and_then(|x| x.as_str()).unwrap_or_default();
+                let context = err.get("context").and_then(|x| x.as_str()).unwrap_or_default();
+                if opts.overwrite && kind == "output_exists" {
                     let queue = &mut *queue.as_ptr();
                     queue.reset_job(*job_id);
-                    log::warn!("[{:08x}] File exists, overwriting: {}", job_id, text.to_string().strip_prefix("file_exists:").unwrap());
+                    log::warn!("[{:08x}] File exists, overwriting: {}", job_id, context);
                     return;
                 }
-                log::error!("[{:08x}] Error: {}", job_id, text.to_string().replace("%1", &arg.to_string()));
+                log::error!("[{:08x}] Error ({}): {}", job_id, kind, context);
             });
             connect!(queue_ptr, q, added, |job_id: &u32| {
                 let queue = &mut *queue.as_ptr();
@@ -348,10 +419,23 @@ pub fn run(open_file: &mut String) -> bool {
 
         log::info!("Done in {:.3}s", time.elapsed().as_millis() as f64 / 1000.0);
 
-        return true;
+        let queue = queue.borrow();
+        let items = queue.queue.borrow();
+        for item in items.iter().filter(|x| *x.get_status() == JobStatus::Skipped) {
+            log::warn!("[{:08x}] {} skipped, output file already exists: {}", item.job_id, item.input_file, item.output_path);
+        }
+        let failed: Vec<_> = items.iter().filter(|x| *x.get_status() == JobStatus::Error).collect();
+        for item in &failed {
+            log::error!("[{:08x}] {} failed: {}", item.job_id, item.input_file, item.error_string);
+        }
+        if !failed.is_empty() {
+            log::error!("{} of {} renders failed", failed.len(), items.len());
+            return Some(EXIT_FAILED);
+        }
+        return Some(EXIT_OK);
     }
 
-    false
+    None
 }
 
 fn detect_types(all_files: &[String]) -> (Vec<String>, Vec<String>, Vec<String>) { // -> Videos/projects, lens profiles, presets
@@ -408,6 +492,7 @@ fn setup_defaults(stab: Arc<StabilizationManager<stabilization::RGBA8>>, queue:
         "DNxHD",
         "EXR Sequence",
         "PNG Sequence",
+        "CineForm",
     ];
 
     // Default settings - project file will override this
@@ -458,6 +543,7 @@ fn setup_defaults(stab: Arc<StabilizationManager<stabilization::RGBA8>>, queue:
             "keyframe_distance":     settings.get("keyframeDistance").unwrap_or(&"1".into()).parse::<u32>().unwrap(),
             "preserve_other_tracks": settings.get("preserveOtherTracks").unwrap_or(&"false".into()).parse::<bool>().unwrap(),
             "pad_with_black":        settings.get("padWithBlack").unwrap_or(&"false".into()).parse::<bool>().unwrap(),
+            "gpmf_passthrough":      settings.get("gpmfPassthrough").unwrap_or(&"false".into()).parse::<bool>().unwrap(),
         },
         "synchronization": {
             "initial_offset":     0,
@@ -6,7 +6,7 @@ use qmetaobject::*;
 use nalgebra::Vector4;
 use std::sync::Arc;
 use std::cell::RefCell;
-use std::sync::atomic::{ AtomicBool, AtomicUsize, Ordering::SeqCst };
+use std::sync::atomic::{ AtomicUsize, Ordering::SeqCst };
 use std::collections::BTreeSet;
 use std::str::FromStr;
 
@@ -20,6 +20,9 @@ use crate::core::synchronization::AutosyncProcess;
 use crate::core::stabilization;
 use crate::core::synchronization;
 use crate::core::keyframes::*;
+use crate::core::preview_quality::PreviewQualityLadder;
+use crate::core::job_manager::{ JobKind, JobToken, ComputePolicy };
+use crate::core::error::{ GyroflowError, ErrorKind, SuggestedAction };
 use crate::rendering;
 use crate::util;
 use crate::wrap_simple_method;
@@ -35,6 +38,23 @@ struct OffsetItem {
     pub offset_ms: f64,
 }
 
+#[derive(Default, SimpleListItem)]
+struct ShakeItem {
+    pub timestamp_us: i64,
+    pub duration_us: i64,
+    pub shake: f64, // deg/s
+    pub level: f64, // 0..1
+}
+
+// Network actions requested while in offline mode
+#[derive(Debug, PartialEq)]
+enum OnlineAction {
+    CheckUpdates,
+    FetchProfiles,
+    RequestRatings,
+    RateProfile(QString, QString, bool),
+}
+
 #[derive(Default, SimpleListItem)]
 struct CalibrationItem {
     pub timestamp_us: i64,
@@ -42,6 +62,52 @@ struct CalibrationItem {
     pub is_forced: bool,
 }
 
+// Passes the decoded frames to the lens calibrator and counts them for the progress
+#[cfg(feature = "opencv")]
+#[derive(Clone)]
+struct CalibrationFeed {
+    cal: Arc<parking_lot::RwLock<Option<LensCalibrator>>>,
+    stretch: (f64, f64), // Input horizontal and vertical stretch of the lens profile
+    no_marker: bool,
+    total: usize,
+    read: Arc<AtomicUsize>,
+    processed: Arc<AtomicUsize>,
+    cancel_flag: Arc<std::sync::atomic::AtomicBool>,
+    progress: Arc<dyn Fn((usize, usize, usize, f64)) + Send + Sync>,
+}
+#[cfg(feature = "opencv")]
+impl CalibrationFeed {
+    // Scales the frame to at most 2160 px height and detects the board in it
+    fn feed(&self, timestamp_us: i64, frame: i32, is_forced: bool, input_frame: &mut ffmpeg_next::frame::Video, converter: &mut rendering::Converter) -> Result<(), rendering::FFmpegError> {
+        let mut width = (input_frame.width() as f64 * self.stretch.0).round() as u32;
+        let mut height = (input_frame.height() as f64 * self.stretch.1).round() as u32;
+        let mut pt_scale = 1.0;
+        if height > 2160 {
+            pt_scale = height as f32 / 2160.0;
+            width = (width as f32 / pt_scale).round() as u32;
+            height = (height as f32 / pt_scale).round() as u32;
+        }
+        let mut small_frame = converter.scale(input_frame, ffmpeg_next::format::Pixel::GRAY8, width, height)?;
+        let (width, height, stride, pixels) = (small_frame.plane_width(0), small_frame.plane_height(0), small_frame.stride(0), small_frame.data_mut(0));
+
+        self.read.fetch_add(1, SeqCst);
+        let mut lock = self.cal.write();
+        let cal = lock.as_mut().unwrap();
+        if is_forced {
+            cal.forced_frames.insert(frame);
+        }
+        cal.no_marker = self.no_marker;
+        let progress = self.progress.clone();
+        cal.feed_frame(timestamp_us, frame, width, height, stride, pt_scale, pixels, self.cancel_flag.clone(), self.total, self.processed.clone(), move |x| progress(x));
+        Ok(())
+    }
+    // Frame that can't be used, counted so the progress can still finish
+    fn skip(&self) {
+        self.read.fetch_add(1, SeqCst);
+        self.processed.fetch_add(1, SeqCst);
+    }
+}
+
 #[derive(Default, QObject)]
 pub struct Controller {
     base: qt_base_class!(trait QObject),
@@ -55,31 +121,71 @@ pub struct Controller {
     load_lens_profile_url: qt_method!(fn(&mut self, url: QUrl)),
     export_lens_profile: qt_method!(fn(&mut self, url: QUrl, info: QJsonObject, upload: bool)),
     export_lens_profile_filename: qt_method!(fn(&mut self, info: QJsonObject) -> QString),
+    lens_profile_upload_status: qt_signal!(status: QString, message: QString),
 
     set_of_method: qt_method!(fn(&self, v: u32)),
     start_autosync: qt_method!(fn(&mut self, timestamps_fract: String, sync_params: String, mode: String)),
+    sync_results_staged: qt_signal!(count: usize, offsets_json: QString),
+    sync_points_rejected: qt_signal!(count: usize, rejected_json: QString),
+    apply_staged_sync: qt_method!(fn(&mut self)),
+    discard_staged_sync: qt_method!(fn(&mut self)),
     update_chart: qt_method!(fn(&self, chart: QJSValue)),
+    update_zoom_curve: qt_method!(fn(&self, chart: QJSValue)),
+    set_chart_physical_units: qt_method!(fn(&self, chart: QJSValue, enabled: bool)),
+    set_chart_unfiltered_overlay: qt_method!(fn(&self, chart: QJSValue, enabled: bool)),
     update_frequency_graph: qt_method!(fn(&self, graph: QJSValue, idx: usize, ts: f64, sr: f64, fft_size: usize)),
     update_keyframes_view: qt_method!(fn(&self, kfview: QJSValue)),
     rolling_shutter_estimated: qt_signal!(rolling_shutter: f64),
     estimate_bias: qt_method!(fn(&self, timestamp_fract: QString)),
-    bias_estimated: qt_signal!(bx: f64, by: f64, bz: f64),
+    bias_estimated: qt_signal!(bx: f64, by: f64, bz: f64, quality: f64, still_segments: usize, total_segments: usize),
+    set_input_crop: qt_method!(fn(&self, x: f64, y: f64, w: f64, h: f64)),
+    set_video_filter: qt_method!(fn(&mut self, filter: QString, player: QJSValue) -> QJsonArray),
+    detect_input_crop: qt_method!(fn(&self)),
+    input_crop_detected: qt_signal!(x: f64, y: f64, w: f64, h: f64),
+    analyze_clip: qt_method!(fn(&self)),
+    clip_analyzed: qt_signal!(report: QJsonObject),
+    suggest_smoothing: qt_method!(fn(&self)),
+    search_smoothness: qt_method!(fn(&self, target_crop: f64)),
+    smoothing_suggested: qt_signal!(smoothness: f64, adaptive_zoom_window: f64),
+    suggest_trim: qt_method!(fn(&self)),
+    trim_suggested: qt_signal!(trim_start: f64, trim_end: f64, start_handling_ms: f64, end_handling_ms: f64),
+    smoothness_search_progress: qt_signal!(progress: f64),
+    check_horizon_lock: qt_method!(fn(&mut self, apply_fallback: bool)),
+    horizon_lock_stability: qt_signal!(report: QJsonObject),
+    smoothness_search_finished: qt_signal!(result: QJsonObject),
+    export_vibration_bands: qt_method!(fn(&self, url: QUrl)),
+    export_diagnostics: qt_method!(fn(&self, url: QUrl)),
     orientation_guessed: qt_signal!(orientation: QString),
+
+    start_axis_capture: qt_method!(fn(&mut self, motion: String, timestamp_ms: f64) -> bool),
+    stop_axis_capture: qt_method!(fn(&mut self, timestamp_ms: f64)),
+    clear_axis_captures: qt_method!(fn(&mut self)),
+    axis_remap_updated: qt_signal!(result_json: QString, error: QString),
     get_optimal_sync_points: qt_method!(fn(&mut self, target_sync_points: usize) -> QString),
 
     start_autocalibrate: qt_method!(fn(&self, max_points: usize, every_nth_frame: usize, iterations: usize, max_sharpness: f64, custom_timestamp_ms: f64, no_marker: bool)),
+    start_autocalibrate_images: qt_method!(fn(&self, urls: QStringList, max_points: usize, iterations: usize, max_sharpness: f64, no_marker: bool)),
+    start_rig_calibration: qt_method!(fn(&self, other_video: QUrl, other_lens_profile: QUrl, time_offset_ms: f64)),
+    export_rig_calibration: qt_method!(fn(&self, url: QUrl)),
+    rig_calibrated: qt_signal!(result: QString),
 
     telemetry_loaded: qt_signal!(is_main_video: bool, filename: QString, camera: QString, imu_orientation: QString, contains_gyro: bool, contains_raw_gyro: bool, contains_quats: bool, frame_readout_time: f64, camera_id_json: QString, sample_rate: f64),
     lens_profile_loaded: qt_signal!(lens_json: QString, filepath: QString),
     realtime_fps_loaded: qt_signal!(fps: f64),
+    orientation_auto_applied: qt_signal!(video_rotation: f64, camera_orientation: f64),
+    lens_profile_ambiguous: qt_signal!(candidates_json: QString, lens_info: QString, gimbal_mode: QString),
+    lens_profile_closest: qt_signal!(name: QString, lens_info: QString, gimbal_mode: QString),
+    gpu_fallback: qt_signal!(failed: QString, used: QString),
 
     set_smoothing_method: qt_method!(fn(&self, index: usize) -> QJsonArray),
     get_smoothing_max_angles: qt_method!(fn(&self) -> QJsonArray),
     get_smoothing_status: qt_method!(fn(&self) -> QJsonArray),
     set_smoothing_param: qt_method!(fn(&self, name: QString, val: f64)),
     set_horizon_lock: qt_method!(fn(&self, lock_percent: f64, roll: f64)),
+    set_horizon_pitch_lock: qt_method!(fn(&self, lock_percent: f64)),
     set_use_gravity_vectors: qt_method!(fn(&self, v: bool)),
     set_preview_resolution: qt_method!(fn(&mut self, target_height: i32, player: QJSValue)),
+    set_preview_playing: qt_method!(fn(&mut self, playing: bool, player: QJSValue)),
     set_background_color: qt_method!(fn(&self, color: QString, player: QJSValue)),
     set_integration_method: qt_method!(fn(&self, index: usize)),
 
@@ -89,16 +195,32 @@ pub struct Controller {
     offset_at_video_timestamp: qt_method!(fn(&self, timestamp_us: i64) -> f64),
     offsets_model: qt_property!(RefCell<SimpleListModel<OffsetItem>>; NOTIFY offsets_updated),
     offsets_updated: qt_signal!(),
+    shake_model: qt_property!(RefCell<SimpleListModel<ShakeItem>>; NOTIFY shake_updated),
+    shake_updated: qt_signal!(),
 
     load_profiles: qt_method!(fn(&self, reload_from_disk: bool)),
     all_profiles_loaded: qt_signal!(profiles: QVariantList),
     fetch_profiles_from_github: qt_method!(fn(&self)),
     lens_profiles_updated: qt_signal!(reload_from_disk: bool),
+    watch_lens_profiles: qt_method!(fn(&mut self)),
+    lens_profile_updates_available: qt_signal!(updates_json: QString),
+    apply_lens_profile_update: qt_method!(fn(&self, path: QString, fields: QStringList)),
 
     set_sync_lpf: qt_method!(fn(&self, lpf: f64)),
     set_imu_lpf: qt_method!(fn(&self, lpf: f64)),
+    set_imu_lpf_type: qt_method!(fn(&self, filter_type: usize, order: usize)),
+    set_imu_lpf_axes: qt_method!(fn(&self, x: f64, y: f64, z: f64)),
     set_imu_rotation: qt_method!(fn(&self, pitch_deg: f64, roll_deg: f64, yaw_deg: f64)),
     set_acc_rotation: qt_method!(fn(&self, pitch_deg: f64, roll_deg: f64, yaw_deg: f64)),
+    set_extrinsic_rotation: qt_method!(fn(&self, pitch_deg: f64, roll_deg: f64, yaw_deg: f64)),
+    get_pixel_aspect_ratio: qt_method!(fn(&self) -> f64),
+    load_rig_calibration: qt_method!(fn(&self, url: QUrl) -> QJsonArray),
+    set_acc_lpf: qt_method!(fn(&self, lpf: f64)),
+    set_quat_fusion_crossover: qt_method!(fn(&self, hz: f64)),
+    get_log_slice: qt_method!(fn(&self) -> QJsonObject),
+    set_log_anchor: qt_method!(fn(&self, start_s: f64)),
+    set_acc_bias: qt_method!(fn(&self, bx: f64, by: f64, bz: f64)),
+    set_acc_scale: qt_method!(fn(&self, sx: f64, sy: f64, sz: f64)),
     set_imu_orientation: qt_method!(fn(&self, orientation: String)),
     set_imu_bias: qt_method!(fn(&self, bx: f64, by: f64, bz: f64)),
     recompute_gyro: qt_method!(fn(&self)),
@@ -106,6 +228,9 @@ pub struct Controller {
     override_video_fps: qt_method!(fn(&self, fps: f64)),
     get_org_duration_ms: qt_method!(fn(&self) -> f64),
     get_scaled_duration_ms: qt_method!(fn(&self) -> f64),
+    source_to_output_timestamp: qt_method!(fn(&self, timestamp_ms: f64) -> f64),
+    output_to_source_timestamp: qt_method!(fn(&self, timestamp_ms: f64) -> f64),
+    get_output_duration_ms: qt_method!(fn(&self) -> f64),
     get_scaled_fps: qt_method!(fn(&self) -> f64),
 
     recompute_threaded: qt_method!(fn(&mut self)),
@@ -120,8 +245,13 @@ pub struct Controller {
     adaptive_zoom: qt_property!(f64; WRITE set_adaptive_zoom),
     zooming_center_x: qt_property!(f64; WRITE set_zooming_center_x),
     zooming_center_y: qt_property!(f64; WRITE set_zooming_center_y),
+    max_zoom: qt_property!(f64; WRITE set_max_zoom),
+    zoom_border_tolerance: qt_property!(f64; WRITE set_zoom_border_tolerance),
 
     lens_correction_amount: qt_property!(f64; WRITE set_lens_correction_amount),
+    stabilization_amount: qt_property!(f64; WRITE set_stabilization_amount),
+    output_projection: qt_property!(i32; WRITE set_output_projection),
+    output_projection_strength: qt_property!(f64; WRITE set_output_projection_strength),
     set_video_speed: qt_method!(fn(&self, v: f64, s: bool, z: bool)),
 
     input_horizontal_stretch: qt_property!(f64; WRITE set_input_horizontal_stretch),
@@ -148,6 +278,7 @@ pub struct Controller {
 
     set_trim_start: qt_method!(fn(&self, trim_start: f64)),
     set_trim_end: qt_method!(fn(&self, trim_end: f64)),
+    set_trim_range: qt_method!(fn(&self, trim_start: f64, trim_end: f64, keyframes_policy: String, offsets_policy: String)),
 
     set_output_size: qt_method!(fn(&self, width: usize, height: usize)),
 
@@ -155,6 +286,7 @@ pub struct Controller {
     keyframes_changed: qt_signal!(),
 
     cancel_current_operation: qt_method!(fn(&mut self)),
+    set_compute_policy: qt_method!(fn(&self, policy: i32)),
 
     sync_in_progress: qt_property!(bool; NOTIFY sync_in_progress_changed),
     sync_in_progress_changed: qt_signal!(),
@@ -186,23 +318,33 @@ pub struct Controller {
     gyroflow_file_loaded: qt_signal!(obj: QJsonObject),
     export_gyroflow_file: qt_method!(fn(&self, thin: bool, extended: bool, additional_data: QJsonObject, override_location: QString, overwrite: bool)),
     export_gyroflow_data: qt_method!(fn(&self, thin: bool, extended: bool, additional_data: QJsonObject) -> QString),
+    embed_gyro_file: qt_property!(bool),
 
+    offline: qt_property!(bool; WRITE set_offline),
     check_updates: qt_method!(fn(&self)),
     updates_available: qt_signal!(version: QString, changelog: QString),
     rate_profile: qt_method!(fn(&self, name: QString, json: QString, is_good: bool)),
     request_profile_ratings: qt_method!(fn(&self)),
 
-    set_zero_copy: qt_method!(fn(&self, player: QJSValue, enabled: bool)),
+    set_zero_copy: qt_method!(fn(&mut self, player: QJSValue, enabled: bool)),
+    set_preview_hdr: qt_method!(fn(&self, player: QJSValue, mode: i32)),
     set_gpu_decoding: qt_method!(fn(&self, enabled: bool)),
 
     list_gpu_devices: qt_method!(fn(&self)),
     set_device: qt_method!(fn(&self, i: i32)),
+    set_export_device: qt_method!(fn(&self, name: String)),
+    set_kernel_precision: qt_method!(fn(&self, v: i32)),
+    fp16_supported: qt_method!(fn(&self, device: i32) -> bool),
+    set_deterministic_compute: qt_method!(fn(&self, v: bool)),
     set_rendering_gpu_type_from_name: qt_method!(fn(&self, name: String)),
+    set_pipeline_config: qt_method!(fn(&self, json: String)),
+    get_pipeline_stats: qt_method!(fn(&self) -> QString),
     gpu_list_loaded: qt_signal!(list: QJsonArray),
 
     is_superview: qt_property!(bool; WRITE set_is_superview),
 
     file_exists: qt_method!(fn(&self, path: QString) -> bool),
+    render_preflight: qt_method!(fn(&self, output_options: String) -> QString),
     file_size: qt_method!(fn(&self, path: QString) -> u64),
     video_duration: qt_method!(fn(&self, path: QString) -> f64),
     resolve_android_url: qt_method!(fn(&self, url: QString) -> QString),
@@ -214,14 +356,28 @@ pub struct Controller {
     path_to_url: qt_method!(fn(&self, path: QString) -> QUrl),
 
     image_to_b64: qt_method!(fn(&self, img: QImage) -> QString),
+    get_stabilized_frame: qt_method!(fn(&self, timestamp_us: i64, width: u32, height: u32) -> QImage),
+    export_frame: qt_method!(fn(&self, url: QUrl, timestamp_us: i64, width: u32, height: u32, burn_in: bool)),
     export_preset: qt_method!(fn(&self, url: QUrl, data: QJsonObject)),
+    get_parameter_sources: qt_method!(fn(&self) -> QJsonObject),
+    diff_projects: qt_method!(fn(&self, old: QUrl, new: QUrl) -> QJsonObject),
+
+    save_project_template: qt_method!(fn(&self, name: QString, additional_data: QJsonObject)),
+    get_project_templates: qt_method!(fn(&self) -> QStringList),
+    delete_project_template: qt_method!(fn(&self, name: QString)),
+    instantiate_project_template: qt_method!(fn(&self, name: QString, video_url: QUrl) -> QJsonObject),
+    reset_parameter_override: qt_method!(fn(&mut self, path: QString)),
 
     message: qt_signal!(text: QString, arg: QString, callback: QString),
-    error: qt_signal!(text: QString, arg: QString, callback: QString),
+    error: qt_signal!(error_json: QString),
 
     gyroflow_exists: qt_signal!(path: QString, thin: bool, extended: bool),
     request_location: qt_signal!(path: QString, thin: bool, extended: bool),
+    gyroflow_file_damaged: qt_signal!(recovered_data: QString, dropped_sections: QString, error: QString),
 
+    get_value_range: qt_method!(fn(&self, name: String) -> QJsonObject),
+    get_capabilities: qt_method!(fn(&self) -> QJsonObject),
+    is_low_memory: qt_method!(fn(&self) -> bool),
     set_keyframe: qt_method!(fn(&self, typ: String, timestamp_us: i64, value: f64)),
     set_keyframe_easing: qt_method!(fn(&self, typ: String, timestamp_us: i64, easing: String)),
     keyframe_easing: qt_method!(fn(&self, typ: String, timestamp_us: i64) -> String),
@@ -244,8 +400,23 @@ pub struct Controller {
     image_sequence_fps: qt_property!(f64),
 
     preview_resolution: i32,
+    preview_quality: Arc<parking_lot::Mutex<PreviewQualityLadder>>,
+    preview_filter: Arc<parking_lot::Mutex<rendering::ffmpeg_filter::PreviewFilterParams>>,
+    zero_copy: bool,
+
+    profile_upload_queue: Arc<parking_lot::Mutex<std::collections::VecDeque<String>>>,
+    profile_upload_running: Arc<std::sync::atomic::AtomicBool>,
+    lens_profiles_watcher: Option<core::lens_profile_database::ProfilesWatcher>,
+
+    // Sync results waiting for the user confirmation: (timestamp, offset, cost)
+    staged_offsets: Vec<(f64, f64, f64)>,
+
+    // IMU orientation wizard, see `core::axis_remap`
+    axis_captures: Vec<core::axis_remap::AxisCapture>,
+    axis_capture_start: Option<(core::axis_remap::CameraMotion, f64)>,
 
-    cancel_flag: Arc<AtomicBool>,
+    offline_flag: Arc<std::sync::atomic::AtomicBool>,
+    pending_online_actions: RefCell<Vec<OnlineAction>>,
 
     ongoing_computations: BTreeSet<u64>,
 
@@ -256,19 +427,24 @@ impl Controller {
     pub fn new() -> Self {
         Self {
             preview_resolution: 720,
+            preview_quality: Default::default(),
+            offline: util::is_offline(),
+            offline_flag: Arc::new(std::sync::atomic::AtomicBool::new(util::is_offline())),
             ..Default::default()
         }
     }
 
     fn load_video(&mut self, url: QUrl, player: QJSValue) {
-        self.stabilizer.clear();
+        self.stabilizer.record_change("load_video", &[format!("{:?}", util::url_to_path(url.clone()))], || self.stabilizer.clear());
+        rendering::frame_server::clear_shared(); // The file could have changed since it was last opened
         self.chart_data_changed();
         self.keyframes_changed();
         self.update_offset_model();
         *self.stabilizer.input_file.write() = gyroflow_core::InputFile {
             path: util::url_to_path(url.clone()),
             image_sequence_start: self.image_sequence_start,
-            image_sequence_fps: self.image_sequence_fps
+            image_sequence_fps: self.image_sequence_fps,
+            video_filter: String::new()
         };
 
         let mut custom_decoder = QString::default(); // eg. BRAW:format=rgba64le
@@ -289,7 +465,7 @@ impl Controller {
         if let Err(e) = sync_params {
             self.sync_in_progress = false;
             self.sync_in_progress_changed();
-            return self.error(QString::from("An error occured: %1"), QString::from(format!("JSON parse error: {}", e)), QString::default());
+            return self.show_error(GyroflowError::new(ErrorKind::InvalidParameters, core::i18n::Message::new("error.json_parse").arg(e).to_string()));
         }
         let mut sync_params = sync_params.unwrap();
 
@@ -299,6 +475,10 @@ impl Controller {
         sync_params.every_nth_frame     = sync_params.every_nth_frame.max(1);
 
         let for_rs = mode == "estimate_rolling_shutter";
+        let (reject_outliers, fit_linear) = (sync_params.reject_outliers, sync_params.fit_linear);
+        // Same as "synchronize", but the results are not applied until the user confirms them
+        let staged = mode == "synchronize_staged";
+        let mode = if staged { "synchronize".to_string() } else { mode };
 
         let every_nth_frame = sync_params.every_nth_frame;
 
@@ -315,22 +495,28 @@ impl Controller {
             this.chart_data_changed();
             this.sync_progress(percent, ready, total);
         });
-        let set_offsets = util::qt_queued_callback_mut(self, move |this, offsets: Vec<(f64, f64, f64)>| {
+        let set_offsets = util::qt_queued_callback_mut(self, move |this, mut offsets: Vec<(f64, f64, f64)>| {
+            let offsets_to_json = |offsets: &[(f64, f64, f64)]| -> QString {
+                QString::from(serde_json::to_string(&offsets.iter().map(|x| serde_json::json!({ "timestamp": x.0, "offset": x.1, "cost": x.2 })).collect::<Vec<_>>()).unwrap_or_default())
+            };
+            if !for_rs && reject_outliers {
+                let filtered = synchronization::offset_filter::filter_offsets(&offsets, fit_linear);
+                // Always report, so the UI can clear the previous result
+                this.sync_points_rejected(filtered.rejected.len(), offsets_to_json(&filtered.rejected));
+                offsets = filtered.accepted;
+            }
             if for_rs {
                 if let Some(offs) = offsets.first() {
                     this.rolling_shutter_estimated(offs.1);
                 }
+            } else if staged {
+                let json = offsets_to_json(&offsets);
+                this.staged_offsets = offsets;
+                this.sync_results_staged(this.staged_offsets.len(), json);
+                return;
             } else {
-                let mut gyro = this.stabilizer.gyro.write();
-                for x in offsets {
-                    ::log::info!("Setting offset at {:.4}: {:.4} (cost {:.4})", x.0, x.1, x.2);
-                    let new_ts = ((x.0 - x.1) * 1000.0) as i64;
-                    // Remove existing offsets within 100ms range
-                    gyro.remove_offsets_near(new_ts, 100.0);
-                    gyro.set_offset(new_ts, x.1);
-                }
-                this.stabilizer.keyframes.write().update_gyro(&gyro);
-                this.stabilizer.invalidate_zooming();
+                this.set_offsets(offsets);
+                this.check_horizon_lock(false);
             }
             this.update_offset_model();
             this.request_recompute();
@@ -339,11 +525,8 @@ impl Controller {
             ::log::info!("Setting orientation {}", &orientation);
             this.orientation_guessed(QString::from(orientation));
         });
-        let err = util::qt_queued_callback_mut(self, |this, (msg, mut arg): (String, String)| {
-            arg.push_str("\n\n");
-            arg.push_str(&rendering::get_log());
-
-            this.error(QString::from(msg), QString::from(arg), QString::default());
+        let err = util::qt_queued_callback_mut(self, |this, e: GyroflowError| {
+            this.show_error(e.append_context(&rendering::get_log()));
 
             this.sync_in_progress = false;
             this.sync_in_progress_changed();
@@ -352,9 +535,9 @@ impl Controller {
         });
         self.sync_progress(0.0, 0, 0);
 
-        self.cancel_flag.store(false, SeqCst);
+        let job = self.stabilizer.jobs.start(JobKind::Sync);
 
-        if let Ok(mut sync) = AutosyncProcess::from_manager(&self.stabilizer, &timestamps_fract, sync_params, mode, self.cancel_flag.clone()) {
+        if let Ok(mut sync) = AutosyncProcess::from_manager(&self.stabilizer, &timestamps_fract, sync_params, mode, job.cancel_flag.clone()) {
             sync.on_progress(move |percent, ready, total| {
                 progress((percent, ready, total));
             });
@@ -367,65 +550,116 @@ impl Controller {
             });
 
             let ranges = sync.get_ranges();
-            let cancel_flag = self.cancel_flag.clone();
+            let cancel_flag = job.cancel_flag.clone();
 
             let input_file = self.stabilizer.input_file.read().clone();
             let (sw, sh) = (size.0 as u32, size.1 as u32);
+            let jobs = self.stabilizer.jobs.clone();
             core::run_threaded(move || {
+                let _job = job;
                 let gpu_decoding = *rendering::GPU_DECODING.read();
 
                 let mut frame_no = 0;
                 let mut abs_frame_no = 0;
 
-                let mut decoder_options = ffmpeg_next::Dictionary::new();
-                if input_file.image_sequence_fps > 0.0 {
-                    let fps = rendering::fps_to_rational(input_file.image_sequence_fps);
-                    decoder_options.set("framerate", &format!("{}/{}", fps.numerator(), fps.denominator()));
-                }
-                if input_file.image_sequence_start > 0 {
-                    decoder_options.set("start_number", &format!("{}", input_file.image_sequence_start));
-                }
-
                 let sync = std::rc::Rc::new(sync);
 
-                match VideoProcessor::from_file(&input_file.path, gpu_decoding, 0, Some(decoder_options)) {
-                    Ok(mut proc) => {
-                        let err2 = err.clone();
-                        let sync2 = sync.clone();
-                        proc.on_frame(move |timestamp_us, input_frame, _output_frame, converter, _rate_control| {
-                            assert!(_output_frame.is_none());
+                let err2 = err.clone();
+                let sync2 = sync.clone();
+                let cancel_flag2 = cancel_flag.clone();
+                let mut feed_frame = move |timestamp_us: i64, input_frame: &mut ffmpeg_next::frame::Video, converter: &mut rendering::Converter| {
+                    // Checked every 25 frames, throttling waits the equivalent of 20 ms per frame
+                    if abs_frame_no % 25 == 0 {
+                        jobs.wait_for_compute_slot(500, || cancel_flag2.load(SeqCst));
+                    }
 
-                            if abs_frame_no % every_nth_frame == 0 {
-                                match converter.scale(input_frame, ffmpeg_next::format::Pixel::GRAY8, sw, sh) {
-                                    Ok(small_frame) => {
-                                        let (width, height, stride, pixels) = (small_frame.plane_width(0), small_frame.plane_height(0), small_frame.stride(0), small_frame.data(0));
+                    if abs_frame_no % every_nth_frame == 0 {
+                        match converter.scale(input_frame, ffmpeg_next::format::Pixel::GRAY8, sw, sh) {
+                            Ok(small_frame) => {
+                                let (width, height, stride, pixels) = (small_frame.plane_width(0), small_frame.plane_height(0), small_frame.stride(0), small_frame.data(0));
 
-                                        sync2.feed_frame(timestamp_us, frame_no, width, height, stride, pixels);
-                                    },
-                                    Err(e) => {
-                                        err2(("An error occured: %1".to_string(), e.to_string()))
-                                    }
-                                }
-                                frame_no += 1;
+                                sync2.feed_frame(timestamp_us, frame_no, width, height, stride, pixels);
+                            },
+                            Err(e) => {
+                                err2(("An error occured: %1".to_string(), e.to_string()))
                             }
-                            abs_frame_no += 1;
+                        }
+                        frame_no += 1;
+                    }
+                    abs_frame_no += 1;
+                };
+
+                // Sync points are sparse, so seek to each of them in the shared frame server instead of running the whole decoder
+                if let Some(server) = rendering::frame_server::shared(&input_file) {
+                    let result = server.and_then(|server| {
+                        for (from_ms, to_ms) in ranges {
+                            if cancel_flag.load(SeqCst) { break; }
+                            // Locked per range, so other consumers (eg. the black bars detection) aren't blocked for the whole sync
+                            server.lock().decode_range(from_ms, to_ms, &cancel_flag, |timestamp_us, input_frame, converter| {
+                                feed_frame(timestamp_us, input_frame, converter);
+                                Ok(())
+                            })?;
+                        }
+                        Ok(())
+                    });
+                    if let Err(e) = result {
+                        err(GyroflowError::from_message(e.to_string()));
+                    }
+                    rendering::frame_server::close_shared(&input_file);
+                    sync.finished_feeding_frames();
+                    return;
+                }
+
+                match VideoProcessor::from_file(&input_file.path, gpu_decoding, 0, Some(rendering::frame_server::decoder_options(&input_file))) {
+                    Ok(mut proc) => {
+                        proc.set_video_filter(&input_file.video_filter);
+                        proc.on_frame(move |timestamp_us, input_frame, _output_frame, converter, _rate_control| {
+                            assert!(_output_frame.is_none());
+                            feed_frame(timestamp_us, input_frame, converter);
                             Ok(())
                         });
                         if let Err(e) = proc.start_decoder_only(ranges, cancel_flag.clone()) {
-                            err(("An error occured: %1".to_string(), e.to_string()));
+                            err(GyroflowError::from_message(e.to_string()));
                         }
                         sync.finished_feeding_frames();
                     }
                     Err(error) => {
-                        err(("An error occured: %1".to_string(), error.to_string()));
+                        err(GyroflowError::from_message(error.to_string()));
                     }
                 }
             });
         } else {
-            err(("An error occured: %1".to_string(), "Invalid parameters".to_string()));
+            err(GyroflowError::new(ErrorKind::InvalidParameters, core::i18n::Message::new("error.invalid_parameters").to_string()));
         }
     }
 
+    fn set_offsets(&mut self, offsets: Vec<(f64, f64, f64)>) {
+        let args = offsets.iter().map(|x| format!("{:.4}: {:.4}", x.0, x.1)).collect::<Vec<_>>();
+        self.stabilizer.record_change("set_offsets", &args, || {
+            let mut gyro = self.stabilizer.gyro.write();
+            for x in offsets {
+                ::log::info!("Setting offset at {:.4}: {:.4} (cost {:.4})", x.0, x.1, x.2);
+                let new_ts = ((x.0 - x.1) * 1000.0) as i64;
+                // Remove existing offsets within 100ms range
+                gyro.remove_offsets_near(new_ts, 100.0);
+                gyro.set_offset(new_ts, x.1);
+            }
+            self.stabilizer.keyframes.write().update_gyro(&gyro);
+        });
+        self.stabilizer.invalidate_zooming();
+    }
+    fn apply_staged_sync(&mut self) {
+        let offsets = std::mem::take(&mut self.staged_offsets);
+        if !offsets.is_empty() {
+            self.set_offsets(offsets);
+            self.update_offset_model();
+            self.request_recompute();
+        }
+    }
+    fn discard_staged_sync(&mut self) {
+        self.staged_offsets.clear();
+    }
+
     fn estimate_bias(&mut self, timestamps_fract: QString) {
         let timestamps_fract: Vec<f64> = timestamps_fract.to_string().split(';').filter_map(|x| x.parse::<f64>().ok()).collect();
 
@@ -441,8 +675,252 @@ impl Controller {
         }).collect();
 
         if !ranges_ms.is_empty() {
-            let bias = self.stabilizer.gyro.read().find_bias(ranges_ms[0].0, ranges_ms[0].1);
-            self.bias_estimated(bias.0, bias.1, bias.2);
+            let est = self.stabilizer.gyro.read().find_bias_multi(&ranges_ms);
+            ::log::info!("Estimated gyro bias: {:?}", est);
+            self.bias_estimated(est.bias.0, est.bias.1, est.bias.2, est.quality, est.still_segments, est.total_segments);
+        }
+    }
+
+    fn set_input_crop(&self, x: f64, y: f64, w: f64, h: f64) {
+        // Zero size disables the crop
+        let crop = if w > 0.0 && h > 0.0 { Some((x.max(0.0), y.max(0.0), w.min(1.0), h.min(1.0))) } else { None };
+        self.stabilizer.set_input_crop(crop);
+        self.request_recompute();
+    }
+
+    // Custom ffmpeg filtergraph applied to the decoded frames, stored in the project file.
+    // Returns the frame size after the filters, which is then the video size, or an empty array if the filters are invalid
+    fn set_video_filter(&mut self, filter: QString, player: QJSValue) -> QJsonArray {
+        let filter = filter.to_string().trim().to_string();
+        let vid = match player.to_qobject::<MDKVideoItem>() {
+            Some(vid) => unsafe { &mut *vid.as_ptr() }, // vid.borrow_mut()
+            None => return QJsonArray::default()
+        };
+        let video_size = (vid.videoWidth.max(0) as u32, vid.videoHeight.max(0) as u32);
+        let filtered_size = if filter.is_empty() || video_size.0 == 0 || video_size.1 == 0 {
+            video_size
+        } else {
+            match rendering::ffmpeg_filter::output_size(&filter, video_size) {
+                Ok(size) => size,
+                Err(e) => {
+                    self.show_error(GyroflowError::from_message(e.to_string()));
+                    return QJsonArray::default();
+                }
+            }
+        };
+        let preview_size = {
+            let mut preview_filter = self.preview_filter.lock();
+            preview_filter.filters = filter.clone();
+            preview_filter.video_size = video_size;
+            preview_filter.filtered_size = filtered_size;
+            preview_filter.filtered_preview_size()
+        };
+        if filtered_size.0 > 0 && filtered_size.1 > 0 {
+            self.stabilizer.set_video_filter(filter.clone(), (filtered_size.0 as usize, filtered_size.1 as usize));
+        } else {
+            self.stabilizer.input_file.write().video_filter = filter.clone(); // Video isn't loaded yet
+        }
+
+        // Zero copy preview skips the CPU frames, which are filtered in `init_player`
+        if !filter.is_empty() {
+            qrhi_undistort::deinit_player(vid.get_mdkplayer());
+        } else if self.zero_copy {
+            qrhi_undistort::init_player(vid.get_mdkplayer(), self.stabilizer.clone());
+        }
+        if preview_size.0 > 0 && preview_size.1 > 0 {
+            self.stabilizer.set_size(preview_size.0 as usize, preview_size.1 as usize);
+        }
+        self.request_recompute();
+        vid.setCurrentFrame(vid.currentFrame);
+
+        util::serde_json_to_qt_array(&serde_json::json!([filtered_size.0, filtered_size.1]))
+    }
+
+    fn detect_input_crop(&mut self) {
+        let (video_size, duration_ms) = {
+            let params = self.stabilizer.params.read();
+            (params.video_size, params.duration_ms)
+        };
+        if video_size.0 == 0 || video_size.1 == 0 || duration_ms <= 0.0 { return; }
+
+        let detected = util::qt_queued_callback_mut(self, |this, crop: Option<(f64, f64, f64, f64)>| {
+            if let Some(crop) = crop {
+                ::log::info!("Detected black bars, image area: {:?}", crop);
+                this.input_crop_detected(crop.0, crop.1, crop.2, crop.3);
+            }
+        });
+
+        // A few frames spread across the video, so a single dark scene doesn't affect the result
+        let ranges: Vec<(f64, f64)> = [0.1, 0.3, 0.5, 0.7, 0.9].iter().map(|x| (x * duration_ms, x * duration_ms + 40.0)).collect();
+        let (sw, sh) = (320u32, ((320 * video_size.1) / video_size.0).max(2) as u32);
+
+        let input_file = self.stabilizer.input_file.read().clone();
+        core::run_threaded(move || {
+            let detector = std::rc::Rc::new(RefCell::new(core::black_bars::BlackBarDetector::new()));
+            if let Some(server) = rendering::frame_server::shared(&input_file) {
+                let cancel_flag = std::sync::atomic::AtomicBool::new(false);
+                let result = server.and_then(|server| {
+                    let mut server = server.lock();
+                    for (from_ms, to_ms) in ranges {
+                        server.decode_range(from_ms, to_ms, &cancel_flag, |_timestamp_us, input_frame, converter| {
+                            let small_frame = converter.scale(input_frame, ffmpeg_next::format::Pixel::GRAY8, sw, sh)?;
+                            detector.borrow_mut().feed_frame(small_frame.plane_width(0), small_frame.plane_height(0), small_frame.stride(0), small_frame.data(0));
+                            Ok(())
+                        })?;
+                    }
+                    Ok(())
+                });
+                if let Err(e) = result {
+                    ::log::warn!("Failed to detect black bars: {:?}", e);
+                }
+                let result = detector.borrow().result();
+                detected(result);
+                return;
+            }
+            match VideoProcessor::from_file(&input_file.path, false, 0, Some(rendering::frame_server::decoder_options(&input_file))) {
+                Ok(mut proc) => {
+                    proc.set_video_filter(&input_file.video_filter);
+                    let detector2 = detector.clone();
+                    proc.on_frame(move |_timestamp_us, input_frame, _output_frame, converter, _rate_control| {
+                        let small_frame = converter.scale(input_frame, ffmpeg_next::format::Pixel::GRAY8, sw, sh)?;
+                        detector2.borrow_mut().feed_frame(small_frame.plane_width(0), small_frame.plane_height(0), small_frame.stride(0), small_frame.data(0));
+                        Ok(())
+                    });
+                    if let Err(e) = proc.start_decoder_only(ranges, Arc::new(std::sync::atomic::AtomicBool::new(false))) {
+                        ::log::warn!("Failed to detect black bars: {:?}", e);
+                    }
+                    let result = detector.borrow().result();
+                    detected(result);
+                }
+                Err(e) => {
+                    ::log::warn!("Failed to detect black bars: {:?}", e);
+                }
+            }
+        });
+    }
+
+    fn analyze_clip(&self) {
+        let finished = util::qt_queued_callback_mut(self, |this, report: Option<core::analysis::ClipAnalysis>| {
+            let report = report.and_then(|x| serde_json::to_value(x).ok()).unwrap_or_default();
+            this.clip_analyzed(util::serde_json_to_qt_object(&report));
+        });
+
+        let stab = self.stabilizer.clone();
+        let job = self.stabilizer.jobs.start(JobKind::Analysis);
+        core::run_threaded(move || {
+            let cancel_flag = job.cancel_flag.clone();
+            finished(stab.analyze_clip(cancel_flag));
+        });
+    }
+
+    fn search_smoothness(&self, target_crop: f64) {
+        let progress = util::qt_queued_callback_mut(self, |this, progress: f64| {
+            this.smoothness_search_progress(progress);
+        });
+        let finished = util::qt_queued_callback_mut(self, |this, result: Option<core::analysis::SmoothnessSearch>| {
+            ::log::info!("Smoothness search: {:?}", result);
+            let result = result.and_then(|x| serde_json::to_value(x).ok()).unwrap_or_default();
+            this.smoothness_search_finished(util::serde_json_to_qt_object(&result));
+        });
+
+        let stab = self.stabilizer.clone();
+        let job = self.stabilizer.jobs.start(JobKind::Analysis);
+        core::run_threaded(move || {
+            let cancel_flag = job.cancel_flag.clone();
+            finished(stab.search_smoothness(target_crop, progress, cancel_flag));
+        });
+    }
+
+    // Compares the gravity vectors with the optical flow of the sync points, optionally blending the horizon lock away where they drift
+    fn check_horizon_lock(&mut self, apply_fallback: bool) {
+        if let Some(report) = self.stabilizer.check_horizon_lock_stability() {
+            ::log::info!("Horizon lock stability: {:?}", report);
+            let fallback_applied = apply_fallback && !report.is_stable;
+            if apply_fallback {
+                // Also when stable, to remove the keyframes of the previous fallback
+                self.stabilizer.apply_horizon_lock_fallback(&report);
+                self.keyframes_changed();
+                self.request_recompute();
+            }
+            let mut obj = serde_json::to_value(report).unwrap_or_default();
+            obj["fallback_applied"] = serde_json::Value::Bool(fallback_applied);
+            obj["has_fallback"] = serde_json::Value::Bool(self.stabilizer.has_horizon_lock_fallback());
+            self.horizon_lock_stability(util::serde_json_to_qt_object(&obj));
+        }
+    }
+
+    fn suggest_smoothing(&self) {
+        let suggested = util::qt_queued_callback_mut(self, |this, s: core::analysis::SmoothingSuggestion| {
+            ::log::info!("Suggested smoothing: {:?}", s);
+            this.smoothing_suggested(s.smoothness, s.adaptive_zoom_window);
+        });
+
+        let stab = self.stabilizer.clone();
+        core::run_threaded(move || {
+            suggested(stab.suggest_smoothing());
+        });
+    }
+
+    // Emits `trim_suggested` with the current range if no handling was detected
+    fn suggest_trim(&self) {
+        let suggested = util::qt_queued_callback_mut(self, |this, s: Option<core::analysis::TrimSuggestion>| {
+            ::log::info!("Suggested trim: {:?}", s);
+            let s = s.unwrap_or_else(|| {
+                let params = this.stabilizer.params.read();
+                core::analysis::TrimSuggestion { trim_start: params.trim_start, trim_end: params.trim_end, ..Default::default() }
+            });
+            this.trim_suggested(s.trim_start, s.trim_end, s.start_handling_ms, s.end_handling_ms);
+        });
+
+        let stab = self.stabilizer.clone();
+        core::run_threaded(move || {
+            suggested(stab.suggest_trim());
+        });
+    }
+
+    // CSV or JSON depending on the extension, one row per dominant frequency band in every second of the video
+    fn export_vibration_bands(&self, url: QUrl) {
+        let path = util::url_to_path(url);
+        let finished = util::qt_queued_callback_mut(self, |this, (path, result): (String, std::io::Result<()>)| {
+            match result {
+                Ok(_) => this.message(QString::from("Vibration frequencies exported to %1."), QString::from(format!("<b>{}</b>", path)), QString::default()),
+                Err(e) => this.show_error(GyroflowError::from(e))
+            }
+        });
+
+        let stab = self.stabilizer.clone();
+        core::run_threaded(move || {
+            let duration_ms = stab.params.read().get_scaled_duration_ms();
+            let bands = core::analysis::vibration_bands(&stab.gyro.read(), duration_ms, 1000.0, 3);
+            let contents = if path.to_ascii_lowercase().ends_with(".json") {
+                serde_json::to_string_pretty(&bands).unwrap_or_default()
+            } else {
+                core::analysis::vibration_bands_to_csv(&bands)
+            };
+            let result = core::filesystem::write(&path, contents);
+            finished((path, result));
+        });
+    }
+
+    // Support bundle: app and system info, the thin project and the journal of parameter changes
+    fn export_diagnostics(&self, url: QUrl) {
+        let path = util::url_to_path(url);
+        let contents = {
+            let stab = &self.stabilizer;
+            let data = serde_json::json!({
+                "app_version": util::get_version(),
+                "os": std::env::consts::OS,
+                "arch": std::env::consts::ARCH,
+                "input": stab.input_file.read().path,
+                "project": stab.export_gyroflow_data(true, false, false, String::new()).ok()
+                    .and_then(|x| serde_json::from_str::<serde_json::Value>(&x).ok()),
+                "parameter_journal": stab.journal.read().to_json(),
+            });
+            serde_json::to_string_pretty(&data).unwrap_or_default()
+        };
+        match core::filesystem::write(&path, contents) {
+            Ok(_) => self.message(QString::from("Diagnostics exported to %1."), QString::from(format!("<b>{}</b>", path)), QString::default()),
+            Err(e) => self.show_error(GyroflowError::from(e))
         }
     }
 
@@ -466,7 +944,62 @@ impl Controller {
             chart.setSyncResultsQuats(&*self.stabilizer.pose_estimator.estimated_quats.read());
 
             chart.setFromGyroSource(&self.stabilizer.gyro.read());
+            chart.setZoomCurve(&self.stabilizer.params.read().zoom_curve);
+        }
+    }
+
+    fn update_zoom_curve(&mut self, chart: QJSValue) {
+        if let Some(chart) = chart.to_qobject::<TimelineGyroChart>() {
+            let chart = unsafe { &mut *chart.as_ptr() }; // _self.borrow_mut();
+
+            chart.setZoomCurve(&self.stabilizer.params.read().zoom_curve);
+        }
+    }
+
+    // Gyro in deg/s and accelerometer in m/s² instead of normalized values, with the applied rotation and bias
+    fn set_chart_physical_units(&mut self, chart: QJSValue, enabled: bool) {
+        if let Some(c) = chart.to_qobject::<TimelineGyroChart>() {
+            let c = unsafe { &mut *c.as_ptr() };
+            c.physical_units = enabled;
         }
+        self.update_chart(chart);
+    }
+    // Signal before the low pass filter, drawn under the filtered one
+    fn set_chart_unfiltered_overlay(&mut self, chart: QJSValue, enabled: bool) {
+        if let Some(c) = chart.to_qobject::<TimelineGyroChart>() {
+            let c = unsafe { &mut *c.as_ptr() };
+            c.show_unfiltered = enabled;
+        }
+        self.update_chart(chart);
+    }
+
+    // The user performs `motion` ("tilt_up", "pan_right", "roll_left" etc.) in the video between the start and stop timestamps
+    fn start_axis_capture(&mut self, motion: String, timestamp_ms: f64) -> bool {
+        match core::axis_remap::CameraMotion::from_name(&motion) {
+            Some(motion) => { self.axis_capture_start = Some((motion, timestamp_ms)); true },
+            None => { ::log::warn!("Unknown camera motion: {}", motion); false }
+        }
+    }
+    fn stop_axis_capture(&mut self, timestamp_ms: f64) {
+        if let Some((motion, start_ms)) = self.axis_capture_start.take() {
+            let sync_results: Vec<_> = self.stabilizer.pose_estimator.estimated_gyro.read().values().cloned().collect();
+            let capture = core::axis_remap::AxisCapture::new(motion, start_ms, timestamp_ms, &self.stabilizer.gyro.read(), &sync_results);
+            let error = match capture {
+                Ok(capture) => {
+                    // Newer capture of the same motion replaces the previous one
+                    self.axis_captures.retain(|x| x.motion != capture.motion);
+                    self.axis_captures.push(capture);
+                    String::new()
+                },
+                Err(e) => e
+            };
+            let result = core::axis_remap::deduce_orientation(&self.axis_captures);
+            self.axis_remap_updated(QString::from(result.map(|x| serde_json::to_string(&x).unwrap_or_default()).unwrap_or_default()), QString::from(error));
+        }
+    }
+    fn clear_axis_captures(&mut self) {
+        self.axis_captures.clear();
+        self.axis_capture_start = None;
     }
 
     fn update_frequency_graph(&mut self, graph: QJSValue, idx: usize, ts: f64, sr: f64, fft_size: usize) {
@@ -540,9 +1073,30 @@ impl Controller {
         util::qt_queued_callback(self, |this, _| {
             this.offsets_updated();
             this.chart_data_changed();
+            this.update_shake_model();
         })(());
     }
 
+    // Offsets change the gyro to video mapping, so this is updated together with the offsets model
+    fn update_shake_model(&self) {
+        let updated = util::qt_queued_callback_mut(self, |this, shake: Vec<f64>| {
+            this.shake_model = RefCell::new(shake.into_iter().enumerate().map(|(i, v)| ShakeItem {
+                timestamp_us: i as i64 * 1_000_000,
+                duration_us: 1_000_000,
+                shake: v,
+                level: (v / core::analysis::SEVERE_SHAKE).min(1.0)
+            }).collect());
+            this.shake_updated();
+        });
+
+        let stab = self.stabilizer.clone();
+        core::run_threaded(move || {
+            let duration_ms = stab.params.read().get_scaled_duration_ms();
+            let shake = core::analysis::shake_per_second(&stab.gyro.read(), duration_ms);
+            updated(shake);
+        });
+    }
+
     fn video_file_loaded(&mut self, url: QUrl, player: QJSValue) {
         let s = util::url_to_path(url);
         let stab = self.stabilizer.clone();
@@ -558,7 +1112,15 @@ impl Controller {
 
             if duration_ms > 0.0 && fps > 0.0 {
                 if let Ok(_) = stab.init_from_video_data(&s, duration_ms, fps, frame_count, video_size) {
-                    stab.set_output_size(video_size.0, video_size.1);
+                    // The player doesn't report the pixel aspect ratio, so read it from the container
+                    let par = rendering::FfmpegProcessor::get_video_info(&s).map(|x| x.pixel_aspect_ratio).unwrap_or(1.0);
+                    if (par - 1.0).abs() > 0.0001 {
+                        ::log::info!("Non-square pixels, pixel aspect ratio: {:.4}", par);
+                    }
+                    stab.set_pixel_aspect_ratio(par);
+                    let display_size = stab.params.read().get_display_size();
+                    stab.set_output_size(display_size.0, display_size.1);
+                    self.detect_input_crop();
                 }
             }
         }
@@ -577,15 +1139,15 @@ impl Controller {
             let fps = vid.frameRate;
             let frame_count = vid.frameCount as usize;
             let video_size = (vid.videoWidth as usize, vid.videoHeight as usize);
-            self.cancel_flag.store(false, SeqCst);
-            let cancel_flag = self.cancel_flag.clone();
+            let job = self.stabilizer.jobs.start(JobKind::LoadTelemetry);
+            let cancel_flag = job.cancel_flag.clone();
 
             if is_main_video {
                 self.set_preview_resolution(self.preview_resolution, player);
             }
 
-            let err = util::qt_queued_callback_mut(self, |this, (msg, arg): (String, String)| {
-                this.error(QString::from(msg), QString::from(arg), QString::default());
+            let err = util::qt_queued_callback_mut(self, |this, e: GyroflowError| {
+                this.show_error(e);
             });
 
             let progress = util::qt_queued_callback_mut(self, move |this, progress: f64| {
@@ -613,6 +1175,14 @@ impl Controller {
             let load_lens = util::qt_queued_callback_mut(self, move |this, path: String| {
                 this.load_lens_profile(path);
             });
+            let lens_ambiguous = util::qt_queued_callback_mut(self, move |this, (candidates, lens_info, gimbal_mode): (Vec<(String, String)>, String, String)| {
+                // Each item is [key, display name]
+                let candidates = serde_json::to_string(&candidates).unwrap_or_default();
+                this.lens_profile_ambiguous(QString::from(candidates), QString::from(lens_info), QString::from(gimbal_mode));
+            });
+            let lens_closest = util::qt_queued_callback_mut(self, move |this, (name, lens_info, gimbal_mode): (String, String, String)| {
+                this.lens_profile_closest(QString::from(name), QString::from(lens_info), QString::from(gimbal_mode));
+            });
             let reload_lens = util::qt_queued_callback_mut(self, move |this, _| {
                 let lens = this.stabilizer.lens.read();
                 if this.lens_loaded || !lens.filename.is_empty() {
@@ -629,21 +1199,40 @@ impl Controller {
                         this.realtime_fps_loaded(md_fps);
                     }
                 }
+                // Camera was recorded upside-down or sideways, rotate the video back.
+                // Video rotation is applied after stabilization, so the IMU orientation stays as it is in the file.
+                // Projects store the orientation which was already added, so it's not added again when they are loaded
+                if let Some(camera_orientation) = md.camera_orientation {
+                    let applied = this.stabilizer.params.read().camera_orientation_applied;
+                    if is_main_video && camera_orientation != 0.0 && applied != Some(camera_orientation) {
+                        let rotation = {
+                            let mut params = this.stabilizer.params.write();
+                            params.video_rotation = (params.video_rotation - applied.unwrap_or_default() + camera_orientation).rem_euclid(360.0);
+                            params.camera_orientation_applied = Some(camera_orientation);
+                            params.video_rotation
+                        };
+                        ::log::info!("Camera orientation: {}°, setting video rotation to {}°", camera_orientation, rotation);
+                        this.orientation_auto_applied(rotation, camera_orientation);
+                        this.request_recompute();
+                    }
+                }
             });
 
             if duration_ms > 0.0 && fps > 0.0 {
                 core::run_threaded(move || {
+                    let _job = job;
                     let mut file_metadata = None;
                     if is_main_video {
                         if let Err(e) = stab.init_from_video_data(&s, duration_ms, fps, frame_count, video_size) {
-                            err(("An error occured: %1".to_string(), e.to_string()));
+                            err(GyroflowError::from_message(e.to_string()));
                         } else {
                             // Ignore the error here, video file may not contain the telemetry and it's ok
                             if let Ok(md) = stab.load_gyro_data(&s, progress, cancel_flag) {
                                 file_metadata = Some(md);
                             }
 
-                            if stab.set_output_size(video_size.0, video_size.1) {
+                            let display_size = stab.params.read().get_display_size();
+                            if stab.set_output_size(display_size.0, display_size.1) {
                                 stab.recompute_undistortion();
                             }
                         }
@@ -653,14 +1242,14 @@ impl Controller {
                                 file_metadata = Some(md);
                             },
                             Err(e) => {
-                                err(("An error occured: %1".to_string(), e.to_string()));
+                                err(GyroflowError::from_message(e.to_string()));
                             }
                         }
                     }
                     stab.recompute_smoothness();
 
                     let gyro = stab.gyro.read();
-                    let detected = gyro.detected_source.as_ref().map(String::clone).unwrap_or_default();
+                    let detected = gyro.detected_source.as_deref().map(core::i18n::translate_english).unwrap_or_default();
                     let orientation = gyro.imu_orientation.as_ref().map(String::clone).unwrap_or_else(|| "XYZ".into());
                     let has_raw_gyro = !gyro.org_raw_imu.is_empty();
                     let has_quats = !gyro.org_quaternions.is_empty();
@@ -678,11 +1267,26 @@ impl Controller {
                     }
                     let camera_id = stab.camera_id.read();
 
-                    let id_str = camera_id.as_ref().map(|v| v.identifier.clone()).unwrap_or_default();
-                    if is_main_video && !id_str.is_empty() {
+                    if let Some(id) = camera_id.as_ref().filter(|_| is_main_video) {
                         let db = stab.lens_profile_db.read();
-                        if db.contains_id(&id_str) {
-                            load_lens(id_str);
+                        match db.find_best_match(id) {
+                            Some(core::lens_profile_database::ProfileMatch::Exact(key)) => load_lens(key),
+                            // Lens and FOV mode of DJI clips are matched against the profile variants
+                            Some(core::lens_profile_database::ProfileMatch::Closest(key)) if id.brand == "DJI" => {
+                                ::log::info!("Lens profile {} selected for {} (lens: {}, gimbal mode: {})", key, id.model, id.lens_info, id.gimbal_mode);
+                                // Not an exact match, so the user is asked to verify it
+                                let name = db.get_by_id(&key).map(|x| x.get_display_name()).unwrap_or_else(|| key.clone());
+                                lens_closest((name, id.lens_info.clone(), id.gimbal_mode.clone()));
+                                load_lens(key);
+                            },
+                            Some(core::lens_profile_database::ProfileMatch::Ambiguous(keys)) if id.brand == "DJI" => {
+                                let candidates = keys.into_iter().map(|key| {
+                                    let name = db.get_by_id(&key).map(|x| x.get_display_name()).unwrap_or_else(|| key.clone());
+                                    (key, name)
+                                }).collect();
+                                lens_ambiguous((candidates, id.lens_info.clone(), id.gimbal_mode.clone()));
+                            },
+                            _ => { }
                         }
                     }
                     reload_lens(());
@@ -703,8 +1307,8 @@ impl Controller {
     }
     fn load_lens_profile(&mut self, path: String) {
         let (json, filepath) = {
-            if let Err(e) = self.stabilizer.load_lens_profile(&path) {
-                self.error(QString::from("An error occured: %1"), QString::from(e.to_string()), QString::default());
+            if let Err(e) = self.stabilizer.record_change("load_lens_profile", &[format!("{:?}", path)], || self.stabilizer.load_lens_profile(&path)) {
+                self.show_error(GyroflowError::new(ErrorKind::InvalidLensProfile, e.to_string()).with_action(SuggestedAction::PickLensProfile));
             }
             let lens = self.stabilizer.lens.read();
             (lens.get_json().unwrap_or_default(), lens.filename.clone())
@@ -715,27 +1319,59 @@ impl Controller {
         self.request_recompute();
     }
 
+    // target_height: -1 = full, 0 = auto
     fn set_preview_resolution(&mut self, target_height: i32, player: QJSValue) {
         self.preview_resolution = target_height;
         if let Some(vid) = player.to_qobject::<MDKVideoItem>() {
             let vid = unsafe { &mut *vid.as_ptr() }; // vid.borrow_mut()
 
-            // fn aligned_to_8(mut x: u32) -> u32 { if x % 8 != 0 { x += 8 - x % 8; } x }
+            let target_height = {
+                let mut ladder = self.preview_quality.lock();
+                ladder.enabled = target_height == 0;
+                ladder.target_fps = vid.frameRate;
+                ladder.video_height = vid.videoHeight;
+                if ladder.enabled {
+                    ladder.reset();
+                    -1
+                } else {
+                    target_height
+                }
+            };
+            self.resize_preview(target_height, vid);
+        }
+    }
+
+    // In auto mode, the preview is processed in full quality when paused
+    fn set_preview_playing(&mut self, playing: bool, player: QJSValue) {
+        if let Some(vid) = player.to_qobject::<MDKVideoItem>() {
+            let vid = unsafe { &mut *vid.as_ptr() }; // vid.borrow_mut()
+
+            let target_height = {
+                let mut ladder = self.preview_quality.lock();
+                ladder.playing = playing;
+                if !ladder.enabled { return; }
+                if playing { ladder.current_height() as i32 } else { -1 }
+            };
+            self.resize_preview(target_height, vid);
+        }
+    }
 
-            if !self.stabilizer.input_file.read().path.is_empty() {
-                let h = if target_height > 0 { target_height as u32 } else { vid.videoHeight };
-                let ratio = vid.videoHeight as f64 / h as f64;
-                let new_w = (vid.videoWidth as f64 / ratio).floor() as u32;
-                let new_h = (vid.videoHeight as f64 / (vid.videoWidth as f64 / new_w as f64)).floor() as u32;
-                ::log::info!("surface size: {}x{}", new_w, new_h);
+    fn resize_preview(&mut self, target_height: i32, vid: &mut MDKVideoItem) {
+        // fn aligned_to_8(mut x: u32) -> u32 { if x % 8 != 0 { x += 8 - x % 8; } x }
 
-                self.stabilizer.pose_estimator.rescale(new_w, new_h);
-                self.chart_data_changed();
+        if !self.stabilizer.input_file.read().path.is_empty() {
+            let h = if target_height > 0 { target_height as u32 } else { vid.videoHeight };
+            let ratio = vid.videoHeight as f64 / h as f64;
+            let new_w = (vid.videoWidth as f64 / ratio).floor() as u32;
+            let new_h = (vid.videoHeight as f64 / (vid.videoWidth as f64 / new_w as f64)).floor() as u32;
+            ::log::info!("surface size: {}x{}", new_w, new_h);
 
-                vid.setSurfaceSize(new_w, new_h);
-                vid.setRotation(vid.getRotation());
-                vid.setCurrentFrame(vid.currentFrame);
-            }
+            self.stabilizer.pose_estimator.rescale(new_w, new_h);
+            self.chart_data_changed();
+
+            vid.setSurfaceSize(new_w, new_h);
+            vid.setRotation(vid.getRotation());
+            vid.setCurrentFrame(vid.currentFrame);
         }
     }
 
@@ -752,24 +1388,25 @@ impl Controller {
         }
 
         core::run_threaded(move || {
-            {
+            stab.record_change("set_integration_method", &[index.to_string()], || {
                 stab.invalidate_ongoing_computations();
 
                 let mut gyro = stab.gyro.write();
                 gyro.integration_method = index;
                 gyro.integrate();
                 stab.smoothing.write().update_quats_checksum(&gyro.quaternions);
-            }
+            });
             stab.invalidate_smoothing();
             finished(());
         });
     }
 
-    fn set_zero_copy(&self, player: QJSValue, enabled: bool) {
+    fn set_zero_copy(&mut self, player: QJSValue, enabled: bool) {
+        self.zero_copy = enabled;
         if let Some(vid) = player.to_qobject::<MDKVideoItem>() {
             let vid = unsafe { &mut *vid.as_ptr() }; // vid.borrow_mut()
 
-            if enabled {
+            if enabled && !self.preview_filter.lock().is_active() {
                 qrhi_undistort::init_player(vid.get_mdkplayer(), self.stabilizer.clone());
             } else {
                 qrhi_undistort::deinit_player(vid.get_mdkplayer());
@@ -778,6 +1415,14 @@ impl Controller {
         }
     }
 
+    fn set_preview_hdr(&self, player: QJSValue, mode: i32) {
+        qrhi_undistort::set_hdr_output(mode);
+        if let Some(vid) = player.to_qobject::<MDKVideoItem>() {
+            let vid = unsafe { &mut *vid.as_ptr() }; // vid.borrow_mut()
+            vid.setCurrentFrame(vid.currentFrame);
+        }
+    }
+
     fn set_gpu_decoding(&self, enabled: bool) {
         *rendering::GPU_DECODING.write() = enabled;
     }
@@ -800,7 +1445,14 @@ impl Controller {
             self.stabilizer.params.write().background = Vector4::new(bg_color.0 as f32 * 255.0, bg_color.1 as f32 * 255.0, bg_color.2 as f32 * 255.0, bg_color.3 as f32 * 255.0);
 
             let stab = self.stabilizer.clone();
+            let preview_filter = self.preview_filter.clone();
             vid.onResize(Box::new(move |width, height| {
+                // With video filters, the preview is stabilized at the filtered size
+                let (width, height) = {
+                    let mut preview_filter = preview_filter.lock();
+                    preview_filter.preview_size = (width as u32, height as u32);
+                    preview_filter.filtered_preview_size()
+                };
                 let current_size = stab.params.read().size;
                 if current_size.0 != width as usize || current_size.1 != height as usize {
                     stab.set_size(width as usize, height as usize);
@@ -810,14 +1462,53 @@ impl Controller {
                 }
             }));
 
+            let vid_ptr = QPointer::from(&*vid);
+            let switch_resolution = util::qt_queued_callback_mut(self, move |this, height: u32| {
+                if !this.preview_quality.lock().playing { return; }
+                if let Some(vid) = vid_ptr.as_pinned() {
+                    let vid = unsafe { &mut *vid.as_ptr() };
+                    this.resize_preview(height as i32, vid);
+                }
+            });
+
             let stab = self.stabilizer.clone();
+            let ladder = self.preview_quality.clone();
             let out_pixels = RefCell::new(Vec::new());
+            let preview_filter = self.preview_filter.clone();
+            let frame_filter = RefCell::new(None::<rendering::ffmpeg_filter::PreviewFilter>);
             vid.onProcessPixels(Box::new(move |_frame, timestamp_ms, width, height, stride, pixels: &mut [u8]| -> (u32, u32, u32, *mut u8) {
-                // let _time = std::time::Instant::now();
+                let time = std::time::Instant::now();
+
+                let mut filtered = None;
+                {
+                    let mut params = preview_filter.lock().clone();
+                    params.preview_size = (width as u32, height as u32);
+                    let mut frame_filter = frame_filter.borrow_mut();
+                    if !params.is_active() {
+                        *frame_filter = None;
+                    } else {
+                        if frame_filter.as_ref().map_or(true, |x| x.params != params) {
+                            *frame_filter = Some(rendering::ffmpeg_filter::PreviewFilter::new(params));
+                        }
+                        match frame_filter.as_mut().unwrap().process((timestamp_ms * 1000.0) as i64, stride as usize, pixels) {
+                            Ok(Some(frame)) => filtered = Some(frame),
+                            Ok(None) => { }
+                            Err(e) => ::log::error!("Preview filter error: {}", e)
+                        }
+                        // The stabilization is set up for the filtered size, show the original frame until the filters output one
+                        if filtered.is_none() {
+                            return (0, 0, 0, std::ptr::null_mut());
+                        }
+                    }
+                }
+                let (width, height, stride, pixels) = match filtered.as_mut() {
+                    Some(frame) => (frame.width() as _, frame.height() as _, frame.stride(0) as _, frame.data_mut(0)),
+                    None => (width, height, stride, pixels)
+                };
 
                 // TODO: cache in atomics instead of locking the mutex every time
                 let (ow, oh) = stab.params.read().output_size;
-                let os = ow * 4; // Assume RGBA8 - 4 bytes per pixel
+                let os = ow * 4; // The player only delivers RGBA8 frames, so the preview stays 8-bit. Higher bit depths are only processed in the export
 
                 let mut out_pixels = out_pixels.borrow_mut();
                 out_pixels.resize_with(os*oh, u8::default);
@@ -834,7 +1525,10 @@ impl Controller {
                     input_rect: None, output_rect: None
                 });
 
-                // println!("Frame {:.3}, {}x{}, {:.2} MB | OpenCL {:.3}ms", timestamp_ms, width, height, pixels.len() as f32 / 1024.0 / 1024.0, _time.elapsed().as_micros() as f64 / 1000.0);
+                // println!("Frame {:.3}, {}x{}, {:.2} MB | OpenCL {:.3}ms", timestamp_ms, width, height, pixels.len() as f32 / 1024.0 / 1024.0, time.elapsed().as_micros() as f64 / 1000.0);
+                if let Some(new_height) = ladder.lock().add_frame_time(time.elapsed().as_micros() as f64 / 1000.0) {
+                    switch_resolution(new_height);
+                }
                 if ret {
                     (ow as u32, oh as u32, os as u32, out_pixels.as_mut_ptr())
                 } else {
@@ -857,17 +1551,18 @@ impl Controller {
     }
 
     fn set_smoothing_method(&mut self, index: usize) -> QJsonArray {
-        let params = util::serde_json_to_qt_array(&self.stabilizer.set_smoothing_method(index));
+        let params = util::serde_json_to_qt_array(&self.stabilizer.record_change("set_smoothing_method", &[index.to_string()], || self.stabilizer.set_smoothing_method(index)));
         self.request_recompute();
         self.chart_data_changed();
         params
     }
     fn set_smoothing_param(&mut self, name: QString, val: f64) {
-        self.stabilizer.set_smoothing_param(&name.to_string(), val);
+        self.stabilizer.record_change(&format!("set_smoothing_param({})", name), &[val.to_string()], || self.stabilizer.set_smoothing_param(&name.to_string(), val));
         self.chart_data_changed();
         self.request_recompute();
     }
     wrap_simple_method!(set_horizon_lock, lock_percent: f64, roll: f64; recompute; chart_data_changed);
+    wrap_simple_method!(set_horizon_pitch_lock, lock_percent: f64; recompute; chart_data_changed);
     wrap_simple_method!(set_use_gravity_vectors, v: bool; recompute; chart_data_changed);
     pub fn get_smoothing_algs(&self) -> QVariantList {
         self.stabilizer.get_smoothing_algs().into_iter().map(QString::from).collect()
@@ -894,8 +1589,19 @@ impl Controller {
         self.compute_progress(id, 0.0);
     }
 
+    // Cancels all foreground operations, renders are cancelled separately in the render queue
     fn cancel_current_operation(&mut self) {
-        self.cancel_flag.store(true, SeqCst);
+        self.stabilizer.jobs.cancel_kinds(&[JobKind::Sync, JobKind::LoadTelemetry, JobKind::Import, JobKind::Calibration]);
+    }
+    fn set_compute_policy(&self, policy: i32) {
+        self.stabilizer.jobs.set_compute_policy(ComputePolicy::from(policy));
+    }
+    // Has to be called after the controller is exposed to QML
+    pub fn init_gpu_fallback(&self) {
+        let notify = util::qt_queued_callback_mut(self, |this, (failed, used): (String, String)| {
+            this.gpu_fallback(QString::from(failed), QString::from(used));
+        });
+        core::gpu::context_manager::set_on_fallback(move |failed, used| notify((failed.to_string(), used.to_string())));
     }
 
     fn export_gyroflow_file(&self, thin: bool, extended: bool, additional_data: QJsonObject, override_location: QString, overwrite: bool) {
@@ -910,7 +1616,7 @@ impl Controller {
         if !overwrite && std::path::Path::new(&gf_path).exists() {
             self.gyroflow_exists(QString::from(gf_path), thin, extended);
         } else {
-            match self.stabilizer.export_gyroflow_file(&gf_path, thin, extended, additional_data.to_json().to_string()) {
+            match self.stabilizer.export_gyroflow_file(&gf_path, thin, extended, self.embed_gyro_file, additional_data.to_json().to_string()) {
                 Ok(_) => {
                     self.message(QString::from("Gyroflow file exported to %1."), QString::from(format!("<b>{}</b>", gf_path)), QString::default());
                 },
@@ -918,20 +1624,20 @@ impl Controller {
                     self.request_location(QString::from(gf_path), thin, extended);
                 },
                 Err(e) => {
-                    self.error(QString::from("An error occured: %1"), QString::from(e.to_string()), QString::default());
+                    self.show_error(GyroflowError::from(e));
                 }
             }
         }
     }
 
     fn export_gyroflow_data(&self, thin: bool, extended: bool, additional_data: QJsonObject) -> QString {
-        QString::from(self.stabilizer.export_gyroflow_data(thin, extended, additional_data.to_json().to_string()).unwrap_or_default())
+        QString::from(self.stabilizer.export_gyroflow_data(thin, extended, self.embed_gyro_file, additional_data.to_json().to_string()).unwrap_or_default())
     }
 
     fn get_paths_from_gyroflow_file(&mut self, url: QUrl) -> QStringList {
         let mut ret = vec![QString::default(); 2];
         let path = util::url_to_path(url);
-        if let Ok(data) = std::fs::read(&path) {
+        if let Ok(data) = core::filesystem::read(&path) {
             let path = std::path::Path::new(&path).to_path_buf();
 
             if let Ok(serde_json::Value::Object(obj)) = serde_json::from_slice(&data) {
@@ -975,17 +1681,25 @@ impl Controller {
             let obj = this.import_gyroflow_internal(obj);
             this.gyroflow_file_loaded(obj);
         });
+        let damaged = util::qt_queued_callback_mut(self, move |this, (obj, dropped, error): (serde_json::Value, Vec<String>, String)| {
+            this.gyroflow_file_damaged(QString::from(obj.to_string()), QString::from(dropped.join(", ")), QString::from(error));
+        });
 
         let stab = self.stabilizer.clone();
-        let cancel_flag = self.cancel_flag.clone();
-        cancel_flag.store(true, SeqCst);
+        let job = self.start_import_job();
         core::run_threaded(move || {
-            if Arc::strong_count(&cancel_flag) > 2 {
-                // Wait for other tasks to finish
-                std::thread::sleep(std::time::Duration::from_millis(200));
+            Self::wait_for_loading_jobs(&stab);
+            // Damaged files are not imported directly, the user decides whether to continue with the recovered sections
+            if let Ok(data) = core::filesystem::read(&path) {
+                if let Err(e) = serde_json::from_slice::<serde_json::Value>(&data) {
+                    if let Some((obj, dropped)) = StabilizationManager::<stabilization::RGBA8>::recover_gyroflow_data(&data) {
+                        ::log::warn!("Project file {} is damaged: {}, dropped sections: {:?}", path, e, dropped);
+                        damaged((obj, dropped, e.to_string()));
+                        return;
+                    }
+                }
             }
-            cancel_flag.store(false, SeqCst);
-            finished(stab.import_gyroflow_file(&path, false, progress, cancel_flag));
+            finished(stab.record_change("import_gyroflow_file", &[format!("{:?}", path)], || stab.import_gyroflow_file(&path, false, progress, job.cancel_flag.clone())));
         });
     }
     fn import_gyroflow_data(&mut self, data: QString) {
@@ -1004,17 +1718,23 @@ impl Controller {
         });
 
         let stab = self.stabilizer.clone();
-        let cancel_flag = self.cancel_flag.clone();
-        cancel_flag.store(true, SeqCst);
+        let job = self.start_import_job();
         core::run_threaded(move || {
-            if Arc::strong_count(&cancel_flag) > 2 {
-                // Wait for other tasks to finish
-                std::thread::sleep(std::time::Duration::from_millis(200));
-            }
-            cancel_flag.store(false, SeqCst);
-            finished(stab.import_gyroflow_data(data.to_string().as_bytes(), false, None, progress, cancel_flag));
+            Self::wait_for_loading_jobs(&stab);
+            finished(stab.record_change("import_gyroflow_data", &[], || stab.import_gyroflow_data(data.to_string().as_bytes(), false, None, progress, job.cancel_flag.clone())));
         });
     }
+
+    // Importing a project replaces the gyro data, so cancel everything that's still loading or syncing the previous one
+    fn start_import_job(&self) -> JobToken {
+        self.stabilizer.jobs.cancel_kinds(&[JobKind::Import, JobKind::LoadTelemetry, JobKind::Sync]);
+        self.stabilizer.jobs.start(JobKind::Import)
+    }
+    fn wait_for_loading_jobs(stab: &StabilizationManager<stabilization::RGBA8>) {
+        if !stab.jobs.wait_for(&[JobKind::LoadTelemetry, JobKind::Sync], 2000) {
+            ::log::warn!("Previous jobs didn't finish in time");
+        }
+    }
     fn import_gyroflow_internal(&mut self, result: std::io::Result<serde_json::Value>) -> QJsonObject {
         match result {
             Ok(thin_obj) => {
@@ -1024,13 +1744,17 @@ impl Controller {
                     let lens_json = self.stabilizer.lens.read().get_json().unwrap_or_default();
                     self.lens_profile_loaded(QString::from(lens_json), QString::default());
                 }
+                if thin_obj.get("videofile_modified").and_then(|x| x.as_bool()).unwrap_or_default() {
+                    let video_path = thin_obj.get("videofile").and_then(|x| x.as_str()).unwrap_or_default();
+                    self.message(QString::from("The video file %1 has changed since the project was saved (it was re-encoded or replaced).\nSynchronization offsets and lens calibration may no longer apply."), QString::from(format!("<b>{}</b>", video_path)), QString::default());
+                }
                 self.request_recompute();
                 self.chart_data_changed();
                 self.keyframes_changed();
                 util::serde_json_to_qt_object(&thin_obj)
             },
             Err(e) => {
-                self.error(QString::from("An error occured: %1"), QString::from(e.to_string()), QString::default());
+                self.show_error(GyroflowError::new(ErrorKind::InvalidProject, e.to_string()));
                 QJsonObject::default()
             }
         }
@@ -1055,11 +1779,33 @@ impl Controller {
     wrap_simple_method!(set_adaptive_zoom,      v: f64; recompute);
     wrap_simple_method!(set_zooming_center_x,   v: f64; recompute);
     wrap_simple_method!(set_zooming_center_y,   v: f64; recompute);
+    wrap_simple_method!(set_max_zoom,           v: f64; recompute);
+    wrap_simple_method!(set_zoom_border_tolerance, v: f64; recompute);
     wrap_simple_method!(set_trim_start,         v: f64; recompute; chart_data_changed);
     wrap_simple_method!(set_trim_end,           v: f64; recompute; chart_data_changed);
+
+    fn set_trim_range(&mut self, trim_start: f64, trim_end: f64, keyframes_policy: String, offsets_policy: String) {
+        let args = [trim_start.to_string(), trim_end.to_string(), keyframes_policy.clone(), offsets_policy.clone()];
+        let (keyframes, offsets) = self.stabilizer.record_change("set_trim_range", &args, || {
+            self.stabilizer.set_trim_range(trim_start, trim_end, TrimPolicy::from(keyframes_policy.as_str()), TrimPolicy::from(offsets_policy.as_str()))
+        });
+        if keyframes > 0 {
+            ::log::info!("Trim range changed, {} keyframes updated ({})", keyframes, keyframes_policy);
+            self.keyframes_changed();
+        }
+        if offsets > 0 {
+            ::log::info!("Trim range changed, {} sync points updated ({})", offsets, offsets_policy);
+            self.update_offset_model();
+        }
+        self.request_recompute();
+        self.chart_data_changed();
+    }
     wrap_simple_method!(set_of_method,          v: u32; recompute; chart_data_changed);
 
     wrap_simple_method!(set_lens_correction_amount,    v: f64; recompute);
+    wrap_simple_method!(set_stabilization_amount,      v: f64; recompute);
+    wrap_simple_method!(set_output_projection,         v: i32; recompute);
+    wrap_simple_method!(set_output_projection_strength, v: f64; recompute);
     wrap_simple_method!(set_input_horizontal_stretch,  v: f64; recompute);
     wrap_simple_method!(set_lens_is_asymmetrical,      v: bool; recompute);
     wrap_simple_method!(set_input_vertical_stretch,    v: f64; recompute);
@@ -1073,8 +1819,64 @@ impl Controller {
     wrap_simple_method!(remove_offset, timestamp_us: i64; recompute; update_offset_model);
 
     wrap_simple_method!(set_imu_lpf, v: f64; recompute; chart_data_changed);
+    wrap_simple_method!(set_imu_lpf_type, filter_type: usize, order: usize; recompute; chart_data_changed);
+    wrap_simple_method!(set_imu_lpf_axes, x: f64, y: f64, z: f64; recompute; chart_data_changed);
     wrap_simple_method!(set_imu_rotation, pitch_deg: f64, roll_deg: f64, yaw_deg: f64; recompute; chart_data_changed);
     wrap_simple_method!(set_acc_rotation, pitch_deg: f64, roll_deg: f64, yaw_deg: f64; recompute; chart_data_changed);
+    wrap_simple_method!(set_extrinsic_rotation, pitch_deg: f64, roll_deg: f64, yaw_deg: f64; recompute; chart_data_changed);
+
+    fn get_pixel_aspect_ratio(&self) -> f64 {
+        self.stabilizer.params.read().pixel_aspect_ratio
+    }
+
+    // Returns [pitch, roll, yaw] in degrees from a rig calibration file or a lens profile calibrated with the rig
+    fn load_rig_calibration(&self, url: QUrl) -> QJsonArray {
+        let result = core::filesystem::read_to_string(&util::url_to_path(url)).map_err(GyroflowError::from).and_then(|json| {
+            Ok(core::calibration::rig::RigRotation::from_json(&json)?)
+        });
+        match result {
+            Ok(rig) => util::serde_json_to_qt_array(&serde_json::json!(rig.euler_deg)),
+            Err(e) => {
+                self.show_error(e);
+                QJsonArray::default()
+            }
+        }
+    }
+
+    wrap_simple_method!(set_acc_lpf, v: f64; recompute; chart_data_changed);
+    wrap_simple_method!(set_quat_fusion_crossover, v: f64; recompute; chart_data_changed);
+
+    // Part of a log spanning multiple videos used for the current one. When its start wasn't found, `start_ms` is the log start.
+    // Empty if the log isn't longer than the video
+    fn get_log_slice(&self) -> QJsonObject {
+        let gyro = self.stabilizer.gyro.read();
+        if let Some(slice) = gyro.log_slice.as_ref() {
+            return util::serde_json_to_qt_object(&serde_json::json!(slice));
+        }
+        if gyro.file_path.is_empty() || gyro.file_path == self.stabilizer.input_file.read().path {
+            return QJsonObject::default();
+        }
+        match gyro.time_range_ms().filter(|x| core::log_slice::is_longer_than_video(*x, gyro.duration_ms)) {
+            Some(range) => util::serde_json_to_qt_object(&serde_json::json!({ "log_path": gyro.file_path, "start_ms": range.0 })),
+            None => QJsonObject::default()
+        }
+    }
+    // Video start in the log timeline, in seconds
+    fn set_log_anchor(&self, start_s: f64) {
+        let finished = util::qt_queued_callback_mut(self, |this, result: std::io::Result<()>| {
+            if let Err(e) = result {
+                this.show_error(GyroflowError::from(e));
+            }
+            this.chart_data_changed();
+            this.request_recompute();
+        });
+        let stab = self.stabilizer.clone();
+        core::run_threaded(move || {
+            finished(stab.set_log_anchor(start_s * 1000.0, Arc::new(std::sync::atomic::AtomicBool::new(false))));
+        });
+    }
+    wrap_simple_method!(set_acc_bias, bx: f64, by: f64, bz: f64; recompute; chart_data_changed);
+    wrap_simple_method!(set_acc_scale, sx: f64, sy: f64, sz: f64; recompute; chart_data_changed);
     wrap_simple_method!(set_imu_orientation, v: String; recompute; chart_data_changed);
     wrap_simple_method!(set_sync_lpf, v: f64; recompute; chart_data_changed);
     wrap_simple_method!(set_imu_bias, bx: f64, by: f64, bz: f64; recompute; chart_data_changed);
@@ -1082,6 +1884,9 @@ impl Controller {
 
     fn get_org_duration_ms   (&self) -> f64 { self.stabilizer.params.read().duration_ms }
     fn get_scaled_duration_ms(&self) -> f64 { self.stabilizer.params.read().get_scaled_duration_ms() }
+    fn source_to_output_timestamp(&self, timestamp_ms: f64) -> f64 { self.stabilizer.video_time_remap().source_to_output(timestamp_ms) }
+    fn output_to_source_timestamp(&self, timestamp_ms: f64) -> f64 { self.stabilizer.video_time_remap().output_to_source(timestamp_ms) }
+    fn get_output_duration_ms    (&self) -> f64 { self.stabilizer.video_time_remap().output_duration_ms() }
     fn get_scaled_fps        (&self) -> f64 { self.stabilizer.params.read().get_scaled_fps() }
     fn get_current_fov       (&self) -> f64 { self.stabilizer.get_current_fov() }
     fn get_scaling_ratio     (&self) -> f64 { self.stabilizer.get_scaling_ratio() }
@@ -1098,11 +1903,44 @@ impl Controller {
         QVariantList::from_iter(vec![q.w,q.i,q.j,q.k,sq.w,sq.i,sq.j,sq.k]) // scalar first
     }
     fn set_lens_param(&self, param: QString, value: f64) {
-        self.stabilizer.set_lens_param(param.to_string().as_str(), value);
+        self.stabilizer.record_change(&format!("set_lens_param({})", param), &[value.to_string()], || self.stabilizer.set_lens_param(param.to_string().as_str(), value));
         self.request_recompute();
     }
 
+    fn show_error(&self, e: GyroflowError) {
+        ::log::error!("{}", e);
+        self.error(QString::from(e.to_json()));
+    }
+
+    fn set_offline(&mut self, offline: bool) {
+        self.offline = offline;
+        self.offline_flag.store(offline, SeqCst);
+        if !offline {
+            let actions = std::mem::take(&mut *self.pending_online_actions.borrow_mut());
+            for action in actions {
+                match action {
+                    OnlineAction::CheckUpdates => self.check_updates(),
+                    OnlineAction::FetchProfiles => self.fetch_profiles_from_github(),
+                    OnlineAction::RequestRatings => self.request_profile_ratings(),
+                    OnlineAction::RateProfile(name, json, is_good) => self.rate_profile(name, json, is_good),
+                }
+            }
+            self.process_lens_profile_uploads();
+        }
+    }
+    // Returns true if the network action can't be performed now. It will be run once we're back online
+    fn defer_until_online(&self, action: OnlineAction) -> bool {
+        if !self.offline { return false; }
+        ::log::info!("Offline mode, deferring {:?}", action);
+        let mut pending = self.pending_online_actions.borrow_mut();
+        if !pending.contains(&action) {
+            pending.push(action);
+        }
+        true
+    }
+
     fn check_updates(&self) {
+        if self.defer_until_online(OnlineAction::CheckUpdates) { return; }
         let update = util::qt_queued_callback_mut(self, |this, (version, changelog): (String, String)| {
             this.updates_available(QString::from(version), QString::from(changelog))
         });
@@ -1145,18 +1983,11 @@ impl Controller {
         {
             rendering::clear_log();
 
-            self.calib_in_progress = true;
-            self.calib_in_progress_changed();
-            self.calib_progress(0.0, 0.0, 0, 0, 0);
-
             let stab = self.stabilizer.clone();
 
-            let (fps, frame_count, trim_start_ms, trim_end_ms, trim_ratio, input_horizontal_stretch, input_vertical_stretch) = {
+            let (fps, frame_count, trim_start_ms, trim_end_ms, trim_ratio) = {
                 let params = stab.params.read();
-                let lens = stab.lens.read();
-                let input_horizontal_stretch = if lens.input_horizontal_stretch > 0.01 { lens.input_horizontal_stretch } else { 1.0 };
-                let input_vertical_stretch = if lens.input_vertical_stretch > 0.01 { lens.input_vertical_stretch } else { 1.0 };
-                (params.fps, params.frame_count, params.trim_start * params.duration_ms, params.trim_end * params.duration_ms, params.trim_end - params.trim_start, input_horizontal_stretch, input_vertical_stretch)
+                (params.fps, params.frame_count, params.trim_start * params.duration_ms, params.trim_end * params.duration_ms, params.trim_end - params.trim_start)
             };
 
             let is_forced = custom_timestamp_ms > -0.5;
@@ -1180,109 +2011,327 @@ impl Controller {
                 cal.max_sharpness = max_sharpness;
             }
 
-            let progress = util::qt_queued_callback_mut(self, |this, (ready, total, good, rms): (usize, usize, usize, f64)| {
-                this.calib_in_progress = ready < total;
-                this.calib_in_progress_changed();
-                this.calib_progress(ready as f64 / total as f64, rms, ready, total, good);
-                if rms > 0.0 {
-                    this.update_calib_model();
-                }
-            });
-            let err = util::qt_queued_callback_mut(self, |this, (msg, mut arg): (String, String)| {
-                arg.push_str("\n\n");
-                arg.push_str(&rendering::get_log());
+            let total = ((frame_count as f64 * trim_ratio) / every_nth_frame as f64) as usize;
+            let input_file = stab.input_file.read().clone();
+            self.run_autocalibration(total, is_forced, no_marker, move |feed| {
+                let gpu_decoding = *rendering::GPU_DECODING.read();
 
-                this.error(QString::from(msg), QString::from(arg), QString::default());
+                let feed_frame = {
+                    let feed = feed.clone();
+                    move |timestamp_us: i64, input_frame: &mut ffmpeg_next::frame::Video, converter: &mut rendering::Converter| -> Result<(), rendering::FFmpegError> {
+                        if is_forced && feed.read.load(SeqCst) > 0 {
+                            return Ok(());
+                        }
+                        let frame = core::frame_at_timestamp(timestamp_us as f64 / 1000.0, fps);
+                        if (frame % every_nth_frame as i32) == 0 {
+                            feed.feed(timestamp_us, frame, is_forced, input_frame, converter)?;
+                        }
+                        Ok(())
+                    }
+                };
 
-                this.calib_in_progress = false;
-                this.calib_in_progress_changed();
+                // A single calibration frame only needs the GOP around it
+                let server = if is_forced { rendering::frame_server::shared(&input_file) } else { None };
+                let result = if let Some(server) = server {
+                    server.and_then(|server| {
+                        let (from_ms, to_ms) = ranges[0];
+                        server.lock().decode_range(from_ms, to_ms, &feed.cancel_flag, feed_frame)
+                    })
+                } else {
+                    VideoProcessor::from_file(&input_file.path, gpu_decoding, 0, None).and_then(|mut proc| {
+                        proc.on_frame(move |timestamp_us, input_frame, _output_frame, converter, _rate_control| {
+                            feed_frame(timestamp_us, input_frame, converter)
+                        });
+                        proc.start_decoder_only(ranges, feed.cancel_flag.clone())
+                    })
+                };
+                result.map_err(|e| GyroflowError::from_message(e.to_string()))
             });
+        }
+    }
 
-            self.cancel_flag.store(false, SeqCst);
-            let cancel_flag = self.cancel_flag.clone();
+    // Calibrates from a set of still photos instead of a video. Every image is decoded the same way as a video frame
+    // and gets a synthetic frame number (its index in the list) and timestamp (one image per second)
+    fn start_autocalibrate_images(&mut self, urls: QStringList, max_points: usize, iterations: usize, max_sharpness: f64, no_marker: bool) {
+        #[cfg(feature = "opencv")]
+        {
+            rendering::clear_log();
 
-            let total = ((frame_count as f64 * trim_ratio) / every_nth_frame as f64) as usize;
-            let total_read = Arc::new(AtomicUsize::new(0));
-            let processed = Arc::new(AtomicUsize::new(0));
+            let paths: Vec<String> = urls.into_iter().map(|x| util::url_to_path(QUrl::from(x.clone()))).filter(|x| !x.is_empty()).collect();
+            if paths.is_empty() { return; }
 
-            let input_file = stab.input_file.read().clone();
-            core::run_threaded(move || {
-                let gpu_decoding = *rendering::GPU_DECODING.read();
-                match VideoProcessor::from_file(&input_file.path, gpu_decoding, 0, None) {
-                    Ok(mut proc) => {
-                        let progress = progress.clone();
-                        let err2 = err.clone();
-                        let cal = cal.clone();
-                        let total_read = total_read.clone();
-                        let processed = processed.clone();
-                        let cancel_flag2 = cancel_flag.clone();
-                        proc.on_frame(move |timestamp_us, input_frame, _output_frame, converter, _rate_control| {
-                            let frame = core::frame_at_timestamp(timestamp_us as f64 / 1000.0, fps);
+            let stab = self.stabilizer.clone();
+            if stab.lens_calibrator.read().is_none() {
+                *stab.lens_calibrator.write() = Some(LensCalibrator::new());
+            }
+            {
+                let mut lock = stab.lens_calibrator.write();
+                let cal = lock.as_mut().unwrap();
+                // Frame numbers of the images overlap with the frames of any previously analyzed video
+                cal.clear();
+                cal.forced_frames.clear();
+                if max_points > 0 { cal.max_images = max_points; }
+                cal.iterations = iterations;
+                cal.max_sharpness = max_sharpness;
+            }
 
-                            if is_forced && total_read.load(SeqCst) > 0 {
+            self.run_autocalibration(paths.len(), false, no_marker, move |feed| {
+                let mut first_size = None;
+                for (i, path) in paths.iter().enumerate() {
+                    if feed.cancel_flag.load(SeqCst) { break; }
+                    let frame = i as i32;
+                    let timestamp_us = i as i64 * 1_000_000;
+
+                    let result = VideoProcessor::from_file(path, false, 0, None).and_then(|mut proc| {
+                        let cancel_flag = feed.cancel_flag.clone();
+                        let feed = feed.clone();
+                        let path2 = path.clone();
+                        let mut first_size = first_size;
+                        let size_out = Arc::new(parking_lot::Mutex::new(None));
+                        let size_out2 = size_out.clone();
+                        proc.on_frame(move |_timestamp_us, input_frame, _output_frame, converter, _rate_control| {
+                            if feed.read.load(SeqCst) > i { return Ok(()); } // Only the first frame of each file
+
+                            let size = (input_frame.width(), input_frame.height());
+                            if *first_size.get_or_insert(size) != size {
+                                ::log::warn!("Skipping {}: image size {}x{} is different than the first image ({}x{})", path2, size.0, size.1, first_size.unwrap().0, first_size.unwrap().1);
+                                feed.skip();
                                 return Ok(());
                             }
+                            *size_out2.lock() = Some(size);
 
-                            if (frame % every_nth_frame as i32) == 0 {
-                                let mut width = (input_frame.width() as f64 * input_horizontal_stretch).round() as u32;
-                                let mut height = (input_frame.height() as f64 * input_vertical_stretch).round() as u32;
-                                let mut pt_scale = 1.0;
-                                if height > 2160 {
-                                    pt_scale = height as f32 / 2160.0;
-                                    width = (width as f32 / pt_scale).round() as u32;
-                                    height = (height as f32 / pt_scale).round() as u32;
-                                }
-                                match converter.scale(input_frame, ffmpeg_next::format::Pixel::GRAY8, width, height) {
-                                    Ok(mut small_frame) => {
-                                        let (width, height, stride, pixels) = (small_frame.plane_width(0), small_frame.plane_height(0), small_frame.stride(0), small_frame.data_mut(0));
-
-                                        total_read.fetch_add(1, SeqCst);
-                                        let mut lock = cal.write();
-                                        let cal = lock.as_mut().unwrap();
-                                        if is_forced {
-                                            cal.forced_frames.insert(frame);
-                                        }
-                                        cal.no_marker = no_marker;
-                                        cal.feed_frame(timestamp_us, frame, width, height, stride, pt_scale, pixels, cancel_flag2.clone(), total, processed.clone(), progress.clone());
-                                    },
-                                    Err(e) => {
-                                        err2(("An error occured: %1".to_string(), e.to_string()))
-                                    }
-                                }
-                            }
-                            Ok(())
+                            feed.feed(timestamp_us, frame, false, input_frame, converter)
                         });
-                        if let Err(e) = proc.start_decoder_only(ranges, cancel_flag.clone()) {
-                            err(("An error occured: %1".to_string(), e.to_string()));
-                        }
+                        proc.start_decoder_only(Vec::new(), cancel_flag)?;
+                        let size = *size_out.lock();
+                        Ok(size)
+                    });
+                    match result {
+                        Ok(size) => { if first_size.is_none() { first_size = size; } },
+                        Err(e) => ::log::warn!("Unable to read {}: {}", path, e)
                     }
-                    Err(error) => {
-                        err(("An error occured: %1".to_string(), error.to_string()));
+                    // Count unreadable images as processed, so the progress can still finish
+                    if feed.read.load(SeqCst) <= i {
+                        feed.skip();
                     }
                 }
-                // Don't lock the UI trying to draw chessboards while we calibrate
-                stab.params.write().is_calibrator = false;
+                if first_size.is_none() {
+                    return Err(GyroflowError::generic(core::i18n::Message::new("calibration.no_readable_images").to_string()));
+                }
+                Ok(())
+            });
+        }
+    }
 
-                while processed.load(SeqCst) < total_read.load(SeqCst) {
-                    std::thread::sleep(std::time::Duration::from_millis(500));
+    // Shared by the video and the still image calibration. `read_frames` runs in a background thread and passes the frames
+    // to `CalibrationFeed::feed`, then the lens is calibrated from the detected boards, unless it returned an error
+    #[cfg(feature = "opencv")]
+    fn run_autocalibration<F>(&mut self, total: usize, is_forced: bool, no_marker: bool, read_frames: F)
+    where F: FnOnce(&CalibrationFeed) -> Result<(), GyroflowError> + Send + 'static {
+        self.calib_in_progress = true;
+        self.calib_in_progress_changed();
+        self.calib_progress(0.0, 0.0, 0, 0, 0);
+
+        let stab = self.stabilizer.clone();
+        let stretch = {
+            let lens = stab.lens.read();
+            (if lens.input_horizontal_stretch > 0.01 { lens.input_horizontal_stretch } else { 1.0 },
+             if lens.input_vertical_stretch   > 0.01 { lens.input_vertical_stretch   } else { 1.0 })
+        };
+
+        let progress = util::qt_queued_callback_mut(self, |this, (ready, total, good, rms): (usize, usize, usize, f64)| {
+            this.calib_in_progress = ready < total;
+            this.calib_in_progress_changed();
+            this.calib_progress(ready as f64 / total as f64, rms, ready, total, good);
+            if rms > 0.0 {
+                this.update_calib_model();
+            }
+        });
+        let err = util::qt_queued_callback_mut(self, |this, e: GyroflowError| {
+            this.show_error(e.append_context(&rendering::get_log()));
+
+            this.calib_in_progress = false;
+            this.calib_in_progress_changed();
+        });
+
+        let job = self.stabilizer.jobs.start(JobKind::Calibration);
+        let feed = CalibrationFeed {
+            cal: stab.lens_calibrator.clone(),
+            stretch,
+            no_marker,
+            total,
+            read: Arc::new(AtomicUsize::new(0)),
+            processed: Arc::new(AtomicUsize::new(0)),
+            cancel_flag: job.cancel_flag.clone(),
+            progress: Arc::new(progress.clone()),
+        };
+
+        core::run_threaded(move || {
+            let _job = job;
+            if let Err(e) = read_frames(&feed) {
+                err(e);
+                return;
+            }
+            // Don't lock the UI trying to draw chessboards while we calibrate
+            stab.params.write().is_calibrator = false;
+
+            while feed.processed.load(SeqCst) < feed.read.load(SeqCst) {
+                std::thread::sleep(std::time::Duration::from_millis(500));
+            }
+
+            let mut lock = feed.cal.write();
+            let cal = lock.as_mut().unwrap();
+            if let Err(e) = cal.calibrate(is_forced) {
+                err(GyroflowError::new(ErrorKind::Calibration, format!("{:?}", e)));
+            } else {
+                stab.lens.write().set_from_calibrator(cal);
+                ::log::debug!("rms: {}, used_frames: {:?}, camera_matrix: {}, coefficients: {}", cal.rms, cal.used_points.keys(), cal.k, cal.d);
+            }
+
+            progress((total, total, 0, cal.rms));
+
+            stab.params.write().is_calibrator = true;
+        });
+    }
+
+    // Solves the rotation between the calibrated camera (with detections of the board in the current video)
+    // and a second camera filming the same board, given its video, lens profile and the time offset between the two videos
+    fn start_rig_calibration(&mut self, other_video: QUrl, other_lens_profile: QUrl, time_offset_ms: f64) {
+        #[cfg(feature = "opencv")]
+        {
+            rendering::clear_log();
+
+            let stab = self.stabilizer.clone();
+            let (ranges, columns, rows, no_marker) = {
+                let lock = stab.lens_calibrator.read();
+                match lock.as_ref() {
+                    Some(cal) if cal.rms > 0.0 && !cal.all_matches.read().is_empty() => {
+                        let fps = stab.params.read().fps.max(1.0);
+                        let frame_ms = 1000.0 / fps;
+                        // Decode the other video only around the frames where the board was found in this one
+                        let ranges: Vec<(f64, f64)> = cal.all_matches.read().values().map(|x| {
+                            let ts = x.timestamp_us as f64 / 1000.0 + time_offset_ms;
+                            (ts - frame_ms, ts + frame_ms)
+                        }).collect();
+                        (ranges, cal.columns, cal.rows, cal.no_marker)
+                    },
+                    _ => {
+                        self.show_error(GyroflowError::generic(core::i18n::Message::new("calibration.not_calibrated").to_string()));
+                        return;
+                    }
                 }
+            };
 
-                let mut lock = cal.write();
-                let cal = lock.as_mut().unwrap();
-                if let Err(e) = cal.calibrate(is_forced) {
-                    err(("An error occured: %1".to_string(), format!("{:?}", e)));
-                } else {
-                    stab.lens.write().set_from_calibrator(cal);
-                    ::log::debug!("rms: {}, used_frames: {:?}, camera_matrix: {}, coefficients: {}", cal.rms, cal.used_points.keys(), cal.k, cal.d);
+            let mut profile = core::lens_profile::LensProfile::default();
+            if let Err(e) = profile.load_from_file(&util::url_to_path(other_lens_profile)) {
+                self.show_error(GyroflowError::from_message(e.to_string()));
+                return;
+            }
+            let other_k = profile.get_camera_matrix((profile.calib_dimension.w, profile.calib_dimension.h), (0, 0));
+            let other_d = profile.get_distortion_coeffs();
+
+            self.calib_in_progress = true;
+            self.calib_in_progress_changed();
+            self.calib_progress(0.0, 0.0, 0, 0, 0);
+
+            let progress = util::qt_queued_callback_mut(self, |this, (ready, total, good, _rms): (usize, usize, usize, f64)| {
+                this.calib_progress(ready as f64 / total.max(1) as f64, 0.0, ready, total, good);
+            });
+            let finished = util::qt_queued_callback_mut(self, |this, result: Result<String, GyroflowError>| {
+                this.calib_in_progress = false;
+                this.calib_in_progress_changed();
+                match result {
+                    Ok(json) => this.rig_calibrated(QString::from(json)),
+                    Err(e) => this.show_error(e.append_context(&rendering::get_log()))
                 }
+            });
 
-                progress((total, total, 0, cal.rms));
+            let job = self.stabilizer.jobs.start(JobKind::Calibration);
+            let cancel_flag = job.cancel_flag.clone();
+
+            let total = ranges.len() * 2; // About two frames are decoded around each timestamp
+            let total_read = Arc::new(AtomicUsize::new(0));
+            let processed = Arc::new(AtomicUsize::new(0));
 
-                stab.params.write().is_calibrator = true;
+            let other_path = util::url_to_path(other_video);
+            core::run_threaded(move || {
+                let _job = job;
+                let mut other = LensCalibrator::new();
+                other.columns = columns;
+                other.rows = rows;
+                other.no_marker = no_marker;
+                other.max_sharpness = f64::MAX;
+                let other = Arc::new(parking_lot::RwLock::new(other));
+
+                let gpu_decoding = *rendering::GPU_DECODING.read();
+                let result = VideoProcessor::from_file(&other_path, gpu_decoding, 0, None).and_then(|mut proc| {
+                    let other = other.clone();
+                    let total_read = total_read.clone();
+                    let processed = processed.clone();
+                    let cancel_flag2 = cancel_flag.clone();
+                    let progress = progress.clone();
+                    let mut frame = 0;
+                    proc.on_frame(move |timestamp_us, input_frame, _output_frame, converter, _rate_control| {
+                        let (mut width, mut height) = (input_frame.width(), input_frame.height());
+                        let mut pt_scale = 1.0;
+                        if height > 2160 {
+                            pt_scale = height as f32 / 2160.0;
+                            width = (width as f32 / pt_scale).round() as u32;
+                            height = (height as f32 / pt_scale).round() as u32;
+                        }
+                        let mut small_frame = converter.scale(input_frame, ffmpeg_next::format::Pixel::GRAY8, width, height)?;
+                        let (width, height, stride, pixels) = (small_frame.plane_width(0), small_frame.plane_height(0), small_frame.stride(0), small_frame.data_mut(0));
+
+                        total_read.fetch_add(1, SeqCst);
+                        frame += 1;
+                        other.write().feed_frame(timestamp_us, frame, width, height, stride, pt_scale, pixels, cancel_flag2.clone(), total, processed.clone(), progress.clone());
+                        Ok(())
+                    });
+                    proc.start_decoder_only(ranges, cancel_flag.clone())
+                });
+                if let Err(e) = result {
+                    finished(Err(GyroflowError::from_message(e.to_string())));
+                    return;
+                }
+                while processed.load(SeqCst) < total_read.load(SeqCst) {
+                    std::thread::sleep(std::time::Duration::from_millis(100));
+                }
+
+                let mut other = other.write();
+                // Lens profile is calibrated at `calib_dimension`, the detected points are in the video resolution
+                let scale = if other.width > 0 && profile.calib_dimension.w > 0 { other.width as f64 / profile.calib_dimension.w as f64 } else { 1.0 };
+                other.k = other_k;
+                for i in 0..2 { for j in 0..3 { other.k[(i, j)] *= scale; } }
+                other.d = Vector4::new(other_d[0], other_d[1], other_d[2], other_d[3]);
+
+                let mut lock = stab.lens_calibrator.write();
+                let cal = lock.as_mut().unwrap();
+                let max_time_diff_us = (500_000.0 / stab.params.read().fps.max(1.0)) as i64;
+                let result = core::calibration::rig::solve_rig_rotation(cal, &other, (time_offset_ms * 1000.0).round() as i64, max_time_diff_us);
+                finished(match result {
+                    Ok(rig) => {
+                        let json = serde_json::to_string(&rig).unwrap_or_default();
+                        // Saved with the lens profile and the project
+                        stab.lens.write().rig_rotation = Some(rig.clone());
+                        cal.rig_rotation = Some(rig);
+                        Ok(json)
+                    },
+                    Err(e) => Err(GyroflowError::new(ErrorKind::Calibration, format!("{:?}", e)))
+                });
             });
         }
     }
 
+    fn export_rig_calibration(&self, url: QUrl) {
+        let rig = self.stabilizer.lens.read().rig_rotation.clone();
+        if let Some(rig) = rig {
+            let json = serde_json::to_string_pretty(&rig).unwrap_or_default();
+            if let Err(e) = core::filesystem::write(&util::url_to_path(url), json) {
+                self.show_error(GyroflowError::from(e));
+            }
+        }
+    }
+
     fn update_calib_model(&mut self) {
         #[cfg(feature = "opencv")]
         {
@@ -1363,24 +2412,107 @@ impl Controller {
                     profile.set_from_calibrator(cal);
                 }
 
+                let mut upload = upload;
+                if upload {
+                    let errors = profile.validate_for_upload();
+                    if !errors.is_empty() {
+                        ::log::warn!("Lens profile not valid for upload: {:?}", errors.iter().map(|x| x.english()).collect::<Vec<_>>());
+                        self.lens_profile_upload_status(QString::from("invalid"), QString::from(errors.iter().map(|x| x.to_string()).join("\n")));
+                        upload = false;
+                    } else if let Some(existing) = self.stabilizer.lens_profile_db.read().find_duplicate(&profile) {
+                        self.lens_profile_upload_status(QString::from("duplicate"), QString::from(existing.get_name()));
+                        upload = false;
+                    }
+                }
+
                 match profile.save_to_file(&path) {
                     Ok(json) => {
                         ::log::debug!("Lens profile json: {}", json);
                         if upload {
-                            core::run_threaded(move || {
-                                if let Ok(Ok(body)) = ureq::post("https://api.gyroflow.xyz/upload_profile").set("Content-Type", "application/json; charset=utf-8").send_string(&json).map(|x| x.into_string()) {
-                                    ::log::debug!("Lens profile uploaded: {}", body.as_str());
-                                }
-                            });
+                            self.queue_lens_profile_upload(json);
                         }
                     }
-                    Err(e) => { self.error(QString::from("An error occured: %1"), QString::from(format!("{:?}", e)), QString::default()); }
+                    Err(e) => { self.show_error(GyroflowError::from(e)); }
                 }
             },
-            Err(e) => { self.error(QString::from("An error occured: %1"), QString::from(format!("{:?}", e)), QString::default()); }
+            Err(e) => { self.show_error(GyroflowError::new(ErrorKind::InvalidLensProfile, format!("{:?}", e))); }
         }
     }
 
+    // Uploads are processed one by one in the background, network errors are retried a few times
+    fn queue_lens_profile_upload(&self, json: String) {
+        let len = {
+            let mut queue = self.profile_upload_queue.lock();
+            queue.push_back(json);
+            queue.len()
+        };
+        self.lens_profile_upload_status(QString::from("queued"), QString::from(len.to_string()));
+        self.process_lens_profile_uploads();
+    }
+    fn process_lens_profile_uploads(&self) {
+        {
+            let _queue = self.profile_upload_queue.lock();
+            if self.offline || self.profile_upload_running.swap(true, SeqCst) {
+                return;
+            }
+        }
+
+        let status = util::qt_queued_callback_mut(self, |this, (status, message): (&'static str, String)| {
+            this.lens_profile_upload_status(QString::from(status), QString::from(message));
+        });
+        let queue = self.profile_upload_queue.clone();
+        let running = self.profile_upload_running.clone();
+        let offline = self.offline_flag.clone();
+        core::run_threaded(move || {
+            const MAX_ATTEMPTS: u32 = 3;
+            loop {
+                let json = {
+                    let mut queue = queue.lock();
+                    // Remaining profiles will be uploaded when we're back online
+                    if offline.load(SeqCst) { running.store(false, SeqCst); break; }
+                    match queue.pop_front() {
+                        Some(x) => x,
+                        None => { running.store(false, SeqCst); break; }
+                    }
+                };
+
+                let mut attempt = 1;
+                loop {
+                    status(("uploading", attempt.to_string()));
+                    match ureq::post("https://api.gyroflow.xyz/upload_profile").set("Content-Type", "application/json; charset=utf-8").send_string(&json) {
+                        Ok(response) => {
+                            let body = response.into_string().unwrap_or_default();
+                            ::log::debug!("Lens profile uploaded: {}", body.as_str());
+                            status(("uploaded", body));
+                            break;
+                        }
+                        // The server rejected the profile, there's no point in retrying
+                        Err(ureq::Error::Status(code, response)) if code < 500 => {
+                            let body = response.into_string().unwrap_or_default();
+                            ::log::warn!("Lens profile rejected ({}): {}", code, body);
+                            status(("failed", if body.is_empty() { format!("HTTP {}", code) } else { body }));
+                            break;
+                        }
+                        Err(e) => {
+                            ::log::warn!("Lens profile upload failed (attempt {}/{}): {}", attempt, MAX_ATTEMPTS, e);
+                            if attempt >= MAX_ATTEMPTS {
+                                status(("failed", e.to_string()));
+                                break;
+                            }
+                            if offline.load(SeqCst) {
+                                queue.lock().push_front(json);
+                                break;
+                            }
+                            status(("retrying", e.to_string()));
+                            std::thread::sleep(std::time::Duration::from_secs(5 * attempt as u64));
+                            attempt += 1;
+                        }
+                    }
+                }
+            }
+        });
+    }
+
     fn load_profiles(&self, reload_from_disk: bool) {
         let loaded = util::qt_queued_callback_mut(self, |this, all_names: QVariantList| {
             this.all_profiles_loaded(all_names)
@@ -1390,6 +2522,7 @@ impl Controller {
             if reload_from_disk {
                 let mut new_db = core::lens_profile_database::LensProfileDatabase::default();
                 new_db.load_all();
+                new_db.upstream_updates = std::mem::take(&mut db.write().upstream_updates);
                 // Important! Disable `fetch_profiles_from_github` before running these functions
                 // new_db.list_all_metadata();
                 // new_db.process_adjusted_metadata();
@@ -1413,40 +2546,72 @@ impl Controller {
         });
     }
 
+    fn watch_lens_profiles(&mut self) {
+        if self.lens_profiles_watcher.is_some() { return; }
+
+        let changed = util::qt_queued_callback_mut(self, |this, _| {
+            this.lens_profiles_updated(true);
+        });
+        self.lens_profiles_watcher = core::lens_profile_database::LensProfileDatabase::watch(move || changed(()));
+    }
+
     fn fetch_profiles_from_github(&self) {
         #[cfg(target_os = "android")]
         {
             return;
         }
 
-        use crate::core::lens_profile_database::LensProfileDatabase;
+        if self.defer_until_online(OnlineAction::FetchProfiles) { return; }
+
+        use crate::core::lens_profile_database::{ LensProfileDatabase, UpstreamIndex, UpstreamCheck };
 
         let update = util::qt_queued_callback_mut(self, |this, _| {
             this.lens_profiles_updated(true);
         });
+        let updates_available = util::qt_queued_callback_mut(self, |this, updates_json: String| {
+            this.lens_profile_updates_available(QString::from(updates_json));
+        });
+        let db = self.stabilizer.lens_profile_db.clone();
 
         core::run_threaded(move || {
             if let Ok(Ok(body)) = ureq::get("https://api.github.com/repos/gyroflow/gyroflow/git/trees/master?recursive=1").call().map(|x| x.into_string()) {
+                let index = Arc::new(parking_lot::Mutex::new(UpstreamIndex::load()));
                 (|| -> Option<()> {
                     let v: serde_json::Value = serde_json::from_str(&body).ok()?;
                     for obj in v.get("tree")?.as_array()? {
                         let obj = obj.as_object()?;
                         let path = obj.get("path")?.as_str()?;
                         if path.contains("/camera_presets/") && (path.contains(".json") || path.contains(".gyroflow")) {
-                            let local_path = LensProfileDatabase::get_path().join(path.replace("resources/camera_presets/", ""));
-                            if !local_path.exists() {
+                            let rel_path = path.replace("resources/camera_presets/", "");
+                            let local_path = LensProfileDatabase::get_path().join(&rel_path);
+                            let sha = obj.get("sha")?.as_str()?.to_string();
+                            let size = obj.get("size").and_then(|x| x.as_u64()).unwrap_or_default();
+                            let check = index.lock().check(&rel_path, &sha, size);
+                            if check != UpstreamCheck::UpToDate {
                                 ::log::info!("Downloading lens profile {:?}", local_path.file_name()?);
 
                                 let url = obj.get("url")?.as_str()?.to_string();
                                 let _ = std::fs::create_dir_all(local_path.parent()?);
                                 let update = update.clone();
+                                let updates_available = updates_available.clone();
+                                let index = index.clone();
+                                let db = db.clone();
                                 core::run_threaded(move || {
                                     let content = ureq::get(&url)
                                         .set("Accept", "application/vnd.github.v3.raw")
                                         .call().map(|x| x.into_string());
                                     if let Ok(Ok(content)) = content {
-                                        if std::fs::write(local_path, content.into_bytes()).is_ok() {
-                                           update(());
+                                        let modified_locally = check == UpstreamCheck::Compare && std::fs::read(&local_path).map(|x| core::lens_profile_database::profiles_differ(&x, &content)).unwrap_or(false);
+                                        if modified_locally {
+                                            // Don't overwrite user's changes, let them review the differences
+                                            let mut db = db.write();
+                                            db.add_upstream_update(&rel_path, &sha, content);
+                                            updates_available(serde_json::to_string(&db.upstream_update_diffs()).unwrap_or_default());
+                                        } else if std::fs::write(local_path, content.as_bytes()).is_ok() {
+                                            let mut index = index.lock();
+                                            index.record(&rel_path, &sha, content.as_bytes());
+                                            index.save();
+                                            update(());
                                         }
                                     }
                                 });
@@ -1455,11 +2620,22 @@ impl Controller {
                     }
                     Some(())
                 }());
+                index.lock().save();
             }
         });
     }
 
+    fn apply_lens_profile_update(&self, path: QString, fields: QStringList) {
+        let fields: Vec<String> = fields.into_iter().map(QString::to_string).collect();
+        let result = self.stabilizer.lens_profile_db.write().apply_upstream_update(&path.to_string(), &fields);
+        match result {
+            Ok(_) => self.lens_profiles_updated(true),
+            Err(e) => self.show_error(GyroflowError::from_message(e.to_string()))
+        }
+    }
+
     fn rate_profile(&self, name: QString, json: QString, is_good: bool) {
+        if self.defer_until_online(OnlineAction::RateProfile(name.clone(), json.clone(), is_good)) { return; }
         core::run_threaded(move || {
             let mut url = url::Url::parse(&format!("https://api.gyroflow.xyz/rate?good={}", is_good)).unwrap();
             url.query_pairs_mut().append_pair("filename", &name.to_string());
@@ -1470,6 +2646,7 @@ impl Controller {
         });
     }
     fn request_profile_ratings(&self) {
+        if self.defer_until_online(OnlineAction::RequestRatings) { return; }
         let update = util::qt_queued_callback_mut(self, |this, _| {
             this.lens_profiles_updated(false);
         });
@@ -1492,20 +2669,138 @@ impl Controller {
         let mut l = self.stabilizer.stabilization.write();
         l.set_device(i as isize);
     }
+    fn set_export_device(&self, name: String) {
+        if !core::gpu::context_manager::MANAGER.write().select_by_name(core::gpu::context_manager::GpuTask::Export, &name) {
+            ::log::warn!("Unknown export device: {}", name);
+        }
+    }
+    // Debug options of the export pipeline, see `core::pipeline::PipelineConfig`
+    fn set_pipeline_config(&self, json: String) {
+        match serde_json::from_str(&json) {
+            Ok(config) => core::pipeline::set_config(config),
+            Err(e) => ::log::error!("Invalid pipeline config {}: {:?}", json, e)
+        }
+    }
+    fn get_pipeline_stats(&self) -> QString {
+        QString::from(serde_json::to_string(&core::pipeline::stats()).unwrap_or_default())
+    }
+    fn set_kernel_precision(&self, v: i32) {
+        self.stabilizer.set_kernel_precision(v);
+    }
+    fn fp16_supported(&self, device: i32) -> bool {
+        core::gpu::context_manager::MANAGER.write().backend(device as isize).map_or(false, core::gpu::KernelPrecision::fp16_supported)
+    }
+    fn set_deterministic_compute(&mut self, v: bool) {
+        self.stabilizer.record_change("set_deterministic_compute", &[v.to_string()], || self.stabilizer.set_deterministic(v));
+        self.request_recompute();
+    }
     fn set_rendering_gpu_type_from_name(&self, name: String) {
         rendering::set_gpu_type_from_name(&name);
     }
 
     fn export_preset(&self, url: QUrl, content: QJsonObject) {
-        let contents = content.to_json_pretty();
-        if let Err(e) = std::fs::write(util::url_to_path(url), contents.to_slice()) {
-            self.error(QString::from("An error occured: %1"), QString::from(e.to_string()), QString::default());
+        let mut obj: serde_json::Value = serde_json::from_str(&content.to_json().to_string()).unwrap_or_default();
+        core::parameter_layers::ParameterLayers::mark_preset(&mut obj);
+        let contents = serde_json::to_string_pretty(&obj).unwrap_or_default();
+        if let Err(e) = core::filesystem::write(&util::url_to_path(url), contents) {
+            self.show_error(GyroflowError::from(e));
+        }
+    }
+
+    fn project_template_path(name: &str) -> std::path::PathBuf {
+        std::path::Path::new(&util::get_data_location()).join("templates").join(format!("{}.gyroflow", core::output_template::sanitize(name.trim())))
+    }
+    fn save_project_template(&self, name: QString, additional_data: QJsonObject) {
+        let path = Self::project_template_path(&name.to_string());
+        let result = self.stabilizer.export_project_template(&name.to_string(), additional_data.to_json().to_string()).and_then(|data| {
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            core::filesystem::write(&path.to_string_lossy(), data)
+        });
+        match result {
+            Ok(_) => self.message(QString::from("Project template %1 saved."), QString::from(format!("<b>{}</b>", name)), QString::default()),
+            Err(e) => self.show_error(GyroflowError::from(e))
+        }
+    }
+    fn get_project_templates(&self) -> QStringList {
+        let dir = std::path::Path::new(&util::get_data_location()).join("templates");
+        let mut names: Vec<String> = std::fs::read_dir(dir).map(|entries| {
+            entries.filter_map(|x| x.ok())
+                .map(|x| x.path())
+                .filter(|x| x.extension().map(|e| e == "gyroflow").unwrap_or_default())
+                .filter_map(|x| Some(x.file_stem()?.to_string_lossy().to_string()))
+                .collect()
+        }).unwrap_or_default();
+        names.sort_by_key(|x| x.to_lowercase());
+        QStringList::from_iter(names.into_iter().map(QString::from))
+    }
+    fn delete_project_template(&self, name: QString) {
+        if let Err(e) = std::fs::remove_file(Self::project_template_path(&name.to_string())) {
+            self.show_error(GyroflowError::from(e));
         }
     }
+    fn instantiate_project_template(&self, name: QString, video_url: QUrl) -> QJsonObject {
+        let result = core::filesystem::read(&Self::project_template_path(&name.to_string()).to_string_lossy()).and_then(|data| {
+            StabilizationManager::<stabilization::RGBA8>::instantiate_project_template(&data, &util::url_to_path(video_url))
+        });
+        match result {
+            Ok(obj) => util::serde_json_to_qt_object(&obj),
+            Err(e) => {
+                self.show_error(GyroflowError::from(e));
+                QJsonObject::default()
+            }
+        }
+    }
+
+    // Where each parameter comes from ("default", "camera", "preset" or "clip"), keyed by its path in the project file, eg. "stabilization.fov"
+    fn get_parameter_sources(&self) -> QJsonObject {
+        let sources = self.stabilizer.get_parameter_sources();
+        let obj = serde_json::Value::Object(sources.into_iter().map(|(k, v)| (k, serde_json::Value::String(v.as_str().into()))).collect());
+        util::serde_json_to_qt_object(&obj)
+    }
+    // Changes between two project files, see `core::project_diff::ProjectDiff`
+    fn diff_projects(&self, old: QUrl, new: QUrl) -> QJsonObject {
+        match core::project_diff::diff_files(&util::url_to_path(old), &util::url_to_path(new)) {
+            Ok(diff) => util::serde_json_to_qt_object(&serde_json::json!(diff)),
+            Err(e) => {
+                self.show_error(e);
+                QJsonObject::default()
+            }
+        }
+    }
+    fn reset_parameter_override(&mut self, path: QString) {
+        match self.stabilizer.reset_parameter_override(&path.to_string()) {
+            Ok(Some(mut obj)) => {
+                obj["version"] = serde_json::json!(2);
+                self.request_recompute();
+                self.gyroflow_file_loaded(util::serde_json_to_qt_object(&obj));
+            },
+            Ok(None) => { },
+            Err(e) => self.show_error(GyroflowError::from(e))
+        }
+    }
+
+    // What this build and machine support, so the UI can hide options which would fail at runtime
+    fn get_capabilities(&self) -> QJsonObject {
+        let mut obj = util::get_capabilities();
+        obj["platform"]["opengl"] = serde_json::json!(util::is_opengl());
+        obj["platform"]["offline"] = serde_json::json!(util::is_offline());
+        util::serde_json_to_qt_object(&obj)
+    }
 
+    fn is_low_memory(&self) -> bool { core::low_memory::is_enabled() }
+
+    // Valid range of a keyframe type or smoothing parameter: { min, max, step }, empty if the name is unknown
+    fn get_value_range(&self, name: String) -> QJsonObject {
+        match self.stabilizer.get_value_range(&name) {
+            Some(range) => util::serde_json_to_qt_object(&serde_json::to_value(range).unwrap_or_default()),
+            None => QJsonObject::default()
+        }
+    }
     fn set_keyframe(&self, typ: String, timestamp_us: i64, value: f64) {
         if let Ok(kf) = KeyframeType::from_str(&typ) {
-            self.stabilizer.set_keyframe(&kf, timestamp_us, value);
+            self.stabilizer.record_change(&format!("set_keyframe({}@{})", typ, timestamp_us), &[value.to_string()], || self.stabilizer.set_keyframe(&kf, timestamp_us, value));
             self.keyframes_changed();
             self.request_recompute();
         }
@@ -1578,6 +2873,10 @@ impl Controller {
         let progress = util::qt_queued_callback_mut(self, move |this, (percent, sdk_name, error_string): (f64, &'static str, String)| {
             this.external_sdk_progress(percent, QString::from(sdk_name), QString::from(error_string), path.clone());
         });
+        if self.offline {
+            progress((1.0, "", "Offline mode is enabled.".to_string()));
+            return;
+        }
         crate::external_sdk::install(&path_str, progress);
     }
 
@@ -1607,6 +2906,21 @@ impl Controller {
 
     // Utilities
     fn file_exists(&self, path: QString) -> bool { std::path::Path::new(&path.to_string()).exists() }
+    // Estimated output size, free disk space and output collisions for the export settings (`Export.qml:getExportOptions`)
+    fn render_preflight(&self, output_options: String) -> QString {
+        let mut render_options = rendering::render_queue::RenderOptions::default();
+        if let Ok(obj) = serde_json::from_str::<serde_json::Value>(&output_options) {
+            render_options.update_from_json(&obj);
+            if let Some(v) = obj.get("output_path").and_then(|x| x.as_str()) { render_options.output_path = v.to_string(); }
+        }
+        let (duration_ms, fps) = {
+            let params = self.stabilizer.params.read();
+            (params.get_scaled_duration_ms() * (params.trim_end - params.trim_start), params.get_scaled_fps())
+        };
+        let input_path = self.stabilizer.input_file.read().path.clone();
+        let result = rendering::preflight::check(&render_options, &input_path, duration_ms, fps);
+        QString::from(serde_json::to_string(&result).unwrap_or_default())
+    }
     fn file_size(&self, path: QString) -> u64 { std::fs::metadata(&path.to_string()).map(|x| x.len()).unwrap_or_default() }
     fn video_duration(&self, path: QString) -> f64 { gyroflow_core::util::get_video_metadata(&path.to_string()).map(|x| x.3).unwrap_or_default() }
     fn resolve_android_url(&mut self, url: QString) -> QString { util::resolve_android_url(url) }
@@ -1615,5 +2929,62 @@ impl Controller {
     fn url_to_path(&self, url: QUrl) -> QString { QString::from(util::url_to_path(url)) }
     fn path_to_url(&self, path: QString) -> QUrl { util::path_to_url(path) }
     fn image_to_b64(&self, img: QImage) -> QString { util::image_to_b64(img) }
+
+    // Decodes and processes a single frame synchronously, independent of the preview player. Width or height of 0 means the current output size
+    pub fn get_stabilized_frame(&self, timestamp_us: i64, width: u32, height: u32) -> QImage {
+        match rendering::get_stabilized_frame(&self.stabilizer, timestamp_us, (width as usize, height as usize)) {
+            Ok((w, h, stride, pixels)) => util::image_from_rgba(w as u32, h as u32, stride as u32, &pixels),
+            Err(e) => {
+                ::log::error!("Failed to get the stabilized frame at {}: {}", timestamp_us, e);
+                QImage::default()
+            }
+        }
+    }
+
+    // Saves the stabilized frame as PNG, JPEG or 16-bit TIFF, depending on the extension.
+    // With `burn_in`, the file name, timestamp and the main stabilization settings are drawn on the image
+    fn export_frame(&self, url: QUrl, timestamp_us: i64, width: u32, height: u32, burn_in: bool) {
+        let path = util::url_to_path(url);
+        let finished = util::qt_queued_callback_mut(self, |this, (path, result): (String, Result<(), GyroflowError>)| {
+            match result {
+                Ok(_) => this.message(QString::from("Frame saved to %1."), QString::from(format!("<b>{}</b>", path)), QString::default()),
+                Err(e) => this.show_error(e)
+            }
+        });
+
+        let ext = std::path::Path::new(&path).extension().map(|x| x.to_string_lossy().to_ascii_lowercase()).unwrap_or_default();
+        let (format, is_16bit) = match ext.as_str() {
+            "tif" | "tiff" => ("tiff", true),
+            "jpg" | "jpeg" => ("jpg", false),
+            _ => ("png", false)
+        };
+
+        let text = if burn_in {
+            let vars = self.stabilizer.output_template_vars();
+            let lens = self.stabilizer.lens.read().get_display_name();
+            let fov = self.stabilizer.params.read().fov;
+            let secs = timestamp_us as f64 / 1_000_000.0;
+            format!("{} | {:02}:{:02}:{:06.3} | {} {} | FOV {:.2} | {}", vars["basename"], (secs / 3600.0) as u32, ((secs / 60.0) as u32) % 60, secs % 60.0, vars["method"], vars["smoothness"], fov, lens)
+        } else {
+            String::new()
+        };
+
+        let stab = self.stabilizer.clone();
+        core::run_threaded(move || {
+            let frame = if is_16bit {
+                rendering::get_stabilized_frame_16bit(&stab, timestamp_us, (width as usize, height as usize))
+            } else {
+                rendering::get_stabilized_frame(&stab, timestamp_us, (width as usize, height as usize))
+            };
+            let result = frame.map_err(|e| GyroflowError::from_message(e.to_string())).and_then(|(w, h, stride, pixels)| {
+                let bytes = util::encode_image(w as u32, h as u32, stride as u32, &pixels, is_16bit, format, &text);
+                if bytes.is_empty() {
+                    return Err(GyroflowError::from_message(format!("Unable to encode the image as {}", format)));
+                }
+                core::filesystem::write(&path, bytes).map_err(GyroflowError::from)
+            });
+            finished((path, result));
+        });
+    }
     fn clear_settings(&self) { util::clear_settings() }
 }
@@ -0,0 +1,587 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2022 Adrian <adrian.eddy at gmail>
+
+// Clip-wide statistics computed without rendering: how much the current settings need to crop,
+// where borders would be visible and how shaky the footage is. Only the trimmed range is analyzed.
+
+use serde::Serialize;
+use std::sync::{ Arc, atomic::{ AtomicBool, Ordering::SeqCst } };
+use crate::keyframes::KeyframeType;
+use crate::gyro_source::{ GyroSource, Quat64 };
+use crate::stabilization::ComputeParams;
+use crate::stabilization_params::StabilizationParams;
+use crate::keyframes::{ KeyframeManager, ValueRange };
+use crate::smoothing::{ SmoothingAlgorithm, horizon::HorizonLock };
+use crate::zooming;
+
+const VIBRATION_CUTOFF_HZ: f64 = 10.0; // Gyro motion above this frequency is considered vibration
+const BORDER_TOLERANCE: f64 = 1.001;   // Ignore borders smaller than 0.1% of the frame
+pub const SEVERE_SHAKE: f64 = 15.0;    // Shake level (deg/s) considered the worst, for the heatmap scale
+const ACCEPTABLE_ZOOM: f64 = 1.3;      // Crop assumed acceptable when estimating the FOV margin, unless the zoom is limited to less
+const COMFORTABLE_MARGIN: f64 = 15.0;  // FOV margin (deg) which allows any smoothness
+const FAST_MOTION: f64 = 60.0;         // Average angular velocity (deg/s) of fast moving footage, eg. FPV
+const BAND_MIN_FREQ: f64 = 20.0;       // Lowest frequency reported as a vibration band, below that it's camera motion rather than noise
+const BAND_MIN_PROMINENCE: f64 = 4.0;  // Peak amplitude relative to the median of the spectrum
+const HORIZON_MIN_DRIFT: f64 = 3.0;    // Difference (deg) between the gravity and optical flow roll which is never considered a drift
+const HORIZON_DRIFT_RATE: f64 = 2.0;   // Drift (deg/s) of the gravity roll against the optical flow roll considered unreliable
+const HORIZON_MIN_SEGMENT: f64 = 0.2;  // Shortest run of analyzed frames (s) used for the comparison
+const TRIM_WINDOW_MS: f64 = 250.0;     // Length of the windows classified as handling, idle or steady
+const TRIM_STEADY_MS: f64 = 2000.0;    // Continuous steady motion needed to consider the recording started
+const TRIM_MIN_HANDLING_MS: f64 = 500.0; // Shorter handling at the clip edge isn't worth trimming
+const TRIM_MAX_RATIO: f64 = 0.4;       // Never suggest trimming more than this part of the clip from one side
+const HANDLING_LPF_HZ: f64 = 15.0;     // Jolts of the handling are below this frequency, vibrations (eg. props) above it
+const HANDLING_JOLT_RATIO: f64 = 4.0;  // Jolt level relative to the median of the clip considered handling
+const IDLE_GYRO: f64 = 2.0;            // Angular velocity (deg/s) of a camera laying still, eg. on the ground before takeoff
+
+#[derive(Default, Clone, Debug, Serialize)]
+pub struct ClipAnalysis {
+    pub analyzed_frames: usize,
+    pub max_required_zoom: f64,             // Zoom needed to hide the borders in the worst frame, 1.0 = no zoom
+    pub max_required_zoom_frame: usize,
+    pub border_frame_count: usize,
+    pub border_frames: Vec<(usize, usize)>, // Ranges of frames (first, last) where borders would be visible at the current zoom
+    pub avg_angular_velocity: f64,          // deg/s
+    pub max_angular_velocity: f64,          // deg/s
+    pub vibration: Option<[f64; 3]>,        // Per axis RMS of the gyro above VIBRATION_CUTOFF_HZ in deg/s, None if there's no raw gyro data
+}
+
+impl ClipAnalysis {
+    // `params` should contain the full gyro data (`ComputeParams::from_manager(mgr, true)`)
+    pub fn compute(params: &ComputeParams, cancel_flag: Arc<AtomicBool>) -> Option<Self> {
+        let fps = params.scaled_fps;
+        let (first_frame, timestamps) = trimmed_timestamps(params)?;
+
+        let mut ret = Self {
+            analyzed_frames: timestamps.len(),
+            max_required_zoom: 1.0,
+            ..Default::default()
+        };
+
+        // ----------- Zoom and borders -----------
+        let required_fovs = zooming::required_fovs(params.clone(), &timestamps);
+        if cancel_flag.load(SeqCst) { return None; }
+
+        for (i, (&ts, &required)) in timestamps.iter().zip(required_fovs.iter()).enumerate() {
+            if required <= 0.0 { continue; }
+            let frame = first_frame + i;
+
+            if 1.0 / required > ret.max_required_zoom {
+                ret.max_required_zoom = 1.0 / required;
+                ret.max_required_zoom_frame = frame;
+            }
+
+            // Same as `FrameTransform::get_fov`, without the output scaling which is already included in `required`
+            let fov_scale = params.keyframes.value_at_video_timestamp(&KeyframeType::Fov, ts).unwrap_or(params.fov_scale);
+            let mut fov = params.fovs.get(frame).copied().unwrap_or(1.0) * fov_scale;
+            if params.fovs.is_empty() && params.lens_fov_adjustment > 0.0001 {
+                fov *= params.lens_fov_adjustment;
+            }
+            if fov > required * BORDER_TOLERANCE {
+                ret.border_frame_count += 1;
+                match ret.border_frames.last_mut() {
+                    Some((_, last)) if *last + 1 == frame => *last = frame,
+                    _ => ret.border_frames.push((frame, frame))
+                }
+            }
+        }
+
+        // ----------- Camera motion -----------
+        let to_gyro_ts = |ts: f64| ts - params.gyro.offset_at_video_timestamp(ts);
+        let start_ms = to_gyro_ts(*timestamps.first()?);
+        let end_ms   = to_gyro_ts(*timestamps.last()? + 1000.0 / fps);
+
+        let quats = params.gyro.quaternions.range((start_ms * 1000.0) as i64..=(end_ms * 1000.0) as i64);
+        (ret.avg_angular_velocity, ret.max_angular_velocity) = angular_velocity(quats);
+        if cancel_flag.load(SeqCst) { return None; }
+
+        // ----------- Vibration -----------
+        let high_passed = high_pass_gyro(&params.gyro, start_ms, end_ms);
+        if !high_passed.is_empty() {
+            let mut sum_sq = [0.0; 3];
+            for (_, s) in &high_passed {
+                for a in 0..3 { sum_sq[a] += s[a] * s[a]; }
+            }
+            ret.vibration = Some(sum_sq.map(|x| (x / high_passed.len() as f64).sqrt()));
+        }
+
+        Some(ret)
+    }
+}
+
+#[derive(Default, Clone, Debug, Serialize)]
+pub struct SmoothingSuggestion {
+    pub smoothness: f64,           // For the default smoothing algorithm
+    pub adaptive_zoom_window: f64, // Same as `StabilizationParams::adaptive_zoom_window`: -1 = static zoom, > 0 = dynamic zooming window in seconds
+    pub shake: f64,                // Median shake level (deg/s)
+    pub fov_margin: f64,           // Horizontal rotation (deg) which can be compensated without exceeding ACCEPTABLE_ZOOM
+}
+
+// Suggests initial stabilization settings, so beginners get a sensible starting point:
+// shakier footage gets more smoothing as long as the lens has enough FOV margin to compensate it,
+// and slow footage gets a static zoom, because there's nothing to gain from zooming dynamically
+pub fn suggest_smoothing(params: &ComputeParams) -> SmoothingSuggestion {
+    let mut shake = shake_per_second(&params.gyro, params.frame_count as f64 * 1000.0 / params.scaled_fps.max(1.0));
+    shake.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let shake = shake.get(shake.len() / 2).copied().unwrap_or_default();
+
+    let (avg_velocity, _) = angular_velocity(params.gyro.quaternions.iter());
+
+    let fov_margin = fov_margin(params);
+
+    let shake_factor  = (shake / SEVERE_SHAKE).clamp(0.0, 1.0);
+    let margin_factor = (fov_margin / COMFORTABLE_MARGIN).clamp(0.0, 1.0);
+    let smoothness = (0.2 + 0.6 * shake_factor * (0.4 + 0.6 * margin_factor)).clamp(0.1, 0.8);
+
+    let adaptive_zoom_window = if shake_factor < 0.1 && avg_velocity < FAST_MOTION / 4.0 {
+        -1.0
+    } else if avg_velocity > FAST_MOTION {
+        2.0
+    } else {
+        4.0
+    };
+
+    SmoothingSuggestion {
+        smoothness: (smoothness * 100.0).round() / 100.0,
+        adaptive_zoom_window,
+        shake,
+        fov_margin
+    }
+}
+
+#[derive(Default, Clone, Debug, Serialize)]
+pub struct SmoothnessSearch {
+    pub parameter: String, // Smoothing parameter which was tuned, eg. "smoothness"
+    pub value: f64,
+    pub max_crop: f64,     // Highest crop over the trim range at the found value, in percent of the frame
+    pub target_crop: f64,
+    pub iterations: usize,
+    pub reached: bool,     // false if even the lowest smoothing crops more than the target
+}
+
+const SEARCH_ITERATIONS: usize = 12;
+
+// Binary search of the highest smoothing strength for which the zoom needed to hide the borders never crops more than `target_crop` percent.
+// More smoothing always needs more zoom, so the crop is monotonic in the smoothing strength.
+// `params` should contain the full gyro data (`ComputeParams::from_manager(mgr, true)`)
+pub fn search_smoothness<F: Fn(f64)>(params: &ComputeParams, alg: &dyn SmoothingAlgorithm, horizon_lock: HorizonLock, stab_params: &StabilizationParams, keyframes: &KeyframeManager, target_crop: f64, progress_cb: F, cancel_flag: Arc<AtomicBool>) -> Option<SmoothnessSearch> {
+    let (_, timestamps) = trimmed_timestamps(params)?;
+
+    // The default algorithm is tuned with "smoothness", the plain one with "time_constant"
+    let param_json = alg.get_parameters_json();
+    let param = param_json.as_array()?.iter().find(|x| {
+        matches!(x.get("keyframe").and_then(|x| x.as_str()), Some("SmoothingParamSmoothness") | Some("SmoothingParamTimeConstant"))
+    })?;
+    let name = param.get("name")?.as_str()?.to_string();
+    let range = ValueRange::from_parameter_json(param)?;
+
+    let max_crop_at = |value: f64| -> Option<f64> {
+        let mut alg = dyn_clone::clone_box(alg);
+        alg.set_parameter(&name, value);
+        let mut params = params.clone();
+        params.gyro.recompute_smoothness(alg.as_ref(), horizon_lock.clone(), stab_params, keyframes);
+        if cancel_flag.load(SeqCst) { return None; }
+
+        let max_zoom = zooming::required_fovs(params, &timestamps).into_iter()
+            .filter(|x| *x > 0.0)
+            .fold(1.0f64, |max, x| max.max(1.0 / x));
+        Some((1.0 - 1.0 / max_zoom) * 100.0)
+    };
+
+    let mut ret = SmoothnessSearch {
+        parameter: name.clone(),
+        value: range.min,
+        max_crop: max_crop_at(range.min)?,
+        target_crop,
+        iterations: 1,
+        reached: false,
+    };
+    if ret.max_crop > target_crop {
+        return Some(ret);
+    }
+    ret.reached = true;
+
+    let (mut low, mut high) = (range.min, range.max);
+    for i in 0..SEARCH_ITERATIONS {
+        progress_cb(i as f64 / SEARCH_ITERATIONS as f64);
+        if high - low < range.step { break; }
+        let mid = (low + high) / 2.0;
+        let crop = max_crop_at(mid)?;
+        ret.iterations += 1;
+        if crop <= target_crop {
+            low = mid;
+            ret.value = mid;
+            ret.max_crop = crop;
+        } else {
+            high = mid;
+        }
+    }
+    progress_cb(1.0);
+    // Round down to the precision of the parameter, so the UI shows a value which still fits the target
+    ret.value = ((ret.value / range.step).floor() * range.step).max(range.min);
+    Some(ret)
+}
+
+#[derive(Default, Clone, Debug, Serialize)]
+pub struct HorizonDrift {
+    pub start_ms: f64,  // Video time
+    pub end_ms: f64,
+    pub drift: f64,     // Gravity roll change which isn't explained by the camera motion (deg)
+    pub drift_rate: f64, // deg/s
+    pub lock_factor: f64 // Suggested horizon lock amount relative to the current one, lower for stronger drift
+}
+#[derive(Default, Clone, Debug, Serialize)]
+pub struct HorizonStability {
+    pub analyzed_segments: usize,
+    pub drifting: Vec<HorizonDrift>, // Segments where the gravity vectors can't be trusted
+    pub max_drift_rate: f64,
+    pub is_stable: bool,
+}
+
+// Horizon lock with gravity vectors is only as good as the accelerometer. The camera roll from the optical flow (sync results)
+// is compared with the change of the gravity vector roll over every run of analyzed frames, a difference means the gravity drifts
+pub fn horizon_lock_stability(gyro: &GyroSource, sync_results: &std::collections::BTreeMap<i64, crate::synchronization::FrameResult>) -> Option<HorizonStability> {
+    let gravity = gyro.gravity_vectors.as_ref().filter(|x| !x.is_empty())?;
+    let gravity_roll = |video_ts_us: i64| -> Option<f64> {
+        let ts_ms = video_ts_us as f64 / 1000.0;
+        let gyro_ts = ((ts_ms - gyro.offset_at_video_timestamp(ts_ms)) * 1000.0).round() as i64;
+        let gv = crate::smoothing::horizon::HorizonLock::interpolate_gravity_vector(gravity, gyro_ts)?;
+        Some(gv[0].atan2(gv[1]).to_degrees())
+    };
+    let wrap = |a: f64| (a + 180.0).rem_euclid(360.0) - 180.0;
+
+    // (start_us, end_us, optical flow roll, gravity roll change)
+    let mut segments = Vec::new();
+    // The optical flow euler angles are rates in rad/s, each one applies until the next frame.
+    // (start_us, end_us, last frame number, roll until end_us, roll rate of the last frame)
+    let mut current: Option<(i64, i64, usize, f64, f64)> = None;
+    let close = |seg: Option<(i64, i64, usize, f64, f64)>, segments: &mut Vec<(i64, i64, f64, f64)>| {
+        if let Some((start, end, _, of_roll, _)) = seg {
+            if (end - start) as f64 / 1_000_000.0 >= HORIZON_MIN_SEGMENT {
+                if let (Some(g1), Some(g2)) = (gravity_roll(start), gravity_roll(end)) {
+                    segments.push((start, end, of_roll, wrap(g2 - g1)));
+                }
+            }
+        }
+    };
+    for (ts, fr) in sync_results.iter() {
+        let continues = current.map(|seg| fr.frame_no == seg.2 + 1).unwrap_or_default();
+        match fr.euler {
+            Some(e) if continues => {
+                if let Some(seg) = current.as_mut() {
+                    seg.3 += seg.4 * (*ts - seg.1) as f64 / 1_000_000.0;
+                    seg.1 = *ts;
+                    seg.2 = fr.frame_no;
+                    seg.4 = e.2.to_degrees();
+                }
+            }
+            Some(e) => {
+                close(current.take(), &mut segments);
+                current = Some((*ts, *ts, fr.frame_no, 0.0, e.2.to_degrees()));
+            }
+            None => close(current.take(), &mut segments),
+        }
+    }
+    close(current.take(), &mut segments);
+    if segments.is_empty() { return None; }
+
+    // The sign convention of the gravity roll relative to the optical flow depends on the IMU orientation, so use the one which fits better
+    let residual = |sign: f64| segments.iter().map(|(_, _, of, g)| (g - sign * of).abs()).sum::<f64>();
+    let sign = if residual(1.0) <= residual(-1.0) { 1.0 } else { -1.0 };
+
+    let mut ret = HorizonStability { analyzed_segments: segments.len(), ..Default::default() };
+    for (start, end, of_roll, grav_roll) in segments {
+        let drift = (grav_roll - sign * of_roll).abs();
+        let drift_rate = drift / ((end - start) as f64 / 1_000_000.0);
+        ret.max_drift_rate = ret.max_drift_rate.max(drift_rate);
+        if drift > HORIZON_MIN_DRIFT && drift_rate > HORIZON_DRIFT_RATE {
+            ret.drifting.push(HorizonDrift {
+                start_ms: start as f64 / 1000.0,
+                end_ms: end as f64 / 1000.0,
+                drift,
+                drift_rate,
+                lock_factor: (HORIZON_DRIFT_RATE / drift_rate).clamp(0.0, 1.0)
+            });
+        }
+    }
+    ret.is_stable = ret.drifting.is_empty();
+    Some(ret)
+}
+
+fn trimmed_timestamps(params: &ComputeParams) -> Option<(usize, Vec<f64>)> {
+    let fps = params.scaled_fps;
+    if params.frame_count == 0 || fps <= 0.0 { return None; }
+
+    let first_frame = ((params.frame_count as f64 * params.trim_start).floor() as usize).min(params.frame_count - 1);
+    let last_frame  = ((params.frame_count as f64 * params.trim_end).ceil() as usize).clamp(first_frame + 1, params.frame_count);
+    Some((first_frame, (first_frame..last_frame).map(|i| i as f64 * 1000.0 / fps).collect()))
+}
+
+fn fov_margin(params: &ComputeParams) -> f64 {
+    let fx = params.camera_matrix[(0, 0)];
+    if fx <= 0.0 { return 0.0; }
+    let max_zoom = if params.max_zoom > 1.0 { params.max_zoom.min(ACCEPTABLE_ZOOM) } else { ACCEPTABLE_ZOOM };
+
+    // Angle of the edge of the frame, after removing the lens distortion
+    let edge = (params.video_width as f64 / 2.0) / fx;
+    let undistorted = params.distortion_model.undistort_point((edge, 0.0), &params.distortion_coeffs, 1.0).map(|x| x.0).unwrap_or(edge);
+
+    let half_fov = undistorted.atan();
+    let cropped_half_fov = (undistorted / max_zoom).atan();
+    (half_fov - cropped_half_fov).to_degrees().max(0.0)
+}
+
+// Shake level for each second of the video: RMS magnitude of the gyro above VIBRATION_CUTOFF_HZ in deg/s
+pub fn shake_per_second(gyro: &GyroSource, duration_ms: f64) -> Vec<f64> {
+    let seconds = (duration_ms / 1000.0).ceil().max(0.0) as usize;
+    let to_gyro_ts = |ts: f64| ts - gyro.offset_at_video_timestamp(ts);
+
+    let high_passed = high_pass_gyro(gyro, to_gyro_ts(0.0), to_gyro_ts(duration_ms));
+    let mut sum_sq = vec![0.0; seconds];
+    let mut count = vec![0usize; seconds];
+
+    // Offsets change slowly, so it's enough to map the second boundaries to the gyro timestamps
+    let boundaries: Vec<f64> = (0..=seconds).map(|i| to_gyro_ts(i as f64 * 1000.0)).collect();
+    let mut second = 0;
+    for (ts, s) in &high_passed {
+        while second < seconds && *ts >= boundaries[second + 1] { second += 1; }
+        if second >= seconds { break; }
+        if *ts < boundaries[second] { continue; }
+        sum_sq[second] += s[0] * s[0] + s[1] * s[1] + s[2] * s[2];
+        count[second] += 1;
+    }
+    sum_sq.iter().zip(count.iter()).map(|(sq, n)| if *n > 0 { (sq / *n as f64).sqrt() } else { 0.0 }).collect()
+}
+
+#[derive(Default, Clone, Debug, Serialize)]
+pub struct VibrationBand {
+    pub time_ms: f64,      // Start of the analysis window in video time
+    pub duration_ms: f64,
+    pub frequency_hz: f64, // Peak frequency
+    pub low_hz: f64,       // Band edges, where the amplitude drops to half of the peak
+    pub high_hz: f64,
+    pub amplitude: f64,    // deg/s
+}
+
+// Dominant vibration frequencies over time, for notching the matching noise (eg. props) in the audio.
+// The gyro is analyzed in `window_ms` long windows, each one reports up to `max_peaks` strongest bands
+pub fn vibration_bands(gyro: &GyroSource, duration_ms: f64, window_ms: f64, max_peaks: usize) -> Vec<VibrationBand> {
+    let sample_rate = gyro.get_sample_rate();
+    if sample_rate < BAND_MIN_FREQ * 2.0 || window_ms <= 0.0 { return Vec::new(); }
+
+    let mut planner = rustfft::FftPlanner::<f64>::new();
+    let mut ret = Vec::new();
+
+    let mut time_ms = 0.0;
+    while time_ms < duration_ms {
+        let start = time_ms - gyro.offset_at_video_timestamp(time_ms);
+        let end = start + window_ms;
+        let from = gyro.org_raw_imu.partition_point(|x| x.timestamp_ms < start);
+        let to   = gyro.org_raw_imu.partition_point(|x| x.timestamp_ms < end);
+        let samples: Vec<[f64; 3]> = gyro.org_raw_imu[from..to.max(from)].iter().filter_map(|x| x.gyro).collect();
+
+        let n = samples.len();
+        if n >= 16 {
+            let fft = planner.plan_fft_forward(n);
+            let window: Vec<f64> = (0..n).map(|i| 0.5 - 0.5 * (2.0 * std::f64::consts::PI * i as f64 / (n - 1) as f64).cos()).collect(); // Hann
+            let window_sum: f64 = window.iter().sum();
+
+            // Combined amplitude spectrum of all axes
+            let mut spectrum = vec![0.0; n / 2];
+            for a in 0..3 {
+                let mean = samples.iter().map(|x| x[a]).sum::<f64>() / n as f64;
+                let mut buf: Vec<rustfft::num_complex::Complex<f64>> = samples.iter().zip(&window).map(|(x, w)| rustfft::num_complex::Complex::new((x[a] - mean) * w, 0.0)).collect();
+                fft.process(&mut buf);
+                for (s, c) in spectrum.iter_mut().zip(&buf) {
+                    *s += (c.norm() * 2.0 / window_sum).powi(2);
+                }
+            }
+            spectrum.iter_mut().for_each(|x| *x = x.sqrt());
+
+            let bin_hz = sample_rate / n as f64;
+            let first_bin = ((BAND_MIN_FREQ / bin_hz).ceil() as usize).max(1);
+            if first_bin + 2 < spectrum.len() {
+                let mut sorted = spectrum[first_bin..].to_vec();
+                sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+                let median = sorted[sorted.len() / 2];
+
+                let mut peaks: Vec<usize> = (first_bin..spectrum.len() - 1)
+                    .filter(|&i| spectrum[i] > spectrum[i - 1] && spectrum[i] >= spectrum[i + 1] && spectrum[i] > median * BAND_MIN_PROMINENCE)
+                    .collect();
+                peaks.sort_by(|a, b| spectrum[*b].partial_cmp(&spectrum[*a]).unwrap_or(std::cmp::Ordering::Equal));
+
+                let mut bands: Vec<VibrationBand> = Vec::new();
+                for peak in peaks {
+                    if bands.len() >= max_peaks { break; }
+                    let half = spectrum[peak] / 2.0;
+                    let mut lo = peak;
+                    while lo > first_bin && spectrum[lo - 1] > half { lo -= 1; }
+                    let mut hi = peak;
+                    while hi < spectrum.len() - 1 && spectrum[hi + 1] > half { hi += 1; }
+
+                    // Skip smaller peaks within an already reported band
+                    let frequency_hz = peak as f64 * bin_hz;
+                    if bands.iter().any(|b| frequency_hz >= b.low_hz && frequency_hz <= b.high_hz) { continue; }
+
+                    bands.push(VibrationBand {
+                        time_ms,
+                        duration_ms: window_ms.min(duration_ms - time_ms),
+                        frequency_hz,
+                        low_hz: lo as f64 * bin_hz,
+                        high_hz: hi as f64 * bin_hz,
+                        amplitude: spectrum[peak],
+                    });
+                }
+                bands.sort_by(|a, b| a.frequency_hz.partial_cmp(&b.frequency_hz).unwrap_or(std::cmp::Ordering::Equal));
+                ret.extend(bands);
+            }
+        }
+        time_ms += window_ms;
+    }
+    ret
+}
+
+pub fn vibration_bands_to_csv(bands: &[VibrationBand]) -> String {
+    let mut ret = String::from("time_s,duration_s,frequency_hz,low_hz,high_hz,amplitude_dps\n");
+    for b in bands {
+        ret.push_str(&format!("{:.3},{:.3},{:.1},{:.1},{:.1},{:.3}\n", b.time_ms / 1000.0, b.duration_ms / 1000.0, b.frequency_hz, b.low_hz, b.high_hz, b.amplitude));
+    }
+    ret
+}
+
+// (average, max) angular velocity in deg/s
+fn angular_velocity<'a>(quats: impl Iterator<Item = (&'a i64, &'a Quat64)>) -> (f64, f64) {
+    let (mut sum, mut max, mut count) = (0.0, 0.0f64, 0);
+    let mut prev: Option<(&i64, &Quat64)> = None;
+    for (ts, q) in quats {
+        if let Some((prev_ts, prev_q)) = prev {
+            let dt = (ts - prev_ts) as f64 / 1_000_000.0;
+            if dt > 0.0 {
+                let velocity = (prev_q.inverse() * q).angle().to_degrees() / dt;
+                max = max.max(velocity);
+                sum += velocity;
+                count += 1;
+            }
+        }
+        prev = Some((ts, q));
+    }
+    (if count > 0 { sum / count as f64 } else { 0.0 }, max)
+}
+
+// High-pass the raw gyro within a range of gyro timestamps by subtracting a centered moving average.
+// Returns (timestamp_ms, [x, y, z]) in deg/s, empty if there's no raw gyro data
+fn high_pass_gyro(gyro: &GyroSource, start_ms: f64, end_ms: f64) -> Vec<(f64, [f64; 3])> {
+    let samples: Vec<(f64, [f64; 3])> = gyro.org_raw_imu.iter()
+        .filter(|x| x.timestamp_ms >= start_ms && x.timestamp_ms <= end_ms)
+        .filter_map(|x| Some((x.timestamp_ms, x.gyro?)))
+        .collect();
+    let sample_rate = gyro.get_sample_rate();
+    if samples.len() <= 2 || sample_rate <= 0.0 { return Vec::new(); }
+
+    let half_window = (sample_rate / VIBRATION_CUTOFF_HZ / 2.0).round().max(1.0) as usize;
+    let mut prefix = vec![[0.0; 3]; samples.len() + 1];
+    for (i, (_, s)) in samples.iter().enumerate() {
+        for a in 0..3 { prefix[i + 1][a] = prefix[i][a] + s[a]; }
+    }
+    samples.iter().enumerate().map(|(i, (ts, s))| {
+        let (lo, hi) = (i.saturating_sub(half_window), (i + half_window + 1).min(samples.len()));
+        let mut ret = [0.0; 3];
+        for a in 0..3 {
+            ret[a] = s[a] - (prefix[hi][a] - prefix[lo][a]) / (hi - lo) as f64;
+        }
+        (*ts, ret)
+    }).collect()
+}
+
+#[derive(Default, Clone, Debug, Serialize)]
+pub struct TrimSuggestion {
+    pub trim_start: f64, // 0..1
+    pub trim_end: f64,   // 0..1
+    pub start_handling_ms: f64,
+    pub end_handling_ms: f64,
+}
+
+// Detects handling at the start and end of the clip (picking up the camera, pressing buttons, mounting, takeoff and landing).
+// The clip is split into short windows: the ones with strong jolts (rate of change of the low passed accelerometer, or gyro without it)
+// compared to the rest of the clip are handling, the ones without rotation are idle. The suggested range is between
+// the first and the last `TRIM_STEADY_MS` of continuous motion which is neither. `None` if there's nothing to trim
+pub fn suggest_trim(gyro: &GyroSource, duration_ms: f64) -> Option<TrimSuggestion> {
+    let windows = (duration_ms / TRIM_WINDOW_MS).floor() as usize;
+    let steady_windows = (TRIM_STEADY_MS / TRIM_WINDOW_MS).ceil() as usize;
+    if windows < steady_windows * 2 { return None; }
+
+    let sample_rate = gyro.get_sample_rate();
+    if gyro.org_raw_imu.len() <= 2 || sample_rate <= 0.0 { return None; }
+
+    let has_accl = gyro.org_raw_imu.iter().any(|x| x.accl.is_some());
+    let samples: Vec<(f64, [f64; 3], [f64; 3])> = gyro.org_raw_imu.iter()
+        .filter_map(|x| {
+            let g = x.gyro?;
+            Some((x.timestamp_ms, g, if has_accl { x.accl? } else { g }))
+        })
+        .collect();
+    if samples.len() <= 2 { return None; }
+
+    // Centered moving average, same as in `high_pass_gyro`
+    let half_window = (sample_rate / HANDLING_LPF_HZ / 2.0).round().max(1.0) as usize;
+    let mut prefix = vec![[0.0; 3]; samples.len() + 1];
+    for (i, (_, _, s)) in samples.iter().enumerate() {
+        for a in 0..3 { prefix[i + 1][a] = prefix[i][a] + s[a]; }
+    }
+    let low_passed = |i: usize| -> [f64; 3] {
+        let (lo, hi) = (i.saturating_sub(half_window), (i + half_window + 1).min(samples.len()));
+        let mut ret = [0.0; 3];
+        for a in 0..3 { ret[a] = (prefix[hi][a] - prefix[lo][a]) / (hi - lo) as f64; }
+        ret
+    };
+
+    let to_gyro_ts = |ts: f64| ts - gyro.offset_at_video_timestamp(ts);
+    let boundaries: Vec<f64> = (0..=windows).map(|i| to_gyro_ts(i as f64 * TRIM_WINDOW_MS)).collect();
+    let mut rotation_sq = vec![0.0; windows];
+    let mut jolt_sq = vec![0.0; windows];
+    let mut count = vec![0usize; windows];
+    let mut window = 0;
+    let mut prev: Option<(f64, [f64; 3])> = None;
+    for (i, (ts, g, _)) in samples.iter().enumerate() {
+        let current = low_passed(i);
+        let prev_sample = prev.replace((*ts, current));
+        while window < windows && *ts >= boundaries[window + 1] { window += 1; }
+        if window >= windows { break; }
+        if *ts < boundaries[window] { continue; }
+        if let Some((prev_ts, prev_v)) = prev_sample {
+            let dt = (ts - prev_ts) / 1000.0;
+            if dt > 0.0 {
+                let d = [(current[0] - prev_v[0]) / dt, (current[1] - prev_v[1]) / dt, (current[2] - prev_v[2]) / dt];
+                jolt_sq[window] += d[0] * d[0] + d[1] * d[1] + d[2] * d[2];
+                rotation_sq[window] += g[0] * g[0] + g[1] * g[1] + g[2] * g[2];
+                count[window] += 1;
+            }
+        }
+    }
+    let rms = |sq: &[f64]| -> Vec<f64> { sq.iter().zip(count.iter()).map(|(s, n)| if *n > 0 { (s / *n as f64).sqrt() } else { 0.0 }).collect() };
+    let (rotation, jolt) = (rms(&rotation_sq), rms(&jolt_sq));
+
+    let mut sorted_jolt: Vec<f64> = jolt.iter().zip(count.iter()).filter(|(_, n)| **n > 0).map(|(j, _)| *j).collect();
+    if sorted_jolt.is_empty() { return None; }
+    sorted_jolt.sort_by(|a, b| a.total_cmp(b));
+    let median_jolt = sorted_jolt[sorted_jolt.len() / 2];
+
+    let steady: Vec<bool> = (0..windows).map(|i| {
+        count[i] > 0 && rotation[i] >= IDLE_GYRO && jolt[i] <= median_jolt * HANDLING_JOLT_RATIO
+    }).collect();
+
+    let first = (0..=windows - steady_windows).find(|&i| steady[i..i + steady_windows].iter().all(|x| *x))?;
+    let last  = (steady_windows - 1..windows).rev().find(|&i| steady[i + 1 - steady_windows..=i].iter().all(|x| *x))? + 1;
+
+    let mut start_handling_ms = first as f64 * TRIM_WINDOW_MS;
+    let mut end_handling_ms = (duration_ms - last as f64 * TRIM_WINDOW_MS).max(0.0);
+    if start_handling_ms < TRIM_MIN_HANDLING_MS || start_handling_ms > duration_ms * TRIM_MAX_RATIO { start_handling_ms = 0.0; }
+    if end_handling_ms   < TRIM_MIN_HANDLING_MS || end_handling_ms   > duration_ms * TRIM_MAX_RATIO { end_handling_ms = 0.0; }
+    if start_handling_ms == 0.0 && end_handling_ms == 0.0 { return None; }
+
+    Some(TrimSuggestion {
+        trim_start: start_handling_ms / duration_ms,
+        trim_end: 1.0 - end_handling_ms / duration_ms,
+        start_handling_ms,
+        end_handling_ms,
+    })
+}
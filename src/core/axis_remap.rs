@@ -0,0 +1,170 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2022 Adrian <adrian.eddy at gmail>
+
+// IMU orientation from simple camera motions performed by the user, instead of trying all 48 combinations.
+// Every capture is a time range in which the camera was rotated around one axis (eg. "tilt up"). The raw gyro is integrated
+// over the range and the orientation which maps the responses to the expected axes and signs is chosen.
+// When the optical flow (sync results) covers the capture, its response is used as the expected one, otherwise the table below.
+// One axis can't be deduced from less than 2 captures around different axes, the sign of the last one needs all 3.
+
+use serde::Serialize;
+use crate::gyro_source::{ GyroSource, TimeIMU };
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum CameraMotion {
+    TiltUp,
+    TiltDown,
+    PanLeft,
+    PanRight,
+    RollLeft,
+    RollRight,
+}
+
+impl CameraMotion {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "tilt_up"    => Some(Self::TiltUp),
+            "tilt_down"  => Some(Self::TiltDown),
+            "pan_left"   => Some(Self::PanLeft),
+            "pan_right"  => Some(Self::PanRight),
+            "roll_left"  => Some(Self::RollLeft),
+            "roll_right" => Some(Self::RollRight),
+            _ => None
+        }
+    }
+
+    // (axis, sign) of the oriented gyro: X is pan, Y is tilt and Z is roll, same as the sync results
+    pub fn expected_axis(&self) -> (usize, f64) {
+        match self {
+            Self::PanRight  => (0,  1.0),
+            Self::PanLeft   => (0, -1.0),
+            Self::TiltUp    => (1,  1.0),
+            Self::TiltDown  => (1, -1.0),
+            Self::RollRight => (2,  1.0),
+            Self::RollLeft  => (2, -1.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AxisCapture {
+    pub motion: CameraMotion,
+    pub start_ms: f64, // video timestamps
+    pub end_ms: f64,
+    pub response: [f64; 3],          // Integrated raw gyro in degrees, before the orientation is applied
+    pub reference: Option<[f64; 3]>, // Integrated optical flow in degrees, if available
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AxisRemapResult {
+    pub orientation: String,
+    pub score: f64, // 0..1, how well the captures agree with the orientation
+    pub candidates: Vec<String>, // All orientations with the same score, more than one if the captures don't cover enough axes
+    pub is_ambiguous: bool,
+}
+
+// Minimum rotation (deg) for the capture to be usable
+const MIN_ROTATION: f64 = 5.0;
+
+fn integrate(samples: &[TimeIMU], from_ms: f64, to_ms: f64) -> [f64; 3] {
+    let mut sum = [0.0; 3];
+    let mut prev_ts = None;
+    for x in samples.iter().filter(|x| x.timestamp_ms >= from_ms && x.timestamp_ms <= to_ms) {
+        if let Some(g) = x.gyro.as_ref() {
+            if let Some(prev_ts) = prev_ts {
+                let dt = (x.timestamp_ms - prev_ts) / 1000.0;
+                for i in 0..3 { sum[i] += g[i] * dt; }
+            }
+            prev_ts = Some(x.timestamp_ms);
+        }
+    }
+    sum
+}
+
+impl AxisCapture {
+    /// Integrates the gyro over the range of the capture. `start_ms` and `end_ms` are video timestamps
+    pub fn new(motion: CameraMotion, start_ms: f64, end_ms: f64, gyro: &GyroSource, sync_results: &[TimeIMU]) -> Result<Self, String> {
+        let (start_ms, end_ms) = (start_ms.min(end_ms), start_ms.max(end_ms));
+        let response = integrate(&gyro.org_raw_imu,
+            start_ms - gyro.offset_at_video_timestamp(start_ms),
+            end_ms   - gyro.offset_at_video_timestamp(end_ms));
+
+        let magnitude = response.iter().map(|x| x * x).sum::<f64>().sqrt();
+        if magnitude < MIN_ROTATION {
+            return Err(format!("Rotation in the captured range is too small ({:.1} deg)", magnitude));
+        }
+
+        let reference = integrate(sync_results, start_ms, end_ms);
+        let has_reference = reference.iter().map(|x| x * x).sum::<f64>().sqrt() >= MIN_ROTATION;
+
+        Ok(Self { motion, start_ms, end_ms, response, reference: has_reference.then(|| reference) })
+    }
+
+    fn target(&self) -> [f64; 3] {
+        match self.reference {
+            Some(r) => r,
+            None => {
+                let (axis, sign) = self.motion.expected_axis();
+                let mut v = [0.0; 3];
+                v[axis] = sign;
+                v
+            }
+        }
+    }
+}
+
+fn all_orientations() -> Vec<String> {
+    const PERMUTATIONS: [[u8; 3]; 6] = [*b"XYZ", *b"XZY", *b"YXZ", *b"YZX", *b"ZXY", *b"ZYX"];
+    let mut ret = Vec::with_capacity(48);
+    for p in PERMUTATIONS {
+        for signs in 0..8 {
+            ret.push((0..3).map(|i| if signs & (1 << i) != 0 { p[i].to_ascii_lowercase() as char } else { p[i] as char }).collect());
+        }
+    }
+    ret
+}
+
+// Same mapping as in `GyroSource::apply_transforms`
+fn orient(v: &[f64; 3], orientation: &[u8]) -> [f64; 3] {
+    let map = |o: u8| -> f64 {
+        match o {
+            b'X' => v[0], b'x' => -v[0],
+            b'Y' => v[1], b'y' => -v[1],
+            b'Z' => v[2], b'z' => -v[2],
+            _ => 0.0
+        }
+    };
+    [map(orientation[0]), map(orientation[1]), map(orientation[2])]
+}
+
+fn normalized(v: [f64; 3]) -> [f64; 3] {
+    let len = v.iter().map(|x| x * x).sum::<f64>().sqrt();
+    if len > 0.0 { [v[0] / len, v[1] / len, v[2] / len] } else { v }
+}
+
+/// Orientation string which best explains all the captures
+pub fn deduce_orientation(captures: &[AxisCapture]) -> Option<AxisRemapResult> {
+    if captures.is_empty() { return None; }
+
+    let scored: Vec<(String, f64)> = all_orientations().into_iter().map(|orientation| {
+        let score = captures.iter().map(|c| {
+            let oriented = normalized(orient(&c.response, orientation.as_bytes()));
+            let target = normalized(c.target());
+            (0..3).map(|i| oriented[i] * target[i]).sum::<f64>()
+        }).sum::<f64>() / captures.len() as f64;
+        (orientation, score)
+    }).collect();
+
+    let best = scored.iter().map(|x| x.1).fold(f64::MIN, f64::max);
+    let candidates: Vec<String> = scored.into_iter().filter(|x| (x.1 - best).abs() < 0.01).map(|x| x.0).collect();
+
+    // Prefer the one closest to the default orientation if ambiguous
+    let orientation = candidates.iter().find(|x| x.as_str() == "XYZ").unwrap_or(&candidates[0]).clone();
+
+    Some(AxisRemapResult {
+        orientation,
+        score: best.max(0.0),
+        is_ambiguous: candidates.len() > 1,
+        candidates,
+    })
+}
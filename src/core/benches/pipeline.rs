@@ -0,0 +1,77 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2022 Adrian <adrian.eddy at gmail>
+
+use criterion::{ criterion_group, criterion_main, Criterion, Throughput, BenchmarkId };
+use gyroflow_core::benchmark::*;
+
+fn integration(c: &mut Criterion) {
+    let md = synthetic_metadata(SYNTHETIC_DURATION_MS);
+    let stab = prepare_manager(&md, SYNTHETIC_DURATION_MS, SYNTHETIC_FPS, FRAME_SIZE);
+    let samples = md.raw_imu.as_ref().map(|x| x.len()).unwrap_or_default();
+
+    let mut group = c.benchmark_group("integration");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(samples as u64));
+    for method in 1..=6 {
+        stab.gyro.write().integration_method = method;
+        group.bench_with_input(BenchmarkId::from_parameter(method), &method, |b, _| b.iter(|| stab.gyro.write().integrate()));
+    }
+    group.finish();
+}
+
+fn smoothing(c: &mut Criterion) {
+    let md = synthetic_metadata(SYNTHETIC_DURATION_MS);
+    let stab = prepare_manager(&md, SYNTHETIC_DURATION_MS, SYNTHETIC_FPS, FRAME_SIZE);
+
+    let mut group = c.benchmark_group("smoothing");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(stab.gyro.read().quaternions.len() as u64));
+    for (i, name) in stab.get_smoothing_algs().into_iter().enumerate() {
+        stab.set_smoothing_method(i);
+        group.bench_function(name, |b| b.iter(|| stab.recompute_smoothness()));
+    }
+    group.finish();
+}
+
+fn zooming(c: &mut Criterion) {
+    let md = synthetic_metadata(SYNTHETIC_DURATION_MS);
+    let stab = prepare_manager(&md, SYNTHETIC_DURATION_MS, SYNTHETIC_FPS, FRAME_SIZE);
+    stab.set_adaptive_zoom(4.0);
+
+    let mut group = c.benchmark_group("zooming");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(stab.params.read().frame_count as u64));
+    group.bench_function("adaptive", |b| b.iter(|| stab.recompute_adaptive_zoom()));
+    group.finish();
+}
+
+fn warp(c: &mut Criterion) {
+    let md = synthetic_metadata(SYNTHETIC_DURATION_MS);
+    let stab = prepare_manager(&md, SYNTHETIC_DURATION_MS, SYNTHETIC_FPS, FRAME_SIZE);
+    let frame_count = stab.params.read().frame_count;
+    let mut input = synthetic_frame(FRAME_SIZE);
+    let mut output = vec![0u8; input.len()];
+
+    let mut group = c.benchmark_group("warp");
+    group.sample_size(20);
+    group.throughput(Throughput::Elements(1));
+
+    warp_frame(&stab, 0, FRAME_SIZE, &mut input, &mut output);
+    let backend = stab.stabilization.read().current_backend();
+    let mut frame = 0;
+    if backend != "CPU" {
+        group.bench_function(backend, |b| b.iter(|| {
+            frame = (frame + 1) % frame_count;
+            warp_frame(&stab, frame, FRAME_SIZE, &mut input, &mut output)
+        }));
+    }
+    stab.stabilization.write().set_device(-1);
+    group.bench_function("CPU", |b| b.iter(|| {
+        frame = (frame + 1) % frame_count;
+        warp_frame(&stab, frame, FRAME_SIZE, &mut input, &mut output)
+    }));
+    group.finish();
+}
+
+criterion_group!(benches, integration, smoothing, zooming, warp);
+criterion_main!(benches);
@@ -0,0 +1,249 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2022 Adrian <adrian.eddy at gmail>
+
+// Measures the throughput of the core pipeline stages on the current machine.
+// Used by the `--benchmark` command line mode and by the criterion benchmarks in `benches/`.
+// Without an input file, the gyro data is synthetic, so the numbers are comparable between machines.
+
+use std::sync::{ Arc, atomic::AtomicBool };
+use std::time::Instant;
+use serde::Serialize;
+use crate::{ StabilizationManager, gyro_source::{ GyroSource, FileMetadata, TimeIMU } };
+use crate::stabilization::RGBA8;
+use crate::gpu::{ BufferDescription, BufferSource };
+
+pub const FRAME_SIZE: (usize, usize) = (1920, 1080);
+pub const SYNTHETIC_DURATION_MS: f64 = 60_000.0;
+pub const SYNTHETIC_IMU_RATE: f64 = 1000.0;
+pub const SYNTHETIC_FPS: f64 = 30.0;
+
+const INTEGRATORS: [(usize, &str); 6] = [
+    (1, "Complementary"),
+    (2, "VQF"),
+    (3, "Simple gyro"),
+    (4, "Simple gyro + accel"),
+    (5, "Mahony"),
+    (6, "Madgwick"),
+];
+
+#[derive(Serialize, Debug, Clone)]
+pub struct StageResult {
+    pub stage: String,
+    pub iterations: usize,
+    pub mean_ms: f64,
+    pub min_ms: f64,
+    pub max_ms: f64,
+    pub throughput: f64, // `unit` per second
+    pub unit: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct BenchmarkReport {
+    pub version: String,
+    pub os: String,
+    pub arch: String,
+    pub threads: usize,
+    pub input: Option<String>,
+    pub imu_samples: usize,
+    pub duration_ms: f64,
+    pub fps: f64,
+    pub frame_size: (usize, usize),
+    pub gpu_backend: String,
+    pub stages: Vec<StageResult>,
+}
+
+pub fn measure<F: FnMut()>(stage: &str, iterations: usize, items: usize, unit: &str, mut cb: F) -> StageResult {
+    let mut times = Vec::with_capacity(iterations);
+    for _ in 0..iterations.max(1) {
+        let start = Instant::now();
+        cb();
+        times.push(start.elapsed().as_secs_f64() * 1000.0);
+    }
+    let mean_ms = times.iter().sum::<f64>() / times.len() as f64;
+    let result = StageResult {
+        stage: stage.to_string(),
+        iterations: times.len(),
+        mean_ms,
+        min_ms: times.iter().copied().fold(f64::MAX, f64::min),
+        max_ms: times.iter().copied().fold(0.0, f64::max),
+        throughput: if mean_ms > 0.0 { items as f64 / (mean_ms / 1000.0) } else { 0.0 },
+        unit: unit.to_string(),
+    };
+    log::info!("{:<36} {:>10.3} ms (min {:.3}, max {:.3}), {:.1} {}/s", result.stage, result.mean_ms, result.min_ms, result.max_ms, result.throughput, result.unit);
+    result
+}
+
+// Slow camera pans with some high frequency shake on top, sampled like a typical action camera IMU
+pub fn synthetic_imu(duration_ms: f64, rate_hz: f64) -> Vec<TimeIMU> {
+    let count = (duration_ms * rate_hz / 1000.0) as usize;
+    (0..count).map(|i| {
+        let t = i as f64 / rate_hz;
+        TimeIMU {
+            timestamp_ms: t * 1000.0,
+            gyro: Some([
+                20.0 * (t * 0.3).sin() + 8.0 * (t * 37.0).sin(),
+                15.0 * (t * 0.2).cos() + 6.0 * (t * 29.0).cos(),
+                5.0  * (t * 0.5).sin() + 4.0 * (t * 43.0).sin()
+            ]),
+            accl: Some([
+                0.3 * (t * 11.0).sin(),
+                -1.0 + 0.2 * (t * 13.0).cos(),
+                0.1 * (t * 7.0).sin()
+            ]),
+            magn: None
+        }
+    }).collect()
+}
+
+pub fn synthetic_metadata(duration_ms: f64) -> FileMetadata {
+    FileMetadata {
+        imu_orientation: Some("XYZ".into()),
+        raw_imu: Some(synthetic_imu(duration_ms, SYNTHETIC_IMU_RATE)),
+        detected_source: Some("Synthetic".into()),
+        frame_rate: Some(SYNTHETIC_FPS),
+        ..Default::default()
+    }
+}
+
+// Duration of the telemetry, when there's no video to take it from
+fn telemetry_duration_ms(md: &FileMetadata) -> f64 {
+    let imu_end = md.raw_imu.as_ref().and_then(|x| x.last()).map(|x| x.timestamp_ms).unwrap_or_default();
+    let quat_end = md.quaternions.as_ref().and_then(|x| x.keys().next_back()).map(|x| *x as f64 / 1000.0).unwrap_or_default();
+    imu_end.max(quat_end)
+}
+
+pub fn prepare_manager(md: &FileMetadata, duration_ms: f64, fps: f64, size: (usize, usize)) -> StabilizationManager<RGBA8> {
+    let stab = StabilizationManager::<RGBA8>::default();
+    let frame_count = (duration_ms * fps / 1000.0).ceil() as usize;
+    let _ = stab.init_from_video_data("", duration_ms, fps, frame_count, size);
+    {
+        let params = stab.params.read();
+        let mut gyro = stab.gyro.write();
+        gyro.init_from_params(&params);
+        gyro.load_from_telemetry(md);
+    }
+    stab.set_output_size(size.0, size.1);
+    stab.set_size(size.0, size.1);
+    stab.recompute_blocking();
+    stab
+}
+
+pub fn warp_frame(stab: &StabilizationManager<RGBA8>, frame: usize, size: (usize, usize), input: &mut [u8], output: &mut [u8]) -> bool {
+    let timestamp_us = (crate::timestamp_at_frame(frame as i32, stab.params.read().fps) * 1000.0).round() as i64;
+    let mut buffers = BufferDescription {
+        input_size:  (size.0, size.1, size.0 * 4),
+        output_size: (size.0, size.1, size.0 * 4),
+        input_rect: None,
+        output_rect: None,
+        buffers: BufferSource::Cpu { input, output }
+    };
+    stab.process_pixels(timestamp_us, &mut buffers)
+}
+
+pub fn synthetic_frame(size: (usize, usize)) -> Vec<u8> {
+    let mut pixels = vec![0u8; size.0 * size.1 * 4];
+    for (i, px) in pixels.chunks_exact_mut(4).enumerate() {
+        let (x, y) = (i % size.0, i / size.0);
+        px[0] = (x * 255 / size.0) as u8;
+        px[1] = (y * 255 / size.1) as u8;
+        px[2] = if (x / 64 + y / 64) % 2 == 0 { 230 } else { 25 };
+        px[3] = 255;
+    }
+    pixels
+}
+
+pub fn run_benchmark(path: Option<&str>) -> BenchmarkReport {
+    let mut stages = Vec::new();
+    let size = FRAME_SIZE;
+
+    ////////////////////////// Telemetry parsing //////////////////////////
+    let md = match path {
+        Some(path) => {
+            let mut md = None;
+            stages.push(measure("Telemetry parse", 3, 1, "files", || {
+                match GyroSource::parse_telemetry_file(path, size, SYNTHETIC_FPS, |_| (), Arc::new(AtomicBool::new(false))) {
+                    Ok(parsed) => md = Some(parsed),
+                    Err(e) => log::error!("Failed to parse telemetry from {}: {:?}", path, e)
+                }
+            }));
+            md.unwrap_or_else(|| synthetic_metadata(SYNTHETIC_DURATION_MS))
+        },
+        None => synthetic_metadata(SYNTHETIC_DURATION_MS)
+    };
+    let imu_samples = md.raw_imu.as_ref().map(|x| x.len()).unwrap_or_default();
+    let duration_ms = Some(telemetry_duration_ms(&md)).filter(|x| *x > 0.0).unwrap_or(SYNTHETIC_DURATION_MS);
+    let fps = md.frame_rate.filter(|x| *x > 0.0).unwrap_or(SYNTHETIC_FPS);
+
+    let stab = prepare_manager(&md, duration_ms, fps, size);
+    let frame_count = stab.params.read().frame_count;
+
+    ////////////////////////// Integration //////////////////////////
+    if imu_samples > 0 {
+        let org_method = stab.gyro.read().integration_method;
+        for (method, name) in INTEGRATORS {
+            stab.gyro.write().integration_method = method;
+            stages.push(measure(&format!("Integration ({})", name), 3, imu_samples, "samples", || {
+                stab.gyro.write().integrate();
+            }));
+        }
+        let mut gyro = stab.gyro.write();
+        gyro.integration_method = org_method;
+        gyro.integrate();
+    }
+
+    ////////////////////////// Smoothing //////////////////////////
+    let quats = stab.gyro.read().quaternions.len();
+    let org_smoothing = stab.smoothing.read().current_id();
+    for (i, name) in stab.get_smoothing_algs().into_iter().enumerate() {
+        stab.set_smoothing_method(i);
+        stages.push(measure(&format!("Smoothing ({})", name), 3, quats, "samples", || {
+            stab.recompute_smoothness();
+        }));
+    }
+    stab.set_smoothing_method(org_smoothing);
+    stab.recompute_smoothness();
+
+    ////////////////////////// Zooming //////////////////////////
+    stab.set_adaptive_zoom(4.0);
+    stages.push(measure("Adaptive zoom", 3, frame_count, "frames", || {
+        stab.recompute_adaptive_zoom();
+    }));
+    stab.recompute_undistortion();
+
+    ////////////////////////// Per-frame warp //////////////////////////
+    let mut input = synthetic_frame(size);
+    let mut output = vec![0u8; input.len()];
+    let frames = frame_count.clamp(1, 120);
+
+    // Initialize the backend outside of the measured loop
+    warp_frame(&stab, 0, size, &mut input, &mut output);
+    let gpu_backend = stab.stabilization.read().current_backend().to_string();
+    if gpu_backend != "CPU" {
+        let mut frame = 0;
+        stages.push(measure(&format!("Warp ({})", gpu_backend), frames, 1, "frames", || {
+            frame += 1;
+            warp_frame(&stab, frame % frame_count.max(1), size, &mut input, &mut output);
+        }));
+    }
+
+    stab.stabilization.write().set_device(-1);
+    let mut frame = 0;
+    stages.push(measure("Warp (CPU)", frames.min(30), 1, "frames", || {
+        frame += 1;
+        warp_frame(&stab, frame % frame_count.max(1), size, &mut input, &mut output);
+    }));
+
+    BenchmarkReport {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        os: std::env::consts::OS.to_string(),
+        arch: std::env::consts::ARCH.to_string(),
+        threads: rayon::current_num_threads(),
+        input: path.map(|x| x.to_string()),
+        imu_samples,
+        duration_ms,
+        fps,
+        frame_size: size,
+        gpu_backend,
+        stages,
+    }
+}
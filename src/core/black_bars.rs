@@ -0,0 +1,73 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2022 Adrian <adrian.eddy at gmail>
+
+// Detection of letterboxing/pillarboxing baked into the video.
+// Frames are fed as GRAY8 and the content rect is the union of the non-black areas of all frames,
+// so a dark scene in one frame doesn't make the bars bigger than they are.
+
+const MAX_LUMA: u8 = 32;        // Brightest pixel allowed in a black row/column
+const MAX_AVG_LUMA: f64 = 20.0; // Average of a black row/column
+const MIN_BAR_SIZE: f64 = 0.01; // Bars smaller than 1% of the dimension are ignored
+
+#[derive(Default, Clone, Debug)]
+pub struct BlackBarDetector {
+    // Content rect of each frame, normalized (left, top, right, bottom)
+    rects: Vec<(f64, f64, f64, f64)>,
+}
+
+impl BlackBarDetector {
+    pub fn new() -> Self { Self::default() }
+
+    pub fn feed_frame(&mut self, width: u32, height: u32, stride: usize, pixels: &[u8]) {
+        let (w, h) = (width as usize, height as usize);
+        if w < 2 || h < 2 || pixels.len() < stride * (h - 1) + w { return; }
+
+        let is_black = |iter: &mut dyn Iterator<Item = u8>| -> bool {
+            let (mut sum, mut count) = (0u64, 0u64);
+            for px in iter {
+                if px > MAX_LUMA { return false; }
+                sum += px as u64;
+                count += 1;
+            }
+            count > 0 && (sum as f64 / count as f64) <= MAX_AVG_LUMA
+        };
+        let row = |y: usize| is_black(&mut pixels[y * stride..y * stride + w].iter().copied());
+        let col = |x: usize, top: usize, bottom: usize| is_black(&mut (top..bottom).map(|y| pixels[y * stride + x]));
+
+        let top = (0..h).find(|&y| !row(y));
+        let top = match top { Some(t) => t, None => return }; // Entirely black frame, skip it
+        let bottom = (0..h).rev().find(|&y| !row(y)).unwrap_or(h - 1) + 1;
+        let left   = (0..w).find(|&x| !col(x, top, bottom)).unwrap_or(0);
+        let right  = (0..w).rev().find(|&x| !col(x, top, bottom)).unwrap_or(w - 1) + 1;
+
+        self.rects.push((
+            left   as f64 / w as f64,
+            top    as f64 / h as f64,
+            right  as f64 / w as f64,
+            bottom as f64 / h as f64,
+        ));
+    }
+
+    // Returns normalized (x, y, width, height) of the image content, or None if there are no black bars
+    pub fn result(&self) -> Option<(f64, f64, f64, f64)> {
+        let first = self.rects.first()?;
+        let (mut l, mut t, mut r, mut b) = *first;
+        for x in &self.rects[1..] {
+            l = l.min(x.0); t = t.min(x.1);
+            r = r.max(x.2); b = b.max(x.3);
+        }
+        if l < MIN_BAR_SIZE       { l = 0.0; }
+        if t < MIN_BAR_SIZE       { t = 0.0; }
+        if r > 1.0 - MIN_BAR_SIZE { r = 1.0; }
+        if b > 1.0 - MIN_BAR_SIZE { b = 1.0; }
+
+        if l == 0.0 && t == 0.0 && r == 1.0 && b == 1.0 {
+            return None;
+        }
+        if r - l < 0.1 || b - t < 0.1 {
+            log::warn!("Detected content area is too small: {:?}", (l, t, r, b));
+            return None;
+        }
+        Some((l, t, r - l, b - t))
+    }
+}
@@ -0,0 +1,53 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// Kernels for the calibration pattern search, see board_search.rs
+
+__kernel void downscale(__global const uchar *src, __global uchar *dst, int src_w, int src_h, int src_stride, int dst_w, int dst_h, int scale) {
+    int x = get_global_id(0);
+    int y = get_global_id(1);
+    if (x >= dst_w || y >= dst_h) return;
+
+    uint sum = 0;
+    uint count = 0;
+    for (int sy = y * scale; sy < min((y + 1) * scale, src_h); sy++) {
+        for (int sx = x * scale; sx < min((x + 1) * scale, src_w); sx++) {
+            sum += src[sy * src_stride + sx];
+            count++;
+        }
+    }
+    float v = (float)sum / (float)max(count, 1u);
+    dst[y * dst_w + x] = (uchar)clamp(v * CONTRAST + BRIGHTNESS, 0.0f, 255.0f);
+}
+
+__kernel void adaptive_threshold(__global const uchar *src, __global uchar *dst, int w, int h) {
+    int x = get_global_id(0);
+    int y = get_global_id(1);
+    if (x >= w || y >= h) return;
+
+    int x0 = max(x - THRESHOLD_RADIUS, 0), x1 = min(x + THRESHOLD_RADIUS + 1, w);
+    int y0 = max(y - THRESHOLD_RADIUS, 0), y1 = min(y + THRESHOLD_RADIUS + 1, h);
+    uint sum = 0;
+    for (int sy = y0; sy < y1; sy++) {
+        for (int sx = x0; sx < x1; sx++) {
+            sum += src[sy * w + sx];
+        }
+    }
+    float mean = (float)sum / (float)((x1 - x0) * (y1 - y0));
+    dst[y * w + x] = (float)src[y * w + x] > mean - THRESHOLD_OFFSET ? 1 : 0;
+}
+
+__kernel void find_junctions(__global const uchar *bin, __global uint *tiles, __global uchar *junctions, int w, int h, int tiles_x) {
+    int x = get_global_id(0);
+    int y = get_global_id(1);
+    int d = JUNCTION_DIST;
+    if (x < d || y < d || x >= w - d || y >= h - d) return;
+
+    uchar a = bin[(y - d) * w + x - d];
+    uchar b = bin[(y - d) * w + x + d];
+    uchar c = bin[(y + d) * w + x - d];
+    uchar e = bin[(y + d) * w + x + d];
+    if (a == e && b == c && a != b) {
+        atomic_inc(&tiles[(y / TILE_SIZE) * tiles_x + x / TILE_SIZE]);
+        junctions[y * w + x] = 1;
+    }
+}
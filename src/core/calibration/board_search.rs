@@ -0,0 +1,514 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// Detection of the calibration pattern on the GPU with OpenCL or wgpu when available, the CPU fallback uses the same algorithm.
+//
+// The frame is downscaled to at most SEARCH_SIZE pixels and binarized with an adaptive threshold. Then every pixel is tested for
+// an X-junction (diagonal neighbors are equal in pairs, but differ across), which is what the inner chessboard corners look like.
+// Clusters of the junction pixels are the corner candidates, which are assembled into the grid of the board on the CPU,
+// so OpenCV is only used for the sub-pixel refinement. When the grid isn't complete, the OpenCV detector runs on the area
+// with the pattern, which is the bounding box of the tiles with enough junctions.
+
+// Same contrast and brightness as applied before the detection
+pub const CONTRAST: f32 = 2.0;
+pub const BRIGHTNESS: f32 = -50.0;
+
+const SEARCH_SIZE: usize = 1024;
+const THRESHOLD_RADIUS: usize = 6;
+const THRESHOLD_OFFSET: f32 = 5.0;
+const JUNCTION_DIST: usize = 2;
+const TILE_SIZE: usize = 16;
+const MIN_TILE_JUNCTIONS: u32 = 3;
+const MIN_TILES: usize = 4;
+const MIN_JUNCTION_PIXELS: usize = 2;
+const MAX_CANDIDATES_PER_CORNER: usize = 4;
+const GRID_TOLERANCE: f32 = 0.35; // Max distance of the corner from its predicted position, relative to the grid spacing
+
+#[derive(Clone, Copy, Debug)]
+struct SearchSize {
+    scale: usize,
+    width: usize,  // Downscaled size
+    height: usize,
+    tiles_x: usize,
+    tiles_y: usize,
+}
+
+// Junctions found in one frame
+pub struct BoardSearch {
+    size: SearchSize,
+    width: usize,
+    height: usize,
+    tiles: Vec<u32>,     // Junction count per tile
+    junctions: Vec<u8>,  // 1 where the downscaled pixel is a junction
+}
+
+impl BoardSearch {
+    pub fn new(pixels: &[u8], width: usize, height: usize, stride: usize) -> Option<Self> {
+        if width < TILE_SIZE || height < TILE_SIZE || pixels.len() < stride * (height - 1) + width { return None; }
+
+        let scale = ((width.max(height) + SEARCH_SIZE - 1) / SEARCH_SIZE).max(1);
+        let (sw, sh) = ((width + scale - 1) / scale, (height + scale - 1) / scale);
+        let size = SearchSize { scale, width: sw, height: sh, tiles_x: (sw + TILE_SIZE - 1) / TILE_SIZE, tiles_y: (sh + TILE_SIZE - 1) / TILE_SIZE };
+
+        let mut result = None;
+        #[cfg(feature = "use-opencl")]
+        if std::env::var("NO_OPENCL").unwrap_or_default().is_empty() {
+            match opencl::find_junctions(pixels, width, height, stride, size) {
+                Ok(r) => result = Some(r),
+                Err(e) => log::debug!("OpenCL pattern search failed: {:?}", e)
+            }
+        }
+        if result.is_none() {
+            result = wgpu_search::find_junctions(pixels, width, height, stride, size);
+        }
+        let (tiles, junctions) = result.unwrap_or_else(|| cpu_find_junctions(pixels, width, height, stride, size));
+
+        Some(Self { size, width, height, tiles, junctions })
+    }
+
+    // Downscale factor of the search, which is also the accuracy of the corners
+    pub fn scale(&self) -> usize { self.size.scale }
+
+    // Returns (x, y, width, height) of the area with the calibration pattern in the frame coordinates, or None if there's no pattern in the frame
+    pub fn region(&self) -> Option<(usize, usize, usize, usize)> {
+        region_from_tiles(&self.tiles, self.size, self.width, self.height)
+    }
+
+    // Inner corners of the board in the frame coordinates, row by row like from the OpenCV detector.
+    // None if the junctions don't form a complete `columns` x `rows` grid, eg. when the board is partially visible or blurry
+    pub fn corners(&self, columns: usize, rows: usize) -> Option<Vec<(f32, f32)>> {
+        let candidates = junction_centers(&self.junctions, self.size.width, self.size.height);
+        if columns < 2 || rows < 2 || candidates.len() < columns * rows || candidates.len() > columns * rows * MAX_CANDIDATES_PER_CORNER {
+            return None;
+        }
+        let scale = self.size.scale as f32;
+        let grid = assemble_grid(&candidates, columns, rows)?;
+        Some(grid.into_iter().map(|(x, y)| ((x + 0.5) * scale - 0.5, (y + 0.5) * scale - 0.5)).collect())
+    }
+}
+
+fn region_from_tiles(tiles: &[u32], size: SearchSize, width: usize, height: usize) -> Option<(usize, usize, usize, usize)> {
+    let hits: Vec<(usize, usize)> = tiles.iter().enumerate()
+        .filter(|(_, count)| **count >= MIN_TILE_JUNCTIONS)
+        .map(|(i, _)| (i % size.tiles_x, i / size.tiles_x))
+        .collect();
+    if hits.len() < MIN_TILES { return None; }
+
+    let tile_px = TILE_SIZE * size.scale;
+    let x0 = hits.iter().map(|x| x.0).min()? * tile_px;
+    let y0 = hits.iter().map(|x| x.1).min()? * tile_px;
+    let x1 = (hits.iter().map(|x| x.0).max()? + 1) * tile_px;
+    let y1 = (hits.iter().map(|x| x.1).max()? + 1) * tile_px;
+
+    // Junctions are only at the inner corners, the detector needs the outer row of squares too
+    let margin_x = (x1 - x0) / 4 + tile_px;
+    let margin_y = (y1 - y0) / 4 + tile_px;
+    let (x, y) = (x0.saturating_sub(margin_x), y0.saturating_sub(margin_y));
+    let (r, b) = ((x1 + margin_x).min(width), (y1 + margin_y).min(height));
+    if r <= x || b <= y { return None; }
+
+    Some((x, y, r - x, b - y))
+}
+
+// Centers of the clusters of junction pixels
+fn junction_centers(junctions: &[u8], w: usize, h: usize) -> Vec<(f32, f32)> {
+    let mut visited = vec![false; w * h];
+    let mut centers = Vec::new();
+    let mut stack = Vec::new();
+    for start in 0..w * h {
+        if junctions[start] == 0 || visited[start] { continue; }
+        visited[start] = true;
+        stack.push(start);
+        let (mut sum_x, mut sum_y, mut count) = (0.0, 0.0, 0);
+        while let Some(i) = stack.pop() {
+            let (x, y) = (i % w, i / w);
+            sum_x += x as f32;
+            sum_y += y as f32;
+            count += 1;
+            for ny in y.saturating_sub(1)..(y + 2).min(h) {
+                for nx in x.saturating_sub(1)..(x + 2).min(w) {
+                    let j = ny * w + nx;
+                    if junctions[j] != 0 && !visited[j] {
+                        visited[j] = true;
+                        stack.push(j);
+                    }
+                }
+            }
+        }
+        if count >= MIN_JUNCTION_PIXELS {
+            centers.push((sum_x / count as f32, sum_y / count as f32));
+        }
+    }
+    centers
+}
+
+// Grows the grid from the candidate closest to the center of all of them. The position of every next corner is predicted
+// from its neighbors in the grid, so the spacing can change with the perspective and lens distortion
+fn assemble_grid(points: &[(f32, f32)], columns: usize, rows: usize) -> Option<Vec<(f32, f32)>> {
+    let sub = |a: (f32, f32), b: (f32, f32)| (a.0 - b.0, a.1 - b.1);
+    let length = |a: (f32, f32)| (a.0 * a.0 + a.1 * a.1).sqrt();
+
+    let n = points.len() as f32;
+    let center = points.iter().fold((0.0, 0.0), |acc, p| (acc.0 + p.0 / n, acc.1 + p.1 / n));
+    let seed = (0..points.len()).min_by(|a, b| length(sub(points[*a], center)).total_cmp(&length(sub(points[*b], center))))?;
+
+    let mut neighbors: Vec<usize> = (0..points.len()).filter(|i| *i != seed).collect();
+    neighbors.sort_by(|a, b| length(sub(points[*a], points[seed])).total_cmp(&length(sub(points[*b], points[seed]))));
+    let first = *neighbors.first()?;
+    let axis1 = sub(points[first], points[seed]);
+    // The second axis goes to the nearest neighbor roughly perpendicular to the first one
+    let second = *neighbors.iter().take(8).find(|i| {
+        let v = sub(points[**i], points[seed]);
+        ((v.0 * axis1.0 + v.1 * axis1.1) / (length(v) * length(axis1))).abs() < 0.5
+    })?;
+    let axis2 = sub(points[second], points[seed]);
+
+    let mut grid = std::collections::HashMap::new();
+    let mut used = vec![false; points.len()];
+    let mut queue = std::collections::VecDeque::new();
+    for (position, i) in [((0, 0), seed), ((1, 0), first), ((0, 1), second)] {
+        grid.insert(position, i);
+        used[i] = true;
+        queue.push_back(position);
+    }
+    while let Some((ci, cj)) = queue.pop_front() {
+        let p = points[grid[&(ci, cj)]];
+        for (di, dj) in [(1, 0), (-1, 0), (0, 1), (0, -1)] {
+            let target = (ci + di, cj + dj);
+            if grid.contains_key(&target) { continue; }
+
+            // Step from the opposite neighbor, or the same step in the neighboring row, or the initial axis
+            let cell = |i: i32, j: i32| grid.get(&(i, j)).map(|x| points[*x]);
+            let axis = if di != 0 { (axis1.0 * di as f32, axis1.1 * di as f32) } else { (axis2.0 * dj as f32, axis2.1 * dj as f32) };
+            let step = cell(ci - di, cj - dj).map(|prev| sub(p, prev))
+                .or_else(|| [(dj, di), (-dj, -di)].iter().find_map(|(oi, oj)| Some(sub(cell(ci + oi + di, cj + oj + dj)?, cell(ci + oi, cj + oj)?))))
+                .unwrap_or(axis);
+            let predicted = (p.0 + step.0, p.1 + step.1);
+            let max_distance = length(step) * GRID_TOLERANCE;
+
+            let found = (0..points.len())
+                .filter(|i| !used[*i])
+                .map(|i| (i, length(sub(points[i], predicted))))
+                .filter(|(_, d)| *d < max_distance)
+                .min_by(|a, b| a.1.total_cmp(&b.1));
+            if let Some((i, _)) = found {
+                grid.insert(target, i);
+                used[i] = true;
+                queue.push_back(target);
+            }
+        }
+    }
+
+    let min_i = grid.keys().map(|x| x.0).min()?;
+    let min_j = grid.keys().map(|x| x.1).min()?;
+    let size_i = (grid.keys().map(|x| x.0).max()? - min_i + 1) as usize;
+    let size_j = (grid.keys().map(|x| x.1).max()? - min_j + 1) as usize;
+    if grid.len() != columns * rows { return None; }
+    let transposed = if size_i == columns && size_j == rows { false } else if size_i == rows && size_j == columns { true } else { return None; };
+
+    let at = |column: usize, row: usize| {
+        let (i, j) = if transposed { (row, column) } else { (column, row) };
+        points[grid[&(min_i + i as i32, min_j + j as i32)]]
+    };
+    // Same orientation in every frame, the columns go to the right and the rows go down
+    let flip_columns = at(columns - 1, 0).0 < at(0, 0).0;
+    let flip_rows = at(0, rows - 1).1 < at(0, 0).1;
+    let mut ret = Vec::with_capacity(columns * rows);
+    for row in 0..rows {
+        for column in 0..columns {
+            ret.push(at(if flip_columns { columns - 1 - column } else { column }, if flip_rows { rows - 1 - row } else { row }));
+        }
+    }
+    Some(ret)
+}
+
+fn cpu_find_junctions(pixels: &[u8], width: usize, height: usize, stride: usize, size: SearchSize) -> (Vec<u32>, Vec<u8>) {
+    use rayon::prelude::*;
+    let (sw, sh, scale) = (size.width, size.height, size.scale);
+
+    let mut small = vec![0u8; sw * sh];
+    small.par_chunks_mut(sw).enumerate().for_each(|(y, row)| {
+        for (x, px) in row.iter_mut().enumerate() {
+            let (mut sum, mut count) = (0u32, 0u32);
+            for sy in y * scale..((y + 1) * scale).min(height) {
+                for sx in x * scale..((x + 1) * scale).min(width) {
+                    sum += pixels[sy * stride + sx] as u32;
+                    count += 1;
+                }
+            }
+            *px = (sum as f32 / count.max(1) as f32 * CONTRAST + BRIGHTNESS).clamp(0.0, 255.0) as u8;
+        }
+    });
+
+    let mut integral = vec![0u32; (sw + 1) * (sh + 1)];
+    for y in 0..sh {
+        let mut row_sum = 0;
+        for x in 0..sw {
+            row_sum += small[y * sw + x] as u32;
+            integral[(y + 1) * (sw + 1) + x + 1] = integral[y * (sw + 1) + x + 1] + row_sum;
+        }
+    }
+
+    let r = THRESHOLD_RADIUS;
+    let mut bin = vec![0u8; sw * sh];
+    bin.par_chunks_mut(sw).enumerate().for_each(|(y, row)| {
+        let (y0, y1) = (y.saturating_sub(r), (y + r + 1).min(sh));
+        for (x, b) in row.iter_mut().enumerate() {
+            let (x0, x1) = (x.saturating_sub(r), (x + r + 1).min(sw));
+            let sum = integral[y1 * (sw + 1) + x1] + integral[y0 * (sw + 1) + x0] - integral[y0 * (sw + 1) + x1] - integral[y1 * (sw + 1) + x0];
+            let mean = sum as f32 / ((x1 - x0) * (y1 - y0)) as f32;
+            *b = (small[y * sw + x] as f32 > mean - THRESHOLD_OFFSET) as u8;
+        }
+    });
+
+    let d = JUNCTION_DIST;
+    let mut tiles = vec![0u32; size.tiles_x * size.tiles_y];
+    let mut junctions = vec![0u8; sw * sh];
+    for y in d..sh.saturating_sub(d) {
+        for x in d..sw.saturating_sub(d) {
+            let (a, b) = (bin[(y - d) * sw + x - d], bin[(y - d) * sw + x + d]);
+            let (c, e) = (bin[(y + d) * sw + x - d], bin[(y + d) * sw + x + d]);
+            if a == e && b == c && a != b {
+                tiles[(y / TILE_SIZE) * size.tiles_x + x / TILE_SIZE] += 1;
+                junctions[y * sw + x] = 1;
+            }
+        }
+    }
+    (tiles, junctions)
+}
+
+#[cfg(feature = "use-opencl")]
+mod opencl {
+    use ocl::*;
+    use parking_lot::Mutex;
+    use super::*;
+
+    lazy_static::lazy_static! {
+        // Device name and the compiled program
+        static ref PROGRAM: Mutex<Option<(String, Program)>> = Mutex::new(None);
+    }
+
+    pub fn find_junctions(pixels: &[u8], width: usize, height: usize, stride: usize, size: SearchSize) -> ocl::Result<(Vec<u32>, Vec<u8>)> {
+        let (device, context) = crate::gpu::opencl::OclWrapper::current_context().ok_or_else(|| ocl::Error::from("OpenCL device is not available"))?;
+        let queue = Queue::new(&context, device, None)?;
+
+        let program = {
+            let device_name = device.name()?;
+            let mut lock = PROGRAM.lock();
+            match lock.as_ref() {
+                Some((name, program)) if *name == device_name => program.clone(),
+                _ => {
+                    let src = include_str!("board_search.cl")
+                        .replace("CONTRAST", &format!("{:.1}f", CONTRAST))
+                        .replace("BRIGHTNESS", &format!("{:.1}f", BRIGHTNESS))
+                        .replace("THRESHOLD_RADIUS", &format!("{}", THRESHOLD_RADIUS))
+                        .replace("THRESHOLD_OFFSET", &format!("{:.1}f", THRESHOLD_OFFSET))
+                        .replace("JUNCTION_DIST", &format!("{}", JUNCTION_DIST))
+                        .replace("TILE_SIZE", &format!("{}", TILE_SIZE));
+                    let program = Program::builder().src(src).devices(device).build(&context)?;
+                    *lock = Some((device_name, program.clone()));
+                    program
+                }
+            }
+        };
+
+        let len = stride * (height - 1) + width;
+        let src = Buffer::<u8>::builder().queue(queue.clone()).len(len)
+            .flags(MemFlags::new().read_only().host_write_only()).copy_host_slice(&pixels[..len]).build()?;
+        let small = Buffer::<u8>::builder().queue(queue.clone()).len(size.width * size.height).build()?;
+        let bin   = Buffer::<u8>::builder().queue(queue.clone()).len(size.width * size.height).build()?;
+        let tiles = Buffer::<u32>::builder().queue(queue.clone()).len(size.tiles_x * size.tiles_y).fill_val(0u32).build()?;
+        let junctions_buf = Buffer::<u8>::builder().queue(queue.clone()).len(size.width * size.height).fill_val(0u8).build()?;
+
+        let (sw, sh) = (size.width as i32, size.height as i32);
+        let downscale = Kernel::builder().program(&program).name("downscale").queue(queue.clone())
+            .global_work_size((size.width, size.height))
+            .arg(&src).arg(&small).arg(width as i32).arg(height as i32).arg(stride as i32).arg(sw).arg(sh).arg(size.scale as i32)
+            .build()?;
+        let threshold = Kernel::builder().program(&program).name("adaptive_threshold").queue(queue.clone())
+            .global_work_size((size.width, size.height))
+            .arg(&small).arg(&bin).arg(sw).arg(sh)
+            .build()?;
+        let junctions = Kernel::builder().program(&program).name("find_junctions").queue(queue.clone())
+            .global_work_size((size.width, size.height))
+            .arg(&bin).arg(&tiles).arg(&junctions_buf).arg(sw).arg(sh).arg(size.tiles_x as i32)
+            .build()?;
+
+        unsafe {
+            downscale.enq()?;
+            threshold.enq()?;
+            junctions.enq()?;
+        }
+
+        let mut ret = vec![0u32; size.tiles_x * size.tiles_y];
+        tiles.read(&mut ret).enq()?;
+        let mut junctions = vec![0u8; size.width * size.height];
+        junctions_buf.read(&mut junctions).enq()?;
+        Ok((ret, junctions))
+    }
+}
+
+mod wgpu_search {
+    use std::borrow::Cow;
+    use wgpu::BufferUsages;
+    use wgpu::util::DeviceExt;
+    use parking_lot::Mutex;
+    use crate::gpu::wgpu::{ ADAPTER, WgpuWrapper };
+    use crate::gpu::context_manager::{ self, GpuTask };
+    use super::*;
+
+    const WORKGROUP_SIZE: usize = 8;
+
+    #[repr(C)]
+    #[derive(Default, Clone, Copy)]
+    struct SearchParams {
+        src_w: i32,
+        src_h: i32,
+        src_stride: i32,
+        dst_w: i32,
+        dst_h: i32,
+        scale: i32,
+        tiles_x: i32,
+        _padding: i32,
+    }
+    unsafe impl bytemuck::Zeroable for SearchParams {}
+    unsafe impl bytemuck::Pod for SearchParams {}
+
+    struct SearchContext {
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        downscale: wgpu::ComputePipeline,
+        threshold: wgpu::ComputePipeline,
+        junctions: wgpu::ComputePipeline,
+    }
+
+    lazy_static::lazy_static! {
+        // Outer None - not initialized yet, inner None - wgpu is not available, so it's not retried on every frame
+        static ref CONTEXT: Mutex<Option<Option<SearchContext>>> = Mutex::new(None);
+    }
+
+    impl SearchContext {
+        fn new() -> Option<Self> {
+            // `with_device` switches the global adapter, so it has to be read while the device is active
+            let selected = context_manager::MANAGER.read().selected(GpuTask::Preview);
+            let (device, queue) = context_manager::with_device(selected, || {
+                if ADAPTER.read().is_none() { WgpuWrapper::initialize_context(); }
+                let lock = ADAPTER.read();
+                let adapter = lock.as_ref()?;
+                let adapter_limits = adapter.limits();
+
+                pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor {
+                    label: None,
+                    features: wgpu::Features::empty(),
+                    limits: wgpu::Limits {
+                        max_buffer_size: adapter_limits.max_buffer_size,
+                        max_storage_buffer_binding_size: adapter_limits.max_storage_buffer_binding_size,
+                        ..wgpu::Limits::default()
+                    },
+                }, None)).ok()
+            }).flatten()?;
+
+            let mut shader_str = include_str!("board_search.wgsl").to_string();
+            shader_str.insert_str(0, &format!(
+                "let CONTRAST: f32 = {:.1};\nlet BRIGHTNESS: f32 = {:.1};\nlet THRESHOLD_RADIUS: i32 = {};\nlet THRESHOLD_OFFSET: f32 = {:.1};\nlet JUNCTION_DIST: i32 = {};\nlet TILE_SIZE: i32 = {};\n",
+                CONTRAST, BRIGHTNESS, THRESHOLD_RADIUS, THRESHOLD_OFFSET, JUNCTION_DIST, TILE_SIZE
+            ));
+
+            let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                source: wgpu::ShaderSource::Wgsl(Cow::Owned(shader_str)),
+                label: None
+            });
+            let pipeline = |entry_point: &str| device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: None,
+                layout: None,
+                module: &shader,
+                entry_point,
+            });
+            let (downscale, threshold, junctions) = (pipeline("downscale"), pipeline("adaptive_threshold"), pipeline("find_junctions"));
+
+            Some(Self { device, queue, downscale, threshold, junctions })
+        }
+
+        fn run(&self, pixels: &[u8], width: usize, height: usize, stride: usize, size: SearchSize) -> Option<(Vec<u32>, Vec<u8>)> {
+            let len = stride * (height - 1) + width;
+            let mut src = pixels[..len].to_vec();
+            src.resize((len + 3) / 4 * 4, 0); // Read as u32 in the shader
+
+            let params = SearchParams {
+                src_w: width as i32,
+                src_h: height as i32,
+                src_stride: stride as i32,
+                dst_w: size.width as i32,
+                dst_h: size.height as i32,
+                scale: size.scale as i32,
+                tiles_x: size.tiles_x as i32,
+                _padding: 0,
+            };
+            let image_size = (size.width * size.height * std::mem::size_of::<u32>()) as wgpu::BufferAddress;
+            let tiles_size = (size.tiles_x * size.tiles_y * std::mem::size_of::<u32>()) as wgpu::BufferAddress;
+
+            let buf_params = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor { label: None, contents: bytemuck::bytes_of(&params), usage: BufferUsages::UNIFORM });
+            let buf_src    = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor { label: None, contents: &src, usage: BufferUsages::STORAGE });
+            let buf_small  = self.device.create_buffer(&wgpu::BufferDescriptor { size: image_size, usage: BufferUsages::STORAGE, label: None, mapped_at_creation: false });
+            let buf_bin    = self.device.create_buffer(&wgpu::BufferDescriptor { size: image_size, usage: BufferUsages::STORAGE, label: None, mapped_at_creation: false });
+            let buf_tiles  = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor { label: None, contents: bytemuck::cast_slice(&vec![0u32; size.tiles_x * size.tiles_y]), usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC });
+            let buf_junctions = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor { label: None, contents: bytemuck::cast_slice(&vec![0u32; size.width * size.height]), usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC });
+            // Tiles followed by the junctions
+            let staging_buffer = self.device.create_buffer(&wgpu::BufferDescriptor { size: tiles_size + image_size, usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST, label: None, mapped_at_creation: false });
+
+            // Every entry point uses only some of the buffers, so each pipeline gets its own bind group
+            let bind_group = |pipeline: &wgpu::ComputePipeline, buffers: &[(u32, &wgpu::Buffer)]| {
+                let entries: Vec<wgpu::BindGroupEntry> = buffers.iter().map(|(binding, buf)| wgpu::BindGroupEntry { binding: *binding, resource: buf.as_entire_binding() }).collect();
+                self.device.create_bind_group(&wgpu::BindGroupDescriptor { label: None, layout: &pipeline.get_bind_group_layout(0), entries: &entries })
+            };
+            let passes = [
+                (&self.downscale, bind_group(&self.downscale, &[(0, &buf_params), (1, &buf_src),   (2, &buf_small)])),
+                (&self.threshold, bind_group(&self.threshold, &[(0, &buf_params), (2, &buf_small), (3, &buf_bin)])),
+                (&self.junctions, bind_group(&self.junctions, &[(0, &buf_params), (3, &buf_bin),   (4, &buf_tiles), (5, &buf_junctions)])),
+            ];
+
+            let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+            for (pipeline, bind_group) in passes.iter() {
+                let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
+                cpass.set_pipeline(pipeline);
+                cpass.set_bind_group(0, bind_group, &[]);
+                cpass.dispatch_workgroups(((size.width + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE) as u32, ((size.height + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE) as u32, 1);
+            }
+            encoder.copy_buffer_to_buffer(&buf_tiles, 0, &staging_buffer, 0, tiles_size);
+            encoder.copy_buffer_to_buffer(&buf_junctions, 0, &staging_buffer, tiles_size, image_size);
+            self.queue.submit(Some(encoder.finish()));
+
+            let buffer_slice = staging_buffer.slice(..);
+            let (sender, receiver) = futures_intrusive::channel::shared::oneshot_channel();
+            buffer_slice.map_async(wgpu::MapMode::Read, move |v| sender.send(v).unwrap());
+
+            self.device.poll(wgpu::Maintain::Wait);
+
+            if let Some(Ok(())) = pollster::block_on(receiver.receive()) {
+                let data = buffer_slice.get_mapped_range();
+                let (tiles, junctions) = bytemuck::cast_slice::<u8, u32>(&data).split_at(size.tiles_x * size.tiles_y);
+                let result = (tiles.to_vec(), junctions.iter().map(|x| *x as u8).collect());
+                drop(data);
+                staging_buffer.unmap();
+                Some(result)
+            } else {
+                log::debug!("wgpu pattern search failed");
+                None
+            }
+        }
+    }
+
+    // Returns `None` if wgpu is not available, the caller uses the CPU implementation then
+    pub fn find_junctions(pixels: &[u8], width: usize, height: usize, stride: usize, size: SearchSize) -> Option<(Vec<u32>, Vec<u8>)> {
+        if !std::env::var("NO_WGPU").unwrap_or_default().is_empty() { return None; }
+
+        let mut lock = CONTEXT.lock();
+        let context = lock.get_or_insert_with(|| {
+            let context = std::panic::catch_unwind(SearchContext::new).ok().flatten();
+            if context.is_none() {
+                log::debug!("wgpu is not available for the pattern search");
+            }
+            context
+        });
+        context.as_ref()?.run(pixels, width, height, stride, size)
+    }
+}
@@ -0,0 +1,83 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// Calibration pattern search on wgpu, same algorithm as board_search.cl. The constants are prepended in board_search.rs
+
+struct SearchParams {
+    src_w:      i32,
+    src_h:      i32,
+    src_stride: i32,
+    dst_w:      i32, // Downscaled size
+    dst_h:      i32,
+    scale:      i32,
+    tiles_x:    i32,
+    _padding:   i32,
+}
+
+@group(0) @binding(0) var<uniform> params: SearchParams;
+@group(0) @binding(1) var<storage, read> src: array<u32>; // 4 pixels per element
+@group(0) @binding(2) var<storage, read_write> small: array<u32>;
+@group(0) @binding(3) var<storage, read_write> bin: array<u32>;
+@group(0) @binding(4) var<storage, read_write> tiles: array<atomic<u32>>;
+@group(0) @binding(5) var<storage, read_write> junctions: array<u32>;
+
+fn src_pixel(i: i32) -> u32 {
+    return (src[u32(i) / 4u] >> ((u32(i) % 4u) * 8u)) & 255u;
+}
+
+@compute @workgroup_size(8, 8)
+fn downscale(@builtin(global_invocation_id) id: vec3<u32>) {
+    let x = i32(id.x);
+    let y = i32(id.y);
+    if (x >= params.dst_w || y >= params.dst_h) { return; }
+
+    var sum: u32 = 0u;
+    var count: u32 = 0u;
+    for (var sy: i32 = y * params.scale; sy < min((y + 1) * params.scale, params.src_h); sy = sy + 1) {
+        for (var sx: i32 = x * params.scale; sx < min((x + 1) * params.scale, params.src_w); sx = sx + 1) {
+            sum = sum + src_pixel(sy * params.src_stride + sx);
+            count = count + 1u;
+        }
+    }
+    let v = f32(sum) / f32(max(count, 1u));
+    small[y * params.dst_w + x] = u32(clamp(v * CONTRAST + BRIGHTNESS, 0.0, 255.0));
+}
+
+@compute @workgroup_size(8, 8)
+fn adaptive_threshold(@builtin(global_invocation_id) id: vec3<u32>) {
+    let x = i32(id.x);
+    let y = i32(id.y);
+    let w = params.dst_w;
+    let h = params.dst_h;
+    if (x >= w || y >= h) { return; }
+
+    let x0 = max(x - THRESHOLD_RADIUS, 0);
+    let x1 = min(x + THRESHOLD_RADIUS + 1, w);
+    let y0 = max(y - THRESHOLD_RADIUS, 0);
+    let y1 = min(y + THRESHOLD_RADIUS + 1, h);
+    var sum: u32 = 0u;
+    for (var sy: i32 = y0; sy < y1; sy = sy + 1) {
+        for (var sx: i32 = x0; sx < x1; sx = sx + 1) {
+            sum = sum + small[sy * w + sx];
+        }
+    }
+    let mean = f32(sum) / f32((x1 - x0) * (y1 - y0));
+    bin[y * w + x] = select(0u, 1u, f32(small[y * w + x]) > mean - THRESHOLD_OFFSET);
+}
+
+@compute @workgroup_size(8, 8)
+fn find_junctions(@builtin(global_invocation_id) id: vec3<u32>) {
+    let x = i32(id.x);
+    let y = i32(id.y);
+    let w = params.dst_w;
+    let d = JUNCTION_DIST;
+    if (x < d || y < d || x >= w - d || y >= params.dst_h - d) { return; }
+
+    let a = bin[(y - d) * w + x - d];
+    let b = bin[(y - d) * w + x + d];
+    let c = bin[(y + d) * w + x - d];
+    let e = bin[(y + d) * w + x + d];
+    if (a == e && b == c && a != b) {
+        atomicAdd(&tiles[(y / TILE_SIZE) * params.tiles_x + x / TILE_SIZE], 1u);
+        junctions[y * w + x] = 1u;
+    }
+}
@@ -22,6 +22,11 @@ use rayon::iter::{ ParallelIterator, IntoParallelIterator };
 use crate::stabilization::distortion_models::GoProSuperview;
 
 pub mod drawing;
+pub mod board_search;
+pub mod rig;
+
+// The detector is much slower on big images, it runs on at most this size and the corners are refined at full resolution
+const DETECTION_SIZE: f64 = 1920.0;
 
 #[derive(Clone, Default, Debug)]
 pub struct Detected {
@@ -58,6 +63,10 @@ pub struct LensCalibrator {
     pub is_superview: bool,
     pub asymmetrical: bool,
 
+    pub input_crop: Option<(f64, f64, f64, f64)>, // Normalized (x, y, width, height) of the image area, excluding black bars
+
+    pub rig_rotation: Option<rig::RigRotation>, // Rotation to the second camera of a rig, see `rig::solve_rig_rotation`
+
     pub all_matches: Arc<RwLock<BTreeMap<i32, Detected>>>, // frame, Detected
     pub image_points: Arc<RwLock<BTreeMap<i32, Detected>>>, // frame, Detected
     pub used_points: BTreeMap<i32, Detected> // frame, Detected
@@ -89,6 +98,14 @@ impl LensCalibrator {
         ret
     }
 
+    // Size of the calibrated image area, ie. without the black bars
+    pub fn calib_size(&self) -> (usize, usize) {
+        match self.input_crop {
+            Some(crop) => ((self.width as f64 * crop.2).round() as usize, (self.height as f64 * crop.3).round() as usize),
+            None => (self.width, self.height)
+        }
+    }
+
     pub fn clear(&mut self) {
         self.all_matches.write().clear();
         self.image_points.write().clear();
@@ -103,7 +120,7 @@ impl LensCalibrator {
         let grid_size = Size::new(self.columns as i32, self.rows as i32);
         let max_sharpness = self.max_sharpness;
 
-        let mut pixels = pixels.to_vec();
+        let pixels = pixels.to_vec();
         let img_points = self.image_points.clone();
         let all_matches = self.all_matches.clone();
         let is_forced = self.forced_frames.contains(&frame);
@@ -125,44 +142,91 @@ impl LensCalibrator {
                     return Ok(());
                 }
 
+                let (width, height) = (width as usize, height as usize);
+
+                // Find the area with the pattern first, so the detector doesn't search the whole frame.
+                // The search can miss patterns which the detector still finds (eg. blurry or low contrast), so use the whole frame then
+                let search = board_search::BoardSearch::new(&pixels, width, height, stride);
+                let (rx, ry, rw, rh) = search.as_ref().and_then(|x| x.region()).unwrap_or((0, 0, width, height));
+
                 // Apply contrast and brightness
-                let contrast = 2.0;
-                let brightness = -50.0;
-                for px in pixels.iter_mut() {
-                    *px = (*px as f64 * contrast + brightness).min(255.0) as u8;
+                let mut roi = vec![0u8; rw * rh];
+                for y in 0..rh {
+                    let src = &pixels[(ry + y) * stride + rx..(ry + y) * stride + rx + rw];
+                    for (dst, px) in roi[y * rw..(y + 1) * rw].iter_mut().zip(src) {
+                        *dst = (*px as f32 * board_search::CONTRAST + board_search::BRIGHTNESS).min(255.0) as u8;
+                    }
                 }
 
-                let inp1 = unsafe { Mat::new_size_with_data(Size::new(width as i32, height as i32), CV_8UC1, pixels.as_ptr() as *mut c_void, stride as usize)? };
-                let mut inp = unsafe { Mat::new_size_with_data(Size::new(width as i32, height as i32), CV_8UC1, pixels.as_ptr() as *mut c_void, stride as usize)? };
+                let roi_size = Size::new(rw as i32, rh as i32);
+                let inp1 = unsafe { Mat::new_size_with_data(roi_size, CV_8UC1, roi.as_ptr() as *mut c_void, rw)? };
+                let mut inp = unsafe { Mat::new_size_with_data(roi_size, CV_8UC1, roi.as_ptr() as *mut c_void, rw)? };
 
                 let _ = opencv::imgproc::equalize_hist(&inp1, &mut inp);
 
-                let mut corners = Mat::default();
-
-                let mut flags = CALIB_CB_MARKER;
-                if no_marker {
-                    flags = 0;
+                let criteria = TermCriteria::new(TermCriteria_Type::EPS as i32 | TermCriteria_Type::COUNT as i32, 30, 0.01)?;
+
+                // Corners assembled from the junctions found by the search only need the sub-pixel refinement
+                let mut detected = None;
+                if let Some(search) = &search {
+                    let in_roi = |(x, y): &(f32, f32)| *x >= rx as f32 && *y >= ry as f32 && *x < (rx + rw) as f32 && *y < (ry + rh) as f32;
+                    if let Some(points) = search.corners(grid_size.width as usize, grid_size.height as usize).filter(|x| x.iter().all(in_roi)) {
+                        let mut corners_full = Vector::<Point2f>::from_iter(points.iter().map(|(x, y)| Point2f::new(x - rx as f32, y - ry as f32)));
+                        let win = (search.scale() as i32 * 2).max(3);
+                        opencv::imgproc::corner_sub_pix(&inp, &mut corners_full, Size::new(win, win), Size::new(-1, -1), criteria)?;
+                        detected = Some(corners_full);
+                    }
                 }
 
-                if opencv::calib3d::find_chessboard_corners_sb(&inp, grid_size, &mut corners, flags)? {
-                    if corners.rows() > 0 {
-                        let sharpness = opencv::calib3d::estimate_chessboard_sharpness(&inp, grid_size, &corners, 0.8, false, &mut Mat::default()).unwrap_or_default();
-                        let avg_sharpness = *sharpness.get(0).unwrap_or(&100.0);
-                        let mut points = Vec::with_capacity(corners.rows() as usize);
-                        for (_pos, mut pt) in corners.iter::<Point2f>()? {
-                            if is_superview {
-                                let pt2 = GoProSuperview::from_superview_calib((pt.x / width as f32, pt.y / height as f32));
-                                pt = Point2f::new(pt2.0 * width as f32, pt2.1 * height as f32);
-                            }
-                            points.push((pt.x * pt_scale, pt.y * pt_scale));
+                if detected.is_none() {
+                    let det_scale = (rw.max(rh) as f64 / DETECTION_SIZE).max(1.0);
+                    let small = if det_scale > 1.0 {
+                        let mut small = Mat::default();
+                        let small_size = Size::new((rw as f64 / det_scale).round() as i32, (rh as f64 / det_scale).round() as i32);
+                        opencv::imgproc::resize(&inp, &mut small, small_size, 0.0, 0.0, opencv::imgproc::INTER_AREA)?;
+                        Some(small)
+                    } else {
+                        None
+                    };
+
+                    let mut corners = Mat::default();
+
+                    let mut flags = CALIB_CB_MARKER;
+                    if no_marker {
+                        flags = 0;
+                    }
+
+                    if opencv::calib3d::find_chessboard_corners_sb(small.as_ref().unwrap_or(&inp), grid_size, &mut corners, flags)? && corners.rows() > 0 {
+                        let mut corners_full = Vector::<Point2f>::with_capacity(corners.rows() as usize);
+                        for (_pos, pt) in corners.iter::<Point2f>()? {
+                            corners_full.push(Point2f::new(((pt.x as f64 + 0.5) * det_scale - 0.5) as f32, ((pt.y as f64 + 0.5) * det_scale - 0.5) as f32));
+                        }
+                        if det_scale > 1.0 {
+                            let win = ((det_scale.ceil() as i32) * 2).max(3);
+                            opencv::imgproc::corner_sub_pix(&inp, &mut corners_full, Size::new(win, win), Size::new(-1, -1), criteria)?;
                         }
-                        log::debug!("avg sharpness: {:.5}, max: {:.5}", avg_sharpness, max_sharpness);
-                        if avg_sharpness < max_sharpness || is_forced {
-                            img_points.write().insert(frame, Detected { points: points.clone(), timestamp_us, frame, avg_sharpness, is_forced });
+                        detected = Some(corners_full);
+                    }
+                }
+
+                if let Some(corners_full) = detected {
+                    let sharpness = opencv::calib3d::estimate_chessboard_sharpness(&inp, grid_size, &corners_full, 0.8, false, &mut Mat::default()).unwrap_or_default();
+                    let avg_sharpness = *sharpness.get(0).unwrap_or(&100.0);
+                    let mut points = Vec::with_capacity(corners_full.len());
+                    for mut pt in corners_full.iter() {
+                        pt = Point2f::new(pt.x + rx as f32, pt.y + ry as f32);
+                        if is_superview {
+                            let pt2 = GoProSuperview::from_superview_calib((pt.x / width as f32, pt.y / height as f32));
+                            pt = Point2f::new(pt2.0 * width as f32, pt2.1 * height as f32);
                         }
-                        all_matches.write().insert(frame, Detected { points, timestamp_us, avg_sharpness, frame, is_forced });
-                        return Ok(());
+                        points.push((pt.x * pt_scale, pt.y * pt_scale));
                     }
+                    log::debug!("avg sharpness: {:.5}, max: {:.5}", avg_sharpness, max_sharpness);
+                    if avg_sharpness < max_sharpness || is_forced {
+                        img_points.write().insert(frame, Detected { points: points.clone(), timestamp_us, frame, avg_sharpness, is_forced });
+                    }
+                    all_matches.write().insert(frame, Detected { points, timestamp_us, avg_sharpness, frame, is_forced });
+                    return Ok(());
                 }
                 Err(opencv::Error::new(0, "Chessboard not found".to_string()))
             })();
@@ -182,11 +246,14 @@ impl LensCalibrator {
         let find_min = |a: (f64, Matrix3::<f64>, Vector4::<f64>, Vec<i32>), b: (f64, Matrix3::<f64>, Vector4::<f64>, Vec<i32>)| -> (f64, Matrix3::<f64>, Vector4::<f64>, Vec<i32>) { if a.0 < b.0 { a } else { b } };
 
         let image_points = self.image_points.read().clone();
-        let mut width = self.width as i32;
+        let (calib_width, calib_height) = self.calib_size();
+        let mut width = calib_width as i32;
         if self.is_superview {
             width = (width as f32 / GoProSuperview::ASPECT_SCALE).round() as i32;
         }
-        let size = Size::new(width, self.height as i32);
+        let size = Size::new(width, calib_height as i32);
+        // Detected points are in the full frame coordinates, move them to the image area
+        let crop_offset = self.input_crop.map(|c| ((self.width as f64 * c.0) as f32, (self.height as f64 * c.1) as f32)).unwrap_or_default();
         let objp = self.objp.clone();
         let max_images = self.max_images;
         let forced_frames = self.forced_frames.clone();
@@ -232,7 +299,7 @@ impl LensCalibrator {
 
             let imgpoints = Vector::<Vector<Point2f>>::from_iter(
                 final_frames.iter().filter_map(|k| Some(Vector::from_iter(
-                    image_points.get(k)?.points.iter().map(|(x, y)| Point2f::new(*x as f32, *y as f32))
+                    image_points.get(k)?.points.iter().map(|(x, y)| Point2f::new(*x as f32 - crop_offset.0, *y as f32 - crop_offset.1))
                 ))
             ));
             let objpoints = Vector::<Vector<Point3d>>::from_iter(
@@ -0,0 +1,125 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2022 Adrian <adrian.eddy at gmail>
+
+// Extrinsic calibration of a rig of two rigidly mounted cameras.
+// Both cameras film the same board at the same time, so for every pair of synchronized detections
+// the board pose is solved in each camera and the rotation from camera A to camera B is R_b * R_a^T.
+// Pairs are averaged after rejecting outliers, eg. when the board without the marker was detected rotated by 180°.
+
+#[cfg(feature = "use-opencv")]
+use opencv::{
+    core::{ Mat, Point2f, Point3d, Vector },
+    prelude::MatTraitConst,
+};
+#[cfg(feature = "use-opencv")]
+use nalgebra::{ Matrix3, Vector3, Vector4, Rotation3 };
+#[cfg(feature = "use-opencv")]
+use super::{ Detected, LensCalibrator };
+use nalgebra::{ UnitQuaternion, Quaternion };
+use serde::{ Serialize, Deserialize };
+
+#[cfg(feature = "use-opencv")]
+const MAX_INLIER_ANGLE: f64 = 2.0; // degrees
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RigRotation {
+    pub quaternion: [f64; 4],  // w, x, y, z, rotation from the reference camera to the other one
+    pub euler_deg: [f64; 3],   // pitch, roll, yaw, in the same convention as the IMU rotation
+    pub error_deg: f64,        // Mean angular deviation of the used pairs from the result
+    pub pairs: usize,
+    pub time_offset_ms: f64,
+}
+
+impl RigRotation {
+    pub fn rotation(&self) -> UnitQuaternion<f64> {
+        let [w, x, y, z] = self.quaternion;
+        UnitQuaternion::from_quaternion(Quaternion::new(w, x, y, z))
+    }
+
+    // Accepts the exported rig calibration or a lens profile which contains it
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        let mut v: serde_json::Value = serde_json::from_str(json)?;
+        if let Some(rig) = v.get_mut("rig_rotation") {
+            v = rig.take();
+        }
+        serde_json::from_value(v)
+    }
+}
+
+// Rotation of the board relative to the camera, from detected points in pixel coordinates
+#[cfg(feature = "use-opencv")]
+fn board_rotation(objp: &[(f64, f64)], detected: &Detected, k: &Matrix3<f64>, d: &Vector4<f64>) -> Result<Rotation3<f64>, opencv::Error> {
+    let k_cv = Mat::from_slice_2d(&[
+        [k[(0, 0)], k[(0, 1)], k[(0, 2)]],
+        [k[(1, 0)], k[(1, 1)], k[(1, 2)]],
+        [k[(2, 0)], k[(2, 1)], k[(2, 2)]],
+    ])?;
+    let d_cv = Mat::from_slice(d.as_slice())?;
+
+    let distorted = Vector::<Point2f>::from_iter(detected.points.iter().map(|(x, y)| Point2f::new(*x, *y)));
+    let mut undistorted = Mat::default();
+    // Without the new camera matrix the points are in normalized coordinates, so the pose is solved with identity K and no distortion
+    opencv::calib3d::fisheye_undistort_points(&distorted, &mut undistorted, &k_cv, &d_cv, &Mat::default(), &Mat::default())?;
+
+    let objpoints = Vector::<Point3d>::from_iter(objp.iter().map(|(x, y)| Point3d::new(*x, *y, 0.0)));
+    let identity = Mat::from_slice_2d(&[[1.0f64, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]])?;
+    let mut rvec = Mat::default();
+    let mut tvec = Mat::default();
+    if !opencv::calib3d::solve_pnp(&objpoints, &undistorted, &identity, &Mat::default(), &mut rvec, &mut tvec, false, opencv::calib3d::SOLVEPNP_ITERATIVE)? {
+        return Err(opencv::Error::new(0, "Unable to solve the board pose".to_string()));
+    }
+    let rv = Vector3::new(*rvec.at::<f64>(0)?, *rvec.at::<f64>(1)?, *rvec.at::<f64>(2)?);
+    Ok(Rotation3::new(rv))
+}
+
+// `a` and `b` must be calibrated (`k` and `d` set) and have their detections in `all_matches`.
+// `time_offset_us` is added to timestamps of camera A to get the matching time in camera B.
+#[cfg(feature = "use-opencv")]
+pub fn solve_rig_rotation(a: &LensCalibrator, b: &LensCalibrator, time_offset_us: i64, max_time_diff_us: i64) -> Result<RigRotation, opencv::Error> {
+    if a.columns != b.columns || a.rows != b.rows {
+        return Err(opencv::Error::new(0, "Both cameras must use the same calibration pattern".to_string()));
+    }
+    let a_matches = a.all_matches.read();
+    let b_matches: Vec<Detected> = b.all_matches.read().values().cloned().collect();
+
+    let mut rotations = Vec::new();
+    for da in a_matches.values() {
+        let ts = da.timestamp_us + time_offset_us;
+        let db = b_matches.iter().min_by_key(|x| (x.timestamp_us - ts).abs());
+        if let Some(db) = db.filter(|x| (x.timestamp_us - ts).abs() <= max_time_diff_us) {
+            match (board_rotation(&a.objp, da, &a.k, &a.d), board_rotation(&b.objp, db, &b.k, &b.d)) {
+                (Ok(ra), Ok(rb)) => rotations.push(UnitQuaternion::from_rotation_matrix(&(rb * ra.inverse()))),
+                (Err(e), _) | (_, Err(e)) => log::warn!("Unable to solve the board pose at {} us: {:?}", da.timestamp_us, e)
+            }
+        }
+    }
+    if rotations.is_empty() {
+        return Err(opencv::Error::new(0, "No synchronized board detections found in both videos".to_string()));
+    }
+
+    // Use the pair with the most consistent neighbours as the reference, and average its inliers
+    let max_angle = MAX_INLIER_ANGLE.to_radians();
+    let reference = rotations.iter().max_by_key(|q| rotations.iter().filter(|x| x.angle_to(q) < max_angle).count()).copied().unwrap();
+    let inliers: Vec<UnitQuaternion<f64>> = rotations.iter().filter(|x| x.angle_to(&reference) < max_angle).copied().collect();
+
+    let mut sum = Vector4::<f64>::zeros();
+    for q in &inliers {
+        // q and -q are the same rotation, keep all of them in the same hemisphere
+        let v = q.as_ref().coords;
+        sum += if v.dot(&reference.as_ref().coords) < 0.0 { -v } else { v };
+    }
+    let mean = UnitQuaternion::from_quaternion(Quaternion::from(sum));
+    let error_deg = inliers.iter().map(|x| x.angle_to(&mean).to_degrees()).sum::<f64>() / inliers.len() as f64;
+
+    log::debug!("Rig rotation: {:?}, pairs: {}/{}, error: {:.3} deg", mean.euler_angles(), inliers.len(), rotations.len(), error_deg);
+
+    // Same axis order as `Rotation3::from_euler_angles(yaw, pitch, roll)` used for the IMU rotation
+    let (x, y, z) = mean.euler_angles();
+    Ok(RigRotation {
+        quaternion: [mean.w, mean.i, mean.j, mean.k],
+        euler_deg: [y.to_degrees(), z.to_degrees(), x.to_degrees()],
+        error_deg,
+        pairs: inliers.len(),
+        time_offset_ms: time_offset_us as f64 / 1000.0,
+    })
+}
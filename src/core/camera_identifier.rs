@@ -18,10 +18,29 @@ pub struct CameraIdentifier {
     pub video_width: usize,
     pub video_height: usize,
     pub additional: String,
+    pub gimbal_mode: String, // DJI, not part of the identifier, only used to pick the best matching profile
 
     pub identifier: String
 }
 
+// Finds the first value of any of the `keys` in nested json objects
+fn find_json_value<'a>(v: &'a serde_json::Value, keys: &[&str]) -> Option<&'a serde_json::Value> {
+    match v {
+        serde_json::Value::Object(obj) => {
+            keys.iter().find_map(|k| obj.get(*k)).or_else(|| obj.values().find_map(|x| find_json_value(x, keys)))
+        },
+        serde_json::Value::Array(arr) => arr.iter().find_map(|x| find_json_value(x, keys)),
+        _ => None
+    }
+}
+fn json_value_to_string(v: &serde_json::Value) -> String {
+    match v {
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Null => String::new(),
+        v => v.to_string()
+    }
+}
+
 impl CameraIdentifier {
     pub fn from_telemetry_parser(input: &Input, video_width: usize, video_height: usize, fps: f64) -> Result<Self> {
         let fps = (fps * 1000.0).round() as usize;
@@ -114,6 +133,28 @@ impl CameraIdentifier {
                     }
                 }
             },
+            "DJI" => {
+                // Multi-lens drones and FPV cameras report the active lens, FOV setting and gimbal mode per clip
+                if let Some(ref samples) = input.samples {
+                    for info in samples {
+                        if let Some(ref tag_map) = info.tag_map {
+                            if let Some(v) = tag_map.get(&GroupId::Default).and_then(|map| map.get_t(TagId::Metadata) as Option<&serde_json::Value>) {
+                                log::debug!("DJI metadata: {:?}", v);
+                                let lens = find_json_value(v, &["lens_type", "camera_type", "lens"]).map(json_value_to_string).unwrap_or_default();
+                                let fov  = find_json_value(v, &["fov_type", "fov_mode", "fov"]).map(json_value_to_string).unwrap_or_default();
+                                id.lens_info = [lens, fov.replace("FovType", "")].iter().filter(|x| !x.is_empty()).cloned().collect::<Vec<_>>().join(" ");
+                                if let Some(mode) = find_json_value(v, &["gimbal_mode", "gimbal_control_mode"]) {
+                                    id.gimbal_mode = json_value_to_string(mode);
+                                }
+                                if let Some(lens_info) = v.get("lens_info").and_then(|v| v.as_str()) {
+                                    if id.lens_info.is_empty() { id.lens_info = lens_info.to_string(); }
+                                }
+                                break;
+                            }
+                        }
+                    }
+                }
+            },
             "Insta360" => {
                 if let Some(ref samples) = input.samples {
                     for info in samples {
@@ -0,0 +1,73 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2022 Adrian <adrian.eddy at gmail>
+
+// Multi-resolution min/max pyramid of a time series, for drawing charts of long, high rate logs (eg. 8 kHz blackbox).
+// Level 0 contains the samples, every next level merges LEVEL_FACTOR buckets of the previous one.
+// A query picks the finest level which fits in the requested number of points, so the cost depends on the chart width, not on the data size.
+
+const LEVEL_FACTOR: usize = 4;
+
+#[derive(Default, Clone, Copy, Debug)]
+struct Bucket {
+    ts: i64,         // Timestamp of the first sample in the bucket
+    min: (i64, f64), // (timestamp, value)
+    max: (i64, f64),
+}
+
+#[derive(Default, Clone, Debug)]
+pub struct MinMaxPyramid {
+    levels: Vec<Vec<Bucket>>,
+}
+
+impl MinMaxPyramid {
+    pub fn new(samples: impl IntoIterator<Item = (i64, f64)>) -> Self {
+        let mut base: Vec<Bucket> = samples.into_iter().map(|(ts, v)| Bucket { ts, min: (ts, v), max: (ts, v) }).collect();
+        base.sort_by_key(|b| b.ts);
+
+        let mut levels = vec![base];
+        while levels.last().unwrap().len() > LEVEL_FACTOR {
+            let next = levels.last().unwrap().chunks(LEVEL_FACTOR).map(|chunk| {
+                let mut b = chunk[0];
+                for x in &chunk[1..] {
+                    if x.min.1 < b.min.1 { b.min = x.min; }
+                    if x.max.1 > b.max.1 { b.max = x.max; }
+                }
+                b
+            }).collect();
+            levels.push(next);
+        }
+        Self { levels }
+    }
+
+    pub fn len(&self) -> usize { self.levels.first().map(|x| x.len()).unwrap_or_default() }
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+
+    // Returns (timestamp, value) points between `from_ts` and `to_ts` (plus one bucket on each side, so lines continue outside of the range).
+    // Each bucket contributes its min and max in time order, so peaks are kept at any zoom level.
+    // The result has at most about `max_points` points, unless the range contains fewer samples
+    pub fn query(&self, from_ts: i64, to_ts: i64, max_points: usize) -> Vec<(i64, f64)> {
+        let max_buckets = (max_points / 2).max(1);
+        let mut ret = Vec::new();
+        for (i, level) in self.levels.iter().enumerate() {
+            let start = level.partition_point(|b| b.ts < from_ts).saturating_sub(1);
+            let end = (level.partition_point(|b| b.ts <= to_ts) + 1).min(level.len());
+            if start >= end { break; }
+            if end - start > max_buckets && i < self.levels.len() - 1 { continue; }
+
+            ret.reserve((end - start) * 2);
+            for b in &level[start..end] {
+                if b.min.0 == b.max.0 {
+                    ret.push(b.min);
+                } else if b.min.0 < b.max.0 {
+                    ret.push(b.min);
+                    ret.push(b.max);
+                } else {
+                    ret.push(b.max);
+                    ret.push(b.min);
+                }
+            }
+            break;
+        }
+        ret
+    }
+}
@@ -0,0 +1,29 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2022 Adrian <adrian.eddy at gmail>
+
+// Deterministic compute mode, for hash based QA of the results.
+// Rayon splits the work depending on the thread scheduling, so a parallel reduction combines the values in a different grouping between runs.
+// The reductions therefore have to be order-stable, see `min_cost`. When enabled, the smoothing is also recomputed every time instead of
+// reusing the cached result, and the zooming doesn't use the GPU, which can round differently between devices and drivers.
+
+use std::cmp::Ordering;
+use std::sync::atomic::{ AtomicBool, Ordering::SeqCst };
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn set_enabled(v: bool) {
+    ENABLED.store(v, SeqCst);
+}
+pub fn is_enabled() -> bool {
+    ENABLED.load(SeqCst)
+}
+
+/// Smaller of two (value, cost) pairs, for parallel searches of the lowest cost.
+/// Ties are broken by the value and NaN costs are the highest, so it's a total order and the result doesn't depend on how the reduction is split
+pub fn min_cost(a: (f64, f64), b: (f64, f64)) -> (f64, f64) {
+    let cost = |x: f64| if x.is_nan() { f64::INFINITY } else { x };
+    match cost(a.1).total_cmp(&cost(b.1)).then(a.0.total_cmp(&b.0)) {
+        Ordering::Greater => b,
+        _ => a
+    }
+}
@@ -0,0 +1,136 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2022 Adrian <adrian.eddy at gmail>
+
+// Detection of duplicated frames, which some cameras insert under heat throttling.
+// Every decoded frame gets a cheap hash (mean values of a coarse grid of blocks) and a frame whose hash matches the previous one
+// is a duplicate. Its content was captured at the time of the original frame, so it's excluded from the optical flow sync
+// and can be dropped or motion compensated with the gyro data at export.
+// Equal frames are also what a static camera records of a static scene, so the gyro has to show that the camera moved in the meantime.
+
+use std::collections::BTreeMap;
+use crate::gyro_source::GyroSource;
+
+const GRID_WIDTH: usize = 32;
+const GRID_HEIGHT: usize = 18;
+// Max difference of a block mean (in 8-bit levels) to still consider the frames equal, it covers the noise of re-encoded frames
+const MAX_BLOCK_DIFF: u8 = 1;
+// Min camera rotation since the original frame (in degrees), below it the frames can be equal just because nothing moved
+const MIN_ROTATION_DEG: f64 = 0.1;
+// Max time since the original frame, longer runs of equal frames are a frozen video and not the duplicates inserted by the camera
+const MAX_DELAY_US: i64 = 200_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicateFrameMode {
+    #[default]
+    Keep,
+    Drop,        // Previous frame stays on the screen for the duration of the duplicate
+    Interpolate, // Duplicate is rendered with the camera motion since the original frame, see `FrameTransform::at_timestamp`
+}
+impl From<i32> for DuplicateFrameMode {
+    fn from(v: i32) -> Self {
+        match v {
+            1 => Self::Drop,
+            2 => Self::Interpolate,
+            _ => Self::Keep
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameHash(Vec<u8>);
+
+impl FrameHash {
+    /// Hash of a single plane. Only one byte of every `step` bytes is read, starting at `offset`,
+    /// eg. step 2 and offset 1 to use the most significant bytes of 16-bit little endian samples
+    pub fn from_plane(data: &[u8], row_bytes: usize, height: usize, stride: usize, step: usize, offset: usize) -> Self {
+        let step = step.max(1);
+        let samples = row_bytes / step;
+        if samples < GRID_WIDTH || height < GRID_HEIGHT || data.len() < stride * height {
+            return Self(Vec::new());
+        }
+        let mut ret = Vec::with_capacity(GRID_WIDTH * GRID_HEIGHT);
+        for by in 0..GRID_HEIGHT {
+            let (y0, y1) = (by * height / GRID_HEIGHT, (by + 1) * height / GRID_HEIGHT);
+            for bx in 0..GRID_WIDTH {
+                let (x0, x1) = (bx * samples / GRID_WIDTH, (bx + 1) * samples / GRID_WIDTH);
+                // Every 4th row and column of the block is enough
+                let mut sum = 0u64;
+                let mut count = 0u64;
+                for y in (y0..y1).step_by(4) {
+                    let row = &data[y * stride..];
+                    for x in (x0..x1).step_by(4) {
+                        sum += row[x * step + offset] as u64;
+                        count += 1;
+                    }
+                }
+                ret.push((sum / count.max(1)) as u8);
+            }
+        }
+        Self(ret)
+    }
+
+    pub fn is_valid(&self) -> bool { !self.0.is_empty() }
+
+    pub fn matches(&self, other: &FrameHash) -> bool {
+        self.is_valid() && self.0.len() == other.0.len() &&
+            self.0.iter().zip(other.0.iter()).all(|(a, b)| a.abs_diff(*b) <= MAX_BLOCK_DIFF)
+    }
+}
+
+/// Compares every frame with the previous one. Frames have to be fed in order, `frame_no` is used to detect gaps
+#[derive(Default)]
+pub struct DuplicateDetector {
+    last: Option<(usize, FrameHash)>,
+    original_ts: i64,
+}
+impl DuplicateDetector {
+    /// Returns the timestamp of the original frame if the frame is a duplicate.
+    /// `rotation_deg` returns the camera rotation between two video timestamps, see `rotation_between`
+    pub fn feed(&mut self, frame_no: usize, timestamp_us: i64, hash: FrameHash, rotation_deg: impl FnOnce(i64, i64) -> Option<f64>) -> Option<i64> {
+        let is_duplicate = match &self.last {
+            Some((last_no, last_hash)) => *last_no + 1 == frame_no && hash.matches(last_hash) &&
+                timestamp_us - self.original_ts <= MAX_DELAY_US &&
+                rotation_deg(self.original_ts, timestamp_us).map_or(false, |x| x >= MIN_ROTATION_DEG),
+            None => false
+        };
+        if !is_duplicate {
+            self.original_ts = timestamp_us;
+        }
+        // Compare with the latest frame, so the noise of a longer run of duplicates doesn't accumulate
+        self.last = Some((frame_no, hash));
+        if is_duplicate { Some(self.original_ts) } else { None }
+    }
+}
+
+/// Camera rotation between two video timestamps (in us) in degrees, None if there's no motion data
+pub fn rotation_between(gyro: &GyroSource, from_us: i64, to_us: i64) -> Option<f64> {
+    if gyro.quaternions.is_empty() { return None; }
+    let quat = |timestamp_us: i64| {
+        let ts = timestamp_us as f64 / 1000.0;
+        gyro.org_quat_at_timestamp(ts + gyro.offset_at_video_timestamp(ts))
+    };
+    Some(quat(from_us).angle_to(&quat(to_us)).to_degrees())
+}
+
+/// Detected duplicates, timestamp of the duplicate -> timestamp of the original frame (both in us)
+#[derive(Default, Clone, Debug)]
+pub struct DuplicateFrames {
+    frames: BTreeMap<i64, i64>,
+}
+impl DuplicateFrames {
+    pub fn insert(&mut self, timestamp_us: i64, original_timestamp_us: i64) {
+        self.frames.insert(timestamp_us, original_timestamp_us);
+    }
+    pub fn original_timestamp(&self, timestamp_us: i64) -> Option<i64> {
+        self.frames.get(&timestamp_us).copied()
+    }
+    pub fn contains(&self, timestamp_us: i64) -> bool {
+        self.frames.contains_key(&timestamp_us)
+    }
+    pub fn len(&self) -> usize { self.frames.len() }
+    pub fn is_empty(&self) -> bool { self.frames.is_empty() }
+    pub fn clear(&mut self) { self.frames.clear(); }
+    pub fn timestamps(&self) -> Vec<i64> {
+        self.frames.keys().copied().collect()
+    }
+}
@@ -0,0 +1,105 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2022 Adrian <adrian.eddy at gmail>
+
+// Structured errors passed to the UI. The UI translates the message based on `kind`
+// and can offer a button for the `action`, `context` contains the technical details.
+
+use serde::Serialize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    Generic,
+    InvalidParameters,
+    FileNotFound,
+    PermissionDenied,
+    InvalidProject,
+    InvalidLensProfile,
+    UnsupportedVideo,
+    NoMotionData,
+    Calibration,
+    EncoderNotFound,
+    OutputExists,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", content = "arg", rename_all = "snake_case")]
+pub enum SuggestedAction {
+    None,
+    InstallSdk(String),
+    PickLensProfile,
+    LoadMotionData,
+    ChooseOutputLocation,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct GyroflowError {
+    pub kind: ErrorKind,
+    pub context: String,
+    pub action: SuggestedAction,
+}
+
+impl GyroflowError {
+    pub fn new<S: Into<String>>(kind: ErrorKind, context: S) -> Self {
+        Self { kind, context: context.into(), action: SuggestedAction::None }
+    }
+    pub fn generic<S: Into<String>>(context: S) -> Self {
+        Self::new(ErrorKind::Generic, context)
+    }
+    pub fn with_action(mut self, action: SuggestedAction) -> Self {
+        self.action = action;
+        self
+    }
+    pub fn append_context(mut self, text: &str) -> Self {
+        if !text.is_empty() {
+            self.context.push_str("\n\n");
+            self.context.push_str(text);
+        }
+        self
+    }
+
+    // Guess the kind and the action from an error message produced by the lower layers (ffmpeg, telemetry-parser etc.)
+    pub fn from_message<S: Into<String>>(message: S) -> Self {
+        let message = message.into();
+        let lower = message.to_ascii_lowercase();
+        if lower.contains("encoder not found") && lower.contains("libx26") {
+            Self::new(ErrorKind::EncoderNotFound, message).with_action(SuggestedAction::InstallSdk("ffmpeg_gpl".into()))
+        } else if lower.contains("permission denied") {
+            Self::new(ErrorKind::PermissionDenied, message).with_action(SuggestedAction::ChooseOutputLocation)
+        } else if lower.contains("no such file") || (lower.contains("not found") && lower.contains("file")) {
+            Self::new(ErrorKind::FileNotFound, message)
+        } else if lower.contains("lens profile") {
+            Self::new(ErrorKind::InvalidLensProfile, message).with_action(SuggestedAction::PickLensProfile)
+        } else if lower.contains("unsupported file format") || lower.contains("no gyro data") || lower.contains("no motion data") {
+            Self::new(ErrorKind::NoMotionData, message).with_action(SuggestedAction::LoadMotionData)
+        } else {
+            Self::generic(message)
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+impl std::fmt::Display for GyroflowError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}: {}", self.kind, self.context)
+    }
+}
+impl std::error::Error for GyroflowError { }
+
+impl From<std::io::Error> for GyroflowError {
+    fn from(e: std::io::Error) -> Self {
+        match e.kind() {
+            std::io::ErrorKind::NotFound         => Self::new(ErrorKind::FileNotFound, e.to_string()),
+            std::io::ErrorKind::PermissionDenied => Self::new(ErrorKind::PermissionDenied, e.to_string()).with_action(SuggestedAction::ChooseOutputLocation),
+            _ => Self::from_message(e.to_string())
+        }
+    }
+}
+impl From<serde_json::Error> for GyroflowError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::new(ErrorKind::InvalidProject, e.to_string())
+    }
+}
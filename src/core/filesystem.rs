@@ -0,0 +1,135 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2022 Adrian <adrian.eddy at gmail>
+
+// File access that works both with regular filesystem paths and Android `content://` URIs (Storage Access Framework).
+// Content URIs can't be opened by path, so the app registers an opener which returns a detached file descriptor for the URI.
+// On iOS and sandboxed macOS, files and folders picked by the user are outside of the app sandbox and can only be accessed between
+// start/stopAccessingSecurityScopedResource calls, which the app provides with `set_security_scoped_access_handler`.
+// The app keeps security-scoped bookmarks of the picked paths, so the access isn't lost after a restart.
+
+use std::fs::File;
+use std::io::{ Read, Write, Result, Error, ErrorKind };
+use parking_lot::RwLock;
+
+// (uri, mode) -> file descriptor. Mode is the same as in ContentResolver.openFileDescriptor: "r", "w", "rw" or "rwt"
+type UriOpener = Box<dyn Fn(&str, &str) -> Option<i32> + Send + Sync>;
+// (path, start) -> whether the access was granted
+type ScopedAccessHandler = Box<dyn Fn(&str, bool) -> bool + Send + Sync>;
+
+lazy_static::lazy_static! {
+    static ref URI_OPENER: RwLock<Option<UriOpener>> = RwLock::new(None);
+    static ref SCOPED_ACCESS: RwLock<Option<ScopedAccessHandler>> = RwLock::new(None);
+}
+
+pub fn set_content_uri_opener<F: Fn(&str, &str) -> Option<i32> + Send + Sync + 'static>(cb: F) {
+    *URI_OPENER.write() = Some(Box::new(cb));
+}
+
+pub fn set_security_scoped_access_handler<F: Fn(&str, bool) -> bool + Send + Sync + 'static>(cb: F) {
+    *SCOPED_ACCESS.write() = Some(Box::new(cb));
+}
+
+// Keeps the security-scoped resource accessible for as long as it's alive.
+// Already opened file descriptors stay valid after the access is stopped
+pub struct ScopedAccess {
+    path: Option<String>
+}
+impl ScopedAccess {
+    pub fn new(path: &str) -> Self {
+        let started = SCOPED_ACCESS.read().as_ref().map(|cb| cb(path, true)).unwrap_or_default();
+        Self { path: if started { Some(path.to_string()) } else { None } }
+    }
+    // For files which don't exist yet, the access has to be granted to the containing folder
+    pub fn for_parent(path: &str) -> Self {
+        match std::path::Path::new(path).parent() {
+            Some(parent) if !is_content_uri(path) => Self::new(&parent.to_string_lossy()),
+            _ => Self { path: None }
+        }
+    }
+}
+impl Drop for ScopedAccess {
+    fn drop(&mut self) {
+        if let Some(ref path) = self.path {
+            if let Some(cb) = SCOPED_ACCESS.read().as_ref() {
+                cb(path, false);
+            }
+        }
+    }
+}
+
+pub fn is_content_uri(path: &str) -> bool {
+    path.starts_with("content://")
+}
+
+fn open_uri(uri: &str, mode: &str) -> Result<File> {
+    let fd = URI_OPENER.read().as_ref().and_then(|cb| cb(uri, mode));
+    match fd {
+        #[cfg(unix)]
+        Some(fd) if fd >= 0 => {
+            use std::os::unix::io::FromRawFd;
+            Ok(unsafe { File::from_raw_fd(fd) })
+        },
+        _ => Err(Error::new(ErrorKind::NotFound, crate::i18n::Message::new("error.unable_to_open").arg(uri).to_string()))
+    }
+}
+
+pub fn open(path: &str) -> Result<File> {
+    if is_content_uri(path) {
+        open_uri(path, "r")
+    } else {
+        let _access = ScopedAccess::new(path);
+        File::open(path)
+    }
+}
+
+pub fn create(path: &str) -> Result<File> {
+    if is_content_uri(path) {
+        open_uri(path, "rwt")
+    } else {
+        let _access = ScopedAccess::for_parent(path);
+        File::create(path)
+    }
+}
+
+pub fn read(path: &str) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    open(path)?.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+pub fn read_to_string(path: &str) -> Result<String> {
+    let mut data = String::new();
+    open(path)?.read_to_string(&mut data)?;
+    Ok(data)
+}
+
+pub fn write<C: AsRef<[u8]>>(path: &str, contents: C) -> Result<()> {
+    create(path)?.write_all(contents.as_ref())
+}
+
+// Some writers (like ffmpeg) need a real path to seek in the output file.
+// For content URIs this returns a path in the temporary directory, which has to be moved with `finish_local_output` after writing.
+// The file keeps the name and the extension of the output (`name.tmp.mp4`), because the writer picks the container by the extension
+pub fn local_output_path(path: &str) -> String {
+    if is_content_uri(path) {
+        // Document URIs have the path encoded in the last segment, e.g. `.../document/primary%3AMovies%2Fname.mp4`
+        let file_name = path.rsplit(['/', ':']).next().unwrap_or_default();
+        let file_name = file_name.rsplit("%2F").next().unwrap_or_default().rsplit("%3A").next().unwrap_or_default();
+        let name = match file_name.rsplit_once('.') {
+            Some((stem, ext)) if !stem.is_empty() && !ext.is_empty() => format!("{}.tmp.{}", stem, ext),
+            _ => format!("gyroflow_output_{:08x}.tmp", crc32fast::hash(path.as_bytes()))
+        };
+        std::env::temp_dir().join(name).to_string_lossy().to_string()
+    } else {
+        path.to_string()
+    }
+}
+
+pub fn finish_local_output(local_path: &str, path: &str) -> Result<()> {
+    if local_path != path {
+        let result = std::io::copy(&mut File::open(local_path)?, &mut create(path)?);
+        let _ = std::fs::remove_file(local_path);
+        result?;
+    }
+    Ok(())
+}
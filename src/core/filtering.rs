@@ -2,6 +2,7 @@
 // Copyright © 2021-2022 Adrian <adrian.eddy at gmail>
 
 use biquad::{Biquad, Coefficients, Type, DirectForm2Transposed, ToHertz};
+use serde::{ Serialize, Deserialize };
 
 use super::gyro_source::TimeIMU;
 
@@ -72,3 +73,116 @@ impl Lowpass {
         Ok(())
     }
 }
+
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterType {
+    #[default]
+    Butterworth,
+    Bessel,
+    MovingAverage,
+}
+impl From<usize> for FilterType {
+    fn from(v: usize) -> Self {
+        match v {
+            1 => Self::Bessel,
+            2 => Self::MovingAverage,
+            _ => Self::Butterworth
+        }
+    }
+}
+
+// Cascade of biquad (and single pole for odd orders) sections, applied per channel
+struct Cascade {
+    sections: Vec<DirectForm2Transposed<f64>>
+}
+impl Cascade {
+    // (frequency multiplier, Q), Q = 0 means a single pole section
+    fn sections(filter_type: FilterType, order: usize) -> Vec<(f64, f64)> {
+        match filter_type {
+            FilterType::Bessel => {
+                // Normalized to -3 dB at the cutoff frequency
+                match order.clamp(2, 4) {
+                    2 => vec![(1.2736, 0.5773)],
+                    3 => vec![(1.3270, 0.0), (1.4524, 0.6910)],
+                    _ => vec![(1.4192, 0.5219), (1.5912, 0.8055)],
+                }
+            },
+            _ => {
+                let order = if order == 0 { 2 } else { order.min(8) };
+                let mut ret: Vec<(f64, f64)> = (0..order / 2).map(|k| {
+                    (1.0, 1.0 / (2.0 * (std::f64::consts::PI * (2 * k + 1) as f64 / (2 * order) as f64).cos()))
+                }).collect();
+                if order % 2 == 1 { ret.push((1.0, 0.0)); }
+                ret
+            }
+        }
+    }
+    fn new(filter_type: FilterType, order: usize, freq: f64, sample_rate: f64) -> Result<Self, biquad::Errors> {
+        let sections = Self::sections(filter_type, order).into_iter().map(|(mult, q)| {
+            let f = (freq * mult).min(sample_rate / 2.0 * 0.99);
+            let coeffs = if q > 0.0 {
+                Coefficients::<f64>::from_params(Type::LowPass, sample_rate.hz(), f.hz(), q)?
+            } else {
+                Coefficients::<f64>::from_params(Type::SinglePoleLowPass, sample_rate.hz(), f.hz(), biquad::Q_BUTTERWORTH_F64)?
+            };
+            Ok(DirectForm2Transposed::<f64>::new(coeffs))
+        }).collect::<Result<Vec<_>, biquad::Errors>>()?;
+        Ok(Self { sections })
+    }
+    fn run(&mut self, data: f64) -> f64 {
+        self.sections.iter_mut().fold(data, |v, s| s.run(v))
+    }
+}
+
+fn channel(data: &mut [TimeIMU], i: usize) -> impl Iterator<Item = &mut f64> {
+    data.iter_mut().filter_map(move |x| if i < 3 { x.gyro.as_mut().map(|g| &mut g[i]) } else { x.accl.as_mut().map(|a| &mut a[i - 3]) })
+}
+
+// Centered moving average, repeated `passes` times. The window is chosen to have -3 dB at `freq`
+fn moving_average(values: &mut [f64], freq: f64, sample_rate: f64, passes: usize) {
+    let window = ((0.443 * sample_rate / freq).round() as usize).max(1);
+    if window < 2 || values.is_empty() { return; }
+    let half = window / 2;
+    for _ in 0..passes.max(1) {
+        let mut prefix = Vec::with_capacity(values.len() + 1);
+        prefix.push(0.0);
+        for v in values.iter() { prefix.push(prefix.last().unwrap() + v); }
+        for (i, v) in values.iter_mut().enumerate() {
+            let from = i.saturating_sub(half);
+            let to = (i + window - half).min(prefix.len() - 1);
+            *v = (prefix[to] - prefix[from]) / (to - from) as f64;
+        }
+    }
+}
+
+// Zero-phase low pass, `cutoff` is per axis (X, Y, Z). Axes with cutoff <= 0 are not filtered
+pub fn filter_gyro(filter_type: FilterType, order: usize, cutoff: [f64; 3], sample_rate: f64, data: &mut [TimeIMU]) -> Result<(), biquad::Errors> {
+    filter_channels(0, filter_type, order, cutoff, sample_rate, data)
+}
+pub fn filter_accl(filter_type: FilterType, order: usize, cutoff: [f64; 3], sample_rate: f64, data: &mut [TimeIMU]) -> Result<(), biquad::Errors> {
+    filter_channels(3, filter_type, order, cutoff, sample_rate, data)
+}
+
+// Channels 0..3 are gyro, 3..6 are accelerometer
+fn filter_channels(first: usize, filter_type: FilterType, order: usize, cutoff: [f64; 3], sample_rate: f64, data: &mut [TimeIMU]) -> Result<(), biquad::Errors> {
+    for i in first..first + 3 {
+        let freq = cutoff[i % 3];
+        if freq <= 0.0 { continue; }
+        match filter_type {
+            FilterType::MovingAverage => {
+                let mut values: Vec<f64> = channel(data, i).map(|x| *x).collect();
+                moving_average(&mut values, freq, sample_rate, order);
+                for (x, v) in channel(data, i).zip(values) { *x = v; }
+            },
+            _ => {
+                let mut forward = Cascade::new(filter_type, order, freq, sample_rate)?;
+                let mut backward = Cascade::new(filter_type, order, freq, sample_rate)?;
+                for x in channel(data, i) { *x = forward.run(*x); }
+                let mut values: Vec<&mut f64> = channel(data, i).collect();
+                for x in values.iter_mut().rev() { **x = backward.run(**x); }
+            }
+        }
+    }
+    Ok(())
+}
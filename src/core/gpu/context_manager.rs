@@ -0,0 +1,155 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2022 Adrian <adrian.eddy at gmail>
+
+// Processing devices of all the GPU backends in one list: `[OpenCL] <platform> <device>` entries first, then `[wgpu] <adapter> (<backend>)`.
+// The preview and the export can use different devices. OpenCL and wgpu build the kernels on a global context (see `OclWrapper::set_device`
+// and `WgpuWrapper::set_device`), so the device is activated right before building, and the built kernel keeps its own context afterwards.
+// When a device fails, `Stabilization` moves on to the next one in `fallback_chain`, which always ends with the CPU.
+// The fallback is used only for the frame that failed, and the switch is reported with the callback set by `set_on_fallback`.
+
+use parking_lot::{ Mutex, RwLock };
+#[cfg(feature = "use-opencl")]
+use super::opencl;
+use super::wgpu;
+
+/// Device index of the CPU path
+pub const CPU: isize = -1;
+
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+pub enum GpuTask {
+    #[default]
+    Preview = 0,
+    Export = 1,
+}
+
+#[derive(Default)]
+pub struct GpuContextManager {
+    devices: Vec<String>,
+    default_device: Option<String>, // Initialized by `initialize_contexts`, used when nothing is selected
+    selected: [Option<isize>; 2], // Index in `devices` or `CPU`, by `GpuTask`
+}
+
+lazy_static::lazy_static! {
+    pub static ref MANAGER: RwLock<GpuContextManager> = RwLock::new(GpuContextManager::default());
+    // Device the global contexts are currently set to, None - the default one. Locked while the kernels are built
+    static ref ACTIVE: Mutex<Option<isize>> = Mutex::new(None);
+    static ref ON_FALLBACK: RwLock<Option<FallbackCallback>> = RwLock::new(None);
+}
+
+type FallbackCallback = Box<dyn Fn(&str, &str) + Send + Sync + 'static>;
+
+/// Called with the names of the failed device and the one used instead
+pub fn set_on_fallback<F: Fn(&str, &str) + Send + Sync + 'static>(cb: F) {
+    *ON_FALLBACK.write() = Some(Box::new(cb));
+}
+pub(crate) fn report_fallback(failed: &str, used: &str) {
+    if let Some(cb) = ON_FALLBACK.read().as_ref() {
+        cb(failed, used);
+    }
+}
+
+impl GpuContextManager {
+    /// Enumerates the devices of all available backends again
+    pub fn list_devices(&mut self) -> Vec<String> {
+        let mut ret = Vec::new();
+        let backends = super::available_backends();
+
+        #[cfg(feature = "use-opencl")]
+        if backends.contains(&"OpenCL") {
+            ret.extend(opencl::OclWrapper::list_devices().into_iter().map(|x| format!("[OpenCL] {x}")));
+        }
+        if backends.contains(&"wgpu") {
+            ret.extend(wgpu::WgpuWrapper::list_devices().into_iter().map(|x| format!("[wgpu] {x}")));
+        }
+        self.devices = ret.clone();
+        ret
+    }
+
+    pub fn devices(&mut self) -> &[String] {
+        if self.devices.is_empty() { self.list_devices(); }
+        &self.devices
+    }
+
+    pub fn set_default_device(&mut self, list_name: String) {
+        self.default_device = Some(list_name);
+    }
+
+    /// "OpenCL" or "wgpu"
+    pub fn backend(&mut self, index: isize) -> Option<&'static str> {
+        let name = self.devices().get(usize::try_from(index).ok()?)?;
+        if name.starts_with("[OpenCL]") { return Some("OpenCL"); }
+        if name.starts_with("[wgpu]") { return Some("wgpu"); }
+        None
+    }
+
+    /// Device for `task`, `index` is in the list returned by `list_devices` or `CPU`. Returns false if there's no such device
+    pub fn select(&mut self, task: GpuTask, index: isize) -> bool {
+        if index != CPU && (index < 0 || index as usize >= self.devices().len()) { return false; }
+        self.selected[task as usize] = Some(index);
+        true
+    }
+    pub fn select_by_name(&mut self, task: GpuTask, name: &str) -> bool {
+        match self.devices().iter().position(|x| x == name) {
+            Some(index) => self.select(task, index as isize),
+            None => false
+        }
+    }
+
+    /// None - the default device. The export uses the preview device, unless it has its own
+    pub fn selected(&self, task: GpuTask) -> Option<isize> {
+        self.selected[task as usize].or(self.selected[GpuTask::Preview as usize])
+    }
+
+    /// Devices to try after `failed`, in the list order and ending with the CPU
+    pub fn fallback_chain(&mut self, failed: Option<isize>) -> Vec<isize> {
+        let default_index = self.default_device.clone().and_then(|x| self.devices().iter().position(|d| *d == x)).map(|x| x as isize);
+        let failed = failed.or(default_index);
+        let mut ret: Vec<isize> = (0..self.devices().len() as isize).filter(|x| Some(*x) != failed).collect();
+        ret.push(CPU);
+        ret
+    }
+
+    pub fn device_name(&mut self, index: Option<isize>) -> String {
+        match index {
+            Some(CPU) => "CPU".into(),
+            Some(i) => self.devices().get(i as usize).cloned().unwrap_or_default(),
+            None => self.default_device.clone().unwrap_or_default()
+        }
+    }
+
+    fn activate(device: Option<isize>) -> bool {
+        let index = match device {
+            Some(x) => x,
+            None => { return super::initialize_contexts().is_some(); }
+        };
+        let (name, first_index) = {
+            let mut lock = MANAGER.write();
+            let name = lock.device_name(Some(index));
+            let prefix = if name.starts_with("[OpenCL]") { "[OpenCL]" } else { "[wgpu]" };
+            (name, lock.devices().iter().position(|x| x.starts_with(prefix)).unwrap_or(0) as isize)
+        };
+        if name.starts_with("[OpenCL]") {
+            #[cfg(feature = "use-opencl")]
+            match opencl::OclWrapper::set_device((index - first_index) as usize) {
+                Ok(_) => { return true; },
+                Err(e) => { log::error!("Failed to set OpenCL device {}: {:?}", name, e); }
+            }
+        } else if name.starts_with("[wgpu]") {
+            if wgpu::WgpuWrapper::set_device((index - first_index) as usize).is_some() {
+                return true;
+            }
+            log::error!("Failed to set wgpu device {}", name);
+        }
+        false
+    }
+}
+
+/// Runs `f` with the global context of the backend set to `device` (None - the default one). None if the device couldn't be activated
+pub fn with_device<R>(device: Option<isize>, f: impl FnOnce() -> R) -> Option<R> {
+    let mut active = ACTIVE.lock();
+    if *active != device {
+        if !GpuContextManager::activate(device) { return None; }
+        *active = device;
+    }
+    Some(f())
+}
@@ -0,0 +1,286 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2022 Adrian <adrian.eddy at gmail>
+
+// CUDA backend for frames which are already in the memory of an NVIDIA GPU, eg. NVDEC surfaces which go straight to NVENC.
+// The OpenCL kernel is compiled with NVRTC, with the OpenCL built-ins defined in `cuda_compat.h`.
+// The driver and NVRTC are loaded at runtime, so the app still starts on machines without them.
+
+use std::ffi::{ c_void, CString };
+use std::os::raw::{ c_char, c_int, c_uint };
+use libloading::Library;
+use super::*;
+use crate::stabilization::KernelParams;
+use crate::stabilization::distortion_models::GoProSuperview;
+
+type CUresult = c_int;
+type CUdeviceptr = u64;
+type CUcontext = *mut c_void;
+type CUmodule = *mut c_void;
+type CUfunction = *mut c_void;
+type CUstream = *mut c_void;
+type NvrtcProgram = *mut c_void;
+
+#[cfg(target_os = "windows")]
+const CUDA_LIBRARIES: &[&str] = &["nvcuda.dll"];
+#[cfg(not(target_os = "windows"))]
+const CUDA_LIBRARIES: &[&str] = &["libcuda.so.1", "libcuda.so"];
+
+#[cfg(target_os = "windows")]
+const NVRTC_LIBRARIES: &[&str] = &["nvrtc64_120_0.dll", "nvrtc64_112_0.dll", "nvrtc64_111_0.dll", "nvrtc64_110_0.dll"];
+#[cfg(not(target_os = "windows"))]
+const NVRTC_LIBRARIES: &[&str] = &["libnvrtc.so.12", "libnvrtc.so.11.2", "libnvrtc.so"];
+
+const BLOCK_SIZE: u32 = 16;
+
+struct Api {
+    cu_ctx_push_current:    unsafe extern "C" fn(CUcontext) -> CUresult,
+    cu_ctx_pop_current:     unsafe extern "C" fn(*mut CUcontext) -> CUresult,
+    cu_module_load_data:    unsafe extern "C" fn(*mut CUmodule, *const c_void) -> CUresult,
+    cu_module_unload:       unsafe extern "C" fn(CUmodule) -> CUresult,
+    cu_module_get_function: unsafe extern "C" fn(*mut CUfunction, CUmodule, *const c_char) -> CUresult,
+    cu_mem_alloc:           unsafe extern "C" fn(*mut CUdeviceptr, usize) -> CUresult,
+    cu_mem_free:            unsafe extern "C" fn(CUdeviceptr) -> CUresult,
+    cu_memcpy_htod_async:   unsafe extern "C" fn(CUdeviceptr, *const c_void, usize, CUstream) -> CUresult,
+    cu_launch_kernel:       unsafe extern "C" fn(CUfunction, c_uint, c_uint, c_uint, c_uint, c_uint, c_uint, c_uint, CUstream, *mut *mut c_void, *mut *mut c_void) -> CUresult,
+    cu_stream_synchronize:  unsafe extern "C" fn(CUstream) -> CUresult,
+
+    nvrtc_create_program:       unsafe extern "C" fn(*mut NvrtcProgram, *const c_char, *const c_char, c_int, *const *const c_char, *const *const c_char) -> c_int,
+    nvrtc_compile_program:      unsafe extern "C" fn(NvrtcProgram, c_int, *const *const c_char) -> c_int,
+    nvrtc_get_ptx_size:         unsafe extern "C" fn(NvrtcProgram, *mut usize) -> c_int,
+    nvrtc_get_ptx:              unsafe extern "C" fn(NvrtcProgram, *mut c_char) -> c_int,
+    nvrtc_get_program_log_size: unsafe extern "C" fn(NvrtcProgram, *mut usize) -> c_int,
+    nvrtc_get_program_log:      unsafe extern "C" fn(NvrtcProgram, *mut c_char) -> c_int,
+    nvrtc_destroy_program:      unsafe extern "C" fn(*mut NvrtcProgram) -> c_int,
+
+    _cuda: Library,
+    _nvrtc: Library,
+}
+
+lazy_static::lazy_static! {
+    static ref API: Option<Api> = Api::load();
+}
+
+fn load_library(names: &[&str]) -> Option<Library> {
+    names.iter().find_map(|name| unsafe { Library::new(name).ok() })
+}
+fn symbol<T: Copy>(lib: &Library, name: &str) -> Option<T> {
+    let sym = unsafe { lib.get::<T>(name.as_bytes()) };
+    match sym {
+        Ok(sym) => Some(*sym),
+        Err(e) => { log::error!("Missing CUDA function {}: {:?}", name, e); None }
+    }
+}
+
+impl Api {
+    fn load() -> Option<Self> {
+        let cuda = load_library(CUDA_LIBRARIES);
+        let nvrtc = load_library(NVRTC_LIBRARIES);
+        if cuda.is_none() || nvrtc.is_none() {
+            log::info!("CUDA not available (driver: {}, nvrtc: {})", cuda.is_some(), nvrtc.is_some());
+            return None;
+        }
+        let (cuda, nvrtc) = (cuda.unwrap(), nvrtc.unwrap());
+
+        let cu_init: unsafe extern "C" fn(c_uint) -> CUresult = symbol(&cuda, "cuInit")?;
+        if unsafe { cu_init(0) } != 0 {
+            log::error!("Failed to initialize CUDA");
+            return None;
+        }
+
+        Some(Self {
+            cu_ctx_push_current:    symbol(&cuda, "cuCtxPushCurrent_v2")?,
+            cu_ctx_pop_current:     symbol(&cuda, "cuCtxPopCurrent_v2")?,
+            cu_module_load_data:    symbol(&cuda, "cuModuleLoadData")?,
+            cu_module_unload:       symbol(&cuda, "cuModuleUnload")?,
+            cu_module_get_function: symbol(&cuda, "cuModuleGetFunction")?,
+            cu_mem_alloc:           symbol(&cuda, "cuMemAlloc_v2")?,
+            cu_mem_free:            symbol(&cuda, "cuMemFree_v2")?,
+            cu_memcpy_htod_async:   symbol(&cuda, "cuMemcpyHtoDAsync_v2")?,
+            cu_launch_kernel:       symbol(&cuda, "cuLaunchKernel")?,
+            cu_stream_synchronize:  symbol(&cuda, "cuStreamSynchronize")?,
+
+            nvrtc_create_program:       symbol(&nvrtc, "nvrtcCreateProgram")?,
+            nvrtc_compile_program:      symbol(&nvrtc, "nvrtcCompileProgram")?,
+            nvrtc_get_ptx_size:         symbol(&nvrtc, "nvrtcGetPTXSize")?,
+            nvrtc_get_ptx:              symbol(&nvrtc, "nvrtcGetPTX")?,
+            nvrtc_get_program_log_size: symbol(&nvrtc, "nvrtcGetProgramLogSize")?,
+            nvrtc_get_program_log:      symbol(&nvrtc, "nvrtcGetProgramLog")?,
+            nvrtc_destroy_program:      symbol(&nvrtc, "nvrtcDestroyProgram")?,
+
+            _cuda: cuda,
+            _nvrtc: nvrtc,
+        })
+    }
+
+    // PTX of the kernel, null terminated
+    fn compile(&self, source: &str) -> Option<Vec<u8>> {
+        let source = CString::new(source).ok()?;
+        let name = CString::new("undistort.cu").ok()?;
+        let options = [b"-default-device\0".as_ptr() as *const c_char];
+        unsafe {
+            let mut program = std::ptr::null_mut();
+            if (self.nvrtc_create_program)(&mut program, source.as_ptr(), name.as_ptr(), 0, std::ptr::null(), std::ptr::null()) != 0 {
+                log::error!("Failed to create the NVRTC program");
+                return None;
+            }
+            let ptx = if (self.nvrtc_compile_program)(program, options.len() as c_int, options.as_ptr()) == 0 {
+                let mut size = 0;
+                (self.nvrtc_get_ptx_size)(program, &mut size);
+                let mut ptx = vec![0u8; size];
+                (self.nvrtc_get_ptx)(program, ptx.as_mut_ptr() as *mut c_char);
+                Some(ptx)
+            } else {
+                let mut size = 0;
+                (self.nvrtc_get_program_log_size)(program, &mut size);
+                let mut log = vec![0u8; size];
+                (self.nvrtc_get_program_log)(program, log.as_mut_ptr() as *mut c_char);
+                log::error!("Failed to compile the CUDA kernel: {}", String::from_utf8_lossy(&log));
+                None
+            };
+            (self.nvrtc_destroy_program)(&mut program);
+            ptx
+        }
+    }
+}
+
+unsafe fn pop_context(api: &Api) {
+    let mut popped = std::ptr::null_mut();
+    (api.cu_ctx_pop_current)(&mut popped);
+}
+
+fn check(result: CUresult, what: &str) -> Option<()> {
+    if result != 0 {
+        log::error!("CUDA error in {}: {}", what, result);
+        return None;
+    }
+    Some(())
+}
+
+/// Whether the CUDA driver and NVRTC could be loaded
+pub fn is_available() -> bool {
+    API.is_some()
+}
+
+pub struct CudaWrapper {
+    context: CUcontext,
+    module: CUmodule,
+    function: CUfunction,
+
+    buf_params: CUdeviceptr,
+    buf_matrices: CUdeviceptr,
+    max_matrix_count: usize,
+}
+
+impl CudaWrapper {
+    /// Only for the `BufferSource::Cuda` buffers, the kernel runs on their context
+    pub fn new(params: &KernelParams, ocl_names: (&str, &str, &str, &str), lens_model_funcs: &str, buffers: &BufferDescription) -> Option<Self> {
+        if params.height < 4 || params.output_height < 4 || params.stride < 1 { return None; }
+
+        let context = match buffers.buffers {
+            BufferSource::Cuda { context, .. } => context,
+            _ => return None
+        };
+        if context.is_null() { return None; }
+        let api = API.as_ref()?;
+
+        let mut kernel = include_str!("opencl_undistort.cl").to_string();
+        kernel.insert_str(0, GoProSuperview::opencl_functions());
+        kernel.insert_str(0, lens_model_funcs);
+        kernel = kernel.replace("DATA_CONVERTF", ocl_names.3)
+                       .replace("DATA_TYPEF", ocl_names.2)
+                       .replace("DATA_CONVERT", ocl_names.1)
+                       .replace("DATA_TYPE", ocl_names.0)
+                       .replace("BG_TYPE", ocl_names.2)
+                       .replace("WARP_FP16", "0")
+                       .replace("PIXEL_BYTES", &format!("{}", params.bytes_per_pixel))
+                       .replace("PIX_ELEMENT_COUNT", &format!("{}", params.pix_element_count))
+                       .replace("INTERPOLATION", &format!("{}", params.interpolation))
+                       // OpenCL syntax which can't be covered by `cuda_compat.h`
+                       .replace("__constant WARP_FLOAT coeffs[", "__constant__ const WARP_FLOAT coeffs[")
+                       .replace("(float2)(", "make_float2(")
+                       .replace("(float4)(", "make_float4(");
+        kernel.insert_str(0, include_str!("cuda_compat.h"));
+
+        let ptx = api.compile(&kernel)?;
+
+        unsafe {
+            check((api.cu_ctx_push_current)(context), "cuCtxPushCurrent")?;
+            let ret = Self::create(api, context, &ptx, params);
+            pop_context(api);
+            ret
+        }
+    }
+
+    unsafe fn create(api: &Api, context: CUcontext, ptx: &[u8], params: &KernelParams) -> Option<Self> {
+        // Everything created so far is released in `drop` if any step fails
+        let mut ret = Self {
+            context,
+            module: std::ptr::null_mut(),
+            function: std::ptr::null_mut(),
+            buf_params: 0,
+            buf_matrices: 0,
+            max_matrix_count: 9 * params.height as usize,
+        };
+        let name = CString::new("undistort_image").ok()?;
+        check((api.cu_module_load_data)(&mut ret.module, ptx.as_ptr() as *const c_void), "cuModuleLoadData")?;
+        check((api.cu_module_get_function)(&mut ret.function, ret.module, name.as_ptr()), "cuModuleGetFunction")?;
+        check((api.cu_mem_alloc)(&mut ret.buf_params, std::mem::size_of::<KernelParams>()), "cuMemAlloc")?;
+        check((api.cu_mem_alloc)(&mut ret.buf_matrices, ret.max_matrix_count * std::mem::size_of::<f32>()), "cuMemAlloc")?;
+        Some(ret)
+    }
+
+    pub fn undistort_image(&mut self, buffers: &mut BufferDescription, itm: &crate::stabilization::FrameTransform) -> bool {
+        let (input, output, stream) = match buffers.buffers {
+            BufferSource::Cuda { input, output, stream, .. } => (input, output, stream),
+            _ => return false
+        };
+        let api = match API.as_ref() { Some(x) => x, None => return false };
+
+        let matrices = unsafe { std::slice::from_raw_parts(itm.matrices.as_ptr() as *const f32, itm.matrices.len() * 9) };
+        if matrices.len() > self.max_matrix_count { log::error!("Buffer size mismatch! {} vs {}", self.max_matrix_count, matrices.len()); return false; }
+
+        unsafe {
+            if check((api.cu_ctx_push_current)(self.context), "cuCtxPushCurrent").is_none() { return false; }
+            let ret = self.launch(api, input, output, stream, &itm.kernel_params, matrices).is_some();
+            pop_context(api);
+            ret
+        }
+    }
+
+    unsafe fn launch(&self, api: &Api, input: CUdeviceptr, output: CUdeviceptr, stream: CUstream, params: &KernelParams, matrices: &[f32]) -> Option<()> {
+        let params_bytes = bytemuck::bytes_of(params);
+        let matrices_bytes: &[u8] = bytemuck::cast_slice(matrices);
+        check((api.cu_memcpy_htod_async)(self.buf_params, params_bytes.as_ptr() as *const c_void, params_bytes.len(), stream), "cuMemcpyHtoDAsync")?;
+        check((api.cu_memcpy_htod_async)(self.buf_matrices, matrices_bytes.as_ptr() as *const c_void, matrices_bytes.len(), stream), "cuMemcpyHtoDAsync")?;
+
+        let mut args = [
+            &input             as *const CUdeviceptr as *mut c_void,
+            &output            as *const CUdeviceptr as *mut c_void,
+            &self.buf_params   as *const CUdeviceptr as *mut c_void,
+            &self.buf_matrices as *const CUdeviceptr as *mut c_void,
+        ];
+        let (width, height) = (params.output_width as u32, params.output_height as u32);
+        check((api.cu_launch_kernel)(self.function,
+            (width + BLOCK_SIZE - 1) / BLOCK_SIZE, (height + BLOCK_SIZE - 1) / BLOCK_SIZE, 1,
+            BLOCK_SIZE, BLOCK_SIZE, 1,
+            0, stream, args.as_mut_ptr(), std::ptr::null_mut()
+        ), "cuLaunchKernel")?;
+
+        // The parameter buffers are reused for the next frame, and the output goes to the encoder on its own stream
+        check((api.cu_stream_synchronize)(stream), "cuStreamSynchronize")
+    }
+}
+
+impl Drop for CudaWrapper {
+    fn drop(&mut self) {
+        if let Some(api) = API.as_ref() {
+            unsafe {
+                if (api.cu_ctx_push_current)(self.context) != 0 { return; }
+                if self.buf_params   != 0 { (api.cu_mem_free)(self.buf_params); }
+                if self.buf_matrices != 0 { (api.cu_mem_free)(self.buf_matrices); }
+                if !self.module.is_null() { (api.cu_module_unload)(self.module); }
+                pop_context(api);
+            }
+        }
+    }
+}
@@ -0,0 +1,68 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2022 Adrian <adrian.eddy at gmail>
+
+// OpenCL built-ins used by `opencl_undistort.cl` and the lens models, for compiling them as CUDA C++ with NVRTC, see `cuda.rs`.
+// Functions without the execution space are compiled as device functions (`-default-device`).
+
+typedef unsigned char uchar;
+typedef unsigned short ushort;
+typedef unsigned int uint;
+
+#define __kernel extern "C" __global__
+#define __global
+#define __constant const
+
+#define get_global_id(i) ((i) == 0 ? (int)(blockIdx.x * blockDim.x + threadIdx.x) : (int)(blockIdx.y * blockDim.y + threadIdx.y))
+
+// Broadcast of a scalar, `(float2)(x)` in OpenCL
+inline float2 make_float2(float s) { return make_float2(s, s); }
+inline float4 make_float4(float s) { return make_float4(s, s, s, s); }
+
+#define FLOAT2_OP(op) \
+    inline float2 operator op(float2 a, float2 b) { return make_float2(a.x op b.x, a.y op b.y); } \
+    inline float2 operator op(float2 a, float b)  { return make_float2(a.x op b, a.y op b); } \
+    inline float2 operator op(float a, float2 b)  { return make_float2(a op b.x, a op b.y); } \
+    inline float2 &operator op##=(float2 &a, float2 b) { a = a op b; return a; } \
+    inline float2 &operator op##=(float2 &a, float b)  { a = a op b; return a; }
+
+#define FLOAT4_OP(op) \
+    inline float4 operator op(float4 a, float4 b) { return make_float4(a.x op b.x, a.y op b.y, a.z op b.z, a.w op b.w); } \
+    inline float4 operator op(float4 a, float b)  { return make_float4(a.x op b, a.y op b, a.z op b, a.w op b); } \
+    inline float4 operator op(float a, float4 b)  { return make_float4(a op b.x, a op b.y, a op b.z, a op b.w); } \
+    inline float4 &operator op##=(float4 &a, float4 b) { a = a op b; return a; } \
+    inline float4 &operator op##=(float4 &a, float b)  { a = a op b; return a; }
+
+FLOAT2_OP(+) FLOAT2_OP(-) FLOAT2_OP(*) FLOAT2_OP(/)
+FLOAT4_OP(+) FLOAT4_OP(-) FLOAT4_OP(*) FLOAT4_OP(/)
+
+inline float2 operator-(float2 a) { return make_float2(-a.x, -a.y); }
+inline float4 operator-(float4 a) { return make_float4(-a.x, -a.y, -a.z, -a.w); }
+
+inline float2 min(float2 a, float2 b) { return make_float2(fminf(a.x, b.x), fminf(a.y, b.y)); }
+inline float2 max(float2 a, float2 b) { return make_float2(fmaxf(a.x, b.x), fmaxf(a.y, b.y)); }
+inline float length(float2 v) { return sqrtf(v.x * v.x + v.y * v.y); }
+inline float  mix(float a,  float b,  float t) { return a + (b - a) * t; }
+inline float2 mix(float2 a, float2 b, float t) { return a + (b - a) * t; }
+
+// Conversions round toward zero and saturate, like in OpenCL
+inline int convert_int_sat_rtz(float v) { return __float2int_rz(v); }
+inline uchar  sat_uchar(float v)  { return (uchar)fminf(fmaxf(v, 0.0f), 255.0f); }
+inline ushort sat_ushort(float v) { return (ushort)fminf(fmaxf(v, 0.0f), 65535.0f); }
+
+inline float  convert_float(uchar v)    { return (float)v; }
+inline float  convert_float(ushort v)   { return (float)v; }
+inline float  convert_float(float v)    { return v; }
+inline float2 convert_float2(uchar2 v)  { return make_float2(v.x, v.y); }
+inline float2 convert_float2(ushort2 v) { return make_float2(v.x, v.y); }
+inline float2 convert_float2(float2 v)  { return v; }
+inline float4 convert_float4(uchar4 v)  { return make_float4(v.x, v.y, v.z, v.w); }
+inline float4 convert_float4(ushort4 v) { return make_float4(v.x, v.y, v.z, v.w); }
+inline float4 convert_float4(float4 v)  { return v; }
+
+inline uchar   convert_uchar_sat(float v)    { return sat_uchar(v); }
+inline uchar2  convert_uchar2_sat(float2 v)  { return make_uchar2(sat_uchar(v.x), sat_uchar(v.y)); }
+inline uchar4  convert_uchar4_sat(float4 v)  { return make_uchar4(sat_uchar(v.x), sat_uchar(v.y), sat_uchar(v.z), sat_uchar(v.w)); }
+inline ushort  convert_ushort_sat(float v)   { return sat_ushort(v); }
+inline ushort2 convert_ushort2_sat(float2 v) { return make_ushort2(sat_ushort(v.x), sat_ushort(v.y)); }
+inline ushort4 convert_ushort4_sat(float4 v) { return make_ushort4(sat_ushort(v.x), sat_ushort(v.y), sat_ushort(v.z), sat_ushort(v.w)); }
+
@@ -0,0 +1,182 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// Fallback for frames larger than what the GPU device can allocate.
+// The input frame is reduced with a mip chain (2x2 box filter per level) until it fits the device limits,
+// processed at that size with adjusted kernel parameters and the result is upscaled back to the output size.
+
+use std::borrow::Cow;
+use rayon::prelude::*;
+use crate::gpu::{ BufferDescription, BufferSource };
+use crate::stabilization::{ FrameTransform, KernelParams };
+
+pub const MAX_MIP_LEVEL: u32 = 4;
+
+#[derive(Default, Clone, Copy, Debug)]
+pub struct DeviceLimits {
+    pub max_dimension: usize,  // max texture width or height, in pixels
+    pub max_allocation: usize, // max size of a single buffer, in bytes
+}
+
+impl DeviceLimits {
+    pub fn fits(&self, params: &KernelParams) -> bool {
+        let max_dim = params.width.max(params.height).max(params.output_width).max(params.output_height) as usize;
+        let max_buf = (params.stride as usize * params.height as usize).max(params.output_stride as usize * params.output_height as usize);
+        max_dim <= self.max_dimension && max_buf <= self.max_allocation
+    }
+
+    /// Mip level at which the frame fits the device, `None` if it doesn't fit even at `MAX_MIP_LEVEL`
+    pub fn mip_level(&self, params: &KernelParams) -> Option<u32> {
+        let level = (0..=MAX_MIP_LEVEL).find(|l| self.fits(&scaled_params(params, *l)));
+        if let Some(level) = level {
+            if level > 0 {
+                let small = scaled_params(params, level);
+                log::warn!("Frame {}x{} -> {}x{} exceeds the GPU limits {:?}, processing at {}x{} -> {}x{} and upscaling",
+                    params.width, params.height, params.output_width, params.output_height, self,
+                    small.width, small.height, small.output_width, small.output_height);
+            }
+        }
+        level
+    }
+}
+
+fn mip_size(mut size: i32, level: u32) -> i32 {
+    for _ in 0..level { size = (size / 2).max(1); }
+    size
+}
+
+/// Kernel parameters for processing at given mip level. All pixel based values are scaled, normalized ones stay the same
+pub fn scaled_params(params: &KernelParams, level: u32) -> KernelParams {
+    if level == 0 { return *params; }
+    let s = 1.0 / (1 << level) as f32;
+    let mut p = *params;
+    p.width         = mip_size(params.width, level);
+    p.height        = mip_size(params.height, level);
+    p.stride        = p.width * params.bytes_per_pixel;
+    p.output_width  = mip_size(params.output_width, level);
+    p.output_height = mip_size(params.output_height, level);
+    p.output_stride = p.output_width * params.bytes_per_pixel;
+    p.f             = [params.f[0] * s, params.f[1] * s];
+    p.c             = [params.c[0] * s, params.c[1] * s];
+    p.translation2d = [params.translation2d[0] * s, params.translation2d[1] * s];
+    if params.matrix_count > 1 {
+        p.matrix_count = params.matrix_count.min(p.height);
+    }
+    p
+}
+
+pub fn scaled_transform(itm: &FrameTransform, level: u32) -> FrameTransform {
+    let kernel_params = scaled_params(&itm.kernel_params, level);
+    let inv_s = (1 << level) as f32;
+    let count = itm.matrices.len();
+    let new_count = kernel_params.matrix_count.max(1) as usize;
+    // Matrices map output pixels, so they need to take the small coordinates, and the rolling shutter rows are resampled to the new height
+    let matrices = (0..new_count).map(|i| {
+        let mut m = itm.matrices[(i * count / new_count).min(count.max(1) - 1)];
+        for j in [0, 1, 3, 4, 6, 7] { m[j] *= inv_s; }
+        m
+    }).collect();
+
+    FrameTransform { matrices, kernel_params, fov: itm.fov }
+}
+
+fn read_scalar(data: &[u8], scalar_bytes: usize) -> f32 {
+    match scalar_bytes {
+        1 => data[0] as f32,
+        2 => u16::from_ne_bytes([data[0], data[1]]) as f32,
+        _ => f32::from_ne_bytes([data[0], data[1], data[2], data[3]]),
+    }
+}
+fn write_scalar(data: &mut [u8], scalar_bytes: usize, v: f32) {
+    match scalar_bytes {
+        1 => data[0] = v.round().max(0.0).min(255.0) as u8,
+        2 => data[..2].copy_from_slice(&(v.round().max(0.0).min(65535.0) as u16).to_ne_bytes()),
+        _ => data[..4].copy_from_slice(&v.to_ne_bytes()),
+    }
+}
+
+/// Reduces the input frame to given mip level, the result is tightly packed (stride = width * bytes_per_pixel)
+pub fn downscale_image(input: &[u8], params: &KernelParams, level: u32) -> Vec<u8> {
+    let bpp = params.bytes_per_pixel as usize;
+    let count = params.pix_element_count.max(1) as usize;
+    let scalar_bytes = bpp / count;
+
+    let (mut w, mut h, mut stride) = (params.width as usize, params.height as usize, params.stride as usize);
+    let mut data = Cow::Borrowed(input);
+    for _ in 0..level {
+        let (nw, nh) = ((w / 2).max(1), (h / 2).max(1));
+        let nstride = nw * bpp;
+        let mut out = vec![0u8; nstride * nh];
+        let src = &data;
+        out.par_chunks_mut(nstride).enumerate().for_each(|(y, row)| {
+            let (y0, y1) = ((y * 2).min(h - 1), (y * 2 + 1).min(h - 1));
+            for x in 0..nw {
+                let (x0, x1) = ((x * 2).min(w - 1), (x * 2 + 1).min(w - 1));
+                for c in 0..count {
+                    let px = |x: usize, y: usize| read_scalar(&src[y * stride + x * bpp + c * scalar_bytes..], scalar_bytes);
+                    let v = (px(x0, y0) + px(x1, y0) + px(x0, y1) + px(x1, y1)) / 4.0;
+                    write_scalar(&mut row[x * bpp + c * scalar_bytes..], scalar_bytes, v);
+                }
+            }
+        });
+        data = Cow::Owned(out);
+        w = nw; h = nh; stride = nstride;
+    }
+    data.into_owned()
+}
+
+/// Bilinear upscale of the processed frame (`small_params` output size) to the full output size
+pub fn upscale_image(input: &[u8], small_params: &KernelParams, output: &mut [u8], params: &KernelParams) {
+    let bpp = params.bytes_per_pixel as usize;
+    let count = params.pix_element_count.max(1) as usize;
+    let scalar_bytes = bpp / count;
+
+    let (sw, sh, sstride) = (small_params.output_width as usize, small_params.output_height as usize, small_params.output_stride as usize);
+    let (w, h, stride) = (params.output_width as usize, params.output_height as usize, params.output_stride as usize);
+    let (rx, ry) = (sw as f32 / w as f32, sh as f32 / h as f32);
+
+    output.par_chunks_mut(stride).take(h).enumerate().for_each(|(y, row)| {
+        let fy = ((y as f32 + 0.5) * ry - 0.5).max(0.0).min((sh - 1) as f32);
+        let (y0, ty) = (fy.floor() as usize, fy.fract());
+        let y1 = (y0 + 1).min(sh - 1);
+        for x in 0..w {
+            let fx = ((x as f32 + 0.5) * rx - 0.5).max(0.0).min((sw - 1) as f32);
+            let (x0, tx) = (fx.floor() as usize, fx.fract());
+            let x1 = (x0 + 1).min(sw - 1);
+            for c in 0..count {
+                let px = |x: usize, y: usize| read_scalar(&input[y * sstride + x * bpp + c * scalar_bytes..], scalar_bytes);
+                let top    = px(x0, y0) * (1.0 - tx) + px(x1, y0) * tx;
+                let bottom = px(x0, y1) * (1.0 - tx) + px(x1, y1) * tx;
+                write_scalar(&mut row[x * bpp + c * scalar_bytes..], scalar_bytes, top * (1.0 - ty) + bottom * ty);
+            }
+        }
+    });
+}
+
+/// Runs `process` at given mip level on CPU buffers: the input is downscaled, processed with scaled parameters and upscaled to the output.
+/// Returns `None` if the buffers aren't on the CPU
+pub fn process_downscaled<R, F>(buffers: &mut BufferDescription, itm: &FrameTransform, level: u32, process: F) -> Option<R>
+    where F: FnOnce(&mut BufferDescription, &FrameTransform) -> R
+{
+    if let BufferSource::Cpu { input, output } = &mut buffers.buffers {
+        let scaled = scaled_transform(itm, level);
+        let p = scaled.kernel_params;
+        let mut small_input = downscale_image(input, &itm.kernel_params, level);
+        let mut small_output = vec![0u8; p.output_stride as usize * p.output_height as usize];
+
+        let ret = process(&mut BufferDescription {
+            input_size:  (p.width as usize, p.height as usize, p.stride as usize),
+            output_size: (p.output_width as usize, p.output_height as usize, p.output_stride as usize),
+            input_rect: None,
+            output_rect: None,
+            buffers: BufferSource::Cpu {
+                input: &mut small_input,
+                output: &mut small_output
+            }
+        }, &scaled);
+
+        upscale_image(&small_output, &p, output, &itm.kernel_params);
+        Some(ret)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,79 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2022 Adrian <adrian.eddy at gmail>
+
+// Disk cache of the compiled kernels, so the undistortion kernel doesn't have to be compiled again on every launch.
+// The key is a hash of the device (with the driver version) and of the final kernel source, so any change to either of them makes a new entry.
+// Only OpenCL programs are cached. wgpu 0.13 has no API for the compiled pipelines, and relies on the shader caches of the drivers instead.
+// Set the `NO_KERNEL_CACHE` environment variable to disable it.
+
+use std::path::PathBuf;
+use parking_lot::RwLock;
+
+// Oldest entries are removed above that
+const MAX_ENTRIES: usize = 32;
+
+lazy_static::lazy_static! {
+    static ref CACHE_DIR: RwLock<PathBuf> = RwLock::new(std::env::temp_dir().join("Gyroflow").join("kernel_cache"));
+}
+
+/// The temporary directory is used by default, apps should set a persistent one
+pub fn set_cache_dir(path: PathBuf) {
+    *CACHE_DIR.write() = path;
+}
+
+fn is_enabled() -> bool {
+    std::env::var("NO_KERNEL_CACHE").unwrap_or_default().is_empty()
+}
+
+pub fn key(device: &str, source: &str) -> String {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(env!("CARGO_PKG_VERSION").as_bytes());
+    hasher.update(device.as_bytes());
+    format!("{:08x}{:08x}", hasher.finalize(), crc32fast::hash(source.as_bytes()))
+}
+
+pub fn load(key: &str) -> Option<Vec<u8>> {
+    if !is_enabled() { return None; }
+    let data = std::fs::read(CACHE_DIR.read().join(format!("{key}.bin"))).ok()?;
+    if data.is_empty() { return None; }
+    log::debug!("Using cached kernel {key}");
+    Some(data)
+}
+
+pub fn store(key: &str, data: &[u8]) {
+    if !is_enabled() || data.is_empty() { return; }
+    let dir = CACHE_DIR.read().clone();
+    if let Err(e) = std::fs::create_dir_all(&dir) {
+        log::warn!("Failed to create the kernel cache directory {:?}: {:?}", dir, e);
+        return;
+    }
+    // Written under a temporary name, so a concurrent `load` never reads a partial file
+    let tmp = dir.join(format!("{key}.{:08x}.tmp", fastrand::u32(..)));
+    if std::fs::write(&tmp, data).and_then(|_| std::fs::rename(&tmp, dir.join(format!("{key}.bin")))).is_err() {
+        log::warn!("Failed to write the kernel cache entry {key}");
+        let _ = std::fs::remove_file(&tmp);
+        return;
+    }
+    prune(&dir);
+}
+
+/// Removes all cached kernels
+pub fn clear() {
+    let _ = std::fs::remove_dir_all(&*CACHE_DIR.read());
+}
+
+fn prune(dir: &std::path::Path) {
+    let mut entries: Vec<_> = match std::fs::read_dir(dir) {
+        Ok(x) => x.filter_map(|x| x.ok())
+            .filter(|x| x.path().extension().map_or(false, |e| e == "bin"))
+            .filter_map(|x| Some((x.metadata().ok()?.modified().ok()?, x.path())))
+            .collect(),
+        Err(_) => { return; }
+    };
+    if entries.len() > MAX_ENTRIES {
+        entries.sort_by(|a, b| b.0.cmp(&a.0));
+        for (_, path) in entries.drain(MAX_ENTRIES..) {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
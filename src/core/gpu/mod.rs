@@ -4,6 +4,20 @@
 #[cfg(feature = "use-opencl")]
 pub mod opencl;
 pub mod wgpu;
+pub mod wgpu_zoom;
+#[cfg(all(any(target_os = "linux", target_os = "android"), feature = "use-vulkan"))]
+pub mod wgpu_vulkan;
+#[cfg(all(any(target_os = "macos", target_os = "ios"), feature = "use-metal"))]
+pub mod wgpu_metal;
+#[cfg(all(target_os = "windows", feature = "use-dx12"))]
+pub mod wgpu_dx12;
+#[cfg(all(any(target_os = "linux", target_os = "android", target_os = "windows", target_os = "macos"), feature = "use-opengl"))]
+pub mod wgpu_opengl;
+#[cfg(feature = "use-cuda")]
+pub mod cuda;
+pub mod downscale;
+pub mod context_manager;
+pub mod kernel_cache;
 
 pub struct BufferDescription<'a> {
     pub input_size:  (usize, usize, usize), // width, height, stride
@@ -26,29 +40,96 @@ pub enum BufferSource<'a> {
         output: ocl::ffi::cl_mem,
         queue: ocl::ffi::cl_command_queue
     },
-    /*OpenGL {
-        input: u32, // GLuint
-        output: u32, // GLuint
+    #[cfg(all(any(target_os = "linux", target_os = "android"), feature = "use-vulkan"))]
+    Vulkan {
+        input: wgpu_vulkan::VulkanImage,
+        output: wgpu_vulkan::VulkanImage,
+    },
+    #[cfg(all(any(target_os = "macos", target_os = "ios"), feature = "use-metal"))]
+    Metal {
+        input: *mut metal::MTLTexture,
+        output: *mut metal::MTLTexture,
     },
+    #[cfg(all(target_os = "windows", feature = "use-dx12"))]
     DirectX {
-        input: u32,
-        output: u32,
+        input: *mut std::ffi::c_void, // Shared NT handle of ID3D11Texture2D or ID3D12Resource, see `wgpu_dx12`
+        output: *mut std::ffi::c_void,
     },
-    Cuda {
-        input: u32,
-        output: u32,
+    #[cfg(all(any(target_os = "linux", target_os = "android", target_os = "windows", target_os = "macos"), feature = "use-opengl"))]
+    OpenGL {
+        input: u32, // GLuint, GL_TEXTURE_2D
+        output: u32, // GLuint
+        get_proc_address: &'a dyn Fn(&str) -> *const std::ffi::c_void, // GL function loader of the host context, see `wgpu_opengl`
     },
-    Metal {
-        input: u32,
-        output: u32,
+    #[cfg(feature = "use-cuda")]
+    Cuda {
+        input: u64, // CUdeviceptr
+        output: u64, // CUdeviceptr
+        context: *mut std::ffi::c_void, // CUcontext which owns both buffers, see `cuda`
+        stream: *mut std::ffi::c_void, // CUstream, can be null
     },
-    Vulkan {
-        input: u32,
-        output: u32,
-    }*/
 }
 
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+pub enum KernelPrecision {
+    #[default]
+    Auto = 0,
+    Fp32 = 1,
+    Fp16 = 2,
+}
+impl From<i32> for KernelPrecision {
+    fn from(v: i32) -> Self {
+        match v {
+            1 => Self::Fp32,
+            2 => Self::Fp16,
+            _ => Self::Auto
+        }
+    }
+}
+impl KernelPrecision {
+    /// Whether the backend can run the warp math in fp16. WGSL `f16` needs the `shader-f16` feature, which this wgpu version doesn't have
+    pub fn fp16_supported(backend: &str) -> bool {
+        backend == "OpenCL"
+    }
+    // Whether the warp math should run in fp16 on given device.
+    // Only 8-bit formats can use it, because 16-bit pixel values don't fit in the half float range (max 65504)
+    pub fn use_fp16(&self, device_name: &str, scalar_bytes: usize) -> bool {
+        if scalar_bytes != 1 { return false; }
+        match self {
+            Self::Fp32 => false,
+            Self::Fp16 => true,
+            Self::Auto => {
+                // Mobile and Apple GPUs have double rate fp16
+                let name = device_name.to_ascii_lowercase();
+                ["apple", "adreno", "mali", "powervr"].iter().any(|x| name.contains(x))
+            }
+        }
+    }
+}
+
+/// GPU processing backends compiled in and not disabled with the `NO_OPENCL`/`NO_WGPU` environment variables
+pub fn available_backends() -> Vec<&'static str> {
+    let mut ret = Vec::new();
+    #[cfg(feature = "use-opencl")]
+    if std::env::var("NO_OPENCL").unwrap_or_default().is_empty() {
+        ret.push("OpenCL");
+    }
+    if std::env::var("NO_WGPU").unwrap_or_default().is_empty() {
+        ret.push("wgpu");
+    }
+    ret
+}
+
+/// Initializes the default device of the first backend which works, returns its name and the name in the device list
 pub fn initialize_contexts() -> Option<(String, String)> {
+    let names = initialize_default_context();
+    if let Some((_, ref list_name)) = names {
+        context_manager::MANAGER.write().set_default_device(list_name.clone());
+    }
+    names
+}
+
+fn initialize_default_context() -> Option<(String, String)> {
     #[cfg(feature = "use-opencl")]
     if std::env::var("NO_OPENCL").unwrap_or_default().is_empty() {
         let cl = std::panic::catch_unwind(|| {
@@ -15,6 +15,8 @@ pub struct OclWrapper {
 
     buf_params: Buffer<u8>,
     buf_matrices: Buffer<f32>,
+
+    mip_level: u32, // > 0 when the frame is processed downscaled, see `downscale`
 }
 
 pub struct CtxWrapper {
@@ -28,6 +30,11 @@ lazy_static::lazy_static! {
 
 const EXCLUSIONS: &[&'static str] = &["Microsoft Basic Render Driver"];
 
+// Buffers which the OpenCL backend can't read from or write to, the next backend will be tried instead
+fn unsupported_buffer(kind: &str) -> ocl::Error {
+    ocl::Error::from(format!("{} are not supported by the OpenCL backend", kind))
+}
+
 impl OclWrapper {
     pub fn list_devices() -> Vec<String> {
         let devices = std::panic::catch_unwind(|| -> Vec<String> {
@@ -137,18 +144,14 @@ impl OclWrapper {
         Ok((name, list_name))
     }
 
-    pub fn new(params: &KernelParams, ocl_names: (&str, &str, &str, &str), lens_model_funcs: &str, buffers: &BufferDescription) -> ocl::Result<Self> {
-        if params.height < 4 || params.output_height < 4 || params.stride < 1 { return Err(ocl::BufferCmdError::AlreadyMapped.into()); }
+    // Device and context for other OpenCL kernels (eg. the calibration pattern search)
+    pub fn current_context() -> Option<(Device, Context)> {
+        if CONTEXT.read().is_none() { Self::initialize_context().ok()?; }
+        CONTEXT.read().as_ref().map(|x| (x.device, x.context.clone()))
+    }
 
-        let mut kernel = include_str!("opencl_undistort.cl").to_string();
-        kernel.insert_str(0, GoProSuperview::opencl_functions());
-        kernel.insert_str(0, lens_model_funcs);
-        kernel = kernel.replace("DATA_CONVERTF", ocl_names.3)
-                       .replace("DATA_TYPEF", ocl_names.2)
-                       .replace("DATA_CONVERT", ocl_names.1)
-                       .replace("DATA_TYPE", ocl_names.0)
-                       .replace("PIXEL_BYTES", &format!("{}", params.bytes_per_pixel))
-                       .replace("INTERPOLATION", &format!("{}", params.interpolation));
+    pub fn new(params: &KernelParams, ocl_names: (&str, &str, &str, &str), lens_model_funcs: &str, buffers: &BufferDescription, precision: KernelPrecision) -> ocl::Result<Self> {
+        if params.height < 4 || params.output_height < 4 || params.stride < 1 { return Err(ocl::BufferCmdError::AlreadyMapped.into()); }
 
         let context_initialized = CONTEXT.read().is_some();
         if !context_initialized { Self::initialize_context()?; }
@@ -157,20 +160,35 @@ impl OclWrapper {
             if ctx.device.name()?.to_ascii_lowercase().contains("core(tm)") {
                 return Err(ocl::BufferCmdError::AlreadyMapped.into());
             }
+            let max_allocation = match ctx.device.info(core::DeviceInfo::MaxMemAllocSize) {
+                Ok(core::DeviceInfoResult::MaxMemAllocSize(size)) => size as usize,
+                _ => usize::MAX
+            };
+            let limits = downscale::DeviceLimits { max_dimension: usize::MAX, max_allocation };
+            let mip_level = limits.mip_level(params).ok_or_else(|| ocl::Error::from("Frame is too large for the OpenCL device"))?;
+            let params = &downscale::scaled_params(params, mip_level);
+
             let mut ocl_queue = Queue::new(&ctx.context, ctx.device, None)?;
 
             let (source_buffer, dest_buffer) =
                 match &buffers.buffers {
                     BufferSource::Cpu { input, output } => {
+                        let (in_len, out_len) = if mip_level > 0 {
+                            ((params.stride * params.height) as usize, (params.output_stride * params.output_height) as usize)
+                        } else {
+                            (input.len(), output.len())
+                        };
                         (
-                            Buffer::builder().queue(ocl_queue.clone()).len(input.len())
+                            Buffer::builder().queue(ocl_queue.clone()).len(in_len)
                                 .flags(MemFlags::new().read_only().host_write_only()).build()?,
 
-                            Buffer::builder().queue(ocl_queue.clone()).len(output.len())
+                            Buffer::builder().queue(ocl_queue.clone()).len(out_len)
                                 .flags(MemFlags::new().write_only().host_read_only().alloc_host_ptr()).build()?
                         )
                     },
                     BufferSource::OpenCL { queue, .. } => {
+                        // Buffers owned by the host app can't be resampled on the CPU
+                        if mip_level > 0 { return Err(unsupported_buffer("Downscaled host OpenCL buffers")); }
                         if !queue.is_null() {
                             let queue_core = unsafe { core::CommandQueue::from_raw_copied_ptr(*queue) };
                             let device_core = queue_core.device()?;
@@ -186,13 +204,80 @@ impl OclWrapper {
                             Buffer::builder().queue(ocl_queue.clone()).len(buffers.input_size.1 * buffers.input_size.2).flags(MemFlags::new().read_only().host_write_only()).build()?,
                             Buffer::builder().queue(ocl_queue.clone()).len(buffers.output_size.1 * buffers.output_size.2).flags(MemFlags::new().write_only().host_read_only().alloc_host_ptr()).build()?
                         )
+                    },
+                    #[cfg(all(any(target_os = "linux", target_os = "android"), feature = "use-vulkan"))]
+                    BufferSource::Vulkan { .. } => {
+                        // Vulkan images are only supported by the wgpu backend
+                        return Err(unsupported_buffer("Vulkan images"));
+                    }
+                    #[cfg(all(any(target_os = "macos", target_os = "ios"), feature = "use-metal"))]
+                    BufferSource::Metal { .. } => {
+                        // Metal textures are only supported by the wgpu backend
+                        return Err(unsupported_buffer("Metal textures"));
+                    }
+                    #[cfg(all(target_os = "windows", feature = "use-dx12"))]
+                    BufferSource::DirectX { .. } => {
+                        // DirectX textures are only supported by the wgpu backend
+                        return Err(unsupported_buffer("DirectX textures"));
+                    }
+                    #[cfg(all(any(target_os = "linux", target_os = "android", target_os = "windows", target_os = "macos"), feature = "use-opengl"))]
+                    BufferSource::OpenGL { .. } => {
+                        // OpenGL textures are only supported by the wgpu backend
+                        return Err(unsupported_buffer("OpenGL textures"));
+                    }
+                    #[cfg(feature = "use-cuda")]
+                    BufferSource::Cuda { .. } => {
+                        // CUDA buffers are only supported by the CUDA backend
+                        return Err(unsupported_buffer("CUDA buffers"));
                     }
                 };
 
-            let program = Program::builder()
-                .src(&kernel)
-                .devices(ctx.device)
-                .build(&ctx.context)?;
+            let scalar_bytes = (params.bytes_per_pixel / params.pix_element_count.max(1)) as usize;
+            let mut fp16 = precision.use_fp16(&format!("{} {}", ctx.device.vendor()?, ctx.device.name()?), scalar_bytes);
+            if fp16 && !ctx.device.info(core::DeviceInfo::Extensions).map(|x| x.to_string().contains("cl_khr_fp16")).unwrap_or(false) {
+                log::warn!("OpenCL device doesn't support cl_khr_fp16, using fp32");
+                fp16 = false;
+            }
+            let (data_typef, data_convertf) = if fp16 {
+                (ocl_names.2.replace("float", "half"), ocl_names.3.replace("float", "half"))
+            } else {
+                (ocl_names.2.to_string(), ocl_names.3.to_string())
+            };
+
+            let mut kernel = include_str!("opencl_undistort.cl").to_string();
+            kernel.insert_str(0, GoProSuperview::opencl_functions());
+            kernel.insert_str(0, lens_model_funcs);
+            kernel = kernel.replace("DATA_CONVERTF", &data_convertf)
+                           .replace("DATA_TYPEF", &data_typef)
+                           .replace("DATA_CONVERT", ocl_names.1)
+                           .replace("DATA_TYPE", ocl_names.0)
+                           .replace("BG_TYPE", ocl_names.2)
+                           .replace("WARP_FP16", if fp16 { "1" } else { "0" })
+                           .replace("PIXEL_BYTES", &format!("{}", params.bytes_per_pixel))
+                           .replace("PIX_ELEMENT_COUNT", &format!("{}", params.pix_element_count))
+                           .replace("INTERPOLATION", &format!("{}", params.interpolation));
+
+            let device_id = format!("{} {} {}", ctx.device.vendor()?, ctx.device.name()?, ctx.device.info(core::DeviceInfo::DriverVersion).map(|x| x.to_string()).unwrap_or_default());
+            let cache_key = kernel_cache::key(&device_id, &kernel);
+            let cached = kernel_cache::load(&cache_key).and_then(|bin| {
+                Program::builder().binaries(&[&bin[..]]).devices(ctx.device).build(&ctx.context)
+                    .map_err(|e| log::warn!("Failed to load the cached OpenCL program: {:?}", e)).ok()
+            });
+            let program = match cached {
+                Some(program) => program,
+                None => {
+                    let program = Program::builder()
+                        .src(&kernel)
+                        .devices(ctx.device)
+                        .build(&ctx.context)?;
+                    if let Ok(core::ProgramInfoResult::Binaries(binaries)) = program.info(core::ProgramInfo::Binaries) {
+                        if let Some(binary) = binaries.first() {
+                            kernel_cache::store(&cache_key, binary);
+                        }
+                    }
+                    program
+                }
+            };
 
             let buf_params = Buffer::builder().queue(ocl_queue.clone()).len(std::mem::size_of::<KernelParams>())
                 .flags(MemFlags::new().read_only().host_write_only()).build()?;
@@ -219,13 +304,25 @@ impl OclWrapper {
                 dst: dest_buffer,
                 buf_params,
                 buf_matrices,
+                mip_level,
             })
         } else {
             Err(ocl::BufferCmdError::AlreadyMapped.into())
         }
     }
 
+    pub fn mip_level(&self) -> u32 { self.mip_level }
+
     pub fn undistort_image(&mut self, buffers: &mut BufferDescription, itm: &crate::stabilization::FrameTransform) -> ocl::Result<()> {
+        if self.mip_level > 0 {
+            let level = self.mip_level;
+            return downscale::process_downscaled(buffers, itm, level, |buffers, itm| self.undistort_frame(buffers, itm))
+                .unwrap_or_else(|| Err(ocl::BufferCmdError::MapUnavailable.into()));
+        }
+        self.undistort_frame(buffers, itm)
+    }
+
+    fn undistort_frame(&mut self, buffers: &mut BufferDescription, itm: &crate::stabilization::FrameTransform) -> ocl::Result<()> {
         let matrices = unsafe { std::slice::from_raw_parts(itm.matrices.as_ptr() as *const f32, itm.matrices.len() * 9 ) };
 
         match buffers.buffers {
@@ -242,6 +339,26 @@ impl OclWrapper {
                     self.kernel.set_arg_unchecked(1, core::ArgVal::from_raw(siz, &output as *const _ as *const std::ffi::c_void, true))?;
                 }
             }
+            #[cfg(all(any(target_os = "linux", target_os = "android"), feature = "use-vulkan"))]
+            BufferSource::Vulkan { .. } => {
+                return Err(unsupported_buffer("Vulkan images"));
+            }
+            #[cfg(all(any(target_os = "macos", target_os = "ios"), feature = "use-metal"))]
+            BufferSource::Metal { .. } => {
+                return Err(unsupported_buffer("Metal textures"));
+            }
+            #[cfg(all(target_os = "windows", feature = "use-dx12"))]
+            BufferSource::DirectX { .. } => {
+                return Err(unsupported_buffer("DirectX textures"));
+            }
+            #[cfg(all(any(target_os = "linux", target_os = "android", target_os = "windows", target_os = "macos"), feature = "use-opengl"))]
+            BufferSource::OpenGL { .. } => {
+                return Err(unsupported_buffer("OpenGL textures"));
+            }
+            #[cfg(feature = "use-cuda")]
+            BufferSource::Cuda { .. } => {
+                return Err(unsupported_buffer("CUDA buffers"));
+            }
         }
 
         if self.buf_matrices.len() < matrices.len() { log::error!("Buffer size mismatch! {} vs {}", self.buf_matrices.len(), matrices.len()); return Ok(()); }
@@ -11,6 +11,13 @@ enum {
 // #pragma OPENCL EXTENSION cl_khr_fp64:enable
 // #endif
 
+#if WARP_FP16
+#pragma OPENCL EXTENSION cl_khr_fp16 : enable
+typedef half WARP_FLOAT;
+#else
+typedef float WARP_FLOAT;
+#endif
+
 typedef struct {
     int width;         // 4
     int height;        // 8
@@ -35,16 +42,17 @@ typedef struct {
     float input_horizontal_stretch;  // 4
     float background_margin;         // 8
     float background_margin_feather; // 12
-    float reserved1;                 // 16
-    float reserved2;                 // 4
-    float reserved3;                 // 8
+    float output_projection_strength;// 16
+    float range_scale;               // 4
+    float range_offset;              // 8
     float2 translation2d;            // 16
     float4 translation3d;            // 16
+    float4 range_luma;               // 16
 } KernelParams;
 
 #if INTERPOLATION == 2 // Bilinear
 #define S_OFFSET 0.0f
-__constant float coeffs[64] = {
+__constant WARP_FLOAT coeffs[64] = {
     1.000000f, 0.000000f, 0.968750f, 0.031250f, 0.937500f, 0.062500f, 0.906250f, 0.093750f, 0.875000f, 0.125000f, 0.843750f, 0.156250f,
     0.812500f, 0.187500f, 0.781250f, 0.218750f, 0.750000f, 0.250000f, 0.718750f, 0.281250f, 0.687500f, 0.312500f, 0.656250f, 0.343750f,
     0.625000f, 0.375000f, 0.593750f, 0.406250f, 0.562500f, 0.437500f, 0.531250f, 0.468750f, 0.500000f, 0.500000f, 0.468750f, 0.531250f,
@@ -54,7 +62,7 @@ __constant float coeffs[64] = {
 };
 #elif INTERPOLATION == 4 // Bicubic
 #define S_OFFSET 1.0f
-__constant float coeffs[128] = {
+__constant WARP_FLOAT coeffs[128] = {
      0.000000f, 1.000000f, 0.000000f,  0.000000f, -0.021996f, 0.997841f, 0.024864f, -0.000710f, -0.041199f, 0.991516f, 0.052429f, -0.002747f,
     -0.057747f, 0.981255f, 0.082466f, -0.005974f, -0.071777f, 0.967285f, 0.114746f, -0.010254f, -0.083427f, 0.949837f, 0.149040f, -0.015450f,
     -0.092834f, 0.929138f, 0.185120f, -0.021423f, -0.100136f, 0.905418f, 0.222755f, -0.028038f, -0.105469f, 0.878906f, 0.261719f, -0.035156f,
@@ -69,7 +77,7 @@ __constant float coeffs[128] = {
 };
 #elif INTERPOLATION == 8 // Lanczos4
 #define S_OFFSET 3.0f
-__constant float coeffs[256] = {
+__constant WARP_FLOAT coeffs[256] = {
      0.000000f,  0.000000f,  0.000000f,  1.000000f,  0.000000f,  0.000000f,  0.000000f,  0.000000f, -0.002981f,  0.009625f, -0.027053f,  0.998265f,
      0.029187f, -0.010246f,  0.003264f, -0.000062f, -0.005661f,  0.018562f, -0.051889f,  0.993077f,  0.060407f, -0.021035f,  0.006789f, -0.000250f,
     -0.008027f,  0.026758f, -0.074449f,  0.984478f,  0.093543f, -0.032281f,  0.010545f, -0.000567f, -0.010071f,  0.034167f, -0.094690f,  0.972534f,
@@ -96,9 +104,18 @@ __constant float coeffs[256] = {
 #endif
 
 // From 0-255(JPEG/Full) to 16-235(MPEG/Limited)
-DATA_TYPEF remap_colorrange(DATA_TYPEF px, bool isY) {
-    if (isY) { return 16.0f + (px * 0.85882352f); } // (235 - 16) / 255
-    else     { return 16.0f + (px * 0.87843137f); } // (240 - 16) / 255
+// Full to limited range or back, the factors for the current plane are computed on the host
+// Packed RGB is scaled for the YUV encoding of the output, luma and chroma scale differently so the luma is mixed in
+DATA_TYPEF remap_colorrange(DATA_TYPEF px, __global KernelParams *params) {
+    DATA_TYPEF res = (WARP_FLOAT)params->range_offset + (px * (WARP_FLOAT)params->range_scale);
+#if PIX_ELEMENT_COUNT >= 3
+    WARP_FLOAT luma = (WARP_FLOAT)params->range_luma.x * ((WARP_FLOAT)params->range_luma.y * px.x + (WARP_FLOAT)params->range_luma.z * px.y + (WARP_FLOAT)params->range_luma.w * px.z);
+    res.x += luma;
+    res.y += luma;
+    res.z += luma;
+    res.w = px.w;
+#endif
+    return res;
 }
 
 DATA_TYPEF sample_input_at(float2 uv, __global const uchar *srcptr, __global KernelParams *params, DATA_TYPEF bg) {
@@ -114,22 +131,22 @@ DATA_TYPEF sample_input_at(float2 uv, __global const uchar *srcptr, __global Ker
     int sx = sx0 >> INTER_BITS;
     int sy = sy0 >> INTER_BITS;
 
-    __constant float *coeffs_x = &coeffs[(sx0 & (INTER_TAB_SIZE - 1)) << shift];
-    __constant float *coeffs_y = &coeffs[(sy0 & (INTER_TAB_SIZE - 1)) << shift];
+    __constant WARP_FLOAT *coeffs_x = &coeffs[(sx0 & (INTER_TAB_SIZE - 1)) << shift];
+    __constant WARP_FLOAT *coeffs_y = &coeffs[(sy0 & (INTER_TAB_SIZE - 1)) << shift];
 
-    DATA_TYPEF sum = 0;
+    DATA_TYPEF sum = (DATA_TYPEF)((WARP_FLOAT)0.0f);
     int src_index = sy * params->stride + sx * PIXEL_BYTES;
 
     #pragma unroll
     for (int yp = 0; yp < INTERPOLATION; ++yp) {
         if (sy + yp >= 0 && sy + yp < params->height) {
-            DATA_TYPEF xsum = 0.0f;
+            DATA_TYPEF xsum = (DATA_TYPEF)((WARP_FLOAT)0.0f);
             #pragma unroll
             for (int xp = 0; xp < INTERPOLATION; ++xp) {
                 if (sx + xp >= 0 && sx + xp < params->width) {
                     DATA_TYPEF srcpx = DATA_CONVERTF(*(__global const DATA_TYPE *)&srcptr[src_index + PIXEL_BYTES * xp]);
                     if (fix_range) {
-                        srcpx = remap_colorrange(srcpx, PIXEL_BYTES == 1);
+                        srcpx = remap_colorrange(srcpx, params);
                     }
                     xsum += srcpx * coeffs_x[xp];
                 } else {
@@ -181,12 +198,12 @@ __kernel void undistort_image(__global const uchar *srcptr, __global uchar *dstp
 
     __global KernelParams *params = (__global KernelParams *)params_buf;
 
-    DATA_TYPEF bg = *(__global DATA_TYPEF *)&params->background;
+    DATA_TYPEF bg = DATA_CONVERTF(*(__global BG_TYPE *)&params->background);
 
     if (matrices == 0 || params->width < 1) return;
 
     if (x >= 0 && y >= 0 && x < params->output_width && y < params->output_height) {
-        __global DATA_TYPE *out_pix = &dstptr[x * PIXEL_BYTES + y * params->output_stride];
+        __global DATA_TYPE *out_pix = (__global DATA_TYPE *)&dstptr[x * PIXEL_BYTES + y * params->output_stride];
 
         if (params->flags & 4) { // Fill with background
             *out_pix = DATA_CONVERT(bg);
@@ -195,6 +212,27 @@ __kernel void undistort_image(__global const uchar *srcptr, __global uchar *dstp
 
         float2 out_pos = (float2)(x, y) + params->translation2d;
 
+        ///////////////////////////////////////////////////////////////////
+        // Output projection (lens look)
+        if (params->output_projection_strength > 0.0f) {
+            float2 out_c = (float2)(params->output_width / 2.0f, params->output_height / 2.0f);
+            if (params->flags & 8) { // Fisheye, corners stay in place
+                float2 out_f = params->f / params->fov;
+                float2 pt = (out_pos - out_c) / out_f;
+                float r = length(pt);
+                float r_max = length(out_c / out_f);
+                if (r > 0.0f) {
+                    float r2 = tan(r / r_max * atan(r_max));
+                    out_pos = out_c + pt * (mix(r, r2, params->output_projection_strength) / r) * out_f;
+                }
+            } else if (params->flags & 16) { // Superview
+                float2 size = out_c * 2.0f;
+                float2 pt2 = (from_superview((out_pos / size) - 0.5f) + 0.5f) * size;
+                out_pos = mix(out_pos, pt2, params->output_projection_strength);
+            }
+        }
+        ///////////////////////////////////////////////////////////////////
+
         ///////////////////////////////////////////////////////////////////
         // Calculate source `y` for rolling shutter
         int sy = y;
@@ -210,7 +248,7 @@ __kernel void undistort_image(__global const uchar *srcptr, __global uchar *dstp
         ///////////////////////////////////////////////////////////////////
         // Add lens distortion back
         if (params->lens_correction_amount < 1.0) {
-            float2 factor = (float2)max(1.0f - params->lens_correction_amount, 0.001f); // FIXME: this is close but wrong
+            float2 factor = (float2)(max(1.0f - params->lens_correction_amount, 0.001f)); // FIXME: this is close but wrong
             float2 out_c = (float2)(params->output_width / 2.0f, params->output_height / 2.0f);
             float2 out_f = (params->f / params->fov) / factor;
 
@@ -227,7 +265,7 @@ __kernel void undistort_image(__global const uchar *srcptr, __global uchar *dstp
         }
         ///////////////////////////////////////////////////////////////////
 
-        int idx = min(sy, params->matrix_count - 1) * 9;
+        int idx = min(sy * params->matrix_count / max(params->height, 1), params->matrix_count - 1) * 9; // Matrices can cover more than one row
         float2 uv = rotate_and_distort(out_pos, idx, params, matrices);
         if (uv.x > -99998.0f) {
             switch (params->background_mode) {
@@ -260,7 +298,7 @@ __kernel void undistort_image(__global const uchar *srcptr, __global uchar *dstp
 
                     DATA_TYPEF c1 = sample_input_at(uv,  srcptr, params, bg);
                     DATA_TYPEF c2 = sample_input_at(pt2, srcptr, params, bg);
-                    *out_pix = DATA_CONVERT(c1 * alpha + c2 * (1.0f - alpha));
+                    *out_pix = DATA_CONVERT(c1 * (WARP_FLOAT)alpha + c2 * (WARP_FLOAT)(1.0f - alpha));
                     return;
                 } break;
             }
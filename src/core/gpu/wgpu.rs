@@ -6,14 +6,15 @@ use wgpu::Adapter;
 use wgpu::BufferUsages;
 use wgpu::util::DeviceExt;
 use parking_lot::RwLock;
-use crate::gpu:: { BufferDescription, BufferSource };
+use crate::gpu:: { BufferDescription, BufferSource, KernelPrecision, downscale };
 use crate::stabilization::KernelParams;
 use crate::stabilization::distortion_models::GoProSuperview;
 
 pub struct WgpuWrapper  {
     device: wgpu::Device,
     queue: wgpu::Queue,
-    staging_buffer: wgpu::Buffer,
+    staging_buffers: Vec<wgpu::Buffer>, // Readback of `chunk_rows` rows each, see `pipeline::PipelineConfig`
+    chunk_rows: u32,
     out_pixels: wgpu::Texture,
     in_pixels: wgpu::Texture,
     buf_matrices: wgpu::Buffer,
@@ -25,10 +26,20 @@ pub struct WgpuWrapper  {
     in_size: u64,
     out_size: u64,
     params_size: u64,
+    mip_level: u32, // > 0 when the frame is processed downscaled, see `downscale`
+
+    #[cfg(all(any(target_os = "linux", target_os = "android"), feature = "use-vulkan"))]
+    vulkan_images: super::wgpu_vulkan::ImageCache,
+    #[cfg(all(target_os = "windows", feature = "use-dx12"))]
+    shared_textures: super::wgpu_dx12::SharedTextureCache,
+    #[cfg(any(all(any(target_os = "linux", target_os = "android"), feature = "use-vulkan"), all(any(target_os = "macos", target_os = "ios"), feature = "use-metal"), all(target_os = "windows", feature = "use-dx12"), all(any(target_os = "linux", target_os = "android", target_os = "windows", target_os = "macos"), feature = "use-opengl")))]
+    format: wgpu::TextureFormat,
+    #[cfg(all(any(target_os = "linux", target_os = "android"), feature = "use-vulkan"))]
+    queue_family_index: u32,
 }
 
 lazy_static::lazy_static! {
-    static ref ADAPTER: RwLock<Option<Adapter>> = RwLock::new(None);
+    pub(crate) static ref ADAPTER: RwLock<Option<Adapter>> = RwLock::new(None);
 }
 
 impl WgpuWrapper {
@@ -78,28 +89,69 @@ impl WgpuWrapper {
         Some((name, list_name))
     }
 
-    pub fn new(params: &KernelParams, wgpu_format: (wgpu::TextureFormat, &str, f64), lens_model_funcs: &str, _buffers: &BufferDescription) -> Option<Self> {
-        let max_matrix_count = 9 * params.height as usize;
-
-        if params.height < 4 || params.output_height < 4 || params.stride < 1 || params.width > 8192 || params.output_width > 8192 { return None; }
-
-        let in_size = (params.stride * params.height) as wgpu::BufferAddress;
-        let out_size = (params.output_stride * params.output_height) as wgpu::BufferAddress;
-        let params_size = (max_matrix_count * std::mem::size_of::<f32>()) as wgpu::BufferAddress;
+    pub fn new(params: &KernelParams, wgpu_format: (wgpu::TextureFormat, &str, f64), lens_model_funcs: &str, _buffers: &BufferDescription, precision: KernelPrecision) -> Option<Self> {
+        if params.height < 4 || params.output_height < 4 || params.stride < 1 { return None; }
 
         let adapter_initialized = ADAPTER.read().is_some();
         if !adapter_initialized { Self::initialize_context(); }
+        // OpenGL textures are only valid on the context of the host app, so the device is created on it instead of the selected adapter
+        #[cfg(all(any(target_os = "linux", target_os = "android", target_os = "windows", target_os = "macos"), feature = "use-opengl"))]
+        let gl_adapter = if let BufferSource::OpenGL { get_proc_address, .. } = _buffers.buffers {
+            Some(super::wgpu_opengl::adapter_from_current_context(get_proc_address)?)
+        } else {
+            None
+        };
         let lock = ADAPTER.read();
-        if let Some(ref adapter) = *lock {
-            let (device, queue) = pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor {
+        #[cfg(all(any(target_os = "linux", target_os = "android", target_os = "windows", target_os = "macos"), feature = "use-opengl"))]
+        let adapter = gl_adapter.as_ref().or(lock.as_ref());
+        #[cfg(not(all(any(target_os = "linux", target_os = "android", target_os = "windows", target_os = "macos"), feature = "use-opengl")))]
+        let adapter = lock.as_ref();
+        // DirectX shared handles can only be opened on a D3D12 device, so the DX12 adapter of the same GPU is used if the selected one isn't
+        #[cfg(all(target_os = "windows", feature = "use-dx12"))]
+        let dx12_adapter = match (&_buffers.buffers, adapter) {
+            (BufferSource::DirectX { .. }, Some(x)) if x.get_info().backend != wgpu::Backend::Dx12 => Some(super::wgpu_dx12::dx12_adapter_for(x)?),
+            _ => None
+        };
+        #[cfg(all(target_os = "windows", feature = "use-dx12"))]
+        let adapter = dx12_adapter.as_ref().or(adapter);
+        if let Some(adapter) = adapter {
+            let adapter_limits = adapter.limits();
+            let mip_level = downscale::DeviceLimits {
+                max_dimension: adapter_limits.max_texture_dimension_2d as usize,
+                max_allocation: adapter_limits.max_buffer_size as usize
+            }.mip_level(params)?;
+            let params = &downscale::scaled_params(params, mip_level);
+
+            let max_matrix_count = 9 * params.height as usize;
+            let in_size = (params.stride * params.height) as wgpu::BufferAddress;
+            let out_size = (params.output_stride * params.output_height) as wgpu::BufferAddress;
+            let params_size = (max_matrix_count * std::mem::size_of::<f32>()) as wgpu::BufferAddress;
+
+            if precision == KernelPrecision::Fp16 && !KernelPrecision::fp16_supported("wgpu") {
+                log::warn!("fp16 is not supported by the wgpu backend, using fp32");
+            }
+
+            let device_desc = wgpu::DeviceDescriptor {
                 label: None,
                 features: wgpu::Features::empty(),
                 limits: wgpu::Limits {
                     max_storage_buffers_per_shader_stage: 4,
                     max_storage_textures_per_shader_stage: 4,
+                    max_texture_dimension_2d: adapter_limits.max_texture_dimension_2d,
+                    max_buffer_size: adapter_limits.max_buffer_size,
                     ..wgpu::Limits::default()
                 },
-            }, None)).ok()?;
+            };
+            // Vulkan images from the host app need a device with the external memory extensions
+            #[cfg(all(any(target_os = "linux", target_os = "android"), feature = "use-vulkan"))]
+            let (device, queue, queue_family_index) = if let BufferSource::Vulkan { .. } = _buffers.buffers {
+                super::wgpu_vulkan::request_device(adapter, &device_desc)?
+            } else {
+                let (device, queue) = pollster::block_on(adapter.request_device(&device_desc, None)).ok()?;
+                (device, queue, 0)
+            };
+            #[cfg(not(all(any(target_os = "linux", target_os = "android"), feature = "use-vulkan")))]
+            let (device, queue) = pollster::block_on(adapter.request_device(&device_desc, None)).ok()?;
 
             let mut shader_str = include_str!("wgpu_undistort.wgsl").to_string();
             shader_str.insert_str(0, GoProSuperview::wgsl_functions());
@@ -117,9 +169,14 @@ impl WgpuWrapper {
             let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as i32;
             let padding = (align - params.output_stride % align) % align;
             let padded_out_stride = params.output_stride + padding;
-            let staging_size = padded_out_stride * params.output_height;
-
-            let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor { size: staging_size as u64, usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST, label: None, mapped_at_creation: false });
+            let pipeline = crate::pipeline::config();
+            let chunk_rows = if pipeline.chunk_rows > 0 { (pipeline.chunk_rows as i32).min(params.output_height) } else { params.output_height }.max(1);
+            let staging_count = if chunk_rows < params.output_height { pipeline.staging_buffers() } else { 1 };
+            let staging_size = padded_out_stride * chunk_rows;
+
+            let staging_buffers = (0..staging_count).map(|_| {
+                device.create_buffer(&wgpu::BufferDescriptor { size: staging_size as u64, usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST, label: None, mapped_at_creation: false })
+            }).collect();
             let buf_matrices  = device.create_buffer(&wgpu::BufferDescriptor { size: params_size, usage: BufferUsages::STORAGE | BufferUsages::COPY_DST, label: None, mapped_at_creation: false });
             let buf_params = device.create_buffer(&wgpu::BufferDescriptor { size: std::mem::size_of::<KernelParams>() as u64, usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST, label: None, mapped_at_creation: false });
             let buf_coeffs  = device.create_buffer_init(&wgpu::util::BufferInitDescriptor { label: None, contents: bytemuck::cast_slice(&crate::stabilization::COEFFS), usage: wgpu::BufferUsages::STORAGE });
@@ -186,7 +243,8 @@ impl WgpuWrapper {
             Some(Self {
                 device,
                 queue,
-                staging_buffer,
+                staging_buffers,
+                chunk_rows: chunk_rows as u32,
                 out_pixels,
                 in_pixels,
                 buf_matrices,
@@ -196,15 +254,48 @@ impl WgpuWrapper {
                 in_size,
                 out_size,
                 params_size,
-                padded_out_stride: padded_out_stride as u32
+                mip_level,
+                padded_out_stride: padded_out_stride as u32,
+                #[cfg(all(any(target_os = "linux", target_os = "android"), feature = "use-vulkan"))]
+                vulkan_images: Default::default(),
+                #[cfg(all(target_os = "windows", feature = "use-dx12"))]
+                shared_textures: Default::default(),
+                #[cfg(any(all(any(target_os = "linux", target_os = "android"), feature = "use-vulkan"), all(any(target_os = "macos", target_os = "ios"), feature = "use-metal"), all(target_os = "windows", feature = "use-dx12"), all(any(target_os = "linux", target_os = "android", target_os = "windows", target_os = "macos"), feature = "use-opengl")))]
+                format: wgpu_format.0,
+                #[cfg(all(any(target_os = "linux", target_os = "android"), feature = "use-vulkan"))]
+                queue_family_index,
             })
         } else {
             None
         }
     }
 
+    pub fn mip_level(&self) -> u32 { self.mip_level }
+
     pub fn undistort_image(&mut self, buffers: &mut BufferDescription, itm: &crate::stabilization::FrameTransform) -> bool {
+        if self.mip_level > 0 {
+            let level = self.mip_level;
+            return downscale::process_downscaled(buffers, itm, level, |buffers, itm| self.undistort_frame(buffers, itm)).unwrap_or(false);
+        }
+        self.undistort_frame(buffers, itm)
+    }
+
+    /// Handles of the VkDevice used for processing, so the decoder can create its Vulkan context on it and pass the images without importing
+    #[cfg(all(any(target_os = "linux", target_os = "android"), feature = "use-vulkan"))]
+    pub fn vulkan_device_handles(&self) -> Option<super::wgpu_vulkan::DeviceHandles> {
+        super::wgpu_vulkan::device_handles(&self.device)
+    }
+
+    fn submit(&self, command_buffer: wgpu::CommandBuffer) -> wgpu::SubmissionIndex {
+        // The Vulkan queue is shared with the ownership transfers of the imported images, see `wgpu_vulkan::queue_lock`
+        #[cfg(all(any(target_os = "linux", target_os = "android"), feature = "use-vulkan"))]
+        let _lock = super::wgpu_vulkan::queue_lock();
+        self.queue.submit(Some(command_buffer))
+    }
+
+    fn undistort_frame(&mut self, buffers: &mut BufferDescription, itm: &crate::stabilization::FrameTransform) -> bool {
         let matrices = bytemuck::cast_slice(&itm.matrices);
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
 
         match &buffers.buffers {
             BufferSource::Cpu { input, output } => {
@@ -226,9 +317,51 @@ impl WgpuWrapper {
                     },
                 );
             },
+            #[cfg(feature = "use-opencl")]
             BufferSource::OpenCL { .. } => {
                 return false;
             }
+            #[cfg(feature = "use-cuda")]
+            BufferSource::Cuda { .. } => {
+                return false;
+            }
+            #[cfg(all(any(target_os = "linux", target_os = "android"), feature = "use-vulkan"))]
+            BufferSource::Vulkan { input, .. } => {
+                let size = (itm.kernel_params.width as u32, itm.kernel_params.height as u32);
+                let format = self.format;
+                if let Some(input) = self.vulkan_images.get(&self.device, self.queue_family_index, input, size, format, wgpu::TextureUsages::COPY_SRC) {
+                    encoder.copy_texture_to_texture(input.as_image_copy(), self.in_pixels.as_image_copy(), wgpu::Extent3d { width: size.0, height: size.1, depth_or_array_layers: 1 });
+                } else {
+                    return false;
+                }
+            }
+            #[cfg(all(any(target_os = "macos", target_os = "ios"), feature = "use-metal"))]
+            BufferSource::Metal { input, .. } => {
+                let size = (itm.kernel_params.width as u32, itm.kernel_params.height as u32);
+                if let Some(input) = super::wgpu_metal::texture_from_raw(&self.device, *input, size, self.format, wgpu::TextureUsages::COPY_SRC) {
+                    encoder.copy_texture_to_texture(input.as_image_copy(), self.in_pixels.as_image_copy(), wgpu::Extent3d { width: size.0, height: size.1, depth_or_array_layers: 1 });
+                } else {
+                    return false;
+                }
+            }
+            #[cfg(all(target_os = "windows", feature = "use-dx12"))]
+            BufferSource::DirectX { input, .. } => {
+                let size = (itm.kernel_params.width as u32, itm.kernel_params.height as u32);
+                if let Some(input) = self.shared_textures.get(&self.device, *input, size, self.format, wgpu::TextureUsages::COPY_SRC) {
+                    encoder.copy_texture_to_texture(input.as_image_copy(), self.in_pixels.as_image_copy(), wgpu::Extent3d { width: size.0, height: size.1, depth_or_array_layers: 1 });
+                } else {
+                    return false;
+                }
+            }
+            #[cfg(all(any(target_os = "linux", target_os = "android", target_os = "windows", target_os = "macos"), feature = "use-opengl"))]
+            BufferSource::OpenGL { input, .. } => {
+                let size = (itm.kernel_params.width as u32, itm.kernel_params.height as u32);
+                if let Some(input) = super::wgpu_opengl::texture_from_raw(&self.device, *input, size, self.format, wgpu::TextureUsages::COPY_SRC) {
+                    encoder.copy_texture_to_texture(input.as_image_copy(), self.in_pixels.as_image_copy(), wgpu::Extent3d { width: size.0, height: size.1, depth_or_array_layers: 1 });
+                } else {
+                    return false;
+                }
+            }
         }
 
         if self.params_size < matrices.len() as u64    { log::error!("Buffer size mismatch! {} vs {}", self.params_size, matrices.len()); return false; }
@@ -236,7 +369,6 @@ impl WgpuWrapper {
         self.queue.write_buffer(&self.buf_matrices, 0, matrices);
         self.queue.write_buffer(&self.buf_params, 0, bytemuck::bytes_of(&itm.kernel_params));
 
-        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
         let view = self.out_pixels.create_view(&wgpu::TextureViewDescriptor::default());
         {
             let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
@@ -256,66 +388,165 @@ impl WgpuWrapper {
             rpass.draw(0..6, 0..1);
         }
 
-        if let BufferSource::Cpu { .. } = buffers.buffers {
-            encoder.copy_texture_to_buffer(wgpu::ImageCopyTexture {
-                texture: &self.out_pixels,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All,
-            }, wgpu::ImageCopyBuffer {
-                buffer: &self.staging_buffer,
-                layout: wgpu::ImageDataLayout {
-                    offset: 0,
-                    bytes_per_row: std::num::NonZeroU32::new(self.padded_out_stride),
-                    rows_per_image: None,
-                },
-            }, wgpu::Extent3d {
-                width: itm.kernel_params.output_width as u32,
-                height: itm.kernel_params.output_height as u32,
-                depth_or_array_layers: 1,
-            });
+        #[cfg(all(any(target_os = "linux", target_os = "android"), feature = "use-vulkan"))]
+        if let BufferSource::Vulkan { output, .. } = &buffers.buffers {
+            let size = (itm.kernel_params.output_width as u32, itm.kernel_params.output_height as u32);
+            let format = self.format;
+            if let Some(output) = self.vulkan_images.get(&self.device, self.queue_family_index, output, size, format, wgpu::TextureUsages::COPY_DST) {
+                encoder.copy_texture_to_texture(self.out_pixels.as_image_copy(), output.as_image_copy(), wgpu::Extent3d { width: size.0, height: size.1, depth_or_array_layers: 1 });
+            } else {
+                return false;
+            }
+        }
+        #[cfg(all(any(target_os = "macos", target_os = "ios"), feature = "use-metal"))]
+        if let BufferSource::Metal { output, .. } = &buffers.buffers {
+            let size = (itm.kernel_params.output_width as u32, itm.kernel_params.output_height as u32);
+            if let Some(output) = super::wgpu_metal::texture_from_raw(&self.device, *output, size, self.format, wgpu::TextureUsages::COPY_DST) {
+                encoder.copy_texture_to_texture(self.out_pixels.as_image_copy(), output.as_image_copy(), wgpu::Extent3d { width: size.0, height: size.1, depth_or_array_layers: 1 });
+            } else {
+                return false;
+            }
+        }
+        #[cfg(all(target_os = "windows", feature = "use-dx12"))]
+        if let BufferSource::DirectX { output, .. } = &buffers.buffers {
+            let size = (itm.kernel_params.output_width as u32, itm.kernel_params.output_height as u32);
+            if let Some(output) = self.shared_textures.get(&self.device, *output, size, self.format, wgpu::TextureUsages::COPY_DST) {
+                encoder.copy_texture_to_texture(self.out_pixels.as_image_copy(), output.as_image_copy(), wgpu::Extent3d { width: size.0, height: size.1, depth_or_array_layers: 1 });
+            } else {
+                return false;
+            }
+        }
+        #[cfg(all(any(target_os = "linux", target_os = "android", target_os = "windows", target_os = "macos"), feature = "use-opengl"))]
+        if let BufferSource::OpenGL { output, .. } = &buffers.buffers {
+            let size = (itm.kernel_params.output_width as u32, itm.kernel_params.output_height as u32);
+            if let Some(output) = super::wgpu_opengl::texture_from_raw(&self.device, *output, size, self.format, wgpu::TextureUsages::COPY_DST) {
+                encoder.copy_texture_to_texture(self.out_pixels.as_image_copy(), output.as_image_copy(), wgpu::Extent3d { width: size.0, height: size.1, depth_or_array_layers: 1 });
+            } else {
+                return false;
+            }
         }
 
-        self.queue.submit(Some(encoder.finish()));
+        let _submission = self.submit(encoder.finish());
 
-        if let BufferSource::Cpu { output, .. } = &mut buffers.buffers {
-            let buffer_slice = self.staging_buffer.slice(..);
-            let (sender, receiver) = futures_intrusive::channel::shared::oneshot_channel();
-            buffer_slice.map_async(wgpu::MapMode::Read, move |v| sender.send(v).unwrap());
+        // Textures of the host app are used by it right after this returns, on its own queue or context which isn't synchronized with ours
+        #[cfg(any(all(any(target_os = "macos", target_os = "ios"), feature = "use-metal"), all(target_os = "windows", feature = "use-dx12"), all(any(target_os = "linux", target_os = "android", target_os = "windows", target_os = "macos"), feature = "use-opengl")))]
+        {
+            let is_host_texture = match &buffers.buffers {
+                #[cfg(all(any(target_os = "macos", target_os = "ios"), feature = "use-metal"))]
+                BufferSource::Metal { .. } => true,
+                #[cfg(all(target_os = "windows", feature = "use-dx12"))]
+                BufferSource::DirectX { .. } => true,
+                #[cfg(all(any(target_os = "linux", target_os = "android", target_os = "windows", target_os = "macos"), feature = "use-opengl"))]
+                BufferSource::OpenGL { .. } => true,
+                _ => false
+            };
+            if is_host_texture {
+                self.device.poll(wgpu::Maintain::WaitForSubmissionIndex(_submission));
+            }
+        }
 
-            self.device.poll(wgpu::Maintain::Wait);
+        // DMA-BUFs are read by another API (eg. VAAPI encoder) right after this returns, so the copy has to be finished
+        #[cfg(all(any(target_os = "linux", target_os = "android"), feature = "use-vulkan"))]
+        if let BufferSource::Vulkan { input, output } = &buffers.buffers {
+            if output.dma_buf { self.device.poll(wgpu::Maintain::Wait); }
+            // Both go back to their external queue family, the release is ordered after the processing on the same queue
+            if !self.vulkan_images.release(&self.device, self.queue_family_index, input) || !self.vulkan_images.release(&self.device, self.queue_family_index, output) {
+                return false;
+            }
+        }
 
-            if let Some(Ok(())) = pollster::block_on(receiver.receive()) {
-                let data = buffer_slice.get_mapped_range();
-                if self.padded_out_stride == itm.kernel_params.output_stride as u32 {
+        if let BufferSource::Cpu { output, .. } = &mut buffers.buffers {
+            return self.read_output(output, itm.kernel_params.output_width as u32, itm.kernel_params.output_height as u32, itm.kernel_params.output_stride as usize);
+        }
+        true
+    }
+
+    // Reads the output texture in chunks of `chunk_rows`. Every chunk is copied to the next staging buffer in its own submission,
+    // so with more staging buffers the GPU copies the next chunks while the previous ones are copied to `output`
+    fn read_output(&self, output: &mut [u8], width: u32, height: u32, stride: usize) -> bool {
+        let padded_stride = self.padded_out_stride as usize;
+        let mut pending = std::collections::VecDeque::new();
+        let mut ok = true;
+
+        let mut read_chunk = |(index, y, rows, submission, receiver): (usize, u32, u32, wgpu::SubmissionIndex, std::sync::mpsc::Receiver<Result<(), wgpu::BufferAsyncError>>)| {
+            let time = std::time::Instant::now();
+            // Waits only for the copy of this chunk, the later ones stay in flight
+            self.device.poll(wgpu::Maintain::WaitForSubmissionIndex(submission));
+            let result = receiver.try_recv().unwrap_or_else(|_| {
+                self.device.poll(wgpu::Maintain::Wait);
+                receiver.recv().unwrap_or(Err(wgpu::BufferAsyncError))
+            });
+            let buffer = &self.staging_buffers[index];
+            if result.is_err() {
+                // TODO change to Result
+                log::error!("failed to run compute on wgpu!");
+                ok = false;
+                return;
+            }
+            let bytes = rows as usize * stride;
+            let dest = &mut output[y as usize * stride..y as usize * stride + bytes];
+            {
+                let data = buffer.slice(..).get_mapped_range();
+                if padded_stride == stride {
                     // Fast path
-                    output.copy_from_slice(data.as_ref());
+                    dest.copy_from_slice(&data[..bytes]);
                 } else {
-                    // data.as_ref()
-                    //     .chunks(self.padded_out_stride as usize)
-                    //     .zip(output.chunks_mut(itm.kernel_params.output_stride as usize))
-                    //     .for_each(|(src, dest)| {
-                    //         dest.copy_from_slice(&src[0..itm.kernel_params.output_stride as usize]);
-                    //     });
                     use rayon::prelude::{ ParallelSliceMut, ParallelSlice };
                     use rayon::iter::{ ParallelIterator, IndexedParallelIterator };
-                    data.as_ref()
-                        .par_chunks(self.padded_out_stride as usize)
-                        .zip(output.par_chunks_mut(itm.kernel_params.output_stride as usize))
+                    data[..rows as usize * padded_stride]
+                        .par_chunks(padded_stride)
+                        .zip(dest.par_chunks_mut(stride))
                         .for_each(|(src, dest)| {
-                            dest.copy_from_slice(&src[0..itm.kernel_params.output_stride as usize]);
+                            dest.copy_from_slice(&src[0..stride]);
                         });
                 }
-
                 // We have to make sure all mapped views are dropped before we unmap the buffer.
-                drop(data);
-                self.staging_buffer.unmap();
-            } else {
-                // TODO change to Result
-                log::error!("failed to run compute on wgpu!");
-                return false;
             }
+            buffer.unmap();
+            crate::pipeline::record(crate::pipeline::Stage::Readback, time.elapsed(), bytes);
+        };
+
+        for (i, y) in (0..height).step_by(self.chunk_rows as usize).enumerate() {
+            let index = i % self.staging_buffers.len();
+            if pending.len() == self.staging_buffers.len() {
+                read_chunk(pending.pop_front().unwrap());
+            }
+            let rows = self.chunk_rows.min(height - y);
+            let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+            encoder.copy_texture_to_buffer(wgpu::ImageCopyTexture {
+                texture: &self.out_pixels,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x: 0, y, z: 0 },
+                aspect: wgpu::TextureAspect::All,
+            }, wgpu::ImageCopyBuffer {
+                buffer: &self.staging_buffers[index],
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(self.padded_out_stride),
+                    rows_per_image: None,
+                },
+            }, wgpu::Extent3d {
+                width,
+                height: rows,
+                depth_or_array_layers: 1,
+            });
+            let submission = self.submit(encoder.finish());
+
+            let (sender, receiver) = std::sync::mpsc::channel();
+            self.staging_buffers[index].slice(..).map_async(wgpu::MapMode::Read, move |v| { let _ = sender.send(v); });
+            pending.push_back((index, y, rows, submission, receiver));
         }
-        true
+        while let Some(chunk) = pending.pop_front() {
+            read_chunk(chunk);
+        }
+        ok
+    }
+}
+
+// The ownership transfer commands of the image cache are created on the raw device, so they are destroyed before it
+#[cfg(all(any(target_os = "linux", target_os = "android"), feature = "use-vulkan"))]
+impl Drop for WgpuWrapper {
+    fn drop(&mut self) {
+        self.vulkan_images = Default::default();
     }
 }
@@ -0,0 +1,82 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2022 Adrian <adrian.eddy at gmail>
+
+// Zero-copy DirectX texture input and output for the wgpu backend on Windows.
+// Textures are passed as shared NT handles (ID3D11Texture2D created with D3D11_RESOURCE_MISC_SHARED_NTHANDLE, or ID3D12Resource with
+// D3D12_HEAP_FLAG_SHARED) and opened on the D3D12 device of wgpu, so it runs on the DX12 adapter of the selected GPU (see `dx12_adapter_for`).
+// The producer has to finish writing to the texture (flush or signal a fence) before passing it.
+
+use std::collections::HashMap;
+use winapi::Interface;
+use winapi::shared::winerror::SUCCEEDED;
+use winapi::um::d3d12::ID3D12Resource;
+use wgpu_hal::api::Dx12;
+
+// Capture tools and players reuse a small pool of textures
+const MAX_CACHED_TEXTURES: usize = 32;
+
+/// DX12 adapter on the same GPU as `adapter`, the shared handles can only be opened on a D3D12 device.
+/// None if there is no such adapter, then the frames are processed with the other buffer types
+pub fn dx12_adapter_for(adapter: &wgpu::Adapter) -> Option<wgpu::Adapter> {
+    let info = adapter.get_info();
+    let instance = wgpu::Instance::new(wgpu::Backends::DX12);
+    let found = instance.enumerate_adapters(wgpu::Backends::DX12).find(|x| x.get_info().name == info.name && x.get_info().device == info.device);
+    if found.is_none() {
+        log::error!("DirectX textures require a DX12 adapter, but {} has none", info.name);
+    }
+    found
+}
+
+// Handle, size and format, the host can reuse a handle value for another texture
+type TextureKey = (usize, (u32, u32), wgpu::TextureFormat);
+
+#[derive(Default)]
+pub struct SharedTextureCache {
+    textures: HashMap<TextureKey, wgpu::Texture>,
+}
+
+impl SharedTextureCache {
+    /// Texture for the shared handle, opening it the first time it's seen
+    pub fn get(&mut self, device: &wgpu::Device, handle: *mut std::ffi::c_void, size: (u32, u32), format: wgpu::TextureFormat, usage: wgpu::TextureUsages) -> Option<&wgpu::Texture> {
+        if handle.is_null() { return None; }
+        let key = (handle as usize, size, format);
+        if !self.textures.contains_key(&key) {
+            if self.textures.len() >= MAX_CACHED_TEXTURES {
+                self.textures.clear();
+            }
+            let texture = Self::open(device, handle, size, format, usage)?;
+            self.textures.insert(key, texture);
+        }
+        self.textures.get(&key)
+    }
+
+    fn open(device: &wgpu::Device, handle: *mut std::ffi::c_void, size: (u32, u32), format: wgpu::TextureFormat, usage: wgpu::TextureUsages) -> Option<wgpu::Texture> {
+        let size = wgpu::Extent3d { width: size.0, height: size.1, depth_or_array_layers: 1 };
+        let hal_texture = unsafe {
+            device.as_hal::<Dx12, _, _>(|hal_device| {
+                let hal_device = match hal_device {
+                    Some(x) => x,
+                    None => { log::error!("DirectX textures require the DX12 wgpu backend"); return None; }
+                };
+                let mut resource = std::ptr::null_mut::<ID3D12Resource>();
+                let hr = hal_device.raw_device().OpenSharedHandle(handle, &ID3D12Resource::uuidof(), &mut resource as *mut *mut _ as *mut *mut _);
+                if !SUCCEEDED(hr) || resource.is_null() {
+                    log::error!("Failed to open the shared DirectX handle {:?}: {:#x}", handle, hr);
+                    return None;
+                }
+                let resource = d3d12::Resource::from_raw(resource);
+                Some(wgpu_hal::dx12::Device::texture_from_raw(resource, format, wgpu::TextureDimension::D2, size, 1, 1))
+            })?
+        };
+
+        Some(unsafe { device.create_texture_from_hal::<Dx12>(hal_texture, &wgpu::TextureDescriptor {
+            label: None,
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage,
+        }) })
+    }
+}
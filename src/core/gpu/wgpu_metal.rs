@@ -0,0 +1,57 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2022 Adrian <adrian.eddy at gmail>
+
+// Zero-copy Metal texture input and output for the wgpu backend on macOS and iOS.
+// Textures from VideoToolbox (through CVMetalTextureCache) are wrapped as wgpu textures without any copy to the CPU.
+// There's only one GPU on Apple Silicon, so the textures can be used on the wgpu device directly.
+
+use wgpu_hal::api::Metal;
+
+/// Wraps the MTLTexture, which is retained for the lifetime of the returned texture
+pub fn texture_from_raw(device: &wgpu::Device, texture: *mut metal::MTLTexture, size: (u32, u32), format: wgpu::TextureFormat, usage: wgpu::TextureUsages) -> Option<wgpu::Texture> {
+    if texture.is_null() { return None; }
+    let raw = unsafe { metal::TextureRef::from_ptr(texture) }.to_owned();
+    if (raw.width() as u32, raw.height() as u32) != size {
+        log::error!("Metal texture size mismatch! {}x{} vs {}x{}", raw.width(), raw.height(), size.0, size.1);
+        return None;
+    }
+
+    let raw_format = raw.pixel_format();
+    if Some(raw_format) != metal_format(format) {
+        log::error!("Metal texture format mismatch! {:?} vs {:?}", raw_format, format);
+        return None;
+    }
+    let hal_texture = unsafe {
+        wgpu_hal::metal::Device::texture_from_raw(raw, raw_format, metal::MTLTextureType::D2, 1, 1, wgpu_hal::CopyExtent {
+            width: size.0,
+            height: size.1,
+            depth: 1,
+        })
+    };
+
+    Some(unsafe { device.create_texture_from_hal::<Metal>(hal_texture, &wgpu::TextureDescriptor {
+        label: None,
+        size: wgpu::Extent3d { width: size.0, height: size.1, depth_or_array_layers: 1 },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage,
+    }) })
+}
+
+// Pixel formats of the textures which can be processed, see `PixelType::wgpu_format`
+fn metal_format(format: wgpu::TextureFormat) -> Option<metal::MTLPixelFormat> {
+    use metal::MTLPixelFormat;
+    match format {
+        wgpu::TextureFormat::R8Unorm     => Some(MTLPixelFormat::R8Unorm),
+        wgpu::TextureFormat::Rg8Unorm    => Some(MTLPixelFormat::RG8Unorm),
+        wgpu::TextureFormat::Rgba8Unorm  => Some(MTLPixelFormat::RGBA8Unorm),
+        wgpu::TextureFormat::R16Uint     => Some(MTLPixelFormat::R16Uint),
+        wgpu::TextureFormat::Rg16Uint    => Some(MTLPixelFormat::RG16Uint),
+        wgpu::TextureFormat::Rgba16Uint  => Some(MTLPixelFormat::RGBA16Uint),
+        wgpu::TextureFormat::R32Float    => Some(MTLPixelFormat::R32Float),
+        wgpu::TextureFormat::Rgba32Float => Some(MTLPixelFormat::RGBA32Float),
+        _ => None
+    }
+}
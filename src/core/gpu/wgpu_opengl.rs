@@ -0,0 +1,64 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2022 Adrian <adrian.eddy at gmail>
+
+// Zero-copy OpenGL texture input and output for the wgpu backend. On Windows and macOS wgpu is built with its ANGLE support for the GL backend.
+// wgpu runs directly on the GL context of the host app, which has to be current on the calling thread, so the texture names are valid as they are.
+// Hosts rendering on another context have to create it in the same share group, and finish writing to the input (glFinish or a fence) before passing it.
+
+use std::ffi::c_void;
+use std::num::NonZeroU32;
+use wgpu_hal::api::Gles;
+
+/// Adapter on the GL context current on this thread. `get_proc_address` is eg. `eglGetProcAddress` or the loader of the host toolkit
+pub fn adapter_from_current_context(get_proc_address: &dyn Fn(&str) -> *const c_void) -> Option<wgpu::Adapter> {
+    let exposed = unsafe { wgpu_hal::gles::Adapter::new_external(|name| get_proc_address(name)) };
+    let exposed = match exposed {
+        Some(x) => x,
+        None => { log::error!("Failed to create wgpu adapter on the current GL context"); return None; }
+    };
+    let instance = wgpu::Instance::new(wgpu::Backends::GL);
+    let adapter = unsafe { instance.create_adapter_from_hal(exposed) };
+    log::debug!("WGPU adapter on the host GL context: {:?}", adapter.get_info());
+    Some(adapter)
+}
+
+/// Wraps the texture, it stays owned by the host app
+pub fn texture_from_raw(device: &wgpu::Device, name: u32, size: (u32, u32), format: wgpu::TextureFormat, usage: wgpu::TextureUsages) -> Option<wgpu::Texture> {
+    let name = NonZeroU32::new(name)?;
+    let size = wgpu::Extent3d { width: size.0, height: size.1, depth_or_array_layers: 1 };
+    let mut hal_usage = wgpu_hal::TextureUses::empty();
+    if usage.contains(wgpu::TextureUsages::COPY_SRC) { hal_usage |= wgpu_hal::TextureUses::COPY_SRC; }
+    if usage.contains(wgpu::TextureUsages::COPY_DST) { hal_usage |= wgpu_hal::TextureUses::COPY_DST; }
+
+    let hal_desc = wgpu_hal::TextureDescriptor {
+        label: None,
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: hal_usage,
+        memory_flags: wgpu_hal::MemoryFlags::empty(),
+    };
+
+    let hal_texture = unsafe {
+        device.as_hal::<Gles, _, _>(|hal_device| {
+            let hal_device = match hal_device {
+                Some(x) => x,
+                None => { log::error!("OpenGL textures require the GL wgpu backend"); return None; }
+            };
+            // With a drop guard wgpu doesn't delete the texture
+            Some(hal_device.texture_from_raw(name, &hal_desc, Some(Box::new(()))))
+        })?
+    };
+
+    Some(unsafe { device.create_texture_from_hal::<Gles>(hal_texture, &wgpu::TextureDescriptor {
+        label: None,
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage,
+    }) })
+}
@@ -25,11 +25,12 @@ struct KernelParams {
     input_horizontal_stretch: f32, // 4
     background_margin:        f32, // 8
     background_margin_feather:f32, // 12
-    reserved1:                f32, // 16
-    reserved2:                f32, // 4
-    reserved3:                f32, // 8
+    output_projection_strength: f32, // 16
+    range_scale:              f32, // 4
+    range_offset:             f32, // 8
     translation2d:      vec2<f32>, // 16
     translation3d:      vec4<f32>, // 16
+    range_luma:         vec4<f32>, // 16
 }
 
 @group(0) @binding(0) @fragment var<uniform> params: KernelParams;
@@ -41,9 +42,12 @@ let INTER_BITS: u32 = 5u;
 let INTER_TAB_SIZE: i32 = 32; // (1u << INTER_BITS);
 
 // From 0-255(JPEG/Full) to 16-235(MPEG/Limited)
-fn remap_colorrange(px: vec4<f32>, isY: bool) -> vec4<f32> {
-    if (isY) { return (16.0 / bg_scaler) + (px * 0.85882352); } // (235 - 16) / 255
-    else     { return (16.0 / bg_scaler) + (px * 0.87843137); } // (240 - 16) / 255
+// Full to limited range or back, the factors for the current plane are computed on the host
+// Packed RGB is scaled for the YUV encoding of the output, luma and chroma scale differently so the luma is mixed in
+fn remap_colorrange(px: vec4<f32>) -> vec4<f32> {
+    let luma = params.range_luma.x * dot(px.xyz, params.range_luma.yzw);
+    let res = (params.range_offset / bg_scaler) + (px * params.range_scale) + luma;
+    return vec4<f32>(res.xyz, px.w);
 }
 
 fn sample_input_at(uv: vec2<f32>) -> vec4<f32> {
@@ -77,7 +81,7 @@ fn sample_input_at(uv: vec2<f32>) -> vec4<f32> {
                 if (sx + xp >= 0 && sx + xp < params.width) {
                     pixel = vec4<f32>(textureLoad(input_tex, vec2<i32>(sx + xp, sy + yp), 0));
                     if (fix_range) {
-                        pixel = remap_colorrange(pixel, params.bytes_per_pixel == 1);
+                        pixel = remap_colorrange(pixel);
                     }
                 } else {
                     pixel = bg;
@@ -142,6 +146,27 @@ fn undistort_fragment(@builtin(position) position: vec4<f32>) -> @location(0) ve
         return bg;
     }
 
+    ///////////////////////////////////////////////////////////////////
+    // Output projection (lens look)
+    if (params.output_projection_strength > 0.0) {
+        let out_c = vec2<f32>(f32(params.output_width) / 2.0, f32(params.output_height) / 2.0);
+        if (bool(params.flags & 8)) { // Fisheye, corners stay in place
+            let out_f = params.f / params.fov;
+            let pt = (out_pos - out_c) / out_f;
+            let r = length(pt);
+            let r_max = length(out_c / out_f);
+            if (r > 0.0) {
+                let r2 = tan(r / r_max * atan(r_max));
+                out_pos = out_c + pt * (mix(r, r2, params.output_projection_strength) / r) * out_f;
+            }
+        } else if (bool(params.flags & 16)) { // Superview
+            let size = out_c * 2.0;
+            let pt2 = (from_superview((out_pos / size) - 0.5) + 0.5) * size;
+            out_pos = mix(out_pos, pt2, vec2<f32>(params.output_projection_strength));
+        }
+    }
+    ///////////////////////////////////////////////////////////////////
+
     ///////////////////////////////////////////////////////////////////
     // Calculate source `y` for rolling shutter
     var sy = u32(position.y);
@@ -174,7 +199,7 @@ fn undistort_fragment(@builtin(position) position: vec4<f32>) -> @location(0) ve
     }
     ///////////////////////////////////////////////////////////////////
 
-    let idx: u32 = min(sy, u32(params.matrix_count - 1)) * 9u;
+    let idx: u32 = min(sy * u32(params.matrix_count) / u32(max(params.height, 1)), u32(params.matrix_count - 1)) * 9u; // Matrices can cover more than one row
 
     var uv = rotate_and_distort(out_pos, idx, params.f, params.c, params.k1, params.k2, params.k3);
     if (uv.x > -99998.0) {
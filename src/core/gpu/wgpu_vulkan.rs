@@ -0,0 +1,404 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2022 Adrian <adrian.eddy at gmail>
+
+// Zero-copy Vulkan image input and output for the wgpu backend on Linux and Android.
+// The images either live on the same VkDevice as wgpu (the decoder can create its Vulkan context from `DeviceHandles`),
+// or their memory is exported from another device (eg. VAAPI surfaces mapped to Vulkan by ffmpeg) and imported here with VK_KHR_external_memory_fd.
+// Imported images are created with the same parameters as the exported ones, so the memory layout (optimal tiling) has to match,
+// and the memory is imported with the memory type of the exported allocation.
+// Images owned by an external queue family are acquired before every use and released back after the processing,
+// the exporter has to release them in the GENERAL layout.
+// DMA-BUF planes (eg. VAAPI surfaces exported as DRM PRIME) are imported with the explicit layout of their DRM format modifier instead,
+// which needs VK_EXT_external_memory_dma_buf and VK_EXT_image_drm_format_modifier. These are only enabled when the driver supports them.
+
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::os::unix::io::FromRawFd;
+use ash::vk;
+use ash::vk::Handle;
+use parking_lot::{ Mutex, MutexGuard };
+use wgpu_hal::api::Vulkan;
+
+// Imported images are kept, because the decoders reuse a small pool of surfaces
+const MAX_CACHED_IMAGES: usize = 32;
+
+lazy_static::lazy_static! {
+    static ref QUEUE_LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// Vulkan queues have to be externally synchronized. Everything submitted to the queue of the wgpu device outside of wgpu
+/// (the ownership transfers here, or a decoder using `DeviceHandles`) and the submissions of wgpu itself hold this lock
+pub fn queue_lock() -> MutexGuard<'static, ()> {
+    QUEUE_LOCK.lock()
+}
+
+#[derive(Default, Clone, Copy, Debug)]
+pub struct VulkanImage {
+    pub image: u64,              // VkImage, used directly when `memory_fd` is -1
+    pub memory: u64,             // VkDeviceMemory bound to the image, imported images are cached by it
+    pub memory_fd: i32,          // Opaque fd exported from `memory`, or -1 when the image was created on the wgpu device. Ownership is transferred
+    pub memory_size: u64,
+    pub memory_type_index: u32,  // Memory type of the exported allocation, OPAQUE_FD memory has to be imported with the same one. Unused for DMA-BUFs
+    pub memory_offset: u64,      // Offset of the plane when `dma_buf` is set
+    pub dma_buf: bool,           // `memory_fd` is a DMA-BUF, and `memory` is only a unique id of the plane used for caching
+    pub drm_modifier: u64,       // DRM format modifier of the DMA-BUF
+    pub row_pitch: u64,          // Bytes per row of the DMA-BUF plane
+    pub format: i32,             // VkFormat, has to match the pixel format of the processed frames
+    pub usage: u32,              // VkImageUsageFlags the image was created with
+    pub queue_family_index: u32, // Queue family owning the image, `DeviceHandles::queue_family_index` or VK_QUEUE_FAMILY_EXTERNAL for imported memory
+}
+
+/// Submissions to the queue have to hold `queue_lock`
+#[derive(Default, Clone, Copy, Debug)]
+pub struct DeviceHandles {
+    pub instance: u64,        // VkInstance
+    pub physical_device: u64, // VkPhysicalDevice
+    pub device: u64,          // VkDevice
+    pub queue_family_index: u32,
+    pub queue_index: u32,
+}
+
+fn external_memory_extensions() -> Vec<&'static CStr> {
+    vec![vk::KhrExternalMemoryFn::name(), vk::KhrExternalMemoryFdFn::name()]
+}
+// Enabled only if available, the import of DMA-BUFs fails without them
+fn dma_buf_extensions() -> [&'static CStr; 4] {
+    [vk::ExtExternalMemoryDmaBufFn::name(), vk::ExtImageDrmFormatModifierFn::name(), vk::KhrImageFormatListFn::name(), vk::KhrSamplerYcbcrConversionFn::name()]
+}
+
+/// Opens the wgpu device on a VkDevice created with the external memory extensions enabled
+pub fn request_device(adapter: &wgpu::Adapter, desc: &wgpu::DeviceDescriptor) -> Option<(wgpu::Device, wgpu::Queue, u32)> {
+    let downlevel_flags = adapter.get_downlevel_capabilities().flags;
+    let open_device = unsafe {
+        adapter.as_hal::<Vulkan, _, _>(|hal_adapter| {
+            let hal_adapter = hal_adapter?;
+            let instance = hal_adapter.shared_instance().raw_instance();
+            let physical_device = hal_adapter.raw_physical_device();
+
+            let mut extensions = hal_adapter.required_device_extensions(desc.features);
+            for ext in external_memory_extensions() {
+                if !extensions.contains(&ext) { extensions.push(ext); }
+            }
+            let available = instance.enumerate_device_extension_properties(physical_device).unwrap_or_default();
+            let available: Vec<&CStr> = available.iter().map(|x| CStr::from_ptr(x.extension_name.as_ptr())).collect();
+            if dma_buf_extensions().iter().all(|x| available.contains(x)) {
+                for ext in dma_buf_extensions() {
+                    if !extensions.contains(&ext) { extensions.push(ext); }
+                }
+            } else {
+                log::info!("Vulkan device doesn't support DMA-BUF import");
+            }
+            let mut physical_features = hal_adapter.physical_device_features(&extensions, desc.features, downlevel_flags);
+
+            let family_index = instance.get_physical_device_queue_family_properties(physical_device)
+                .iter()
+                .position(|x| x.queue_flags.contains(vk::QueueFlags::GRAPHICS))? as u32;
+
+            let queue_info = vk::DeviceQueueCreateInfo::builder()
+                .queue_family_index(family_index)
+                .queue_priorities(&[1.0])
+                .build();
+            let extension_names: Vec<_> = extensions.iter().map(|x| x.as_ptr()).collect();
+            let info = vk::DeviceCreateInfo::builder()
+                .queue_create_infos(std::slice::from_ref(&queue_info))
+                .enabled_extension_names(&extension_names);
+            let info = physical_features.add_to_device_create_builder(info);
+
+            let raw_device = match instance.create_device(physical_device, &info, None) {
+                Ok(x) => x,
+                Err(e) => { log::error!("Failed to create Vulkan device with external memory: {:?}", e); return None; }
+            };
+
+            hal_adapter.device_from_raw(raw_device, true, &extensions, desc.features, family_index, 0).ok().map(|x| (x, family_index))
+        })?
+    };
+    let (open_device, family_index) = open_device;
+    let (device, queue) = unsafe { adapter.create_device_from_hal(open_device, desc, None) }.ok()?;
+    Some((device, queue, family_index))
+}
+
+pub fn device_handles(device: &wgpu::Device) -> Option<DeviceHandles> {
+    unsafe {
+        device.as_hal::<Vulkan, _, _>(|hal_device| {
+            let hal_device = hal_device?;
+            Some(DeviceHandles {
+                instance:           hal_device.shared_instance().raw_instance().handle().as_raw(),
+                physical_device:    hal_device.raw_physical_device().as_raw(),
+                device:             hal_device.raw_device().handle().as_raw(),
+                queue_family_index: hal_device.queue_family_index(),
+                queue_index:        0,
+            })
+        })
+    }
+}
+
+// Destroys the image and the memory created for the import, when wgpu releases the texture
+struct ImportedImage {
+    device: ash::Device,
+    image: vk::Image,
+    memory: vk::DeviceMemory,
+}
+impl Drop for ImportedImage {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_image(self.image, None);
+            self.device.free_memory(self.memory, None);
+        }
+    }
+}
+
+// Command buffer and fence for the queue family ownership transfers, created on the first one and reused
+struct TransferCommands {
+    device: ash::Device,
+    pool: vk::CommandPool,
+    cmd: vk::CommandBuffer,
+    fence: vk::Fence,
+}
+impl Drop for TransferCommands {
+    fn drop(&mut self) {
+        unsafe {
+            // Every transfer is waited for, so the command buffer isn't in use anymore
+            self.device.destroy_fence(self.fence, None);
+            self.device.destroy_command_pool(self.pool, None);
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct ImageCache {
+    textures: HashMap<u64, (wgpu::Texture, vk::Image)>,
+    transfer: Option<TransferCommands>,
+}
+
+impl ImageCache {
+    /// Texture for the Vulkan image, importing its memory the first time it's seen
+    pub fn get(&mut self, device: &wgpu::Device, queue_family_index: u32, image: &VulkanImage, size: (u32, u32), format: wgpu::TextureFormat, usage: wgpu::TextureUsages) -> Option<&wgpu::Texture> {
+        if image.queue_family_index != queue_family_index && image.queue_family_index != vk::QUEUE_FAMILY_EXTERNAL {
+            log::error!("Vulkan image is owned by queue family {}, expected {}", image.queue_family_index, queue_family_index);
+            return None;
+        }
+        // Images on the wgpu device may not have the memory handle
+        let key = if image.memory_fd < 0 { image.image } else { image.memory };
+        if self.textures.contains_key(&key) {
+            if image.memory_fd >= 0 {
+                // Already imported, but the fd is still ours to close
+                drop(unsafe { std::fs::File::from_raw_fd(image.memory_fd) });
+            }
+        } else {
+            if self.textures.len() >= MAX_CACHED_IMAGES {
+                self.textures.clear();
+            }
+            let texture = Self::import(device, image, size, format, usage)?;
+            self.textures.insert(key, texture);
+        }
+        let vk_image = self.textures.get(&key)?.1;
+        if image.queue_family_index != queue_family_index && !self.transfer_ownership(device, vk_image, image.queue_family_index, true) {
+            return None;
+        }
+        self.textures.get(&key).map(|x| &x.0)
+    }
+
+    /// Releases the image back to its external queue family, after the processing was submitted. Both the inputs and the outputs are released
+    pub fn release(&mut self, device: &wgpu::Device, queue_family_index: u32, image: &VulkanImage) -> bool {
+        if image.queue_family_index == queue_family_index { return true; }
+        let key = if image.memory_fd < 0 { image.image } else { image.memory };
+        match self.textures.get(&key).map(|x| x.1) {
+            Some(vk_image) => self.transfer_ownership(device, vk_image, image.queue_family_index, false),
+            None => false
+        }
+    }
+
+    // Queue family ownership transfer of an image shared with another device or API, in the GENERAL layout.
+    // It's submitted right away on the wgpu queue and waited for, so it's ordered before (acquire) or after (release) the processing
+    fn transfer_ownership(&mut self, device: &wgpu::Device, vk_image: vk::Image, external_family: u32, acquire: bool) -> bool {
+        let transfer = &mut self.transfer;
+        unsafe {
+            device.as_hal::<Vulkan, _, _>(|hal_device| {
+                let hal_device = hal_device?;
+                let raw = hal_device.raw_device();
+                let family = hal_device.queue_family_index();
+
+                if transfer.is_none() {
+                    *transfer = Some(TransferCommands::new(raw, family)?);
+                }
+                let commands = transfer.as_ref()?;
+                let cmd = commands.cmd;
+
+                let result = (|| -> Result<(), vk::Result> {
+                    raw.reset_fences(std::slice::from_ref(&commands.fence))?;
+                    raw.reset_command_buffer(cmd, vk::CommandBufferResetFlags::empty())?;
+                    raw.begin_command_buffer(cmd, &vk::CommandBufferBeginInfo::builder().flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT))?;
+
+                    let (src_family, dst_family) = if acquire { (external_family, family) } else { (family, external_family) };
+                    let barrier = vk::ImageMemoryBarrier::builder()
+                        .src_access_mask(if acquire { vk::AccessFlags::empty() } else { vk::AccessFlags::MEMORY_WRITE })
+                        .dst_access_mask(if acquire { vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE } else { vk::AccessFlags::empty() })
+                        .old_layout(vk::ImageLayout::GENERAL)
+                        .new_layout(vk::ImageLayout::GENERAL)
+                        .src_queue_family_index(src_family)
+                        .dst_queue_family_index(dst_family)
+                        .image(vk_image)
+                        .subresource_range(vk::ImageSubresourceRange { aspect_mask: vk::ImageAspectFlags::COLOR, base_mip_level: 0, level_count: 1, base_array_layer: 0, layer_count: 1 })
+                        .build();
+                    let (src_stage, dst_stage) = if acquire {
+                        (vk::PipelineStageFlags::TOP_OF_PIPE, vk::PipelineStageFlags::ALL_COMMANDS)
+                    } else {
+                        (vk::PipelineStageFlags::ALL_COMMANDS, vk::PipelineStageFlags::BOTTOM_OF_PIPE)
+                    };
+                    raw.cmd_pipeline_barrier(cmd, src_stage, dst_stage, vk::DependencyFlags::empty(), &[], &[], std::slice::from_ref(&barrier));
+                    raw.end_command_buffer(cmd)?;
+
+                    // Same queue as wgpu's, see `request_device`
+                    let queue = raw.get_device_queue(family, 0);
+                    let submit_info = vk::SubmitInfo::builder().command_buffers(std::slice::from_ref(&cmd)).build();
+                    {
+                        let _lock = queue_lock();
+                        raw.queue_submit(queue, std::slice::from_ref(&submit_info), commands.fence)?;
+                    }
+                    raw.wait_for_fences(std::slice::from_ref(&commands.fence), true, u64::MAX)
+                })();
+
+                if let Err(e) = result {
+                    log::error!("Failed to transfer the ownership of the Vulkan image: {:?}", e);
+                    return None;
+                }
+                Some(())
+            }).is_some()
+        }
+    }
+
+    fn import(device: &wgpu::Device, image: &VulkanImage, size: (u32, u32), format: wgpu::TextureFormat, usage: wgpu::TextureUsages) -> Option<(wgpu::Texture, vk::Image)> {
+        let size = wgpu::Extent3d { width: size.0, height: size.1, depth_or_array_layers: 1 };
+        let mut hal_usage = wgpu_hal::TextureUses::empty();
+        if usage.contains(wgpu::TextureUsages::COPY_SRC) { hal_usage |= wgpu_hal::TextureUses::COPY_SRC; }
+        if usage.contains(wgpu::TextureUsages::COPY_DST) { hal_usage |= wgpu_hal::TextureUses::COPY_DST; }
+
+        let hal_desc = wgpu_hal::TextureDescriptor {
+            label: None,
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: hal_usage,
+            memory_flags: wgpu_hal::MemoryFlags::empty(),
+        };
+
+        let (hal_texture, vk_image) = unsafe {
+            device.as_hal::<Vulkan, _, _>(|hal_device| {
+                let hal_device = hal_device?;
+                if image.memory_fd < 0 {
+                    // Same device, the image stays owned by the caller
+                    let vk_image = vk::Image::from_raw(image.image);
+                    return Some((hal_device.texture_from_raw(vk_image, &hal_desc, Some(Box::new(()))), vk_image));
+                }
+
+                let raw = hal_device.raw_device();
+                let handle_type = if image.dma_buf { vk::ExternalMemoryHandleTypeFlags::DMA_BUF_EXT } else { vk::ExternalMemoryHandleTypeFlags::OPAQUE_FD };
+
+                // The plane offset is a part of the DRM layout, so the memory is bound at 0
+                let plane_layout = vk::SubresourceLayout { offset: image.memory_offset, size: 0, row_pitch: image.row_pitch, array_pitch: 0, depth_pitch: 0 };
+                let mut drm_info = vk::ImageDrmFormatModifierExplicitCreateInfoEXT::builder()
+                    .drm_format_modifier(image.drm_modifier)
+                    .plane_layouts(std::slice::from_ref(&plane_layout));
+                let mut external_info = vk::ExternalMemoryImageCreateInfo::builder().handle_types(handle_type);
+                let mut image_info = vk::ImageCreateInfo::builder()
+                    .push_next(&mut external_info)
+                    .image_type(vk::ImageType::TYPE_2D)
+                    .format(vk::Format::from_raw(image.format))
+                    .extent(vk::Extent3D { width: size.width, height: size.height, depth: 1 })
+                    .mip_levels(1)
+                    .array_layers(1)
+                    .samples(vk::SampleCountFlags::TYPE_1)
+                    .tiling(if image.dma_buf { vk::ImageTiling::DRM_FORMAT_MODIFIER_EXT } else { vk::ImageTiling::OPTIMAL })
+                    .usage(vk::ImageUsageFlags::from_raw(image.usage))
+                    .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                    .initial_layout(vk::ImageLayout::UNDEFINED);
+                if image.dma_buf {
+                    image_info = image_info.push_next(&mut drm_info);
+                }
+                let vk_image = match raw.create_image(&image_info, None) {
+                    Ok(x) => x,
+                    Err(e) => {
+                        log::error!("Failed to create Vulkan image for import: {:?}", e);
+                        drop(std::fs::File::from_raw_fd(image.memory_fd));
+                        return None;
+                    }
+                };
+
+                let requirements = raw.get_image_memory_requirements(vk_image);
+                let mut memory_type_bits = requirements.memory_type_bits;
+                if image.dma_buf {
+                    // The DMA-BUF can only be imported to some of the memory types
+                    let external_fd = ash::extensions::khr::ExternalMemoryFd::new(hal_device.shared_instance().raw_instance(), raw);
+                    match external_fd.get_memory_fd_properties(handle_type, image.memory_fd) {
+                        Ok(props) => { memory_type_bits &= props.memory_type_bits; },
+                        Err(e) => { log::warn!("Failed to get the DMA-BUF memory properties: {:?}", e); }
+                    }
+                }
+                // Opaque memory is only valid with the memory type it was allocated with
+                let memory_type_index = if image.dma_buf { memory_type_bits.trailing_zeros() } else { image.memory_type_index };
+                if memory_type_index >= 32 || memory_type_bits & (1 << memory_type_index) == 0 {
+                    log::error!("Memory type {} of the exported image can't be used for the import, allowed types: {:b}", memory_type_index, memory_type_bits);
+                    raw.destroy_image(vk_image, None);
+                    drop(std::fs::File::from_raw_fd(image.memory_fd));
+                    return None;
+                }
+                let offset = if image.dma_buf { 0 } else { image.memory_offset };
+                let mut import_info = vk::ImportMemoryFdInfoKHR::builder().handle_type(handle_type).fd(image.memory_fd);
+                let alloc_info = vk::MemoryAllocateInfo::builder()
+                    .push_next(&mut import_info)
+                    .allocation_size(image.memory_size.max(requirements.size))
+                    .memory_type_index(memory_type_index);
+                let memory = match raw.allocate_memory(&alloc_info, None) {
+                    Ok(x) => x,
+                    Err(e) => {
+                        log::error!("Failed to import Vulkan memory: {:?}", e);
+                        raw.destroy_image(vk_image, None);
+                        drop(std::fs::File::from_raw_fd(image.memory_fd));
+                        return None;
+                    }
+                };
+                if let Err(e) = raw.bind_image_memory(vk_image, memory, offset) {
+                    log::error!("Failed to bind imported Vulkan memory: {:?}", e);
+                    raw.destroy_image(vk_image, None);
+                    raw.free_memory(memory, None);
+                    return None;
+                }
+
+                let guard = ImportedImage { device: raw.clone(), image: vk_image, memory };
+                Some((hal_device.texture_from_raw(vk_image, &hal_desc, Some(Box::new(guard))), vk_image))
+            })?
+        };
+
+        Some((unsafe { device.create_texture_from_hal::<Vulkan>(hal_texture, &wgpu::TextureDescriptor {
+            label: None,
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage,
+        }) }, vk_image))
+    }
+}
+
+impl TransferCommands {
+    unsafe fn new(raw: &ash::Device, family: u32) -> Option<Self> {
+        let pool_info = vk::CommandPoolCreateInfo::builder().queue_family_index(family).flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER);
+        let pool = match raw.create_command_pool(&pool_info, None) {
+            Ok(x) => x,
+            Err(e) => { log::error!("Failed to create Vulkan command pool: {:?}", e); return None; }
+        };
+        let alloc_info = vk::CommandBufferAllocateInfo::builder().command_pool(pool).level(vk::CommandBufferLevel::PRIMARY).command_buffer_count(1);
+        let cmd = match raw.allocate_command_buffers(&alloc_info) {
+            Ok(x) => x[0],
+            Err(e) => { log::error!("Failed to allocate Vulkan command buffer: {:?}", e); raw.destroy_command_pool(pool, None); return None; }
+        };
+        let fence = match raw.create_fence(&vk::FenceCreateInfo::default(), None) {
+            Ok(x) => x,
+            Err(e) => { log::error!("Failed to create Vulkan fence: {:?}", e); raw.destroy_command_pool(pool, None); return None; }
+        };
+        Some(Self { device: raw.clone(), pool, cmd, fence })
+    }
+}
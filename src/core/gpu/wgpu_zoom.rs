@@ -0,0 +1,172 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2022 Adrian <adrian.eddy at gmail>
+
+// Adaptive zoom safe area search on the GPU, see wgpu_zoom.wgsl.
+// The device and the pipeline are kept between the computations, because zooming is recomputed after every parameter change.
+// The pipeline is rebuilt when the lens model changes, because its functions are compiled into the shader.
+
+use std::borrow::Cow;
+use wgpu::BufferUsages;
+use wgpu::util::DeviceExt;
+use parking_lot::Mutex;
+use super::wgpu::{ ADAPTER, WgpuWrapper };
+use super::context_manager::{ self, GpuTask };
+use crate::stabilization::distortion_models::GoProSuperview;
+
+const WORKGROUP_SIZE: u32 = 64;
+// Max frames per dispatch, the workgroup count per dimension is limited to 65535
+const MAX_FRAMES_PER_DISPATCH: usize = 16384;
+
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+pub struct ZoomParams {
+    pub k: [f32; 12],
+    pub f: [f32; 2],
+    pub c: [f32; 2],
+    pub size: [f32; 2],
+    pub stretch: [f32; 2],
+    pub inv_aspect: f32,
+    pub max_scale: f32,
+    pub margin: f32,
+    pub flags: i32,
+    pub rows: u32,
+    pub frames: u32,
+    pub points: u32,
+    pub _padding: u32,
+}
+unsafe impl bytemuck::Zeroable for ZoomParams {}
+unsafe impl bytemuck::Pod for ZoomParams {}
+
+pub struct ZoomFrame {
+    pub matrices: Vec<[f32; 9]>, // Inverted rotation for every row, `ZoomParams::rows` of them
+    pub center: (f32, f32),
+    pub lens_correction_amount: f32,
+}
+
+struct ZoomContext {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    pipeline: wgpu::ComputePipeline,
+}
+
+lazy_static::lazy_static! {
+    // Lens model functions the context was compiled with, and the context or None if it failed, so it's not retried on every zoom update
+    static ref CONTEXT: Mutex<Option<(&'static str, Option<ZoomContext>)>> = Mutex::new(None);
+}
+
+impl ZoomContext {
+    fn new(lens_model_funcs: &'static str) -> Option<Self> {
+        // `with_device` switches the global adapter, so it has to be read while the device is active
+        let selected = context_manager::MANAGER.read().selected(GpuTask::Preview);
+        let (device, queue) = context_manager::with_device(selected, || {
+            if ADAPTER.read().is_none() { WgpuWrapper::initialize_context(); }
+            let lock = ADAPTER.read();
+            let adapter = lock.as_ref()?;
+            let adapter_limits = adapter.limits();
+
+            pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor {
+                label: None,
+                features: wgpu::Features::empty(),
+                limits: wgpu::Limits {
+                    max_buffer_size: adapter_limits.max_buffer_size,
+                    max_storage_buffer_binding_size: adapter_limits.max_storage_buffer_binding_size,
+                    ..wgpu::Limits::default()
+                },
+            }, None)).ok()
+        }).flatten()?;
+
+        let mut shader_str = include_str!("wgpu_zoom.wgsl").to_string();
+        shader_str.insert_str(0, GoProSuperview::wgsl_functions());
+        shader_str.insert_str(0, lens_model_funcs);
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            source: wgpu::ShaderSource::Wgsl(Cow::Owned(shader_str)),
+            label: None
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: None,
+            layout: None,
+            module: &shader,
+            entry_point: "find_safe_area",
+        });
+
+        Some(Self { device, queue, pipeline })
+    }
+
+    fn run(&self, params: &ZoomParams, frames: &[ZoomFrame]) -> Option<Vec<f32>> {
+        let rows = params.rows as usize;
+        let mut frame_data = Vec::with_capacity(frames.len() * (rows * 9 + 4));
+        for frame in frames {
+            if frame.matrices.len() != rows { return None; }
+            frame.matrices.iter().for_each(|m| frame_data.extend_from_slice(m));
+            frame_data.extend_from_slice(&[frame.center.0, frame.center.1, frame.lens_correction_amount, 0.0]);
+        }
+        let params = ZoomParams { frames: frames.len() as u32, ..*params };
+        let result_size = (frames.len() * params.points as usize * std::mem::size_of::<f32>()) as wgpu::BufferAddress;
+
+        let buf_params = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor { label: None, contents: bytemuck::bytes_of(&params), usage: BufferUsages::UNIFORM });
+        let buf_frames = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor { label: None, contents: bytemuck::cast_slice(&frame_data), usage: BufferUsages::STORAGE });
+        let buf_result = self.device.create_buffer(&wgpu::BufferDescriptor { size: result_size, usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC, label: None, mapped_at_creation: false });
+        let staging_buffer = self.device.create_buffer(&wgpu::BufferDescriptor { size: result_size, usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST, label: None, mapped_at_creation: false });
+
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &self.pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: buf_params.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 1, resource: buf_frames.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 2, resource: buf_result.as_entire_binding() },
+            ],
+        });
+
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        {
+            let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
+            cpass.set_pipeline(&self.pipeline);
+            cpass.set_bind_group(0, &bind_group, &[]);
+            cpass.dispatch_workgroups((params.points + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE, frames.len() as u32, 1);
+        }
+        encoder.copy_buffer_to_buffer(&buf_result, 0, &staging_buffer, 0, result_size);
+        self.queue.submit(Some(encoder.finish()));
+
+        let buffer_slice = staging_buffer.slice(..);
+        let (sender, receiver) = futures_intrusive::channel::shared::oneshot_channel();
+        buffer_slice.map_async(wgpu::MapMode::Read, move |v| sender.send(v).unwrap());
+
+        self.device.poll(wgpu::Maintain::Wait);
+
+        if let Some(Ok(())) = pollster::block_on(receiver.receive()) {
+            let data = buffer_slice.get_mapped_range();
+            let result = bytemuck::cast_slice::<u8, f32>(&data).to_vec();
+            drop(data);
+            staging_buffer.unmap();
+            Some(result)
+        } else {
+            log::error!("failed to run the zoom compute on wgpu!");
+            None
+        }
+    }
+}
+
+/// Largest scale in every direction of every frame (`params.points` values per frame).
+/// Returns `None` if wgpu is not available, the caller should use the CPU implementation then
+pub fn find_safe_areas(params: &ZoomParams, frames: &[ZoomFrame], lens_model_funcs: &'static str) -> Option<Vec<f32>> {
+    if frames.is_empty() || params.points == 0 { return Some(Vec::new()); }
+    if !std::env::var("NO_WGPU").unwrap_or_default().is_empty() { return None; }
+
+    let mut lock = CONTEXT.lock();
+    if lock.as_ref().map(|x| x.0 != lens_model_funcs).unwrap_or(true) {
+        let ctx = std::panic::catch_unwind(|| ZoomContext::new(lens_model_funcs)).ok().flatten();
+        if ctx.is_none() {
+            log::debug!("wgpu is not available for the zoom calculation");
+        }
+        *lock = Some((lens_model_funcs, ctx));
+    }
+    let ctx = lock.as_ref()?.1.as_ref()?;
+
+    let mut ret = Vec::with_capacity(frames.len() * params.points as usize);
+    for chunk in frames.chunks(MAX_FRAMES_PER_DISPATCH) {
+        ret.extend(ctx.run(params, chunk)?);
+    }
+    Some(ret)
+}
@@ -0,0 +1,112 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2022 Adrian <adrian.eddy at gmail>
+
+// Safe area search for the adaptive zoom. Every invocation takes one frame and one direction from the zoom center
+// to the border of the output rectangle, and finds the largest scale at which the point in that direction still maps inside the input frame.
+// The smallest scale of all directions is the largest output rectangle which doesn't show any borders.
+// The mapping is the inverse of `undistort_points` in cpu_undistort.rs, so the result matches the CPU zooming.
+
+struct ZoomParams {
+    k1: vec4<f32>, k2: vec4<f32>, k3: vec4<f32>, // distortion coefficients
+    f:        vec2<f32>, // focal length in pixels
+    c:        vec2<f32>, // lens center
+    size:     vec2<f32>, // input width, height
+    stretch:  vec2<f32>, // input horizontal, vertical stretch
+    inv_aspect: f32,     // output height / output width
+    max_scale:  f32,     // upper bound of the search, in pixels
+    margin:     f32,     // pixels at the input edges which are considered outside
+    flags:      i32,     // 2 - GoPro Superview
+    rows:       u32,     // matrices per frame, > 1 with rolling shutter correction
+    frames:     u32,
+    points:     u32,     // directions per frame
+    _padding:   u32,
+}
+
+@group(0) @binding(0) var<uniform> params: ZoomParams;
+@group(0) @binding(1) var<storage, read> frames: array<f32>; // per frame: `rows` inverted 3x3 matrices, center x, center y, lens correction amount, unused
+@group(0) @binding(2) var<storage, read_write> result: array<f32>;
+
+let SEARCH_STEPS: i32 = 24;
+
+fn to_input(pos: vec2<f32>, idx: u32) -> vec2<f32> {
+    let _x = (pos.x * frames[idx + 0u]) + (pos.y * frames[idx + 1u]) + frames[idx + 2u];
+    let _y = (pos.x * frames[idx + 3u]) + (pos.y * frames[idx + 4u]) + frames[idx + 5u];
+    let _w = (pos.x * frames[idx + 6u]) + (pos.y * frames[idx + 7u]) + frames[idx + 8u];
+
+    if (_w > 0.0) {
+        var uv = params.f * distort_point(vec2<f32>(_x, _y) / _w, params.k1, params.k2, params.k3) + params.c;
+
+        if (bool(params.flags & 2)) { // GoPro Superview
+            uv = (to_superview((uv / params.size) - 0.5) + 0.5) * params.size;
+        }
+
+        if (params.stretch.x > 0.001) { uv.x /= params.stretch.x; }
+        if (params.stretch.y > 0.001) { uv.y /= params.stretch.y; }
+
+        return uv;
+    }
+    return vec2<f32>(-99999.0, -99999.0);
+}
+
+fn is_inside(pos: vec2<f32>, base: u32, lens_correction_amount: f32) -> bool {
+    var pos = pos;
+
+    // Add lens distortion back
+    if (lens_correction_amount < 1.0) {
+        if (bool(params.flags & 2)) {
+            pos = pos / params.size - 0.5;
+            pos.x /= 1.0 + (0.15 * (1.0 - lens_correction_amount));
+            pos = (pos + 0.5) * params.size;
+        }
+        var out_c = params.c;
+        if (params.stretch.x > 0.001) { out_c.x /= params.stretch.x; }
+        if (params.stretch.y > 0.001) { out_c.y /= params.stretch.y; }
+        pos = undistort_point((pos - out_c) / params.f, params.k1, params.k2, params.k3, lens_correction_amount) * params.f + out_c;
+    }
+
+    // Row of the input frame for the rolling shutter correction
+    var row = 0u;
+    if (params.rows > 1u) {
+        let uv = to_input(pos, base + (params.rows / 2u) * 9u); // Use middle matrix
+        if (uv.x > -99998.0) {
+            row = u32(clamp(uv.y / params.size.y * f32(params.rows), 0.0, f32(params.rows - 1u)));
+        }
+    }
+
+    let uv = to_input(pos, base + row * 9u);
+    return uv.x >= params.margin && uv.y >= params.margin && uv.x <= params.size.x - params.margin && uv.y <= params.size.y - params.margin;
+}
+
+@compute @workgroup_size(64)
+fn find_safe_area(@builtin(global_invocation_id) id: vec3<u32>) {
+    let point = id.x;
+    let frame = id.y;
+    if (point >= params.points || frame >= params.frames) { return; }
+
+    // Point on the border of the output rectangle with half width 1, going around from the top left corner
+    let t = f32(point) / f32(params.points) * 4.0;
+    let side = u32(floor(t));
+    let s = (t - f32(side)) * 2.0 - 1.0;
+    let a = params.inv_aspect;
+    var dir = vec2<f32>(s, -a);
+    if (side == 1u) { dir = vec2<f32>(1.0, s * a); }
+    if (side == 2u) { dir = vec2<f32>(-s, a); }
+    if (side == 3u) { dir = vec2<f32>(-1.0, -s * a); }
+
+    let base = frame * (params.rows * 9u + 4u);
+    let data = base + params.rows * 9u;
+    let center = vec2<f32>(frames[data], frames[data + 1u]);
+    let lens_correction_amount = frames[data + 2u];
+
+    var lo = 0.0;
+    var hi = params.max_scale;
+    for (var i: i32 = 0; i < SEARCH_STEPS; i = i + 1) {
+        let mid = (lo + hi) * 0.5;
+        if (is_inside(center + dir * mid, base, lens_correction_amount)) {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    result[frame * params.points + point] = lo;
+}
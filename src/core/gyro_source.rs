@@ -6,7 +6,6 @@ use std::iter::zip;
 use std::collections::BTreeMap;
 use std::collections::btree_map::Entry;
 use std::sync::{ Arc, atomic::AtomicBool };
-use std::fs::File;
 use telemetry_parser::{ Input, util };
 use telemetry_parser::tags_impl::{ GetWithType, GroupId, TagId, TimeQuaternion };
 
@@ -23,6 +22,22 @@ pub type TimeIMU = telemetry_parser::util::IMUData;
 pub type TimeQuat = BTreeMap<i64, Quat64>; // key is timestamp_us
 pub type TimeVec = BTreeMap<i64, Vector3<f64>>; // key is timestamp_us
 
+// GoPro GPMF streams which are not used for stabilization, but are kept so they can be passed through to the exported files.
+// (FourCC, name used by the parser)
+pub const GPMF_PASSTHROUGH_STREAMS: [(&str, &str); 3] = [
+    ("FACE", "Face"),  // Face detection
+    ("SCEN", "Scene"), // Scene classification
+    ("WNDM", "Wind"),  // Wind processing
+];
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct GpmfSample {
+    pub timestamp_ms: f64,
+    pub duration_ms: f64,
+    pub data: serde_json::Value,
+}
+pub type GpmfStreams = BTreeMap<String, Vec<GpmfSample>>; // key is the FourCC of the stream
+
 #[derive(Default)]
 pub struct FileMetadata {
     pub imu_orientation: Option<String>,
@@ -34,7 +49,54 @@ pub struct FileMetadata {
     pub frame_readout_time: Option<f64>,
     pub frame_rate: Option<f64>,
     pub camera_identifier: Option<CameraIdentifier>,
-    pub lens_profile: Option<serde_json::Value>
+    pub lens_profile: Option<serde_json::Value>,
+    // Physical roll of the camera while recording (0, 90, 180 or 270 degrees), if reported in the metadata
+    pub camera_orientation: Option<f64>,
+    pub gpmf_streams: GpmfStreams,
+}
+
+impl FileMetadata {
+    /// Keeps only the part of a long log used by the video (see `log_slice`), with the margins, and moves it to start at 0
+    pub fn slice(&mut self, start_ms: f64, duration_ms: f64) {
+        let from_ms = start_ms - crate::log_slice::MARGIN_MS;
+        let to_ms = start_ms + duration_ms + crate::log_slice::MARGIN_MS;
+        let in_range_us = |ts: i64| ts as f64 / 1000.0 >= from_ms && ts as f64 / 1000.0 <= to_ms;
+        let shift_us = (start_ms * 1000.0).round() as i64;
+
+        if let Some(ref mut imu) = self.raw_imu {
+            imu.retain(|x| x.timestamp_ms >= from_ms && x.timestamp_ms <= to_ms);
+            for x in imu.iter_mut() {
+                x.timestamp_ms -= start_ms;
+            }
+        }
+        if let Some(ref mut quats) = self.quaternions {
+            *quats = quats.iter().filter(|(ts, _)| in_range_us(**ts)).map(|(ts, q)| (ts - shift_us, *q)).collect();
+        }
+        if let Some(ref mut quats) = self.image_orientations {
+            *quats = quats.iter().filter(|(ts, _)| in_range_us(**ts)).map(|(ts, q)| (ts - shift_us, *q)).collect();
+        }
+        if let Some(ref mut vecs) = self.gravity_vectors {
+            *vecs = vecs.iter().filter(|(ts, _)| in_range_us(**ts)).map(|(ts, v)| (ts - shift_us, *v)).collect();
+        }
+    }
+
+    /// First and last timestamp of the log in ms
+    pub fn time_range_ms(&self) -> Option<(f64, f64)> {
+        if let Some(imu) = self.raw_imu.as_ref().filter(|x| x.len() > 1) {
+            return Some((imu.first()?.timestamp_ms, imu.last()?.timestamp_ms));
+        }
+        let quats = self.quaternions.as_ref().filter(|x| x.len() > 1)?;
+        Some((*quats.keys().next()? as f64 / 1000.0, *quats.keys().next_back()? as f64 / 1000.0))
+    }
+}
+
+#[derive(Default, Clone, Copy, Debug)]
+pub struct BiasEstimate {
+    pub bias: (f64, f64, f64),
+    pub quality: f64, // 0..1, based on the variance of the used samples and the number of still segments
+    pub still_segments: usize,
+    pub total_segments: usize,
+    pub samples: usize,
 }
 
 #[derive(Default, Clone)]
@@ -53,11 +115,24 @@ pub struct GyroSource {
     pub imu_rotation: Option<Rotation3<f64>>,
     pub acc_rotation_angles: Option<[f64; 3]>,
     pub acc_rotation: Option<Rotation3<f64>>,
+    // Fixed rotation from the device which recorded the telemetry to the filming camera (eg. from a rig calibration),
+    // applied after the IMU rotation and also to the quaternions recorded by the device
+    pub extrinsic_rotation_angles: Option<[f64; 3]>,
+    pub extrinsic_rotation: Option<Quat64>,
     pub imu_lpf: f64,
+    pub imu_lpf_type: super::filtering::FilterType,
+    pub imu_lpf_order: usize,
+    pub imu_lpf_axes: Option<[f64; 3]>, // Per axis cutoff, `imu_lpf` is used for all axes if not set
+
+    // Accelerometer is processed independently from the gyro. Without its own cutoff, it's filtered with the gyro low pass
+    pub acc_lpf: Option<f64>,
+    pub acc_bias: Option<[f64; 3]>,
+    pub acc_scale: Option<[f64; 3]>,
 
     pub gyro_bias: Option<[f64; 3]>,
 
     pub integration_method: usize,
+    pub quat_fusion_crossover: f64, // Hz, for the integration method 7 (camera quaternions + raw gyro)
 
     pub quaternions: TimeQuat,
     pub org_quaternions: TimeQuat,
@@ -77,6 +152,10 @@ pub struct GyroSource {
     offsets: BTreeMap<i64, f64>, // <microseconds timestamp, offset in milliseconds>
     offsets_adjusted: BTreeMap<i64, f64>, // <timestamp + offset, offset>
 
+    pub gpmf_streams: GpmfStreams, // Timestamps are in the video timeline, the streams come from the video file itself
+
+    pub log_slice: Option<crate::log_slice::LogSlice>, // Part of a log spanning multiple videos used for this one
+
     pub file_path: String
 }
 
@@ -84,7 +163,9 @@ impl GyroSource {
     pub fn new() -> Self {
         Self {
             integration_method: 1,
+            quat_fusion_crossover: 1.0,
             use_gravity_vectors: false,
+            imu_lpf_order: 2,
             ..Default::default()
         }
     }
@@ -100,7 +181,7 @@ impl GyroSource {
         self.duration_ms = stabilization_params.get_scaled_duration_ms();
     }
     pub fn parse_telemetry_file<F: Fn(f64)>(path: &str, size: (usize, usize), fps: f64, progress_cb: F, cancel_flag: Arc<AtomicBool>) -> Result<FileMetadata> {
-        let mut stream = File::open(path)?;
+        let mut stream = crate::filesystem::open(path)?;
         let filesize = stream.metadata()?.len() as usize;
 
         let input = Input::from_stream(&mut stream, filesize, &path, progress_cb, cancel_flag)?;
@@ -116,6 +197,9 @@ impl GyroSource {
         let mut image_orientations = None;
         let mut lens_profile = None;
         let mut frame_rate = None;
+        let mut camera_orientation = None;
+        let mut gpmf_streams = GpmfStreams::new();
+        let is_gopro = detected_source.starts_with("GoPro");
 
         // Get IMU orientation and quaternions
         if let Some(ref samples) = input.samples {
@@ -126,6 +210,20 @@ impl GyroSource {
             let mut grav_is_usable = false;
             for info in samples {
                 if let Some(ref tag_map) = info.tag_map {
+                    if is_gopro {
+                        for (group, map) in tag_map.iter() {
+                            let group_name = format!("{:?}", group);
+                            if let Some((fourcc, _)) = GPMF_PASSTHROUGH_STREAMS.iter().find(|(fourcc, name)| group_name.contains(fourcc) || group_name.contains(name)) {
+                                if let Some(data) = map.get(&TagId::Data).and_then(|x| serde_json::to_value(&x.value).ok()) {
+                                    gpmf_streams.entry(fourcc.to_string()).or_default().push(GpmfSample {
+                                        timestamp_ms: info.timestamp_ms,
+                                        duration_ms: info.duration_ms,
+                                        data
+                                    });
+                                }
+                            }
+                        }
+                    }
                     if let Some(map) = tag_map.get(&GroupId::Quaternion) {
                         if let Some(arr) = map.get_t(TagId::Data) as Option<&Vec<TimeQuaternion<f64>>> {
                             for v in arr {
@@ -148,6 +246,15 @@ impl GyroSource {
                         if let Some(v) = map.get_t(TagId::FrameRate) as Option<&f64> {
                             frame_rate = Some(*v);
                         }
+                        // GoPro orientation setting, the image is not rotated in camera when it's locked to Down/Left/Right
+                        if let Some(v) = map.get_t(TagId::Unknown(0x4f52454e/*OREN*/)) as Option<&String> {
+                            camera_orientation = match v.as_str() {
+                                "D" => Some(180.0),
+                                "L" => Some(90.0),
+                                "R" => Some(270.0),
+                                _ => None
+                            };
+                        }
                     }
                     if let Some(map) = tag_map.get(&GroupId::GravityVector) {
                         let scale = *(map.get_t(TagId::Scale) as Option<&i16>).unwrap_or(&32767) as f64;
@@ -223,7 +330,9 @@ impl GyroSource {
             frame_readout_time: input.frame_readout_time(),
             frame_rate,
             lens_profile,
-            camera_identifier
+            camera_identifier,
+            camera_orientation,
+            gpmf_streams
         })
     }
 
@@ -248,9 +357,11 @@ impl GyroSource {
         self.imu_rotation = None;
         self.acc_rotation = None;
         self.imu_lpf = 0.0;
+        self.acc_lpf = None;
 
         self.imu_orientation = telemetry.imu_orientation.clone();
         self.detected_source = telemetry.detected_source.clone();
+        self.gpmf_streams = telemetry.gpmf_streams.clone();
 
         if let Some(quats) = &telemetry.quaternions {
             self.quaternions = quats.clone();
@@ -286,8 +397,29 @@ impl GyroSource {
             4 => self.quaternions = SimpleGyroAccelIntegrator::integrate(&self.raw_imu, self.duration_ms),
             5 => self.quaternions = MahonyIntegrator::integrate(&self.raw_imu, self.duration_ms),
             6 => self.quaternions = MadgwickIntegrator::integrate(&self.raw_imu, self.duration_ms),
+            7 if self.org_quaternions.is_empty() => self.quaternions = ComplementaryIntegrator::integrate(&self.raw_imu, self.duration_ms),
+            7 => {
+                // Raw samples are already rotated in `apply_transforms`, so the quaternions have to be in the camera frame too
+                let mut quats = self.org_quaternions.clone();
+                if let Some(ext) = self.extrinsic_rotation {
+                    let inv = ext.inverse();
+                    for q in quats.values_mut() {
+                        *q *= inv;
+                    }
+                }
+                self.quaternions = QuaternionFusion::fuse(&quats, &self.image_orientations, &self.raw_imu, self.duration_ms, self.quat_fusion_crossover);
+            },
             _ => log::error!("Unknown integrator")
         }
+        if self.integration_method == 0 {
+            // Device orientation -> camera orientation. Raw samples are already rotated in `apply_transforms`
+            if let Some(ext) = self.extrinsic_rotation {
+                let inv = ext.inverse();
+                for q in self.quaternions.values_mut() {
+                    *q *= inv;
+                }
+            }
+        }
     }
 
     pub fn recompute_smoothness(&mut self, alg: &dyn SmoothingAlgorithm, horizon_lock: super::smoothing::horizon::HorizonLock, stabilization_params: &StabilizationParams, keyframes: &KeyframeManager) {
@@ -310,6 +442,17 @@ impl GyroSource {
         }
     }
 
+    // Passthrough GPMF samples overlapping the range, with timestamps relative to `start_ms`. Used to embed them in the trimmed output
+    pub fn gpmf_streams_in_range(&self, start_ms: f64, end_ms: f64) -> GpmfStreams {
+        self.gpmf_streams.iter().filter_map(|(fourcc, samples)| {
+            let samples: Vec<GpmfSample> = samples.iter()
+                .filter(|x| x.timestamp_ms + x.duration_ms > start_ms && x.timestamp_ms < end_ms)
+                .map(|x| GpmfSample { timestamp_ms: x.timestamp_ms - start_ms, ..x.clone() })
+                .collect();
+            if samples.is_empty() { None } else { Some((fourcc.clone(), samples)) }
+        }).collect()
+    }
+
     pub fn set_offset(&mut self, timestamp_us: i64, offset_ms: f64) {
         if offset_ms.is_finite() && !offset_ms.is_nan() {
             match self.offsets.entry(timestamp_us) {
@@ -343,16 +486,77 @@ impl GyroSource {
         self.offsets_adjusted = self.offsets.iter().map(|(k, v)| (*k + (*v * 1000.0).round() as i64, *v)).collect::<BTreeMap<i64, f64>>();
     }
 
-    pub fn apply_transforms(&mut self) {
-        self.raw_imu = self.org_raw_imu.clone();
-        if self.imu_lpf > 0.0 && !self.org_raw_imu.is_empty() && self.duration_ms > 0.0 {
-            let sample_rate = self.org_raw_imu.len() as f64 / (self.duration_ms / 1000.0);
-            if let Err(e) = super::filtering::Lowpass::filter_gyro_forward_backward(self.imu_lpf, sample_rate, &mut self.raw_imu) {
+    // Accelerometer chain: low pass, bias, scale. Used mostly for the gravity vectors
+    fn update_rotations(&mut self) {
+        const DEG2RAD: f64 = std::f64::consts::PI / 180.0;
+        if let Some([pitch_deg, roll_deg, yaw_deg]) = self.imu_rotation_angles {
+            if pitch_deg.abs() > 0.0 || roll_deg.abs() > 0.0 || yaw_deg.abs() > 0.0 {
+                self.imu_rotation = Some(Rotation3::from_euler_angles(
+                    yaw_deg * DEG2RAD,
+                    pitch_deg * DEG2RAD,
+                    roll_deg * DEG2RAD
+                ));
+            } else {
+                self.imu_rotation = None;
+            }
+        }
+        if let Some([pitch_deg, roll_deg, yaw_deg]) = self.acc_rotation_angles {
+            if pitch_deg.abs() > 0.0 || roll_deg.abs() > 0.0 || yaw_deg.abs() > 0.0 {
+                self.acc_rotation = Some(Rotation3::from_euler_angles(
+                    yaw_deg * DEG2RAD,
+                    pitch_deg * DEG2RAD,
+                    roll_deg * DEG2RAD
+                ));
+            } else {
+                self.acc_rotation = None;
+            }
+        }
+        if let Some([pitch_deg, roll_deg, yaw_deg]) = self.extrinsic_rotation_angles {
+            if pitch_deg.abs() > 0.0 || roll_deg.abs() > 0.0 || yaw_deg.abs() > 0.0 {
+                self.extrinsic_rotation = Some(Quat64::from_euler_angles(
+                    yaw_deg * DEG2RAD,
+                    pitch_deg * DEG2RAD,
+                    roll_deg * DEG2RAD
+                ));
+            } else {
+                self.extrinsic_rotation = None;
+            }
+        }
+    }
+
+    // Low pass filters (if `lpf` is set), biases, orientation and rotations, in that order
+    fn transform_imu(&self, imu: &mut [TimeIMU], lpf: bool) {
+        let sample_rate = imu.len() as f64 / (self.duration_ms / 1000.0);
+        if lpf && self.imu_lpf > 0.0 && !imu.is_empty() && self.duration_ms > 0.0 {
+            let cutoff = self.imu_lpf_axes.unwrap_or([self.imu_lpf; 3]);
+            if let Err(e) = super::filtering::filter_gyro(self.imu_lpf_type, self.imu_lpf_order, cutoff, sample_rate, imu) {
                 log::error!("Filter error {:?}", e);
             }
         }
+        let acc_cutoff = match self.acc_lpf {
+            Some(v) => [v; 3],
+            None => self.imu_lpf_axes.unwrap_or([self.imu_lpf; 3])
+        };
+        if lpf && acc_cutoff.iter().any(|x| *x > 0.0) && !imu.is_empty() && self.duration_ms > 0.0 {
+            if let Err(e) = super::filtering::filter_accl(self.imu_lpf_type, self.imu_lpf_order, acc_cutoff, sample_rate, imu) {
+                log::error!("Accelerometer filter error {:?}", e);
+            }
+        }
+        if self.acc_bias.is_some() || self.acc_scale.is_some() {
+            let bias = self.acc_bias.unwrap_or([0.0; 3]);
+            let scale = self.acc_scale.unwrap_or([1.0; 3]);
+            for x in imu.iter_mut() {
+                if let Some(a) = x.accl.as_mut() {
+                    *a = [
+                        (a[0] + bias[0]) * scale[0],
+                        (a[1] + bias[1]) * scale[1],
+                        (a[2] + bias[2]) * scale[2]
+                    ];
+                }
+            }
+        }
         if let Some(bias) = self.gyro_bias {
-            for x in &mut self.raw_imu {
+            for x in imu.iter_mut() {
                 if let Some(g) = x.gyro.as_mut() {
                     *g = [
                         g[0] + bias[0],
@@ -374,7 +578,7 @@ impl GyroSource {
                 };
                 [map(io[0]), map(io[1]), map(io[2]) ]
             }
-            for x in &mut self.raw_imu {
+            for x in imu.iter_mut() {
                 // Change orientation
                 if let Some(g) = x.gyro.as_mut() { *g = orient(g, orientation.as_bytes()); }
                 if let Some(a) = x.accl.as_mut() { *a = orient(a, orientation.as_bytes()); }
@@ -382,29 +586,6 @@ impl GyroSource {
             }
         }
         // Rotate
-        const DEG2RAD: f64 = std::f64::consts::PI / 180.0;
-        if let Some([pitch_deg, roll_deg, yaw_deg]) = self.imu_rotation_angles {
-            if pitch_deg.abs() > 0.0 || roll_deg.abs() > 0.0 || yaw_deg.abs() > 0.0 {
-                self.imu_rotation = Some(Rotation3::from_euler_angles(
-                    yaw_deg * DEG2RAD,
-                    pitch_deg * DEG2RAD,
-                    roll_deg * DEG2RAD
-                ));
-            } else {
-                self.imu_rotation = None;
-            }
-        }
-        if let Some([pitch_deg, roll_deg, yaw_deg]) = self.acc_rotation_angles {
-            if pitch_deg.abs() > 0.0 || roll_deg.abs() > 0.0 || yaw_deg.abs() > 0.0 {
-                self.acc_rotation = Some(Rotation3::from_euler_angles(
-                    yaw_deg * DEG2RAD,
-                    pitch_deg * DEG2RAD,
-                    roll_deg * DEG2RAD
-                ));
-            } else {
-                self.acc_rotation = None;
-            }
-        }
         if self.imu_rotation.is_some() || self.acc_rotation.is_some() {
             let rotate = |inp: &[f64; 3], rot: Rotation3<f64>| -> [f64; 3] {
                 let rotated = rot.transform_vector(&Vector3::new(inp[0], inp[1], inp[2]));
@@ -412,16 +593,41 @@ impl GyroSource {
             };
             let grot = self.imu_rotation;
             let arot = if self.acc_rotation.is_some() { self.acc_rotation } else { self.imu_rotation };
-            for x in &mut self.raw_imu {
+            for x in imu.iter_mut() {
                 if let Some(g) = x.gyro.as_mut() { if let Some(grot) = grot { *g = rotate(g, grot); } }
                 if let Some(a) = x.accl.as_mut() { if let Some(arot) = arot { *a = rotate(a, arot); } }
                 if let Some(m) = x.magn.as_mut() { if let Some(grot) = grot { *m = rotate(m, grot); } }
             }
         }
+        if let Some(ext) = self.extrinsic_rotation {
+            let rotate = |inp: &[f64; 3]| -> [f64; 3] {
+                let rotated = ext.transform_vector(&Vector3::new(inp[0], inp[1], inp[2]));
+                [rotated[0], rotated[1], rotated[2]]
+            };
+            for x in imu.iter_mut() {
+                if let Some(g) = x.gyro.as_mut() { *g = rotate(g); }
+                if let Some(a) = x.accl.as_mut() { *a = rotate(a); }
+                if let Some(m) = x.magn.as_mut() { *m = rotate(m); }
+            }
+        }
+    }
+
+    pub fn apply_transforms(&mut self) {
+        self.update_rotations();
+        let mut raw_imu = self.org_raw_imu.clone();
+        self.transform_imu(&mut raw_imu, true);
+        self.raw_imu = raw_imu;
 
         self.integrate();
     }
 
+    /// `raw_imu` without the low pass filters, for comparing the filtered and unfiltered signal. Rotations are taken from the last `apply_transforms`
+    pub fn unfiltered_imu(&self) -> Vec<TimeIMU> {
+        let mut imu = self.org_raw_imu.clone();
+        self.transform_imu(&mut imu, false);
+        imu
+    }
+
     fn quat_at_timestamp(&self, quats: &TimeQuat, mut timestamp_ms: f64) -> Quat64 {
         if quats.len() < 2 || self.duration_ms <= 0.0 { return Quat64::identity(); }
 
@@ -475,6 +681,11 @@ impl GyroSource {
             }
         }
     }
+    /// First and last timestamp of the loaded log in ms
+    pub fn time_range_ms(&self) -> Option<(f64, f64)> {
+        Some((*self.quaternions.keys().next()? as f64 / 1000.0, *self.quaternions.keys().next_back()? as f64 / 1000.0))
+    }
+
     pub fn offset_at_video_timestamp(&self, timestamp_ms: f64) -> f64 { Self::offset_at_timestamp(&self.offsets_adjusted, timestamp_ms) }
     pub fn offset_at_gyro_timestamp (&self, timestamp_ms: f64) -> f64 { Self::offset_at_timestamp(&self.offsets, timestamp_ms) }
 
@@ -489,6 +700,7 @@ impl GyroSource {
             gravity_vectors:      self.gravity_vectors.clone(),
             use_gravity_vectors:  self.use_gravity_vectors,
             integration_method:   self.integration_method,
+            quat_fusion_crossover: self.quat_fusion_crossover,
             ..Default::default()
         }
     }
@@ -508,25 +720,70 @@ impl GyroSource {
     }
 
     pub fn find_bias(&self, timestamp_start: f64, timestamp_stop: f64) -> (f64, f64, f64) {
-        let ts_start = timestamp_start - self.offset_at_video_timestamp(timestamp_start);
-        let ts_stop = timestamp_stop - self.offset_at_video_timestamp(timestamp_stop);
-        let mut bias_vals = [0.0, 0.0, 0.0];
-        let mut n = 0;
+        self.find_bias_multi(&[(timestamp_start, timestamp_stop)]).bias
+    }
 
-        for x in &self.org_raw_imu {
-            if let Some(g) = x.gyro {
-                if x.timestamp_ms > ts_start && x.timestamp_ms < ts_stop {
-                    bias_vals[0] -= g[0];
-                    bias_vals[1] -= g[1];
-                    bias_vals[2] -= g[2];
-                    n += 1;
+    // Estimates the gyro bias from all given ranges (video timestamps in ms).
+    // Segments with too much variance (camera wasn't actually still) are excluded, unless none of them were still
+    pub fn find_bias_multi(&self, ranges: &[(f64, f64)]) -> BiasEstimate {
+        // Max standard deviation of the gyro magnitude (deg/s) for the segment to be considered still
+        const STILL_STDDEV_LIMIT: f64 = 0.5;
+
+        let segments: Vec<([f64; 3], [f64; 3], usize)> = ranges.iter().map(|(start, stop)| {
+            let ts_start = start - self.offset_at_video_timestamp(*start);
+            let ts_stop = stop - self.offset_at_video_timestamp(*stop);
+            let mut sum = [0.0; 3];
+            let mut sum_sq = [0.0; 3];
+            let mut n = 0;
+            for x in &self.org_raw_imu {
+                if let Some(g) = x.gyro {
+                    if x.timestamp_ms > ts_start && x.timestamp_ms < ts_stop {
+                        for i in 0..3 {
+                            sum[i] += g[i];
+                            sum_sq[i] += g[i] * g[i];
+                        }
+                        n += 1;
+                    }
                 }
             }
+            (sum, sum_sq, n)
+        }).collect();
+
+        let stddev = |sum: &[f64; 3], sum_sq: &[f64; 3], n: usize| -> f64 {
+            if n < 2 { return f64::MAX; }
+            let n = n as f64;
+            (0..3).map(|i| (sum_sq[i] / n - (sum[i] / n).powi(2)).max(0.0)).sum::<f64>().sqrt()
+        };
+
+        let still: Vec<bool> = segments.iter().map(|(sum, sum_sq, n)| stddev(sum, sum_sq, *n) < STILL_STDDEV_LIMIT).collect();
+        let still_segments = still.iter().filter(|x| **x).count();
+
+        let mut sum = [0.0; 3];
+        let mut sum_sq = [0.0; 3];
+        let mut n = 0;
+        for (i, seg) in segments.iter().enumerate() {
+            if still[i] || still_segments == 0 {
+                for j in 0..3 {
+                    sum[j] += seg.0[j];
+                    sum_sq[j] += seg.1[j];
+                }
+                n += seg.2;
+            }
         }
-        for b in bias_vals.iter_mut() {
-            *b /= n.max(1) as f64;
-        }
+        let bias = [-sum[0] / n.max(1) as f64, -sum[1] / n.max(1) as f64, -sum[2] / n.max(1) as f64];
 
-        (bias_vals[0], bias_vals[1], bias_vals[2])
+        let quality = if n < 2 || segments.is_empty() {
+            0.0
+        } else {
+            (1.0 - stddev(&sum, &sum_sq, n) / STILL_STDDEV_LIMIT).clamp(0.0, 1.0) * (still_segments as f64 / segments.len() as f64)
+        };
+
+        BiasEstimate {
+            bias: (bias[0], bias[1], bias[2]),
+            quality,
+            still_segments,
+            total_segments: segments.len(),
+            samples: n
+        }
     }
 }
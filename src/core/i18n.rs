@@ -0,0 +1,107 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2022 Adrian <adrian.eddy at gmail>
+
+// Message catalog for user-facing strings generated in core.
+// Each message has a stable key and an English template with %1, %2... placeholders.
+// The UI layer registers a translator which receives the key and the template and returns the translated template,
+// arguments are substituted after translation.
+
+use parking_lot::RwLock;
+use serde::Serialize;
+
+pub const CATALOG: &[(&str, &str)] = &[
+    ("source.gyroflow_file",          "Gyroflow file"),
+
+    ("error.invalid_parameters",      "Invalid parameters"),
+    ("error.unable_to_read_video",    "Unable to read the video file."),
+    ("error.unable_to_open",          "Unable to open %1"),
+    ("error.json_parse",              "JSON parse error: %1"),
+    ("error.loading_file",            "Error loading %1: %2"),
+    ("error.preview_image_sequence",  "Preview is not available for image sequences."),
+
+    ("calibration.no_readable_images", "Unable to read any of the calibration images."),
+    ("calibration.not_calibrated",    "Calibrate this camera first."),
+
+    ("lens.missing_brand",            "Camera brand is missing."),
+    ("lens.missing_model",            "Camera model is missing."),
+    ("lens.missing_dimensions",       "Calibration dimensions are missing."),
+    ("lens.missing_fps",              "Frame rate is missing."),
+    ("lens.not_enough_frames",        "Not enough calibration frames (%1, at least %2 required)."),
+    ("lens.not_calibrated",           "Lens is not calibrated."),
+    ("lens.invalid_coefficients",     "Calibration coefficients are invalid."),
+    ("lens.missing_rms",              "Reprojection error is missing."),
+    ("lens.rms_too_high",             "Reprojection error is too high (%1, should be less than %2)."),
+];
+
+type Translator = Box<dyn Fn(&str, &str) -> Option<String> + Send + Sync>;
+
+lazy_static::lazy_static! {
+    static ref TRANSLATOR: RwLock<Option<Translator>> = RwLock::new(None);
+}
+
+pub fn set_translator<F: Fn(&str, &str) -> Option<String> + Send + Sync + 'static>(cb: F) {
+    *TRANSLATOR.write() = Some(Box::new(cb));
+}
+
+pub fn template(key: &str) -> Option<&'static str> {
+    CATALOG.iter().find(|(k, _)| *k == key).map(|(_, v)| *v)
+}
+
+fn translate_template(key: &str, template: &str) -> String {
+    TRANSLATOR.read().as_ref().and_then(|cb| cb(key, template)).unwrap_or_else(|| template.to_string())
+}
+
+// Translates a plain English string if it's in the catalog (eg. a stored detected source name), otherwise returns it unchanged
+pub fn translate_english(text: &str) -> String {
+    match CATALOG.iter().find(|(_, v)| *v == text) {
+        Some((key, template)) => translate_template(key, template),
+        None => text.to_string()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Message {
+    pub key: &'static str,
+    pub template: &'static str,
+    pub args: Vec<String>,
+}
+
+impl Message {
+    pub fn new(key: &'static str) -> Self {
+        let template = template(key).unwrap_or_else(|| {
+            log::warn!("Missing message in the catalog: {}", key);
+            key
+        });
+        Self { key, template, args: Vec::new() }
+    }
+    // For messages defined outside of `CATALOG`, eg. generated by macros
+    pub fn with_template(key: &'static str, template: &'static str) -> Self {
+        Self { key, template, args: Vec::new() }
+    }
+    pub fn arg<T: ToString>(mut self, v: T) -> Self {
+        self.args.push(v.to_string());
+        self
+    }
+
+    pub fn english(&self) -> String {
+        Self::fill(self.template, &self.args)
+    }
+    pub fn translated(&self) -> String {
+        Self::fill(&translate_template(self.key, self.template), &self.args)
+    }
+
+    fn fill(template: &str, args: &[String]) -> String {
+        let mut ret = template.to_string();
+        // Replace from the highest index, so %1 doesn't match the beginning of %10
+        for (i, arg) in args.iter().enumerate().rev() {
+            ret = ret.replace(&format!("%{}", i + 1), arg);
+        }
+        ret
+    }
+}
+
+impl std::fmt::Display for Message {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.translated())
+    }
+}
@@ -15,6 +15,7 @@ pub trait GyroIntegrator {
 }
 
 pub struct QuaternionConverter { }
+pub struct QuaternionFusion { }
 pub struct ComplementaryIntegrator { }
 pub struct VQFIntegrator { }
 pub struct SimpleGyroIntegrator { }
@@ -42,6 +43,48 @@ impl QuaternionConverter {
     }
 }
 
+// Camera quaternions for the low frequencies and the raw gyro for the high frequencies.
+// The difference between the camera orientation and the integrated gyro is low passed at `crossover_hz` (forward and backward, so without delay),
+// which removes the gyro drift and the noise of the quaternions, and then applied to the integrated gyro, at the gyro sample rate.
+impl QuaternionFusion {
+    pub fn fuse(org_quaternions: &TimeQuat, image_orientations: &TimeQuat, imu_data: &[TimeIMU], duration_ms: f64, crossover_hz: f64) -> TimeQuat {
+        let gyro_quats = SimpleGyroIntegrator::integrate(imu_data, duration_ms);
+        if gyro_quats.len() < 2 || org_quaternions.len() < 2 || crossover_hz <= 0.0 {
+            return org_quaternions.clone();
+        }
+        let quat_at = |ts: i64| -> Quat64 {
+            let prev = org_quaternions.range(..=ts).next_back();
+            let next = org_quaternions.range(ts..).next();
+            match (prev, next) {
+                (Some(a), Some(b)) if a.0 != b.0 => a.1.slerp(b.1, (ts - a.0) as f64 / (b.0 - a.0) as f64),
+                (Some(a), _) => *a.1,
+                (_, Some(b)) => *b.1,
+                _ => Quat64::identity()
+            }
+        };
+
+        // Integrated gyro in the camera frame, and its correction to the camera orientation
+        let samples: Vec<(i64, Quat64, Quat64)> = gyro_quats.iter().map(|(&ts, &gyro_quat)| {
+            let io_quat = image_orientations.range(ts..).next().map(|x| *x.1).unwrap_or(UnitQuaternion::identity());
+            let gyro_quat = gyro_quat * io_quat;
+            (ts, gyro_quat, quat_at(ts) * gyro_quat.inverse())
+        }).collect();
+
+        let rc = 1.0 / (2.0 * std::f64::consts::PI * crossover_hz);
+        let alpha = |dt_us: i64| { let dt = dt_us.unsigned_abs() as f64 / 1_000_000.0; dt / (rc + dt) };
+
+        let mut corr = vec![samples[0].2; samples.len()];
+        for i in 1..samples.len() {
+            corr[i] = corr[i - 1].slerp(&samples[i].2, alpha(samples[i].0 - samples[i - 1].0));
+        }
+        for i in (0..samples.len() - 1).rev() {
+            corr[i] = corr[i + 1].slerp(&corr[i], alpha(samples[i + 1].0 - samples[i].0));
+        }
+
+        samples.iter().zip(corr.iter()).map(|((ts, gyro_quat, _), corr)| (*ts, corr * gyro_quat)).collect()
+    }
+}
+
 ///////////////////////////////////////////////////////////////////////////////
 ///////////////////////////////////////////////////////////////////////////////
 ///////////////////////////////////////////////////////////////////////////////
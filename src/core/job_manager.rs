@@ -0,0 +1,127 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2022 Adrian <adrian.eddy at gmail>
+
+// Background jobs (sync, import, calibration, rendering etc.) with their own cancellation flags,
+// so cancelling one operation doesn't cancel everything else running at the same time.
+// A job is registered for as long as its `JobToken` is alive.
+// The jobs run as soon as they are started, the manager only tracks them for cancellation and `ComputePolicy`.
+
+use std::collections::BTreeMap;
+use std::sync::{ Arc, atomic::{ AtomicBool, AtomicU64, Ordering::SeqCst } };
+use parking_lot::RwLock;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum JobKind {
+    Sync,
+    LoadTelemetry,
+    Import,
+    Calibration,
+    Render,
+    Analysis,
+}
+
+// What to do with interactive computations (recompute, sync) while a render is running
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ComputePolicy {
+    #[default]
+    Normal = 0,
+    Throttle = 1,
+    Pause = 2,
+}
+impl From<i32> for ComputePolicy {
+    fn from(v: i32) -> Self {
+        match v {
+            1 => Self::Throttle,
+            2 => Self::Pause,
+            _ => Self::Normal
+        }
+    }
+}
+
+struct Job {
+    kind: JobKind,
+    cancel_flag: Arc<AtomicBool>,
+}
+
+#[derive(Default)]
+pub struct JobManager {
+    jobs: RwLock<BTreeMap<u64, Job>>,
+    next_id: AtomicU64,
+    compute_policy: RwLock<ComputePolicy>,
+}
+
+impl JobManager {
+    pub fn start(self: &Arc<Self>, kind: JobKind) -> JobToken {
+        let id = self.next_id.fetch_add(1, SeqCst) + 1;
+        let cancel_flag = Arc::new(AtomicBool::new(false));
+        self.jobs.write().insert(id, Job { kind, cancel_flag: cancel_flag.clone() });
+        ::log::debug!("Job {} started: {:?}", id, kind);
+
+        JobToken { id, cancel_flag, manager: self.clone() }
+    }
+
+    pub fn cancel_kinds(&self, kinds: &[JobKind]) {
+        for job in self.jobs.read().values().filter(|x| kinds.contains(&x.kind)) {
+            job.cancel_flag.store(true, SeqCst);
+        }
+    }
+
+    pub fn is_running(&self, kind: JobKind) -> bool {
+        self.jobs.read().values().any(|x| x.kind == kind)
+    }
+
+    // Waits until all jobs of given kinds are finished, returns false on timeout
+    pub fn wait_for(&self, kinds: &[JobKind], timeout_ms: u64) -> bool {
+        let start = std::time::Instant::now();
+        while self.jobs.read().values().any(|x| kinds.contains(&x.kind)) {
+            if start.elapsed().as_millis() as u64 > timeout_ms {
+                return false;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+        true
+    }
+
+    pub fn set_compute_policy(&self, policy: ComputePolicy) {
+        *self.compute_policy.write() = policy;
+    }
+
+    // Called by interactive computations before doing the heavy work, so they don't compete with the renders for the GPU.
+    // Opt-in, does nothing with the default `Normal` policy. `throttle_ms` is the delay used in `Throttle` mode.
+    // Returns early when `is_cancelled` returns true
+    pub fn wait_for_compute_slot<F: Fn() -> bool>(&self, throttle_ms: u64, is_cancelled: F) {
+        match *self.compute_policy.read() {
+            ComputePolicy::Normal => { },
+            ComputePolicy::Throttle => {
+                if self.is_running(JobKind::Render) {
+                    std::thread::sleep(std::time::Duration::from_millis(throttle_ms));
+                }
+            },
+            ComputePolicy::Pause => {
+                while self.is_running(JobKind::Render) && !is_cancelled() {
+                    std::thread::sleep(std::time::Duration::from_millis(100));
+                }
+            }
+        }
+    }
+
+    fn finish(&self, id: u64) {
+        if let Some(job) = self.jobs.write().remove(&id) {
+            ::log::debug!("Job {} finished: {:?}, cancelled: {}", id, job.kind, job.cancel_flag.load(SeqCst));
+        }
+    }
+}
+
+pub struct JobToken {
+    pub id: u64,
+    pub cancel_flag: Arc<AtomicBool>,
+    manager: Arc<JobManager>,
+}
+impl JobToken {
+    pub fn is_cancelled(&self) -> bool { self.cancel_flag.load(SeqCst) }
+}
+impl Drop for JobToken {
+    fn drop(&mut self) {
+        self.manager.finish(self.id);
+    }
+}
@@ -5,7 +5,7 @@ use std::{ collections::BTreeMap, collections::btree_map::Entry, str::FromStr };
 use crate::gyro_source::GyroSource;
 
 macro_rules! define_keyframes {
-    ($($name:ident, $color:literal, $text:literal, $format:expr,)*) => {
+    ($($name:ident, $color:literal, $text:literal, $range:expr, $format:expr,)*) => {
         #[derive(Debug, Copy, Clone, PartialEq, PartialOrd, Eq, Ord, ::serde::Serialize, ::serde::Deserialize)]
         pub enum KeyframeType {
             $($name),*
@@ -16,32 +16,69 @@ macro_rules! define_keyframes {
         pub fn keyframe_text(kf: &KeyframeType) -> &'static str {
             match kf { $(KeyframeType::$name => $text),* }
         }
+        pub fn keyframe_message(kf: &KeyframeType) -> crate::i18n::Message {
+            match kf { $(KeyframeType::$name => crate::i18n::Message::with_template(concat!("keyframe.", stringify!($name)), $text)),* }
+        }
         pub fn keyframe_format_value(kf: &KeyframeType, v: f64) -> String {
             match kf { $(KeyframeType::$name => $format(v)),* }
         }
+        pub fn keyframe_range(kf: &KeyframeType) -> ValueRange {
+            match kf { $(KeyframeType::$name => ValueRange::from($range)),* }
+        }
     };
 }
 
+// Valid values of a parameter. Values outside of it can break the recompute, so they are clamped before being stored
+#[derive(Debug, Copy, Clone, PartialEq, ::serde::Serialize, ::serde::Deserialize)]
+pub struct ValueRange {
+    pub min: f64,
+    pub max: f64,
+    pub step: f64,
+}
+impl From<(f64, f64, f64)> for ValueRange {
+    fn from((min, max, step): (f64, f64, f64)) -> Self { Self { min, max, step } }
+}
+impl ValueRange {
+    pub fn contains(&self, v: f64) -> bool { v >= self.min && v <= self.max }
+    /// None for NaN, which can't be mapped to any valid value
+    pub fn clamp(&self, v: f64) -> Option<f64> {
+        if v.is_nan() { return None; }
+        Some(v.max(self.min).min(self.max))
+    }
+    // Range of a smoothing algorithm parameter from its description in `get_parameters_json`
+    pub fn from_parameter_json(v: &serde_json::Value) -> Option<Self> {
+        let min = v.get("from")?.as_f64()?;
+        let max = v.get("to")?.as_f64()?;
+        let step = v.get("precision").and_then(|x| x.as_i64()).map(|p| 10f64.powi(-p as i32)).unwrap_or(0.01);
+        Some(Self { min, max, step })
+    }
+}
+
 define_keyframes! {
-    Fov,                         "#8ee6ea", "FOV",                              |v| format!("{:.2}", v),
-    VideoRotation,               "#eae38e", "Video rotation",                   |v| format!("{:.1}°", v),
-    ZoomingSpeed,                "#32e595", "Zooming speed",                    |v| format!("{:.2}s", v),
-    ZoomingCenterX,              "#6fefb6", "Zooming center offset X",          |v| format!("{:.0}%", v * 100.0),
-    ZoomingCenterY,              "#5ddba2", "Zooming center offset Y",          |v| format!("{:.0}%", v * 100.0),
-    BackgroundMargin,            "#6e5ddb", "Background margin",                |v| format!("{:.0}%", v),
-    BackgroundFeather,           "#9d93e1", "Background feather",               |v| format!("{:.0}%", v),
-    LockHorizonAmount,           "#ed7789", "Horizon lock amount",              |v| format!("{:.0}%", v),
-    LockHorizonRoll,             "#e86176", "Horizon lock roll correction",     |v| format!("{:.1}°", v),
-    LensCorrectionStrength,      "#e8ae61", "Lens correction strength",         |v| format!("{:.0}%", v * 100.0),
-
-    SmoothingParamTimeConstant,  "#94ea8e", "Max smoothness",                   |v| format!("{:.2}", v),
-    SmoothingParamTimeConstant2, "#89df82", "Max smoothness at high velocity",  |v| format!("{:.2}", v),
-    SmoothingParamSmoothness,    "#7ced74", "Smoothness",                       |v| format!("{:.2}", v),
-    SmoothingParamPitch,         "#59c451", "Smoothness pitch",                 |v| format!("{:.2}", v),
-    SmoothingParamRoll,          "#51c485", "Smoothness roll",                  |v| format!("{:.2}", v),
-    SmoothingParamYaw,           "#88c451", "Smoothness yaw",                   |v| format!("{:.2}", v),
-
-    VideoSpeed,                  "#f6e926", "Video speed",                      |v| format!("{:.1}%", v * 100.0),
+    Fov,                         "#8ee6ea", "FOV",                              (0.1, 10.0, 0.01),      |v| format!("{:.2}", v),
+    VideoRotation,               "#eae38e", "Video rotation",                   (-360.0, 360.0, 0.1),   |v| format!("{:.1}°", v),
+    ZoomingSpeed,                "#32e595", "Zooming speed",                    (0.1, 30.0, 0.1),       |v| format!("{:.2}s", v),
+    ZoomingCenterX,              "#6fefb6", "Zooming center offset X",          (-1.0, 1.0, 0.01),      |v| format!("{:.0}%", v * 100.0),
+    ZoomingCenterY,              "#5ddba2", "Zooming center offset Y",          (-1.0, 1.0, 0.01),      |v| format!("{:.0}%", v * 100.0),
+    MaxZoom,                     "#3fd6c4", "Max zoom",                         (1.0, 10.0, 0.01),      |v| format!("{:.2}x", v),
+    ZoomBorderTolerance,         "#2fb8a8", "Border tolerance",                 (0.0, 100.0, 0.1),      |v| format!("{:.1}%", v),
+    BackgroundMargin,            "#6e5ddb", "Background margin",                (0.0, 100.0, 1.0),      |v| format!("{:.0}%", v),
+    BackgroundFeather,           "#9d93e1", "Background feather",               (0.0, 100.0, 1.0),      |v| format!("{:.0}%", v),
+    LockHorizonAmount,           "#ed7789", "Horizon lock amount",              (0.0, 100.0, 1.0),      |v| format!("{:.0}%", v),
+    LockHorizonRoll,             "#e86176", "Horizon lock roll correction",     (-180.0, 180.0, 0.1),   |v| format!("{:.1}°", v),
+    LockHorizonPitchAmount,      "#d9566b", "Horizon lock pitch amount",        (0.0, 100.0, 1.0),      |v| format!("{:.0}%", v),
+    LensCorrectionStrength,      "#e8ae61", "Lens correction strength",         (0.0, 1.0, 0.01),       |v| format!("{:.0}%", v * 100.0),
+    StabilizationAmount,         "#d97b52", "Stabilization amount",             (0.0, 1.0, 0.01),       |v| format!("{:.0}%", v * 100.0),
+    OutputProjectionStrength,    "#c9a0e8", "Lens look strength",               (0.0, 1.0, 0.01),       |v| format!("{:.0}%", v * 100.0),
+
+    SmoothingParamTimeConstant,  "#94ea8e", "Max smoothness",                   (0.01, 10.0, 0.01),     |v| format!("{:.2}", v),
+    SmoothingParamTimeConstant2, "#89df82", "Max smoothness at high velocity",  (0.01, 10.0, 0.01),     |v| format!("{:.2}", v),
+    SmoothingParamSmoothness,    "#7ced74", "Smoothness",                       (0.001, 1.0, 0.001),    |v| format!("{:.2}", v),
+    SmoothingParamPitch,         "#59c451", "Smoothness pitch",                 (-180.0, 180.0, 0.001), |v| format!("{:.2}", v),
+    SmoothingParamRoll,          "#51c485", "Smoothness roll",                  (-180.0, 180.0, 0.001), |v| format!("{:.2}", v),
+    SmoothingParamYaw,           "#88c451", "Smoothness yaw",                   (-180.0, 180.0, 0.001), |v| format!("{:.2}", v),
+
+    VideoSpeed,                  "#f6e926", "Video speed",                      (0.1, 10.0, 0.01),      |v| format!("{:.1}%", v * 100.0),
 }
 
 #[derive(Default, Debug, Copy, Clone, PartialEq, PartialOrd, Eq, Ord, ::serde::Serialize, ::serde::Deserialize)]
@@ -53,6 +90,52 @@ pub enum Easing {
     EaseInOut
 }
 
+// What happens to the keyframes and sync points outside of the new range when the trim range changes
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TrimPolicy {
+    #[default]
+    Keep,
+    Delete,  // Everything outside of the new range is removed
+    Rescale, // Everything is moved proportionally from the old range to the new one
+}
+impl From<&str> for TrimPolicy {
+    fn from(v: &str) -> Self {
+        match v {
+            "delete"  => Self::Delete,
+            "rescale" => Self::Rescale,
+            _ => Self::Keep
+        }
+    }
+}
+
+/// Applies `policy` to the timestamps of `map` for the trim range change from `old` to `new` (both in the key units).
+/// Returns the new map and the number of removed or moved entries. If rescaling would move two entries to the same timestamp, nothing is moved
+pub fn retime_trimmed<V: Clone>(map: &BTreeMap<i64, V>, old: (i64, i64), new: (i64, i64), policy: TrimPolicy) -> (BTreeMap<i64, V>, usize) {
+    match policy {
+        TrimPolicy::Keep => (map.clone(), 0),
+        TrimPolicy::Delete => {
+            let ret: BTreeMap<i64, V> = map.iter().filter(|(ts, _)| (new.0..=new.1).contains(ts)).map(|(k, v)| (*k, v.clone())).collect();
+            let removed = map.len() - ret.len();
+            (ret, removed)
+        },
+        TrimPolicy::Rescale => {
+            if old.1 <= old.0 || old == new { return (map.clone(), 0); }
+            let scale = (new.1 - new.0) as f64 / (old.1 - old.0) as f64;
+            let mut moved = 0;
+            let ret: BTreeMap<i64, V> = map.iter().map(|(ts, v)| {
+                let new_ts = new.0 + ((ts - old.0) as f64 * scale).round() as i64;
+                if new_ts != *ts { moved += 1; }
+                (new_ts, v.clone())
+            }).collect();
+            if ret.len() != map.len() {
+                log::warn!("Rescaling to the new trim range would move {} entries to the same timestamps, keeping them unchanged", map.len() - ret.len());
+                return (map.clone(), 0);
+            }
+            (ret, moved)
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone, Default, ::serde::Serialize, ::serde::Deserialize)]
 pub struct Keyframe {
     pub value: f64,
@@ -189,6 +272,95 @@ impl KeyframeManager {
                 .min_by_key(|(_nt, nts, _nk)| (nts - ts).abs())
         }
     }
+
+    /// Applies the trim policy to all keyframes, the ranges are video timestamps in ms. Returns the number of changed keyframes
+    pub fn apply_trim(&mut self, old_ms: (f64, f64), new_ms: (f64, f64), policy: TrimPolicy) -> usize {
+        let scale = self.timestamp_scale.unwrap_or(1.0);
+        let to_key = |(from, to): (f64, f64)| ((from * 1000.0 * scale).round() as i64, (to * 1000.0 * scale).round() as i64);
+        let mut changed = 0;
+        for kfs in self.keyframes.values_mut() {
+            let (retimed, count) = retime_trimmed(kfs, to_key(old_ms), to_key(new_ms), policy);
+            *kfs = retimed;
+            changed += count;
+        }
+        changed
+    }
+
+    pub fn get_checksum(&self, typ: &KeyframeType) -> u64 {
+        use std::hash::Hasher;
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        for (ts, kf) in self.keyframes.get(typ).into_iter().flatten() {
+            hasher.write_i64(*ts);
+            hasher.write_u64(kf.value.to_bits());
+            hasher.write_u8(kf.easing as u8);
+        }
+        hasher.write_u64(self.timestamp_scale.unwrap_or(1.0).to_bits());
+        hasher.finish()
+    }
+
+    pub fn time_remap(&self, default_speed: f64, duration_ms: f64) -> TimeRemap {
+        TimeRemap::new(self, default_speed, duration_ms)
+    }
+}
+
+// Mapping between the source video time and the output time when the video speed is changed (possibly keyframed).
+// Output time is the integral of 1/speed over the source time, computed with the trapezoidal rule on a fixed grid
+#[derive(Default, Clone, Debug)]
+pub struct TimeRemap {
+    step_ms: f64,
+    output_ms: Vec<f64>, // Output time at each grid point of the source time
+    last_speed: f64,
+    duration_ms: f64,
+}
+
+impl TimeRemap {
+    pub fn new(keyframes: &KeyframeManager, default_speed: f64, duration_ms: f64) -> Self {
+        let speed_at = |ts: f64| keyframes.value_at_video_timestamp(&KeyframeType::VideoSpeed, ts).unwrap_or(default_speed).max(0.01);
+
+        let duration_ms = duration_ms.max(0.0);
+        let step_ms = (duration_ms / 100_000.0).max(1.0);
+        let steps = (duration_ms / step_ms).ceil() as usize;
+
+        let mut output_ms = Vec::with_capacity(steps + 1);
+        output_ms.push(0.0);
+        let mut prev_inv = 1.0 / speed_at(0.0);
+        for i in 1..=steps {
+            let inv = 1.0 / speed_at(i as f64 * step_ms);
+            output_ms.push(output_ms[i - 1] + (prev_inv + inv) / 2.0 * step_ms);
+            prev_inv = inv;
+        }
+        Self { step_ms, output_ms, last_speed: 1.0 / prev_inv, duration_ms }
+    }
+
+    pub fn source_to_output(&self, source_ms: f64) -> f64 {
+        if self.output_ms.len() < 2 { return source_ms / self.last_speed.max(0.01); }
+        let pos = (source_ms / self.step_ms).max(0.0);
+        let i = (pos.floor() as usize).min(self.output_ms.len() - 2);
+        let alpha = pos - i as f64;
+        // Beyond the end, continue with the last speed
+        if alpha > 1.0 {
+            return self.output_ms[i + 1] + (alpha - 1.0) * self.step_ms / self.last_speed;
+        }
+        self.output_ms[i] * (1.0 - alpha) + self.output_ms[i + 1] * alpha
+    }
+
+    pub fn output_to_source(&self, output_ms: f64) -> f64 {
+        let last = match self.output_ms.last() {
+            Some(v) if self.output_ms.len() >= 2 => *v,
+            _ => return output_ms * self.last_speed
+        };
+        if output_ms >= last {
+            return (self.output_ms.len() - 1) as f64 * self.step_ms + (output_ms - last) * self.last_speed;
+        }
+        let i = self.output_ms.partition_point(|x| *x <= output_ms).max(1) - 1;
+        let span = self.output_ms[i + 1] - self.output_ms[i];
+        let alpha = if span > 0.0 { (output_ms - self.output_ms[i]) / span } else { 0.0 };
+        (i as f64 + alpha.clamp(0.0, 1.0)) * self.step_ms
+    }
+
+    pub fn output_duration_ms(&self) -> f64 {
+        self.source_to_output(self.duration_ms)
+    }
 }
 
 impl FromStr for KeyframeType {
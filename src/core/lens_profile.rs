@@ -8,6 +8,7 @@ use serde::{ Serialize, Deserialize };
 use crate::keyframes::KeyframeManager;
 
 use super::zooming;
+use crate::i18n::Message;
 
 #[cfg(feature = "opencv")]
 use super::LensCalibrator;
@@ -64,6 +65,8 @@ pub struct LensProfile {
 
     pub distortion_model_id: i32,
 
+    pub rig_rotation: Option<crate::calibration::rig::RigRotation>, // Rotation to the second camera of a rig, applied as the extrinsic rotation of the motion data
+
     #[serde(skip)]
     pub filename: String,
 
@@ -104,13 +107,15 @@ impl LensProfile {
         if self.input_horizontal_stretch <= 0.01 { self.input_horizontal_stretch = 1.0; }
         if self.input_vertical_stretch   <= 0.01 { self.input_vertical_stretch   = 1.0; }
 
-        self.calib_dimension = Dimensions { w: cal.width, h: cal.height };
-        self.orig_dimension  = Dimensions { w: cal.width, h: cal.height };
+        let (w, h) = cal.calib_size();
+        self.calib_dimension = Dimensions { w, h };
+        self.orig_dimension  = Dimensions { w, h };
         self.num_images = cal.used_points.len();
         self.is_superview = cal.is_superview;
         self.optimal_fov = None;
 
         self.asymmetrical = cal.asymmetrical;
+        self.rig_rotation = cal.rig_rotation.clone();
 
         self.fisheye_params = CameraParams {
             RMS_error: cal.rms,
@@ -194,6 +199,33 @@ impl LensProfile {
         Ok(json)
     }
 
+    // Checks if the profile is complete and good enough to be submitted to the lens profile database.
+    // Returns a list of problems, empty if the profile is valid
+    pub fn validate_for_upload(&self) -> Vec<Message> {
+        const MAX_RMS: f64 = 5.0;
+        const MIN_IMAGES: usize = 5;
+
+        let mut errors = Vec::new();
+        if self.camera_brand.trim().is_empty() { errors.push(Message::new("lens.missing_brand")); }
+        if self.camera_model.trim().is_empty() { errors.push(Message::new("lens.missing_model")); }
+        if self.calib_dimension.w == 0 || self.calib_dimension.h == 0 { errors.push(Message::new("lens.missing_dimensions")); }
+        if self.fps <= 0.0 { errors.push(Message::new("lens.missing_fps")); }
+        if self.num_images < MIN_IMAGES { errors.push(Message::new("lens.not_enough_frames").arg(self.num_images).arg(MIN_IMAGES)); }
+
+        let params = &self.fisheye_params;
+        if params.camera_matrix.len() != 3 || params.distortion_coeffs.is_empty() {
+            errors.push(Message::new("lens.not_calibrated"));
+        } else if params.camera_matrix.iter().flatten().chain(params.distortion_coeffs.iter()).any(|x| !x.is_finite()) {
+            errors.push(Message::new("lens.invalid_coefficients"));
+        }
+        if params.RMS_error <= 0.0 || !params.RMS_error.is_finite() {
+            errors.push(Message::new("lens.missing_rms"));
+        } else if params.RMS_error > MAX_RMS {
+            errors.push(Message::new("lens.rms_too_high").arg(format!("{:.3}", params.RMS_error)).arg(MAX_RMS));
+        }
+        errors
+    }
+
     fn get_camera_matrix_internal(&self) -> Option<nalgebra::Matrix3<f64>> {
         if self.fisheye_params.camera_matrix.len() == 3 {
             let mut mat = nalgebra::Matrix3::from_rows(&[
@@ -2,16 +2,26 @@
 // Copyright © 2021-2022 Adrian <adrian.eddy at gmail>
 
 use walkdir::WalkDir;
-use std::collections::{ HashSet, HashMap, BTreeMap };
+use std::collections::{ HashSet, HashMap, BTreeMap, BTreeSet };
 use crate::LensProfile;
+use crate::camera_identifier::CameraIdentifier;
+use serde::{ Serialize, Deserialize };
+use serde_json::Value;
 use std::path::PathBuf;
 
 #[cfg(target_os = "android")]
 static LENS_PROFILES_STATIC: include_dir::Dir = include_dir::include_dir!("$CARGO_MANIFEST_DIR/../../resources/camera_presets/");
 
+#[cfg(not(target_os = "android"))]
+pub type ProfilesWatcher = notify::RecommendedWatcher;
+#[cfg(target_os = "android")]
+pub type ProfilesWatcher = ();
+
 #[derive(Default)]
 pub struct LensProfileDatabase {
     map: HashMap<String, LensProfile>,
+    // Newer official versions of locally modified profiles, waiting for the user's decision. Key is the path relative to `get_path()`
+    pub upstream_updates: BTreeMap<String, UpstreamUpdate>,
     loaded: bool
 }
 
@@ -52,6 +62,49 @@ impl LensProfileDatabase {
         std::fs::canonicalize(&candidates[0]).unwrap_or_default()
     }
 
+    // Calls `cb` when a profile was added, removed or modified, until the returned watcher is dropped.
+    // Saving a file produces several events, so `cb` is called once they stop coming for 500 ms
+    #[cfg(not(target_os = "android"))]
+    pub fn watch<F: Fn() + Send + 'static>(cb: F) -> Option<ProfilesWatcher> {
+        use notify::Watcher;
+        let (tx, rx) = std::sync::mpsc::channel();
+        let watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            if let Ok(event) = event {
+                let is_profile = event.paths.iter().any(|x| x.extension().map_or(false, |ext| ext == "json" || ext == "gyroflow"));
+                if is_profile && (event.kind.is_create() || event.kind.is_modify() || event.kind.is_remove()) {
+                    let _ = tx.send(());
+                }
+            }
+        });
+        let mut watcher = match watcher {
+            Ok(x) => x,
+            Err(e) => { log::error!("Failed to watch the lens profiles: {:?}", e); return None; }
+        };
+        if let Err(e) = watcher.watch(&Self::get_path(), notify::RecursiveMode::Recursive) {
+            log::error!("Failed to watch the lens profiles: {:?}", e);
+            return None;
+        }
+        std::thread::spawn(move || {
+            // The channel is closed when the watcher is dropped
+            while rx.recv().is_ok() {
+                loop {
+                    match rx.recv_timeout(std::time::Duration::from_millis(500)) {
+                        Ok(_) => { }
+                        Err(std::sync::mpsc::RecvTimeoutError::Timeout) => break,
+                        Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => return
+                    }
+                }
+                log::info!("Lens profiles changed on disk");
+                cb();
+            }
+        });
+        Some(watcher)
+    }
+    #[cfg(target_os = "android")]
+    pub fn watch<F: Fn() + Send + 'static>(_cb: F) -> Option<ProfilesWatcher> {
+        None // Profiles are built in
+    }
+
     pub fn load_all(&mut self) {
         log::info!("Lens profiles directory: {:?}", Self::get_path());
 
@@ -191,6 +244,66 @@ impl LensProfileDatabase {
         }
     }
 
+    // Profile for the camera. If there's no profile with exactly the same identifier, looks for the closest variant of the same camera model.
+    // Used for cameras where the clip identifier contains settings which the profiles don't specify, eg. DJI drones with multiple lenses or FOV modes.
+    // A variant is only picked when it matches better than all the others, otherwise the best candidates are returned for the user to choose
+    pub fn find_best_match(&self, id: &CameraIdentifier) -> Option<ProfileMatch> {
+        if !id.identifier.is_empty() && self.map.contains_key(&id.identifier) {
+            return Some(ProfileMatch::Exact(id.identifier.clone()));
+        }
+        fn normalize(s: &str) -> String { s.chars().filter(|c| c.is_alphanumeric()).collect::<String>().to_ascii_lowercase() }
+
+        let (brand, model) = (normalize(&id.brand), normalize(&id.model));
+        if brand.is_empty() || model.is_empty() { return None; }
+        let lens_words: Vec<String> = id.lens_info.split_whitespace().map(normalize).filter(|x| !x.is_empty()).collect();
+        let gimbal_mode = normalize(&id.gimbal_mode);
+        let video_ratio = id.video_width as f64 / id.video_height.max(1) as f64;
+
+        let mut candidates: Vec<(i32, &String)> = Vec::new();
+        for (key, profile) in &self.map {
+            if normalize(&profile.camera_brand) != brand || normalize(&profile.camera_model) != model { continue; }
+
+            let text = normalize(&format!("{} {} {} {}", profile.lens_model, profile.camera_setting, profile.note, profile.name));
+            let mut score = 0;
+            if !lens_words.is_empty() {
+                let matched = lens_words.iter().filter(|w| text.contains(w.as_str())).count() as i32;
+                if matched == 0 { continue; } // Different lens or FOV mode
+                score += 10 * matched;
+            }
+            if !gimbal_mode.is_empty() && text.contains(&gimbal_mode) { score += 3; }
+            let calib_ratio = profile.calib_dimension.w as f64 / profile.calib_dimension.h.max(1) as f64;
+            if id.video_width > 0 && (calib_ratio - video_ratio).abs() < 0.01 { score += 2; }
+            if profile.calib_dimension.w == id.video_width && profile.calib_dimension.h == id.video_height { score += 1; }
+
+            candidates.push((score, key));
+        }
+        candidates.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(b.1)));
+        match candidates.as_slice() {
+            [] => None,
+            [(_, key)] => Some(ProfileMatch::Closest((*key).clone())),
+            [(best, key), (second, _), ..] if best > second => Some(ProfileMatch::Closest((*key).clone())),
+            [(best, _), ..] => Some(ProfileMatch::Ambiguous(candidates.iter().filter(|(s, _)| s == best).map(|(_, k)| (*k).clone()).collect()))
+        }
+    }
+
+    // Finds an existing profile with the same calibration resolution and (nearly) identical coefficients
+    pub fn find_duplicate(&self, profile: &LensProfile) -> Option<&LensProfile> {
+        fn close(a: &[f64], b: &[f64]) -> bool {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| (a - b).abs() <= 1e-6 * a.abs().max(b.abs()).max(1.0))
+        }
+        let params = &profile.fisheye_params;
+        if params.camera_matrix.is_empty() { return None; }
+        let matrix: Vec<f64> = params.camera_matrix.iter().flatten().copied().collect();
+
+        self.map.values().find(|v| {
+            !v.is_copy &&
+            v.calib_dimension.w == profile.calib_dimension.w &&
+            v.calib_dimension.h == profile.calib_dimension.h &&
+            close(&v.fisheye_params.distortion_coeffs, &params.distortion_coeffs) &&
+            close(&v.fisheye_params.camera_matrix.iter().flatten().copied().collect::<Vec<f64>>(), &matrix)
+        })
+    }
+
     // -------------------------------------------------------------------
     // ---------------------- Maintenance functions ----------------------
     // -------------------------------------------------------------------
@@ -331,4 +444,167 @@ impl LensProfileDatabase {
             }
         }
     }
+
+    pub fn add_upstream_update(&mut self, rel_path: &str, sha: &str, content: String) {
+        self.upstream_updates.insert(rel_path.to_string(), UpstreamUpdate { sha: sha.to_string(), content });
+    }
+
+    // Returns (relative path, differing fields) of every pending update
+    pub fn upstream_update_diffs(&self) -> Vec<(String, Vec<ProfileFieldDiff>)> {
+        self.upstream_updates.iter().filter_map(|(rel_path, update)| {
+            let local: Value = serde_json::from_str(&std::fs::read_to_string(Self::get_path().join(rel_path)).ok()?).ok()?;
+            let upstream: Value = serde_json::from_str(&update.content).ok()?;
+            Some((rel_path.clone(), diff_profiles(&local, &upstream))).filter(|x| !x.1.is_empty())
+        }).collect()
+    }
+
+    // Takes the upstream value of `fields` (as returned by `diff_profiles`) and keeps the local value of everything else.
+    // With empty `fields` the local version is kept and the update is dismissed until the profile changes upstream again
+    pub fn apply_upstream_update(&mut self, rel_path: &str, fields: &[String]) -> std::io::Result<()> {
+        let update = self.upstream_updates.get(rel_path).ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, rel_path.to_string()))?;
+        let local_path = Self::get_path().join(rel_path);
+        if !fields.is_empty() {
+            let local: Value = serde_json::from_str(&std::fs::read_to_string(&local_path)?)?;
+            let upstream: Value = serde_json::from_str(&update.content)?;
+            if diff_profiles(&local, &upstream).iter().all(|x| fields.contains(&x.field)) {
+                std::fs::write(&local_path, &update.content)?;
+            } else {
+                std::fs::write(&local_path, serde_json::to_string_pretty(&merge_profiles(local, &upstream, fields))?)?;
+            }
+        }
+        let mut index = UpstreamIndex::load();
+        index.record(rel_path, &update.sha, update.content.as_bytes());
+        index.save();
+
+        self.upstream_updates.remove(rel_path);
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ProfileMatch {
+    Exact(String),          // Same identifier
+    Closest(String),        // Variant which matches the camera settings better than all the others
+    Ambiguous(Vec<String>), // Equally good variants, the user has to choose
+}
+
+#[derive(Clone, Debug)]
+pub struct UpstreamUpdate {
+    pub sha: String,
+    pub content: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ProfileFieldDiff {
+    pub field: String, // Path of the field, eg. "fisheye_params/camera_matrix"
+    pub local: Value,  // Null if the field doesn't exist
+    pub upstream: Value,
+}
+
+// True if the files contain different profiles. Formatting and number notation (1 vs 1.0) don't matter, files which aren't valid JSON are compared byte by byte
+pub fn profiles_differ(local: &[u8], upstream: &str) -> bool {
+    match (serde_json::from_slice::<Value>(local), serde_json::from_str::<Value>(upstream)) {
+        (Ok(local), Ok(upstream)) => !diff_profiles(&local, &upstream).is_empty(),
+        _ => local != upstream.as_bytes()
+    }
+}
+
+// Compares two profiles field by field. Objects are compared recursively, everything else (including arrays) as a whole value.
+// Numbers are compared by their value, so `1` and `1.0` are the same
+pub fn diff_profiles(local: &Value, upstream: &Value) -> Vec<ProfileFieldDiff> {
+    fn same_value(a: &Value, b: &Value) -> bool {
+        match (a, b) {
+            (Value::Number(a), Value::Number(b)) => a == b || a.as_f64() == b.as_f64(),
+            (Value::Array(a), Value::Array(b)) => a.len() == b.len() && a.iter().zip(b).all(|(a, b)| same_value(a, b)),
+            (Value::Object(a), Value::Object(b)) => a.len() == b.len() && a.iter().all(|(k, v)| b.get(k).map_or(false, |b| same_value(v, b))),
+            _ => a == b
+        }
+    }
+    fn walk(path: &str, a: &Value, b: &Value, ret: &mut Vec<ProfileFieldDiff>) {
+        match (a, b) {
+            (Value::Object(a), Value::Object(b)) => {
+                let keys: BTreeSet<&String> = a.keys().chain(b.keys()).collect();
+                for key in keys {
+                    let field = if path.is_empty() { key.clone() } else { format!("{}/{}", path, key) };
+                    walk(&field, a.get(key).unwrap_or(&Value::Null), b.get(key).unwrap_or(&Value::Null), ret);
+                }
+            },
+            _ if !same_value(a, b) => ret.push(ProfileFieldDiff { field: path.to_string(), local: a.clone(), upstream: b.clone() }),
+            _ => { }
+        }
+    }
+    let mut ret = Vec::new();
+    walk("", local, upstream, &mut ret);
+    ret
+}
+
+// Replaces `fields` in `local` with the values from `upstream`, fields missing in `upstream` are removed
+pub fn merge_profiles(mut local: Value, upstream: &Value, fields: &[String]) -> Value {
+    for field in fields {
+        let parts: Vec<&str> = field.split('/').collect();
+        let (last, parents) = match parts.split_last() { Some(x) => x, None => continue };
+        let new_value = parts.iter().try_fold(upstream, |v, k| v.get(*k)).cloned();
+
+        let mut target = Some(&mut local);
+        for key in parents {
+            target = target.and_then(|x| x.as_object_mut()).map(|x| x.entry(key.to_string()).or_insert_with(|| Value::Object(Default::default())));
+        }
+        if let Some(obj) = target.and_then(|x| x.as_object_mut()) {
+            match new_value {
+                Some(v) => { obj.insert(last.to_string(), v); },
+                None    => { obj.remove(*last); }
+            }
+        }
+    }
+    local
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UpstreamCheck {
+    UpToDate,
+    Download, // Missing or not modified locally, the upstream version can be written directly
+    Compare,  // Modified locally, the upstream version should be offered as an update
+}
+
+// Tracks which upstream (GitHub) version of each official profile is on disk, so updated profiles can be detected without downloading all of them.
+// Keys are paths relative to the profiles directory, values are (git blob sha, crc32 of the upstream content)
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+pub struct UpstreamIndex {
+    files: BTreeMap<String, (String, u32)>
+}
+
+impl UpstreamIndex {
+    fn path() -> PathBuf { LensProfileDatabase::get_path().join("upstream.index") }
+
+    pub fn load() -> Self {
+        std::fs::read_to_string(Self::path()).ok().and_then(|x| serde_json::from_str(&x).ok()).unwrap_or_default()
+    }
+    pub fn save(&self) {
+        if let Ok(json) = serde_json::to_string(self) {
+            if let Err(e) = std::fs::write(Self::path(), json) {
+                log::error!("Failed to save {:?}: {:?}", Self::path(), e);
+            }
+        }
+    }
+
+    pub fn check(&mut self, rel_path: &str, sha: &str, size: u64) -> UpstreamCheck {
+        let local = match std::fs::read(LensProfileDatabase::get_path().join(rel_path)) {
+            Ok(x) => x,
+            Err(_) => return UpstreamCheck::Download
+        };
+        match self.files.get(rel_path) {
+            Some((s, _)) if s == sha => UpstreamCheck::UpToDate,
+            Some((_, crc)) if *crc == crc32fast::hash(&local) => UpstreamCheck::Download,
+            Some(_) => UpstreamCheck::Compare,
+            // Not tracked yet (eg. bundled with the app), assume it's the upstream version if the size matches
+            None if local.len() as u64 == size => {
+                self.record(rel_path, sha, &local);
+                UpstreamCheck::UpToDate
+            },
+            None => UpstreamCheck::Compare
+        }
+    }
+    pub fn record(&mut self, rel_path: &str, sha: &str, content: &[u8]) {
+        self.files.insert(rel_path.to_string(), (sha.to_string(), crc32fast::hash(content)));
+    }
 }
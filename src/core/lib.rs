@@ -19,10 +19,30 @@ pub mod filtering;
 pub mod gpu;
 
 pub mod util;
+pub mod filesystem;
+pub mod preview_quality;
+pub mod job_manager;
+pub mod error;
+pub mod i18n;
 pub mod stabilization_params;
+pub mod black_bars;
+pub mod analysis;
+pub mod chart_pyramid;
+pub mod output_template;
+pub mod parameter_layers;
+pub mod benchmark;
+pub mod determinism;
+pub mod axis_remap;
+pub mod parameter_journal;
+pub mod duplicate_frames;
+pub mod low_memory;
+pub mod pipeline;
+pub mod log_slice;
+pub mod project_diff;
 
 use std::sync::{ Arc, atomic::{ AtomicU64, AtomicBool, Ordering::SeqCst } };
 use std::path::PathBuf;
+use std::str::FromStr;
 use keyframes::*;
 use parking_lot::{ RwLock, RwLockUpgradableReadGuard };
 use nalgebra::Vector4;
@@ -34,6 +54,9 @@ use smoothing::Smoothing;
 use stabilization::Stabilization;
 use zooming::ZoomingAlgorithm;
 use camera_identifier::CameraIdentifier;
+use parameter_layers::{ ParameterLayers, ParameterSource };
+use parameter_journal::ParameterJournal;
+use job_manager::JobManager;
 pub use stabilization::PixelType;
 use gpu::{ BufferDescription, BufferSource };
 
@@ -51,7 +74,8 @@ lazy_static::lazy_static! {
 pub struct InputFile {
     pub path: String,
     pub image_sequence_fps: f64,
-    pub image_sequence_start: i32
+    pub image_sequence_start: i32,
+    pub video_filter: String, // ffmpeg filtergraph applied to the decoded frames before stabilization, eg. "hqdn3d=4"
 }
 
 pub struct StabilizationManager<T: PixelType> {
@@ -77,6 +101,18 @@ pub struct StabilizationManager<T: PixelType> {
 
     pub keyframes: Arc<RwLock<KeyframeManager>>,
 
+    pub parameter_layers: Arc<RwLock<ParameterLayers>>,
+
+    pub journal: Arc<RwLock<ParameterJournal>>,
+
+    // Horizon lock amount keyframes set by `apply_horizon_lock_fallback`: timestamp -> (replaced keyframe, value)
+    horizon_lock_fallback: Arc<RwLock<std::collections::BTreeMap<i64, (Option<keyframes::Keyframe>, f64)>>>,
+
+    // Last `video_time_remap` with the checksum of its inputs
+    time_remap: Arc<RwLock<Option<(u64, Arc<keyframes::TimeRemap>)>>>,
+
+    pub jobs: Arc<JobManager>,
+
     pub params: Arc<RwLock<StabilizationParams>>
 }
 
@@ -108,7 +144,17 @@ impl<T: PixelType> Default for StabilizationManager<T> {
 
             keyframes: Arc::new(RwLock::new(KeyframeManager::new())),
 
+            parameter_layers: Arc::new(RwLock::new(ParameterLayers::default())),
+
+            journal: Arc::new(RwLock::new(ParameterJournal::default())),
+
+            horizon_lock_fallback: Arc::new(RwLock::new(std::collections::BTreeMap::new())),
+
+            time_remap: Arc::new(RwLock::new(None)),
+
             camera_id: Arc::new(RwLock::new(None)),
+
+            jobs: Arc::new(JobManager::default()),
         }
     }
 }
@@ -122,6 +168,8 @@ impl<T: PixelType> StabilizationManager<T> {
             params.duration_ms = duration_ms;
             params.video_size = video_size;
         }
+        // Black bars are detected again for the new video
+        self.set_input_crop(None);
 
         self.pose_estimator.sync_results.write().clear();
         self.keyframes.write().clear();
@@ -149,19 +197,46 @@ impl<T: PixelType> StabilizationManager<T> {
             }
         };
 
-        let (fps, size) = {
+        let (fps, size, duration_ms) = {
             let params = self.params.read();
-            (params.fps, params.video_size)
+            (params.fps, params.video_size, params.duration_ms)
         };
 
         let cancel_flag2 = cancel_flag.clone();
         let mut md = GyroSource::parse_telemetry_file(path, size, fps, progress_cb, cancel_flag2)?;
+
+        // External log which may span multiple videos
+        let video_path = self.input_file.read().path.clone();
+        let log_slice = if !video_path.is_empty() && video_path != path {
+            let saved = self.gyro.read().log_slice.clone();
+            md.time_range_ms().and_then(|range| {
+                let slice = log_slice::find(path, &video_path, range, duration_ms, saved.as_ref())?;
+                if slice.source == log_slice::AnchorSource::Manual {
+                    return Some(log_slice::anchor(path, &video_path, range, slice.start_ms));
+                }
+                Some(slice)
+            })
+        } else {
+            None
+        };
+        if let Some(ref slice) = log_slice {
+            if log_slice::is_exact(slice.source) {
+                md.slice(slice.start_ms, duration_ms);
+            }
+        }
+        self.gyro.write().log_slice = log_slice.clone();
+
         if md.detected_source.as_ref().map(|v| v.starts_with("GoPro ")).unwrap_or_default() {
             // If gopro reports rolling shutter value, it already applied it, ie. the video is already corrected
             md.frame_readout_time = None;
         }
         if !cancel_flag.load(SeqCst) {
-            self.gyro.write().load_from_telemetry(&md);
+            let mut gyro = self.gyro.write();
+            gyro.load_from_telemetry(&md);
+            // Whole log kept, the guessed start is only the initial offset for the synchronization
+            if let Some(slice) = log_slice.filter(|x| !log_slice::is_exact(x.source)) {
+                gyro.set_offset(0, -slice.start_ms);
+            }
         }
         self.params.write().frame_readout_time = md.frame_readout_time.unwrap_or_default();
         let quats = self.gyro.read().quaternions.clone();
@@ -185,6 +260,16 @@ impl<T: PixelType> StabilizationManager<T> {
         Ok(md)
     }
 
+    /// Sets the video start in the timeline of a log spanning multiple videos, and loads the log again
+    pub fn set_log_anchor(&self, start_ms: f64, cancel_flag: Arc<AtomicBool>) -> std::io::Result<()> {
+        let path = {
+            let mut gyro = self.gyro.write();
+            gyro.log_slice = Some(log_slice::LogSlice { log_path: gyro.file_path.clone(), start_ms, source: log_slice::AnchorSource::Manual });
+            gyro.file_path.clone()
+        };
+        self.load_gyro_data(&path, |_| (), cancel_flag).map(|_| ())
+    }
+
     pub fn load_lens_profile(&self, path: &str) -> Result<(), serde_json::Error> {
         let db = self.lens_profile_db.read();
         if let Some(lens) = db.get_by_id(path) {
@@ -244,7 +329,7 @@ impl<T: PixelType> StabilizationManager<T> {
         false
     }
 
-    pub fn recompute_adaptive_zoom_static(zoom: &Box<dyn ZoomingAlgorithm>, params: &RwLock<StabilizationParams>, keyframes: &KeyframeManager) -> Vec<f64> {
+    pub fn recompute_adaptive_zoom_static(zoom: &Box<dyn ZoomingAlgorithm>, params: &RwLock<StabilizationParams>, keyframes: &KeyframeManager) -> zooming::ZoomCurve {
         let (window, frames, fps) = {
             let params = params.read();
             (params.adaptive_zoom_window, params.frame_count, params.get_scaled_fps())
@@ -256,20 +341,127 @@ impl<T: PixelType> StabilizationManager<T> {
             }
 
             let fovs = zoom.compute(&timestamps, &keyframes);
-            fovs.iter().map(|v| v.0).collect()
+            zooming::ZoomCurve::new(&timestamps, &fovs)
         } else {
-            Vec::new()
+            zooming::ZoomCurve::default()
         }
     }
     pub fn recompute_adaptive_zoom(&self) {
         let params = stabilization::ComputeParams::from_manager(self, false);
         let lens_fov_adjustment = params.lens_fov_adjustment;
         let mut zoom = zooming::from_compute_params(params);
-        let fovs = Self::recompute_adaptive_zoom_static(&mut zoom, &self.params, &self.keyframes.read());
+        let curve = Self::recompute_adaptive_zoom_static(&mut zoom, &self.params, &self.keyframes.read());
 
         let mut stab_params = self.params.write();
-        stab_params.set_fovs(fovs, lens_fov_adjustment);
+        stab_params.set_fovs(curve.fovs.clone(), lens_fov_adjustment);
         stab_params.zooming_debug_points = zoom.get_debug_points();
+        stab_params.zoom_curve = curve;
+    }
+
+    pub fn analyze_clip(&self, cancel_flag: Arc<AtomicBool>) -> Option<analysis::ClipAnalysis> {
+        let params = stabilization::ComputeParams::from_manager(self, true);
+        analysis::ClipAnalysis::compute(&params, cancel_flag)
+    }
+    pub fn suggest_smoothing(&self) -> analysis::SmoothingSuggestion {
+        let params = stabilization::ComputeParams::from_manager(self, true);
+        analysis::suggest_smoothing(&params)
+    }
+    /// Trim range without the handling at the start and end of the clip, see `analysis::suggest_trim`
+    pub fn suggest_trim(&self) -> Option<analysis::TrimSuggestion> {
+        let duration_ms = self.params.read().get_scaled_duration_ms();
+        analysis::suggest_trim(&self.gyro.read(), duration_ms)
+    }
+    /// Highest smoothing of the current algorithm which doesn't crop more than `target_crop` percent anywhere in the trim range.
+    /// Doesn't change the current settings
+    pub fn search_smoothness<F: Fn(f64)>(&self, target_crop: f64, progress_cb: F, cancel_flag: Arc<AtomicBool>) -> Option<analysis::SmoothnessSearch> {
+        let params = stabilization::ComputeParams::from_manager(self, true);
+        let (alg, horizon_lock) = {
+            let smoothing = self.smoothing.read();
+            (smoothing.current().clone(), smoothing.horizon_lock.clone())
+        };
+        let stab_params = self.params.read().clone();
+        let keyframes = self.keyframes.read().clone();
+        analysis::search_smoothness(&params, alg.as_ref(), horizon_lock, &stab_params, &keyframes, target_crop, progress_cb, cancel_flag)
+    }
+
+    /// None if the horizon isn't locked with gravity vectors or there are no optical flow results to compare with
+    pub fn check_horizon_lock_stability(&self) -> Option<analysis::HorizonStability> {
+        if !self.smoothing.read().horizon_lock.lock_enabled { return None; }
+        let gyro = self.gyro.read();
+        if !gyro.use_gravity_vectors { return None; }
+        let sync_results = self.pose_estimator.sync_results.read();
+        analysis::horizon_lock_stability(&gyro, &sync_results)
+    }
+    /// Whether keyframes of `apply_horizon_lock_fallback` are currently set
+    pub fn has_horizon_lock_fallback(&self) -> bool {
+        !self.horizon_lock_fallback.read().is_empty()
+    }
+    /// Blends the horizon lock toward the unlocked orientation where the gravity drifts, using horizon lock amount keyframes.
+    /// The keyframes of the previous call are replaced, so it can be applied again after every sync
+    pub fn apply_horizon_lock_fallback(&self, report: &analysis::HorizonStability) {
+        const RAMP_US: i64 = 500_000; // Transition between the locked and the blended horizon
+
+        // Drifts closer than the transitions are blended together
+        let mut ranges: Vec<(i64, i64, f64)> = Vec::new();
+        for d in &report.drifting {
+            let (start, end) = ((d.start_ms * 1000.0).round() as i64, (d.end_ms * 1000.0).round() as i64);
+            match ranges.last_mut() {
+                Some(last) if start - last.1 < RAMP_US * 2 => { last.1 = end; last.2 = last.2.min(d.lock_factor); },
+                _ => ranges.push((start, end, d.lock_factor))
+            }
+        }
+        let typ = KeyframeType::LockHorizonAmount;
+        let amount = self.smoothing.read().horizon_lock.horizonlockpercent;
+        {
+            let mut keyframes = self.keyframes.write();
+            let mut applied = self.horizon_lock_fallback.write();
+            // Restore the keyframes from before the previous fallback, unless they were edited since
+            for (ts, (replaced, value)) in std::mem::take(&mut *applied) {
+                if keyframes.get_keyframes(&typ).and_then(|x| x.get(&ts)).map(|x| x.value) != Some(value) { continue; }
+                keyframes.remove(&typ, ts);
+                if let Some(kf) = replaced {
+                    keyframes.set(&typ, ts, kf.value);
+                    keyframes.set_easing(&typ, ts, kf.easing);
+                }
+            }
+
+            let amount_at = |kf: &KeyframeManager, ts: i64| kf.value_at_video_timestamp(&KeyframeType::LockHorizonAmount, ts as f64 / 1000.0).unwrap_or(amount);
+            let values: Vec<_> = ranges.iter().map(|(start, end, factor)| {
+                (amount_at(&keyframes, start - RAMP_US), amount_at(&keyframes, start + (end - start) / 2) * factor, amount_at(&keyframes, end + RAMP_US))
+            }).collect();
+            for ((start, end, _), (before, reduced, after)) in ranges.into_iter().zip(values) {
+                for (ts, value) in [((start - RAMP_US).max(0), before), (start, reduced), (end, reduced), (end + RAMP_US, after)] {
+                    let replaced = keyframes.get_keyframes(&typ).and_then(|x| x.get(&ts)).copied();
+                    applied.entry(ts).or_insert((replaced, value)).1 = value;
+                    keyframes.set(&typ, ts, value);
+                }
+            }
+        }
+        self.keyframes_updated(&typ);
+    }
+
+    // Variables of the output filename template which come from the project, see `output_template`
+    pub fn output_template_vars(&self) -> output_template::TemplateVars {
+        let mut vars = output_template::TemplateVars::new();
+        let path = self.input_file.read().path.clone();
+        let basename = std::path::Path::new(&path).file_stem().map(|x| x.to_string_lossy().to_string()).unwrap_or_default();
+        vars.insert("basename", basename);
+
+        let now = time::OffsetDateTime::now_local().unwrap_or_else(|_| time::OffsetDateTime::now_utc());
+        vars.insert("date", now.date().to_string());
+        vars.insert("time", format!("{:02}{:02}{:02}", now.hour(), now.minute(), now.second()));
+
+        {
+            let smoothing_lock = self.smoothing.read();
+            let smoothing = smoothing_lock.current();
+            vars.insert("method", smoothing.get_name());
+            let smoothness = smoothing.get_parameters_json().as_array().and_then(|arr| {
+                arr.iter().find(|x| x.get("name").and_then(|x| x.as_str()) == Some("smoothness"))?.get("value")?.as_f64()
+            });
+            vars.insert("smoothness", smoothness.map(|x| format!("{:.2}", x)).unwrap_or_default());
+        }
+        vars.insert("fps", format!("{:.3}", self.params.read().fps).trim_end_matches('0').trim_end_matches('.').to_string());
+        vars
     }
 
     pub fn recompute_smoothness(&self) {
@@ -315,12 +507,15 @@ impl<T: PixelType> StabilizationManager<T> {
         let zooming_checksum = self.zooming_checksum.clone();
 
         let stabilization = self.stabilization.clone();
+        let jobs = self.jobs.clone();
         THREAD_POOL.spawn(move || {
             // std::thread::sleep(std::time::Duration::from_millis(20));
+            jobs.wait_for_compute_slot(250, || current_compute_id.load(SeqCst) != compute_id);
             if current_compute_id.load(SeqCst) != compute_id { return cb((compute_id, true)); }
 
             let mut smoothing_changed = false;
-            if smoothing.read().get_state_checksum() != smoothing_checksum.load(SeqCst) {
+            // The cached smoothing only tracks the algorithm state, in deterministic mode the result can't depend on the previous computations
+            if determinism::is_enabled() || smoothing.read().get_state_checksum() != smoothing_checksum.load(SeqCst) {
                 let (mut smoothing, horizon_lock) = {
                     let lock = smoothing.read();
                     (lock.current().clone(), lock.horizon_lock.clone())
@@ -342,13 +537,15 @@ impl<T: PixelType> StabilizationManager<T> {
 
             let mut zoom = zooming::from_compute_params(params.clone());
             if smoothing_changed || zooming::get_checksum(&zoom) != zooming_checksum.load(SeqCst) {
-                params.fovs = Self::recompute_adaptive_zoom_static(&mut zoom, &stabilization_params, &keyframes);
+                let curve = Self::recompute_adaptive_zoom_static(&mut zoom, &stabilization_params, &keyframes);
+                params.fovs = curve.fovs.clone();
 
                 if current_compute_id.load(SeqCst) != compute_id { return cb((compute_id, true)); }
 
                 let mut stab_params = stabilization_params.write();
                 stab_params.set_fovs(params.fovs.clone(), params.lens_fov_adjustment);
                 stab_params.zooming_debug_points = zoom.get_debug_points();
+                stab_params.zoom_curve = curve;
             }
 
             if current_compute_id.load(SeqCst) != compute_id { return cb((compute_id, true)); }
@@ -546,6 +743,39 @@ impl<T: PixelType> StabilizationManager<T> {
     pub fn set_trim_start(&self, v: f64) { self.params.write().trim_start = v; self.invalidate_smoothing(); }
     pub fn set_trim_end  (&self, v: f64) { self.params.write().trim_end   = v; self.invalidate_smoothing(); }
 
+    /// Changes the trim range and applies the policies to the keyframes and sync points in one step.
+    /// Returns the number of removed or moved (keyframes, sync points)
+    pub fn set_trim_range(&self, start: f64, end: f64, keyframes_policy: TrimPolicy, offsets_policy: TrimPolicy) -> (usize, usize) {
+        let (old, duration_ms) = {
+            let params = self.params.read();
+            ((params.trim_start, params.trim_end), params.duration_ms)
+        };
+        let mut changed = (0, 0);
+        if old != (start, end) && duration_ms > 0.0 {
+            let old_ms = (old.0 * duration_ms, old.1 * duration_ms);
+            let new_ms = (start * duration_ms, end * duration_ms);
+            let to_us = |(from, to): (f64, f64)| ((from * 1000.0).round() as i64, (to * 1000.0).round() as i64);
+
+            let mut gyro = self.gyro.write();
+            let mut keyframes = self.keyframes.write();
+            changed.0 = keyframes.apply_trim(old_ms, new_ms, keyframes_policy);
+
+            let (offsets, count) = retime_trimmed(gyro.get_offsets(), to_us(old_ms), to_us(new_ms), offsets_policy);
+            if count > 0 {
+                gyro.set_offsets(offsets);
+                keyframes.update_gyro(&gyro);
+                changed.1 = count;
+            }
+        }
+        {
+            let mut params = self.params.write();
+            params.trim_start = start;
+            params.trim_end = end;
+        }
+        self.invalidate_smoothing();
+        changed
+    }
+
     pub fn set_of_method(&self, v: u32) { self.params.write().of_method = v; self.pose_estimator.clear(); }
     pub fn set_show_detected_features(&self, v: bool) { self.params.write().show_detected_features = v; }
     pub fn set_show_optical_flow     (&self, v: bool) { self.params.write().show_optical_flow      = v; }
@@ -554,14 +784,55 @@ impl<T: PixelType> StabilizationManager<T> {
     pub fn set_adaptive_zoom         (&self, v: f64)  { self.params.write().adaptive_zoom_window   = v; self.invalidate_zooming(); }
     pub fn set_zooming_center_x      (&self, v: f64)  { self.params.write().adaptive_zoom_center_offset.0 = v; self.invalidate_zooming(); }
     pub fn set_zooming_center_y      (&self, v: f64)  { self.params.write().adaptive_zoom_center_offset.1 = v; self.invalidate_zooming(); }
-    pub fn set_fov                   (&self, v: f64)  { self.params.write().fov                    = v; }
-    pub fn set_lens_correction_amount(&self, v: f64)  { self.params.write().lens_correction_amount = v; self.invalidate_zooming(); }
+    pub fn set_max_zoom              (&self, v: f64)  { if let Some(v) = Self::validated_max_zoom(v) { self.params.write().max_zoom = v; self.invalidate_zooming(); } }
+    pub fn set_zoom_border_tolerance (&self, v: f64)  { if let Some(v) = Self::validated(&KeyframeType::ZoomBorderTolerance, v) { self.params.write().zoom_border_tolerance = v; self.invalidate_zooming(); } }
+    pub fn set_fov                   (&self, v: f64)  { if let Some(v) = Self::validated(&KeyframeType::Fov, v) { self.params.write().fov = v; } }
+    pub fn set_lens_correction_amount(&self, v: f64)  { if let Some(v) = Self::validated(&KeyframeType::LensCorrectionStrength, v) { self.params.write().lens_correction_amount = v; self.invalidate_zooming(); } }
+    pub fn set_stabilization_amount  (&self, v: f64)  { if let Some(v) = Self::validated(&KeyframeType::StabilizationAmount, v) { self.params.write().stabilization_amount = v; self.invalidate_zooming(); } }
+    pub fn set_output_projection     (&self, v: i32)  { self.params.write().output_projection = stabilization_params::OutputProjection::from(v); }
+    pub fn set_output_projection_strength(&self, v: f64) { if let Some(v) = Self::validated(&KeyframeType::OutputProjectionStrength, v) { self.params.write().output_projection_strength = v; } }
     pub fn set_background_mode       (&self, v: i32)  { self.params.write().background_mode = stabilization_params::BackgroundMode::from(v); }
-    pub fn set_background_margin     (&self, v: f64)  { self.params.write().background_margin = v; }
-    pub fn set_background_margin_feather(&self, v: f64) { self.params.write().background_margin_feather = v; }
+    pub fn set_background_margin     (&self, v: f64)  { if let Some(v) = Self::validated(&KeyframeType::BackgroundMargin, v) { self.params.write().background_margin = v; } }
+    pub fn set_background_margin_feather(&self, v: f64) { if let Some(v) = Self::validated(&KeyframeType::BackgroundFeather, v) { self.params.write().background_margin_feather = v; } }
     pub fn set_input_horizontal_stretch (&self, v: f64) { self.lens.write().input_horizontal_stretch = v; self.invalidate_zooming(); }
     pub fn set_input_vertical_stretch   (&self, v: f64) { self.lens.write().input_vertical_stretch   = v; self.invalidate_zooming(); }
 
+    pub fn set_kernel_precision(&self, v: i32) {
+        let precision = gpu::KernelPrecision::from(v);
+        self.params.write().kernel_precision = precision;
+        self.stabilization.write().set_kernel_precision(precision);
+    }
+    pub fn set_deterministic(&self, v: bool) {
+        if determinism::is_enabled() != v {
+            determinism::set_enabled(v);
+            self.invalidate_smoothing();
+        }
+    }
+
+    /// Runs `f` and adds the parameters it changed to the journal, `args` are the debug formatted arguments of the setter
+    pub fn record_change<R>(&self, setter: &str, args: &[String], f: impl FnOnce() -> R) -> R {
+        let before = self.journal_snapshot();
+        let ret = f();
+        let changes = project_diff::diff(&before, &self.journal_snapshot());
+        self.journal.write().record(setter, args.join(", "), changes);
+        ret
+    }
+    // Parameters compared by the journal, in the same layout as the project file
+    fn journal_snapshot(&self) -> serde_json::Value {
+        let mut ret = self.parameters_json();
+        {
+            let params = self.params.read();
+            ret["trim_start"] = params.trim_start.into();
+            ret["trim_end"] = params.trim_end.into();
+            ret["input_crop"] = serde_json::json!(params.input_crop);
+        }
+        ret["videofile"] = self.input_file.read().path.clone().into();
+        ret["lens_profile"] = self.lens.read().filename.clone().into();
+        ret["offsets"] = serde_json::json!(self.gyro.read().get_offsets());
+        ret["keyframes"] = self.keyframes.read().serialize();
+        ret
+    }
+
     pub fn set_video_speed(&self, v: f64, link_with_smoothness: bool, link_with_zooming: bool) {
         let mut params = self.params.write();
         params.video_speed = v;
@@ -569,6 +840,28 @@ impl<T: PixelType> StabilizationManager<T> {
         params.video_speed_affects_zooming = link_with_zooming;
         self.invalidate_smoothing();
     }
+    // Source video time <-> output time mapping, taking the (keyframed) video speed into account.
+    // It's rebuilt only when the speed, its keyframes or the duration change
+    pub fn video_time_remap(&self) -> Arc<keyframes::TimeRemap> {
+        let (speed, duration_ms) = { let params = self.params.read(); (params.video_speed, params.get_scaled_duration_ms()) };
+        let keyframes = self.keyframes.read();
+        let checksum = {
+            use std::hash::Hasher;
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            hasher.write_u64(speed.to_bits());
+            hasher.write_u64(duration_ms.to_bits());
+            hasher.write_u64(keyframes.get_checksum(&KeyframeType::VideoSpeed));
+            hasher.finish()
+        };
+        if let Some((cached_checksum, remap)) = self.time_remap.read().as_ref() {
+            if *cached_checksum == checksum {
+                return remap.clone();
+            }
+        }
+        let remap = Arc::new(keyframes.time_remap(speed, duration_ms));
+        *self.time_remap.write() = Some((checksum, remap.clone()));
+        remap
+    }
 
     pub fn get_scaling_ratio         (&self) -> f64 { let params = self.params.read(); params.video_size.0 as f64 / params.video_output_size.0 as f64 }
     pub fn get_current_fov           (&self) -> f64 { self.current_fov_10000.load(SeqCst) as f64 / 10000.0 }
@@ -585,6 +878,20 @@ impl<T: PixelType> StabilizationManager<T> {
         }
         self.invalidate_zooming();
     }
+    // Filters can crop or scale, the filtered frames are then the video for the stabilization
+    pub fn set_video_filter(&self, filter: String, filtered_size: (usize, usize)) {
+        self.input_file.write().video_filter = filter;
+        self.params.write().video_size = filtered_size;
+        self.invalidate_zooming();
+    }
+    pub fn set_input_crop(&self, v: Option<(f64, f64, f64, f64)>) {
+        self.params.write().input_crop = v;
+        #[cfg(feature = "opencv")]
+        if let Some(ref mut calib) = *self.lens_calibrator.write() {
+            calib.input_crop = v;
+        }
+        self.invalidate_zooming();
+    }
     pub fn set_lens_is_asymmetrical(&self, v: bool) {
         self.lens.write().asymmetrical = v;
         #[cfg(feature = "opencv")]
@@ -616,12 +923,40 @@ impl<T: PixelType> StabilizationManager<T> {
     pub fn set_imu_lpf(&self, lpf: f64) {
         self.gyro.write().imu_lpf = lpf;
     }
+    pub fn set_imu_lpf_type(&self, filter_type: usize, order: usize) {
+        let mut gyro = self.gyro.write();
+        gyro.imu_lpf_type = filtering::FilterType::from(filter_type);
+        gyro.imu_lpf_order = order;
+    }
+    pub fn set_imu_lpf_axes(&self, x: f64, y: f64, z: f64) {
+        // All zeros means the same cutoff for all axes
+        self.gyro.write().imu_lpf_axes = if x > 0.0 || y > 0.0 || z > 0.0 { Some([x, y, z]) } else { None };
+    }
     pub fn set_imu_rotation(&self, pitch_deg: f64, roll_deg: f64, yaw_deg: f64) {
         self.gyro.write().imu_rotation_angles = Some([pitch_deg, roll_deg, yaw_deg]);
     }
+    pub fn set_pixel_aspect_ratio(&self, par: f64) {
+        self.params.write().pixel_aspect_ratio = if par > 0.01 { par } else { 1.0 };
+    }
     pub fn set_acc_rotation(&self, pitch_deg: f64, roll_deg: f64, yaw_deg: f64) {
         self.gyro.write().acc_rotation_angles = Some([pitch_deg, roll_deg, yaw_deg]);
     }
+    pub fn set_extrinsic_rotation(&self, pitch_deg: f64, roll_deg: f64, yaw_deg: f64) {
+        self.gyro.write().extrinsic_rotation_angles = Some([pitch_deg, roll_deg, yaw_deg]);
+    }
+    pub fn set_quat_fusion_crossover(&self, hz: f64) {
+        self.gyro.write().quat_fusion_crossover = hz;
+    }
+    // Negative `lpf` filters the accelerometer with the gyro low pass, 0 disables the filter
+    pub fn set_acc_lpf(&self, lpf: f64) {
+        self.gyro.write().acc_lpf = if lpf >= 0.0 { Some(lpf) } else { None };
+    }
+    pub fn set_acc_bias(&self, bx: f64, by: f64, bz: f64) {
+        self.gyro.write().acc_bias = if bx != 0.0 || by != 0.0 || bz != 0.0 { Some([bx, by, bz]) } else { None };
+    }
+    pub fn set_acc_scale(&self, sx: f64, sy: f64, sz: f64) {
+        self.gyro.write().acc_scale = if sx != 1.0 || sy != 1.0 || sz != 1.0 { Some([sx, sy, sz]) } else { None };
+    }
     pub fn set_imu_orientation(&self, orientation: String) {
         self.gyro.write().imu_orientation = Some(orientation);
     }
@@ -679,12 +1014,25 @@ impl<T: PixelType> StabilizationManager<T> {
         smooth.current().get_parameters_json()
     }
     pub fn set_smoothing_param(&self, name: &str, val: f64) {
+        let val = match self.get_value_range(name).map(|r| r.clamp(val)) {
+            Some(Some(v)) => v,
+            Some(None) => { ::log::warn!("Invalid value {} for smoothing parameter {}", val, name); return; },
+            None => val
+        };
         self.smoothing.write().current_mut().as_mut().set_parameter(name, val);
         self.invalidate_smoothing();
     }
     pub fn set_horizon_lock(&self, lock_percent: f64, roll: f64) {
-        self.smoothing.write().horizon_lock.set_horizon(lock_percent, roll);
-        self.invalidate_smoothing();
+        if let (Some(lock_percent), Some(roll)) = (Self::validated(&KeyframeType::LockHorizonAmount, lock_percent), Self::validated(&KeyframeType::LockHorizonRoll, roll)) {
+            self.smoothing.write().horizon_lock.set_horizon(lock_percent, roll);
+            self.invalidate_smoothing();
+        }
+    }
+    pub fn set_horizon_pitch_lock(&self, lock_percent: f64) {
+        if let Some(lock_percent) = Self::validated(&KeyframeType::LockHorizonPitchAmount, lock_percent) {
+            self.smoothing.write().horizon_lock.set_pitch_lock(lock_percent);
+            self.invalidate_smoothing();
+        }
     }
     pub fn set_use_gravity_vectors(&self, v: bool) {
         self.gyro.write().set_use_gravity_vectors(v);
@@ -729,6 +1077,8 @@ impl<T: PixelType> StabilizationManager<T> {
 
         *self.gyro.write() = GyroSource::new();
         self.keyframes.write().clear();
+        self.horizon_lock_fallback.write().clear();
+        *self.time_remap.write() = None;
 
         self.pose_estimator.clear();
     }
@@ -753,29 +1103,23 @@ impl<T: PixelType> StabilizationManager<T> {
     pub fn list_gpu_devices<F: Fn(Vec<String>) + Send + Sync + 'static>(&self, cb: F) {
         let stab = self.stabilization.clone();
         run_threaded(move || {
-            let lock = stab.upgradable_read();
-            let list = lock.list_devices();
-
-            {
-                let mut lock = RwLockUpgradableReadGuard::upgrade(lock);
-                lock.gpu_list = list.clone();
-            }
+            let list = stab.read().list_devices();
             cb(list);
         });
     }
 
-    pub fn export_gyroflow_file(&self, filepath: impl AsRef<std::path::Path>, thin: bool, extended: bool, additional_data: String) -> std::io::Result<()> {
-        let data = self.export_gyroflow_data(thin, extended, additional_data)?;
-        std::fs::write(filepath, data)?;
+    pub fn export_gyroflow_file(&self, filepath: &str, thin: bool, extended: bool, embed_gyro_file: bool, additional_data: String) -> std::io::Result<()> {
+        let data = self.export_gyroflow_data(thin, extended, embed_gyro_file, additional_data)?;
+        filesystem::write(filepath, data)?;
 
         Ok(())
     }
-    pub fn export_gyroflow_data(&self, thin: bool, extended: bool, additional_data: String) -> std::io::Result<String> {
-        let gyro = self.gyro.read();
-        let params = self.params.read();
-
-        let (smoothing_name, smoothing_params, horizon_amount, horizon_roll) = {
-            let smoothing_lock = self.smoothing.read();
+    // Adjustable parameters of the project file. Also used to resolve the parameter layers, without exporting the whole project
+    fn parameters_json(&self) -> serde_json::Value {
+        Self::parameters_json_of(&self.smoothing.read(), &self.gyro.read(), &self.params.read())
+    }
+    fn parameters_json_of(smoothing_lock: &Smoothing, gyro: &GyroSource, params: &StabilizationParams) -> serde_json::Value {
+        let (smoothing_name, smoothing_params, horizon_amount, horizon_roll, horizon_pitch_amount) = {
             let smoothing = smoothing_lock.current();
 
             let mut parameters = smoothing.get_parameters_json();
@@ -794,37 +1138,15 @@ impl<T: PixelType> StabilizationManager<T> {
                 horizon_amount = 0.0;
             }
 
-            (smoothing.get_name(), parameters, horizon_amount, smoothing_lock.horizon_lock.horizonroll)
+            (smoothing.get_name(), parameters, horizon_amount, smoothing_lock.horizon_lock.horizonroll, smoothing_lock.horizon_lock.horizonlockpitch)
         };
 
-        let input_file = self.input_file.read().clone();
-
-        let mut obj = serde_json::json!({
-            "title": "Gyroflow data file",
-            "version": 2,
-            "app_version": env!("CARGO_PKG_VERSION").to_string(),
-            "videofile": input_file.path,
-            "calibration_data": self.lens.read().get_json_value().unwrap_or_else(|_| serde_json::json!({})),
-            "date": time::OffsetDateTime::now_local().map(|v| v.date().to_string()).unwrap_or_default(),
-
-            "image_sequence_start": input_file.image_sequence_start,
-            "image_sequence_fps": input_file.image_sequence_fps,
+        serde_json::json!({
             "background_color": params.background.as_slice(),
             "background_mode":  params.background_mode as i32,
             "background_margin":          params.background_margin,
             "background_margin_feather":  params.background_margin_feather,
 
-            "video_info": {
-                "width":       params.video_size.0,
-                "height":      params.video_size.1,
-                "rotation":    params.video_rotation,
-                "num_frames":  params.frame_count,
-                "fps":         params.fps,
-                "duration_ms": params.duration_ms,
-                "fps_scale":   params.fps_scale,
-                "vfr_fps":     params.get_scaled_fps(),
-                "vfr_duration_ms": params.get_scaled_duration_ms(),
-            },
             "stabilization": {
                 "fov":                    params.fov,
                 "method":                 smoothing_name,
@@ -832,23 +1154,77 @@ impl<T: PixelType> StabilizationManager<T> {
                 "frame_readout_time":     params.frame_readout_time,
                 "adaptive_zoom_window":   params.adaptive_zoom_window,
                 "adaptive_zoom_center_offset": params.adaptive_zoom_center_offset,
+                "max_zoom":               params.max_zoom,
+                "zoom_border_tolerance":  params.zoom_border_tolerance,
                 // "adaptive_zoom_fovs":     if !thin { util::compress_to_base91(&params.fovs) } else { None },
                 "lens_correction_amount": params.lens_correction_amount,
+                "stabilization_amount":   params.stabilization_amount,
+                "output_projection":      params.output_projection as i32,
+                "output_projection_strength": params.output_projection_strength,
                 "horizon_lock_amount":    horizon_amount,
                 "horizon_lock_roll":      horizon_roll,
+                "horizon_lock_pitch_amount": horizon_pitch_amount,
                 "use_gravity_vectors":    gyro.use_gravity_vectors,
                 "video_speed":                   params.video_speed,
                 "video_speed_affects_smoothing": params.video_speed_affects_smoothing,
                 "video_speed_affects_zooming":   params.video_speed_affects_zooming,
             },
             "gyro_source": {
-                "filepath":           gyro.file_path,
                 "lpf":                gyro.imu_lpf,
+                "lpf_type":           gyro.imu_lpf_type,
+                "lpf_order":          gyro.imu_lpf_order,
+                "lpf_axes":           gyro.imu_lpf_axes,
                 "rotation":           gyro.imu_rotation_angles,
                 "acc_rotation":       gyro.acc_rotation_angles,
+                "extrinsic_rotation": gyro.extrinsic_rotation_angles,
+                "acc_lpf":            gyro.acc_lpf,
+                "acc_bias":           gyro.acc_bias,
+                "acc_scale":          gyro.acc_scale,
                 "imu_orientation":    gyro.imu_orientation,
                 "gyro_bias":          gyro.gyro_bias,
                 "integration_method": gyro.integration_method,
+                "quat_fusion_crossover": gyro.quat_fusion_crossover
+            }
+        })
+    }
+
+    pub fn export_gyroflow_data(&self, thin: bool, extended: bool, embed_gyro_file: bool, additional_data: String) -> std::io::Result<String> {
+        let parameters = self.parameters_json();
+        let gyro = self.gyro.read();
+        let params = self.params.read();
+
+        let input_file = self.input_file.read().clone();
+
+        let mut obj = serde_json::json!({
+            "title": "Gyroflow data file",
+            "version": 2,
+            "app_version": env!("CARGO_PKG_VERSION").to_string(),
+            "videofile": input_file.path,
+            "videofile_fingerprint": util::file_fingerprint(&input_file.path),
+            "calibration_data": self.lens.read().get_json_value().unwrap_or_else(|_| serde_json::json!({})),
+            "date": time::OffsetDateTime::now_local().map(|v| v.date().to_string()).unwrap_or_default(),
+
+            "image_sequence_start": input_file.image_sequence_start,
+            "image_sequence_fps": input_file.image_sequence_fps,
+            "video_filter": input_file.video_filter,
+
+            "video_info": {
+                "width":       params.video_size.0,
+                "height":      params.video_size.1,
+                "rotation":    params.video_rotation,
+                "camera_orientation_applied": params.camera_orientation_applied,
+                "pixel_aspect_ratio": params.pixel_aspect_ratio,
+                "input_crop":  params.input_crop,
+                "num_frames":  params.frame_count,
+                "fps":         params.fps,
+                "duration_ms": params.duration_ms,
+                "fps_scale":   params.fps_scale,
+                "vfr_fps":     params.get_scaled_fps(),
+                "vfr_duration_ms": params.get_scaled_duration_ms(),
+            },
+            "gyro_source": {
+                "filepath":           gyro.file_path,
+                "log_slice":          gyro.log_slice,
                 "raw_imu":            if !thin { util::compress_to_base91(&gyro.org_raw_imu) } else { None },
                 "quaternions":        if !thin && input_file.path != gyro.file_path { util::compress_to_base91(&gyro.org_quaternions) } else { None },
                 "image_orientations": if !thin && input_file.path != gyro.file_path { util::compress_to_base91(&gyro.image_orientations) } else { None },
@@ -866,8 +1242,19 @@ impl<T: PixelType> StabilizationManager<T> {
             // "stab_transform":    {} // timestamp, final quaternion
         });
 
+        util::merge_json(&mut obj, &parameters);
         util::merge_json(&mut obj, &serde_json::from_str(&additional_data).unwrap_or_default());
 
+        {
+            let layers = self.parameter_layers.read();
+            if layers.has_preset() {
+                obj["preset"] = serde_json::json!({
+                    "name":       layers.preset_name,
+                    "parameters": layers.preset
+                });
+            }
+        }
+
         if extended {
             if let Some(serde_json::Value::Object(ref mut obj)) = obj.get_mut("gyro_source") {
                 if let Some(q) = util::compress_to_base91(&gyro.quaternions) {
@@ -879,9 +1266,62 @@ impl<T: PixelType> StabilizationManager<T> {
             }
         }
 
+        // Store the original telemetry file itself, so the project is complete even if the file is deleted later
+        if embed_gyro_file && !thin && !gyro.file_path.is_empty() && input_file.path != gyro.file_path {
+            match filesystem::read(&gyro.file_path) {
+                Ok(bytes) => {
+                    if let Some(serde_json::Value::Object(ref mut obj)) = obj.get_mut("gyro_source") {
+                        let filename = std::path::Path::new(&gyro.file_path).file_name().map(|x| x.to_string_lossy().to_string()).unwrap_or_default();
+                        obj.insert("embedded_file".into(), serde_json::json!({
+                            "filename": filename,
+                            "size":     bytes.len(),
+                            "data":     util::compress_to_base91(&bytes)
+                        }));
+                    }
+                },
+                Err(e) => { ::log::warn!("Failed to embed gyro file {}: {:?}", gyro.file_path, e); }
+            }
+        }
+
         Ok(serde_json::to_string_pretty(&obj)?)
     }
 
+    /// Project without any media or clip specific data, which can be instantiated for another video of the same kind of shoot
+    pub fn export_project_template(&self, name: &str, additional_data: String) -> std::io::Result<String> {
+        let data = self.export_gyroflow_data(true, false, false, additional_data)?;
+        let mut obj: serde_json::Value = serde_json::from_str(&data)?;
+        if let serde_json::Value::Object(ref mut obj) = obj {
+            for key in ["videofile", "videofile_fingerprint", "video_info", "image_sequence_start", "image_sequence_fps", "offsets", "keyframes", "trim_start", "trim_end", "date"] {
+                obj.remove(key);
+            }
+            if let Some(serde_json::Value::Object(gyro)) = obj.get_mut("gyro_source") {
+                gyro.remove("filepath");
+            }
+            if let Some(serde_json::Value::Object(output)) = obj.get_mut("output") {
+                output.remove("output_path");
+            }
+            obj.insert("title".into(), "Gyroflow project template".into());
+            obj.insert("template_name".into(), name.into());
+        }
+        Ok(serde_json::to_string_pretty(&obj)?)
+    }
+    /// Project data for `video_path` created from a template. The trim range covers the whole clip and the synchronization is done again
+    pub fn instantiate_project_template(template: &[u8], video_path: &str) -> std::io::Result<serde_json::Value> {
+        let mut obj: serde_json::Value = serde_json::from_slice(template)?;
+        if let serde_json::Value::Object(ref mut obj) = obj {
+            obj.insert("title".into(), "Gyroflow data file".into());
+            obj.insert("videofile".into(), video_path.into());
+            obj.insert("trim_start".into(), 0.0.into());
+            obj.insert("trim_end".into(),   1.0.into());
+            obj.remove("offsets");
+            obj.remove("template_name");
+            if let Some(sync) = obj.entry("synchronization").or_insert(serde_json::json!({})).as_object_mut() {
+                sync.insert("do_autosync".into(), true.into());
+            }
+        }
+        Ok(obj)
+    }
+
     pub fn get_new_videofile_path(file_path: &str, path: Option<std::path::PathBuf>) -> PathBuf {
         let mut file_path = std::path::Path::new(file_path).to_path_buf();
         if path.is_some() && !file_path.exists() {
@@ -895,12 +1335,70 @@ impl<T: PixelType> StabilizationManager<T> {
         file_path
     }
 
+    // Writes the telemetry file embedded in the project next to the project file (or to the temp directory if that's not possible)
+    fn extract_embedded_file(embedded: Option<&serde_json::Value>, project_path: Option<&PathBuf>) -> Option<PathBuf> {
+        let embedded = embedded?;
+        let filename = embedded.get("filename").and_then(|x| x.as_str()).filter(|x| !x.is_empty())?;
+        let filename = std::path::Path::new(filename).file_name()?.to_owned();
+        let bytes = util::decompress_from_base91(embedded.get("data").and_then(|x| x.as_str())?)?;
+        let bytes: Vec<u8> = bincode::deserialize(&bytes).ok()?;
+
+        let mut candidates = Vec::new();
+        if let Some(p) = project_path { candidates.push(p.with_file_name(&filename)); }
+        candidates.push(std::env::temp_dir().join("Gyroflow").join(&filename));
+
+        for target in candidates {
+            if target.exists() {
+                if std::fs::metadata(&target).map(|m| m.len() as usize == bytes.len()).unwrap_or_default() {
+                    return Some(target);
+                }
+                continue;
+            }
+            if let Some(parent) = target.parent() { let _ = std::fs::create_dir_all(parent); }
+            match filesystem::write(&util::path_to_str(&target), &bytes) {
+                Ok(_) => {
+                    ::log::info!("Extracted embedded gyro file to {:?}", target);
+                    return Some(target);
+                },
+                Err(e) => { ::log::warn!("Failed to extract embedded gyro file to {:?}: {:?}", target, e); }
+            }
+        }
+        None
+    }
+
     pub fn import_gyroflow_file<F: Fn(f64)>(&self, path: &str, blocking: bool, progress_cb: F, cancel_flag: Arc<AtomicBool>) -> std::io::Result<serde_json::Value> {
-        let data = std::fs::read(path)?;
+        let data = filesystem::read(path)?;
         self.import_gyroflow_data(&data, blocking, Some(std::path::Path::new(path).to_path_buf()), progress_cb, cancel_flag)
     }
+    /// Recovers the valid top level sections of a damaged or truncated project file, together with the names of the dropped sections
+    pub fn recover_gyroflow_data(data: &[u8]) -> Option<(serde_json::Value, Vec<String>)> {
+        let (obj, dropped) = util::recover_json_object(data)?;
+        if dropped.is_empty() && serde_json::from_slice::<serde_json::Value>(data).is_ok() {
+            return None;
+        }
+        Some((obj, dropped))
+    }
     pub fn import_gyroflow_data<F: Fn(f64)>(&self, data: &[u8], blocking: bool, path: Option<std::path::PathBuf>, progress_cb: F, cancel_flag: Arc<AtomicBool>) -> std::io::Result<serde_json::Value> {
         let mut obj: serde_json::Value = serde_json::from_slice(&data)?;
+        if ParameterLayers::is_preset(&obj) {
+            // The preset parameters become the preset layer, see `parameter_layers` for the precedence
+            let name = path.as_ref().and_then(|x| x.file_stem()).map(|x| x.to_string_lossy().to_string()).unwrap_or_default();
+            self.apply_preset_layer(&name, &mut obj);
+        } else if obj.get("videofile").is_some() {
+            let mut layers = self.parameter_layers.write();
+            match obj.get("preset") {
+                Some(preset) => {
+                    let name = preset.get("name").and_then(|x| x.as_str()).unwrap_or_default();
+                    layers.set_preset(name, preset.get("parameters").unwrap_or(&serde_json::Value::Null));
+                },
+                None => layers.clear_preset()
+            }
+            layers.clip = serde_json::Value::Null;
+        }
+        // Other partial files (eg. presets saved by older versions) are applied as plain values and don't change the layers
+        self.import_gyroflow_value(obj, blocking, path, progress_cb, cancel_flag)
+    }
+    fn import_gyroflow_value<F: Fn(f64)>(&self, mut obj: serde_json::Value, blocking: bool, path: Option<std::path::PathBuf>, progress_cb: F, cancel_flag: Arc<AtomicBool>) -> std::io::Result<serde_json::Value> {
         if let serde_json::Value::Object(ref mut obj) = obj {
             let mut output_size = None;
             let org_video_path = obj.get("videofile").and_then(|x| x.as_str()).unwrap_or(&"").to_string();
@@ -909,6 +1407,13 @@ impl<T: PixelType> StabilizationManager<T> {
             if let Some(videofile) = obj.get_mut("videofile") {
                 *videofile = serde_json::Value::String(util::path_to_str(&video_path));
             }
+            // Sync offsets and calibration may not apply anymore if the video was re-encoded or replaced
+            if let Some(fingerprint) = obj.get("videofile_fingerprint").and_then(|x| x.as_str()) {
+                if util::verify_file_fingerprint(&util::path_to_str(&video_path), fingerprint) == Some(false) {
+                    ::log::warn!("Video file {:?} was modified since the project was saved", video_path);
+                    obj.insert("videofile_modified".into(), serde_json::Value::Bool(true));
+                }
+            }
 
             if let Some(vid_info) = obj.get("video_info") {
                 let mut params = self.params.write();
@@ -917,8 +1422,11 @@ impl<T: PixelType> StabilizationManager<T> {
                         params.video_size = (w as usize, h as usize);
                     }
                 }
-                output_size = Some(params.video_size);
                 if let Some(v) = vid_info.get("rotation")   .and_then(|x| x.as_f64()) { params.video_rotation = v; }
+                params.camera_orientation_applied = vid_info.get("camera_orientation_applied").and_then(|x| x.as_f64());
+                params.pixel_aspect_ratio = vid_info.get("pixel_aspect_ratio").and_then(|x| x.as_f64()).unwrap_or(1.0);
+                output_size = Some(params.get_display_size());
+                if let Some(v) = vid_info.get("input_crop") { params.input_crop = serde_json::from_value(v.clone()).ok(); }
                 if let Some(v) = vid_info.get("num_frames") .and_then(|x| x.as_u64()) { params.frame_count    = v as usize; }
                 if let Some(v) = vid_info.get("fps")        .and_then(|x| x.as_f64()) { params.fps            = v; }
                 if let Some(v) = vid_info.get("duration_ms").and_then(|x| x.as_f64()) { params.duration_ms    = v; }
@@ -933,7 +1441,12 @@ impl<T: PixelType> StabilizationManager<T> {
             obj.remove("stab_transform");
             if let Some(serde_json::Value::Object(ref mut obj)) = obj.get_mut("gyro_source") {
                 let org_gyro_path = obj.get("filepath").and_then(|x| x.as_str()).unwrap_or(&"").to_string();
-                let gyro_path = Self::get_new_videofile_path(&org_gyro_path, path.clone());
+                let mut gyro_path = Self::get_new_videofile_path(&org_gyro_path, path.clone());
+                if !org_gyro_path.is_empty() && !gyro_path.exists() {
+                    if let Some(extracted) = Self::extract_embedded_file(obj.get("embedded_file"), path.as_ref()) {
+                        gyro_path = extracted;
+                    }
+                }
                 if let Some(fp) = obj.get_mut("filepath") {
                     *fp = serde_json::Value::String(util::path_to_str(&gyro_path));
                 }
@@ -941,6 +1454,9 @@ impl<T: PixelType> StabilizationManager<T> {
 
                 let is_compressed = obj.get("raw_imu").map(|x| x.is_string()).unwrap_or_default();
 
+                // Before loading the log, so the anchor of this video is used. The embedded IMU data is already sliced
+                self.gyro.write().log_slice = obj.get("log_slice").and_then(|x| serde_json::from_value(x.clone()).ok());
+
                 // Load IMU data only if it's from another file
                 if !org_gyro_path.is_empty() && org_gyro_path != org_video_path {
                     let mut raw_imu = None;
@@ -1005,6 +1521,8 @@ impl<T: PixelType> StabilizationManager<T> {
                             frame_readout_time: None,
                             frame_rate: None,
                             camera_identifier: None,
+                            camera_orientation: None,
+                            gpmf_streams: Default::default(),
                         };
 
                         let mut gyro = self.gyro.write();
@@ -1026,10 +1544,18 @@ impl<T: PixelType> StabilizationManager<T> {
                 }
 
                 if let Some(v) = obj.get("lpf").and_then(|x| x.as_f64()) { gyro.imu_lpf = v; }
+                if let Some(v) = obj.get("lpf_type")  { gyro.imu_lpf_type = serde_json::from_value(v.clone()).unwrap_or_default(); }
+                if let Some(v) = obj.get("lpf_order").and_then(|x| x.as_u64()) { gyro.imu_lpf_order = v as usize; }
+                if let Some(v) = obj.get("lpf_axes")  { gyro.imu_lpf_axes = serde_json::from_value(v.clone()).ok(); }
                 if let Some(v) = obj.get("integration_method").and_then(|x| x.as_u64()) { gyro.integration_method = v as usize; }
+                if let Some(v) = obj.get("quat_fusion_crossover").and_then(|x| x.as_f64()) { gyro.quat_fusion_crossover = v; }
                 if let Some(v) = obj.get("imu_orientation").and_then(|x| x.as_str()) { gyro.imu_orientation = Some(v.to_string()); }
                 if let Some(v) = obj.get("rotation")     { gyro.imu_rotation_angles = serde_json::from_value(v.clone()).ok(); }
                 if let Some(v) = obj.get("acc_rotation") { gyro.acc_rotation_angles = serde_json::from_value(v.clone()).ok(); }
+                if let Some(v) = obj.get("extrinsic_rotation") { gyro.extrinsic_rotation_angles = serde_json::from_value(v.clone()).ok(); }
+                if let Some(v) = obj.get("acc_bias")     { gyro.acc_bias            = serde_json::from_value(v.clone()).ok(); }
+                if let Some(v) = obj.get("acc_scale")    { gyro.acc_scale           = serde_json::from_value(v.clone()).ok(); }
+                gyro.acc_lpf = obj.get("acc_lpf").and_then(|x| x.as_f64());
                 if let Some(v) = obj.get("gyro_bias")    { gyro.gyro_bias           = serde_json::from_value(v.clone()).ok(); }
 
                 obj.remove("raw_imu");
@@ -1037,13 +1563,19 @@ impl<T: PixelType> StabilizationManager<T> {
                 obj.remove("smoothed_quaternions");
                 obj.remove("image_orientations");
                 obj.remove("gravity_vectors");
+                obj.remove("embedded_file");
             }
             if let Some(serde_json::Value::Object(ref mut obj)) = obj.get_mut("stabilization") {
                 let mut params = self.params.write();
                 if let Some(v) = obj.get("fov")                   .and_then(|x| x.as_f64()) { params.fov                     = v; }
                 if let Some(v) = obj.get("frame_readout_time")    .and_then(|x| x.as_f64()) { params.frame_readout_time      = v; }
                 if let Some(v) = obj.get("adaptive_zoom_window")  .and_then(|x| x.as_f64()) { params.adaptive_zoom_window    = v; }
+                if let Some(v) = obj.get("max_zoom")              .and_then(|x| x.as_f64()) { params.max_zoom                = v; }
+                if let Some(v) = obj.get("zoom_border_tolerance") .and_then(|x| x.as_f64()) { params.zoom_border_tolerance   = v; }
                 if let Some(v) = obj.get("lens_correction_amount").and_then(|x| x.as_f64()) { params.lens_correction_amount  = v; }
+                if let Some(v) = obj.get("stabilization_amount")  .and_then(|x| x.as_f64()) { params.stabilization_amount    = v; }
+                if let Some(v) = obj.get("output_projection")     .and_then(|x| x.as_i64()) { params.output_projection       = stabilization_params::OutputProjection::from(v as i32); }
+                if let Some(v) = obj.get("output_projection_strength").and_then(|x| x.as_f64()) { params.output_projection_strength = v; }
 
                 if let Some(v) = obj.get("video_speed").and_then(|x| x.as_f64()) { params.video_speed = v; }
                 if let Some(v) = obj.get("video_speed_affects_smoothing").and_then(|x| x.as_bool()) { params.video_speed_affects_smoothing = v; }
@@ -1083,6 +1615,9 @@ impl<T: PixelType> StabilizationManager<T> {
                         smoothing.horizon_lock.set_horizon(horizon_amount, horizon_roll);
                     }
                 }
+                if let Some(v) = obj.get("horizon_lock_pitch_amount").and_then(|x| x.as_f64()) {
+                    smoothing.horizon_lock.set_pitch_lock(v);
+                }
                 if let Some(v) = obj.get("use_gravity_vectors").and_then(|x| x.as_bool()) {
                     self.gyro.write().set_use_gravity_vectors(v);
                 }
@@ -1140,6 +1675,10 @@ impl<T: PixelType> StabilizationManager<T> {
                 if let Some(seq_fps) = obj.get("image_sequence_fps").and_then(|x| x.as_f64()) {
                     input_file.image_sequence_fps = seq_fps;
                 }
+                // Only in projects of the clip, presets don't include it
+                if let Some(v) = obj.get("video_filter").and_then(|x| x.as_str()) {
+                    input_file.video_filter = v.to_string();
+                }
                 if !org_video_path.is_empty() {
                     input_file.path = util::path_to_str(&video_path);
                 }
@@ -1160,9 +1699,94 @@ impl<T: PixelType> StabilizationManager<T> {
         Ok(obj)
     }
 
+    // Current parameters in the form of a project json, without the clip data
+    fn parameter_snapshot(&self) -> serde_json::Value {
+        ParameterLayers::filter_parameters(&self.parameters_json())
+    }
+    fn update_parameter_layers(&self) {
+        let current = self.parameter_snapshot();
+        let defaults = if self.parameter_layers.read().defaults.is_null() {
+            Some(ParameterLayers::filter_parameters(&Self::parameters_json_of(&Smoothing::default(), &GyroSource::new(), &StabilizationParams::default())))
+        } else {
+            None
+        };
+        let camera = ParameterLayers::camera_layer(&self.lens.read());
+
+        let mut layers = self.parameter_layers.write();
+        if let Some(defaults) = defaults {
+            layers.defaults = defaults;
+        }
+        layers.camera = camera;
+        layers.update_clip_overrides(&current);
+    }
+    fn apply_preset_layer(&self, name: &str, preset: &mut serde_json::Value) {
+        self.update_parameter_layers();
+
+        let mut layers = self.parameter_layers.write();
+        if let Some(calib) = preset.get("calibration_data") {
+            // The preset changes the lens, so the camera defaults come from the new profile
+            let mut lens = LensProfile::default();
+            lens.load_from_json_value(calib);
+            layers.camera = ParameterLayers::camera_layer(&lens);
+        }
+        layers.apply_preset(name, preset);
+        layers.resolve_into(preset);
+    }
+    pub fn get_parameter_sources(&self) -> std::collections::BTreeMap<String, ParameterSource> {
+        self.update_parameter_layers();
+        self.parameter_layers.read().sources()
+    }
+    pub fn get_parameter_source(&self, path: &str) -> ParameterSource {
+        self.update_parameter_layers();
+        self.parameter_layers.read().source_of(path)
+    }
+    /// Removes the explicit override of the clip, so the value is inherited from the preset or camera defaults again
+    pub fn reset_parameter_override(&self, path: &str) -> std::io::Result<Option<serde_json::Value>> {
+        self.update_parameter_layers();
+        let resolved = {
+            let mut layers = self.parameter_layers.write();
+            if !layers.clear_override(path) {
+                return Ok(None);
+            }
+            layers.resolve()
+        };
+        self.import_gyroflow_value(resolved, false, None, |_| (), Arc::new(AtomicBool::new(false))).map(Some)
+    }
+
+    // Clamps the value to the valid range of the parameter, None if it can't be used at all
+    fn validated(typ: &KeyframeType, value: f64) -> Option<f64> {
+        let clamped = keyframe_range(typ).clamp(value);
+        if clamped != Some(value) {
+            ::log::warn!("Value {} is out of range for {:?}, using {:?}", value, typ, clamped);
+        }
+        clamped
+    }
+    // 0 or less means no zoom limit, anything else is clamped to the range
+    fn validated_max_zoom(value: f64) -> Option<f64> {
+        if value <= 0.0 { return Some(0.0); }
+        Self::validated(&KeyframeType::MaxZoom, value)
+    }
+    /// Valid range of a keyframe type (eg. "Fov") or a parameter of the current smoothing algorithm (eg. "smoothness").
+    /// Smoothing keyframes use the range of the parameter they are bound to in the current algorithm
+    pub fn get_value_range(&self, name: &str) -> Option<ValueRange> {
+        let params = self.smoothing.read().current().get_parameters_json();
+        let param = params.as_array()?.iter().find(|x| {
+            x.get("name").and_then(|x| x.as_str()) == Some(name) || x.get("keyframe").and_then(|x| x.as_str()) == Some(name)
+        });
+        if let Some(range) = param.and_then(ValueRange::from_parameter_json) {
+            return Some(range);
+        }
+        KeyframeType::from_str(name).ok().map(|kf| keyframe_range(&kf))
+    }
     pub fn set_keyframe(&self, typ: &KeyframeType, timestamp_us: i64, value: f64) {
-        self.keyframes.write().set(typ, timestamp_us, value);
-        self.keyframes_updated(typ);
+        let range = self.get_value_range(&format!("{:?}", typ)).unwrap_or_else(|| keyframe_range(typ));
+        match range.clamp(value) {
+            Some(value) => {
+                self.keyframes.write().set(typ, timestamp_us, value);
+                self.keyframes_updated(typ);
+            },
+            None => ::log::warn!("Invalid keyframe value {} for {:?}", value, typ)
+        }
     }
     pub fn set_keyframe_easing(&self, typ: &KeyframeType, timestamp_us: i64, easing: Easing) {
         self.keyframes.write().set_easing(typ, timestamp_us, easing);
@@ -1189,10 +1813,15 @@ impl<T: PixelType> StabilizationManager<T> {
         match typ {
             KeyframeType::VideoRotation |
             KeyframeType::ZoomingCenterX |
-            KeyframeType::ZoomingCenterY => self.invalidate_zooming(),
+            KeyframeType::ZoomingCenterY |
+            KeyframeType::MaxZoom |
+            KeyframeType::ZoomBorderTolerance |
+            KeyframeType::LensCorrectionStrength |
+            KeyframeType::StabilizationAmount => self.invalidate_zooming(),
 
             KeyframeType::LockHorizonAmount |
             KeyframeType::LockHorizonRoll |
+            KeyframeType::LockHorizonPitchAmount |
             KeyframeType::SmoothingParamTimeConstant |
             KeyframeType::SmoothingParamTimeConstant2 |
             KeyframeType::SmoothingParamSmoothness |
@@ -1210,3 +1839,12 @@ pub fn frame_at_timestamp(timestamp_ms: f64, fps: f64) -> i32 { (timestamp_ms *
 pub fn run_threaded<F>(cb: F) where F: FnOnce() + Send + 'static {
     THREAD_POOL.spawn(cb);
 }
+
+/// Optional features this crate was compiled with
+pub fn compiled_features() -> serde_json::Value {
+    serde_json::json!({
+        "opencv": cfg!(feature = "use-opencv"),
+        "opencl": cfg!(feature = "use-opencl"),
+        "cuda":   cfg!(feature = "use-cuda"),
+    })
+}
@@ -0,0 +1,168 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2022 Adrian <adrian.eddy at gmail>
+
+// External loggers often record continuously over several video files. When the log is much longer than the video,
+// only the part from the video start (`LogSlice::start_ms` in the log timeline) is used, shifted so it starts at 0.
+// The start is found from the date and time in both file names, or set manually. The recording times (creation time of the video
+// and the modification time of the log minus its duration) are too unreliable to cut the log, so with those the whole log is kept
+// and the start is only used as the initial sync offset. A manual anchor of one clip gives the clock difference between the camera
+// and the logger, which is then used for the other clips of the same log.
+
+use std::collections::HashMap;
+use std::io::{ Read, Seek, SeekFrom };
+use byteorder::{ BigEndian, ReadBytesExt };
+use parking_lot::RwLock;
+
+// Log data kept before and after the video, so the synchronization can still find offsets at the edges
+pub const MARGIN_MS: f64 = 5000.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnchorSource {
+    Manual,
+    Filename,
+    Rtc,
+}
+
+/// Anchors precise enough to cut the log, with `Rtc` the whole log is kept
+pub fn is_exact(source: AnchorSource) -> bool {
+    source != AnchorSource::Rtc
+}
+
+/// Whether the log has more data than the video needs, ie. it probably spans multiple videos
+pub fn is_longer_than_video(log_range_ms: (f64, f64), video_duration_ms: f64) -> bool {
+    video_duration_ms > 0.0 && log_range_ms.1 - log_range_ms.0 > video_duration_ms + 2.0 * MARGIN_MS
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct LogSlice {
+    pub log_path: String,
+    pub start_ms: f64, // Video start in the log timeline
+    pub source: AnchorSource,
+}
+
+lazy_static::lazy_static! {
+    // Clock difference between the camera and the logger from a manual anchor, by log path
+    static ref CLOCK_OFFSETS: RwLock<HashMap<String, f64>> = RwLock::new(HashMap::new());
+}
+
+/// Slice of the log at `log_path` for the video, or None if the log isn't longer than the video.
+/// `saved` is the slice from the project file, its manual anchor is kept. Only the file names are compared, so it still applies after the files are moved
+pub fn find(log_path: &str, video_path: &str, log_range_ms: (f64, f64), video_duration_ms: f64, saved: Option<&LogSlice>) -> Option<LogSlice> {
+    let file_name = |path: &str| std::path::Path::new(path).file_name().map(|x| x.to_os_string());
+    if let Some(saved) = saved {
+        if saved.source == AnchorSource::Manual && file_name(&saved.log_path) == file_name(log_path) {
+            return Some(LogSlice { log_path: log_path.to_string(), ..saved.clone() });
+        }
+    }
+    if !is_longer_than_video(log_range_ms, video_duration_ms) {
+        return None;
+    }
+    let (relative_ms, source) = match recording_offset(log_path, video_path, log_range_ms) {
+        Some(x) => x,
+        None => {
+            log::warn!("Log {} is longer than the video, but its start time couldn't be determined. Set the anchor manually", log_path);
+            return None;
+        }
+    };
+    let start_ms = log_range_ms.0 + relative_ms + CLOCK_OFFSETS.read().get(log_path).copied().unwrap_or_default();
+    if start_ms + video_duration_ms < log_range_ms.0 || start_ms > log_range_ms.1 {
+        log::warn!("Video {} starts at {:.3} s, outside of the log {}", video_path, start_ms / 1000.0, log_path);
+        return None;
+    }
+    log::info!("Using the log {} from {:.3} s ({:?})", log_path, start_ms / 1000.0, source);
+    Some(LogSlice { log_path: log_path.to_string(), start_ms, source })
+}
+
+/// Manual anchor of the video start in the log, the saved slice is passed to `find` when the log is loaded.
+/// Also stores the clock difference for the other videos of the same log
+pub fn anchor(log_path: &str, video_path: &str, log_range_ms: (f64, f64), start_ms: f64) -> LogSlice {
+    if let Some((relative_ms, _)) = recording_offset(log_path, video_path, log_range_ms) {
+        CLOCK_OFFSETS.write().insert(log_path.to_string(), start_ms - log_range_ms.0 - relative_ms);
+    }
+    LogSlice { log_path: log_path.to_string(), start_ms, source: AnchorSource::Manual }
+}
+
+// Video start relative to the log start, in ms
+fn recording_offset(log_path: &str, video_path: &str, log_range_ms: (f64, f64)) -> Option<(f64, AnchorSource)> {
+    if let (Some(video), Some(log)) = (filename_timestamp(video_path), filename_timestamp(log_path)) {
+        return Some((video - log, AnchorSource::Filename));
+    }
+    let video = video_creation_time(video_path)?;
+    let log = file_modified_time(log_path)? - (log_range_ms.1 - log_range_ms.0);
+    Some((video - log, AnchorSource::Rtc))
+}
+
+/// Date and time in the file name (eg. `DJI_20221015143012_0001.MP4`, `LOG 2022-10-15 14-30-12.csv`), as ms since the Unix epoch.
+/// The time zone isn't known, so it's only useful to compare two file names
+pub fn filename_timestamp(path: &str) -> Option<f64> {
+    let name = std::path::Path::new(path).file_stem()?.to_string_lossy().to_string();
+    // Digits, with the separators between them removed
+    let mut runs = vec![String::new()];
+    let chars: Vec<char> = name.chars().collect();
+    for (i, c) in chars.iter().enumerate() {
+        if c.is_ascii_digit() {
+            runs.last_mut().unwrap().push(*c);
+        } else if !(matches!(c, '-' | '_' | ' ' | '.' | 'T' | ':') && i > 0 && chars[i - 1].is_ascii_digit() && chars.get(i + 1).map_or(false, |x| x.is_ascii_digit())) {
+            runs.push(String::new());
+        }
+    }
+    runs.iter().filter(|x| x.len() >= 14).find_map(|run| {
+        (0..=run.len() - 14).find_map(|i| {
+            let s = &run[i..i + 14];
+            let num = |a: usize, b: usize| s[a..b].parse::<u32>().ok();
+            let year = num(0, 4)?;
+            if !(1990..2100).contains(&year) { return None; }
+            let month = time::Month::try_from(num(4, 6)? as u8).ok()?;
+            let date = time::Date::from_calendar_date(year as i32, month, num(6, 8)? as u8).ok()?;
+            let time_of_day = time::Time::from_hms(num(8, 10)? as u8, num(10, 12)? as u8, num(12, 14)? as u8).ok()?;
+            Some(time::PrimitiveDateTime::new(date, time_of_day).assume_utc().unix_timestamp() as f64 * 1000.0)
+        })
+    })
+}
+
+// Creation time of the MP4/MOV file from the `mvhd` box, in ms since the Unix epoch
+fn video_creation_time(path: &str) -> Option<f64> {
+    // Seconds between 1904-01-01 and 1970-01-01
+    const MAC_EPOCH_OFFSET: u64 = 2082844800;
+
+    let mut file = crate::filesystem::open(path).ok()?;
+    let file_size = file.metadata().ok()?.len();
+    let moov = find_box(&mut file, 0, file_size, b"moov")?;
+    let mvhd = find_box(&mut file, moov.0, moov.1, b"mvhd")?;
+    file.seek(SeekFrom::Start(mvhd.0)).ok()?;
+    let version = file.read_u8().ok()?;
+    file.seek(SeekFrom::Current(3)).ok()?; // flags
+    let created = if version == 1 { file.read_u64::<BigEndian>().ok()? } else { file.read_u32::<BigEndian>().ok()? as u64 };
+    if created <= MAC_EPOCH_OFFSET { return None; } // Not set by the camera
+    Some((created - MAC_EPOCH_OFFSET) as f64 * 1000.0)
+}
+
+// (data offset, data end) of the first box of type `typ` between `start` and `end`
+fn find_box<R: Read + Seek>(stream: &mut R, start: u64, end: u64, typ: &[u8; 4]) -> Option<(u64, u64)> {
+    let mut offset = start;
+    while offset + 8 <= end {
+        stream.seek(SeekFrom::Start(offset)).ok()?;
+        let mut size = stream.read_u32::<BigEndian>().ok()? as u64;
+        let mut name = [0u8; 4];
+        stream.read_exact(&mut name).ok()?;
+        let mut header = 8;
+        if size == 1 {
+            size = stream.read_u64::<BigEndian>().ok()?;
+            header = 16;
+        } else if size == 0 {
+            size = end - offset;
+        }
+        if size < header { return None; }
+        if &name == typ {
+            return Some((offset + header, offset + size));
+        }
+        offset += size;
+    }
+    None
+}
+
+fn file_modified_time(path: &str) -> Option<f64> {
+    let modified = crate::filesystem::open(path).ok()?.metadata().ok()?.modified().ok()?;
+    Some(modified.duration_since(std::time::UNIX_EPOCH).ok()?.as_secs_f64() * 1000.0)
+}
@@ -0,0 +1,31 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2022 Adrian <adrian.eddy at gmail>
+
+// Low-memory profile for 32-bit builds and machines which run out of memory with long, high resolution clips.
+// It's selected once at startup and trades speed for memory: fewer cached frame transforms, a smaller undistortion lookup table,
+// coarser charts, shallower decoder queues and a bounded render frame pool.
+
+use std::sync::atomic::{ AtomicBool, Ordering::SeqCst };
+
+// Frame transforms kept in the cache of the `Stabilization`, the ones furthest from the current frame are dropped first
+pub const MAX_CACHED_TRANSFORMS: usize = 16;
+// Rows of the rolling shutter correction matrices, instead of one for every row of the video. The kernels use the nearest one
+pub const MAX_MATRIX_ROWS: usize = 256;
+// Chart points per pixel of the chart width (2 in the normal mode: min and max for every pixel)
+pub const CHART_POINTS_PER_PIXEL: f64 = 1.0;
+// Decoder threads, ie. frames decoded ahead
+pub const MAX_DECODER_THREADS: usize = 2;
+// Memory budget of the render frame pool when the render doesn't set its own
+pub const RENDER_MEMORY_BUDGET_MB: usize = 1024;
+
+static ENABLED: AtomicBool = AtomicBool::new(cfg!(target_pointer_width = "32"));
+
+pub fn set_enabled(enabled: bool) {
+    if enabled != is_enabled() {
+        log::info!("Low-memory mode: {}", enabled);
+    }
+    ENABLED.store(enabled, SeqCst);
+}
+pub fn is_enabled() -> bool {
+    ENABLED.load(SeqCst)
+}
@@ -0,0 +1,102 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2022 Adrian <adrian.eddy at gmail>
+
+// Output filename templates, evaluated when the output path of a render is generated.
+// Variables are written in braces, eg. "{basename}_{w}x{h}_{codec}". Unknown variables are left unchanged.
+// `{counter}` (or `{counter:3}` for zero padding) is replaced with the lowest number for which the path isn't taken yet,
+// so the outputs of a batch can be numbered without overwriting each other.
+
+use std::collections::BTreeMap;
+use serde::{ Serialize, Deserialize };
+
+pub const DEFAULT_TEMPLATE: &str = "{basename}{suffix}";
+const MAX_COUNTER: usize = 100000;
+
+// What to do when the output file already exists
+#[derive(Default, Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CollisionPolicy {
+    #[default]
+    Ask,       // Report the existing file and let the user decide
+    Overwrite,
+    Rename,    // Append _1, _2... to the file name
+    Skip,      // Don't render the file
+}
+
+pub type TemplateVars = BTreeMap<&'static str, String>;
+
+// Characters which can't be used in file names on any of the supported platforms
+pub fn sanitize(value: &str) -> String {
+    value.chars().map(|c| if "<>:\"/\\|?*".contains(c) || c.is_control() { '_' } else { c }).collect()
+}
+
+fn substitute(template: &str, vars: &TemplateVars, counter: Option<usize>) -> String {
+    let mut ret = String::with_capacity(template.len() * 2);
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        ret.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let end = match after.find('}') { Some(x) => x, None => { rest = &rest[start..]; break; } };
+        let name = &after[..end];
+
+        let value = if name == "counter" || name.starts_with("counter:") {
+            counter.map(|n| {
+                let width = name.strip_prefix("counter:").and_then(|x| x.parse::<usize>().ok()).unwrap_or(1);
+                format!("{:0width$}", n, width = width)
+            })
+        } else {
+            vars.get(name).map(|x| sanitize(x))
+        };
+        match value {
+            Some(v) => ret.push_str(&v),
+            None => { ret.push('{'); ret.push_str(name); ret.push('}'); }
+        }
+        rest = &after[end + 1..];
+    }
+    ret.push_str(rest);
+    ret
+}
+
+pub fn has_counter(template: &str) -> bool {
+    template.contains("{counter}") || template.contains("{counter:")
+}
+
+// Evaluates `template` into a file name (without extension). `is_taken` is called with the evaluated name
+// to find the first free `{counter}` value, it's not used if the template doesn't contain a counter
+pub fn evaluate(template: &str, vars: &TemplateVars, is_taken: impl Fn(&str) -> bool) -> String {
+    let template = if template.trim().is_empty() { DEFAULT_TEMPLATE } else { template };
+    if !has_counter(template) {
+        return substitute(template, vars, None);
+    }
+    for i in 1..MAX_COUNTER {
+        let name = substitute(template, vars, Some(i));
+        if !is_taken(&name) {
+            return name;
+        }
+    }
+    substitute(template, vars, Some(MAX_COUNTER))
+}
+
+// Returns the path with _1, _2... appended to the file name, whichever is the first one not taken.
+// Image sequence patterns (eg. `_%05d.png`) are kept at the end
+pub fn unique_path(path: &str, is_taken: impl Fn(&str) -> bool) -> String {
+    let (dir, fname) = match path.rfind(|c| c == '/' || c == '\\') {
+        Some(i) => path.split_at(i + 1),
+        None => ("", path)
+    };
+    let (stem, ext) = match fname.rfind('.') {
+        Some(i) if i > 0 => fname.split_at(i),
+        _ => (fname, "")
+    };
+    let (stem, sequence) = match stem.rfind("_%0") {
+        Some(i) if stem.ends_with('d') => stem.split_at(i),
+        _ => (stem, "")
+    };
+    for i in 1..MAX_COUNTER {
+        let candidate = format!("{}{}_{}{}{}", dir, stem, i, sequence, ext);
+        if !is_taken(&candidate) {
+            return candidate;
+        }
+    }
+    path.to_string()
+}
@@ -0,0 +1,90 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2022 Adrian <adrian.eddy at gmail>
+
+// Timestamped journal of parameter changes, exported with the diagnostics so reports like
+// "it suddenly looks wrong" can be traced back to the exact sequence of changes.
+// The parameters are compared before and after every setter, so each entry has the values which were actually changed.
+// Consecutive changes of the same setter (eg. dragging a slider) are merged into a single entry.
+
+use std::collections::VecDeque;
+use std::time::{ SystemTime, UNIX_EPOCH };
+use crate::project_diff::ProjectDiff;
+
+const MAX_ENTRIES: usize = 2000;
+const MERGE_WINDOW_MS: u64 = 1000;
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct JournalEntry {
+    pub timestamp_ms: u64,
+    pub setter: String,
+    pub args: String,
+    pub changes: ProjectDiff, // Previous and new values of everything the setter changed
+}
+
+#[derive(Default, Debug, Clone)]
+pub struct ParameterJournal {
+    entries: VecDeque<JournalEntry>,
+}
+
+fn unix_timestamp_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|x| x.as_millis() as u64).unwrap_or_default()
+}
+
+impl ParameterJournal {
+    /// Records the call of `setter`. Calls which didn't change anything are only recorded once in a row
+    pub fn record(&mut self, setter: &str, args: String, changes: ProjectDiff) {
+        let now = unix_timestamp_ms();
+        if let Some(last) = self.entries.back_mut() {
+            if last.setter == setter {
+                if changes.is_empty() && last.args == args { return; }
+                if !changes.is_empty() && !last.changes.is_empty() && now.saturating_sub(last.timestamp_ms) < MERGE_WINDOW_MS {
+                    last.timestamp_ms = now;
+                    last.args = args;
+                    merge(&mut last.changes, changes);
+                    return;
+                }
+            }
+        }
+        self.entries.push_back(JournalEntry { timestamp_ms: now, setter: setter.to_string(), args, changes });
+        while self.entries.len() > MAX_ENTRIES {
+            self.entries.pop_front();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &JournalEntry> {
+        self.entries.iter()
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::to_value(&self.entries).unwrap_or_default()
+    }
+}
+
+// The merged entry keeps the values from before the first change and the latest new values
+fn merge(into: &mut ProjectDiff, changes: ProjectDiff) {
+    for x in changes.params {
+        match into.params.iter_mut().find(|y| y.path == x.path) {
+            Some(y) => y.new = x.new,
+            None => into.params.push(x)
+        }
+    }
+    for x in changes.keyframes {
+        match into.keyframes.iter_mut().find(|y| y.keyframe == x.keyframe && y.timestamp_us == x.timestamp_us) {
+            Some(y) => y.new = x.new,
+            None => into.keyframes.push(x)
+        }
+    }
+    for x in changes.offsets {
+        match into.offsets.iter_mut().find(|y| y.timestamp_us == x.timestamp_us) {
+            Some(y) => y.new = x.new,
+            None => into.offsets.push(x)
+        }
+    }
+    for x in changes.motion_data {
+        if !into.motion_data.contains(&x) { into.motion_data.push(x); }
+    }
+}
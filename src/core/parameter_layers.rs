@@ -0,0 +1,225 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// Layered parameter resolution. The effective parameters of a clip come from, in order of priority:
+// per-clip explicit overrides, the chosen preset, camera defaults from the lens profile and the app defaults.
+// Every layer is a partial project json (same structure as the .gyroflow file), so updating the preset
+// changes all values which weren't explicitly overridden for the clip.
+// Applying a preset is an explicit choice of its values, so it replaces the clip overrides of the values it contains.
+// Overrides of other values are kept, and editing a value afterwards overrides the preset again.
+// Presets are marked with `"is_preset": true` when saved. Files without the marker are never treated as presets.
+
+use serde_json::{ json, Map, Value };
+use std::collections::BTreeMap;
+use crate::lens_profile::LensProfile;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum ParameterSource {
+    Default,
+    Camera,
+    Preset,
+    Clip
+}
+impl ParameterSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Default => "default",
+            Self::Camera  => "camera",
+            Self::Preset  => "preset",
+            Self::Clip    => "clip",
+        }
+    }
+}
+
+const PRESET_MARKER: &str = "is_preset";
+
+// Top level keys of the project file which hold adjustable parameters
+const PARAMETER_KEYS: &[&str] = &[
+    "stabilization",
+    "gyro_source",
+    "background_color",
+    "background_mode",
+    "background_margin",
+    "background_margin_feather",
+];
+// Data of the clip itself, never inherited
+const EXCLUDED_PATHS: &[&str] = &[
+    "gyro_source.filepath",
+    "gyro_source.raw_imu",
+    "gyro_source.quaternions",
+    "gyro_source.image_orientations",
+    "gyro_source.gravity_vectors",
+    "gyro_source.integrated_quaternions",
+    "gyro_source.smoothed_quaternions",
+    "gyro_source.embedded_file",
+];
+
+#[derive(Default, Clone, Debug)]
+pub struct ParameterLayers {
+    pub defaults: Value,
+    pub camera: Value,
+    pub preset: Value,
+    pub preset_name: String,
+    pub clip: Value,
+}
+
+impl ParameterLayers {
+    /// Keeps only the parameter part of a project or preset json
+    pub fn filter_parameters(obj: &Value) -> Value {
+        let mut ret = Map::new();
+        if let Value::Object(obj) = obj {
+            for key in PARAMETER_KEYS {
+                if let Some(v) = obj.get(*key) {
+                    ret.insert(key.to_string(), v.clone());
+                }
+            }
+        }
+        let mut ret = Value::Object(ret);
+        for path in EXCLUDED_PATHS {
+            remove_path(&mut ret, path);
+        }
+        ret
+    }
+
+    pub fn is_preset(obj: &Value) -> bool {
+        obj.get(PRESET_MARKER).and_then(|x| x.as_bool()).unwrap_or_default()
+    }
+    pub fn mark_preset(obj: &mut Value) {
+        if let Value::Object(obj) = obj {
+            obj.insert(PRESET_MARKER.into(), Value::Bool(true));
+        }
+    }
+
+    pub fn camera_layer(lens: &LensProfile) -> Value {
+        let mut ret = json!({});
+        if let Some(v) = lens.frame_readout_time { set_path(&mut ret, "stabilization.frame_readout_time", json!(v)); }
+        if let Some(v) = lens.gyro_lpf           { set_path(&mut ret, "gyro_source.lpf", json!(v)); }
+        ret
+    }
+
+    /// Effective parameters without the per-clip overrides
+    pub fn inherited(&self) -> Value {
+        let mut ret = self.defaults.clone();
+        overlay(&mut ret, &self.camera);
+        overlay(&mut ret, &self.preset);
+        ret
+    }
+    pub fn resolve(&self) -> Value {
+        let mut ret = self.inherited();
+        overlay(&mut ret, &self.clip);
+        ret
+    }
+
+    /// Stores every value of `current` which differs from the inherited one as an explicit override of the clip
+    pub fn update_clip_overrides(&mut self, current: &Value) {
+        let inherited = self.inherited();
+        let mut clip = json!({});
+        for (path, value) in leaves(&Self::filter_parameters(current)) {
+            if get_path(&inherited, &path) != Some(&value) {
+                set_path(&mut clip, &path, value);
+            }
+        }
+        self.clip = clip;
+    }
+
+    pub fn set_preset(&mut self, name: &str, preset: &Value) {
+        self.preset_name = name.to_string();
+        self.preset = Self::filter_parameters(preset);
+    }
+    /// Sets the preset chosen by the user, its values replace the clip overrides
+    pub fn apply_preset(&mut self, name: &str, preset: &Value) {
+        self.set_preset(name, preset);
+        for (path, _) in leaves(&self.preset) {
+            remove_path(&mut self.clip, &path);
+        }
+    }
+    /// Writes the resolved parameters over `obj`, keeping its other values
+    pub fn resolve_into(&self, obj: &mut Value) {
+        overlay(obj, &self.resolve());
+    }
+    pub fn clear_preset(&mut self) {
+        self.preset_name.clear();
+        self.preset = Value::Null;
+    }
+    pub fn has_preset(&self) -> bool {
+        self.preset.as_object().map(|x| !x.is_empty()).unwrap_or_default()
+    }
+
+    pub fn set_override(&mut self, path: &str, value: Value) {
+        set_path(&mut self.clip, path, value);
+    }
+    pub fn clear_override(&mut self, path: &str) -> bool {
+        remove_path(&mut self.clip, path)
+    }
+
+    /// Where the current value of `path` (eg. "stabilization.fov") comes from
+    pub fn source_of(&self, path: &str) -> ParameterSource {
+        if      get_path(&self.clip,   path).is_some() { ParameterSource::Clip }
+        else if get_path(&self.preset, path).is_some() { ParameterSource::Preset }
+        else if get_path(&self.camera, path).is_some() { ParameterSource::Camera }
+        else                                           { ParameterSource::Default }
+    }
+    pub fn sources(&self) -> BTreeMap<String, ParameterSource> {
+        leaves(&self.resolve()).into_iter().map(|(path, _)| {
+            let source = self.source_of(&path);
+            (path, source)
+        }).collect()
+    }
+}
+
+// Unlike util::merge_json, arrays are replaced and not extended, because they are values (eg. smoothing params or rotation)
+fn overlay(a: &mut Value, b: &Value) {
+    match (a, b) {
+        (Value::Object(ref mut a), Value::Object(ref b)) => {
+            for (k, v) in b {
+                overlay(a.entry(k).or_insert(Value::Null), v);
+            }
+        }
+        (a, b) => {
+            *a = b.clone();
+        }
+    }
+}
+
+fn leaves(v: &Value) -> Vec<(String, Value)> {
+    fn collect(prefix: &str, v: &Value, out: &mut Vec<(String, Value)>) {
+        match v {
+            Value::Object(obj) => {
+                for (k, v) in obj {
+                    let path = if prefix.is_empty() { k.clone() } else { format!("{}.{}", prefix, k) };
+                    collect(&path, v, out);
+                }
+            }
+            _ => {
+                if !prefix.is_empty() {
+                    out.push((prefix.to_string(), v.clone()));
+                }
+            }
+        }
+    }
+    let mut ret = Vec::new();
+    collect("", v, &mut ret);
+    ret
+}
+
+fn get_path<'a>(v: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.').try_fold(v, |v, k| v.as_object()?.get(k))
+}
+
+fn set_path(v: &mut Value, path: &str, value: Value) {
+    let mut v = v;
+    for k in path.split('.') {
+        if !v.is_object() {
+            *v = json!({});
+        }
+        v = v.as_object_mut().unwrap().entry(k).or_insert(Value::Null);
+    }
+    *v = value;
+}
+
+fn remove_path(v: &mut Value, path: &str) -> bool {
+    let (parent, key) = match path.rsplit_once('.') {
+        Some((parent, key)) => (parent.split('.').try_fold(v, |v, k| v.as_object_mut()?.get_mut(k)), key),
+        None => (Some(v), path)
+    };
+    parent.and_then(|x| x.as_object_mut()).and_then(|x| x.remove(key)).is_some()
+}
@@ -0,0 +1,128 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2022 Adrian <adrian.eddy at gmail>
+
+// Internal parameters of the export pipeline, adjustable for troubleshooting the performance on unusual hardware,
+// and the throughput each stage achieved, so the reported numbers show which stage is the bottleneck.
+// A zeroed config is what the pipeline does by default. It can be set with `set_config` or the `GYROFLOW_PIPELINE` environment variable,
+// eg. `{"frames_in_flight": 4, "staging_buffers": 3, "chunk_rows": 256}`.
+// The stats are shared by all renders running in parallel, like the memory budget of the frame pool.
+
+use std::sync::atomic::{ AtomicU64, Ordering::SeqCst };
+use std::time::{ Duration, Instant };
+use parking_lot::{ Mutex, RwLock };
+
+#[derive(Default, Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct PipelineConfig {
+    pub frames_in_flight: usize, // Frame copies held by the frame-threaded encoders (ProRes, DNxHD, CineForm, PNG, EXR), 0 - only limited by the memory budget
+    pub staging_buffers: usize,  // Readback buffers of the wgpu backend used in rotation, so the GPU copies the next chunk while the previous one is read. 0 - 2
+    pub chunk_rows: usize,       // Output rows read back from the GPU per submission, 0 - the whole frame at once
+}
+
+impl PipelineConfig {
+    pub fn staging_buffers(&self) -> usize {
+        if self.staging_buffers > 0 { self.staging_buffers } else { 2 }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stage {
+    Decode = 0,   // Decoding, including the download of hardware frames
+    Process = 1,  // Stabilization of all planes
+    Encode = 2,   // Conversion to the encoder format, encoding and muxing
+    Readback = 3, // Copies from the GPU to the system memory, part of `Process`
+}
+const STAGES: usize = 4;
+
+#[derive(Default, Clone, Copy, Debug, serde::Serialize)]
+pub struct StageStats {
+    pub count: u64,          // Frames, or chunks for the readback
+    pub busy_s: f64,         // Time spent in the stage
+    pub per_second: f64,     // Achieved throughput when the stage is busy
+    pub mb_per_second: f64,  // Compressed output for the encoder, 0 for the stages which don't report the bytes
+}
+
+#[derive(Default, Clone, Copy, Debug, serde::Serialize)]
+pub struct PipelineStats {
+    pub config: PipelineConfig,
+    pub decode: StageStats,
+    pub process: StageStats,
+    pub encode: StageStats,
+    pub readback: StageStats,
+    pub elapsed_s: f64,
+    pub fps: f64, // Overall, by the processed frames
+}
+
+#[derive(Default)]
+struct StageCounter {
+    count: AtomicU64,
+    nanos: AtomicU64,
+    bytes: AtomicU64,
+}
+
+fn config_from_env() -> PipelineConfig {
+    let json = std::env::var("GYROFLOW_PIPELINE").unwrap_or_default();
+    if json.is_empty() { return PipelineConfig::default(); }
+    serde_json::from_str(&json).unwrap_or_else(|e| {
+        log::error!("Invalid GYROFLOW_PIPELINE {}: {:?}", json, e);
+        PipelineConfig::default()
+    })
+}
+
+lazy_static::lazy_static! {
+    static ref CONFIG: RwLock<PipelineConfig> = RwLock::new(config_from_env());
+    static ref COUNTERS: [StageCounter; STAGES] = Default::default();
+    static ref STARTED: Mutex<Option<Instant>> = Mutex::new(None);
+}
+
+pub fn config() -> PipelineConfig {
+    *CONFIG.read()
+}
+/// Applies to the kernels created afterwards, the wgpu buffers are allocated with the kernel
+pub fn set_config(config: PipelineConfig) {
+    log::info!("Pipeline config: {:?}", config);
+    *CONFIG.write() = config;
+}
+
+/// Clears the stats, at the start of the render
+pub fn reset_stats() {
+    for x in COUNTERS.iter() {
+        x.count.store(0, SeqCst);
+        x.nanos.store(0, SeqCst);
+        x.bytes.store(0, SeqCst);
+    }
+    *STARTED.lock() = Some(Instant::now());
+}
+
+pub fn record(stage: Stage, duration: Duration, bytes: usize) {
+    let counter = &COUNTERS[stage as usize];
+    counter.count.fetch_add(1, SeqCst);
+    counter.nanos.fetch_add(duration.as_nanos() as u64, SeqCst);
+    counter.bytes.fetch_add(bytes as u64, SeqCst);
+}
+
+pub fn stats() -> PipelineStats {
+    let stage = |stage: Stage| {
+        let counter = &COUNTERS[stage as usize];
+        let count = counter.count.load(SeqCst);
+        let busy_s = counter.nanos.load(SeqCst) as f64 / 1_000_000_000.0;
+        let bytes = counter.bytes.load(SeqCst) as f64;
+        StageStats {
+            count,
+            busy_s,
+            per_second:    if busy_s > 0.0 { count as f64 / busy_s } else { 0.0 },
+            mb_per_second: if busy_s > 0.0 { bytes / 1024.0 / 1024.0 / busy_s } else { 0.0 },
+        }
+    };
+    let elapsed_s = STARTED.lock().map(|x| x.elapsed().as_secs_f64()).unwrap_or_default();
+    let process = stage(Stage::Process);
+    PipelineStats {
+        config: config(),
+        decode: stage(Stage::Decode),
+        process,
+        encode: stage(Stage::Encode),
+        readback: stage(Stage::Readback),
+        elapsed_s,
+        fps: if elapsed_s > 0.0 { process.count as f64 / elapsed_s } else { 0.0 },
+    }
+}
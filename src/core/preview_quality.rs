@@ -0,0 +1,72 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2022 Adrian <adrian.eddy at gmail>
+
+// Automatic preview resolution. Processing time of the preview frames is measured during playback
+// and the processing resolution is stepped down when the frames can't keep up with the video frame rate,
+// and back up when there's enough headroom. Output crop doesn't change, because the output size is always relative to the input size.
+
+use std::collections::VecDeque;
+use std::time::Instant;
+
+const LADDER: [u32; 4] = [2160, 1080, 720, 480];
+const WINDOW: usize = 15;
+const SWITCH_COOLDOWN_MS: u128 = 1000;
+
+#[derive(Default)]
+pub struct PreviewQualityLadder {
+    pub enabled: bool,
+    pub target_fps: f64,
+    pub video_height: u32,
+    pub playing: bool,
+
+    rung: usize,
+    frame_times: VecDeque<f64>,
+    last_switch: Option<Instant>,
+}
+
+impl PreviewQualityLadder {
+    // Available processing heights for the video, starting from the full resolution
+    pub fn rungs(video_height: u32) -> Vec<u32> {
+        std::iter::once(video_height).chain(LADDER.iter().copied().filter(|&h| h < video_height)).collect()
+    }
+
+    pub fn reset(&mut self) {
+        self.rung = 0;
+        self.frame_times.clear();
+        self.last_switch = None;
+    }
+
+    pub fn current_height(&self) -> u32 {
+        let rungs = Self::rungs(self.video_height);
+        rungs[self.rung.min(rungs.len() - 1)]
+    }
+
+    // Returns the new processing height if the resolution should be changed
+    pub fn add_frame_time(&mut self, ms: f64) -> Option<u32> {
+        if !self.enabled || !self.playing || self.target_fps <= 0.0 || self.video_height == 0 { return None; }
+        if self.last_switch.map(|t| t.elapsed().as_millis() < SWITCH_COOLDOWN_MS).unwrap_or_default() { return None; }
+
+        self.frame_times.push_back(ms);
+        if self.frame_times.len() > WINDOW { self.frame_times.pop_front(); }
+        if self.frame_times.len() < WINDOW { return None; }
+
+        let avg = self.frame_times.iter().sum::<f64>() / self.frame_times.len() as f64;
+        // Leave some time for decoding and drawing
+        let budget = 1000.0 / self.target_fps * 0.8;
+
+        let rungs = Self::rungs(self.video_height);
+        let new_rung = if avg > budget && self.rung + 1 < rungs.len() {
+            self.rung + 1
+        } else if avg < budget * 0.4 && self.rung > 0 {
+            self.rung - 1
+        } else {
+            return None;
+        };
+        ::log::debug!("Preview frame time {:.2}ms, budget {:.2}ms, switching to {}p", avg, budget, rungs[new_rung]);
+
+        self.rung = new_rung;
+        self.frame_times.clear();
+        self.last_switch = Some(Instant::now());
+        Some(rungs[new_rung])
+    }
+}
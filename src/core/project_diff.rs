@@ -0,0 +1,156 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2022 Adrian <adrian.eddy at gmail>
+
+// Differences between two project files, for reviewing what changed between versions of a project.
+// Parameters are compared field by field (arrays as a whole value), keyframes and sync offsets by their timestamps.
+// The embedded motion data is only reported as changed, without the values. Fields which change on every save
+// (app version, date, file fingerprint) are ignored.
+
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeSet;
+use crate::error::GyroflowError;
+
+// Handled separately or ignored
+const SKIPPED_KEYS: &[&str] = &["title", "version", "app_version", "date", "videofile_fingerprint", "offsets", "keyframes"];
+// Compressed motion data in `gyro_source`
+const DATA_KEYS: &[&str] = &["raw_imu", "quaternions", "image_orientations", "gravity_vectors", "integrated_quaternions", "smoothed_quaternions"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Change {
+    Added,
+    Removed,
+    Modified,
+}
+impl Change {
+    fn between(old: &Value, new: &Value) -> Self {
+        match (old.is_null(), new.is_null()) {
+            (true, _) => Self::Added,
+            (_, true) => Self::Removed,
+            _         => Self::Modified
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ParamDiff {
+    pub path: String, // eg. "stabilization/fov"
+    pub change: Change,
+    pub old: Value, // Null if the field doesn't exist
+    pub new: Value,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct KeyframeDiff {
+    pub keyframe: String, // Keyframe type, eg. "Fov"
+    pub timestamp_us: i64,
+    pub change: Change,
+    pub old: Value,
+    pub new: Value,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct OffsetDiff {
+    pub timestamp_us: i64,
+    pub change: Change,
+    pub old: Option<f64>, // ms
+    pub new: Option<f64>,
+}
+
+#[derive(Default, Clone, Debug, PartialEq, Serialize)]
+pub struct ProjectDiff {
+    pub params: Vec<ParamDiff>,
+    pub keyframes: Vec<KeyframeDiff>,
+    pub offsets: Vec<OffsetDiff>,
+    pub motion_data: Vec<String>, // Paths of the embedded motion data which differ
+}
+impl ProjectDiff {
+    pub fn is_empty(&self) -> bool {
+        self.params.is_empty() && self.keyframes.is_empty() && self.offsets.is_empty() && self.motion_data.is_empty()
+    }
+}
+
+/// Loads two .gyroflow files and compares them, `old` is the reference
+pub fn diff_files(old: &str, new: &str) -> Result<ProjectDiff, GyroflowError> {
+    let load = |path: &str| -> Result<Value, GyroflowError> {
+        Ok(serde_json::from_str(&crate::filesystem::read_to_string(path)?)?)
+    };
+    Ok(diff(&load(old)?, &load(new)?))
+}
+
+pub fn diff(old: &Value, new: &Value) -> ProjectDiff {
+    let mut ret = ProjectDiff::default();
+    walk("", old, new, &mut ret);
+    ret.keyframes = diff_keyframes(old.get("keyframes").unwrap_or(&Value::Null), new.get("keyframes").unwrap_or(&Value::Null));
+    ret.offsets = diff_offsets(old.get("offsets").unwrap_or(&Value::Null), new.get("offsets").unwrap_or(&Value::Null));
+    ret
+}
+
+fn walk(path: &str, a: &Value, b: &Value, ret: &mut ProjectDiff) {
+    match (a, b) {
+        (Value::Object(a), Value::Object(b)) => {
+            let keys: BTreeSet<&String> = a.keys().chain(b.keys()).collect();
+            for key in keys {
+                if path.is_empty() && SKIPPED_KEYS.contains(&key.as_str()) { continue; }
+                let field = if path.is_empty() { key.clone() } else { format!("{}/{}", path, key) };
+                let (a, b) = (a.get(key).unwrap_or(&Value::Null), b.get(key).unwrap_or(&Value::Null));
+                if path == "gyro_source" && DATA_KEYS.contains(&key.as_str()) {
+                    if a != b { ret.motion_data.push(field); }
+                    continue;
+                }
+                walk(&field, a, b, ret);
+            }
+        },
+        _ if !values_equal(a, b) => ret.params.push(ParamDiff { path: path.to_string(), change: Change::between(a, b), old: a.clone(), new: b.clone() }),
+        _ => { }
+    }
+}
+
+// Numbers are compared with a tolerance, they don't always serialize to the same text
+fn values_equal(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => (a.as_f64().unwrap_or_default() - b.as_f64().unwrap_or_default()).abs() < 1e-9,
+        (Value::Array(a), Value::Array(b)) => a.len() == b.len() && a.iter().zip(b).all(|(a, b)| values_equal(a, b)),
+        _ => a == b
+    }
+}
+
+// Keyframes are stored as { type: { timestamp_us: keyframe } }
+fn diff_keyframes(old: &Value, new: &Value) -> Vec<KeyframeDiff> {
+    let empty = serde_json::Map::new();
+    let (old, new) = (old.as_object().unwrap_or(&empty), new.as_object().unwrap_or(&empty));
+    let mut ret = Vec::new();
+    for typ in old.keys().chain(new.keys()).collect::<BTreeSet<_>>() {
+        let a = old.get(typ).and_then(|x| x.as_object()).unwrap_or(&empty);
+        let b = new.get(typ).and_then(|x| x.as_object()).unwrap_or(&empty);
+        let mut timestamps: Vec<(i64, &String)> = a.keys().chain(b.keys()).filter_map(|x| Some((x.parse::<i64>().ok()?, x))).collect();
+        timestamps.sort();
+        timestamps.dedup();
+        for (timestamp_us, key) in timestamps {
+            let (a, b) = (a.get(key).unwrap_or(&Value::Null), b.get(key).unwrap_or(&Value::Null));
+            if !values_equal(a, b) {
+                ret.push(KeyframeDiff { keyframe: typ.clone(), timestamp_us, change: Change::between(a, b), old: a.clone(), new: b.clone() });
+            }
+        }
+    }
+    ret
+}
+
+// Offsets are stored as { timestamp_us: offset_ms }
+fn diff_offsets(old: &Value, new: &Value) -> Vec<OffsetDiff> {
+    let parse = |v: &Value| -> std::collections::BTreeMap<i64, f64> {
+        v.as_object().map(|x| x.iter().filter_map(|(k, v)| Some((k.parse::<i64>().ok()?, v.as_f64()?))).collect()).unwrap_or_default()
+    };
+    let (old, new) = (parse(old), parse(new));
+    old.keys().chain(new.keys()).collect::<BTreeSet<_>>().into_iter().filter_map(|ts| {
+        let (a, b) = (old.get(ts).copied(), new.get(ts).copied());
+        let change = match (a, b) {
+            (None, Some(_)) => Change::Added,
+            (Some(_), None) => Change::Removed,
+            (Some(a), Some(b)) if (a - b).abs() > 1e-6 => Change::Modified,
+            _ => { return None; }
+        };
+        Some(OffsetDiff { timestamp_us: *ts, change, old: a, new: b })
+    }).collect()
+}
@@ -26,11 +26,34 @@ pub fn lock_horizon_angle(q: &UnitQuaternion<f64>, roll_correction: f64) -> Unit
     initial_quat * rot_yaw * rot_pitch * rot_roll
 }
 
+// Moves the view direction towards the horizontal plane by `amount` (0..1) of its pitch, keeping the yaw.
+// `up` is the vertical direction in the coordinates of `q`, -z without gravity vectors.
+// The rotation is around the horizontal axis perpendicular to the view direction, so the roll isn't affected
+pub fn lock_pitch_angle(q: &UnitQuaternion<f64>, amount: f64, up: &Vector3<f64>) -> UnitQuaternion<f64> {
+    if amount <= 0.0 { return *q; }
+    let up = match up.try_normalize(1e-9) { Some(v) => v, None => return *q };
+
+    let view = (q * nalgebra::Vector3::<f64>::z_axis()).into_inner();
+    let elevation = view.dot(&up).clamp(-1.0, 1.0);
+    let horizontal = match (view - up * elevation).try_normalize(1e-9) {
+        Some(v) => v,
+        None => return *q // Looking straight up or down, there's no yaw to keep
+    };
+
+    let pitch = elevation.asin() * (1.0 - amount.min(1.0));
+    let target = horizontal * pitch.cos() + up * pitch.sin();
+    match UnitQuaternion::rotation_between(&view, &target) {
+        Some(rot) => rot * q,
+        None => *q
+    }
+}
+
 #[derive(Clone)]
 pub struct HorizonLock {
     pub lock_enabled: bool,
-    pub horizonlockpercent: f64,
+    pub horizonlockpercent: f64, // Roll lock strength
     pub horizonroll: f64,
+    pub horizonlockpitch: f64, // Pitch lock strength, usually undesirable for FPV where the camera is tilted on purpose
 }
 
 impl Default for HorizonLock {
@@ -38,6 +61,7 @@ impl Default for HorizonLock {
         lock_enabled: false,
         horizonlockpercent: 100.0,
         horizonroll: 0.0,
+        horizonlockpitch: 0.0,
     } }
 }
 
@@ -45,17 +69,33 @@ impl HorizonLock {
     pub fn set_horizon(&mut self, lock_percent: f64, roll: f64) {
         self.horizonroll = roll;
         self.horizonlockpercent = lock_percent;
+        self.update_enabled();
+    }
+    pub fn set_pitch_lock(&mut self, lock_percent: f64) {
+        self.horizonlockpitch = lock_percent;
+        self.update_enabled();
+    }
+    fn update_enabled(&mut self) {
         self.lock_enabled = self.horizonlockpercent > 1e-6;
     }
+    // Pitch is locked on its own, `lock_enabled` is only for the roll
+    pub fn pitch_lock_enabled(&self) -> bool {
+        self.horizonlockpitch > 1e-6
+    }
     pub fn get_checksum(&self) -> u64 {
         let mut hasher = std::collections::hash_map::DefaultHasher::new();
         hasher.write_u64(self.horizonlockpercent.to_bits());
         hasher.write_u64(self.horizonroll.to_bits());
+        hasher.write_u64(self.horizonlockpitch.to_bits());
         hasher.finish()
     }
 
     pub fn lock(&self, quats: &TimeQuat, org_quats: &TimeQuat, grav: &Option<crate::gyro_source::TimeVec>, use_grav: bool, _int_method: usize, keyframes: &KeyframeManager) -> TimeQuat {
-        if self.lock_enabled {
+        if self.lock_enabled || self.pitch_lock_enabled() {
+            // Keyframes apply only to the enabled locks
+            let roll_amount = |timestamp_ms: f64| if self.lock_enabled { keyframes.value_at_gyro_timestamp(&KeyframeType::LockHorizonAmount, timestamp_ms).unwrap_or(self.horizonlockpercent) } else { 0.0 };
+            let pitch_amount = |timestamp_ms: f64| if self.pitch_lock_enabled() { keyframes.value_at_gyro_timestamp(&KeyframeType::LockHorizonPitchAmount, timestamp_ms).unwrap_or(self.horizonlockpitch) } else { 0.0 };
+
             if let Some(gvec) = grav {
                 if !gvec.is_empty() && use_grav {
                     let z_axis = nalgebra::Vector3::<f64>::z_axis();
@@ -65,6 +105,8 @@ impl HorizonLock {
                     return quats.iter().map(|(ts, smoothed_ori)| {
                             let gv = Self::interpolate_gravity_vector(&gvec, *ts).unwrap_or(*y_axis);
                             let ori = org_quats.get(ts).unwrap_or(&smoothed_ori).to_rotation_matrix();
+                            // Gravity points down in the camera coordinates, `ori` rotates it to the coordinates of the quaternions
+                            let up = -(ori * gv);
 
                             // Correct for angle difference between original and smoothed orientation
                             let correction = ori.inverse() * smoothed_ori.to_rotation_matrix();
@@ -72,12 +114,14 @@ impl HorizonLock {
 
                             let timestamp_ms = *ts as f64 / 1000.0;
                             let horizonroll = keyframes.value_at_gyro_timestamp(&KeyframeType::LockHorizonRoll, timestamp_ms).unwrap_or(self.horizonroll);
-                            let horizonlockpercent = keyframes.value_at_gyro_timestamp(&KeyframeType::LockHorizonAmount, timestamp_ms).unwrap_or(self.horizonlockpercent);
+                            let horizonlockpercent = roll_amount(timestamp_ms);
+                            let horizonlockpitch = pitch_amount(timestamp_ms);
 
                             // let gv_corrected = corr.inverse() * correction * corr * gv; // Alternative matrix approach
                             // let locked_ori = smoothed_ori.to_rotation_matrix() * Rotation3::from_axis_angle(&z_axis, gv_corrected[0].simd_atan2(gv_corrected[1]) + horizonroll * std::f64::consts::PI / 180.0);
                             let locked_ori = smoothed_ori.to_rotation_matrix() * Rotation3::from_axis_angle(&z_axis, -angle_corr + gv[0].simd_atan2(gv[1]) + horizonroll * std::f64::consts::PI / 180.0);
-                            (*ts, UnitQuaternion::from_rotation_matrix(&locked_ori).slerp(&smoothed_ori, 1.0 - horizonlockpercent / 100.0))
+                            let roll_locked = if self.lock_enabled { UnitQuaternion::from_rotation_matrix(&locked_ori).slerp(&smoothed_ori, 1.0 - horizonlockpercent / 100.0) } else { *smoothed_ori };
+                            (*ts, lock_pitch_angle(&roll_locked, horizonlockpitch / 100.0, &up))
                         }).collect();
                 }
             }
@@ -85,9 +129,11 @@ impl HorizonLock {
             return quats.iter().map(|(ts, smoothed_ori)| {
                     let timestamp_ms = *ts as f64 / 1000.0;
                     let horizonroll = keyframes.value_at_gyro_timestamp(&KeyframeType::LockHorizonRoll, timestamp_ms).unwrap_or(self.horizonroll);
-                    let horizonlockpercent = keyframes.value_at_gyro_timestamp(&KeyframeType::LockHorizonAmount, timestamp_ms).unwrap_or(self.horizonlockpercent);
+                    let horizonlockpercent = roll_amount(timestamp_ms);
+                    let horizonlockpitch = pitch_amount(timestamp_ms);
 
-                    (*ts, lock_horizon_angle(smoothed_ori, horizonroll * std::f64::consts::PI / 180.0).slerp(&smoothed_ori, 1.0 - horizonlockpercent / 100.0))
+                    let roll_locked = if self.lock_enabled { lock_horizon_angle(smoothed_ori, horizonroll * std::f64::consts::PI / 180.0).slerp(&smoothed_ori, 1.0 - horizonlockpercent / 100.0) } else { *smoothed_ori };
+                    (*ts, lock_pitch_angle(&roll_locked, horizonlockpitch / 100.0, &-Vector3::z()))
                 }).collect();
         }
         quats.clone()
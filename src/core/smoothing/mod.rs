@@ -86,6 +86,9 @@ impl Smoothing {
     pub fn set_current(&mut self, id: usize) {
         self.current_id = id.min(self.algs.len() - 1);
     }
+    pub fn current_id(&self) -> usize {
+        self.current_id
+    }
 
     pub fn current(&self) -> &Box<dyn SmoothingAlgorithm> {
         &self.algs[self.current_id]
@@ -6,6 +6,7 @@ use super::PixelType;
 use super::distortion_models::DistortionModel;
 use crate::GyroSource;
 use crate::keyframes::KeyframeManager;
+use crate::duplicate_frames::DuplicateFrames;
 use nalgebra::Matrix3;
 
 #[derive(Default, Clone)]
@@ -30,6 +31,9 @@ pub struct ComputeParams {
     pub distortion_coeffs: [f64; 12],
     pub radial_distortion_limit: f64,
     pub lens_correction_amount: f64,
+    pub stabilization_amount: f64,
+    pub output_projection: crate::stabilization_params::OutputProjection,
+    pub output_projection_strength: f64,
     pub video_speed: f64,
     pub video_speed_affects_smoothing: bool,
     pub video_speed_affects_zooming: bool,
@@ -44,11 +48,16 @@ pub struct ComputeParams {
     pub input_vertical_stretch: f64,
     pub adaptive_zoom_window: f64,
     pub adaptive_zoom_center_offset: (f64, f64),
+    pub max_zoom: f64,
+    pub zoom_border_tolerance: f64,
     pub is_superview: bool,
     pub framebuffer_inverted: bool,
 
     pub zooming_debug_points: bool,
 
+    // Duplicated frames rendered with the camera motion since the original frame, see `FrameTransform::at_timestamp`
+    pub duplicate_frames: DuplicateFrames,
+
     pub distortion_model: DistortionModel
 }
 impl ComputeParams {
@@ -66,15 +75,22 @@ impl ComputeParams {
             (params.size.0.max(1) as f64, params.size.1.max(1) as f64)
         };
 
-        let input_horizontal_stretch = if lens.input_horizontal_stretch > 0.01 { lens.input_horizontal_stretch } else { 1.0 };
+        // Profiles with their own stretch (eg. for anamorphic lenses) already describe the pixel geometry,
+        // otherwise the non-square pixels of the video are stretched to square ones before undistortion
+        let input_horizontal_stretch = if lens.input_horizontal_stretch > 0.01 && (lens.input_horizontal_stretch - 1.0).abs() > 0.0001 { lens.input_horizontal_stretch } else if params.pixel_aspect_ratio > 0.01 { params.pixel_aspect_ratio } else { 1.0 };
         let input_vertical_stretch = if lens.input_vertical_stretch > 0.01 { lens.input_vertical_stretch } else { 1.0 };
 
-        let lens_ratiox = (params.video_size.0 as f64 / calib_width) * input_horizontal_stretch;
-        let lens_ratioy = (params.video_size.1 as f64 / calib_height) * input_vertical_stretch;
+        // With black bars in the video, the lens profile corresponds to the image area only
+        let crop = params.input_crop.unwrap_or((0.0, 0.0, 1.0, 1.0));
+
+        let lens_ratiox = (params.video_size.0 as f64 * crop.2 / calib_width) * input_horizontal_stretch;
+        let lens_ratioy = (params.video_size.1 as f64 * crop.3 / calib_height) * input_vertical_stretch;
         camera_matrix[(0, 0)] *= lens_ratiox;
         camera_matrix[(1, 1)] *= lens_ratioy;
         camera_matrix[(0, 2)] *= lens_ratiox;
         camera_matrix[(1, 2)] *= lens_ratioy;
+        camera_matrix[(0, 2)] += params.video_size.0 as f64 * crop.0 * input_horizontal_stretch;
+        camera_matrix[(1, 2)] += params.video_size.1 as f64 * crop.1 * input_vertical_stretch;
 
         let distortion_model = DistortionModel::from_id(lens.distortion_model_id);
 
@@ -101,6 +117,9 @@ impl ComputeParams {
             background_margin: params.background_margin,
             background_margin_feather: params.background_margin_feather,
             lens_correction_amount: params.lens_correction_amount,
+            stabilization_amount: params.stabilization_amount,
+            output_projection: params.output_projection,
+            output_projection_strength: params.output_projection_strength,
             framebuffer_inverted: params.framebuffer_inverted,
             frame_readout_time: params.frame_readout_time,
             trim_start: params.trim_start,
@@ -110,6 +129,8 @@ impl ComputeParams {
             scaled_fps: params.get_scaled_fps(),
             adaptive_zoom_window: params.adaptive_zoom_window,
             adaptive_zoom_center_offset: params.adaptive_zoom_center_offset,
+            max_zoom: params.max_zoom,
+            zoom_border_tolerance: params.zoom_border_tolerance,
             video_speed: params.video_speed,
             video_speed_affects_smoothing: params.video_speed_affects_smoothing,
             video_speed_affects_zooming: params.video_speed_affects_zooming,
@@ -119,7 +140,9 @@ impl ComputeParams {
 
             keyframes: mgr.keyframes.read().clone(),
 
-            zooming_debug_points: false
+            zooming_debug_points: false,
+
+            duplicate_frames: DuplicateFrames::default()
         }
     }
 }
@@ -130,9 +153,16 @@ impl std::fmt::Debug for ComputeParams {
          .field("gyro.imu_orientation", &self.gyro.imu_orientation)
          .field("gyro.imu_rotation", &self.gyro.imu_rotation_angles)
          .field("gyro.acc_rotation", &self.gyro.acc_rotation_angles)
+         .field("gyro.extrinsic_rotation", &self.gyro.extrinsic_rotation_angles)
          .field("gyro.duration_ms", &self.gyro.duration_ms)
          .field("gyro.fps", &self.gyro.fps)
          .field("gyro.imu_lpf", &self.gyro.imu_lpf)
+         .field("gyro.imu_lpf_type", &self.gyro.imu_lpf_type)
+         .field("gyro.imu_lpf_order", &self.gyro.imu_lpf_order)
+         .field("gyro.imu_lpf_axes", &self.gyro.imu_lpf_axes)
+         .field("gyro.acc_lpf", &self.gyro.acc_lpf)
+         .field("gyro.acc_bias", &self.gyro.acc_bias)
+         .field("gyro.acc_scale", &self.gyro.acc_scale)
          .field("gyro.gyro_bias", &self.gyro.gyro_bias)
          .field("gyro.integration_method", &self.gyro.integration_method)
          .field("fovs.len", &self.fovs.len())
@@ -154,6 +184,9 @@ impl std::fmt::Debug for ComputeParams {
          .field("distortion_coeffs",    &self.distortion_coeffs)
          .field("radial_distortion_limit",   &self.radial_distortion_limit)
          .field("lens_correction_amount",    &self.lens_correction_amount)
+         .field("stabilization_amount",      &self.stabilization_amount)
+         .field("output_projection",         &self.output_projection)
+         .field("output_projection_strength", &self.output_projection_strength)
          .field("background_mode",           &self.background_mode)
          .field("background_margin",         &self.background_margin)
          .field("background_margin_feather", &self.background_margin_feather)
@@ -165,9 +198,12 @@ impl std::fmt::Debug for ComputeParams {
          .field("input_vertical_stretch",    &self.input_vertical_stretch)
          .field("adaptive_zoom_window",      &self.adaptive_zoom_window)
          .field("adaptive_zoom_center_offset", &self.adaptive_zoom_center_offset)
+         .field("max_zoom", &self.max_zoom)
+         .field("zoom_border_tolerance", &self.zoom_border_tolerance)
          .field("is_superview",              &self.is_superview)
          .field("framebuffer_inverted",      &self.framebuffer_inverted)
          .field("zooming_debug_points",      &self.zooming_debug_points)
+         .field("duplicate_frames",          &self.duplicate_frames.len())
          .field("distortion_model",          &self.distortion_model.id())
          .finish()
     }
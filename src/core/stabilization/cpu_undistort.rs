@@ -5,6 +5,7 @@ use super::{ PixelType, Stabilization, ComputeParams, FrameTransform, KernelPara
 use nalgebra::{ Vector4, Matrix3 };
 use rayon::{ prelude::ParallelSliceMut, iter::{ ParallelIterator, IndexedParallelIterator } };
 use super::distortion_models::GoProSuperview;
+use crate::keyframes::KeyframeType;
 
 pub const COEFFS: [f32; 64+128+256] = [
     // Bilinear
@@ -59,11 +60,12 @@ impl<T: PixelType> Stabilization<T> {
     // https://github.com/opencv/opencv/blob/2b60166e5c65f1caccac11964ad760d847c536e4/modules/imgproc/src/opencl/remap.cl#L390-L498
     pub fn undistort_image_cpu<const I: i32>(pixels: &[u8], out_pixels: &mut [u8], params: &KernelParams, distortion_model: &DistortionModel, matrices: &[[f32; 9]]) {
         // From 0-255(JPEG/Full) to 16-235(MPEG/Limited)
-        fn remap_colorrange(px: &mut Vector4<f32>, is_y: bool) {
-            if is_y { *px *= 0.85882352; } // (235 - 16) / 255
-            else    { *px *= 0.87843137; } // (240 - 16) / 255
-            px[0] += 16.0;
-            px[1] += 16.0;
+        // Packed RGB also mixes in the luma, because luma and chroma of the YUV encoding of the output scale differently
+        fn remap_colorrange(px: &mut Vector4<f32>, params: &KernelParams) {
+            let luma = params.range_luma[0] * (params.range_luma[1] * px[0] + params.range_luma[2] * px[1] + params.range_luma[3] * px[2]);
+            let alpha = px[3];
+            *px = *px * params.range_scale + Vector4::repeat(params.range_offset + luma);
+            px[3] = alpha;
         }
 
         fn rotate_and_distort(pos: (f32, f32), idx: usize, params: &KernelParams, matrices: &[[f32; 9]], distortion_model: &DistortionModel, r_limit: f32) -> Option<(f32, f32)> {
@@ -124,7 +126,7 @@ impl<T: PixelType> Stabilization<T> {
                             let px1: &T = bytemuck::from_bytes(&pixels[src_index as usize + (params.bytes_per_pixel * xp) as usize..src_index as usize + (params.bytes_per_pixel * (xp + 1)) as usize]);
                             let mut src_px = PixelType::to_float(*px1);
                             if fix_range {
-                                remap_colorrange(&mut src_px, params.bytes_per_pixel == 1)
+                                remap_colorrange(&mut src_px, params)
                             }
                             src_px
                         } else {
@@ -152,6 +154,10 @@ impl<T: PixelType> Stabilization<T> {
         let out_c2 = (params.output_width as f64, params.output_height as f64);
         let out_f = ((params.f[0] / params.fov / factor), (params.f[1] / params.fov / factor));
 
+        let proj_f = (params.f[0] / params.fov, params.f[1] / params.fov);
+        let proj_r_max = ((out_c.0 / proj_f.0).powi(2) + (out_c.1 / proj_f.1).powi(2)).sqrt();
+        let proj_strength = params.output_projection_strength;
+
         out_pixels.par_chunks_mut(params.output_stride as usize).enumerate().for_each(|(y, row_bytes)| { // Parallel iterator over buffer rows
             row_bytes.chunks_mut(params.bytes_per_pixel as usize).enumerate().for_each(|(x, pix_chunk)| { // iterator over row pixels
                 if y < params.output_height as usize && x < params.output_width as usize {
@@ -166,6 +172,28 @@ impl<T: PixelType> Stabilization<T> {
                         return;
                     }
 
+                    ///////////////////////////////////////////////////////////////////
+                    // Output projection (lens look)
+                    if proj_strength > 0.0 {
+                        if (params.flags & 8) == 8 { // Fisheye, corners stay in place
+                            let pt = ((out_pos.0 - out_c.0) / proj_f.0, (out_pos.1 - out_c.1) / proj_f.1);
+                            let r = (pt.0 * pt.0 + pt.1 * pt.1).sqrt();
+                            if r > 0.0 {
+                                let r2 = (r / proj_r_max * proj_r_max.atan()).tan();
+                                let scale = (r * (1.0 - proj_strength) + r2 * proj_strength) / r;
+                                out_pos = (out_c.0 + pt.0 * scale * proj_f.0, out_c.1 + pt.1 * scale * proj_f.1);
+                            }
+                        } else if (params.flags & 16) == 16 { // Superview
+                            let mut pt2 = GoProSuperview::from_superview((out_pos.0 as f64 / out_c2.0 - 0.5, out_pos.1 as f64 / out_c2.1 - 0.5));
+                            pt2 = ((pt2.0 + 0.5) * out_c2.0, (pt2.1 + 0.5) * out_c2.1);
+                            out_pos = (
+                                out_pos.0 * (1.0 - proj_strength) + pt2.0 as f32 * proj_strength,
+                                out_pos.1 * (1.0 - proj_strength) + pt2.1 as f32 * proj_strength
+                            );
+                        }
+                    }
+                    ///////////////////////////////////////////////////////////////////
+
                     ///////////////////////////////////////////////////////////////////
                     // Calculate source `y` for rolling shutter
                     let mut sy = y;
@@ -195,7 +223,7 @@ impl<T: PixelType> Stabilization<T> {
                     }
                     ///////////////////////////////////////////////////////////////////
 
-                    let idx = sy.min(params.matrix_count as usize - 1);
+                    let idx = (sy * params.matrix_count as usize / params.height.max(1) as usize).min(params.matrix_count as usize - 1);
                     if let Some(mut uv) = rotate_and_distort(out_pos, idx, params, matrices, distortion_model, r_limit) {
                         let width_f = params.width as f32;
                         let height_f = params.height as f32;
@@ -255,16 +283,19 @@ pub fn undistort_points_with_rolling_shutter(distorted: &[(f64, f64)], timestamp
     if distorted.is_empty() { return Vec::new(); }
     let (camera_matrix, distortion_coeffs, _p, rotations) = FrameTransform::at_timestamp_for_points(params, distorted, timestamp_ms);
 
-    undistort_points(distorted, camera_matrix, &distortion_coeffs, rotations[0], Some(Matrix3::identity()), Some(rotations), params)
+    // Use the keyframed value, so the zooming follows the animated lens correction strength
+    let lens_correction_amount = params.keyframes.value_at_video_timestamp(&KeyframeType::LensCorrectionStrength, timestamp_ms).unwrap_or(params.lens_correction_amount);
+
+    undistort_points(distorted, camera_matrix, &distortion_coeffs, rotations[0], Some(Matrix3::identity()), Some(rotations), lens_correction_amount, params)
 }
 pub fn undistort_points_with_params(distorted: &[(f64, f64)], rotation: Matrix3<f64>, p: Option<Matrix3<f64>>, rot_per_point: Option<Vec<Matrix3<f64>>>, params: &ComputeParams) -> Vec<(f64, f64)> {
     let img_dim_ratio = FrameTransform::get_ratio(params);
     let scaled_k = params.camera_matrix * img_dim_ratio;
 
-    undistort_points(distorted, scaled_k, &params.distortion_coeffs, rotation, p, rot_per_point, params)
+    undistort_points(distorted, scaled_k, &params.distortion_coeffs, rotation, p, rot_per_point, params.lens_correction_amount, params)
 }
 // Ported from OpenCV: https://github.com/opencv/opencv/blob/4.x/modules/calib3d/src/fisheye.cpp#L321
-pub fn undistort_points(distorted: &[(f64, f64)], camera_matrix: Matrix3<f64>, distortion_coeffs: &[f64], rotation: Matrix3<f64>, p: Option<Matrix3<f64>>, rot_per_point: Option<Vec<Matrix3<f64>>>, params: &ComputeParams) -> Vec<(f64, f64)> {
+pub fn undistort_points(distorted: &[(f64, f64)], camera_matrix: Matrix3<f64>, distortion_coeffs: &[f64], rotation: Matrix3<f64>, p: Option<Matrix3<f64>>, rot_per_point: Option<Vec<Matrix3<f64>>>, lens_correction_amount: f64, params: &ComputeParams) -> Vec<(f64, f64)> {
     let f = (camera_matrix[(0, 0)], camera_matrix[(1, 1)]);
     let c = (camera_matrix[(0, 2)], camera_matrix[(1, 2)]);
     let k = distortion_coeffs;
@@ -296,20 +327,20 @@ pub fn undistort_points(distorted: &[(f64, f64)], camera_matrix: Matrix3<f64>, d
             let pr = rot * nalgebra::Vector3::new(pt.0, pt.1, 1.0); // rotated point optionally multiplied by new camera matrix
             pt = (pr[0] / pr[2], pr[1] / pr[2]);
 
-            if params.lens_correction_amount < 1.0 {
+            if lens_correction_amount < 1.0 {
                 let mut out_c = c; // (params.output_width as f64 / 2.0, params.output_height as f64 / 2.0);
                 if params.input_horizontal_stretch > 0.001 { out_c.0 /= params.input_horizontal_stretch; }
                 if params.input_vertical_stretch   > 0.001 { out_c.1 /= params.input_vertical_stretch; }
 
                 pt = ((pt.0 - out_c.0) / f.0, (pt.1 - out_c.1) / f.1);
-                pt = params.distortion_model.distort_point(pt, k, params.lens_correction_amount);
+                pt = params.distortion_model.distort_point(pt, k, lens_correction_amount);
                 pt = ((pt.0 * f.0) + out_c.0, (pt.1 * f.1) + out_c.1);
 
                 if params.is_superview {
                     // TODO: This calculation is wrong but it somewhat works
                     let size = (params.width as f64, params.height as f64);
                     pt = (pt.0 / size.0 - 0.5, pt.1 / size.1 - 0.5);
-                    pt.0 *= 1.0 + (0.15 * (1.0 - params.lens_correction_amount));
+                    pt.0 *= 1.0 + (0.15 * (1.0 - lens_correction_amount));
                     pt = ((pt.0 + 0.5) * size.0, (pt.1 + 0.5) * size.1);
                 }
             }
@@ -5,6 +5,8 @@ use nalgebra::Matrix3;
 use super::{ ComputeParams, KernelParams };
 use rayon::iter::{ ParallelIterator, IntoParallelIterator };
 use crate::keyframes::KeyframeType;
+use crate::gyro_source::Quat64;
+use crate::stabilization_params::OutputProjection;
 
 #[derive(Default, Clone)]
 pub struct FrameTransform {
@@ -45,12 +47,25 @@ impl FrameTransform {
         fov
     }
 
+    // Rotation from the smoothed to the original camera orientation. `amount` < 1 slerps it towards identity (ie. the original path),
+    // so some of the natural camera motion is kept
+    fn smoothed_quat(params: &ComputeParams, quat_time: f64, amount: f64) -> Quat64 {
+        let quat = params.gyro.smoothed_quat_at_timestamp(quat_time);
+        if amount >= 1.0 {
+            quat
+        } else {
+            Quat64::identity().try_slerp(&quat, amount.max(0.0), 1e-9).unwrap_or(quat)
+        }
+    }
+
     pub fn at_timestamp(params: &ComputeParams, timestamp_ms: f64, frame: usize) -> Self {
         // ----------- Keyframes -----------
         let video_rotation = params.keyframes.value_at_video_timestamp(&KeyframeType::VideoRotation, timestamp_ms).unwrap_or(params.video_rotation);
         let background_margin = params.keyframes.value_at_video_timestamp(&KeyframeType::BackgroundMargin, timestamp_ms).unwrap_or(params.background_margin);
         let background_feather = params.keyframes.value_at_video_timestamp(&KeyframeType::BackgroundFeather, timestamp_ms).unwrap_or(params.background_margin_feather);
         let lens_correction_amount = params.keyframes.value_at_video_timestamp(&KeyframeType::LensCorrectionStrength, timestamp_ms).unwrap_or(params.lens_correction_amount);
+        let stabilization_amount = params.keyframes.value_at_video_timestamp(&KeyframeType::StabilizationAmount, timestamp_ms).unwrap_or(params.stabilization_amount);
+        let output_projection_strength = params.keyframes.value_at_video_timestamp(&KeyframeType::OutputProjectionStrength, timestamp_ms).unwrap_or(params.output_projection_strength);
         let adaptive_zoom_center_x = params.keyframes.value_at_video_timestamp(&KeyframeType::ZoomingCenterX, timestamp_ms).unwrap_or(params.adaptive_zoom_center_offset.0);
         let adaptive_zoom_center_y = params.keyframes.value_at_video_timestamp(&KeyframeType::ZoomingCenterY, timestamp_ms).unwrap_or(params.adaptive_zoom_center_offset.1);
         // ----------- Keyframes -----------
@@ -80,18 +95,30 @@ impl FrameTransform {
 
         let quat1 = params.gyro.org_quat_at_timestamp(timestamp_ms).inverse();
 
+        // Content of a duplicated frame was captured at the time of the original frame, so the rows are rotated
+        // with the camera orientation at that time, same as the rolling shutter correction does for every row
+        let content_delay_ms = params.duplicate_frames.original_timestamp((timestamp_ms * 1000.0).round() as i64)
+            .map(|x| timestamp_ms - x as f64 / 1000.0)
+            .unwrap_or_default();
+
         // Only compute 1 matrix if not using rolling shutter correction
-        let rows = if frame_readout_time.abs() > 0.0 { params.height } else { 1 };
+        let rows = if frame_readout_time.abs() > 0.0 {
+            if crate::low_memory::is_enabled() { params.height.min(crate::low_memory::MAX_MATRIX_ROWS) } else { params.height }
+        } else {
+            1
+        };
+        // Each matrix covers `row_step` rows of the video and is computed for the middle of them
+        let row_step = params.height as f64 / rows as f64;
 
         let matrices = (0..rows).into_par_iter().map(|y| {
             let quat_time = if frame_readout_time.abs() > 0.0 && timestamp_ms > 0.0 {
-                start_ts + row_readout_time * y as f64
+                start_ts + row_readout_time * ((y as f64 + 0.5) * row_step - 0.5)
             } else {
                 timestamp_ms
             };
-            let quat = params.gyro.smoothed_quat_at_timestamp(quat_time)
+            let quat = Self::smoothed_quat(params, quat_time, stabilization_amount)
                      * quat1
-                     * params.gyro.org_quat_at_timestamp(quat_time);
+                     * params.gyro.org_quat_at_timestamp(quat_time - content_delay_ms);
 
             let mut r = image_rotation * *quat.to_rotation_matrix().matrix();
             if params.framebuffer_inverted {
@@ -127,6 +154,7 @@ impl FrameTransform {
             background_mode:          params.background_mode as i32,
             background_margin:        background_margin as f32,
             background_margin_feather:background_feather as f32,
+            output_projection_strength: if params.output_projection == OutputProjection::Rectilinear { 0.0 } else { output_projection_strength.max(0.0).min(1.0) as f32 },
             translation2d: [(adaptive_zoom_center_x * params.width as f64 / fov) as f32, (adaptive_zoom_center_y * params.height as f64 / fov) as f32],
             translation3d: [0.0, 0.0, 0.0, 0.0], // currently unused
             ..Default::default()
@@ -142,6 +170,7 @@ impl FrameTransform {
     pub fn at_timestamp_for_points(params: &ComputeParams, points: &[(f64, f64)], timestamp_ms: f64) -> (Matrix3<f64>, [f64; 12], Matrix3<f64>, Vec<Matrix3<f64>>) { // camera_matrix, dist_coeffs, p, rotations_per_point
         // ----------- Keyframes -----------
         let video_rotation = params.keyframes.value_at_video_timestamp(&KeyframeType::VideoRotation, timestamp_ms).unwrap_or(params.video_rotation);
+        let stabilization_amount = params.keyframes.value_at_video_timestamp(&KeyframeType::StabilizationAmount, timestamp_ms).unwrap_or(params.stabilization_amount);
         // ----------- Keyframes -----------
 
         let img_dim_ratio = Self::get_ratio(params);
@@ -170,7 +199,7 @@ impl FrameTransform {
             } else {
                 timestamp_ms
             };
-            let quat = params.gyro.smoothed_quat_at_timestamp(quat_time)
+            let quat = Self::smoothed_quat(params, quat_time, stabilization_amount)
                      * quat1
                      * params.gyro.org_quat_at_timestamp(quat_time);
 
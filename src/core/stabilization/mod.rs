@@ -2,18 +2,22 @@
 // Copyright © 2021-2022 Adrian <adrian.eddy at gmail>
 
 use std::collections::BTreeMap;
+use std::sync::{ Arc, atomic::{ AtomicU32, Ordering::SeqCst } };
 use nalgebra::Vector4;
 
 #[cfg(feature = "use-opencl")]
 use super::gpu::opencl;
 use super::gpu::*;
+use super::gpu::context_manager::{ self, GpuTask, CPU };
 use super::StabilizationManager;
+use super::stabilization_params::OutputProjection;
 
 mod compute_params;
 mod frame_transform;
 mod cpu_undistort;
 mod pixel_formats;
 pub mod distortion_models;
+pub mod reference;
 pub use pixel_formats::*;
 pub use compute_params::ComputeParams;
 pub use frame_transform::FrameTransform;
@@ -33,6 +37,8 @@ bitflags::bitflags! {
         const FIX_COLOR_RANGE      = 1;
         const IS_GOPRO_SUPERVIEW   = 2;
         const FILL_WITH_BACKGROUND = 4;
+        const OUTPUT_FISHEYE       = 8;
+        const OUTPUT_SUPERVIEW     = 16;
     }
 }
 
@@ -64,11 +70,12 @@ pub struct KernelParams {
     pub input_horizontal_stretch: f32, // 4
     pub background_margin:        f32, // 8
     pub background_margin_feather:f32, // 12
-    pub reserved1:                f32, // 16
-    pub reserved2:                f32, // 4
-    pub reserved3:                f32, // 8
+    pub output_projection_strength: f32, // 16
+    pub range_scale:              f32, // 4  - color range conversion when FIX_COLOR_RANGE is set: px * range_scale + range_offset + range_luma[0] * luma
+    pub range_offset:             f32, // 8
     pub translation2d:         [f32; 2], // 16
     pub translation3d:         [f32; 4], // 16
+    pub range_luma:            [f32; 4], // 16 - weight of the luma and its RGB coefficients, only used by packed RGB
 }
 unsafe impl bytemuck::Zeroable for KernelParams {}
 unsafe impl bytemuck::Pod for KernelParams {}
@@ -83,15 +90,28 @@ pub struct Stabilization<T: PixelType> {
 
     pub interpolation: Interpolation,
     pub kernel_flags: KernelParamsFlags,
+    pub color_range_conversion: Option<(f32, f32, [f32; 4])>, // (scale, offset, luma) applied to the input pixels when the input and output color range differ
+    kernel_precision: KernelPrecision,
 
     #[cfg(feature = "use-opencl")]
     cl: Option<opencl::OclWrapper>,
 
+    #[cfg(feature = "use-cuda")]
+    cuda: Option<cuda::CudaWrapper>,
+
     wgpu: Option<wgpu::WgpuWrapper>,
 
     backend_initialized: Option<(usize, usize, usize,   usize, usize, usize)>, // (in_w, in_h, in_s,  out_w, out_h, out_s)
 
-    pub gpu_list: Vec<String>,
+    task: GpuTask,
+    device: Option<isize>, // Index in the device list or `CPU`, None - the default device. Differs from `selected_device` while a frame is processed on a fallback
+    selected_device: Option<isize>,
+    fallback: Option<Vec<isize>>, // Devices left to try after a failure in the current frame, see `GpuContextManager::fallback_chain`
+    reported_fallback: Option<(Option<isize>, isize)>, // Last switch reported to the UI, so it's not repeated for every frame
+
+    // Highest mip level the frames were processed at, 0 if everything was processed at full size, see `gpu::downscale`.
+    // Shared by all planes of a render
+    pub downscaled_level: Arc<AtomicU32>,
 
     pub current_fov: f64,
     compute_params: ComputeParams,
@@ -104,6 +124,16 @@ impl<T: PixelType> Stabilization<T> {
         self.stab_data.clear();
         self.compute_params = params;
         self.kernel_flags.set(KernelParamsFlags::IS_GOPRO_SUPERVIEW, self.compute_params.is_superview);
+        self.kernel_flags.set(KernelParamsFlags::OUTPUT_FISHEYE,     self.compute_params.output_projection == OutputProjection::Fisheye);
+        self.kernel_flags.set(KernelParamsFlags::OUTPUT_SUPERVIEW,   self.compute_params.output_projection == OutputProjection::Superview);
+    }
+
+    /// Marks the frame at `timestamp_us` as a duplicate of the frame at `original_timestamp_us`
+    pub fn set_duplicate_frame(&mut self, timestamp_us: i64, original_timestamp_us: i64) {
+        if self.compute_params.duplicate_frames.original_timestamp(timestamp_us) != Some(original_timestamp_us) {
+            self.compute_params.duplicate_frames.insert(timestamp_us, original_timestamp_us);
+            self.stab_data.remove(&timestamp_us);
+        }
     }
 
     pub fn ensure_stab_data_at_timestamp(&mut self, timestamp_us: i64) {
@@ -123,8 +153,22 @@ impl<T: PixelType> Stabilization<T> {
             transform.kernel_params.bytes_per_pixel = (T::COUNT * T::SCALAR_BYTES) as i32;
             transform.kernel_params.pix_element_count = T::COUNT as i32;
             transform.kernel_params.flags = self.kernel_flags.bits();
+            if let Some((scale, offset, luma)) = self.color_range_conversion {
+                transform.kernel_params.flags |= KernelParamsFlags::FIX_COLOR_RANGE.bits();
+                transform.kernel_params.range_scale  = scale;
+                transform.kernel_params.range_offset = offset;
+                transform.kernel_params.range_luma   = luma;
+            }
 
             self.stab_data.insert(timestamp_us, transform);
+
+            if crate::low_memory::is_enabled() {
+                while self.stab_data.len() > crate::low_memory::MAX_CACHED_TRANSFORMS {
+                    let first = *self.stab_data.keys().next().unwrap();
+                    let last = *self.stab_data.keys().next_back().unwrap();
+                    self.stab_data.remove(if timestamp_us - first > last - timestamp_us { &first } else { &last });
+                }
+            }
         }
     }
 
@@ -133,6 +177,8 @@ impl<T: PixelType> Stabilization<T> {
 
         #[cfg(feature = "use-opencl")]
         if self.cl  .is_some() { self.backend_initialized = None; }
+        #[cfg(feature = "use-cuda")]
+        if self.cuda.is_some() { self.backend_initialized = None; }
         if self.wgpu.is_some() { self.backend_initialized = None; }
 
         self.size = size;
@@ -151,51 +197,73 @@ impl<T: PixelType> Stabilization<T> {
     }
 
     pub fn list_devices(&self) -> Vec<String> {
-        let mut ret = Vec::new();
+        context_manager::MANAGER.write().list_devices()
+    }
 
+    pub fn current_backend(&self) -> &'static str {
+        #[cfg(feature = "use-cuda")]
+        if self.cuda.is_some() { return "CUDA"; }
         #[cfg(feature = "use-opencl")]
-        if std::env::var("NO_OPENCL").unwrap_or_default().is_empty() {
-            ret.extend(opencl::OclWrapper::list_devices().into_iter().map(|x| format!("[OpenCL] {x}")));
-        }
-        if std::env::var("NO_WGPU").unwrap_or_default().is_empty() {
-            ret.extend(wgpu::WgpuWrapper::list_devices().into_iter().map(|x| format!("[wgpu] {x}")));
+        if self.cl.is_some() { return "OpenCL"; }
+        if self.wgpu.is_some() { return "wgpu"; }
+        "CPU"
+    }
+
+    pub fn set_kernel_precision(&mut self, precision: KernelPrecision) {
+        if self.kernel_precision != precision {
+            self.kernel_precision = precision;
+            // Kernels have to be recompiled
+            if self.device != Some(CPU) { self.backend_initialized = None; }
         }
-        ret
     }
 
+    /// Device index in `list_devices`, -1 for CPU. The device is also selected for the task of this instance, see `set_task`
     pub fn set_device(&mut self, i: isize) -> bool {
-        if i < 0 { // CPU
-            #[cfg(feature = "use-opencl")]
-            { self.cl = None; }
-            self.wgpu = None;
-            self.backend_initialized = Some(Default::default());
-            return true;
+        let i = if i < 0 { CPU } else { i };
+        if !context_manager::MANAGER.write().select(self.task, i) {
+            log::error!("Invalid processing device index: {}", i);
+            return false;
         }
-        if let Some(name) = self.gpu_list.get(i as usize) {
-            if name.starts_with("[OpenCL]") {
-                self.backend_initialized = None;
-                #[cfg(feature = "use-opencl")]
-                match opencl::OclWrapper::set_device(i as usize) {
-                    Ok(_) => { return true; },
-                    Err(e) => {
-                        log::error!("Failed to set OpenCL device {}: {:?}", name, e);
-                    }
-                }
-            } else if name.starts_with("[wgpu]") {
-                self.backend_initialized = None;
-                let first_ind = self.gpu_list.iter().enumerate().find(|(_, m)| m.starts_with("[wgpu]")).map(|(idx, _)| idx).unwrap_or(0);
-                let wgpu_ind = i - first_ind as isize;
-                if wgpu_ind >= 0 {
-                    match wgpu::WgpuWrapper::set_device(wgpu_ind as usize) {
-                        Some(_) => { return true; },
-                        None => {
-                            log::error!("Failed to set wgpu device {}", name);
-                        }
-                    }
-                }
-            }
+        self.selected_device = Some(i);
+        self.use_device(Some(i));
+        self.fallback = None;
+        self.reported_fallback = None;
+        true
+    }
+
+    /// Switches to the device selected for `task`
+    pub fn set_task(&mut self, task: GpuTask) {
+        self.task = task;
+        let device = context_manager::MANAGER.read().selected(task);
+        self.selected_device = device;
+        self.use_device(device);
+        self.fallback = None;
+        self.reported_fallback = None;
+    }
+
+    fn use_device(&mut self, device: Option<isize>) {
+        #[cfg(feature = "use-opencl")]
+        { self.cl = None; }
+        self.wgpu = None;
+        self.device = device;
+        self.backend_initialized = None;
+    }
+
+    // Moves to the next device in the fallback chain after the current one failed. Only for the current frame, see `process_pixels`
+    fn next_device(&mut self) {
+        let failed = self.device;
+        let chain = self.fallback.get_or_insert_with(|| context_manager::MANAGER.write().fallback_chain(failed));
+        let next = if chain.is_empty() { CPU } else { chain.remove(0) };
+        let (failed_name, next_name) = {
+            let mut manager = context_manager::MANAGER.write();
+            (manager.device_name(failed), manager.device_name(Some(next)))
+        };
+        log::warn!("Processing device {} failed, falling back to {}", failed_name, next_name);
+        if self.reported_fallback != Some((failed, next)) {
+            self.reported_fallback = Some((failed, next));
+            context_manager::report_fallback(&failed_name, &next_name);
         }
-        return false;
+        self.use_device(Some(next));
     }
 
     pub fn init_backends(&mut self, timestamp_us: i64, buffers: &BufferDescription) {
@@ -209,14 +277,31 @@ impl<T: PixelType> Stabilization<T> {
             if let Some(itm) = self.stab_data.get(&timestamp_us) {
                 let params = itm.kernel_params;
 
+                // Buffers in the CUDA memory can only be processed by the CUDA backend
+                #[cfg(feature = "use-cuda")]
+                if let BufferSource::Cuda { .. } = buffers.buffers {
+                    self.cuda = cuda::CudaWrapper::new(&params, T::ocl_names(), self.compute_params.distortion_model.opencl_functions(), buffers);
+                    if self.cuda.is_none() { log::error!("Failed to initialize CUDA"); }
+                    self.backend_initialized = Some(tuple);
+                    return;
+                }
+
+                // With the default device, OpenCL is tried first and then wgpu
+                let device = self.device;
+                let backend = device.and_then(|x| context_manager::MANAGER.write().backend(x));
+                if device == Some(CPU) { gpu_initialized = true; }
+
                 #[cfg(feature = "use-opencl")]
-                if std::env::var("NO_OPENCL").unwrap_or_default().is_empty() {
+                if !gpu_initialized && (device.is_none() || backend == Some("OpenCL")) && std::env::var("NO_OPENCL").unwrap_or_default().is_empty() {
                     let cl = std::panic::catch_unwind(|| {
-                        opencl::OclWrapper::new(&params, T::ocl_names(), self.compute_params.distortion_model.opencl_functions(), buffers)
+                        context_manager::with_device(device, || {
+                            opencl::OclWrapper::new(&params, T::ocl_names(), self.compute_params.distortion_model.opencl_functions(), buffers, self.kernel_precision)
+                        })
                     });
                     match cl {
-                        Ok(Ok(cl)) => { self.cl = Some(cl); gpu_initialized = true; },
-                        Ok(Err(e)) => { log::error!("OpenCL error: {:?}", e); },
+                        Ok(Some(Ok(cl))) => { self.cl = Some(cl); gpu_initialized = true; },
+                        Ok(Some(Err(e))) => { log::error!("OpenCL error: {:?}", e); },
+                        Ok(None) => { log::error!("Failed to activate OpenCL device"); },
                         Err(e) => {
                             if let Some(s) = e.downcast_ref::<&str>() {
                                 log::error!("Failed to initialize OpenCL {}", s);
@@ -228,12 +313,14 @@ impl<T: PixelType> Stabilization<T> {
                         }
                     }
                 }
-                if !gpu_initialized && T::wgpu_format().is_some() && std::env::var("NO_WGPU").unwrap_or_default().is_empty() {
+                if !gpu_initialized && (device.is_none() || backend == Some("wgpu")) && T::wgpu_format().is_some() && std::env::var("NO_WGPU").unwrap_or_default().is_empty() {
                     let wgpu = std::panic::catch_unwind(|| {
-                        wgpu::WgpuWrapper::new(&params, T::wgpu_format().unwrap(), self.compute_params.distortion_model.wgsl_functions(), buffers)
+                        context_manager::with_device(device, || {
+                            wgpu::WgpuWrapper::new(&params, T::wgpu_format().unwrap(), self.compute_params.distortion_model.wgsl_functions(), buffers, self.kernel_precision)
+                        })
                     });
                     match wgpu {
-                        Ok(Some(wgpu)) => { self.wgpu = Some(wgpu); },
+                        Ok(Some(Some(wgpu))) => { self.wgpu = Some(wgpu); },
                         Err(e) => {
                             if let Some(s) = e.downcast_ref::<&str>() {
                                 log::error!("Failed to initialize wgpu {}", s);
@@ -256,25 +343,62 @@ impl<T: PixelType> Stabilization<T> {
         if self.size != buffers.input_size || self.output_size != buffers.output_size || buffers.input_size.1 < 4 || buffers.output_size.1 < 4 { return false; }
 
         self.ensure_stab_data_at_timestamp(timestamp_us);
-        self.init_backends(timestamp_us, buffers);
 
-        if let Some(itm) = self.stab_data.get(&timestamp_us) {
+        // The previous frame fell back to another device, try the selected one again
+        if self.fallback.take().is_some() && self.device != self.selected_device {
+            self.use_device(self.selected_device);
+        }
+
+        // Until a device processes the frame, the last one in the fallback chain is the CPU
+        loop {
+            self.init_backends(timestamp_us, buffers);
+
+            let itm = match self.stab_data.get(&timestamp_us) {
+                Some(itm) => itm,
+                None => { return false; }
+            };
             self.current_fov = itm.fov;
 
+            // CUDA path
+            #[cfg(feature = "use-cuda")]
+            if let Some(ref mut cuda) = self.cuda {
+                if cuda.undistort_image(buffers, &itm) {
+                    return true;
+                }
+            }
+            #[cfg(feature = "use-cuda")]
+            if let BufferSource::Cuda { .. } = buffers.buffers {
+                return false;
+            }
+
             // OpenCL path
             #[cfg(feature = "use-opencl")]
             if let Some(ref mut cl) = self.cl {
                 if let Err(err) = cl.undistort_image(buffers, &itm) {
                     log::error!("OpenCL error: {:?}", err);
                 } else {
+                    self.downscaled_level.fetch_max(cl.mip_level(), SeqCst);
                     return true;
                 }
             }
 
             // wgpu path
             if let Some(ref mut wgpu) = self.wgpu {
-                wgpu.undistort_image(buffers, &itm);
-                return true;
+                if wgpu.undistort_image(buffers, &itm) {
+                    self.downscaled_level.fetch_max(wgpu.mip_level(), SeqCst);
+                    return true;
+                }
+                log::error!("wgpu processing failed");
+            }
+
+            // Either the kernel failed, or it couldn't be initialized on the selected device
+            #[cfg(feature = "use-opencl")]
+            let cl_initialized = self.cl.is_some();
+            #[cfg(not(feature = "use-opencl"))]
+            let cl_initialized = false;
+            if cl_initialized || self.wgpu.is_some() || self.device.map_or(false, |x| x != CPU) {
+                self.next_device();
+                continue;
             }
 
             if let BufferSource::Cpu { input, output } = &mut buffers.buffers {
@@ -286,9 +410,8 @@ impl<T: PixelType> Stabilization<T> {
                 }
                 return true;
             }
-
+            return false;
         }
-        false
     }
 }
 
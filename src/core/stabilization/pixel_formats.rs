@@ -32,7 +32,10 @@ fn rgb_to_yuv(v: Vector4<f32>) -> Vector4<f32> {
 #[derive(Default, Clone, Copy, PartialEq, PartialOrd)] pub struct RGB16(u16, u16, u16);
 #[derive(Default, Clone, Copy, PartialEq, PartialOrd)] pub struct RGBA16(u16, u16, u16, u16);
 #[derive(Default, Clone, Copy, PartialEq, PartialOrd)] pub struct AYUV16(u16, u16, u16, u16);
+// Higher bit depths are only processed in the export: RGBA16 (RGBA64) and planar float (GBRP(A)F32) frames.
+// There is no half float type, because ffmpeg 5 has no RGBA half float pixel format to decode or encode it
 #[derive(Default, Clone, Copy, PartialEq, PartialOrd)] pub struct RGBAf(f32, f32, f32, f32);
+#[derive(Default, Clone, Copy, PartialEq, PartialOrd)] pub struct Lumaf(f32); // Plane of the planar float RGB formats
 #[derive(Default, Clone, Copy, PartialEq, PartialOrd)] pub struct UV8(u8, u8);
 #[derive(Default, Clone, Copy, PartialEq, PartialOrd)] pub struct UV16(u16, u16);
 
@@ -130,7 +133,19 @@ impl PixelType for RGBAf {
     #[inline] fn from_float(v: Vector4<f32>) -> Self { Self(v[0], v[1], v[2], v[3]) }
     #[inline] fn from_rgb_color(v: Vector4<f32>, _ind: &[usize], _max_val: f32) -> Vector4<f32> { v }
     #[inline] fn ocl_names() -> (&'static str, &'static str, &'static str, &'static str) { ("float4", "convert_float4", "float4", "convert_float4") }
-    #[inline] fn wgpu_format() -> Option<(wgpu::TextureFormat, &'static str, f64)> { Some((wgpu::TextureFormat::Rgba32Float, "f32", 255.0)) }
+    #[inline] fn wgpu_format() -> Option<(wgpu::TextureFormat, &'static str, f64)> { Some((wgpu::TextureFormat::Rgba32Float, "f32", 1.0)) }
+}
+unsafe impl bytemuck::Zeroable for Lumaf { }
+unsafe impl bytemuck::Pod for Lumaf { }
+impl PixelType for Lumaf {
+    const COUNT: usize = 1;
+    const SCALAR_BYTES: usize = 4;
+    type Scalar = f32;
+    #[inline] fn to_float(v: Self) -> Vector4<f32> { Vector4::new(v.0, 0.0, 0.0, 0.0) }
+    #[inline] fn from_float(v: Vector4<f32>) -> Self { Self(v[0]) }
+    #[inline] fn from_rgb_color(v: Vector4<f32>, ind: &[usize], max_val: f32) -> Vector4<f32> { Vector4::new(v[ind[0]] / 255.0 * max_val, 0.0, 0.0, 0.0) }
+    #[inline] fn ocl_names() -> (&'static str, &'static str, &'static str, &'static str) { ("float", "convert_float", "float", "convert_float") }
+    #[inline] fn wgpu_format() -> Option<(wgpu::TextureFormat, &'static str, f64)> { Some((wgpu::TextureFormat::R32Float, "f32", 1.0)) }
 }
 unsafe impl bytemuck::Zeroable for UV8 { }
 unsafe impl bytemuck::Pod for UV8 { }
@@ -0,0 +1,477 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2022 Adrian <adrian.eddy at gmail>
+
+// Reference implementation of the complete warp, in plain Rust and double precision.
+// It follows `cpu_undistort.rs` and the kernels step by step, but without any of their float32 shortcuts,
+// so it can be used as the ground truth for the OpenCL, wgpu and CPU backends.
+// Interpolation uses the same coefficient tables as the kernels, so differences only come from the geometry.
+//
+// `run_conformance` renders a set of synthetic cases with every available backend and compares them against the reference.
+
+use nalgebra::{ Matrix3, Rotation3, Vector3, Vector4 };
+use serde::Serialize;
+use super::{ PixelType, RGBA8, Stabilization, FrameTransform, KernelParams, KernelParamsFlags, Interpolation, COEFFS, distortion_models::DistortionModel };
+use super::super::gpu::{ BufferDescription, BufferSource, KernelPrecision };
+
+fn remap_colorrange(px: &mut Vector4<f64>, params: &KernelParams) {
+    let l = params.range_luma.map(|x| x as f64);
+    let luma = l[0] * (l[1] * px[0] + l[2] * px[1] + l[3] * px[2]);
+    let alpha = px[3];
+    *px = *px * params.range_scale as f64 + Vector4::repeat(params.range_offset as f64 + luma);
+    px[3] = alpha;
+}
+
+fn from_superview(mut pt: (f64, f64)) -> (f64, f64) {
+    pt.0 *= 1.0 - 0.45 * pt.0.abs();
+    pt.0 *= 0.168827 * (5.53572 + pt.0.abs());
+    pt.1 *= 0.130841 * (7.14285 + pt.1.abs());
+    pt
+}
+fn to_superview(mut pt: (f64, f64)) -> (f64, f64) {
+    pt.1 = (3.57143 - 0.5 * (51.0203 + 30.5714 * pt.1.abs()).sqrt()) * (-pt.1 / pt.1.abs().max(0.000001));
+    pt.0 = (2.76785 - 0.5 * (30.6441 + 23.6928 * pt.0.abs()).sqrt()) * (-pt.0 / pt.0.abs().max(0.000001));
+    pt.0 = (1.11111 - 0.5 * (4.93827 - 8.88889 * pt.0.abs()).sqrt()) * ( pt.0 / pt.0.abs().max(0.000001));
+    pt
+}
+
+struct Params {
+    size: (f64, f64),
+    f: (f64, f64),
+    c: (f64, f64),
+    k: [f64; 12],
+    r_limit_sq: f64,
+    lens_correction_amount: f64,
+    stretch: (f64, f64),
+    translation2d: (f64, f64),
+    translation3d: [f64; 3],
+    flags: i32,
+    matrices: Vec<Matrix3<f64>>,
+}
+
+fn rotate_and_distort(pos: (f64, f64), idx: usize, p: &Params, model: &DistortionModel) -> Option<(f64, f64)> {
+    let m = &p.matrices[idx];
+    let v = m * Vector3::new(pos.0, pos.1, 1.0);
+    let (x, y, w) = (v[0] + p.translation3d[0], v[1] + p.translation3d[1], v[2] + p.translation3d[2]);
+    if w <= 0.0 { return None; }
+
+    let pos = (x / w, y / w);
+    if p.r_limit_sq > 0.0 && (pos.0 * pos.0 + pos.1 * pos.1) > p.r_limit_sq {
+        return None;
+    }
+    let mut uv = model.distort_point(pos, &p.k, 0.0);
+    uv = (uv.0 * p.f.0 + p.c.0, uv.1 * p.f.1 + p.c.1);
+
+    if (p.flags & KernelParamsFlags::IS_GOPRO_SUPERVIEW.bits()) != 0 {
+        uv = to_superview((uv.0 / p.size.0 - 0.5, uv.1 / p.size.1 - 0.5));
+        uv = ((uv.0 + 0.5) * p.size.0, (uv.1 + 0.5) * p.size.1);
+    }
+
+    if p.stretch.0 > 0.001 { uv.0 /= p.stretch.0; }
+    if p.stretch.1 > 0.001 { uv.1 /= p.stretch.1; }
+
+    Some(uv)
+}
+
+fn sample_input_at<const I: i32, T: PixelType>(uv: (f64, f64), pixels: &[u8], params: &KernelParams, bg: &Vector4<f64>) -> Vector4<f64> {
+    let fix_range = (params.flags & KernelParamsFlags::FIX_COLOR_RANGE.bits()) != 0;
+
+    const INTER_BITS: usize = 5;
+    const INTER_TAB_SIZE: usize = 1 << INTER_BITS;
+    let shift: i32 = (I >> 2) + 1;
+    let offset: f64 = [0.0, 1.0, 3.0][I as usize >> 2];
+    let ind: usize = [0, 64, 64 + 128][I as usize >> 2];
+
+    let sx0 = ((uv.0 - offset) * INTER_TAB_SIZE as f64).round() as i64;
+    let sy0 = ((uv.1 - offset) * INTER_TAB_SIZE as f64).round() as i64;
+
+    let sx = (sx0 >> INTER_BITS) as i32;
+    let sy = (sy0 >> INTER_BITS) as i32;
+
+    let coeffs_x = &COEFFS[ind + ((sx0 as usize & (INTER_TAB_SIZE - 1)) << shift)..];
+    let coeffs_y = &COEFFS[ind + ((sy0 as usize & (INTER_TAB_SIZE - 1)) << shift)..];
+
+    let bpp = params.bytes_per_pixel as usize;
+    let mut sum = Vector4::<f64>::zeros();
+    for yp in 0..I {
+        let row = sy + yp;
+        if row >= 0 && row < params.height {
+            let mut xsum = Vector4::<f64>::zeros();
+            for xp in 0..I {
+                let col = sx + xp;
+                let pixel = if col >= 0 && col < params.width {
+                    let offs = row as usize * params.stride as usize + col as usize * bpp;
+                    let px: &T = bytemuck::from_bytes(&pixels[offs..offs + bpp]);
+                    let mut src_px: Vector4<f64> = nalgebra::convert(PixelType::to_float(*px));
+                    if fix_range {
+                        remap_colorrange(&mut src_px, params);
+                    }
+                    src_px
+                } else {
+                    *bg
+                };
+                xsum += pixel * coeffs_x[xp as usize] as f64;
+            }
+            sum += xsum * coeffs_y[yp as usize] as f64;
+        } else {
+            sum += bg * coeffs_y[yp as usize] as f64;
+        }
+    }
+    sum
+}
+
+fn undistort_pixel<const I: i32, T: PixelType>(x: usize, y: usize, pixels: &[u8], params: &KernelParams, p: &Params, model: &DistortionModel, bg: &Vector4<f64>) -> Vector4<f64> {
+    let flags = params.flags;
+    if (flags & KernelParamsFlags::FILL_WITH_BACKGROUND.bits()) != 0 {
+        return *bg;
+    }
+    let fov = params.fov as f64;
+    let factor = (1.0 - p.lens_correction_amount).max(0.001);
+    let out_size = (params.output_width as f64, params.output_height as f64);
+    let out_c = (out_size.0 / 2.0, out_size.1 / 2.0);
+    let out_f = (p.f.0 / fov / factor, p.f.1 / fov / factor);
+    let proj_f = (p.f.0 / fov, p.f.1 / fov);
+    let proj_strength = params.output_projection_strength as f64;
+
+    let mut out_pos = (x as f64 + p.translation2d.0, y as f64 + p.translation2d.1);
+
+    // Output projection (lens look)
+    if proj_strength > 0.0 {
+        if (flags & KernelParamsFlags::OUTPUT_FISHEYE.bits()) != 0 {
+            let proj_r_max = ((out_c.0 / proj_f.0).powi(2) + (out_c.1 / proj_f.1).powi(2)).sqrt();
+            let pt = ((out_pos.0 - out_c.0) / proj_f.0, (out_pos.1 - out_c.1) / proj_f.1);
+            let r = (pt.0 * pt.0 + pt.1 * pt.1).sqrt();
+            if r > 0.0 {
+                let r2 = (r / proj_r_max * proj_r_max.atan()).tan();
+                let scale = (r * (1.0 - proj_strength) + r2 * proj_strength) / r;
+                out_pos = (out_c.0 + pt.0 * scale * proj_f.0, out_c.1 + pt.1 * scale * proj_f.1);
+            }
+        } else if (flags & KernelParamsFlags::OUTPUT_SUPERVIEW.bits()) != 0 {
+            let mut pt2 = from_superview((out_pos.0 / out_size.0 - 0.5, out_pos.1 / out_size.1 - 0.5));
+            pt2 = ((pt2.0 + 0.5) * out_size.0, (pt2.1 + 0.5) * out_size.1);
+            out_pos = (
+                out_pos.0 * (1.0 - proj_strength) + pt2.0 * proj_strength,
+                out_pos.1 * (1.0 - proj_strength) + pt2.1 * proj_strength
+            );
+        }
+    }
+
+    // Source row for the rolling shutter correction
+    let matrix_count = p.matrices.len();
+    let mut sy = y;
+    if matrix_count > 1 {
+        if let Some(pt) = rotate_and_distort(out_pos, matrix_count / 2, p, model) {
+            sy = (pt.1.round() as i64).min(params.height as i64).max(0) as usize;
+        }
+    }
+
+    // Add lens distortion back
+    if p.lens_correction_amount < 1.0 {
+        if (flags & KernelParamsFlags::IS_GOPRO_SUPERVIEW.bits()) != 0 {
+            let mut pt2 = from_superview((out_pos.0 / out_size.0 - 0.5, out_pos.1 / out_size.1 - 0.5));
+            pt2 = ((pt2.0 + 0.5) * out_size.0, (pt2.1 + 0.5) * out_size.1);
+            out_pos = (
+                pt2.0 * (1.0 - p.lens_correction_amount) + out_pos.0 * p.lens_correction_amount,
+                pt2.1 * (1.0 - p.lens_correction_amount) + out_pos.1 * p.lens_correction_amount
+            );
+        }
+        out_pos = ((out_pos.0 - out_c.0) / out_f.0, (out_pos.1 - out_c.1) / out_f.1);
+        out_pos = model.undistort_point(out_pos, &p.k, p.lens_correction_amount).unwrap_or_default();
+        out_pos = (out_pos.0 * out_f.0 + out_c.0, out_pos.1 * out_f.1 + out_c.1);
+    }
+
+    let mut uv = match rotate_and_distort(out_pos, (sy * matrix_count / (params.height as usize).max(1)).min(matrix_count - 1), p, model) {
+        Some(uv) => uv,
+        None => return *bg
+    };
+
+    match params.background_mode {
+        1 => { // Edge repeat
+            uv = (uv.0.max(0.0).min(p.size.0 - 1.0), uv.1.max(0.0).min(p.size.1 - 1.0));
+        },
+        2 => { // Edge mirror
+            let (rx, ry) = (uv.0.round(), uv.1.round());
+            let (width3, height3) = (p.size.0 - 3.0, p.size.1 - 3.0);
+            if rx > width3  { uv.0 = width3  - (rx - width3); }
+            if rx < 3.0     { uv.0 = 3.0 + p.size.0 - (width3  + rx); }
+            if ry > height3 { uv.1 = height3 - (ry - height3); }
+            if ry < 3.0     { uv.1 = 3.0 + p.size.1 - (height3 + ry); }
+        },
+        3 => { // Margin with feather
+            let (widthf, heightf) = (p.size.0 - 1.0, p.size.1 - 1.0);
+            let margin = params.background_margin as f64;
+            let feather = (params.background_margin_feather as f64 * heightf).max(0.0001);
+            let mut pt2 = uv;
+            let mut alpha = 1.0;
+            if uv.0 > widthf - feather || uv.0 < feather || uv.1 > heightf - feather || uv.1 < feather {
+                alpha = ((widthf - uv.0).min(heightf - uv.1).min(uv.0).min(uv.1) / feather).clamp(0.0, 1.0);
+                pt2 = (
+                    ((pt2.0 / p.size.0 - 0.5) * (1.0 - margin) + 0.5) * p.size.0,
+                    ((pt2.1 / p.size.1 - 0.5) * (1.0 - margin) + 0.5) * p.size.1
+                );
+            }
+            let c1 = sample_input_at::<I, T>(uv, pixels, params, bg);
+            let c2 = sample_input_at::<I, T>(pt2, pixels, params, bg);
+            return c1 * alpha + c2 * (1.0 - alpha);
+        },
+        _ => { }
+    }
+    sample_input_at::<I, T>(uv, pixels, params, bg)
+}
+
+pub fn undistort_image<T: PixelType>(pixels: &[u8], out_pixels: &mut [u8], params: &KernelParams, distortion_model: &DistortionModel, matrices: &[[f32; 9]]) {
+    let k = params.k;
+    let p = Params {
+        size:  (params.width as f64, params.height as f64),
+        f:     (params.f[0] as f64, params.f[1] as f64),
+        c:     (params.c[0] as f64, params.c[1] as f64),
+        k:     k.map(|x| x as f64),
+        r_limit_sq: (params.r_limit as f64).powi(2),
+        lens_correction_amount: params.lens_correction_amount as f64,
+        stretch: (params.input_horizontal_stretch as f64, params.input_vertical_stretch as f64),
+        translation2d: (params.translation2d[0] as f64, params.translation2d[1] as f64),
+        translation3d: [params.translation3d[0] as f64, params.translation3d[1] as f64, params.translation3d[2] as f64],
+        flags: params.flags,
+        matrices: matrices.iter().take(params.matrix_count.max(1) as usize).map(|m| Matrix3::from_row_slice(&m.map(|x| x as f64))).collect(),
+    };
+    let bg = Vector4::new(params.background[0] as f64, params.background[1] as f64, params.background[2] as f64, params.background[3] as f64);
+    let bpp = params.bytes_per_pixel as usize;
+
+    for y in 0..params.output_height as usize {
+        for x in 0..params.output_width as usize {
+            let px = match params.interpolation {
+                4 => undistort_pixel::<4, T>(x, y, pixels, params, &p, distortion_model, &bg),
+                8 => undistort_pixel::<8, T>(x, y, pixels, params, &p, distortion_model, &bg),
+                _ => undistort_pixel::<2, T>(x, y, pixels, params, &p, distortion_model, &bg),
+            };
+            let offs = y * params.output_stride as usize + x * bpp;
+            let out: &mut T = bytemuck::from_bytes_mut(&mut out_pixels[offs..offs + bpp]);
+            *out = PixelType::from_float(nalgebra::convert(px));
+        }
+    }
+}
+
+// ------------------------------ Conformance ------------------------------
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConformanceResult {
+    pub case: String,
+    pub backend: String,
+    pub max_diff: f64,          // In 8-bit pixel values
+    pub mean_diff: f64,
+    pub mismatched_pixels: usize, // Pixels with any channel differing by more than `MAX_PIXEL_DIFF`
+    pub passed: bool,
+    pub error: Option<String>,    // The backend failed to render the case
+}
+
+const MAX_PIXEL_DIFF: f64 = 2.0;
+const MAX_MISMATCHED_RATIO: f64 = 0.001;
+
+struct ConformanceCase {
+    name: &'static str,
+    transform: FrameTransform,
+    distortion_model: DistortionModel,
+}
+
+const IN_SIZE: (usize, usize) = (320, 240);
+const OUT_SIZE: (usize, usize) = (256, 192);
+
+// Gradients with a checkerboard, so both the colors and the edges move with any geometric error
+fn synthetic_input() -> Vec<u8> {
+    let mut pixels = vec![0u8; IN_SIZE.0 * IN_SIZE.1 * 4];
+    for y in 0..IN_SIZE.1 {
+        for x in 0..IN_SIZE.0 {
+            let offs = (y * IN_SIZE.0 + x) * 4;
+            let checker = ((x / 16) + (y / 16)) % 2 == 0;
+            pixels[offs]     = (x * 255 / (IN_SIZE.0 - 1)) as u8;
+            pixels[offs + 1] = (y * 255 / (IN_SIZE.1 - 1)) as u8;
+            pixels[offs + 2] = if checker { 230 } else { 25 };
+            pixels[offs + 3] = 255;
+        }
+    }
+    pixels
+}
+
+fn synthetic_cases() -> Vec<ConformanceCase> {
+    let f = IN_SIZE.0 as f64 * 0.8;
+    let c = (IN_SIZE.0 as f64 / 2.0, IN_SIZE.1 as f64 / 2.0);
+
+    let base = KernelParams {
+        width:  IN_SIZE.0 as i32, height: IN_SIZE.1 as i32, stride: IN_SIZE.0 as i32 * 4,
+        output_width: OUT_SIZE.0 as i32, output_height: OUT_SIZE.1 as i32, output_stride: OUT_SIZE.0 as i32 * 4,
+        matrix_count: 1,
+        interpolation: Interpolation::Bilinear as i32,
+        bytes_per_pixel: 4,
+        pix_element_count: 4,
+        background: [0.0, 0.0, 0.0, 255.0],
+        f: [f as f32, f as f32],
+        c: [c.0 as f32, c.1 as f32],
+        k: [0.05, -0.02, 0.01, -0.005, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+        fov: 1.0,
+        lens_correction_amount: 1.0,
+        ..Default::default()
+    };
+
+    // Same as in `FrameTransform::at_timestamp`: inverse of (new camera matrix * rotation)
+    let matrix = |params: &KernelParams, pitch: f64, roll: f64, yaw: f64| -> [f32; 9] {
+        let fov = params.fov as f64;
+        let new_k = Matrix3::new(
+            f / fov, 0.0,     params.output_width  as f64 / 2.0,
+            0.0,     f / fov, params.output_height as f64 / 2.0,
+            0.0,     0.0,     1.0
+        );
+        let r = Rotation3::from_euler_angles(pitch.to_radians(), yaw.to_radians(), roll.to_radians());
+        let i_r: Matrix3<f32> = nalgebra::convert((new_k * r.matrix()).try_inverse().unwrap_or_default());
+        [
+            i_r[(0, 0)], i_r[(0, 1)], i_r[(0, 2)],
+            i_r[(1, 0)], i_r[(1, 1)], i_r[(1, 2)],
+            i_r[(2, 0)], i_r[(2, 1)], i_r[(2, 2)],
+        ]
+    };
+    let case = |name: &'static str, model: i32, params: KernelParams, matrices: Vec<[f32; 9]>| {
+        let mut kernel_params = params;
+        kernel_params.matrix_count = matrices.len() as i32;
+        ConformanceCase { name, transform: FrameTransform { matrices, kernel_params, fov: params.fov as f64 }, distortion_model: DistortionModel::from_id(model) }
+    };
+
+    let mut cases = Vec::new();
+    cases.push(case("identity", 0, base, vec![matrix(&base, 0.0, 0.0, 0.0)]));
+    cases.push(case("rotation", 0, base, vec![matrix(&base, 3.0, 7.0, -4.0)]));
+    cases.push(case("zoom", 0, KernelParams { fov: 0.7, ..base }, vec![matrix(&KernelParams { fov: 0.7, ..base }, 1.0, -2.0, 2.5)]));
+    cases.push(case("bicubic", 0, KernelParams { interpolation: Interpolation::Bicubic as i32, ..base }, vec![matrix(&base, 2.0, 5.0, 1.0)]));
+    cases.push(case("lanczos4", 0, KernelParams { interpolation: Interpolation::Lanczos4 as i32, ..base }, vec![matrix(&base, 2.0, 5.0, 1.0)]));
+    cases.push(case("opencv_standard", 1, KernelParams { k: [0.1, -0.05, 0.001, 0.002, 0.01, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0], ..base }, vec![matrix(&base, -2.0, 4.0, 3.0)]));
+    cases.push(case("poly3", 2, KernelParams { k: [0.02, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0], ..base }, vec![matrix(&base, 1.0, 3.0, -1.0)]));
+    cases.push(case("poly5", 3, KernelParams { k: [0.03, -0.01, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0], ..base }, vec![matrix(&base, 1.0, 3.0, -1.0)]));
+    cases.push(case("lens_correction_50", 0, KernelParams { lens_correction_amount: 0.5, ..base }, vec![matrix(&base, 1.5, 2.0, -3.0)]));
+    cases.push(case("edge_repeat", 0, KernelParams { background_mode: 1, fov: 1.3, ..base }, vec![matrix(&KernelParams { fov: 1.3, ..base }, 4.0, 9.0, 5.0)]));
+    cases.push(case("edge_mirror", 0, KernelParams { background_mode: 2, fov: 1.3, ..base }, vec![matrix(&KernelParams { fov: 1.3, ..base }, 4.0, 9.0, 5.0)]));
+    cases.push(case("margin_feather", 0, KernelParams { background_mode: 3, background_margin: 0.2, background_margin_feather: 0.1, fov: 1.3, ..base }, vec![matrix(&KernelParams { fov: 1.3, ..base }, 4.0, 9.0, 5.0)]));
+    cases.push(case("radial_limit", 0, KernelParams { r_limit: 0.6, fov: 1.4, ..base }, vec![matrix(&KernelParams { fov: 1.4, ..base }, 0.0, 0.0, 0.0)]));
+    cases.push(case("stretch", 0, KernelParams { input_horizontal_stretch: 1.33, ..base }, vec![matrix(&base, 0.0, 2.0, 0.0)]));
+    cases.push(case("translation", 0, KernelParams { translation2d: [12.0, -7.5], ..base }, vec![matrix(&base, 0.0, 2.0, 0.0)]));
+    cases.push(case("superview", 0, KernelParams { flags: KernelParamsFlags::IS_GOPRO_SUPERVIEW.bits(), lens_correction_amount: 0.5, ..base }, vec![matrix(&base, 1.0, 2.0, 1.0)]));
+    cases.push(case("output_fisheye", 0, KernelParams { flags: KernelParamsFlags::OUTPUT_FISHEYE.bits(), output_projection_strength: 0.7, ..base }, vec![matrix(&base, 1.0, 2.0, 1.0)]));
+    cases.push(case("output_superview", 0, KernelParams { flags: KernelParamsFlags::OUTPUT_SUPERVIEW.bits(), output_projection_strength: 0.7, ..base }, vec![matrix(&base, 1.0, 2.0, 1.0)]));
+    cases.push(case("fix_color_range", 0, KernelParams { flags: KernelParamsFlags::FIX_COLOR_RANGE.bits(), range_scale: 224.0 / 255.0, range_offset: 16.0, range_luma: [-5.0 / 255.0, 0.2126, 0.7152, 0.0722], ..base }, vec![matrix(&base, 1.0, 2.0, 1.0)]));
+    cases.push(case("rolling_shutter", 0, base, (0..IN_SIZE.1).map(|y| matrix(&base, 1.0 + y as f64 * 0.01, 2.0, -1.0 + y as f64 * 0.02)).collect()));
+    cases
+}
+
+fn compare(case: &str, backend: &str, reference: &[u8], output: &[u8]) -> ConformanceResult {
+    let mut max_diff = 0.0f64;
+    let mut sum = 0.0f64;
+    let mut mismatched_pixels = 0;
+    for (a, b) in reference.chunks(4).zip(output.chunks(4)) {
+        let diff = a.iter().zip(b).map(|(a, b)| (*a as f64 - *b as f64).abs()).fold(0.0, f64::max);
+        max_diff = max_diff.max(diff);
+        sum += diff;
+        if diff > MAX_PIXEL_DIFF { mismatched_pixels += 1; }
+    }
+    let count = (reference.len() / 4).max(1);
+    ConformanceResult {
+        case: case.to_string(),
+        backend: backend.to_string(),
+        max_diff,
+        mean_diff: sum / count as f64,
+        mismatched_pixels,
+        passed: (mismatched_pixels as f64 / count as f64) <= MAX_MISMATCHED_RATIO,
+        error: None,
+    }
+}
+
+fn backend_failed(case: &str, backend: &str, error: String) -> ConformanceResult {
+    log::error!("{} failed in conformance case {}: {}", backend, case, error);
+    ConformanceResult {
+        case: case.to_string(),
+        backend: backend.to_string(),
+        max_diff: 0.0,
+        mean_diff: 0.0,
+        mismatched_pixels: 0,
+        passed: false,
+        error: Some(error),
+    }
+}
+
+// Renders the synthetic cases with the CPU path and all available GPU backends (with fp32 kernels) and compares them with the reference.
+// A backend which has a device but fails to render a case is a failed result, backends without any device are skipped
+pub fn run_conformance() -> Vec<ConformanceResult> {
+    let mut input = synthetic_input();
+    let out_len = OUT_SIZE.0 * OUT_SIZE.1 * 4;
+    let mut results = Vec::new();
+
+    #[cfg(feature = "use-opencl")]
+    let has_opencl = std::env::var("NO_OPENCL").unwrap_or_default().is_empty() && !super::super::gpu::opencl::OclWrapper::list_devices().is_empty();
+    let has_wgpu = std::env::var("NO_WGPU").unwrap_or_default().is_empty() && !super::super::gpu::wgpu::WgpuWrapper::list_devices().is_empty();
+
+    for case in synthetic_cases() {
+        let params = &case.transform.kernel_params;
+        let mut reference = vec![0u8; out_len];
+        undistort_image::<RGBA8>(&input, &mut reference, params, &case.distortion_model, &case.transform.matrices);
+
+        let mut output = vec![0u8; out_len];
+        match params.interpolation {
+            4 => Stabilization::<RGBA8>::undistort_image_cpu::<4>(&input, &mut output, params, &case.distortion_model, &case.transform.matrices),
+            8 => Stabilization::<RGBA8>::undistort_image_cpu::<8>(&input, &mut output, params, &case.distortion_model, &case.transform.matrices),
+            _ => Stabilization::<RGBA8>::undistort_image_cpu::<2>(&input, &mut output, params, &case.distortion_model, &case.transform.matrices),
+        }
+        results.push(compare(case.name, "CPU", &reference, &output));
+
+        macro_rules! buffers {
+            ($output:ident) => {
+                BufferDescription {
+                    input_size:  (IN_SIZE.0,  IN_SIZE.1,  IN_SIZE.0 * 4),
+                    output_size: (OUT_SIZE.0, OUT_SIZE.1, OUT_SIZE.0 * 4),
+                    input_rect: None,
+                    output_rect: None,
+                    buffers: BufferSource::Cpu { input: &mut input, output: &mut $output }
+                }
+            };
+        }
+
+        #[cfg(feature = "use-opencl")]
+        if has_opencl {
+            let mut output = vec![0u8; out_len];
+            let mut buffers = buffers!(output);
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| -> ocl::Result<()> {
+                let mut cl = super::super::gpu::opencl::OclWrapper::new(params, RGBA8::ocl_names(), case.distortion_model.opencl_functions(), &buffers, KernelPrecision::Fp32)?;
+                cl.undistort_image(&mut buffers, &case.transform)
+            }));
+            match result {
+                Ok(Ok(_)) => results.push(compare(case.name, "OpenCL", &reference, &output)),
+                Ok(Err(e)) => results.push(backend_failed(case.name, "OpenCL", format!("{:?}", e))),
+                Err(_) => results.push(backend_failed(case.name, "OpenCL", "panicked".into())),
+            }
+        }
+
+        if has_wgpu {
+            let mut output = vec![0u8; out_len];
+            let mut buffers = buffers!(output);
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                super::super::gpu::wgpu::WgpuWrapper::new(params, RGBA8::wgpu_format().unwrap(), case.distortion_model.wgsl_functions(), &buffers, KernelPrecision::Fp32)
+                    .map(|mut wgpu| wgpu.undistort_image(&mut buffers, &case.transform))
+            }));
+            match result {
+                Ok(Some(true)) => results.push(compare(case.name, "wgpu", &reference, &output)),
+                Ok(Some(false)) => results.push(backend_failed(case.name, "wgpu", "processing failed".into())),
+                Ok(None) => results.push(backend_failed(case.name, "wgpu", "initialization failed".into())),
+                Err(_) => results.push(backend_failed(case.name, "wgpu", "panicked".into())),
+            }
+        }
+    }
+
+    for x in results.iter().filter(|x| x.error.is_none()) {
+        let msg = format!("{:<20} {:<7} max diff: {:>6.2}, mean diff: {:.4}, mismatched: {}", x.case, x.backend, x.max_diff, x.mean_diff, x.mismatched_pixels);
+        if x.passed { log::info!("{}", msg); } else { log::error!("{} - FAILED", msg); }
+    }
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn conformance() {
+        let results = super::run_conformance();
+        let failed: Vec<String> = results.iter().filter(|x| !x.passed).map(|x| format!("{} ({})", x.case, x.backend)).collect();
+        assert!(failed.is_empty(), "{} of {} conformance checks failed: {}", failed.len(), results.len(), failed.join(", "));
+    }
+}
@@ -24,18 +24,39 @@ impl From<i32> for BackgroundMode {
     }
 }
 
+// Projection applied to the output after stabilization, to keep the look of the original lens
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+pub enum OutputProjection {
+    #[default]
+    Rectilinear = 0,
+    Fisheye = 1,
+    Superview = 2,
+}
+impl From<i32> for OutputProjection {
+    fn from(v: i32) -> Self {
+        match v {
+            1 => Self::Fisheye,
+            2 => Self::Superview,
+            _ => Self::Rectilinear
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct StabilizationParams {
     pub size: (usize, usize), // Processing input size
     pub output_size: (usize, usize), // Processing output size
     pub video_size: (usize, usize), // Full resolution input size
     pub video_output_size: (usize, usize), // Full resoution output size
+    pub pixel_aspect_ratio: f64, // Of the input video, width / height of a single pixel
 
     pub background: Vector4<f32>,
 
     pub frame_readout_time: f64,
     pub adaptive_zoom_window: f64,
     pub adaptive_zoom_center_offset: (f64, f64),
+    pub max_zoom: f64, // Max dynamic zoom factor, 0 means unlimited
+    pub zoom_border_tolerance: f64, // Allowed border intrusion in percent of the frame size
     pub fov: f64,
     pub fovs: Vec<f64>,
     pub min_fov: f64,
@@ -51,8 +72,14 @@ pub struct StabilizationParams {
     pub trim_end: f64,
 
     pub video_rotation: f64,
+    pub camera_orientation_applied: Option<f64>, // Camera orientation from the metadata already added to `video_rotation`
+
+    pub input_crop: Option<(f64, f64, f64, f64)>, // Normalized (x, y, width, height) of the image area, excluding black bars
 
     pub lens_correction_amount: f64,
+    pub stabilization_amount: f64, // 0 - original camera motion, 1 - fully smoothed
+    pub output_projection: OutputProjection,
+    pub output_projection_strength: f64,
     pub background_mode: BackgroundMode,
     pub background_margin: f64,
     pub background_margin_feather: f64,
@@ -66,7 +93,10 @@ pub struct StabilizationParams {
 
     pub of_method: u32,
 
-    pub zooming_debug_points: std::collections::BTreeMap<i64, Vec<(f64, f64)>>
+    pub kernel_precision: crate::gpu::KernelPrecision,
+
+    pub zooming_debug_points: std::collections::BTreeMap<i64, Vec<(f64, f64)>>,
+    pub zoom_curve: crate::zooming::ZoomCurve,
 }
 impl Default for StabilizationParams {
     fn default() -> Self {
@@ -80,15 +110,24 @@ impl Default for StabilizationParams {
             frame_readout_time: 0.0,
             adaptive_zoom_window: 0.0,
             adaptive_zoom_center_offset: (0.0, 0.0),
+            max_zoom: 0.0,
+            zoom_border_tolerance: 0.0,
 
             size: (0, 0),
             output_size: (0, 0),
             video_size: (0, 0),
             video_output_size: (0, 0),
+            pixel_aspect_ratio: 1.0,
 
             video_rotation: 0.0,
+            camera_orientation_applied: None,
+
+            input_crop: None,
 
             lens_correction_amount: 1.0,
+            stabilization_amount: 1.0,
+            output_projection: OutputProjection::Rectilinear,
+            output_projection_strength: 1.0,
             background_mode: BackgroundMode::SolidColor,
             background_margin: 0.0,
             background_margin_feather: 0.0,
@@ -100,11 +139,14 @@ impl Default for StabilizationParams {
             trim_end: 1.0,
 
             zooming_debug_points: BTreeMap::new(),
+            zoom_curve: Default::default(),
 
             background: Vector4::new(0.0, 0.0, 0.0, 0.0),
 
             of_method: 2,
 
+            kernel_precision: Default::default(),
+
             fps: 0.0,
             fps_scale: None,
             video_speed: 1.0,
@@ -117,6 +159,14 @@ impl Default for StabilizationParams {
 }
 
 impl StabilizationParams {
+    // Size of the input video with square pixels, ie. the width is scaled by the pixel aspect ratio
+    pub fn get_display_size(&self) -> (usize, usize) {
+        if (self.pixel_aspect_ratio - 1.0).abs() < 0.0001 || self.pixel_aspect_ratio <= 0.0 {
+            return self.video_size;
+        }
+        let w = (self.video_size.0 as f64 * self.pixel_aspect_ratio / 2.0).round() as usize * 2;
+        (w, self.video_size.1)
+    }
     pub fn get_scaled_duration_ms(&self) -> f64 {
         match self.fps_scale {
             Some(scale) => self.duration_ms / scale,
@@ -149,8 +199,13 @@ impl StabilizationParams {
             show_optical_flow:         self.show_optical_flow,
             background:                self.background,
             adaptive_zoom_window:      self.adaptive_zoom_window,
+            max_zoom:                  self.max_zoom,
+            zoom_border_tolerance:     self.zoom_border_tolerance,
             framebuffer_inverted:      self.framebuffer_inverted,
             lens_correction_amount:    self.lens_correction_amount,
+            stabilization_amount:      self.stabilization_amount,
+            output_projection:         self.output_projection,
+            output_projection_strength: self.output_projection_strength,
             video_speed:               self.video_speed,
             video_speed_affects_smoothing: self.video_speed_affects_smoothing,
             video_speed_affects_zooming:   self.video_speed_affects_zooming,
@@ -158,6 +213,7 @@ impl StabilizationParams {
             background_margin:         self.background_margin,
             background_margin_feather: self.background_margin_feather,
             of_method:                 self.of_method,
+            kernel_precision:          self.kernel_precision,
             ..Default::default()
         };
     }
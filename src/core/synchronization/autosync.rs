@@ -4,9 +4,10 @@
 use std::sync::atomic::{ AtomicBool, AtomicUsize, Ordering::Relaxed, Ordering::SeqCst };
 use std::sync::Arc;
 use itertools::Either;
-use parking_lot::RwLock;
+use parking_lot::{ Mutex, RwLock };
 
 use crate::StabilizationManager;
+use crate::duplicate_frames::{ self, DuplicateDetector, FrameHash };
 use crate::stabilization::ComputeParams;
 use super::PoseEstimator;
 use super::SyncParams;
@@ -29,6 +30,8 @@ pub struct AutosyncProcess {
 
     sync_params: SyncParams,
 
+    duplicate_detector: Mutex<DuplicateDetector>,
+
     thread_pool: rayon::ThreadPool,
 }
 
@@ -112,6 +115,7 @@ impl AutosyncProcess {
             finished_cb: None,
             progress_cb: None,
             cancel_flag,
+            duplicate_detector: Mutex::new(DuplicateDetector::default()),
             thread_pool
         })
     }
@@ -138,6 +142,14 @@ impl AutosyncProcess {
         }
 
         if let Some(_current_range) = self.scaled_ranges_us.iter().find(|(from, to)| (*from..*to).contains(&timestamp_us)).copied() {
+            // Duplicated frame has no motion to the previous one and double motion to the next one, so it's skipped.
+            // Without features detected for it, neither of these pairs is used for the optical flow
+            let hash = FrameHash::from_plane(pixels, width as usize, height as usize, stride, 1, 0);
+            let rotation = |from_us, to_us| duplicate_frames::rotation_between(&self.compute_params.read().gyro, from_us, to_us);
+            if let Some(original_ts) = self.duplicate_detector.lock().feed(frame_no, timestamp_us, hash, rotation) {
+                log::debug!("Frame {} at {} us is a duplicate of the frame at {} us, skipping", frame_no, timestamp_us, original_ts);
+                return;
+            }
             self.total_read_frames.fetch_add(1, SeqCst);
 
             self.thread_pool.spawn(move || {
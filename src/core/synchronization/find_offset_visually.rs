@@ -73,7 +73,7 @@ pub fn find_offsets<F: Fn(f64) + Sync>(ranges: &[(i64, i64)], estimator: &PoseEs
             total_dist
         };
 
-        let find_min = |a: (f64, f64), b: (f64, f64)| -> (f64, f64) { if a.1 < b.1 { a } else { b } };
+        let find_min = crate::determinism::min_cost;
 
         if for_rs { // Estimate rolling shutter
             // First search every 1 ms
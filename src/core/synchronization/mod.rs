@@ -34,6 +34,7 @@ pub mod optimsync;
 // mod cpp_wrapper;
 mod find_offset_visually;
 mod autosync;
+pub mod offset_filter;
 pub use autosync::AutosyncProcess;
 use crate::util::MapClosest;
 use enum_dispatch::enum_dispatch;
@@ -54,8 +55,12 @@ pub struct SyncParams {
     pub every_nth_frame: usize,
     pub time_per_syncpoint: f64,
     pub of_method: usize,
-    pub offset_method: usize
+    pub offset_method: usize,
+    #[serde(default = "default_true")]
+    pub reject_outliers: bool, // On when missing, so the older projects and presets keep rejecting outliers
+    pub fit_linear: bool,
 }
+fn default_true() -> bool { true }
 
 #[enum_dispatch]
 #[derive(Clone)]
@@ -0,0 +1,70 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2022 Adrian <adrian.eddy at gmail>
+
+// Post-processing of the autosync results before they are applied.
+// Points which deviate too much from the others (or from the linear trend when `fit_linear` is set) are rejected,
+// using the median absolute deviation, so a single wrong point doesn't affect the threshold.
+
+pub type SyncOffset = (f64, f64, f64); // (timestamp, offset, cost)
+
+#[derive(Default, Clone, Debug)]
+pub struct FilteredOffsets {
+    pub accepted: Vec<SyncOffset>,
+    pub rejected: Vec<SyncOffset>,
+}
+
+// Points further than this many (scaled) MADs from the median are rejected
+const MAD_THRESHOLD: f64 = 3.0;
+// Don't reject points closer than this (in ms), otherwise nearly identical offsets would give MAD ~ 0
+const MIN_DEVIATION_MS: f64 = 1.0;
+
+fn median(v: &mut [f64]) -> f64 {
+    v.sort_by(|a, b| a.total_cmp(b));
+    let mid = v.len() / 2;
+    if v.len() % 2 == 0 { (v[mid - 1] + v[mid]) / 2.0 } else { v[mid] }
+}
+
+// Theil-Sen estimator, returns (intercept, slope) of offset over timestamp
+fn robust_line(offsets: &[SyncOffset]) -> (f64, f64) {
+    let mut slopes = Vec::with_capacity(offsets.len() * offsets.len() / 2);
+    for (i, a) in offsets.iter().enumerate() {
+        for b in &offsets[i + 1..] {
+            if (b.0 - a.0).abs() > 1e-6 {
+                slopes.push((b.1 - a.1) / (b.0 - a.0));
+            }
+        }
+    }
+    let slope = if slopes.is_empty() { 0.0 } else { median(&mut slopes) };
+    let intercept = median(&mut offsets.iter().map(|x| x.1 - slope * x.0).collect::<Vec<_>>());
+    (intercept, slope)
+}
+
+pub fn filter_offsets(offsets: &[SyncOffset], fit_linear: bool) -> FilteredOffsets {
+    // Not enough points to tell which one is wrong
+    if offsets.len() < 3 {
+        return FilteredOffsets { accepted: offsets.to_vec(), rejected: Vec::new() };
+    }
+
+    let residuals: Vec<f64> = if fit_linear && offsets.len() >= 4 {
+        let (intercept, slope) = robust_line(offsets);
+        offsets.iter().map(|x| x.1 - (intercept + slope * x.0)).collect()
+    } else {
+        let med = median(&mut offsets.iter().map(|x| x.1).collect::<Vec<_>>());
+        offsets.iter().map(|x| x.1 - med).collect()
+    };
+
+    let res_median = median(&mut residuals.clone());
+    let mad = median(&mut residuals.iter().map(|x| (x - res_median).abs()).collect::<Vec<_>>());
+    let limit = (1.4826 * mad * MAD_THRESHOLD).max(MIN_DEVIATION_MS);
+
+    let mut ret = FilteredOffsets::default();
+    for (x, r) in offsets.iter().zip(residuals.iter()) {
+        if (r - res_median).abs() > limit {
+            ::log::warn!("Rejecting sync point at {:.4}: {:.4} (deviation {:.4} ms, limit {:.4} ms)", x.0, x.1, r - res_median, limit);
+            ret.rejected.push(*x);
+        } else {
+            ret.accepted.push(*x);
+        }
+    }
+    ret
+}
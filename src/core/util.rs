@@ -1,11 +1,10 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 // Copyright © 2022 Adrian <adrian.eddy at gmail>
 
-use std::fs::File;
 use std::io::Result;
 
 pub fn get_video_metadata(filepath: &str) -> Result<(usize, usize, f64, f64)> { // -> (width, height, fps, duration_s)
-    let mut stream = File::open(&filepath)?;
+    let mut stream = crate::filesystem::open(filepath)?;
     let filesize = stream.metadata().unwrap().len() as usize;
     telemetry_parser::util::get_video_metadata(&mut stream, filesize)
 }
@@ -38,6 +37,45 @@ pub fn path_to_str(path: &std::path::Path) -> String {
     path.to_string_lossy().replace("\\", "/")
 }
 
+pub fn checksum_rows<'a>(rows: impl Iterator<Item = &'a [u8]>) -> String {
+    let mut hasher = crc32fast::Hasher::new();
+    for row in rows {
+        hasher.update(row);
+    }
+    format!("{:08x}", hasher.finalize())
+}
+
+// Quick content fingerprint of a file: size and a checksum of a few evenly spaced chunks.
+// Doesn't read the whole file, so it's fast even for huge videos, and still detects re-encoded or replaced files.
+const FINGERPRINT_CHUNKS: u64 = 16;
+const FINGERPRINT_CHUNK_SIZE: u64 = 64 * 1024;
+
+pub fn file_fingerprint(filepath: &str) -> Option<String> {
+    use std::io::{ Read, Seek, SeekFrom };
+    if filepath.is_empty() { return None; }
+
+    let mut file = crate::filesystem::open(filepath).ok()?;
+    let size = file.metadata().ok()?.len();
+
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buf = Vec::with_capacity(FINGERPRINT_CHUNK_SIZE as usize);
+    let step = size.saturating_sub(FINGERPRINT_CHUNK_SIZE) / (FINGERPRINT_CHUNKS - 1);
+    for i in 0..FINGERPRINT_CHUNKS {
+        let offset = (i * step).min(size);
+        file.seek(SeekFrom::Start(offset)).ok()?;
+        buf.clear();
+        (&mut file).take(FINGERPRINT_CHUNK_SIZE).read_to_end(&mut buf).ok()?;
+        hasher.update(&buf);
+        if step == 0 { break; } // The whole file fits in one chunk
+    }
+    Some(format!("{}:{:08x}", size, hasher.finalize()))
+}
+
+// Returns None if the file can't be read, so missing files are not reported as modified
+pub fn verify_file_fingerprint(filepath: &str, fingerprint: &str) -> Option<bool> {
+    Some(file_fingerprint(filepath)? == fingerprint)
+}
+
 
 use std::collections::BTreeMap;
 pub trait MapClosest<V> {
@@ -85,6 +123,75 @@ pub fn merge_json(a: &mut serde_json::Value, b: &serde_json::Value) {
     }
 }
 
+// Parses the top level object section by section, so a damaged or truncated file still yields all the valid sections.
+// Returns the recovered object and the keys of the sections which couldn't be parsed
+pub fn recover_json_object(data: &[u8]) -> Option<(serde_json::Value, Vec<String>)> {
+    use serde_json::Value;
+    let s = String::from_utf8_lossy(data);
+    let bytes = s.as_bytes();
+    let skip_ws = |mut i: usize| { while i < bytes.len() && (bytes[i] as char).is_ascii_whitespace() { i += 1; } i };
+    // Position of the comma or closing brace which ends the value starting at `i`
+    let skip_value = |mut i: usize| {
+        let mut depth = 0i32;
+        let mut in_string = false;
+        while i < bytes.len() {
+            match bytes[i] {
+                b'\\' if in_string => { i += 1; }
+                b'"' => { in_string = !in_string; }
+                b'{' | b'[' if !in_string => { depth += 1; }
+                b'}' | b']' if !in_string => { if depth == 0 { return i; } depth -= 1; }
+                b',' if !in_string && depth == 0 => { return i; }
+                _ => { }
+            }
+            i += 1;
+        }
+        bytes.len()
+    };
+
+    let mut i = skip_ws(0);
+    if bytes.get(i) != Some(&b'{') { return None; }
+    i += 1;
+
+    let mut ret = serde_json::Map::new();
+    let mut dropped = Vec::new();
+    loop {
+        while i < bytes.len() && (bytes[i] == b',' || (bytes[i] as char).is_ascii_whitespace()) { i += 1; }
+        if i >= bytes.len() || bytes[i] == b'}' { break; }
+
+        let mut de = serde_json::Deserializer::from_str(&s[i..]).into_iter::<String>();
+        let key = match de.next() { Some(Ok(k)) => k, _ => break };
+        i = skip_ws(i + de.byte_offset());
+        if bytes.get(i) != Some(&b':') {
+            dropped.push(key);
+            i = skip_value(i);
+            continue;
+        }
+        i += 1;
+
+        let mut de = serde_json::Deserializer::from_str(&s[i..]).into_iter::<Value>();
+        match de.next() {
+            Some(Ok(v)) => {
+                let end = skip_ws(i + de.byte_offset());
+                // Anything else than the end of the value means the section itself is damaged.
+                // At the end of a truncated file only values with closing delimiters are known to be complete
+                let complete = if end < bytes.len() { bytes[end] == b',' || bytes[end] == b'}' } else { v.is_object() || v.is_array() || v.is_string() };
+                if complete {
+                    ret.insert(key, v);
+                } else {
+                    dropped.push(key);
+                }
+                i = skip_value(i);
+            }
+            _ => {
+                dropped.push(key);
+                i = skip_value(i);
+            }
+        }
+    }
+    if ret.is_empty() { return None; }
+    Some((Value::Object(ret), dropped))
+}
+
 /*
 pub fn rename_calib_videos() {
     use telemetry_parser::Input;
@@ -0,0 +1,120 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2022 Adrian <adrian.eddy at gmail>
+
+// Same result as `FovIterative`, but the safe area of every frame is found on the GPU (see gpu/wgpu_zoom.rs).
+// Instead of shrinking the rectangle around the undistorted outline, every direction from the center is binary searched
+// for the last point which still maps inside the input frame, and the rectangle is the smallest of them.
+// Falls back to `FovIterative` when wgpu is not available, in the deterministic mode, with debug points enabled and for short clips.
+
+use super::*;
+use super::fov_iterative::{ FovIterative, fill_outside_range };
+use crate::gpu::wgpu_zoom::{ self, ZoomParams, ZoomFrame };
+use crate::stabilization::{ FrameTransform, KernelParamsFlags };
+use crate::keyframes::*;
+use std::collections::BTreeMap;
+use rayon::iter::{ ParallelIterator, IntoParallelIterator };
+
+// Directions searched per frame
+const POINTS: u32 = 128;
+// Rows with separate rotation when rolling shutter correction is enabled
+const ROWS: usize = 16;
+// Below that the upload and readback take longer than the CPU computation
+const MIN_GPU_FRAMES: usize = 64;
+// Same as in `points_around_rect`
+const MARGIN: f32 = 2.0;
+
+pub struct FovGpu {
+    input_dim: (f64, f64),
+    output_dim: (f64, f64),
+    output_inv_aspect: f64,
+    compute_params: ComputeParams,
+    fallback: FovIterative,
+}
+impl FieldOfViewAlgorithm for FovGpu {
+    fn get_debug_points(&self) -> BTreeMap<i64, Vec<(f64, f64)>> {
+        self.fallback.get_debug_points()
+    }
+
+    fn compute(&self, timestamps: &[f64], range: (f64, f64)) -> (Vec<f64>, Vec<Point2D>) {
+        if let Some(mut fov_values) = self.compute_gpu(timestamps) {
+            fill_outside_range(&mut fov_values, range);
+
+            let cp = Point2D(self.input_dim.0 / 2.0, self.input_dim.1 / 2.0);
+            return (fov_values, timestamps.iter().map(|_| cp).collect());
+        }
+        self.fallback.compute(timestamps, range)
+    }
+}
+
+impl FovGpu {
+    pub fn new(compute_params: ComputeParams) -> Self {
+        let ratio = compute_params.video_width as f64 / compute_params.video_output_width.max(1) as f64;
+        let input_dim = (compute_params.video_width as f64, compute_params.video_height as f64);
+        let output_dim = (compute_params.video_output_width as f64 * ratio, compute_params.video_output_height as f64 * ratio);
+        let output_inv_aspect = output_dim.1 / output_dim.0;
+
+        Self {
+            input_dim,
+            output_dim,
+            output_inv_aspect,
+            fallback: FovIterative::new(compute_params.clone()),
+            compute_params,
+        }
+    }
+
+    fn compute_gpu(&self, timestamps: &[f64]) -> Option<Vec<f64>> {
+        let params = &self.compute_params;
+        if timestamps.len() < MIN_GPU_FRAMES || params.zooming_debug_points || crate::determinism::is_enabled() {
+            return None;
+        }
+
+        // Rolling shutter rotation is taken at the middle of every row band
+        let row_points: Vec<(f64, f64)> = (0..ROWS).map(|i| (0.0, (i as f64 + 0.5) * params.height as f64 / ROWS as f64)).collect();
+
+        let frames: Vec<ZoomFrame> = timestamps.into_par_iter().map(|&ts| {
+            let (_, _, _, rotations) = FrameTransform::at_timestamp_for_points(params, &row_points, ts);
+
+            let center_x = params.keyframes.value_at_video_timestamp(&KeyframeType::ZoomingCenterX, ts).unwrap_or(params.adaptive_zoom_center_offset.0);
+            let center_y = params.keyframes.value_at_video_timestamp(&KeyframeType::ZoomingCenterY, ts).unwrap_or(params.adaptive_zoom_center_offset.1);
+            let lens_correction_amount = params.keyframes.value_at_video_timestamp(&KeyframeType::LensCorrectionStrength, ts).unwrap_or(params.lens_correction_amount);
+
+            ZoomFrame {
+                matrices: rotations.iter().map(|r| {
+                    let i_r: nalgebra::Matrix3<f32> = nalgebra::convert(r.pseudo_inverse(0.000001).unwrap_or_default());
+                    [
+                        i_r[(0, 0)], i_r[(0, 1)], i_r[(0, 2)],
+                        i_r[(1, 0)], i_r[(1, 1)], i_r[(1, 2)],
+                        i_r[(2, 0)], i_r[(2, 1)], i_r[(2, 2)],
+                    ]
+                }).collect(),
+                center: ((self.input_dim.0 * (0.5 + center_x)) as f32, (self.input_dim.1 * (0.5 + center_y)) as f32),
+                lens_correction_amount: lens_correction_amount as f32,
+            }
+        }).collect();
+        let rows = frames.first()?.matrices.len() as u32;
+
+        let camera_matrix = params.camera_matrix * FrameTransform::get_ratio(params);
+        let zoom_params = ZoomParams {
+            k: params.distortion_coeffs.map(|x| x as f32),
+            f: [camera_matrix[(0, 0)] as f32, camera_matrix[(1, 1)] as f32],
+            c: [camera_matrix[(0, 2)] as f32, camera_matrix[(1, 2)] as f32],
+            size: [params.width as f32, params.height as f32],
+            stretch: [params.input_horizontal_stretch as f32, params.input_vertical_stretch as f32],
+            inv_aspect: self.output_inv_aspect as f32,
+            max_scale: (self.input_dim.0 * 4.0) as f32,
+            margin: MARGIN,
+            flags: if params.is_superview { KernelParamsFlags::IS_GOPRO_SUPERVIEW.bits() } else { 0 },
+            rows,
+            points: POINTS,
+            ..Default::default()
+        };
+
+        let result = wgpu_zoom::find_safe_areas(&zoom_params, &frames, params.distortion_model.wgsl_functions())?;
+
+        // Half width of the largest rectangle which fits in all directions
+        Some(result.chunks(POINTS as usize).map(|scales| {
+            let half_width = scales.iter().copied().fold(f32::MAX, f32::min) as f64;
+            half_width * 2.0 / self.output_dim.0
+        }).collect())
+    }
+}
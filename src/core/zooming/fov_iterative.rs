@@ -33,7 +33,6 @@ impl FieldOfViewAlgorithm for FovIterative {
         if timestamps.is_empty() {
             return (Vec::new(), Vec::new());
         }
-        let l = (timestamps.len() - 1) as f64;
 
         let rect = points_around_rect(self.input_dim.0, self.input_dim.1, 31, 31);
 
@@ -45,24 +44,28 @@ impl FieldOfViewAlgorithm for FovIterative {
             .map(|(&ts, center)| self.find_fov(&rect, ts, center))
             .collect();
 
-        if range.0 > 0.0 || range.1 < 1.0 {
-            // Only within render range.
-            if let Some(max_fov) = fov_values.iter().copied().reduce(f64::max) {
-                let first_ind = (l * range.0).floor() as usize;
-                let last_ind  = (l * range.1).ceil() as usize;
-                if fov_values.len() > first_ind {
-                    fov_values[0..first_ind].iter_mut().for_each(|v| *v = max_fov);
-                }
-                if fov_values.len() > last_ind {
-                    fov_values[last_ind..].iter_mut().for_each(|v| *v = max_fov);
-                }
-            }
-        }
+        fill_outside_range(&mut fov_values, range);
 
         (fov_values, center_positions)
     }
 }
 
+// Only within render range, frames outside of it get the max fov
+pub fn fill_outside_range(fov_values: &mut [f64], range: (f64, f64)) {
+    if fov_values.is_empty() || (range.0 <= 0.0 && range.1 >= 1.0) { return; }
+    let l = (fov_values.len() - 1) as f64;
+    if let Some(max_fov) = fov_values.iter().copied().reduce(f64::max) {
+        let first_ind = (l * range.0).floor() as usize;
+        let last_ind  = (l * range.1).ceil() as usize;
+        if fov_values.len() > first_ind {
+            fov_values[0..first_ind].iter_mut().for_each(|v| *v = max_fov);
+        }
+        if fov_values.len() > last_ind {
+            fov_values[last_ind..].iter_mut().for_each(|v| *v = max_fov);
+        }
+    }
+}
+
 impl FovIterative {
     pub fn new(compute_params: ComputeParams) -> Self {
         let ratio = compute_params.video_width as f64 / compute_params.video_output_width.max(1) as f64;
@@ -4,6 +4,7 @@
 pub mod fov_default;
 pub mod fov_direct;
 pub mod fov_iterative;
+pub mod fov_gpu;
 
 pub mod zoom_disabled;
 pub mod zoom_static;
@@ -47,7 +48,7 @@ pub trait FieldOfViewAlgorithm {
     fn get_debug_points(&self) -> BTreeMap<i64, Vec<(f64, f64)>>;
 }
 
-pub fn from_compute_params(mut compute_params: ComputeParams) -> Box<dyn ZoomingAlgorithm> {
+fn prepare_compute_params(mut compute_params: ComputeParams) -> ComputeParams {
     compute_params.fov_scale = 1.0;
     compute_params.fovs.clear();
 
@@ -57,6 +58,11 @@ pub fn from_compute_params(mut compute_params: ComputeParams) -> Box<dyn Zooming
     compute_params.height = compute_params.video_height;
     compute_params.output_width = compute_params.video_width;
     compute_params.output_height = compute_params.video_height;
+    compute_params
+}
+
+pub fn from_compute_params(compute_params: ComputeParams) -> Box<dyn ZoomingAlgorithm> {
+    let compute_params = prepare_compute_params(compute_params);
 
     let mode = if compute_params.adaptive_zoom_window < -0.9 {
         Mode::Static
@@ -66,7 +72,8 @@ pub fn from_compute_params(mut compute_params: ComputeParams) -> Box<dyn Zooming
         Mode::Disabled
     };
 
-    let fov_estimator = Box::new(fov_iterative::FovIterative::new(compute_params.clone()));
+    let fov_estimator = Box::new(fov_gpu::FovGpu::new(compute_params.clone()));
+    // let fov_estimator = Box::new(fov_iterative::FovIterative::new(compute_params.clone()));
     // let fov_estimator = Box::new(fov_direct::FovDirect::new(compute_params.clone()));
     //let fov_estimator = Box::new(fov_default::FovDefault::new(compute_params.clone()));
     match mode {
@@ -76,6 +83,37 @@ pub fn from_compute_params(mut compute_params: ComputeParams) -> Box<dyn Zooming
     }
 }
 
+// Per-frame output of the zooming algorithm, kept so the timeline can plot the zoom curve and show where zoom spikes come from
+#[derive(Default, Clone, Debug)]
+pub struct ZoomCurve {
+    pub timestamps: Vec<f64>, // ms
+    pub fovs: Vec<f64>,
+    pub centers: Vec<(f64, f64)>,
+}
+
+impl ZoomCurve {
+    pub fn new(timestamps: &[f64], output: &[(f64, Point2D)]) -> Self {
+        Self {
+            timestamps: timestamps.to_vec(),
+            fovs: output.iter().map(|v| v.0).collect(),
+            centers: output.iter().map(|v| ((v.1).0, (v.1).1)).collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool { self.fovs.is_empty() }
+
+    /// Zoom factor (1 / fov) for every frame, as (timestamp_us, zoom)
+    pub fn samples(&self) -> impl Iterator<Item = (i64, f64)> + '_ {
+        self.timestamps.iter().zip(self.fovs.iter()).map(|(ts, fov)| ((ts * 1000.0).round() as i64, 1.0 / fov.max(0.0001)))
+    }
+}
+
+// FOV needed by each frame to not show any borders, without the zoom smoothing and limits applied
+pub fn required_fovs(compute_params: ComputeParams, timestamps: &[f64]) -> Vec<f64> {
+    let fov_estimator = fov_gpu::FovGpu::new(prepare_compute_params(compute_params));
+    fov_estimator.compute(timestamps, (0.0, 1.0)).0
+}
+
 pub fn get_checksum(zoom: &Box<dyn ZoomingAlgorithm>) -> u64 {
     let compute_params = zoom.compute_params();
 
@@ -27,6 +27,8 @@ impl ZoomingAlgorithm for ZoomDynamic {
 
         let (mut fov_values, center_position) = self.fov_estimator.compute(timestamps, (self.compute_params.trim_start, self.compute_params.trim_end));
 
+        self.apply_limits(&mut fov_values, timestamps, keyframes);
+
         if keyframes.is_keyframed(&KeyframeType::ZoomingSpeed) || (self.compute_params.video_speed_affects_zooming && (self.compute_params.video_speed != 1.0 || keyframes.is_keyframed(&KeyframeType::VideoSpeed))) {
             // Keyframed window
             let mut max_window = 0;
@@ -73,6 +75,8 @@ impl ZoomingAlgorithm for ZoomDynamic {
 
     fn hash(&self, hasher: &mut dyn Hasher) {
         hasher.write_u64(self.window.to_bits());
+        hasher.write_u64(self.compute_params.max_zoom.to_bits());
+        hasher.write_u64(self.compute_params.zoom_border_tolerance.to_bits());
     }
 }
 
@@ -85,6 +89,25 @@ impl ZoomDynamic {
         }
     }
 
+    // Border tolerance lets the corners show a bit of the border instead of zooming in more,
+    // max zoom caps the zoom factor (1 / fov) regardless of the borders. Done before smoothing, so the smoothed curve respects both
+    fn apply_limits(&self, fov_values: &mut [f64], timestamps: &[f64], keyframes: &KeyframeManager) {
+        let is_keyframed = keyframes.is_keyframed(&KeyframeType::MaxZoom) || keyframes.is_keyframed(&KeyframeType::ZoomBorderTolerance);
+        if !is_keyframed && self.compute_params.max_zoom <= 0.0 && self.compute_params.zoom_border_tolerance <= 0.0 {
+            return;
+        }
+        for (fov, ts) in fov_values.iter_mut().zip(timestamps) {
+            let tolerance = keyframes.value_at_video_timestamp(&KeyframeType::ZoomBorderTolerance, *ts).unwrap_or(self.compute_params.zoom_border_tolerance);
+            let max_zoom  = keyframes.value_at_video_timestamp(&KeyframeType::MaxZoom,             *ts).unwrap_or(self.compute_params.max_zoom);
+            if tolerance > 0.0 {
+                *fov *= 1.0 + tolerance / 100.0;
+            }
+            if max_zoom > 0.0 {
+                *fov = fov.max(1.0 / max_zoom);
+            }
+        }
+    }
+
     fn get_frames_per_window(&self, window: f64) -> usize {
         let mut frames = (window * self.compute_params.scaled_fps).floor() as usize;
         if frames % 2 == 0 {
@@ -16,6 +16,14 @@ pub fn requires_install(path: &str) -> bool {
     false
 }
 
+/// Which of the optional SDKs are installed
+pub fn installed() -> serde_json::Value {
+    serde_json::json!({
+        "braw":       braw::BrawSdk::is_installed(),
+        "ffmpeg_gpl": FfmpegGpl::is_installed(),
+    })
+}
+
 pub fn install<F: Fn((f64, &'static str, String)) + Send + Sync + Clone + 'static>(path: &str, cb: F) {
     let (url, sdk_name) = if path.to_lowercase().ends_with(".braw") {
         (braw::BrawSdk::get_download_url(), "Blackmagic RAW SDK")
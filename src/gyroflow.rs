@@ -59,16 +59,32 @@ fn entry() {
         qApp->setOrganizationDomain("gyroflow.xyz");
         qApp->setApplicationName("Gyroflow");
     });
+    util::upload_crash_dumps();
+
+    // Caches and decoder queues are sized when they are created, so the profile can only be selected at startup
+    // The default depends on the build, so it's only overridden when the option was changed
+    let low_memory = util::get_setting("lowMemory");
+    if !low_memory.is_empty() {
+        core::low_memory::set_enabled(low_memory == "true");
+    }
+
+    // Before the CLI, so headless renders also use the kernel cache in the data location
+    core::gpu::kernel_cache::set_cache_dir(std::path::Path::new(&util::get_data_location()).join("kernel_cache"));
 
     let mut open_file = String::new();
-    if cli::run(&mut open_file) {
-        return;
+    if let Some(exit_code) = cli::run(&mut open_file) {
+        std::process::exit(exit_code);
     }
 
     crate::resources::rsrc();
     #[cfg(not(compiled_qml))]
     crate::resources_qml::rsrc_qml();
 
+    // HDR swapchain can only be selected before the window is created
+    if util::get_setting("previewHdr").parse::<i32>().unwrap_or_default() > 0 {
+        qt_gpu::qrhi_undistort::request_hdr_swapchain();
+    }
+
     qml_video_rs::register_qml_types();
     qml_register_type::<TimelineGyroChart>(cstr::cstr!("Gyroflow"), 1, 0, cstr::cstr!("TimelineGyroChart"));
     qml_register_type::<TimelineKeyframesView>(cstr::cstr!("Gyroflow"), 1, 0, cstr::cstr!("TimelineKeyframesView"));
@@ -102,6 +118,14 @@ fn entry() {
 
     let _ = external_sdk::cleanup();
 
+    #[cfg(target_os = "android")]
+    core::filesystem::set_content_uri_opener(util::open_android_content_uri);
+    #[cfg(any(target_os = "ios", target_os = "macos"))]
+    core::filesystem::set_security_scoped_access_handler(util::security_scoped_access);
+
+    // Messages generated in core are translated in the "Core" context
+    core::i18n::set_translator(|_key, text| Some(util::translate("Core", text)));
+
     let ctl = RefCell::new(controller::Controller::new());
     let ctlpinned = unsafe { QObjectPinned::new(&ctl) };
 
@@ -118,6 +142,7 @@ fn entry() {
     engine.set_object_property("main_controller".into(), ctlpinned);
     engine.set_object_property("ui_tools".into(), ui_tools_pinned);
     engine.set_object_property("render_queue".into(), rqpinned);
+    ctl.borrow().init_gpu_fallback();
     {
         let mut ui = ui_tools.borrow_mut();
         ui.engine_ptr = Some(&mut engine as *mut _);
@@ -1,5 +1,7 @@
 #!/bin/bash
-QSB='../../../ext/6.3.2/msvc2019_64/bin/qsb.exe --glsl "100 es,200 es,300 es,330,400,120,100" --hlsl 50 --msl 12'
+# Set QSB to the qsb tool of the Qt installation if it's not in PATH
+# The build compiles these itself when qsb is available and keeps the .hash files up to date, prefer running `cargo build`
+QSB="${QSB:-qsb} --glsl \"100 es,200 es,300 es,330,400,120,100\" --hlsl 50 --msl 12"
 
 DISTORTION_MODELS=( "opencv_fisheye" "opencv_standard" "poly3" "poly5" "ptlens" )
 
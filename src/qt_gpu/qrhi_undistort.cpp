@@ -4,6 +4,7 @@
 #include <QQuickWindow>
 #include <QFile>
 #include <private/qquickitem_p.h>
+#include <private/qquickwindow_p.h>
 #include <private/qrhi_p.h>
 #include <private/qsgrenderer_p.h>
 #include <private/qsgdefaultrendercontext_p.h>
@@ -36,6 +37,9 @@ static float quadVertexData[16] = { // Y up, CCW
 };
 static quint16 quadIndexData[6] = { 0, 1, 2, 0, 2, 3 };
 
+// 0 - SDR, 1 - PQ, 2 - HLG
+static int previewHdrMode = 0;
+
 // ubufAlignment
 // static inline uint aligned(uint v, uint byteAlign) { return (v + byteAlign - 1) & ~(byteAlign - 1); }
 
@@ -52,7 +56,9 @@ public:
 
         m_initialUpdates = rhi->nextResourceUpdateBatch();
 
-        m_texIn.reset(rhi->newTexture(QRhiTexture::RGBA8, textureSize, 1, QRhiTexture::UsedAsTransferSource));
+        // Use the same format as the player texture, so 10-bit and float frames are not truncated to 8-bit
+        auto inFormat = item->rhiTexture()? item->rhiTexture()->format() : QRhiTexture::RGBA8;
+        m_texIn.reset(rhi->newTexture(inFormat, textureSize, 1, QRhiTexture::UsedAsTransferSource));
         if (!m_texIn->create()) { qDebug() << "failed to create m_texIn"; return false; }
 
         m_workaroundTexture.reset(rhi->newTexture(QRhiTexture::RGBA8, QSize(16, 16), 1, QRhiTexture::UsedAsTransferSource));
@@ -75,7 +81,7 @@ public:
         if (!m_indexBuffer->create()) { qDebug() << "failed to create m_indexBuffer"; return false; }
         m_initialUpdates->uploadStaticBuffer(m_indexBuffer.get(), quadIndexData);
 
-        m_drawingUniform.reset(rhi->newBuffer(QRhiBuffer::Dynamic, QRhiBuffer::UniformBuffer, 64 + 4));
+        m_drawingUniform.reset(rhi->newBuffer(QRhiBuffer::Dynamic, QRhiBuffer::UniformBuffer, 64 + 4 + 4));
         if (!m_drawingUniform->create()) { qDebug() << "failed to create m_drawingUniform"; return false; }
         qint32 flip = rhi->isYUpInFramebuffer();
         m_initialUpdates->updateDynamicBuffer(m_drawingUniform.get(), 64, 4, &flip);
@@ -148,6 +154,9 @@ public:
         mvp.scale(2.0f);
         u->updateDynamicBuffer(m_drawingUniform.get(), 0, 64, mvp.constData());
 
+        qint32 transfer = outputTransfer(item->qmlWindow());
+        u->updateDynamicBuffer(m_drawingUniform.get(), 68, 4, &transfer);
+
         cb->resourceUpdate(u);
         u = rhi->nextResourceUpdateBatch();
 
@@ -163,6 +172,18 @@ public:
         return true;
     }
 
+    // The encoding has to match the swapchain format, which is selected when the window is created (QSG_RHI_HDR)
+    static qint32 outputTransfer(QQuickWindow *window) {
+        if (previewHdrMode == 0) return 0;
+        auto swapchain = QQuickWindowPrivate::get(window)->swapchain;
+        if (!swapchain) return 0;
+        switch (swapchain->format()) {
+            case QRhiSwapChain::HDR10:                 return previewHdrMode;
+            case QRhiSwapChain::HDRExtendedSrgbLinear: return 3;
+            default:                                   return 0;
+        }
+    }
+
     std::vector<unsigned char> kernel_params;
     std::vector<float> matrices;
 
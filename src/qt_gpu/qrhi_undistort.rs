@@ -78,6 +78,20 @@ pub fn init_player(mdkplayer: &mut MDKPlayerWrapper, stab: Arc<StabilizationMana
     });
 }
 
+// 0 - SDR, 1 - PQ, 2 - HLG. HDR output is used only when the window has an HDR10 or scRGB swapchain
+pub fn set_hdr_output(mode: i32) {
+    cpp!(unsafe [mode as "int"] {
+        previewHdrMode = mode;
+    });
+}
+
+// Requests an HDR swapchain for the windows, has to be called before the first window is created
+pub fn request_hdr_swapchain() {
+    if std::env::var("QSG_RHI_HDR").is_err() {
+        std::env::set_var("QSG_RHI_HDR", "hdr10");
+    }
+}
+
 pub fn deinit_player(mdkplayer: &mut MDKPlayerWrapper) {
     cpp!(unsafe [mdkplayer as "MDKPlayerWrapper *"] {
         if (!mdkplayer || !mdkplayer->mdkplayer) return;
@@ -6,6 +6,7 @@ layout(location = 0) out vec2 v_texcoord;
 layout(std140, binding = 0) uniform buf {
     mat4 mvp;
     int flip;
+    int output_transfer;
 } ubuf;
 out gl_PerVertex { vec4 gl_Position; };
 
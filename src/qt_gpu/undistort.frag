@@ -9,6 +9,12 @@
 layout(location = 0) in vec2 v_texcoord;
 layout(location = 0) out vec4 fragColor;
 
+layout(std140, binding = 0) uniform buf {
+    mat4 mvp;
+    int flip;
+    int output_transfer; // 0 - SDR, 1 - PQ, 2 - HLG, 3 - scRGB linear
+} ubuf;
+
 layout(binding = 1) uniform sampler2D texIn;
 
 layout(std140, binding = 2) uniform KernelParams {
@@ -35,11 +41,12 @@ layout(std140, binding = 2) uniform KernelParams {
     float input_horizontal_stretch; // 4
     float background_margin;        // 8
     float background_margin_feather;// 12
-    float reserved1;                // 16
-    float reserved2;                // 4
-    float reserved3;                // 8
+    float output_projection_strength; // 16
+    float range_scale;              // 4
+    float range_offset;             // 8
     vec2 translation2d;             // 16
     vec4 translation3d;             // 16
+    vec4 range_luma;                // 16
 } params;
 
 layout(binding = 3) uniform sampler2D texParams;
@@ -75,7 +82,39 @@ vec2 rotate_and_distort(vec2 pos, float idx, vec2 f, vec2 c, vec4 k1, vec4 k2, v
     return vec2(-99999.0, -99999.0);
 }
 
-void main() {
+// SDR reference white is placed at 203 nits (ITU-R BT.2408)
+const float SDR_WHITE_NITS = 203.0;
+
+vec3 encode_hdr(vec3 rgb, int transfer) {
+    // BT.709 (gamma 2.4) to linear light
+    vec3 lin = pow(max(rgb, vec3(0.0)), vec3(2.4));
+    if (transfer == 3) { // scRGB: linear BT.709 primaries, 1.0 = 80 nits
+        return lin * (SDR_WHITE_NITS / 80.0);
+    }
+    // BT.709 to BT.2020 primaries
+    lin = mat3(0.6274, 0.0691, 0.0164,
+               0.3293, 0.9195, 0.0880,
+               0.0433, 0.0114, 0.8956) * lin;
+    if (transfer == 1) { // PQ (SMPTE ST 2084)
+        const float m1 = 0.1593017578125;
+        const float m2 = 78.84375;
+        const float c1 = 0.8359375;
+        const float c2 = 18.8515625;
+        const float c3 = 18.6875;
+        vec3 y = pow(lin * (SDR_WHITE_NITS / 10000.0), vec3(m1));
+        return pow((c1 + c2 * y) / (1.0 + c3 * y), vec3(m2));
+    }
+    if (transfer == 2) { // HLG (ARIB STD-B67), with SDR white at 75% signal level
+        const float a = 0.17883277;
+        const float b = 0.28466892;
+        const float c = 0.55991073;
+        vec3 e = lin * 0.2649;
+        return mix(sqrt(3.0 * e), a * log(max(12.0 * e - b, vec3(0.0001))) + c, step(vec3(1.0 / 12.0), e));
+    }
+    return rgb;
+}
+
+void undistort() {
     vec2 texPos = v_texcoord.xy * vec2(params.output_width, params.output_height) + params.translation2d;
 
     if (bool(params.flags & 4)) { // Fill with background
@@ -83,6 +122,27 @@ void main() {
         return;
     }
 
+    ///////////////////////////////////////////////////////////////////
+    // Output projection (lens look)
+    if (params.output_projection_strength > 0.0) {
+        vec2 out_c = vec2(params.output_width / 2.0, params.output_height / 2.0);
+        if (bool(params.flags & 8)) { // Fisheye, corners stay in place
+            vec2 out_f = params.f / params.fov;
+            vec2 pt = (texPos - out_c) / out_f;
+            float r = length(pt);
+            float r_max = length(out_c / out_f);
+            if (r > 0.0) {
+                float r2 = tan(r / r_max * atan(r_max));
+                texPos = out_c + pt * (mix(r, r2, params.output_projection_strength) / r) * out_f;
+            }
+        } else if (bool(params.flags & 16)) { // Superview
+            vec2 size = out_c * 2.0;
+            vec2 pt2 = (from_superview((texPos / size) - 0.5) + 0.5) * size;
+            texPos = mix(texPos, pt2, params.output_projection_strength);
+        }
+    }
+    ///////////////////////////////////////////////////////////////////
+
     ///////////////////////////////////////////////////////////////////
     // Calculate source `y` for rolling shutter
     float sy = texPos.y;
@@ -115,7 +175,7 @@ void main() {
     }
     ///////////////////////////////////////////////////////////////////
 
-    float idx = min(sy + 2.0, params.matrix_count - 1.0);
+    float idx = min(floor((sy + 2.0) * float(params.matrix_count) / float(max(params.height, 1))), params.matrix_count - 1.0); // Matrices can cover more than one row
 
     vec2 uv = rotate_and_distort(texPos, idx, params.f, params.c, params.k1, params.k2, params.k3, params.r_limit);
     if (uv.x > -99998.0) {
@@ -159,3 +219,10 @@ void main() {
     }
     fragColor = params.background / 255.0;
 }
+
+void main() {
+    undistort();
+    if (ubuf.output_transfer > 0) {
+        fragColor.rgb = encode_hdr(fragColor.rgb, ubuf.output_transfer);
+    }
+}
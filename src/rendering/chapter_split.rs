@@ -0,0 +1,126 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2022 Adrian <adrian.eddy at gmail>
+
+// Export split into numbered segments: `<name>_001.<ext>`, `<name>_002.<ext>`, ...
+// A new segment starts at every multiple of the configured duration and/or where the recording has a gap in the frame timestamps,
+// eg. cameras which drop a few frames between the chapter files, when the chapters were merged before stabilization.
+// The segments are written by the ffmpeg segment muxer, and the encoder is forced to start each of them with a keyframe.
+
+use std::ffi::{ CStr, CString };
+use ffmpeg_next::{ ffi, format, media, Dictionary, Rational };
+
+// Distance between two frames, in frame intervals, which is considered a gap in the recording
+const GAP_FRAMES: f64 = 1.5;
+// Split points closer than that to the previous one or to the end are skipped
+const MIN_SEGMENT_S: f64 = 1.0;
+
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+pub struct ChapterSplit {
+    pub duration_s: f64, // 0 - don't split by duration
+    pub at_gaps: bool,
+}
+
+impl ChapterSplit {
+    pub fn is_enabled(&self) -> bool {
+        self.duration_s > 0.0 || self.at_gaps
+    }
+
+    /// `<output name>_<number>.<ext>`, `number` is either the index or the `%03d` pattern for the muxer
+    pub fn segment_path(output_path: &str, number: &str) -> String {
+        let path = std::path::Path::new(output_path);
+        match path.extension() {
+            Some(ext) => {
+                let ext = ext.to_string_lossy();
+                format!("{}_{}.{}", &output_path[..output_path.len() - ext.len() - 1], number, ext)
+            },
+            None => format!("{}_{}", output_path, number)
+        }
+    }
+
+    /// Muxer which would be used for the file, the segment muxer needs it explicitly
+    pub fn guess_format(output_path: &str) -> Option<String> {
+        let path = CString::new(output_path).ok()?;
+        unsafe {
+            let fmt = ffi::av_guess_format(std::ptr::null(), path.as_ptr(), std::ptr::null());
+            if fmt.is_null() { return None; }
+            Some(CStr::from_ptr((*fmt).name).to_string_lossy().into_owned())
+        }
+    }
+
+    /// Timestamps (ms) of the first frame after every gap in the video stream. The input is rewound to the beginning afterwards
+    pub fn find_gaps(ictx: &mut format::context::Input) -> Result<Vec<f64>, ffmpeg_next::Error> {
+        let (index, time_base, frame_rate) = match ictx.streams().best(media::Type::Video) {
+            Some(stream) => (stream.index(), stream.time_base(), stream.avg_frame_rate()),
+            None => return Ok(Vec::new())
+        };
+        if frame_rate.numerator() <= 0 || frame_rate.denominator() <= 0 {
+            return Ok(Vec::new());
+        }
+
+        // Only the packets are read, without decoding
+        let mut timestamps: Vec<i64> = ictx.packets()
+            .filter(|(stream, _)| stream.index() == index)
+            .filter_map(|(_, packet)| packet.pts().or_else(|| packet.dts()))
+            .collect();
+        ictx.seek(0, ..0)?;
+
+        // Packets are in the decoding order
+        timestamps.sort_unstable();
+        timestamps.dedup();
+
+        let to_ms = |ts: i64| ts as f64 * f64::from(time_base) * 1000.0;
+        let frame_ms = 1000.0 / f64::from(frame_rate);
+        Ok(timestamps.windows(2)
+            .filter(|x| to_ms(x[1]) - to_ms(x[0]) > frame_ms * GAP_FRAMES)
+            .map(|x| to_ms(x[1]))
+            .collect())
+    }
+
+    /// Split points in the output timeline (s), for the output starting at `range_ms.0` of the source and ending at `range_ms.1`
+    pub fn split_times(&self, gaps_ms: &[f64], range_ms: (f64, f64)) -> Vec<f64> {
+        let duration_s = (range_ms.1 - range_ms.0) / 1000.0;
+
+        let mut times: Vec<f64> = gaps_ms.iter().filter(|x| **x > range_ms.0 && **x < range_ms.1).map(|x| (x - range_ms.0) / 1000.0).collect();
+        if self.duration_s > 0.0 {
+            let interval = self.duration_s.max(MIN_SEGMENT_S);
+            times.extend((1..).map(|i| i as f64 * interval).take_while(|x| *x < duration_s));
+        }
+        times.sort_by(|a, b| a.total_cmp(b));
+
+        let mut result = Vec::<f64>::new();
+        for t in times {
+            if t - result.last().copied().unwrap_or(0.0) >= MIN_SEGMENT_S && duration_s - t >= MIN_SEGMENT_S {
+                result.push(t);
+            }
+        }
+        result
+    }
+
+    /// Output context writing `<name>_%03d.<ext>` files
+    pub fn open_output(output_path: &str) -> Result<format::context::Output, ffmpeg_next::Error> {
+        let pattern = Self::segment_path(output_path, "%03d");
+        let mut octx = format::output_as(&pattern, "segment")?;
+        // `output_as` opens the pattern as a file, but the segment muxer creates the files itself
+        unsafe { ffi::avio_closep(&mut (*octx.as_mut_ptr()).pb); }
+        let _ = std::fs::remove_file(&pattern);
+        Ok(octx)
+    }
+
+    /// Options of the segment muxer, `format_options` are passed to the muxer of every segment
+    pub fn muxer_options(format_name: &str, split_times: &[f64], frame_rate: Option<Rational>, format_options: &Dictionary) -> Dictionary<'static> {
+        let mut options = Dictionary::new();
+        options.set("segment_format", format_name);
+        options.set("segment_times", &split_times.iter().map(|x| format!("{:.6}", x)).collect::<Vec<_>>().join(","));
+        options.set("segment_start_number", "1");
+        options.set("reset_timestamps", "1");
+        if let Some(frame_rate) = frame_rate.filter(|x| x.numerator() > 0) {
+            // Keyframe timestamps are rounded to the stream time base
+            options.set("segment_time_delta", &format!("{:.6}", 0.5 / f64::from(frame_rate)));
+        }
+        let format_options = format_options.iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<_>>();
+        if !format_options.is_empty() {
+            options.set("segment_format_options", &format_options.join(":"));
+        }
+        options
+    }
+}
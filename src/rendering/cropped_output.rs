@@ -0,0 +1,324 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2022 Adrian <adrian.eddy at gmail>
+
+// Second output of a render, with the frames cropped to the area without borders ("max crop") and scaled back to the output size.
+// The main output keeps the full stabilized frame (eg. without zoom and with transparent background), and its warped frames are reused
+// for the cropped file, so both are produced with a single decode and stabilization pass.
+// The crop size is static, the largest one which hides the borders in every frame of the render range. Its position follows the zoom center.
+// The video is encoded to a temporary file, and the audio of the main output is muxed with it when the render is finished.
+// Unfinished files are removed when the output is dropped.
+
+use ffmpeg_next::{ codec, encoder, filter, format, frame, media, Dictionary, Packet, Rational, Error, rescale::Rescale };
+use super::ffmpeg_processor::FFmpegError;
+use crate::core::{ StabilizationManager, keyframes::{ KeyframeManager, KeyframeType }, stabilization::{ ComputeParams, PixelType }, zooming };
+
+pub struct CropArea {
+    pub ratio: f64,         // of the output frame
+    center: (f64, f64),     // Zoom center offset, when it's not keyframed
+    keyframes: KeyframeManager,
+}
+impl CropArea {
+    // Center of the crop at the source timestamp, 0.5 is the middle of the frame
+    fn center_at(&self, timestamp_us: i64) -> (f64, f64) {
+        let ts = timestamp_us as f64 / 1000.0;
+        let x = self.keyframes.value_at_video_timestamp(&KeyframeType::ZoomingCenterX, ts).unwrap_or(self.center.0);
+        let y = self.keyframes.value_at_video_timestamp(&KeyframeType::ZoomingCenterY, ts).unwrap_or(self.center.1);
+        (0.5 + x, 0.5 + y)
+    }
+}
+
+pub struct CroppedOutput {
+    pub path: String,
+    video_path: String, // Encoded video without audio
+    octx: Option<format::context::Output>,
+    codec: codec::codec::Codec,
+    options: Dictionary<'static>,
+    pixel_format: Option<format::Pixel>,
+    encoder: Option<encoder::video::Encoder>,
+    graph: Option<filter::Graph>,
+    size: (u32, u32),
+    crop: CropArea,
+    crop_size: (u32, u32),
+    frame_rate: Rational,
+    bitrate: Option<f64>,
+    keyframe_distance_s: f64,
+    finished: bool,
+}
+
+impl CroppedOutput {
+    /// `<output name>_cropped.<ext>`
+    pub fn path_for(output_path: &str) -> String {
+        let path = std::path::Path::new(output_path);
+        match path.extension() {
+            Some(ext) => {
+                let ext = ext.to_string_lossy();
+                format!("{}_cropped.{}", &output_path[..output_path.len() - ext.len() - 1], ext)
+            },
+            None => format!("{}_cropped", output_path)
+        }
+    }
+
+    /// Crop of the main output frames which hides the borders in the whole render range
+    pub fn crop_for<T: PixelType>(stab: &StabilizationManager<T>) -> CropArea {
+        let compute_params = ComputeParams::from_manager(stab, false);
+        let (frame_count, fps, trim) = {
+            let params = stab.params.read();
+            (params.frame_count, params.get_scaled_fps(), (params.trim_start, params.trim_end))
+        };
+        let first = ((trim.0 * frame_count as f64).floor() as usize).min(frame_count);
+        let last  = ((trim.1 * frame_count as f64).ceil()  as usize).min(frame_count);
+        let timestamps: Vec<f64> = (first..last).map(|i| crate::core::timestamp_at_frame(i as i32, fps)).collect();
+
+        // FOVs of the main output, with the adaptive zoom if it's enabled
+        let current: Vec<f64> = (first..last).map(|i| compute_params.fovs.get(i).copied().unwrap_or(1.0) * compute_params.fov_scale).collect();
+        let required = zooming::required_fovs(compute_params.clone(), &timestamps);
+
+        let ratio = required.iter().zip(&current).filter(|(_, c)| **c > 0.0).map(|(r, c)| r / c).reduce(f64::min).unwrap_or(1.0).clamp(0.05, 1.0);
+        CropArea { ratio, center: compute_params.adaptive_zoom_center_offset, keyframes: compute_params.keyframes }
+    }
+
+    pub fn new(path: &str, encoder_name: &str, params: super::ffmpeg_video::EncoderParams<'static>, size: (u32, u32), crop: CropArea, frame_rate: Rational, bitrate: Option<f64>) -> Result<Self, FFmpegError> {
+        let codec = encoder::find_by_name(encoder_name).ok_or(Error::EncoderNotFound)?;
+        log::debug!("Cropped output: {}, encoder: {}, crop: {:.3}", path, encoder_name, crop.ratio);
+
+        // `<name>_cropped.video.<ext>`, the extension selects the same container
+        let video_path = match std::path::Path::new(path).extension() {
+            Some(ext) => {
+                let ext = ext.to_string_lossy();
+                format!("{}.video.{}", &path[..path.len() - ext.len() - 1], ext)
+            },
+            None => format!("{}.video", path)
+        };
+
+        Ok(Self {
+            path: path.to_string(),
+            video_path,
+            octx: None,
+            codec,
+            options: params.options,
+            pixel_format: params.pixel_format,
+            encoder: None,
+            graph: None,
+            size,
+            crop,
+            crop_size: (0, 0),
+            frame_rate,
+            bitrate,
+            keyframe_distance_s: params.keyframe_distance_s,
+            finished: false,
+        })
+    }
+
+    fn init(&mut self, frame: &frame::Video) -> Result<(), FFmpegError> {
+        let supported = unsafe { super::ffmpeg_hw::pix_formats_to_vec((*self.codec.as_ptr()).pix_fmts) };
+        let pixel_format = self.pixel_format
+            .or_else(|| Some(frame.format()).filter(|x| supported.contains(x)))
+            .or_else(|| supported.first().copied())
+            .unwrap_or(format::Pixel::YUV420P);
+
+        // Size of the crop in the main output frame, with even dimensions for the chroma subsampling. The frames are cropped in `write`
+        let ratio = self.crop.ratio;
+        let (fw, fh) = (frame.width() as f64, frame.height() as f64);
+        self.crop_size = (((fw * ratio / 2.0).floor() * 2.0).max(2.0) as u32, ((fh * ratio / 2.0).floor() * 2.0).max(2.0) as u32);
+
+        let mut graph = filter::Graph::new();
+        let args = format!("video_size={}x{}:pix_fmt={}:time_base=1/1000000:pixel_aspect=1/1",
+            self.crop_size.0, self.crop_size.1, ffmpeg_next::ffi::AVPixelFormat::from(frame.format()) as i32);
+        graph.add(&filter::find("buffer").ok_or(Error::FilterNotFound)?, "in", &args)?;
+        graph.add(&filter::find("buffersink").ok_or(Error::FilterNotFound)?, "out", "")?;
+        let spec = format!("scale={}:{}:flags=lanczos,format=pix_fmts={}",
+            self.size.0, self.size.1, pixel_format.descriptor().map(|x| x.name()).unwrap_or("yuv420p"));
+        log::debug!("Cropped output filter: {}", spec);
+        graph.output("in", 0)?.input("out", 0)?.parse(&spec).map_err(|e| FFmpegError::FilterError(format!("{}: {}", spec, e)))?;
+        graph.validate().map_err(|e| FFmpegError::FilterError(format!("{}: {}", spec, e)))?;
+        self.graph = Some(graph);
+
+        let mut octx = format::output(&self.video_path)?;
+        {
+            let mut ost = octx.add_stream(self.codec)?;
+            ost.set_rate(self.frame_rate);
+            ost.set_time_base(self.frame_rate.invert());
+            ost.set_avg_frame_rate(self.frame_rate);
+        }
+
+        let mut enc = codec::context::Context::new().encoder().video()?;
+        enc.set_width(self.size.0);
+        enc.set_height(self.size.1);
+        enc.set_aspect_ratio(Rational::new(1, 1));
+        enc.set_format(pixel_format);
+        enc.set_frame_rate(Some(self.frame_rate));
+        enc.set_time_base(self.frame_rate.invert());
+        if let Some(bitrate) = self.bitrate {
+            let bitrate = (bitrate * 1024.0 * 1024.0) as usize;
+            enc.set_bit_rate(bitrate);
+            enc.set_max_bit_rate(bitrate);
+        }
+        enc.set_color_range(frame.color_range());
+        enc.set_colorspace(frame.color_space());
+        let fps: f64 = self.frame_rate.into();
+        enc.set_gop(((fps * self.keyframe_distance_s) as u32).max(1));
+        if octx.format().flags().contains(format::Flags::GLOBAL_HEADER) {
+            enc.set_flags(codec::Flags::GLOBAL_HEADER);
+        }
+        let enc = enc.open_as_with(self.codec, self.options.to_owned())?;
+        octx.stream_mut(0).ok_or(Error::StreamNotFound)?.set_parameters(&enc);
+        self.encoder = Some(enc);
+
+        octx.write_header()?;
+        self.octx = Some(octx);
+        Ok(())
+    }
+
+    /// Adds the stabilized frame of the main output, `repeat_times` and `repeat_interval` are the same as in `RateControl`.
+    /// `source_timestamp_us` is the timestamp of the frame in the source video, for the keyframed zoom center
+    pub fn write(&mut self, frame: &mut frame::Video, source_timestamp_us: i64, timestamp_us: i64, repeat_times: i64, repeat_interval: i64) -> Result<(), FFmpegError> {
+        if repeat_times <= 0 { return Ok(()); }
+        if self.encoder.is_none() {
+            self.init(frame)?;
+        }
+
+        // Crop rectangle kept inside the frame, at even coordinates for the chroma subsampling
+        let (cx, cy) = self.crop.center_at(source_timestamp_us);
+        let (fw, fh) = (frame.width() as f64, frame.height() as f64);
+        let (w, h) = (self.crop_size.0 as f64, self.crop_size.1 as f64);
+        let x = ((fw * cx - w / 2.0).clamp(0.0, fw - w) / 2.0).floor() as usize * 2;
+        let y = ((fh * cy - h / 2.0).clamp(0.0, fh - h) / 2.0).floor() as usize * 2;
+
+        let mut source = frame::Video::empty();
+        unsafe {
+            use ffmpeg_next::ffi::*;
+            if av_frame_ref(source.as_mut_ptr(), frame.as_ptr()) < 0 { return Err(Error::Bug.into()); }
+            let ptr = source.as_mut_ptr();
+            (*ptr).crop_left   = x;
+            (*ptr).crop_top    = y;
+            (*ptr).crop_right  = frame.width() as usize - x - self.crop_size.0 as usize;
+            (*ptr).crop_bottom = frame.height() as usize - y - self.crop_size.1 as usize;
+            // 1 = AV_FRAME_CROP_UNALIGNED, the exact crop is needed and not the one with aligned data pointers
+            let ret = av_frame_apply_cropping(ptr, 1);
+            if ret < 0 { return Err(Error::from(ret).into()); }
+        }
+
+        let mut cropped = frame::Video::empty();
+        {
+            let graph = self.graph.as_mut().ok_or(Error::FilterNotFound)?;
+            source.set_pts(Some(timestamp_us));
+            graph.get("in").ok_or(Error::FilterNotFound)?.source().add(&source)?;
+            if graph.get("out").ok_or(Error::FilterNotFound)?.sink().frame(&mut cropped).is_err() {
+                return Ok(());
+            }
+        }
+
+        let time_base = self.frame_rate.invert();
+        let mut ts = timestamp_us;
+        for _ in 0..repeat_times {
+            cropped.set_pts(Some(ts.rescale((1, 1000000), time_base)));
+            self.encoder.as_mut().ok_or(FFmpegError::EncoderNotFound)?.send_frame(&cropped)?;
+            self.write_packets()?;
+            ts += repeat_interval;
+        }
+        Ok(())
+    }
+
+    fn write_packets(&mut self) -> Result<(), FFmpegError> {
+        let octx = self.octx.as_mut().ok_or(Error::StreamNotFound)?;
+        let ost_time_base = octx.stream(0).ok_or(Error::StreamNotFound)?.time_base();
+        let time_base = self.frame_rate.invert();
+        let encoder = self.encoder.as_mut().ok_or(FFmpegError::EncoderNotFound)?;
+        let mut encoded = Packet::empty();
+        while encoder.receive_packet(&mut encoded).is_ok() {
+            encoded.set_stream(0);
+            encoded.rescale_ts(time_base, ost_time_base);
+            encoded.write_interleaved(octx)?;
+        }
+        Ok(())
+    }
+
+    /// Flushes the encoder and finalizes the file, with the audio streams of `audio_source` (the finished main output).
+    /// Nothing is written if the render didn't output any frame
+    pub fn finish(&mut self, audio_source: Option<&str>) -> Result<(), FFmpegError> {
+        if let Some(encoder) = self.encoder.as_mut() {
+            encoder.send_eof()?;
+            self.write_packets()?;
+            if let Some(mut octx) = self.octx.take() {
+                octx.write_trailer()?;
+            }
+            match audio_source {
+                Some(audio_source) => {
+                    mux_audio(&self.video_path, audio_source, &self.path)?;
+                    let _ = std::fs::remove_file(&self.video_path);
+                },
+                None => std::fs::rename(&self.video_path, &self.path)?
+            }
+        }
+        self.finished = true;
+        Ok(())
+    }
+}
+
+impl Drop for CroppedOutput {
+    // Removes the incomplete files when the render failed or was cancelled
+    fn drop(&mut self) {
+        if !self.finished {
+            self.octx = None; // Closes the file
+            for path in [&self.video_path, &self.path] {
+                match std::fs::remove_file(path) {
+                    Ok(_) => log::debug!("Removed the partial output {}", path),
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => { },
+                    Err(e) => log::warn!("Failed to remove the partial output {}: {:?}", path, e)
+                }
+            }
+        }
+    }
+}
+
+// Copies the video of `video_path` and the audio streams of `audio_path` to `output_path`, interleaved by their timestamps
+fn mux_audio(video_path: &str, audio_path: &str, output_path: &str) -> Result<(), FFmpegError> {
+    let mut vctx = format::input(&video_path)?;
+    let mut actx = format::input(&audio_path)?;
+    let mut octx = format::output(&output_path)?;
+
+    let mut stream_mapping = vec![-1i32; actx.streams().count()]; // Audio input stream -> output stream
+    {
+        let ist = vctx.stream(0).ok_or(Error::StreamNotFound)?;
+        let mut ost = octx.add_stream(encoder::find(codec::Id::None))?;
+        ost.set_parameters(ist.parameters());
+    }
+    for (i, ist) in actx.streams().enumerate() {
+        if ist.parameters().medium() == media::Type::Audio {
+            let mut ost = octx.add_stream(encoder::find(codec::Id::None))?;
+            ost.set_parameters(ist.parameters());
+            stream_mapping[i] = ost.index() as i32;
+        }
+    }
+    octx.write_header()?;
+
+    let mut video_packets = vctx.packets().map(|(ist, packet)| (0, ist.time_base(), packet));
+    let mut audio_packets = actx.packets().filter_map(|(ist, packet)| {
+        let index = stream_mapping[ist.index()];
+        if index < 0 { None } else { Some((index as usize, ist.time_base(), packet)) }
+    });
+    let mut next_video = video_packets.next();
+    let mut next_audio = audio_packets.next();
+    loop {
+        let dts_us = |x: &(usize, Rational, Packet)| x.2.dts().or(x.2.pts()).unwrap_or_default().rescale(x.1, (1, 1000000));
+        let take_video = match (&next_video, &next_audio) {
+            (Some(v), Some(a)) => dts_us(v) <= dts_us(a),
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            (None, None) => break
+        };
+        let next = if take_video {
+            std::mem::replace(&mut next_video, video_packets.next())
+        } else {
+            std::mem::replace(&mut next_audio, audio_packets.next())
+        };
+        let (index, time_base, mut packet) = next.unwrap();
+        let ost_time_base = octx.stream(index).ok_or(Error::StreamNotFound)?.time_base();
+        packet.set_stream(index);
+        packet.rescale_ts(time_base, ost_time_base);
+        packet.set_position(-1);
+        packet.write_interleaved(&mut octx)?;
+    }
+    octx.write_trailer()?;
+    Ok(())
+}
@@ -0,0 +1,85 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2022 Adrian <adrian.eddy at gmail>
+
+// Direct NVDEC -> CUDA -> NVENC pipeline. The decoded frames stay in the GPU memory, their planes are warped by the CUDA backend
+// of the stabilization into surfaces from a CUDA frames pool, which go straight to NVENC. Nothing is copied to the system memory.
+// Only NV12 and P010/P016 surfaces are supported, which is what NVDEC outputs for 4:2:0 video.
+
+use ffmpeg_next::{ ffi, format, frame };
+use std::ffi::c_void;
+use super::ffmpeg_processor::FFmpegError;
+
+// From libavutil/hwcontext_cuda.h, which isn't in the bindings because it needs cuda.h
+#[repr(C)]
+struct AVCUDADeviceContext {
+    cuda_ctx: *mut c_void, // CUcontext
+    stream: *mut c_void, // CUstream
+    internal: *mut c_void,
+}
+
+const SUPPORTED_FORMATS: &[format::Pixel] = &[format::Pixel::NV12, format::Pixel::P010LE, format::Pixel::P016LE];
+
+pub struct CudaFrames {
+    frames_ref: *mut ffi::AVBufferRef, // Frames context of the output surfaces
+}
+
+impl CudaFrames {
+    /// Pool of the output surfaces, on the same device as the decoded `input`. None if the frame isn't a supported CUDA surface
+    pub fn new(input: &frame::Video, size: (u32, u32)) -> Option<Self> {
+        unsafe {
+            let in_frames_ref = (*input.as_ptr()).hw_frames_ctx;
+            if input.format() != format::Pixel::CUDA || in_frames_ref.is_null() { return None; }
+            let in_frames = (*in_frames_ref).data as *const ffi::AVHWFramesContext;
+            let sw_format: format::Pixel = (*in_frames).sw_format.into();
+            if !SUPPORTED_FORMATS.contains(&sw_format) {
+                log::warn!("CUDA pipeline doesn't support {:?} surfaces", sw_format);
+                return None;
+            }
+
+            let mut frames_ref = ffi::av_hwframe_ctx_alloc((*in_frames).device_ref);
+            if frames_ref.is_null() { return None; }
+            let frames = (*frames_ref).data as *mut ffi::AVHWFramesContext;
+            (*frames).format    = ffi::AVPixelFormat::AV_PIX_FMT_CUDA;
+            (*frames).sw_format = (*in_frames).sw_format;
+            (*frames).width     = size.0 as i32;
+            (*frames).height    = size.1 as i32;
+            let err = ffi::av_hwframe_ctx_init(frames_ref);
+            if err < 0 {
+                super::append_log(&format!("Failed to initialize CUDA frames context. Error code: {}\n", err));
+                ffi::av_buffer_unref(&mut frames_ref);
+                return None;
+            }
+            log::debug!("CUDA pipeline: {:?} {}x{}", sw_format, size.0, size.1);
+            Some(Self { frames_ref })
+        }
+    }
+
+    /// New surface from the pool. The encoder keeps a reference to the frames it didn't encode yet, so the surfaces can't be reused
+    pub fn get_buffer(&self) -> Result<frame::Video, FFmpegError> {
+        let mut frame = frame::Video::empty();
+        let err = unsafe { ffi::av_hwframe_get_buffer(self.frames_ref, frame.as_mut_ptr(), 0) };
+        if err < 0 { return Err(FFmpegError::ToHWBufferError(err)); }
+        Ok(frame)
+    }
+}
+
+impl Drop for CudaFrames {
+    fn drop(&mut self) {
+        unsafe { ffi::av_buffer_unref(&mut self.frames_ref); }
+    }
+}
+
+/// Device pointers of the plane in both frames, with the CUDA context and stream which own them
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub fn buffer_source<'a>(input: &frame::Video, output: &frame::Video, plane_index: usize) -> gyroflow_core::gpu::BufferSource<'a> {
+    unsafe {
+        let frames = (*(*input.as_ptr()).hw_frames_ctx).data as *const ffi::AVHWFramesContext;
+        let hwctx = (*(*frames).device_ctx).hwctx as *const AVCUDADeviceContext;
+        gyroflow_core::gpu::BufferSource::Cuda {
+            input: (*input.as_ptr()).data[plane_index] as u64,
+            output: (*output.as_ptr()).data[plane_index] as u64,
+            context: (*hwctx).cuda_ctx,
+            stream: (*hwctx).stream,
+        }
+    }
+}
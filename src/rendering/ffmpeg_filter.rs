@@ -0,0 +1,202 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2022 Adrian <adrian.eddy at gmail>
+
+// ffmpeg filtergraph applied to the decoded frames before stabilization.
+// Used for deinterlacing and for the custom filters of the input file (eg. hqdn3d, lut3d, crop).
+// Custom filters can change the frame size, the filtered frames are then the video for the stabilization (see `output_size`).
+// When deinterlacing, every field is output as a separate frame (bwdif in `send_field` mode), so the frame rate is doubled
+// and each field gets its own timestamp, which means its own gyro sample.
+
+use ffmpeg_next::{ ffi, filter, format, frame, Rational };
+use super::ffmpeg_processor::FFmpegError;
+
+#[derive(Default, Clone, Copy, PartialEq, Debug)]
+pub enum DeinterlaceMode {
+    #[default]
+    Auto, // Only when the video is flagged as interlaced
+    Disabled,
+    Always,
+}
+impl From<i32> for DeinterlaceMode {
+    fn from(v: i32) -> Self {
+        match v {
+            1 => Self::Disabled,
+            2 => Self::Always,
+            _ => Self::Auto
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FieldOrder {
+    TopFirst,
+    BottomFirst,
+}
+impl FieldOrder {
+    // Field order from the container, which is more reliable than the per-frame flags
+    pub fn from_stream(field_order: ffi::AVFieldOrder) -> Option<Self> {
+        match field_order {
+            ffi::AVFieldOrder::AV_FIELD_TT | ffi::AVFieldOrder::AV_FIELD_TB => Some(Self::TopFirst),
+            ffi::AVFieldOrder::AV_FIELD_BB | ffi::AVFieldOrder::AV_FIELD_BT => Some(Self::BottomFirst),
+            _ => None
+        }
+    }
+    pub fn from_frame(frame: &frame::Video) -> Option<Self> {
+        let (interlaced, tff) = unsafe { ((*frame.as_ptr()).interlaced_frame != 0, (*frame.as_ptr()).top_field_first != 0) };
+        if !interlaced { return None; }
+        Some(if tff { Self::TopFirst } else { Self::BottomFirst })
+    }
+    pub fn deinterlace_filter(&self) -> String {
+        let parity = match self {
+            Self::TopFirst => "tff",
+            Self::BottomFirst => "bff",
+        };
+        format!("bwdif=mode=send_field:parity={}:deint=all", parity)
+    }
+}
+
+pub struct FrameFilter {
+    graph: filter::Graph,
+    sw_frame: frame::Video,
+    pub output_size: (u32, u32),
+}
+
+impl FrameFilter {
+    // `frame` is the first decoded frame, used for the format of the filter input. Timestamps are in microseconds.
+    // `filters` are in the ffmpeg syntax, eg. "hqdn3d=4,eq=gamma=1.1"
+    pub fn new(frame: &frame::Video, filters: &[String]) -> Result<Self, FFmpegError> {
+        let format = if unsafe { !(*frame.as_ptr()).hw_frames_ctx.is_null() } {
+            unsafe { super::ffmpeg_hw::get_transfer_formats_from_gpu(frame.as_ptr() as *mut _) }.first().copied().unwrap_or(format::Pixel::NV12)
+        } else {
+            frame.format()
+        };
+        let sar = frame.aspect_ratio();
+        let sar = if sar.numerator() > 0 && sar.denominator() > 0 { sar } else { Rational::new(1, 1) };
+
+        let mut graph = filter::Graph::new();
+        let args = format!("video_size={}x{}:pix_fmt={}:time_base=1/1000000:pixel_aspect={}/{}",
+            frame.width(), frame.height(), ffi::AVPixelFormat::from(format) as i32, sar.numerator(), sar.denominator());
+        graph.add(&filter::find("buffer").ok_or(ffmpeg_next::Error::FilterNotFound)?, "in", &args)?;
+        graph.add(&filter::find("buffersink").ok_or(ffmpeg_next::Error::FilterNotFound)?, "out", "")?;
+
+        // Filters may need a different pixel format (eg. bwdif doesn't support semi-planar ones), so convert back to the decoded format at the end
+        let format_name = format.descriptor().map(|x| x.name()).unwrap_or("yuv420p");
+        let spec = format!("{},settb=expr=1/1000000,format=pix_fmts={}", filters.join(","), format_name);
+        log::debug!("Video filter: {}", spec);
+
+        graph.output("in", 0)?.input("out", 0)?.parse(&spec).map_err(|e| FFmpegError::FilterError(format!("{}: {}", filters.join(","), e)))?;
+        graph.validate().map_err(|e| FFmpegError::FilterError(format!("{}: {}", filters.join(","), e)))?;
+
+        let output_size = unsafe {
+            let sink = graph.get("out").ok_or(ffmpeg_next::Error::FilterNotFound)?.as_ptr();
+            (ffi::av_buffersink_get_w(sink) as u32, ffi::av_buffersink_get_h(sink) as u32)
+        };
+
+        Ok(Self { graph, sw_frame: frame::Video::empty(), output_size })
+    }
+
+    pub fn push(&mut self, frame: &mut frame::Video) -> Result<(), FFmpegError> {
+        let frame = if unsafe { !(*frame.as_ptr()).hw_frames_ctx.is_null() } {
+            unsafe {
+                if ffi::av_hwframe_transfer_data(self.sw_frame.as_mut_ptr(), frame.as_ptr(), 0) < 0 { return Err(FFmpegError::FromHWTransferError(-1)); }
+                ffi::av_frame_copy_props(self.sw_frame.as_mut_ptr(), frame.as_ptr());
+            }
+            &mut self.sw_frame
+        } else {
+            frame
+        };
+        unsafe { (*frame.as_mut_ptr()).pts = (*frame.as_ptr()).best_effort_timestamp; }
+        let mut src = self.graph.get("in").ok_or(ffmpeg_next::Error::FilterNotFound)?;
+        src.source().add(frame)?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), FFmpegError> {
+        let mut src = self.graph.get("in").ok_or(ffmpeg_next::Error::FilterNotFound)?;
+        src.source().flush()?;
+        Ok(())
+    }
+
+    pub fn pull_all(&mut self, out: &mut Vec<frame::Video>) -> Result<(), FFmpegError> {
+        if let Some(mut sink) = self.graph.get("out") {
+            loop {
+                let mut frame = frame::Video::empty();
+                if sink.sink().frame(&mut frame).is_err() { break; }
+                // The size has to stay the same for the whole video, the stabilization is set up for it
+                if (frame.width(), frame.height()) != self.output_size {
+                    return Err(FFmpegError::FilterError(format!("Filters changed the frame size during the video ({}x{} -> {}x{})", self.output_size.0, self.output_size.1, frame.width(), frame.height())));
+                }
+                // Decoded frames use the best effort timestamp
+                unsafe { (*frame.as_mut_ptr()).best_effort_timestamp = (*frame.as_ptr()).pts; }
+                out.push(frame);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Frame size after `filters` for frames of `size`, also checks that the filters are valid
+pub fn output_size(filters: &str, size: (u32, u32)) -> Result<(u32, u32), FFmpegError> {
+    ffmpeg_next::init()?;
+    let frame = frame::Video::new(format::Pixel::YUV420P, size.0, size.1);
+    Ok(FrameFilter::new(&frame, &[filters.to_string()])?.output_size)
+}
+
+#[derive(Default, Clone, PartialEq, Debug)]
+pub struct PreviewFilterParams {
+    pub filters: String,
+    pub video_size: (u32, u32), // Decoded frame size
+    pub filtered_size: (u32, u32), // Frame size after the filters
+    pub preview_size: (u32, u32), // Decoded frame size in the player
+}
+impl PreviewFilterParams {
+    pub fn is_active(&self) -> bool {
+        !self.filters.is_empty() && self.video_size.0 > 0 && self.video_size.1 > 0
+    }
+    // Size of the filtered preview frames, the preview is stabilized at this size
+    pub fn filtered_preview_size(&self) -> (u32, u32) {
+        if !self.is_active() { return self.preview_size; }
+        let scale = self.preview_size.0 as f64 / self.video_size.0 as f64;
+        (((self.filtered_size.0 as f64 * scale / 2.0).round() * 2.0).max(2.0) as u32,
+         ((self.filtered_size.1 as f64 * scale / 2.0).round() * 2.0).max(2.0) as u32)
+    }
+}
+
+// Filters of the RGBA preview frames, which are scaled down to the preview resolution. Frames are scaled to the video size
+// before the filters, so their sizes in pixels (eg. crop) mean the same as when rendering, and back to the preview scale after them
+pub struct PreviewFilter {
+    pub params: PreviewFilterParams,
+    filter: Option<FrameFilter>,
+    last_timestamp_us: i64,
+}
+impl PreviewFilter {
+    pub fn new(params: PreviewFilterParams) -> Self {
+        Self { params, filter: None, last_timestamp_us: i64::MIN }
+    }
+
+    // Returns None when the filters didn't output a frame yet (eg. they need the next frames)
+    pub fn process(&mut self, timestamp_us: i64, stride: usize, pixels: &[u8]) -> Result<Option<frame::Video>, FFmpegError> {
+        let (width, height) = self.params.preview_size;
+        let mut input = frame::Video::new(format::Pixel::RGBA, width, height);
+        let (row, dst_stride) = (width as usize * 4, input.stride(0));
+        let dst = input.data_mut(0);
+        for y in 0..height as usize {
+            dst[y * dst_stride..y * dst_stride + row].copy_from_slice(&pixels[y * stride..y * stride + row]);
+        }
+        unsafe { (*input.as_mut_ptr()).best_effort_timestamp = timestamp_us; }
+
+        // Timestamps have to increase, start over after seeking back
+        if self.filter.is_none() || timestamp_us <= self.last_timestamp_us {
+            let (video_size, out_size) = (self.params.video_size, self.params.filtered_preview_size());
+            let spec = format!("scale={}:{},{},scale={}:{}", video_size.0, video_size.1, self.params.filters, out_size.0, out_size.1);
+            self.filter = Some(FrameFilter::new(&input, &[spec])?);
+        }
+        self.last_timestamp_us = timestamp_us;
+
+        let filter = self.filter.as_mut().unwrap();
+        filter.push(&mut input)?;
+        let mut out = Vec::new();
+        filter.pull_all(&mut out)?;
+        Ok(out.pop())
+    }
+}
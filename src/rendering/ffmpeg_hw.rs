@@ -1,7 +1,7 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 // Copyright © 2021-2022 Adrian <adrian.eddy at gmail>
 
-use ffmpeg_next::{ ffi, format, codec, encoder };
+use ffmpeg_next::{ ffi, format, frame, codec, encoder };
 
 use std::collections::HashMap;
 use std::collections::hash_map::Entry;
@@ -211,6 +211,31 @@ pub unsafe fn get_transfer_formats_to_gpu(frame: *mut ffi::AVFrame) -> Vec<forma
     }
 }
 
+// Format of the pixel data, for hardware frames it's the format of the surfaces
+pub fn sw_format(frame: &frame::Video) -> format::Pixel {
+    unsafe {
+        let frames_ref = (*frame.as_ptr()).hw_frames_ctx;
+        if frames_ref.is_null() { return frame.format(); }
+        (*((*frames_ref).data as *const ffi::AVHWFramesContext)).sw_format.into()
+    }
+}
+
+// (width, height, stride) of the plane. Same as `plane_width`, `plane_height` and `stride`, but also for hardware frames
+pub fn plane_size(frame: &frame::Video, index: usize) -> (usize, usize, usize) {
+    if unsafe { (*frame.as_ptr()).hw_frames_ctx.is_null() } {
+        return (frame.plane_width(index) as usize, frame.plane_height(index) as usize, frame.stride(index));
+    }
+    let stride = unsafe { (*frame.as_ptr()).linesize[index] as usize };
+    let (width, height) = (frame.width() as usize, frame.height() as usize);
+    match sw_format(frame).descriptor() {
+        Some(desc) if index == 1 || index == 2 => {
+            let (sw, sh) = (desc.log2_chroma_w() as usize, desc.log2_chroma_h() as usize);
+            ((width + (1 << sw) - 1) >> sw, (height + (1 << sh) - 1) >> sh, stride)
+        },
+        _ => (width, height, stride)
+    }
+}
+
 pub fn initialize_hwframes_context(encoder_ctx: *mut ffi::AVCodecContext, _frame_ctx: *mut ffi::AVFrame, type_: DeviceType, _pixel_format: ffi::AVPixelFormat, _size: (u32, u32)) -> Result<(), ()> {
     let mut devices = DEVICES.lock();
     if let Some(dev) = devices.get_mut(&type_) {
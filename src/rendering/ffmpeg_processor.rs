@@ -1,7 +1,7 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 // Copyright © 2021-2022 Adrian <adrian.eddy at gmail>
 
-use std::collections::HashMap;
+use std::collections::{ BTreeMap, HashMap };
 use std::sync::atomic::Ordering::Relaxed;
 use std::sync::Arc;
 use std::error;
@@ -11,6 +11,9 @@ use ffmpeg_next::{ ffi, codec, encoder, format, frame, media, Dictionary, Ration
 use super::*;
 use super::ffmpeg_video::*;
 use super::ffmpeg_audio::*;
+use super::timecode::SourceTimecode;
+use super::chapter_split::ChapterSplit;
+use super::memory_output::{ self, MemoryBuffer, MemoryIo };
 
 pub struct FfmpegProcessor<'a> {
     pub gpu_decoding: bool,
@@ -29,16 +32,40 @@ pub struct FfmpegProcessor<'a> {
     pub decoder_fps: f64,
 
     pub preserve_other_tracks: bool,
+    pub copy_gpmf_track: bool, // Copy the GoPro metadata track, with timestamps adjusted for the trim range
+
+    // Constant output frame rate, if different from the source
+    pub output_frame_rate: Option<Rational>,
+
+    // Container metadata written in addition to the one copied from the input file, eg. copyright, artist, comment
+    pub metadata: BTreeMap<String, String>,
+
+    // Output split into numbered segments, see `chapter_split.rs`
+    pub chapter_split: ChapterSplit,
+
+    // Muxed output is written to this buffer instead of the file, see `memory_output.rs`
+    pub memory_output: Option<MemoryBuffer>,
 
     ost_time_bases: Vec<Rational>,
 }
 
+// Metadata keys which the mov/mp4 muxer writes to the udta atom by default
+const MOV_UDTA_TAGS: &[&str] = &["title", "artist", "album", "comment", "copyright", "date", "description", "genre", "encoder", "composer"];
+
 #[derive(PartialEq)]
 pub enum Status {
     Continue,
     Finish
 }
 
+// What happened with the partially written output after cancelling the render
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PartialOutput {
+    Kept,
+    Removed,
+    RemoveFailed
+}
+
 #[derive(Debug)]
 pub enum FFmpegError {
     EncoderNotFound,
@@ -55,10 +82,15 @@ pub enum FFmpegError {
     CannotCreateGPUDecoding,
     NoFramesContext,
     GPUDecodingFailed,
+    GPUProcessingFailed(i64),
     ToHWBufferError(i32),
     PixelFormatNotSupported((format::Pixel, Vec<format::Pixel>)),
+    EncoderUnsupported(Vec<super::EncoderProbeIssue>),
     UnknownPixelFormat(format::Pixel),
+    FilterError(String),
     InternalError(ffmpeg_next::Error),
+    IoError(std::io::Error),
+    Cancelled(PartialOutput),
 }
 
 impl std::fmt::Display for FFmpegError {
@@ -77,11 +109,16 @@ impl std::fmt::Display for FFmpegError {
             FFmpegError::ToHWBufferError(i)     => write!(f, "Error getting HW transfer buffer to the GPU: {:?}", ffmpeg_next::Error::Other { errno: *i }),
             FFmpegError::NoFramesContext             => write!(f, "Empty hw frames context"),
             FFmpegError::GPUDecodingFailed           => write!(f, "GPU decoding failed, please try again."),
+            FFmpegError::GPUProcessingFailed(ts) => write!(f, "Failed to process the GPU frame at {} us", ts),
             FFmpegError::CannotCreateGPUDecoding     => write!(f, "Unable to create HW devices context"),
             FFmpegError::NoGPUDecodingDevice         => write!(f, "Unable to create any HW decoding context"),
             FFmpegError::UnknownPixelFormat(v) => write!(f, "Unknown pixel format: {:?}", v),
             FFmpegError::PixelFormatNotSupported(v) => write!(f, "Pixel format {:?} is not supported. Supported ones: {:?}", v.0, v.1),
+            FFmpegError::EncoderUnsupported(v) => write!(f, "Encoder doesn't support the requested settings: {}", v.iter().map(|x| x.message.as_str()).collect::<Vec<_>>().join(" ")),
+            FFmpegError::FilterError(e)       => write!(f, "Video filter error: {}", e),
             FFmpegError::InternalError(e)     => write!(f, "ffmpeg error: {:?}", e),
+            FFmpegError::IoError(e)           => write!(f, "I/O error: {}", e),
+            FFmpegError::Cancelled(v)         => write!(f, "Render cancelled, partial output: {:?}", v),
         }
     }
 }
@@ -89,6 +126,7 @@ impl error::Error for FFmpegError {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match *self {
             FFmpegError::InternalError(ref e) => Some(e),
+            FFmpegError::IoError(ref e) => Some(e),
             _ => None
         }
     }
@@ -96,6 +134,9 @@ impl error::Error for FFmpegError {
 impl From<ffmpeg_next::Error> for FFmpegError {
     fn from(err: ffmpeg_next::Error) -> FFmpegError { FFmpegError::InternalError(err) }
 }
+impl From<std::io::Error> for FFmpegError {
+    fn from(err: std::io::Error) -> FFmpegError { FFmpegError::IoError(err) }
+}
 
 #[derive(Debug, Clone)]
 pub struct VideoInfo {
@@ -105,10 +146,51 @@ pub struct VideoInfo {
     pub width: u32,
     pub height: u32,
     pub bitrate: f64, // in Mbps
+    pub pixel_aspect_ratio: f64,
+}
+
+// Decoder parallelism and buffering, 0 means the default for the codec
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+pub struct DecoderTuning {
+    pub threads: usize,     // Frame threads, ie. how many frames are decoded ahead
+    pub queue_depth: usize, // Frames queued in asynchronous hardware decoders (`async_depth`)
+    pub hw_surfaces: usize, // Hardware surfaces allocated in addition to the ones the decoder needs for reference frames
+}
+impl DecoderTuning {
+    pub fn resolve(&self, codec: codec::Id, width: u32, height: u32) -> Self {
+        let cores = std::thread::available_parallelism().map(|x| x.get()).unwrap_or(4);
+        let is_4k = width as u64 * height as u64 >= 3840 * 2160;
+        let (threads, queue_depth, hw_surfaces) = match codec {
+            // Long-GOP codecs: frames depend on each other, so decoding ahead on more threads helps the most.
+            // Every frame in flight holds a hardware surface, so the surface pool has to grow with the thread count
+            codec::Id::HEVC | codec::Id::H264 | codec::Id::AV1 | codec::Id::VP9 => {
+                let threads = if is_4k { (cores / 2).clamp(3, 8) } else { 3 };
+                (threads, 4, threads + 2)
+            },
+            // Intra-only codecs are cheap to decode per frame, but every decoded frame is big
+            codec::Id::PRORES | codec::Id::DNXHD | codec::Id::MJPEG | codec::Id::CFHD => (if is_4k { 4 } else { 3 }, 1, 0),
+            _ => (3, 1, 0)
+        };
+        // Every frame in flight is a full size frame in memory
+        let (threads, queue_depth, hw_surfaces) = if gyroflow_core::low_memory::is_enabled() {
+            let threads = threads.min(gyroflow_core::low_memory::MAX_DECODER_THREADS);
+            (threads, 1, hw_surfaces.min(threads + 1))
+        } else {
+            (threads, queue_depth, hw_surfaces)
+        };
+        Self {
+            threads:     if self.threads     > 0 { self.threads     } else { threads },
+            queue_depth: if self.queue_depth > 0 { self.queue_depth } else { queue_depth },
+            hw_surfaces: if self.hw_surfaces > 0 { self.hw_surfaces } else { hw_surfaces },
+        }
+    }
 }
 
 impl<'a> FfmpegProcessor<'a> {
-    pub fn from_file(path: &str, mut gpu_decoding: bool, gpu_decoder_index: usize, decoder_options: Option<Dictionary>) -> Result<Self, FFmpegError> {
+    pub fn from_file(path: &str, gpu_decoding: bool, gpu_decoder_index: usize, decoder_options: Option<Dictionary>) -> Result<Self, FFmpegError> {
+        Self::from_file_with_tuning(path, gpu_decoding, gpu_decoder_index, decoder_options, DecoderTuning::default())
+    }
+    pub fn from_file_with_tuning(path: &str, mut gpu_decoding: bool, gpu_decoder_index: usize, decoder_options: Option<Dictionary>, tuning: DecoderTuning) -> Result<Self, FFmpegError> {
         ffmpeg_next::init()?;
         let _ = crate::rendering::init();
 
@@ -131,9 +213,13 @@ impl<'a> FfmpegProcessor<'a> {
         let decoder = strm.1;
 
         let decoder_fps = stream.rate().into();
+        let field_order = ffmpeg_filter::FieldOrder::from_stream(unsafe { (*stream.parameters().as_ptr()).field_order });
 
         let mut decoder_ctx = codec::context::Context::from_parameters(stream.parameters())?;
-        decoder_ctx.set_threading(ffmpeg_next::threading::Config { kind: ffmpeg_next::threading::Type::Frame, count: 3, safe: false });
+        let (width, height) = unsafe { ((*stream.parameters().as_ptr()).width as u32, (*stream.parameters().as_ptr()).height as u32) };
+        let tuning = tuning.resolve(decoder_ctx.id(), width, height);
+        log::debug!("Decoder tuning for {:?} {}x{}: {:?}", decoder_ctx.id(), width, height, tuning);
+        decoder_ctx.set_threading(ffmpeg_next::threading::Config { kind: ffmpeg_next::threading::Type::Frame, count: tuning.threads, safe: false });
 
         let mut hw_backend = String::new();
         if gpu_decoding {
@@ -142,6 +228,13 @@ impl<'a> FfmpegProcessor<'a> {
             hw_backend = hw.2;
         }
         gpu_decoding = !hw_backend.is_empty();
+        let mut codec_options = Dictionary::new();
+        if gpu_decoding {
+            unsafe { (*decoder_ctx.as_mut_ptr()).extra_hw_frames = tuning.hw_surfaces as i32; }
+            // Only the hardware decoders have an asynchronous queue
+            codec_options.set("async_depth", &tuning.queue_depth.to_string());
+        }
+        let codec_id = decoder_ctx.id();
 
         Ok(Self {
             gpu_decoding,
@@ -156,6 +249,14 @@ impl<'a> FfmpegProcessor<'a> {
             end_ms: None,
 
             preserve_other_tracks: false,
+            copy_gpmf_track: false,
+
+            output_frame_rate: None,
+
+            metadata: BTreeMap::new(),
+
+            chapter_split: ChapterSplit::default(),
+            memory_output: None,
 
             decoder_fps,
 
@@ -167,7 +268,10 @@ impl<'a> FfmpegProcessor<'a> {
                     options: Dictionary::new(),
                     ..EncoderParams::default()
                 },
-                decoder: Some(decoder_ctx.decoder().video()?),
+                decoder: Some(decoder_ctx.decoder().open_as_with(codec_id, codec_options)?.video()?),
+                field_order,
+                // Frames are only deinterlaced when rendering, decoding for sync and preview uses the original frames
+                deinterlace: ffmpeg_filter::DeinterlaceMode::Disabled,
                 ..VideoTranscoder::default()
             },
 
@@ -182,16 +286,53 @@ impl<'a> FfmpegProcessor<'a> {
         let mut atranscoders = HashMap::new();
         let mut output_index = 0usize;
 
+        // Image sequences are already split into files
+        let split_format = ChapterSplit::guess_format(output_path).filter(|x| self.chapter_split.is_enabled() && !x.contains("image"));
+        let mut split_times = Vec::new();
+        if split_format.is_some() {
+            // Gaps are found before seeking to the start, because the input is rewound afterwards
+            let gaps = if self.chapter_split.at_gaps { ChapterSplit::find_gaps(&mut self.input_context)? } else { Vec::new() };
+            let start_time = self.input_context.start_time();
+            let start_ms = self.start_ms.unwrap_or(if start_time != ffi::AV_NOPTS_VALUE { start_time as f64 / 1000.0 } else { 0.0 });
+            let end_ms = self.end_ms.unwrap_or(start_ms + self.input_context.duration().max(0) as f64 / 1000.0);
+            split_times = self.chapter_split.split_times(&gaps, (start_ms, end_ms));
+            log::debug!("Chapter split at {:?} s, recording gaps at {:?} ms", split_times, gaps);
+        }
+
         if let Some(start_ms) = self.start_ms {
             let position = (start_ms as i64).rescale((1, 1000), rescale::TIME_BASE);
             self.input_context.seek(position, ..position)?;
         }
 
-        let mut octx = format::output(&output_path)?;
+        let mut octx = match &split_format {
+            // Single segment still gets the numbered name
+            Some(_) if split_times.is_empty() => format::output(&ChapterSplit::segment_path(output_path, "001"))?,
+            Some(_) => ChapterSplit::open_output(output_path)?,
+            None if self.memory_output.is_some() => memory_output::alloc_output(output_path)?,
+            None => format::output(&output_path)?
+        };
+        // Declared after `octx`, so it's detached from the context before the context is dropped
+        let _memory_io = match &self.memory_output {
+            Some(buffer) if split_format.is_none() => Some(MemoryIo::attach(&mut octx, buffer.clone())?),
+            _ => None
+        };
+        let format_name = split_format.clone().unwrap_or_else(|| octx.format().name().to_string());
+        let mut gpmf_indices = Vec::new();
+        // Only mov/mp4 can store the GPMF track
+        let container_supports_gpmf = format_name.contains("mov") || format_name.contains("mp4");
+
+        // Timecode of the first output frame. A copied timecode track would keep the untrimmed start, so the muxer writes a new one instead
+        let source_timecode = SourceTimecode::from_input(&self.input_context);
+        let output_timecode = self.input_context.streams().find(|x| x.parameters().medium() == media::Type::Video).and_then(|stream| {
+            let input_rate = stream.avg_frame_rate();
+            source_timecode.shifted(input_rate, self.output_frame_rate.unwrap_or(input_rate), self.start_ms.unwrap_or_default() / 1000.0)
+        });
 
         for (i, stream) in self.input_context.streams().enumerate() {
             let medium = stream.parameters().medium();
-            if medium != media::Type::Audio && medium != media::Type::Video && (!self.preserve_other_tracks || medium != media::Type::Data) {
+            let is_gpmf = medium == media::Type::Data && Self::is_gpmf_stream(&stream) && (self.preserve_other_tracks || (self.copy_gpmf_track && container_supports_gpmf));
+            let is_replaced_timecode = medium == media::Type::Data && output_timecode.is_some() && Self::is_timecode_stream(&stream);
+            if (medium != media::Type::Audio && medium != media::Type::Video && (!self.preserve_other_tracks || medium != media::Type::Data) && !is_gpmf) || is_replaced_timecode {
                 stream_mapping[i] = -1;
                 continue;
             }
@@ -216,12 +357,27 @@ impl<'a> FfmpegProcessor<'a> {
                 let mut out_stream = octx.add_stream(codec)?;
                 self.video.encoder_params.codec = Some(codec);
 
-                self.video.encoder_params.frame_rate = Some(stream.avg_frame_rate());
-                self.video.encoder_params.time_base = Some(stream.rate().invert());
+                if let Some(rate) = self.output_frame_rate {
+                    self.video.encoder_params.frame_rate = Some(rate);
+                    self.video.encoder_params.time_base = Some(rate.invert());
+
+                    out_stream.set_rate(rate);
+                    out_stream.set_time_base(rate.invert());
+                    out_stream.set_avg_frame_rate(rate);
+                } else {
+                    self.video.encoder_params.frame_rate = Some(stream.avg_frame_rate());
+                    self.video.encoder_params.time_base = Some(stream.rate().invert());
 
-                out_stream.set_rate(stream.rate());
-                out_stream.set_time_base(stream.time_base());
-                out_stream.set_avg_frame_rate(stream.avg_frame_rate());
+                    out_stream.set_rate(stream.rate());
+                    out_stream.set_time_base(stream.time_base());
+                    out_stream.set_avg_frame_rate(stream.avg_frame_rate());
+                }
+                if let Some(reel_name) = &source_timecode.reel_name {
+                    // The mov muxer stores it in the timecode track, which it links to this stream
+                    let mut stream_metadata = Dictionary::new();
+                    stream_metadata.set("reel_name", reel_name);
+                    out_stream.set_metadata(stream_metadata);
+                }
 
                 output_index += 1;
             } else if medium == media::Type::Audio && self.audio_codec != codec::Id::None {
@@ -236,20 +392,47 @@ impl<'a> FfmpegProcessor<'a> {
                     atranscoders.insert(i, AudioTranscoder::new(self.audio_codec, &stream, &mut octx, output_index as _)?);
                 }
                 output_index += 1;
-            } else if self.preserve_other_tracks && medium == media::Type::Data {
+            } else if (self.preserve_other_tracks || is_gpmf) && medium == media::Type::Data {
                 // Direct stream copy
                 let mut ost = octx.add_stream(encoder::find(codec::Id::None))?;
                 ost.set_parameters(stream.parameters());
                 ost.set_avg_frame_rate(stream.avg_frame_rate());
+                if is_gpmf {
+                    // GoPro tools look for the track by its handler name
+                    ost.set_metadata(stream.metadata().to_owned());
+                    gpmf_indices.push(i);
+                }
                 output_index += 1;
             }
         }
 
-        octx.set_metadata(self.input_context.metadata().to_owned());
+        let mut metadata = self.input_context.metadata().to_owned();
+        if let Some(timecode) = &output_timecode {
+            log::debug!("Output timecode: {} (source: {:?}, reel: {:?})", timecode, source_timecode.timecode, source_timecode.reel_name);
+            metadata.set("timecode", timecode);
+        }
+        for (key, value) in self.metadata.iter().filter(|(_, v)| !v.is_empty()) {
+            metadata.set(key, value);
+            if !MOV_UDTA_TAGS.contains(&key.as_str()) {
+                // Store custom keys in the `keys` atom, udta only supports a fixed set of tags
+                let movflags = self.video.muxer_options.get("movflags").map(|x| x.to_string()).unwrap_or_default();
+                if !movflags.contains("use_metadata_tags") {
+                    self.video.muxer_options.set("movflags", &format!("{}+use_metadata_tags", movflags));
+                }
+            }
+        }
+        octx.set_metadata(metadata);
+        if !split_times.is_empty() {
+            self.video.muxer_options = ChapterSplit::muxer_options(&format_name, &split_times, self.video.encoder_params.frame_rate, &self.video.muxer_options);
+            self.video.forced_keyframes_us = split_times.iter().map(|x| (x * 1_000_000.0).round() as i64).collect();
+        }
         // Header will be written after video encoder is initalized, in ffmpeg_video.rs:init_encoder
 
         let mut video_inited = false;
 
+        // Checked by the transcoder before every frame, so the cancellation doesn't wait for the whole decoded packet
+        self.video.cancel_flag = Some(cancel_flag.clone());
+
         let mut pending_packets: Vec<(Stream, ffmpeg_next::Packet, usize, isize)> = Vec::new();
 
         // let mut copied_stream_first_pts = None;
@@ -328,7 +511,18 @@ impl<'a> FfmpegProcessor<'a> {
                         }
                     }
                 }
-            } else if self.audio_codec != codec::Id::None || self.preserve_other_tracks {
+            } else if self.audio_codec != codec::Id::None || self.preserve_other_tracks || !gpmf_indices.is_empty() {
+                if gpmf_indices.contains(&ist_index) {
+                    // Shift the GPMF packets to the trimmed timeline, keep the ones which overlap the range
+                    let start = self.start_ms.map(|x| (x.round() as i64).rescale((1, 1000), stream.time_base())).unwrap_or_default();
+                    let end = self.end_ms.map(|x| (x.round() as i64).rescale((1, 1000), stream.time_base()));
+                    let pts = packet.pts().unwrap_or_default();
+                    if pts + packet.duration() <= start || end.map_or(false, |end| pts >= end) {
+                        continue;
+                    }
+                    packet.set_pts(Some((pts - start).max(0)));
+                    packet.set_dts(packet.dts().map(|x| (x - start).max(0)));
+                }
                 if !video_inited {
                     pending_packets.push((stream, packet, ist_index, ost_index));
                     continue;
@@ -338,10 +532,18 @@ impl<'a> FfmpegProcessor<'a> {
             }
         }
 
+        if cancel_flag.load(Relaxed) {
+            // Don't drain the decoder and encoder queues, the output is incomplete anyway
+            self.video.cancel_flag = None;
+            return Err(FFmpegError::Cancelled(PartialOutput::Kept));
+        }
+        self.video.cancel_flag = None;
+
         // Flush encoders and decoders.
         {
             let ost_time_base = self.ost_time_bases[self.video.output_index.unwrap_or_default()];
             self.video.decoder.as_mut().ok_or(Error::DecoderNotFound)?.send_eof()?;
+            self.video.end_of_stream = true;
             // self.video.decoder.as_mut().ok_or(Error::DecoderNotFound)?.flush();
             self.video.receive_and_process_video_frames(output_size, bitrate, Some(&mut octx), &mut self.ost_time_bases, self.start_ms, self.end_ms)?;
             self.video.encoder.as_mut().ok_or(Error::EncoderNotFound)?.send_eof()?;
@@ -359,6 +561,17 @@ impl<'a> FfmpegProcessor<'a> {
         Ok(())
     }
 
+    // GoPro metadata track, "gpmd" codec tag with the "GoPro MET" handler
+    fn is_gpmf_stream(stream: &Stream) -> bool {
+        let codec_tag = unsafe { (*stream.parameters().as_ptr()).codec_tag };
+        codec_tag == u32::from_le_bytes(*b"gpmd") || stream.metadata().get("handler_name").map_or(false, |x| x.contains("GoPro MET"))
+    }
+
+    // QuickTime timecode track
+    fn is_timecode_stream(stream: &Stream) -> bool {
+        unsafe { (*stream.parameters().as_ptr()).codec_tag == u32::from_le_bytes(*b"tmcd") }
+    }
+
     pub fn start_decoder_only(&mut self, mut ranges: Vec<(f64, f64)>, cancel_flag: Arc<AtomicBool>) -> Result<(), FFmpegError> {
         if !ranges.is_empty() {
             let next_range = ranges.remove(0);
@@ -458,6 +671,11 @@ impl<'a> FfmpegProcessor<'a> {
                 let mut frames = stream.frames() as usize;
                 if frames == 0 { frames = (stream.duration() as f64 * f64::from(stream.time_base()) * f64::from(stream.rate())) as usize; }
 
+                // Container value has priority over the codec one, same as in `av_guess_sample_aspect_ratio`
+                let sar = unsafe { (*stream.as_ptr()).sample_aspect_ratio };
+                let sar = if sar.num > 0 && sar.den > 0 { Rational::new(sar.num, sar.den) } else { video.aspect_ratio() };
+                let pixel_aspect_ratio = if sar.numerator() > 0 && sar.denominator() > 0 { f64::from(sar) } else { 1.0 };
+
                 return Ok(VideoInfo {
                     duration_ms: stream.duration() as f64 * f64::from(stream.time_base()) * 1000.0,
                     frame_count: frames,
@@ -465,6 +683,7 @@ impl<'a> FfmpegProcessor<'a> {
                     width: video.width(),
                     height: video.height(),
                     bitrate: bitrate as f64 / 1024.0 / 1024.0,
+                    pixel_aspect_ratio,
                 });
             }
         }
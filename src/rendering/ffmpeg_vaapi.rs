@@ -0,0 +1,172 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2022 Adrian <adrian.eddy at gmail>
+
+// Direct VAAPI decoder -> wgpu (Vulkan) -> VAAPI encoder pipeline on Linux. The planes of the decoded surfaces and of the output surfaces
+// are exported as DMA-BUFs (DRM PRIME), imported to the Vulkan device of the wgpu backend, and the output surfaces go straight to the encoder.
+// Nothing is copied to the system memory. Only NV12 and P010 surfaces are supported, which is what the VAAPI decoders output for 4:2:0 video.
+// V4L2 M2M encoders only accept frames in the system memory, so they still use the regular path.
+
+use ffmpeg_next::{ ffi, format, frame };
+use super::ffmpeg_processor::FFmpegError;
+
+const SUPPORTED_FORMATS: &[format::Pixel] = &[format::Pixel::NV12, format::Pixel::P010LE];
+
+pub struct VaapiFrames {
+    frames_ref: *mut ffi::AVBufferRef, // Frames context of the output surfaces
+}
+
+impl VaapiFrames {
+    /// Pool of the output surfaces, on the same device as the decoded `input`. None if the frame isn't a supported VAAPI surface
+    pub fn new(input: &frame::Video, size: (u32, u32)) -> Option<Self> {
+        unsafe {
+            let in_frames_ref = (*input.as_ptr()).hw_frames_ctx;
+            if input.format() != format::Pixel::VAAPI || in_frames_ref.is_null() { return None; }
+            let in_frames = (*in_frames_ref).data as *const ffi::AVHWFramesContext;
+            let sw_format: format::Pixel = (*in_frames).sw_format.into();
+            if !SUPPORTED_FORMATS.contains(&sw_format) {
+                log::warn!("VAAPI pipeline doesn't support {:?} surfaces", sw_format);
+                return None;
+            }
+
+            let mut frames_ref = ffi::av_hwframe_ctx_alloc((*in_frames).device_ref);
+            if frames_ref.is_null() { return None; }
+            let frames = (*frames_ref).data as *mut ffi::AVHWFramesContext;
+            (*frames).format    = ffi::AVPixelFormat::AV_PIX_FMT_VAAPI;
+            (*frames).sw_format = (*in_frames).sw_format;
+            (*frames).width     = size.0 as i32;
+            (*frames).height    = size.1 as i32;
+            let err = ffi::av_hwframe_ctx_init(frames_ref);
+            if err < 0 {
+                super::append_log(&format!("Failed to initialize VAAPI frames context. Error code: {}\n", err));
+                ffi::av_buffer_unref(&mut frames_ref);
+                return None;
+            }
+            log::debug!("VAAPI pipeline: {:?} {}x{}", sw_format, size.0, size.1);
+            Some(Self { frames_ref })
+        }
+    }
+
+    /// New surface from the pool. The encoder keeps a reference to the frames it didn't encode yet, so the surfaces can't be reused
+    pub fn get_buffer(&self) -> Result<frame::Video, FFmpegError> {
+        let mut frame = frame::Video::empty();
+        let err = unsafe { ffi::av_hwframe_get_buffer(self.frames_ref, frame.as_mut_ptr(), 0) };
+        if err < 0 { return Err(FFmpegError::ToHWBufferError(err)); }
+        Ok(frame)
+    }
+}
+
+impl Drop for VaapiFrames {
+    fn drop(&mut self) {
+        unsafe { ffi::av_buffer_unref(&mut self.frames_ref); }
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub use dma_buf::buffer_source;
+
+// DMA-BUF export of the surface planes
+#[cfg(target_os = "linux")]
+mod dma_buf {
+    use ffmpeg_next::{ ffi, format, frame };
+    use std::os::unix::io::{ FromRawFd, IntoRawFd };
+    use gyroflow_core::gpu::wgpu_vulkan::VulkanImage;
+
+    // From libavutil/hwcontext_drm.h, which isn't in the bindings
+    const AV_DRM_MAX_PLANES: usize = 4;
+    #[repr(C)]
+    struct AVDRMObjectDescriptor {
+        fd: i32,
+        size: usize,
+        format_modifier: u64,
+    }
+    #[repr(C)]
+    struct AVDRMPlaneDescriptor {
+        object_index: i32,
+        offset: isize,
+        pitch: isize,
+    }
+    #[repr(C)]
+    #[allow(dead_code)]
+    struct AVDRMLayerDescriptor {
+        format: u32,
+        nb_planes: i32,
+        planes: [AVDRMPlaneDescriptor; AV_DRM_MAX_PLANES],
+    }
+    #[repr(C)]
+    #[allow(dead_code)]
+    struct AVDRMFrameDescriptor {
+        nb_objects: i32,
+        objects: [AVDRMObjectDescriptor; AV_DRM_MAX_PLANES],
+        nb_layers: i32,
+        layers: [AVDRMLayerDescriptor; AV_DRM_MAX_PLANES],
+    }
+
+    // VkFormat of the planes: VK_FORMAT_R8_UNORM, VK_FORMAT_R8G8_UNORM, VK_FORMAT_R16_UNORM, VK_FORMAT_R16G16_UNORM
+    fn plane_vk_format(sw_format: format::Pixel, plane_index: usize) -> i32 {
+        match (sw_format, plane_index) {
+            (format::Pixel::NV12, 0) => 9,
+            (format::Pixel::NV12, _) => 16,
+            (_, 0) => 70,
+            _ => 77,
+        }
+    }
+    // VK_IMAGE_USAGE_TRANSFER_SRC_BIT and VK_IMAGE_USAGE_TRANSFER_DST_BIT
+    const USAGE_SRC: u32 = 1;
+    const USAGE_DST: u32 = 2;
+    const VK_QUEUE_FAMILY_EXTERNAL: u32 = !1;
+
+    // Exports the plane of the surface as a DMA-BUF. The descriptor is only valid while the mapping exists, so the fd is duplicated
+    fn export_plane(frame: &frame::Video, plane_index: usize, write: bool) -> Option<VulkanImage> {
+        unsafe {
+            let mut drm = frame::Video::empty();
+            (*drm.as_mut_ptr()).format = ffi::AVPixelFormat::AV_PIX_FMT_DRM_PRIME as i32;
+            let flags = if write { ffi::AV_HWFRAME_MAP_WRITE } else { ffi::AV_HWFRAME_MAP_READ };
+            let err = ffi::av_hwframe_map(drm.as_mut_ptr(), frame.as_ptr(), flags as i32);
+            if err < 0 {
+                log::error!("Failed to map the VAAPI surface to DRM PRIME: {}", err);
+                return None;
+            }
+            let desc = &*((*drm.as_ptr()).data[0] as *const AVDRMFrameDescriptor);
+
+            // Depending on the driver, the planes are either in one layer or in a layer each
+            let plane = desc.layers[..desc.nb_layers as usize].iter()
+                .flat_map(|layer| layer.planes[..layer.nb_planes as usize].iter())
+                .nth(plane_index)?;
+            let object = &desc.objects[plane.object_index as usize];
+
+            let fd = std::mem::ManuallyDrop::new(std::fs::File::from_raw_fd(object.fd));
+            let fd = fd.try_clone().ok()?.into_raw_fd();
+
+            let sw_format = crate::rendering::ffmpeg_hw::sw_format(frame);
+            // VASurfaceID, unique on the display
+            let surface = (*frame.as_ptr()).data[3] as u64;
+            Some(VulkanImage {
+                image: 0,
+                memory: (surface << 8) | ((write as u64) << 4) | plane_index as u64,
+                memory_fd: fd,
+                memory_size: object.size as u64,
+                memory_type_index: 0,
+                memory_offset: plane.offset as u64,
+                dma_buf: true,
+                drm_modifier: object.format_modifier,
+                row_pitch: plane.pitch as u64,
+                format: plane_vk_format(sw_format, plane_index),
+                usage: if write { USAGE_DST } else { USAGE_SRC },
+                queue_family_index: VK_QUEUE_FAMILY_EXTERNAL,
+            })
+        }
+    }
+
+    /// Planes of both surfaces exported as DMA-BUFs, for the wgpu backend
+    pub fn buffer_source<'a>(input: &frame::Video, output: &frame::Video, plane_index: usize) -> Option<gyroflow_core::gpu::BufferSource<'a>> {
+        let input = export_plane(input, plane_index, false)?;
+        let output = match export_plane(output, plane_index, true) {
+            Some(x) => x,
+            None => {
+                drop(unsafe { std::fs::File::from_raw_fd(input.memory_fd) });
+                return None;
+            }
+        };
+        Some(gyroflow_core::gpu::BufferSource::Vulkan { input, output })
+    }
+}
@@ -2,10 +2,17 @@
 // Copyright © 2021-2022 Adrian <adrian.eddy at gmail>
 
 use ffmpeg_next::{ ffi, codec, decoder, encoder, format, frame, picture, software, util, Dictionary, Packet, Rational, Error, rescale::Rescale };
+use std::sync::{ Arc, atomic::{ AtomicBool, Ordering::Relaxed } };
+use std::time::{ Duration, Instant };
+use gyroflow_core::pipeline::{ self, Stage };
 
 use super::ffmpeg_processor::Status;
 use super::ffmpeg_processor::FFmpegError;
-use super::ffmpeg_video_converter::Converter;
+use super::ffmpeg_video_converter::{ self, Converter };
+use super::ffmpeg_filter::{ FrameFilter, DeinterlaceMode, FieldOrder };
+use super::frame_pool::FramePool;
+use super::ffmpeg_cuda::CudaFrames;
+use super::ffmpeg_vaapi::VaapiFrames;
 
 pub struct FrameBuffers {
     pub sw_frame: frame::Video,
@@ -49,6 +56,7 @@ pub struct VideoTranscoder<'a> {
     pub encoder_name: String,
 
     pub encoder_params: EncoderParams<'a>,
+    pub muxer_options: Dictionary<'a>,
 
     pub codec_supported_formats: Vec<format::Pixel>,
 
@@ -58,6 +66,7 @@ pub struct VideoTranscoder<'a> {
     pub gpu_decoding: bool,
     pub gpu_encoding: bool,
     pub clone_frames: bool,
+    pub frame_pool: FramePool, // Copies of the frames sent to the encoder when `clone_frames` is set
 
     pub converter: Converter,
 
@@ -68,7 +77,74 @@ pub struct VideoTranscoder<'a> {
 
     pub first_frame_ts: Option<i64>,
 
-    pub processing_order: ProcessingOrder
+    pub processing_order: ProcessingOrder,
+
+    pub deinterlace: DeinterlaceMode,
+    pub field_order: Option<FieldOrder>, // From the container, None if the stream is progressive or unknown
+    pub video_filter: String, // Custom ffmpeg filtergraph applied before stabilization
+    pub output_color_range: Option<util::color::Range>, // None - same as the source
+    pub end_of_stream: bool,
+    pub cancel_flag: Option<Arc<AtomicBool>>,
+    pub forced_keyframes_us: Vec<i64>, // Output timestamps which have to start with a keyframe, in ascending order
+    pub cuda_pipeline: bool, // Keep the NVDEC frames in the GPU memory and encode the output surfaces directly, see `ffmpeg_cuda`
+    cuda_frames: Option<CudaFrames>,
+    pub vaapi_pipeline: bool, // Same with the VAAPI surfaces, processed by the wgpu backend, see `ffmpeg_vaapi`
+    vaapi_frames: Option<VaapiFrames>,
+    decoded: DecodedFrames,
+}
+
+// Decoded frames, passed through the deinterlacer and the custom filters when any are used
+#[derive(Default)]
+struct DecodedFrames {
+    filter: Option<FrameFilter>,
+    filter_checked: bool,
+    pending: Vec<frame::Video>,
+}
+impl DecodedFrames {
+    // Next frame to process, `None` when the decoder needs more packets
+    fn next(&mut self, decoder: &mut decoder::Video, deinterlacing: bool, field_order: Option<FieldOrder>, video_filter: &str, end_of_stream: bool, decode_time: &mut Duration) -> Result<Option<frame::Video>, FFmpegError> {
+        loop {
+            if !self.pending.is_empty() {
+                return Ok(Some(self.pending.remove(0)));
+            }
+            let decode_start = Instant::now();
+            let mut decoded = frame::Video::empty();
+            let received = decoder.receive_frame(&mut decoded).is_ok();
+            if received {
+                if !self.filter_checked {
+                    self.filter_checked = true;
+                    let mut filters = Vec::new();
+                    if deinterlacing {
+                        let field_order = field_order.or_else(|| FieldOrder::from_frame(&decoded)).unwrap_or(FieldOrder::TopFirst);
+                        log::debug!("Deinterlacing the video, field order: {:?}", field_order);
+                        filters.push(field_order.deinterlace_filter());
+                    }
+                    if !video_filter.trim().is_empty() {
+                        filters.push(video_filter.trim().to_string());
+                    }
+                    if !filters.is_empty() {
+                        self.filter = Some(FrameFilter::new(&decoded, &filters)?);
+                    }
+                }
+                if let Some(filter) = self.filter.as_mut() {
+                    filter.push(&mut decoded)?;
+                    filter.pull_all(&mut self.pending)?;
+                } else {
+                    self.pending.push(decoded);
+                }
+            } else if end_of_stream {
+                // Filters can keep the last frames (eg. for the deinterlacer lookahead), output them once the decoder is drained
+                if let Some(mut filter) = self.filter.take() {
+                    filter.flush()?;
+                    filter.pull_all(&mut self.pending)?;
+                }
+            }
+            *decode_time += decode_start.elapsed();
+            if !received && self.pending.is_empty() {
+                return Ok(None);
+            }
+        }
+    }
 }
 
 pub struct RateControl {
@@ -86,6 +162,26 @@ macro_rules! ffmpeg {
 }
 
 impl<'a> VideoTranscoder<'a> {
+    // Whether the fields are output as separate frames, which doubles the frame rate
+    pub fn deinterlacing(&self) -> bool {
+        match self.deinterlace {
+            DeinterlaceMode::Disabled => false,
+            DeinterlaceMode::Always => true,
+            DeinterlaceMode::Auto => self.field_order.is_some(),
+        }
+    }
+
+    // RGB sources are encoded to limited range YUV, unless requested otherwise
+    fn output_full_range(&self, src: &frame::Video) -> bool {
+        match self.output_color_range {
+            Some(range) => range == util::color::Range::JPEG,
+            None => {
+                let format = super::ffmpeg_hw::sw_format(src);
+                !ffmpeg_video_converter::is_rgb_or_gray(format) && ffmpeg_video_converter::is_full_range(format, src.color_range())
+            }
+        }
+    }
+
     fn init_encoder(frame: &mut frame::Video, params: &EncoderParams, decoder: &mut decoder::Video, size: (u32, u32), bitrate_mbps: Option<f64>, octx: &mut format::context::Output, output_index: usize) -> Result<encoder::video::Video, FFmpegError> {
         let global_header = octx.format().flags().contains(format::Flags::GLOBAL_HEADER);
         let mut ost = octx.stream_mut(output_index).unwrap();
@@ -107,7 +203,8 @@ impl<'a> VideoTranscoder<'a> {
 
         encoder.set_width(size.0);
         encoder.set_height(size.1);
-        encoder.set_aspect_ratio(frame.aspect_ratio());
+        // Stabilized frames are always rendered with square pixels, the pixel aspect ratio of the input is already applied in the undistortion
+        encoder.set_aspect_ratio(Rational::new(1, 1));
         encoder.set_format(pixel_format);
         encoder.set_frame_rate(params.frame_rate);
         encoder.set_time_base(params.time_base.unwrap());
@@ -141,6 +238,12 @@ impl<'a> VideoTranscoder<'a> {
                 }
             }
         }
+        unsafe {
+            // Output surfaces which are already in the GPU memory, see `ffmpeg_cuda` and `ffmpeg_vaapi`
+            if !(*frame.as_ptr()).hw_frames_ctx.is_null() {
+                (*encoder.as_mut_ptr()).hw_frames_ctx = ffi::av_buffer_ref((*frame.as_ptr()).hw_frames_ctx);
+            }
+        }
 
         let encoder = encoder.open_with(params.options.to_owned())?;
         ost.set_parameters(&encoder);
@@ -157,12 +260,18 @@ impl<'a> VideoTranscoder<'a> {
     pub fn receive_and_process_video_frames(&mut self, size: (u32, u32), bitrate: Option<f64>, mut octx: Option<&mut format::context::Output>, ost_time_bases: &mut Vec<Rational>, start_ms: Option<f64>, end_ms: Option<f64>) -> Result<Status, FFmpegError> {
         let mut status = Status::Continue;
 
+        let deinterlacing = self.deinterlacing();
         let decoder = self.decoder.as_mut().ok_or(FFmpegError::DecoderNotFound)?;
 
-        let mut frame = frame::Video::empty();
         let mut sw_frame = &mut self.buffers.sw_frame;
+        // Also includes the frames before `start_ms`, so it's carried over to the next processed frame
+        let mut decode_time = Duration::ZERO;
 
-        while decoder.receive_frame(&mut frame).is_ok() {
+        while let Some(mut frame) = self.decoded.next(decoder, deinterlacing, self.field_order, &self.video_filter, self.end_of_stream, &mut decode_time)? {
+            if self.cancel_flag.as_ref().map_or(false, |x| x.load(Relaxed)) {
+                status = Status::Finish;
+                break;
+            }
             let time_base = self.encoder_params.time_base.unwrap();
 
             if let Some(mut ts) = frame.timestamp() {
@@ -178,9 +287,36 @@ impl<'a> VideoTranscoder<'a> {
                     let mut rate_control = RateControl::default();
                     rate_control.out_timestamp_us = ts;
 
+                    if self.cuda_pipeline && self.cuda_frames.is_none() {
+                        // Decided on the first frame, the surfaces have to be in a format which the warp kernel supports
+                        self.cuda_frames = CudaFrames::new(&frame, size);
+                        if self.cuda_frames.is_some() {
+                            self.encoder_params.pixel_format = Some(format::Pixel::CUDA);
+                            self.processing_order = ProcessingOrder::PreConversion;
+                            self.clone_frames = false;
+                        } else {
+                            log::warn!("CUDA pipeline is not available for {:?} frames, copying them to the system memory", frame.format());
+                            self.cuda_pipeline = false;
+                        }
+                    }
+                    if self.vaapi_pipeline && self.vaapi_frames.is_none() {
+                        self.vaapi_frames = VaapiFrames::new(&frame, size);
+                        if self.vaapi_frames.is_some() {
+                            self.encoder_params.pixel_format = Some(format::Pixel::VAAPI);
+                            self.processing_order = ProcessingOrder::PreConversion;
+                            self.clone_frames = false;
+                        } else {
+                            log::warn!("VAAPI pipeline is not available for {:?} frames, copying them to the system memory", frame.format());
+                            self.vaapi_pipeline = false;
+                        }
+                    }
+
                     let mut hw_formats = None;
+                    let transfer_start = Instant::now();
                     let input_frame =
-                        if unsafe { !(*frame.as_mut_ptr()).hw_frames_ctx.is_null() } {
+                        if self.cuda_pipeline || self.vaapi_pipeline {
+                            &mut frame
+                        } else if unsafe { !(*frame.as_mut_ptr()).hw_frames_ctx.is_null() } {
                             hw_formats = Some(unsafe { super::ffmpeg_hw::get_transfer_formats_from_gpu(frame.as_mut_ptr()) });
                             // log::debug!("Hardware transfer formats from GPU: {:?}", hw_formats);
                             // retrieve data from GPU to CPU
@@ -190,11 +326,16 @@ impl<'a> VideoTranscoder<'a> {
                         } else {
                             &mut frame
                         };
+                    pipeline::record(Stage::Decode, std::mem::take(&mut decode_time) + transfer_start.elapsed(), 0);
 
                     if input_frame.format() == format::Pixel::YUVJ420P {
                         input_frame.set_format(format::Pixel::YUV420P);
                         input_frame.set_color_range(util::color::Range::JPEG);
                     }
+                    if self.encoder.is_none() && !self.decode_only {
+                        log::debug!("Input color range: {:?}, color space: {:?}, primaries: {:?}, transfer: {:?}", input_frame.color_range(), input_frame.color_space(), input_frame.color_primaries(), input_frame.color_transfer_characteristic());
+                    }
+                    let output_range = if self.output_full_range(input_frame) { util::color::Range::JPEG } else { util::color::Range::MPEG };
 
                     if !self.decode_only {
                         if self.encoder_name.is_empty() {
@@ -211,9 +352,28 @@ impl<'a> VideoTranscoder<'a> {
                             self.processing_order = ProcessingOrder::PostConversion;
                         }
 
-                        if self.processing_order == ProcessingOrder::PreConversion && self.buffers.output_frame_pre.is_none()  {
+                        if let Some(cuda_frames) = &self.cuda_frames {
+                            // The encoder keeps the previous surfaces until they are encoded, so every frame gets a new one
+                            let mut out_frame = cuda_frames.get_buffer()?;
+                            unsafe { Self::copy_frame_props(out_frame.as_mut_ptr(), input_frame.as_ptr()) }
+                            out_frame.set_color_range(output_range);
+                            self.buffers.output_frame_pre = Some(out_frame);
+                        } else if let Some(vaapi_frames) = &self.vaapi_frames {
+                            let mut out_frame = vaapi_frames.get_buffer()?;
+                            unsafe { Self::copy_frame_props(out_frame.as_mut_ptr(), input_frame.as_ptr()) }
+                            out_frame.set_color_range(output_range);
+                            self.buffers.output_frame_pre = Some(out_frame);
+                        } else if self.processing_order == ProcessingOrder::PreConversion && self.buffers.output_frame_pre.is_none()  {
                             let mut out_frame = frame::Video::new(input_frame.format(), size.0, size.1);
                             unsafe { Self::copy_frame_props(out_frame.as_mut_ptr(), input_frame.as_ptr()) }
+                            if !ffmpeg_video_converter::is_rgb_or_gray(input_frame.format()) {
+                                // Range conversion of YUV planes is done in the stabilization kernel
+                                out_frame.set_color_range(output_range);
+                            } else if ffmpeg_video_converter::is_packed_rgb(input_frame.format()) {
+                                // Packed RGB encoded to YUV is scaled for the range of the output in the stabilization kernel, and tagged with it
+                                let target_format = self.encoder_params.pixel_format.unwrap_or(input_frame.format());
+                                out_frame.set_color_range(if ffmpeg_video_converter::is_rgb_or_gray(target_format) { util::color::Range::JPEG } else { output_range });
+                            }
                             self.buffers.output_frame_pre = Some(out_frame);
                         }
                     }
@@ -221,12 +381,17 @@ impl<'a> VideoTranscoder<'a> {
                     // Process frame
                     if self.decode_only || self.processing_order == ProcessingOrder::PreConversion {
                         if let Some(ref mut cb) = self.on_frame_callback {
+                            let process_start = Instant::now();
                             cb(timestamp_us, input_frame, self.buffers.output_frame_pre.as_mut(), &mut self.converter, &mut rate_control)?;
+                            pipeline::record(Stage::Process, process_start.elapsed(), 0);
                         }
                     }
 
                     // Encode output frame
                     if !self.decode_only {
+                        let encode_start = Instant::now();
+                        let mut process_time = Duration::ZERO;
+                        let mut encoded_bytes = 0;
                         let in_format = input_frame.format();
                         let mut final_frame = if self.processing_order == ProcessingOrder::PreConversion {
                             self.buffers.output_frame_pre.as_mut().unwrap()
@@ -254,6 +419,8 @@ impl<'a> VideoTranscoder<'a> {
                                 self.buffers.converted_frame = frame::Video::new(target_format, final_frame.width(), final_frame.height());
 
                                 unsafe { Self::copy_frame_props(self.buffers.converted_frame.as_mut_ptr(), final_frame.as_ptr()) }
+                                let dst_full_range = ffmpeg_video_converter::is_rgb_or_gray(target_format) || output_range == util::color::Range::JPEG;
+                                self.buffers.converted_frame.set_color_range(if dst_full_range { util::color::Range::JPEG } else { util::color::Range::MPEG });
                                 let mut conv = software::scaling::Context::get(
                                     final_frame.format(), // input
                                     final_frame.width(),
@@ -264,25 +431,9 @@ impl<'a> VideoTranscoder<'a> {
                                     software::scaling::flag::Flags::LANCZOS,
                                 )?;
 
-                                unsafe {
-                                    use std::os::raw::c_int;
-                                    // let mut dummy: [c_int; 4] = [0; 4];
-                                    let mut src_range: c_int = 0;
-                                    let dst_range: c_int = 0;
-                                    // let mut brightness: c_int = 0;
-                                    // let mut contrast: c_int = 0;
-                                    // let mut saturation: c_int = 0;
-                                    // ffi::sws_getColorspaceDetails(conv.as_mut_ptr(), &mut dummy.as_mut_ptr(), &mut src_range, &mut dummy.as_mut_ptr(), &mut dst_range, &mut brightness, &mut contrast, &mut saturation);
-                                    let coefs = ffi::sws_getCoefficients(ffi::SWS_CS_DEFAULT);
-                                    if final_frame.color_range() == util::color::Range::JPEG {
-                                        src_range |= 1;
-                                    }
-                                    // if self.buffers.converted_frame.color_range() == util::color::Range::JPEG {
-                                    //     dst_range |= 1;
-                                    // }
-                                    ffi::sws_setColorspaceDetails(conv.as_mut_ptr(), coefs, src_range, coefs, dst_range, 0, 1 << 16, 1 << 16);
-                                    //self.encoder.as_mut().ok_or(FFmpegError::EncoderNotFound)?.set_color_range(self.buffers.converted_frame.color_range());
-                                }
+                                // Packed RGB tagged as limited range is already scaled for it, so it's encoded as full range
+                                let scaled_in_kernel = ffmpeg_video_converter::is_packed_rgb(final_frame.format()) && final_frame.color_range() == util::color::Range::MPEG;
+                                ffmpeg_video_converter::set_colorspace_details(&mut conv, final_frame, dst_full_range || scaled_in_kernel);
                                 self.encoder_converter = Some(conv);
                             }
                             let conv = self.encoder_converter.as_mut().ok_or(FFmpegError::EncoderConverterEmpty)?;
@@ -299,7 +450,10 @@ impl<'a> VideoTranscoder<'a> {
                                     self.buffers.output_frame_post = Some(out_frame);
                                 }
 
+                                let process_start = Instant::now();
                                 cb(timestamp_us, final_frame, self.buffers.output_frame_post.as_mut(), &mut self.converter, &mut rate_control)?;
+                                process_time = process_start.elapsed();
+                                pipeline::record(Stage::Process, process_time, 0);
 
                                 final_frame = self.buffers.output_frame_post.as_mut().unwrap();
                             }
@@ -325,7 +479,7 @@ impl<'a> VideoTranscoder<'a> {
 
                             self.encoder = Some(result?);
 
-                            octx.write_header()?;
+                            octx.write_header_with(self.muxer_options.to_owned())?;
                             // format::context::output::dump(&octx, 0, Some(&output_path));
 
                             for (ost_index, _) in octx.streams().enumerate() {
@@ -347,10 +501,13 @@ impl<'a> VideoTranscoder<'a> {
                             let timestamp = Some(ts.rescale((1, 1000000), time_base));
                             final_frame.set_pts(timestamp);
                             final_frame.set_kind(picture::Type::None);
+                            if self.forced_keyframes_us.first().map_or(false, |x| ts >= *x) {
+                                self.forced_keyframes_us.retain(|x| *x > ts);
+                                final_frame.set_kind(picture::Type::I);
+                            }
 
                             if self.clone_frames {
-                                // TODO: ideally this should be a buffer pool per thread, but we need to figure out which thread ffmpeg actually used for that frame
-                                encoder.send_frame(&final_frame.clone())?;
+                                encoder.send_frame(&self.frame_pool.copy_of(final_frame)?)?;
                             } else {
                                 encoder.send_frame(final_frame)?;
                             }
@@ -362,6 +519,7 @@ impl<'a> VideoTranscoder<'a> {
                             let time_base = self.encoder_params.time_base.unwrap();//self.decoder.as_ref().ok_or(FFmpegError::DecoderNotFound)?.time_base();
                             let mut encoded = Packet::empty();
                             while encoder.receive_packet(&mut encoded).is_ok() {
+                                encoded_bytes += encoded.size();
                                 encoded.set_stream(self.output_index.unwrap_or_default());
                                 encoded.rescale_ts(time_base, ost_time_base);
                                 if octx.format().name().contains("image") {
@@ -371,6 +529,7 @@ impl<'a> VideoTranscoder<'a> {
                                 }
                             }
                         }
+                        pipeline::record(Stage::Encode, encode_start.elapsed().saturating_sub(process_time), encoded_bytes);
                     }
                     if end_ms.is_some() && timestamp_ms > end_ms.unwrap() {
                         status = Status::Finish;
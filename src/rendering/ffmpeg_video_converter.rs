@@ -1,9 +1,58 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 // Copyright © 2021-2022 Adrian <adrian.eddy at gmail>
 
-use ffmpeg_next::{ ffi, format, frame, software };
+use ffmpeg_next::{ ffi, format, frame, software, util::color };
 use crate::rendering::FFmpegError;
 
+// RGB and gray formats are always full range
+pub fn is_rgb_or_gray(format: format::Pixel) -> bool {
+    unsafe {
+        let desc = ffi::av_pix_fmt_desc_get(format.into());
+        !desc.is_null() && (((*desc).flags & ffi::AV_PIX_FMT_FLAG_RGB as u64) != 0 || (*desc).nb_components < 3)
+    }
+}
+// Whether the frame data uses the full (JPEG) range. Untagged YUV is limited
+pub fn is_full_range(format: format::Pixel, range: color::Range) -> bool {
+    is_rgb_or_gray(format) ||
+        matches!(format, format::Pixel::YUVJ420P | format::Pixel::YUVJ422P | format::Pixel::YUVJ444P | format::Pixel::YUVJ440P | format::Pixel::YUVJ411P) ||
+        range == color::Range::JPEG
+}
+
+// Packed RGB formats which are scaled for the range of the YUV output in the stabilization kernel
+pub fn is_packed_rgb(format: format::Pixel) -> bool {
+    matches!(format, format::Pixel::RGB24 | format::Pixel::RGBA | format::Pixel::RGB48BE | format::Pixel::RGBA64BE | format::Pixel::RGBA64LE)
+}
+
+fn color_space(src: &frame::Video) -> color::Space {
+    match src.color_space() {
+        // Untagged HD video is almost always BT.709
+        color::Space::Unspecified if src.height() >= 720 => color::Space::BT709,
+        space => space
+    }
+}
+
+// (Kr, Kg, Kb) of the YUV matrix used by `set_colorspace_details`
+pub fn luma_coefficients(src: &frame::Video) -> (f32, f32, f32) {
+    let (kr, kb) = match color_space(src) {
+        color::Space::RGB | color::Space::BT709 => (0.2126, 0.0722),
+        color::Space::FCC        => (0.30,   0.11),
+        color::Space::SMPTE240M  => (0.212,  0.087),
+        color::Space::BT2020NCL | color::Space::BT2020CL => (0.2627, 0.0593),
+        _ => (0.299, 0.114) // BT.601, the default of sws
+    };
+    (kr, 1.0 - kr - kb, kb)
+}
+
+// sws assumes limited range BT.601 YUV unless told otherwise, so full range or BT.709 sources would be converted with wrong levels and colors
+pub fn set_colorspace_details(ctx: &mut software::scaling::Context, src: &frame::Video, dst_full_range: bool) {
+    let space: ffi::AVColorSpace = color_space(src).into();
+    let src_full_range = is_full_range(src.format(), src.color_range());
+    unsafe {
+        let coefs = ffi::sws_getCoefficients(space as i32);
+        ffi::sws_setColorspaceDetails(ctx.as_mut_ptr(), coefs, src_full_range as i32, coefs, dst_full_range as i32, 0, 1 << 16, 1 << 16);
+    }
+}
+
 #[derive(Default)]
 pub struct Converter {
     pub convert_to: Option<software::scaling::Context>,
@@ -14,10 +63,17 @@ pub struct Converter {
 impl<'a> Converter {
     pub fn convert_pixel_format<F>(&mut self, frame: &mut frame::Video, out_frame: &mut frame::Video, format: format::Pixel, mut cb: F) -> Result<(), FFmpegError> where F: FnMut(&mut frame::Video, &mut frame::Video) + 'a {
         if frame.format() != format {
+            // The intermediate frames keep the range of the source, the range of the output is applied in the last conversion
+            let full_range = is_full_range(frame.format(), frame.color_range());
+            let range = if full_range { color::Range::JPEG } else { color::Range::MPEG };
+
             if self.sw_frame_converted.is_none() {
-                self.sw_frame_converted = Some(frame::Video::new(format, frame.width(), frame.height()));
+                let mut converted = frame::Video::new(format, frame.width(), frame.height());
+                converted.set_color_range(range);
+                converted.set_color_space(frame.color_space());
+                self.sw_frame_converted = Some(converted);
                 //self.convert_from = Some(software::converter((frame.width(), frame.height()), frame.format(), format)?);
-                self.convert_from = Some(software::scaling::Context::get(
+                let mut conv = software::scaling::Context::get(
                     frame.format(), // input
                     frame.width(),
                     frame.height(),
@@ -25,13 +81,17 @@ impl<'a> Converter {
                     frame.width(),
                     frame.height(),
                     software::scaling::flag::Flags::LANCZOS,
-                )?);
+                )?;
+                set_colorspace_details(&mut conv, frame, full_range);
+                self.convert_from = Some(conv);
             }
 
             if self.sw_frame_converted_out.is_none() {
-                self.sw_frame_converted_out = Some(frame::Video::new(format, out_frame.width(), out_frame.height()));
+                let mut converted_out = frame::Video::new(format, out_frame.width(), out_frame.height());
+                converted_out.set_color_range(range);
+                converted_out.set_color_space(frame.color_space());
                 //self.convert_to = Some(software::converter((out_frame.width(), out_frame.height()), format, out_frame.format())?);
-                self.convert_to = Some(software::scaling::Context::get(
+                let mut conv = software::scaling::Context::get(
                     format, // input
                     out_frame.width(),
                     out_frame.height(),
@@ -39,7 +99,10 @@ impl<'a> Converter {
                     out_frame.width(),
                     out_frame.height(),
                     software::scaling::flag::Flags::LANCZOS,
-                )?);
+                )?;
+                set_colorspace_details(&mut conv, &converted_out, is_full_range(out_frame.format(), out_frame.color_range()));
+                self.convert_to = Some(conv);
+                self.sw_frame_converted_out = Some(converted_out);
             }
 
             let sw_frame_converted = self.sw_frame_converted.as_mut().ok_or(FFmpegError::FrameEmpty)?;
@@ -58,16 +121,19 @@ impl<'a> Converter {
         Ok(())
     }
 
-    // Scale is only used for autosync
+    // Used for autosync, thumbnails and exporting single frames
     pub fn scale(&mut self, frame: &mut frame::Video, format: format::Pixel, width: u32, height: u32) -> Result<frame::Video, FFmpegError> {
         if frame.width() != width || frame.height() != height || frame.format() != format {
             if self.sw_frame_converted.is_none() {
-                self.sw_frame_converted = Some(frame::Video::new(format, width, height));
-                self.convert_to = Some(
-                    software::scaling::Context::get(
-                        frame.format(), frame.width(), frame.height(), format, width, height, software::scaling::Flags::BILINEAR,
-                    )?
-                );
+                let dst_full_range = is_rgb_or_gray(format) || is_full_range(frame.format(), frame.color_range());
+                let mut converted = frame::Video::new(format, width, height);
+                converted.set_color_range(if dst_full_range { color::Range::JPEG } else { color::Range::MPEG });
+                self.sw_frame_converted = Some(converted);
+                let mut conv = software::scaling::Context::get(
+                    frame.format(), frame.width(), frame.height(), format, width, height, software::scaling::Flags::BILINEAR,
+                )?;
+                set_colorspace_details(&mut conv, frame, dst_full_range);
+                self.convert_to = Some(conv);
             }
 
             let sw_frame_converted = self.sw_frame_converted.as_mut().ok_or(FFmpegError::FrameEmpty)?;
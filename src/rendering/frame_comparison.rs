@@ -0,0 +1,134 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2022 Adrian <adrian.eddy at gmail>
+
+// Before/after comparison output: original frame next to (or above) the stabilized one, with a label burned in.
+
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+pub enum ComparisonMode {
+    #[default]
+    None = 0,
+    SideBySide = 1,
+    Stacked = 2,
+}
+impl From<i32> for ComparisonMode {
+    fn from(v: i32) -> Self {
+        match v {
+            1 => Self::SideBySide,
+            2 => Self::Stacked,
+            _ => Self::None
+        }
+    }
+}
+
+impl ComparisonMode {
+    pub fn is_enabled(&self) -> bool { *self != Self::None }
+
+    // Full frame size for given size of a single view
+    pub fn output_size(&self, width: usize, height: usize) -> (usize, usize) {
+        match self {
+            Self::None       => (width, height),
+            Self::SideBySide => (width * 2, height),
+            Self::Stacked    => (width, height * 2),
+        }
+    }
+
+    // Size of a single view for given plane size of the full frame
+    pub fn view_size(&self, width: usize, height: usize) -> (usize, usize) {
+        match self {
+            Self::None       => (width, height),
+            Self::SideBySide => (width / 2, height),
+            Self::Stacked    => (width, height / 2),
+        }
+    }
+
+    // Top-left positions of the (original, stabilized) views
+    pub fn view_positions(&self, view_size: (usize, usize)) -> ((usize, usize), (usize, usize)) {
+        match self {
+            Self::None       => ((0, 0), (0, 0)),
+            Self::SideBySide => ((0, 0), (view_size.0, 0)),
+            Self::Stacked    => ((0, 0), (0, view_size.1)),
+        }
+    }
+}
+
+// Nearest neighbor scale of `input` into the rectangle of `output`. Works on raw bytes so it handles every plane type
+pub fn scale_into(input: &[u8], input_size: (usize, usize, usize), output: &mut [u8], output_stride: usize, rect: (usize, usize, usize, usize), bytes_per_pixel: usize) {
+    let (x, y, w, h) = rect;
+    if w == 0 || h == 0 || input_size.0 == 0 || input_size.1 == 0 { return; }
+
+    for oy in 0..h {
+        let iy = ((oy as f64 + 0.5) * input_size.1 as f64 / h as f64) as usize;
+        let in_row = iy.min(input_size.1 - 1) * input_size.2;
+        let out_row = (y + oy) * output_stride;
+        for ox in 0..w {
+            let ix = ((ox as f64 + 0.5) * input_size.0 as f64 / w as f64) as usize;
+            let src = in_row + ix.min(input_size.0 - 1) * bytes_per_pixel;
+            let dst = out_row + (x + ox) * bytes_per_pixel;
+            if src + bytes_per_pixel <= input.len() && dst + bytes_per_pixel <= output.len() {
+                output[dst..dst + bytes_per_pixel].copy_from_slice(&input[src..src + bytes_per_pixel]);
+            }
+        }
+    }
+}
+
+pub fn copy_into(input: &[u8], input_size: (usize, usize, usize), output: &mut [u8], output_stride: usize, pos: (usize, usize), bytes_per_pixel: usize) {
+    let row_bytes = input_size.0 * bytes_per_pixel;
+    for iy in 0..input_size.1 {
+        let src = iy * input_size.2;
+        let dst = (pos.1 + iy) * output_stride + pos.0 * bytes_per_pixel;
+        if src + row_bytes <= input.len() && dst + row_bytes <= output.len() {
+            output[dst..dst + row_bytes].copy_from_slice(&input[src..src + row_bytes]);
+        }
+    }
+}
+
+// 5x7 bitmap glyphs, one byte per row, 5 least significant bits used
+fn glyph(c: char) -> [u8; 7] {
+    match c {
+        'A' => [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'B' => [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+        'D' => [0x1E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x1E],
+        'E' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+        'G' => [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+        'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+        'N' => [0x11, 0x19, 0x15, 0x13, 0x11, 0x11, 0x11],
+        'O' => [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+        'S' => [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+        'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'Z' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+        _   => [0x00; 7]
+    }
+}
+
+// Draws `text` at `pos` with every font pixel scaled to `scale`x`scale` pixels. `pixel` contains raw bytes of a single pixel
+pub fn draw_label(output: &mut [u8], output_size: (usize, usize, usize), pos: (usize, usize), scale: usize, text: &str, pixel: &[u8]) {
+    let bpp = pixel.len();
+    let scale = scale.max(1);
+    for (i, c) in text.chars().enumerate() {
+        let rows = glyph(c.to_ascii_uppercase());
+        let gx = pos.0 + i * 6 * scale;
+        for (ry, row) in rows.iter().enumerate() {
+            for rx in 0..5 {
+                if row & (1 << (4 - rx)) == 0 { continue; }
+                for sy in 0..scale {
+                    for sx in 0..scale {
+                        let (x, y) = (gx + rx * scale + sx, pos.1 + ry * scale + sy);
+                        if x >= output_size.0 || y >= output_size.1 { continue; }
+                        let dst = y * output_size.2 + x * bpp;
+                        if dst + bpp <= output.len() {
+                            output[dst..dst + bpp].copy_from_slice(pixel);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Font scale and margin relative to the view height, so the labels look the same on every plane
+pub fn label_geometry(view_size: (usize, usize)) -> (usize, usize) {
+    let scale = (view_size.1 / 180).max(1);
+    (scale, scale * 4)
+}
@@ -0,0 +1,185 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2022 Adrian <adrian.eddy at gmail>
+
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicUsize, Ordering::SeqCst };
+use std::time::{ Duration, Instant };
+use parking_lot::{ Condvar, Mutex };
+use ffmpeg_next::{ ffi, frame };
+
+use super::ffmpeg_processor::FFmpegError;
+
+// Frames sent to frame-threaded encoders (ProRes, DNxHD, CineForm, PNG, EXR) have to be copies, because the encoder keeps a reference
+// until the frame is encoded. At 8K every copy is 100-200 MB, so they are allocated from a pool which is bounded by the memory budget of the render.
+// When the budget is used up, the render waits until the encoder releases one of the previous frames (backpressure)
+// instead of queueing more frames. The copies are wrapped in a buffer with a free callback, so the encoder releasing the last
+// reference returns the frame to the pool and wakes up the render.
+
+// The memory budget is a soft limit: if the encoder doesn't release any frame in this time, the frame is allocated over the limit
+// instead of waiting forever (some encoders keep all their frames until they are flushed). Every such frame raises `MemoryStats::peak_mb` over the limit
+const MAX_WAIT: Duration = Duration::from_secs(10);
+
+// One per render
+#[derive(Default)]
+pub struct MemoryBudget {
+    limit: AtomicUsize, // 0 - unlimited
+    state: Mutex<BudgetState>,
+    released: Condvar,
+}
+
+#[derive(Default)]
+struct BudgetState {
+    used: usize,
+    peak: usize,
+    waits: usize,
+    frames: usize, // Allocated frames, including the ones held by the encoder
+    free: Vec<(frame::Video, usize)>, // Frames released by the encoder, with their reserved bytes
+}
+
+#[derive(Default, Clone, Copy, Debug, serde::Serialize)]
+pub struct MemoryStats {
+    pub used_mb: f64,
+    pub peak_mb: f64,
+    pub limit_mb: f64, // 0 - unlimited
+    pub waits: usize, // How many times the render had to wait for the encoder
+}
+
+impl MemoryBudget {
+    pub fn set_limit_mb(&self, mb: usize) {
+        self.limit.store(mb * 1024 * 1024, SeqCst);
+        let mut state = self.state.lock();
+        if state.used == 0 {
+            state.peak = 0;
+            state.waits = 0;
+        }
+    }
+
+    pub fn stats(&self) -> MemoryStats {
+        const MB: f64 = 1024.0 * 1024.0;
+        let state = self.state.lock();
+        MemoryStats {
+            used_mb:  state.used as f64 / MB,
+            peak_mb:  state.peak as f64 / MB,
+            limit_mb: self.limit.load(SeqCst) as f64 / MB,
+            waits:    state.waits,
+        }
+    }
+}
+
+// Owned by the buffer sent to the encoder, returned to the pool when the buffer is freed
+struct PooledFrame {
+    budget: Arc<MemoryBudget>,
+    frame: frame::Video,
+    bytes: usize,
+}
+
+unsafe extern "C" fn release_frame(opaque: *mut std::os::raw::c_void, _data: *mut u8) {
+    let PooledFrame { budget, frame, bytes } = *Box::from_raw(opaque as *mut PooledFrame);
+    budget.state.lock().free.push((frame, bytes));
+    budget.released.notify_all();
+}
+
+#[derive(Default)]
+pub struct FramePool {
+    budget: Arc<MemoryBudget>,
+}
+
+impl FramePool {
+    pub fn new(budget: Arc<MemoryBudget>) -> Self {
+        Self { budget }
+    }
+
+    // Returns a copy of `src` which can be sent to the encoder.
+    // Reuses a frame already released by the encoder, allocates a new one if the memory budget allows it, or waits for the encoder otherwise.
+    pub fn copy_of(&mut self, src: &frame::Video) -> Result<frame::Video, FFmpegError> {
+        let bytes = Self::frame_bytes(src);
+        let deadline = Instant::now() + MAX_WAIT;
+        // Optional cap of the frames in flight, see `gyroflow_core::pipeline::PipelineConfig`
+        let frames_in_flight = gyroflow_core::pipeline::config().frames_in_flight;
+        let limit = self.budget.limit.load(SeqCst);
+
+        let mut state = self.budget.state.lock();
+        let mut waited = false;
+        let (mut dst, bytes) = loop {
+            if let Some(i) = state.free.iter().position(|(x, _)| Self::is_reusable(x, src)) {
+                break state.free.swap_remove(i);
+            }
+
+            let timed_out = Instant::now() >= deadline;
+            // Always allow at least one frame, otherwise nothing could be rendered
+            let allowed = state.frames == 0 ||
+                ((frames_in_flight == 0 || state.frames < frames_in_flight) && (limit == 0 || state.used + bytes <= limit));
+            if allowed || timed_out {
+                if timed_out {
+                    log::warn!("Encoder didn't release any frame in {:?}, exceeding the memory limit ({} frames in the pool)", MAX_WAIT, state.frames);
+                }
+                state.used += bytes;
+                state.peak = state.peak.max(state.used);
+                state.frames += 1;
+                break (frame::Video::new(src.format(), src.width(), src.height()), bytes);
+            }
+            // Released frames of a different format or size only take up the budget
+            if let Some((_, unused)) = state.free.pop() {
+                state.used -= unused;
+                state.frames -= 1;
+                continue;
+            }
+
+            if !waited {
+                state.waits += 1;
+                waited = true;
+            }
+            self.budget.released.wait_until(&mut state, deadline);
+        };
+        drop(state);
+
+        unsafe {
+            let ret = ffi::av_frame_copy(dst.as_mut_ptr(), src.as_ptr());
+            if ret < 0 { self.give_back(dst, bytes); return Err(FFmpegError::InternalError(ffmpeg_next::Error::from(ret))); }
+            let ret = ffi::av_frame_copy_props(dst.as_mut_ptr(), src.as_ptr());
+            if ret < 0 { self.give_back(dst, bytes); return Err(FFmpegError::InternalError(ffmpeg_next::Error::from(ret))); }
+
+            // Same planes and properties, but referenced through a buffer which returns `dst` to the pool when it's freed
+            let mut out = frame::Video::empty();
+            let ret = ffi::av_frame_ref(out.as_mut_ptr(), dst.as_ptr());
+            if ret < 0 { self.give_back(dst, bytes); return Err(FFmpegError::InternalError(ffmpeg_next::Error::from(ret))); }
+            let out_ptr = out.as_mut_ptr();
+            for buf in (*out_ptr).buf.iter_mut() {
+                ffi::av_buffer_unref(buf);
+            }
+            let data = (*dst.as_mut_ptr()).data[0];
+            let pooled = Box::into_raw(Box::new(PooledFrame { budget: self.budget.clone(), frame: dst, bytes }));
+            (*out_ptr).buf[0] = ffi::av_buffer_create(data, bytes, Some(release_frame), pooled as *mut _, 0);
+            if (*out_ptr).buf[0].is_null() {
+                let PooledFrame { frame, .. } = *Box::from_raw(pooled);
+                self.give_back(frame, bytes);
+                return Err(FFmpegError::InternalError(ffmpeg_next::Error::Bug));
+            }
+            Ok(out)
+        }
+    }
+
+    fn give_back(&self, frame: frame::Video, bytes: usize) {
+        self.budget.state.lock().free.push((frame, bytes));
+        self.budget.released.notify_all();
+    }
+
+    fn is_reusable(frame: &frame::Video, src: &frame::Video) -> bool {
+        frame.format() == src.format() && frame.width() == src.width() && frame.height() == src.height()
+    }
+
+    fn frame_bytes(frame: &frame::Video) -> usize {
+        unsafe { ffi::av_image_get_buffer_size(frame.format().into(), frame.width() as i32, frame.height() as i32, 1).max(0) as usize }
+    }
+}
+
+impl Drop for FramePool {
+    fn drop(&mut self) {
+        // Frames still held by the encoder are released to the budget when they are freed
+        let mut state = self.budget.state.lock();
+        let freed: usize = state.free.iter().map(|(_, bytes)| bytes).sum();
+        state.used -= freed;
+        state.frames -= state.free.len();
+        state.free.clear();
+    }
+}
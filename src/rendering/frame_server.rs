@@ -0,0 +1,300 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2022 Adrian <adrian.eddy at gmail>
+
+// Seek based decoder for sparse frame access (sync points, black bars detection, single frame export).
+// The file is opened once and kept open, every request seeks to the keyframe before the requested time and decodes only
+// the frames up to it. If the next request is shortly after the last decoded frame, the decoder just continues
+// instead of seeking, so consecutive ranges don't decode the same GOP twice.
+// Servers are shared between all sparse consumers of the same input, see `shared`. With GPU decoding enabled, the frames
+// are decoded on the GPU and copied to the system memory.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicBool, Ordering::Relaxed };
+use ffmpeg_next::{ ffi, codec, decoder, format, frame, media, Dictionary, Stream, rescale, rescale::Rescale };
+use parking_lot::Mutex;
+
+use super::ffmpeg_processor::FFmpegError;
+use super::ffmpeg_filter::FrameFilter;
+use super::ffmpeg_video_converter::Converter;
+use crate::core::InputFile;
+
+// Used until the actual keyframe interval is known from the packets
+const DEFAULT_GOP_US: i64 = 2_000_000;
+// Number of open files kept in `shared`
+const MAX_SHARED: usize = 2;
+
+lazy_static::lazy_static! {
+    static ref SERVERS: Mutex<Vec<(String, Arc<Mutex<FrameServer>>)>> = Mutex::new(Vec::new());
+}
+
+pub struct FrameServer {
+    input_context: format::context::Input,
+    decoder: decoder::Video,
+    stream_index: usize,
+    frame_duration_us: i64,
+
+    video_filter: String,
+    filter: Option<FrameFilter>,
+    converter: Converter,
+    pending: VecDeque<frame::Video>, // Decoded frames which weren't requested yet
+
+    position_us: Option<i64>, // Timestamp of the last decoded frame
+    last_keyframe_us: Option<i64>,
+    gop_us: i64,
+    eof: bool,
+}
+// Only accessed through the mutex in `shared`
+unsafe impl Send for FrameServer { }
+
+impl FrameServer {
+    pub fn open(path: &str, gpu_decoding: bool, decoder_options: Option<Dictionary>) -> Result<Self, FFmpegError> {
+        ffmpeg_next::init()?;
+        let _ = crate::rendering::init();
+
+        let mut input_context = decoder_options.map_or_else(|| format::input(&path), |dict| format::input_with_dictionary(&path, dict))?;
+
+        let (stream, codec) = unsafe {
+            let mut codec: *const ffi::AVCodec = std::ptr::null();
+            let index = ffi::av_find_best_stream(input_context.as_mut_ptr(), media::Type::Video.into(), -1i32, -1i32, &mut codec, 0);
+            if index >= 0 && !codec.is_null() {
+                Ok((Stream::wrap(&input_context, index as usize), codec))
+            } else {
+                Err(ffmpeg_next::Error::StreamNotFound)
+            }
+        }?;
+
+        let fps: f64 = stream.avg_frame_rate().into();
+        let fps = if fps > 0.0 && fps.is_finite() { fps } else { stream.rate().into() };
+        let frame_duration_us = (1_000_000.0 / if fps > 0.0 && fps.is_finite() { fps } else { 30.0 }).round() as i64;
+        let stream_index = stream.index();
+
+        let mut decoder_ctx = codec::context::Context::from_parameters(stream.parameters())?;
+        decoder_ctx.set_threading(ffmpeg_next::threading::Config { kind: ffmpeg_next::threading::Type::Frame, count: 0, safe: false });
+        if gpu_decoding {
+            let hw = super::ffmpeg_hw::init_device_for_decoding(0, codec, &mut decoder_ctx)?;
+            log::debug!("Frame server HW backend {:?} ({})", hw.1, hw.2);
+        }
+        let decoder = decoder_ctx.decoder().video()?;
+
+        Ok(Self {
+            input_context,
+            decoder,
+            stream_index,
+            frame_duration_us,
+            video_filter: String::new(),
+            filter: None,
+            converter: Converter::default(),
+            pending: VecDeque::new(),
+            position_us: None,
+            last_keyframe_us: None,
+            gop_us: DEFAULT_GOP_US,
+            eof: false,
+        })
+    }
+
+    /// Opens the input with its image sequence options
+    pub fn for_input(input_file: &InputFile, gpu_decoding: bool) -> Result<Self, FFmpegError> {
+        let mut server = Self::open(&input_file.path, gpu_decoding, Some(decoder_options(input_file)))?;
+        server.video_filter = input_file.video_filter.trim().to_string();
+        Ok(server)
+    }
+
+    pub fn frame_duration_us(&self) -> i64 { self.frame_duration_us }
+
+    /// Decodes the first frame which covers `timestamp_us` and passes it to `cb`
+    pub fn frame_at<F, R>(&mut self, timestamp_us: i64, cb: F) -> Result<R, FFmpegError>
+        where F: FnOnce(i64, &mut frame::Video, &mut Converter) -> Result<R, FFmpegError>
+    {
+        let from_us = timestamp_us - self.frame_duration_us / 2;
+        self.position(from_us)?;
+
+        let mut cb = Some(cb);
+        let mut ret = None;
+        self.decode(from_us, i64::MAX, None, |ts, frame, converter| {
+            if let Some(cb) = cb.take() {
+                ret = Some(cb(ts, frame, converter)?);
+            }
+            Ok(true)
+        })?;
+        ret.ok_or(FFmpegError::FrameEmpty)
+    }
+
+    /// Passes all frames between `from_ms` and `to_ms` to `cb`
+    pub fn decode_range<F>(&mut self, from_ms: f64, to_ms: f64, cancel_flag: &AtomicBool, mut cb: F) -> Result<(), FFmpegError>
+        where F: FnMut(i64, &mut frame::Video, &mut Converter) -> Result<(), FFmpegError>
+    {
+        let from_us = (from_ms * 1000.0).round() as i64;
+        let to_us = (to_ms * 1000.0).round() as i64;
+        self.position(from_us)?;
+        self.decode(from_us, to_us, Some(cancel_flag), |ts, frame, converter| {
+            cb(ts, frame, converter)?;
+            Ok(false)
+        })
+    }
+
+    // Continues decoding if `from_us` is within the current GOP ahead of the last frame, seeks otherwise
+    fn position(&mut self, from_us: i64) -> Result<(), FFmpegError> {
+        // Each request can scale to a different size or format
+        self.converter = Converter::default();
+
+        let ahead = self.position_us.map(|pos| from_us - pos);
+        if matches!(ahead, Some(x) if x > 0 && x <= self.gop_us) {
+            return Ok(());
+        }
+        let position = from_us.max(0).rescale((1, 1000000), rescale::TIME_BASE);
+        self.input_context.seek(position, ..position)?;
+        self.decoder.flush();
+        self.filter = None;
+        self.pending.clear();
+        self.position_us = None;
+        self.last_keyframe_us = None;
+        self.eof = false;
+        Ok(())
+    }
+
+    // `cb` returns true to stop decoding
+    fn decode<F>(&mut self, from_us: i64, to_us: i64, cancel_flag: Option<&AtomicBool>, mut cb: F) -> Result<(), FFmpegError>
+        where F: FnMut(i64, &mut frame::Video, &mut Converter) -> Result<bool, FFmpegError>
+    {
+        let mut decoded = frame::Video::empty();
+        loop {
+            while let Some(mut frame) = self.pending.pop_front() {
+                let ts = frame.timestamp().unwrap_or_default();
+                if ts < from_us {
+                    self.position_us = Some(ts);
+                    continue;
+                }
+                if ts >= to_us || cancel_flag.map_or(false, |x| x.load(Relaxed)) {
+                    // Keep it for the next request, in case it continues from here
+                    self.pending.push_front(frame);
+                    return Ok(());
+                }
+                self.position_us = Some(ts);
+                if cb(ts, &mut frame, &mut self.converter)? {
+                    return Ok(());
+                }
+            }
+
+            if self.decoder.receive_frame(&mut decoded).is_ok() {
+                let mut received = download(std::mem::replace(&mut decoded, frame::Video::empty()))?;
+                if !self.video_filter.is_empty() {
+                    if self.filter.is_none() {
+                        self.filter = Some(FrameFilter::new(&received, &[self.video_filter.clone()])?);
+                    }
+                    let mut frames = Vec::new();
+                    let filter = self.filter.as_mut().unwrap();
+                    filter.push(&mut received)?;
+                    filter.pull_all(&mut frames)?;
+                    self.pending.extend(frames);
+                } else {
+                    self.pending.push_back(received);
+                }
+                continue;
+            }
+            if self.eof {
+                // Filters can keep the last frames, output them once the decoder is drained
+                if let Some(mut filter) = self.filter.take() {
+                    let mut frames = Vec::new();
+                    filter.flush()?;
+                    filter.pull_all(&mut frames)?;
+                    self.pending.extend(frames);
+                    continue;
+                }
+                return Ok(());
+            }
+
+            let packet = self.input_context.packets().next().map(|(stream, mut packet)| {
+                packet.rescale_ts(stream.time_base(), (1, 1000000)); // rescale to microseconds
+                (stream.index(), packet)
+            });
+            match packet {
+                Some((index, packet)) if index == self.stream_index => {
+                    if packet.is_key() {
+                        if let (Some(prev), Some(pts)) = (self.last_keyframe_us, packet.pts()) {
+                            if pts > prev { self.gop_us = self.gop_us.max(pts - prev); }
+                        }
+                        self.last_keyframe_us = packet.pts();
+                    }
+                    if let Err(e) = self.decoder.send_packet(&packet) {
+                        log::error!("Decoder error {:?}", e);
+                    }
+                }
+                Some(_) => { }
+                None => {
+                    self.decoder.send_eof()?;
+                    self.eof = true;
+                }
+            }
+        }
+    }
+}
+
+// Frames decoded on the GPU are copied to the system memory, the consumers only read the pixels
+fn download(mut frame: frame::Video) -> Result<frame::Video, FFmpegError> {
+    if unsafe { (*frame.as_ptr()).hw_frames_ctx.is_null() } {
+        return Ok(frame);
+    }
+    let mut sw_frame = frame::Video::empty();
+    unsafe {
+        let err = ffi::av_hwframe_transfer_data(sw_frame.as_mut_ptr(), frame.as_mut_ptr(), 0);
+        if err < 0 { return Err(FFmpegError::FromHWTransferError(err)); }
+        let err = ffi::av_frame_copy_props(sw_frame.as_mut_ptr(), frame.as_mut_ptr());
+        if err < 0 { return Err(FFmpegError::FromHWTransferError(err)); }
+    }
+    Ok(sw_frame)
+}
+
+pub fn decoder_options(input_file: &InputFile) -> Dictionary<'static> {
+    let mut decoder_options = Dictionary::new();
+    if input_file.image_sequence_fps > 0.0 {
+        let fps = super::fps_to_rational(input_file.image_sequence_fps);
+        decoder_options.set("framerate", &format!("{}/{}", fps.numerator(), fps.denominator()));
+    }
+    if input_file.image_sequence_start > 0 {
+        decoder_options.set("start_number", &format!("{}", input_file.image_sequence_start));
+    }
+    decoder_options
+}
+
+fn shared_key(input_file: &InputFile, gpu_decoding: bool) -> String {
+    format!("{}|{}|{}|{}|{}", input_file.path, input_file.image_sequence_fps, input_file.image_sequence_start, input_file.video_filter.trim(), gpu_decoding)
+}
+
+/// Frame server for the input, reused by all sparse consumers until another file is opened. Decodes on the GPU if `GPU_DECODING` is set.
+/// Returns `None` for formats which aren't decoded with ffmpeg (.braw), these have to use `VideoProcessor`
+pub fn shared(input_file: &InputFile) -> Option<Result<Arc<Mutex<FrameServer>>, FFmpegError>> {
+    if input_file.path.to_lowercase().ends_with(".braw") {
+        return None;
+    }
+    let gpu_decoding = *super::GPU_DECODING.read();
+    let key = shared_key(input_file, gpu_decoding);
+    let mut servers = SERVERS.lock();
+    if let Some(pos) = servers.iter().position(|(k, _)| *k == key) {
+        let entry = servers.remove(pos);
+        let server = entry.1.clone();
+        servers.push(entry);
+        return Some(Ok(server));
+    }
+    Some(FrameServer::for_input(input_file, gpu_decoding).map(|server| {
+        let server = Arc::new(Mutex::new(server));
+        servers.push((key, server.clone()));
+        if servers.len() > MAX_SHARED {
+            servers.remove(0);
+        }
+        server
+    }))
+}
+
+/// Closes the shared servers of the input once a longer task is done with it, the file is opened again on the next request.
+/// Servers which are still in use are closed when their last user drops them
+pub fn close_shared(input_file: &InputFile) {
+    let key = shared_key(input_file, false);
+    let key_gpu = shared_key(input_file, true);
+    SERVERS.lock().retain(|(k, _)| *k != key && *k != key_gpu);
+}
+
+/// Closes all shared servers, eg. when the file was changed on disk
+pub fn clear_shared() {
+    SERVERS.lock().clear();
+}
@@ -0,0 +1,108 @@
+// Output muxed to a memory buffer instead of a file, used for the export preview.
+// `output_path` only selects the container, the muxer writes to a custom AVIO context which supports seeking,
+// so the containers which update the header after the last packet (mov/mp4) work the same as with a file.
+
+use std::ffi::{ c_void, CString };
+use std::os::raw::c_int;
+use std::sync::Arc;
+use parking_lot::Mutex;
+use ffmpeg_next::{ ffi, format, Error };
+use ffmpeg_next::error::{ EINVAL, ENOMEM };
+
+pub type MemoryBuffer = Arc<Mutex<Vec<u8>>>;
+
+const IO_BUFFER_SIZE: usize = 64 * 1024;
+
+#[derive(Default)]
+struct Writer {
+    data: Vec<u8>,
+    position: usize
+}
+
+// Allocates the output context for the container guessed from `output_path`, without opening the file
+pub fn alloc_output(output_path: &str) -> Result<format::context::Output, Error> {
+    let path = CString::new(output_path).map_err(|_| Error::InvalidData)?;
+    let mut ptr = std::ptr::null_mut();
+    unsafe {
+        match ffi::avformat_alloc_output_context2(&mut ptr, std::ptr::null_mut(), std::ptr::null(), path.as_ptr()) {
+            e if e < 0 => Err(Error::from(e)),
+            _ if ptr.is_null() => Err(Error::MuxerNotFound),
+            _ => Ok(format::context::Output::wrap(ptr))
+        }
+    }
+}
+
+// Custom IO of the output context. It has to be dropped before the context, because `Output` would close it as a file.
+// The written data is moved to the buffer when it's dropped
+pub struct MemoryIo {
+    octx: *mut ffi::AVFormatContext,
+    avio: *mut ffi::AVIOContext,
+    writer: *mut Writer,
+    buffer: MemoryBuffer
+}
+
+impl MemoryIo {
+    pub fn attach(octx: &mut format::context::Output, buffer: MemoryBuffer) -> Result<Self, Error> {
+        unsafe {
+            let io_buffer = ffi::av_malloc(IO_BUFFER_SIZE) as *mut u8;
+            if io_buffer.is_null() {
+                return Err(Error::Other { errno: ENOMEM });
+            }
+            let writer = Box::into_raw(Box::new(Writer::default()));
+            let avio = ffi::avio_alloc_context(io_buffer, IO_BUFFER_SIZE as c_int, 1, writer as *mut c_void, None, Some(write_packet), Some(seek));
+            if avio.is_null() {
+                ffi::av_free(io_buffer as *mut c_void);
+                drop(Box::from_raw(writer));
+                return Err(Error::Other { errno: ENOMEM });
+            }
+            let ptr = octx.as_mut_ptr();
+            (*ptr).pb = avio;
+            (*ptr).flags |= ffi::AVFMT_FLAG_CUSTOM_IO as c_int;
+            Ok(Self { octx: ptr, avio, writer, buffer })
+        }
+    }
+}
+
+impl Drop for MemoryIo {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::avio_flush(self.avio);
+            (*self.octx).pb = std::ptr::null_mut();
+            ffi::av_freep(&mut (*self.avio).buffer as *mut *mut u8 as *mut c_void);
+            ffi::avio_context_free(&mut self.avio);
+            let writer = Box::from_raw(self.writer);
+            *self.buffer.lock() = writer.data;
+        }
+    }
+}
+
+unsafe extern "C" fn write_packet(opaque: *mut c_void, buf: *mut u8, size: c_int) -> c_int {
+    let writer = &mut *(opaque as *mut Writer);
+    let data = std::slice::from_raw_parts(buf, size.max(0) as usize);
+    let end = writer.position + data.len();
+    if writer.data.len() < end {
+        writer.data.resize(end, 0);
+    }
+    writer.data[writer.position..end].copy_from_slice(data);
+    writer.position = end;
+    size
+}
+
+unsafe extern "C" fn seek(opaque: *mut c_void, offset: i64, whence: c_int) -> i64 {
+    let writer = &mut *(opaque as *mut Writer);
+    let whence = whence & !(ffi::AVSEEK_FORCE as c_int);
+    if whence == ffi::AVSEEK_SIZE as c_int {
+        return writer.data.len() as i64;
+    }
+    let position = match whence {
+        0 => offset, // SEEK_SET
+        1 => writer.position as i64 + offset, // SEEK_CUR
+        2 => writer.data.len() as i64 + offset, // SEEK_END
+        _ => return ffi::AVERROR(EINVAL) as i64
+    };
+    if position < 0 {
+        return ffi::AVERROR(EINVAL) as i64;
+    }
+    writer.position = position as usize;
+    position
+}
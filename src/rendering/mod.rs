@@ -4,17 +4,32 @@
 mod ffmpeg_audio;
 mod ffmpeg_video;
 mod ffmpeg_video_converter;
+pub mod ffmpeg_filter;
+pub mod frame_pool;
+pub mod frame_server;
 mod audio_resampler;
+mod frame_comparison;
+mod cropped_output;
+mod timecode;
+mod chapter_split;
+pub mod memory_output;
+mod ffmpeg_cuda;
+mod ffmpeg_vaapi;
 pub mod ffmpeg_processor;
 pub mod ffmpeg_hw;
 pub mod render_queue;
+pub mod render_log;
+pub mod preflight;
 pub mod mdk_processor;
 pub mod video_processor;
 
 pub use self::video_processor::VideoProcessor;
-pub use self::ffmpeg_processor::{ FfmpegProcessor, FFmpegError };
+pub use self::ffmpeg_video_converter::Converter;
+pub use self::ffmpeg_processor::{ FfmpegProcessor, FFmpegError, PartialOutput };
 use render_queue::RenderOptions;
+use frame_comparison::ComparisonMode;
 use crate::core::{ StabilizationManager, stabilization::* };
+use crate::core::duplicate_frames::{ DuplicateDetector, DuplicateFrameMode, FrameHash };
 use ffmpeg_next::{ format::Pixel, frame::Video, codec, Error, ffi };
 use std::ffi::c_void;
 use std::os::raw::c_char;
@@ -29,13 +44,15 @@ enum GpuType {
 lazy_static::lazy_static! {
     static ref GPU_TYPE: RwLock<GpuType> = RwLock::new(GpuType::Unknown);
     pub static ref GPU_DECODING: RwLock<bool> = RwLock::new(true);
+    static ref GPU_NAME: RwLock<String> = RwLock::new(String::new());
 }
 pub fn set_gpu_type_from_name(name: &str) {
+    *GPU_NAME.write() = name.to_string();
     let name = name.to_ascii_lowercase();
          if name.contains("nvidia") { *GPU_TYPE.write() = GpuType::NVIDIA; }
     else if name.contains("amd") || name.contains("advanced micro devices") { *GPU_TYPE.write() = GpuType::AMD; }
     else if name.contains("intel") && !name.contains("intel(r) core(tm)") { *GPU_TYPE.write() = GpuType::Intel; }
-    else if name.contains("Apple M") { *GPU_TYPE.write() = GpuType::AppleSilicon; }
+    else if name.contains("apple m") || (cfg!(target_os = "ios") && name.contains("apple")) { *GPU_TYPE.write() = GpuType::AppleSilicon; }
     else {
         log::warn!("Unknown GPU {}", name);
     }
@@ -58,6 +75,9 @@ pub fn get_possible_encoders(codec: &str, use_gpu: bool) -> Vec<(&'static str, b
     if codec.contains("PNG") || codec.contains("png") { return vec![("png", false)]; }
     if codec.contains("EXR") || codec.contains("exr") { return vec![("exr", false)]; }
 
+    // iOS builds don't include the GPL encoders, so VideoToolbox is the only option for H.264/H.265
+    let use_gpu = use_gpu || cfg!(target_os = "ios");
+
     let mut encoders = if use_gpu {
         match codec {
             "H.264/AVC" => vec![
@@ -100,6 +120,7 @@ pub fn get_possible_encoders(codec: &str, use_gpu: bool) -> Vec<(&'static str, b
                 ("prores_ks", false)
             ],
             "DNxHD"  => vec![("dnxhd", false)],
+            "CineForm" => vec![("cfhd", false)],
             _        => vec![]
         }
     } else {
@@ -108,6 +129,7 @@ pub fn get_possible_encoders(codec: &str, use_gpu: bool) -> Vec<(&'static str, b
             "H.265/HEVC" => vec![("libx265", false)],
             "ProRes"     => vec![("prores_ks", false)],
             "DNxHD"      => vec![("dnxhd", false)],
+            "CineForm"   => vec![("cfhd", false)],
             _            => vec![]
         }
     };
@@ -126,12 +148,381 @@ pub fn get_possible_encoders(codec: &str, use_gpu: bool) -> Vec<(&'static str, b
     encoders
 }
 
-pub fn render<T: PixelType, F, F2>(stab: Arc<StabilizationManager<T>>, progress: F, input_file: &gyroflow_core::InputFile, render_options: &RenderOptions, gpu_decoder_index: i32, cancel_flag: Arc<AtomicBool>, pause_flag: Arc<AtomicBool>, encoder_initialized: F2) -> Result<(), FFmpegError>
+// Encoders and decoders available in the linked ffmpeg build, and the hardware acceleration it can use
+pub fn get_capabilities() -> serde_json::Value {
+    let gpu_type = *GPU_TYPE.read();
+    let encoders: serde_json::Map<String, serde_json::Value> = ["H.264/AVC", "H.265/HEVC", "ProRes", "DNxHD", "CineForm", "PNG", "EXR"].iter().map(|codec| {
+        let available = get_possible_encoders(codec, true).into_iter()
+            .filter(|(name, _)| ffmpeg_next::encoder::find_by_name(name).is_some())
+            .map(|(name, is_gpu)| serde_json::json!({ "name": name, "gpu": is_gpu }))
+            .collect::<Vec<_>>();
+        (codec.to_string(), serde_json::Value::Array(available))
+    }).collect();
+    let decoders: Vec<&str> = ["h264", "hevc", "av1", "vp9", "prores", "dnxhd", "mjpeg", "png", "exr", "cfhd"].into_iter()
+        .filter(|name| ffmpeg_next::decoder::find_by_name(name).is_some())
+        .collect();
+
+    serde_json::json!({
+        "gpu_name": *GPU_NAME.read(),
+        "gpu_type": format!("{:?}", gpu_type),
+        "gpu_decoding": *GPU_DECODING.read(),
+        "hw_device_types": ffmpeg_hw::supported_gpu_backends(),
+        "encoders": encoders,
+        "prores_profiles": PRORES_PROFILES.iter().map(|(name, _, alpha)| serde_json::json!({ "name": name, "alpha": alpha })).collect::<Vec<_>>(),
+        "decoders": decoders,
+    })
+}
+
+// ProRes profiles in the order of the `profile` option of prores_ks and prores_videotoolbox,
+// with the names accepted in the codec options (lowercase, without spaces), and whether the profile can carry alpha
+pub const PRORES_PROFILES: [(&str, &[&str], bool); 6] = [
+    ("Proxy",    &["proxy", "422proxy"],      false),
+    ("LT",       &["lt", "422lt"],            false),
+    ("Standard", &["standard", "422"],        false),
+    ("HQ",       &["hq", "422hq"],            false),
+    ("4444",     &["4444"],                   true),
+    ("4444XQ",   &["4444xq", "xq"],           true),
+];
+
+/// Index in `PRORES_PROFILES` from the codec options, eg. "HQ", "422 HQ", "ProRes 4444 XQ"
+pub fn prores_profile(codec_options: &str) -> Option<usize> {
+    let name = codec_options.chars().filter(|c| !c.is_whitespace() && *c != '-' && *c != '_').collect::<String>().to_ascii_lowercase();
+    let name = name.strip_prefix("prores").unwrap_or(&name);
+    PRORES_PROFILES.iter().position(|(_, names, _)| names.contains(&name))
+}
+
+// The alpha channel is only encoded by prores_ks, so the hardware encoder isn't used for 4444 with a transparent background
+fn needs_cpu_encoder(render_options: &RenderOptions, has_alpha: bool) -> bool {
+    has_alpha && render_options.codec == "ProRes" && prores_profile(&render_options.codec_options).map_or(false, |x| PRORES_PROFILES[x].2)
+}
+
+// Codec specific encoder parameters, shared by the render and the pre-flight check. Returns whether the frames have to be cloned before encoding
+fn configure_encoder(encoder_name: &str, render_options: &RenderOptions, has_alpha: bool, params: &mut ffmpeg_video::EncoderParams) -> bool {
+    let mut clone_frames = false;
+    let mut pixel_format = render_options.pixel_format.clone();
+
+    match encoder_name {
+        "prores_ks" | "prores_videotoolbox" => {
+            match prores_profile(&render_options.codec_options) {
+                Some(profile) => {
+                    params.options.set("profile", &format!("{}", profile));
+                    // 4444 keeps the alpha of the stabilized frames (transparent background), the other profiles are 4:2:2 without alpha
+                    let with_alpha = PRORES_PROFILES[profile].2;
+                    if encoder_name == "prores_ks" {
+                        params.pixel_format = Some(match (with_alpha, has_alpha) {
+                            (true, true)  => Pixel::YUVA444P10LE,
+                            (true, false) => Pixel::YUV444P10LE,
+                            _             => Pixel::YUV422P10LE
+                        });
+                        if with_alpha && has_alpha {
+                            params.options.set("alpha_bits", "16");
+                        }
+                    }
+                    if has_alpha && !with_alpha {
+                        log::warn!("ProRes {} doesn't support alpha, the transparency will be lost", PRORES_PROFILES[profile].0);
+                    }
+                },
+                None => {
+                    if !render_options.codec_options.is_empty() {
+                        log::warn!("Unknown ProRes profile: {}", render_options.codec_options);
+                    }
+                }
+            }
+            clone_frames = encoder_name == "prores_ks";
+        }
+        "dnxhd" => {
+            let profiles = ["DNxHD", "DNxHR LB", "DNxHR SQ", "DNxHR HQ", "DNxHR HQX", "DNxHR 444"];
+            let pix_fmts = [Pixel::YUV422P, Pixel::YUV422P, Pixel::YUV422P, Pixel::YUV422P, Pixel::YUV422P10LE, Pixel::YUV444P10LE];
+            if let Some(profile) = profiles.iter().position(|&x| x == render_options.codec_options) {
+                params.options.set("profile", &format!("{}", profile));
+                params.pixel_format = Some(pix_fmts[profile]);
+            }
+            clone_frames = true;
+        }
+        "cfhd" => {
+            // `quality` of the encoder, from the smallest files
+            let profiles = [("Low", "low"), ("Medium", "medium"), ("High", "high"), ("Film Scan", "film1"), ("Film Scan 2", "film2"), ("Film Scan 3", "film3")];
+            if let Some((_, quality)) = profiles.iter().find(|(name, _)| *name == render_options.codec_options) {
+                params.options.set("quality", quality);
+            }
+            // 12-bit RGB with a transparent background, the alpha channel is kept
+            params.pixel_format = Some(if has_alpha { Pixel::GBRAP12LE } else { Pixel::YUV422P10LE });
+            clone_frames = true;
+        }
+        "png" => {
+            if render_options.codec_options.contains("16-bit") {
+                params.pixel_format = Some(if has_alpha { Pixel::RGBA64BE } else { Pixel::RGB48BE });
+            } else {
+                params.pixel_format = Some(if has_alpha { Pixel::RGBA } else { Pixel::RGB24 });
+            }
+            clone_frames = true;
+        }
+        "exr" => {
+            clone_frames = true;
+            params.options.set("compression", "1"); // RLE compression
+            params.options.set("gamma", "1.0");
+            params.pixel_format = Some(if has_alpha { Pixel::GBRAPF32LE } else { Pixel::GBRPF32LE });
+            /*Decoder options:
+                -layer             <string>     .D.V....... Set the decoding layer (default "")
+                -part              <int>        .D.V....... Set the decoding part (from 0 to INT_MAX) (default 0)
+                -gamma             <float>      .D.V....... Set the float gamma value when decoding (from 0.001 to FLT_MAX) (default 1)
+                -apply_trc         <int>        .D.V....... color transfer characteristics to apply to EXR linear input (from 1 to 18) (default gamma)
+                    bt709           1            .D.V....... BT.709
+                    gamma           2            .D.V....... gamma
+                    gamma22         4            .D.V....... BT.470 M
+                    gamma28         5            .D.V....... BT.470 BG
+                    smpte170m       6            .D.V....... SMPTE 170 M
+                    smpte240m       7            .D.V....... SMPTE 240 M
+                    linear          8            .D.V....... Linear
+                    log             9            .D.V....... Log
+                    log_sqrt        10           .D.V....... Log square root
+                    iec61966_2_4    11           .D.V....... IEC 61966-2-4
+                    bt1361          12           .D.V....... BT.1361
+                    iec61966_2_1    13           .D.V....... IEC 61966-2-1
+                    bt2020_10bit    14           .D.V....... BT.2020 - 10 bit
+                    bt2020_12bit    15           .D.V....... BT.2020 - 12 bit
+                    smpte2084       16           .D.V....... SMPTE ST 2084
+                    smpte428_1      17           .D.V....... SMPTE ST 428-1
+            */
+        }
+        _ => { }
+    }
+
+    params.options.set("allow_sw", "1");
+    params.options.set("realtime", "0");
+
+    for (key, value) in render_options.get_encoder_options_dict().iter() {
+        log::info!("Setting encoder option {}: {}", key, value);
+        if key == "pix_fmt" {
+            pixel_format = value.to_string();
+            continue;
+        }
+        params.options.set(key, value);
+    }
+
+    if !pixel_format.is_empty() {
+        use std::str::FromStr;
+        match Pixel::from_str(&pixel_format.to_ascii_lowercase()) {
+            Ok(px) => { params.pixel_format = Some(px); },
+            Err(e) => { ::log::debug!("Unknown requested pixel format: {}, {:?}", pixel_format, e); }
+        }
+    }
+
+    clone_frames
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct EncoderProbeIssue {
+    pub feature: &'static str, // "encoder", "resolution", "pixel_format" or "frame_rate"
+    pub encoder: String,
+    pub requested: String,
+    pub message: String,
+}
+
+// Opens the selected encoder with the requested output parameters before the render starts, so unsupported combinations
+// (eg. 8K 10-bit 4:2:2 on a hardware encoder) are reported up front instead of failing after decoding the first frames
+pub fn probe_encoder(input_file: &gyroflow_core::InputFile, render_options: &RenderOptions, fps: f64, has_alpha: bool) -> Result<(), FFmpegError> {
+    ffmpeg_next::init()?;
+    let _ = init();
+    let _input_access = crate::core::filesystem::ScopedAccess::new(&input_file.path);
+
+    let encoder = ffmpeg_hw::find_working_encoder(&get_possible_encoders(&render_options.codec, render_options.use_gpu && !needs_cpu_encoder(render_options, has_alpha)));
+    let issue = |feature: &'static str, requested: String, message: String| EncoderProbeIssue { feature, encoder: encoder.0.to_string(), requested, message };
+
+    if encoder.0.is_empty() {
+        return Err(FFmpegError::EncoderUnsupported(vec![issue("encoder", render_options.codec.clone(), format!("No encoder available for {}.", render_options.codec))]));
+    }
+    // These need a hardware frames context to open, which is only created with the first decoded frame
+    if encoder.0.contains("vaapi") || encoder.0.contains("v4l2m2m") { return Ok(()); }
+
+    let codec = ffmpeg_next::encoder::find_by_name(encoder.0).ok_or(FFmpegError::EncoderNotFound)?;
+    let supported_formats = unsafe { ffmpeg_hw::pix_formats_to_vec((*codec.as_ptr()).pix_fmts) };
+
+    let mut params = ffmpeg_video::EncoderParams::default();
+    configure_encoder(encoder.0, render_options, has_alpha, &mut params);
+
+    let size = (render_options.output_width as u32, render_options.output_height as u32);
+    if size.0 == 0 || size.1 == 0 { return Ok(()); }
+
+    let pixel_format = match params.pixel_format {
+        Some(px) => {
+            if !supported_formats.is_empty() && !supported_formats.contains(&px) {
+                return Err(FFmpegError::PixelFormatNotSupported((px, supported_formats)));
+            }
+            px
+        },
+        None => {
+            // Same as the render: the decoded format is used when it's supported by the encoder
+            let source_format = ffmpeg_next::format::input(&input_file.path).ok().and_then(|ictx| {
+                let stream = ictx.streams().best(ffmpeg_next::media::Type::Video)?;
+                let decoder = codec::context::Context::from_parameters(stream.parameters()).ok()?.decoder().video().ok()?;
+                Some(decoder.format())
+            });
+            match source_format {
+                Some(px) => ffmpeg_hw::find_best_matching_codec(px, &supported_formats),
+                None => supported_formats.first().copied().unwrap_or(Pixel::None)
+            }
+        }
+    };
+    if pixel_format == Pixel::None { return Ok(()); }
+
+    let mut issues = Vec::new();
+
+    let (chroma_w, chroma_h) = unsafe {
+        let desc = ffi::av_pix_fmt_desc_get(pixel_format.into());
+        if desc.is_null() { (0, 0) } else { ((*desc).log2_chroma_w as u32, (*desc).log2_chroma_h as u32) }
+    };
+    if size.0 % (1 << chroma_w) != 0 || size.1 % (1 << chroma_h) != 0 {
+        issues.push(issue("resolution", format!("{}x{}", size.0, size.1), format!("Output size {}x{} must be a multiple of {}x{} for pixel format {:?}.", size.0, size.1, 1 << chroma_w, 1 << chroma_h, pixel_format)));
+    }
+    // The CineForm encoder fails to open with other widths
+    if encoder.0 == "cfhd" && size.0 % 16 != 0 {
+        issues.push(issue("resolution", format!("{}x{}", size.0, size.1), format!("Output width {} must be a multiple of 16 for CineForm.", size.0)));
+    }
+
+    let output_fps = if render_options.output_fps > 0.0 { render_options.output_fps } else { fps };
+    let bitrate = (render_options.bitrate * 1024.0 * 1024.0) as usize;
+
+    let try_open = |size: (u32, u32), pixel_format: Pixel, fps: f64| -> Result<(), Error> {
+        let ctx_ptr = unsafe { ffi::avcodec_alloc_context3(codec.as_ptr()) };
+        let context = unsafe { codec::context::Context::wrap(ctx_ptr, None) };
+        let mut enc = context.encoder().video()?;
+        let rate = fps_to_rational(fps.max(1.0));
+        enc.set_width(size.0);
+        enc.set_height(size.1);
+        enc.set_format(pixel_format);
+        enc.set_frame_rate(Some(rate));
+        enc.set_time_base(rate.invert());
+        if bitrate > 0 {
+            enc.set_bit_rate(bitrate);
+            enc.set_max_bit_rate(bitrate);
+        }
+        enc.open_with(params.options.to_owned()).map(|_| ())
+    };
+
+    if issues.is_empty() {
+        if let Err(e) = try_open(size, pixel_format, output_fps) {
+            log::warn!("Encoder {} failed to open with {}x{} {:?} {:.3}fps: {:?}", encoder.0, size.0, size.1, pixel_format, output_fps, e);
+            // Find out which of the parameters is the problem by relaxing them one at a time
+            let is_8bit = matches!(pixel_format, Pixel::YUV420P | Pixel::NV12);
+            let fallback_format = [Pixel::YUV420P, Pixel::NV12].into_iter().find(|x| supported_formats.contains(x));
+            let small_size = (size.0.min(1920) & !1, size.1.min(1080) & !1);
+
+            if (output_fps - 30.0).abs() > 0.01 && try_open(size, pixel_format, 30.0).is_ok() {
+                issues.push(issue("frame_rate", format!("{:.3}", output_fps), format!("Frame rate {:.3} fps is not supported by this encoder.", output_fps)));
+            } else if small_size != size && try_open(small_size, pixel_format, output_fps).is_ok() {
+                issues.push(issue("resolution", format!("{}x{}", size.0, size.1), format!("Resolution {}x{} is not supported by this encoder.", size.0, size.1)));
+            } else if !is_8bit && fallback_format.map(|px| try_open(size, px, output_fps).is_ok()).unwrap_or_default() {
+                issues.push(issue("pixel_format", format!("{:?}", pixel_format), format!("Pixel format {:?} (bit depth or chroma subsampling) is not supported by this encoder at {}x{}.", pixel_format, size.0, size.1)));
+            } else {
+                issues.push(issue("encoder", format!("{}x{} {:?} {:.3}fps", size.0, size.1, pixel_format, output_fps), format!("Unable to initialize the encoder: {}", e)));
+            }
+        }
+    }
+
+    if issues.is_empty() {
+        Ok(())
+    } else {
+        Err(FFmpegError::EncoderUnsupported(issues))
+    }
+}
+
+// Decodes the frame at `timestamp_us` and returns it stabilized as RGBA8 (width, height, stride, pixels).
+// Works on a copy of the stabilizer, so it doesn't interfere with the preview. Output size of 0 means the current output size
+pub fn get_stabilized_frame(stab: &StabilizationManager<RGBA8>, timestamp_us: i64, output_size: (usize, usize)) -> Result<(usize, usize, usize, Vec<u8>), FFmpegError> {
+    stabilized_frame::<RGBA8>(stab, timestamp_us, output_size, Pixel::RGBA, 255.0)
+}
+// Same as `get_stabilized_frame`, but RGBA with 16 bits per channel in native endianness
+pub fn get_stabilized_frame_16bit(stab: &StabilizationManager<RGBA8>, timestamp_us: i64, output_size: (usize, usize)) -> Result<(usize, usize, usize, Vec<u8>), FFmpegError> {
+    #[cfg(target_endian = "little")]
+    let format = Pixel::RGBA64LE;
+    #[cfg(target_endian = "big")]
+    let format = Pixel::RGBA64BE;
+    stabilized_frame::<RGBA16>(stab, timestamp_us, output_size, format, 65535.0)
+}
+fn stabilized_frame<T: PixelType>(stab: &StabilizationManager<RGBA8>, timestamp_us: i64, output_size: (usize, usize), format: Pixel, max_val: f32) -> Result<(usize, usize, usize, Vec<u8>), FFmpegError> {
+    let stab = stab.get_cloned();
+    let (video_size, current_output_size, fps) = {
+        let params = stab.params.read();
+        (params.video_size, params.output_size, params.fps)
+    };
+    let output_size = if output_size.0 > 0 && output_size.1 > 0 { output_size } else { current_output_size };
+    if video_size.0 == 0 || video_size.1 == 0 || output_size.0 == 0 || output_size.1 == 0 {
+        return Err(FFmpegError::FrameEmpty);
+    }
+    stab.set_render_params(video_size, output_size);
+
+    let input_file = stab.input_file.read().clone();
+    let _input_access = crate::core::filesystem::ScopedAccess::new(&input_file.path);
+
+    let to_rgba = |frame_ts: i64, input_frame: &mut Video, converter: &mut ffmpeg_video_converter::Converter| -> Result<(i64, usize, Vec<u8>), FFmpegError> {
+        let rgba = converter.scale(input_frame, format, video_size.0 as u32, video_size.1 as u32)?;
+        let stride = rgba.stride(0);
+        Ok((frame_ts, stride, rgba.data(0)[..stride * video_size.1].to_vec()))
+    };
+
+    let (frame_ts, stride, mut pixels) = if let Some(server) = frame_server::shared(&input_file) {
+        server?.lock().frame_at(timestamp_us, to_rgba)?
+    } else {
+        let frame_duration_us = 1_000_000.0 / fps.max(1.0);
+        let cancel_flag = Arc::new(AtomicBool::new(false));
+        let decoded = std::rc::Rc::new(std::cell::RefCell::new(None));
+
+        let mut proc = VideoProcessor::from_file(&input_file.path, false, 0, None)?;
+        proc.set_video_filter(&input_file.video_filter);
+        let (decoded2, cancel_flag2) = (decoded.clone(), cancel_flag.clone());
+        proc.on_frame(move |frame_ts, input_frame, _output_frame, converter, _rate_control| {
+            // The decoder starts at the previous keyframe, take the first frame which covers the requested timestamp
+            if decoded2.borrow().is_none() && frame_ts as f64 >= timestamp_us as f64 - frame_duration_us / 2.0 {
+                *decoded2.borrow_mut() = Some(to_rgba(frame_ts, input_frame, converter)?);
+                cancel_flag2.store(true, std::sync::atomic::Ordering::SeqCst);
+            }
+            Ok(())
+        });
+        let from_ms = (timestamp_us as f64 - frame_duration_us).max(0.0) / 1000.0;
+        proc.start_decoder_only(vec![(from_ms, from_ms + frame_duration_us * 3.0 / 1000.0)], cancel_flag)?;
+
+        let decoded = decoded.borrow_mut().take();
+        decoded.ok_or(FFmpegError::FrameEmpty)?
+    };
+
+    let out_stride = output_size.0 * T::COUNT * T::SCALAR_BYTES;
+    let mut out_pixels = vec![0u8; out_stride * output_size.1];
+
+    let mut plane = Stabilization::<T>::default();
+    plane.set_task(gyroflow_core::gpu::context_manager::GpuTask::Export);
+    plane.interpolation = Interpolation::Lanczos4;
+    plane.set_kernel_precision(stab.params.read().kernel_precision);
+    let bg = stab.params.read().background;
+    plane.init_size(T::from_rgb_color(bg, &[], max_val) * (max_val / 255.0), (video_size.0, video_size.1, stride), (output_size.0, output_size.1, out_stride));
+    plane.set_compute_params(ComputeParams::from_manager(&stab, false));
+    plane.ensure_stab_data_at_timestamp(frame_ts);
+
+    use gyroflow_core::gpu::{ BufferDescription, BufferSource };
+    plane.process_pixels(frame_ts, &mut BufferDescription {
+        input_size: (video_size.0, video_size.1, stride),
+        output_size: (output_size.0, output_size.1, out_stride),
+        buffers: BufferSource::Cpu {
+            input: &mut pixels,
+            output: &mut out_pixels
+        },
+        input_rect: None, output_rect: None
+    });
+
+    Ok((output_size.0, output_size.1, out_stride, out_pixels))
+}
+
+pub fn render<T: PixelType, F, F2, F3>(stab: Arc<StabilizationManager<T>>, progress: F, input_file: &gyroflow_core::InputFile, render_options: &RenderOptions, gpu_decoder_index: i32, cancel_flag: Arc<AtomicBool>, pause_flag: Arc<AtomicBool>, memory_budget: Arc<frame_pool::MemoryBudget>, encoder_initialized: F2, frame_hashed: F3) -> Result<(), FFmpegError>
     where F: Fn((f64, usize, usize, bool)) + Send + Sync + Clone,
-          F2: Fn(String) + Send + Sync + Clone
+          F2: Fn(String) + Send + Sync + Clone,
+          F3: Fn((usize, String)) + Send + Sync + Clone
 {
     log::debug!("ffmpeg_hw::supported_gpu_backends: {:?}", ffmpeg_hw::supported_gpu_backends());
 
+    // ffmpeg opens the files by path, so keep the security-scoped access for the whole render (iOS)
+    let _input_access = crate::core::filesystem::ScopedAccess::new(&input_file.path);
+    let _output_access = crate::core::filesystem::ScopedAccess::for_parent(&render_options.output_path);
+
     let params = stab.params.read();
     let trim_ratio = if !render_options.pad_with_black && !render_options.preserve_other_tracks {
         params.trim_end - params.trim_start
@@ -140,9 +531,7 @@ pub fn render<T: PixelType, F, F2>(stab: Arc<StabilizationManager<T>>, progress:
     };
     let total_frame_count = params.frame_count;
     let fps_scale = params.fps_scale;
-    let has_alpha = params.background[3] < 255.0;
-
-    let mut pixel_format = render_options.pixel_format.clone();
+    let mut has_alpha = params.background[3] < 255.0;
 
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     let _prevent_system_sleep = keep_awake::inhibit_system("Gyroflow", "Rendering video");
@@ -163,6 +552,9 @@ pub fn render<T: PixelType, F, F2>(stab: Arc<StabilizationManager<T>>, progress:
 
     let (trim_start, trim_end) = (params.trim_start, params.trim_end);
 
+    let comparison = ComparisonMode::from(render_options.frame_comparison);
+    let verify_frames: std::collections::HashSet<usize> = render_options.verify_frames.iter().copied().collect();
+
     drop(params);
 
     let mut decoder_options = ffmpeg_next::Dictionary::new();
@@ -175,10 +567,12 @@ pub fn render<T: PixelType, F, F2>(stab: Arc<StabilizationManager<T>>, progress:
     }
 
     let gpu_decoding = *GPU_DECODING.read();
-    let mut proc = FfmpegProcessor::from_file(&input_file.path, gpu_decoding && gpu_decoder_index >= 0, gpu_decoder_index as usize, Some(decoder_options))?;
+    let mut proc = FfmpegProcessor::from_file_with_tuning(&input_file.path, gpu_decoding && gpu_decoder_index >= 0, gpu_decoder_index as usize, Some(decoder_options), render_options.decoder_tuning())?;
 
     log::debug!("proc.gpu_device: {:?}", &proc.gpu_device);
-    let encoder = ffmpeg_hw::find_working_encoder(&get_possible_encoders(&render_options.codec, render_options.use_gpu));
+    // Alpha of the input (eg. ProRes 4444 or PNG) is kept as well, not only a transparent background
+    has_alpha |= proc.video.decoder.as_ref().map_or(false, |x| pixel_format_has_alpha(x.format()));
+    let encoder = ffmpeg_hw::find_working_encoder(&get_possible_encoders(&render_options.codec, render_options.use_gpu && !needs_cpu_encoder(render_options, has_alpha)));
     proc.video_codec = Some(encoder.0.to_owned());
     proc.video.gpu_encoding = encoder.1;
     proc.video.encoder_params.hw_device_type = encoder.2;
@@ -191,103 +585,82 @@ pub fn render<T: PixelType, F, F2>(stab: Arc<StabilizationManager<T>>, progress:
         if trim_end   < 1.0 { proc.end_ms   = Some(trim_end   * duration_ms); }
     }
 
-    match proc.video_codec.as_deref() {
-        Some("prores_ks") | Some("prores_videotoolbox") => {
-            let profiles = ["Proxy", "LT", "Standard", "HQ", "4444", "4444XQ"];
-            let pix_fmts = [Pixel::YUV422P10LE, Pixel::YUV422P10LE, Pixel::YUV422P10LE, Pixel::YUV422P10LE, Pixel::YUVA444P10LE, Pixel::YUVA444P10LE];
-            if let Some(profile) = profiles.iter().position(|&x| x == render_options.codec_options) {
-                proc.video.encoder_params.options.set("profile", &format!("{}", profile));
-                if proc.video_codec.as_deref() == Some("prores_ks") {
-                    proc.video.encoder_params.pixel_format = Some(pix_fmts[profile]);
-                }
-            }
-            proc.video.clone_frames = proc.video_codec.as_deref() == Some("prores_ks");
-        }
-        Some("dnxhd") => {
-            let profiles = ["DNxHD", "DNxHR LB", "DNxHR SQ", "DNxHR HQ", "DNxHR HQX", "DNxHR 444"];
-            let pix_fmts = [Pixel::YUV422P, Pixel::YUV422P, Pixel::YUV422P, Pixel::YUV422P, Pixel::YUV422P10LE, Pixel::YUV444P10LE];
-            if let Some(profile) = profiles.iter().position(|&x| x == render_options.codec_options) {
-                proc.video.encoder_params.options.set("profile", &format!("{}", profile));
-                proc.video.encoder_params.pixel_format = Some(pix_fmts[profile]);
-            }
-            proc.video.clone_frames = true;
-        }
-        Some("png") => {
-            if render_options.codec_options.contains("16-bit") {
-                proc.video.encoder_params.pixel_format = Some(if has_alpha { Pixel::RGBA64BE } else { Pixel::RGB48BE });
-            } else {
-                proc.video.encoder_params.pixel_format = Some(if has_alpha { Pixel::RGBA } else { Pixel::RGB24 });
-            }
-            proc.video.clone_frames = true;
-        }
-        Some("exr") => {
-            proc.video.clone_frames = true;
-            proc.video.encoder_params.options.set("compression", "1"); // RLE compression
-            proc.video.encoder_params.options.set("gamma", "1.0");
-            proc.video.encoder_params.pixel_format = Some(if has_alpha { Pixel::GBRAPF32LE } else { Pixel::GBRPF32LE });
-            /*Decoder options:
-                -layer             <string>     .D.V....... Set the decoding layer (default "")
-                -part              <int>        .D.V....... Set the decoding part (from 0 to INT_MAX) (default 0)
-                -gamma             <float>      .D.V....... Set the float gamma value when decoding (from 0.001 to FLT_MAX) (default 1)
-                -apply_trc         <int>        .D.V....... color transfer characteristics to apply to EXR linear input (from 1 to 18) (default gamma)
-                    bt709           1            .D.V....... BT.709
-                    gamma           2            .D.V....... gamma
-                    gamma22         4            .D.V....... BT.470 M
-                    gamma28         5            .D.V....... BT.470 BG
-                    smpte170m       6            .D.V....... SMPTE 170 M
-                    smpte240m       7            .D.V....... SMPTE 240 M
-                    linear          8            .D.V....... Linear
-                    log             9            .D.V....... Log
-                    log_sqrt        10           .D.V....... Log square root
-                    iec61966_2_4    11           .D.V....... IEC 61966-2-4
-                    bt1361          12           .D.V....... BT.1361
-                    iec61966_2_1    13           .D.V....... IEC 61966-2-1
-                    bt2020_10bit    14           .D.V....... BT.2020 - 10 bit
-                    bt2020_12bit    15           .D.V....... BT.2020 - 12 bit
-                    smpte2084       16           .D.V....... SMPTE ST 2084
-                    smpte428_1      17           .D.V....... SMPTE ST 428-1
-            */
-        }
-        _ => { }
-    }
-
-    proc.video.encoder_params.options.set("allow_sw", "1");
-    proc.video.encoder_params.options.set("realtime", "0");
+    proc.video.clone_frames = configure_encoder(encoder.0, render_options, has_alpha, &mut proc.video.encoder_params);
+    let max_memory_mb = if render_options.max_memory_mb == 0 && gyroflow_core::low_memory::is_enabled() { gyroflow_core::low_memory::RENDER_MEMORY_BUDGET_MB } else { render_options.max_memory_mb };
+    memory_budget.set_limit_mb(max_memory_mb);
+    proc.video.frame_pool = frame_pool::FramePool::new(memory_budget);
+    gyroflow_core::pipeline::reset_stats();
 
     proc.video.encoder_params.keyframe_distance_s = render_options.keyframe_distance.max(0.0001);
 
     proc.preserve_other_tracks = render_options.preserve_other_tracks;
-
-    for (key, value) in render_options.get_encoder_options_dict().iter() {
-        log::info!("Setting encoder option {}: {}", key, value);
-        if key == "pix_fmt" {
-            pixel_format = value.to_string();
-            continue;
+    proc.metadata = render_options.metadata.clone();
+
+    if render_options.gpmf_passthrough {
+        proc.copy_gpmf_track = true;
+        // Parsed streams are also stored as JSON, for tools which don't read the GPMF track
+        let streams = stab.gyro.read().gpmf_streams_in_range(proc.start_ms.unwrap_or_default(), proc.end_ms.unwrap_or(duration_ms));
+        if !streams.is_empty() {
+            if let Ok(json) = serde_json::to_string(&streams) {
+                proc.metadata.insert("com.gopro.gpmf.streams".into(), json);
+            }
         }
-        proc.video.encoder_params.options.set(key, value);
     }
 
-    if !pixel_format.is_empty() {
-        use std::str::FromStr;
-        match Pixel::from_str(&pixel_format.to_ascii_lowercase()) {
-            Ok(px) => { proc.video.encoder_params.pixel_format = Some(px); },
-            Err(e) => { ::log::debug!("Unknown requested pixel format: {}, {:?}", pixel_format, e); }
-        }
-    }
+    proc.video.deinterlace = ffmpeg_filter::DeinterlaceMode::from(render_options.deinterlace);
+    proc.video.video_filter = input_file.video_filter.clone();
+    proc.video.output_color_range = render_options.output_color_range();
+    // Interlaced video is stabilized per field, so every field has its own gyro sample and the frame rate is doubled
+    let source_fps = if proc.video.deinterlacing() { fps * 2.0 } else { fps };
+    let render_frame_count = if proc.video.deinterlacing() { render_frame_count * 2 } else { render_frame_count };
+
+    let output_fps = Some(render_options.output_fps).filter(|x| *x > 0.0 && (x - source_fps).abs() > 0.001);
+    let blend_frames = output_fps.is_some() && render_options.fps_interpolation == 1;
+    proc.output_frame_rate = output_fps.or(Some(source_fps).filter(|x| *x != fps)).map(fps_to_rational);
 
     let start_us = (proc.start_ms.unwrap_or_default() * 1000.0) as i64;
 
+    // Cropped copy of the output, encoded from the same stabilized frames. Only next to a video file in the filesystem,
+    // image sequences and content URIs don't have a name the copy could be derived from
+    let is_image_sequence = regex::Regex::new(r#"%[0-9]+d"#).unwrap().is_match(&render_options.output_path);
+    let cropped_output = if render_options.cropped_output && !comparison.is_enabled() && !is_image_sequence && !crate::core::filesystem::is_content_uri(&render_options.output_path) {
+        let encoder_name = ffmpeg_hw::find_working_encoder(&get_possible_encoders(&render_options.codec, false)).0;
+        let mut params = ffmpeg_video::EncoderParams::default();
+        configure_encoder(encoder_name, render_options, false, &mut params);
+        params.keyframe_distance_s = render_options.keyframe_distance.max(0.0001);
+        let frame_rate = proc.output_frame_rate.unwrap_or_else(|| fps_to_rational(source_fps));
+        let path = cropped_output::CroppedOutput::path_for(&render_options.output_path);
+        let output = cropped_output::CroppedOutput::new(&path, encoder_name, params, (render_options.output_width as u32, render_options.output_height as u32),
+            cropped_output::CroppedOutput::crop_for(&*stab), frame_rate, if render_options.bitrate > 0.0 { Some(render_options.bitrate) } else { None })?;
+        Some(std::rc::Rc::new(std::cell::RefCell::new(output)))
+    } else {
+        None
+    };
+    let cropped_output2 = cropped_output.clone();
+
     if !render_options.audio {
         proc.audio_codec = codec::Id::None;
     }
 
     log::debug!("start_us: {}, render_duration: {}, render_frame_count: {}", start_us, render_duration, render_frame_count);
 
-    let mut planes = Vec::<Box<dyn FnMut(i64, &mut Video, &mut Video, usize, bool)>>::new();
+    let mut planes = Vec::<Box<dyn FnMut(i64, &mut Video, &mut Video, usize, bool, Option<i64>) -> Result<(), FFmpegError>>>::new();
 
-    let is_prores_videotoolbox = proc.video_codec.as_deref() == Some("prores_videotoolbox");
+    // Workaround for a bug in prores videotoolbox encoder, it always encodes limited range, so full range sources have to be converted
+    if proc.video_codec.as_deref() == Some("prores_videotoolbox") && proc.video.output_color_range.is_none() {
+        proc.video.output_color_range = Some(ffmpeg_next::util::color::Range::MPEG);
+    }
+    // Avid and Premiere always interpret DNxHR and CineForm YUV as limited range
+    if matches!(proc.video_codec.as_deref(), Some("dnxhd") | Some("cfhd")) && !has_alpha && proc.video.output_color_range.is_none() {
+        proc.video.output_color_range = Some(ffmpeg_next::util::color::Range::MPEG);
+    }
+    // Intermediate codecs go to editing apps, which rely on the `colr` atom for the color space
+    if matches!(render_options.codec.as_str(), "ProRes" | "DNxHD" | "CineForm") {
+        proc.video.muxer_options.set("movflags", "+write_colr");
+    }
 
     let progress2 = progress.clone();
+    let cancel_flag2 = cancel_flag.clone();
     let mut process_frame = 0;
 
     proc.on_encoder_initialized(|enc: &ffmpeg_next::encoder::video::Video| {
@@ -298,12 +671,60 @@ pub fn render<T: PixelType, F, F2>(stab: Arc<StabilizationManager<T>>, progress:
     let mut prev_real_ts = 0;
     let mut ramped_ts = 0.0;
     let mut final_ts = 0;
-    let interval = (1_000_000.0 / fps).round() as i64;
+    let interval = (1_000_000.0 / output_fps.unwrap_or(source_fps)).round() as i64;
     let is_speed_changed = video_speed != 1.0 || stab.keyframes.read().is_keyframed(&gyroflow_core::keyframes::KeyframeType::VideoSpeed);
+    let is_retimed = is_speed_changed || output_fps.is_some();
+    let time_remap = if is_speed_changed { Some(stab.video_time_remap()) } else { None };
+    let mut prev_video_ts_ms = 0.0;
+    let mut prev_output_planes: Vec<Vec<u8>> = Vec::new();
+    let duplicate_mode = DuplicateFrameMode::from(render_options.duplicate_frames);
+    let mut duplicate_detector = DuplicateDetector::default();
+    let mut decoded_frames = 0;
     if is_speed_changed {
         proc.audio_codec = codec::Id::None; // Audio not supported when changing speed
     }
 
+    proc.chapter_split = chapter_split::ChapterSplit {
+        duration_s: render_options.split_duration_s.max(0.0),
+        // Gaps are found in the source timestamps, which don't match the output ones after retiming
+        at_gaps: render_options.split_at_gaps && !is_retimed,
+    };
+    if render_options.split_at_gaps && is_retimed {
+        ::log::warn!("Splitting at recording gaps is not supported when the speed or frame rate is changed");
+    }
+    if proc.chapter_split.is_enabled() && crate::core::filesystem::is_content_uri(&render_options.output_path) {
+        // Segment files can't be created next to a content URI
+        ::log::warn!("Chapter split is not supported for {}", render_options.output_path);
+        proc.chapter_split = Default::default();
+    }
+    if proc.chapter_split.is_enabled() {
+        // Forced keyframes have to be IDR frames, so every segment can be decoded on its own
+        proc.video.encoder_params.options.set("forced-idr", "1");
+    }
+    let chapter_split = proc.chapter_split.is_enabled();
+    proc.memory_output = render_options.memory_output.clone();
+
+    // NVDEC frames go to NVENC without leaving the GPU, when nothing else needs the pixels in the system memory
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    if render_options.cuda_pipeline {
+        proc.video.cuda_pipeline = gpu_decoding && gpu_decoder_index >= 0 && encoder.0.contains("nvenc") && gyroflow_core::gpu::cuda::is_available() &&
+            !comparison.is_enabled() && !blend_frames && cropped_output.is_none() && verify_frames.is_empty() &&
+            duplicate_mode == DuplicateFrameMode::Keep && !proc.video.deinterlacing() && input_file.video_filter.trim().is_empty();
+        if !proc.video.cuda_pipeline {
+            ::log::info!("CUDA pipeline is not available with the current settings, frames will be copied to the system memory");
+        }
+    }
+    // Same for VAAPI, the surfaces are shared with the wgpu backend as DMA-BUFs
+    #[cfg(target_os = "linux")]
+    if render_options.vaapi_pipeline {
+        proc.video.vaapi_pipeline = gpu_decoding && gpu_decoder_index >= 0 && encoder.0.contains("vaapi") &&
+            !comparison.is_enabled() && !blend_frames && cropped_output.is_none() && verify_frames.is_empty() &&
+            duplicate_mode == DuplicateFrameMode::Keep && !proc.video.deinterlacing() && input_file.video_filter.trim().is_empty();
+        if !proc.video.vaapi_pipeline {
+            ::log::info!("VAAPI pipeline is not available with the current settings, frames will be copied to the system memory");
+        }
+    }
+
     proc.on_frame(move |mut timestamp_us, input_frame, output_frame, converter, rate_control| {
         let fill_with_background = render_options.pad_with_black &&
             (timestamp_us < (trim_start * duration_ms * 1000.0).round() as i64 ||
@@ -313,25 +734,68 @@ pub fn render<T: PixelType, F, F2>(stab: Arc<StabilizationManager<T>>, progress:
             timestamp_us = (timestamp_us as f64 / scale).round() as i64;
         }
 
-        if is_speed_changed {
-            let vid_speed = stab.keyframes.read().value_at_video_timestamp(&gyroflow_core::keyframes::KeyframeType::VideoSpeed, timestamp_us as f64 / 1000.0).unwrap_or(video_speed);
+        // Timestamp of the original frame if this one is a duplicate to be interpolated
+        let mut duplicate_of = None;
+        if duplicate_mode != DuplicateFrameMode::Keep {
+            let hash = input_frame_hash(input_frame);
+            let original_ts = duplicate_detector.feed(decoded_frames, timestamp_us, hash, |from_us, to_us| gyroflow_core::duplicate_frames::rotation_between(&stab.gyro.read(), from_us, to_us));
+            decoded_frames += 1;
+            if let Some(original_ts) = original_ts {
+                ::log::debug!("Frame at {} us is a duplicate of the frame at {} us ({:?})", timestamp_us, original_ts, duplicate_mode);
+                if duplicate_mode == DuplicateFrameMode::Drop {
+                    rate_control.repeat_times = 0;
+                    process_frame += 1;
+                    return Ok(());
+                }
+                duplicate_of = Some(original_ts);
+            }
+        }
+
+        // Weight of the current frame when blending with the previous one, None if the frame is not blended
+        let mut blend_weight = None;
+        let mut skip_output = false;
+        if is_retimed {
+            // Gyro is always looked up at the source timestamp, only the output timestamps are changed
+            let video_ts_ms = timestamp_us as f64 / 1000.0;
+            let vid_speed = if let Some(remap) = &time_remap {
+                // Average speed over the frame interval, so the output time follows the integral of the speed curve
+                let output_delta = remap.source_to_output(video_ts_ms) - remap.source_to_output(prev_video_ts_ms);
+                if output_delta > 0.0 {
+                    (video_ts_ms - prev_video_ts_ms) / output_delta
+                } else {
+                    stab.keyframes.read().value_at_video_timestamp(&gyroflow_core::keyframes::KeyframeType::VideoSpeed, video_ts_ms).unwrap_or(video_speed)
+                }
+            } else {
+                1.0
+            };
+            prev_video_ts_ms = video_ts_ms;
             let current_interval = ((rate_control.out_timestamp_us - prev_real_ts) as f64) / vid_speed;
             ramped_ts += current_interval;
             prev_real_ts = rate_control.out_timestamp_us;
-            if ramped_ts < (final_ts as f64 + interval as f64 / 2.0) { // interval/2 because we want frame in the middle of the range, not in the end
+            let target_ts = final_ts as f64 + interval as f64 / 2.0; // interval/2 because we want frame in the middle of the range, not in the end
+            if ramped_ts < target_ts {
                 rate_control.repeat_times = 0; // skip this frame
-                process_frame += 1;
-                return Ok(());
+                if !blend_frames {
+                    process_frame += 1;
+                    return Ok(());
+                }
+                // Still needs to be stabilized, because it's blended into the next output frame
+                skip_output = true;
             } else {
                 let repeat_times = current_interval / interval as f64;
                 if repeat_times >= 1.5 {
                     // Need to duplicate the frames
                     rate_control.repeat_times = repeat_times.round() as i64;
                     rate_control.repeat_interval = interval;
+                } else if blend_frames && current_interval > 0.0 {
+                    // Previous frame is at `ramped_ts - current_interval`, current one at `ramped_ts`
+                    blend_weight = Some((1.0 - (ramped_ts - target_ts) / current_interval).clamp(0.0, 1.0));
                 }
             }
-            rate_control.out_timestamp_us = final_ts;
-            final_ts += interval * rate_control.repeat_times;
+            if !skip_output {
+                rate_control.out_timestamp_us = final_ts;
+                final_ts += interval * rate_control.repeat_times;
+            }
         }
 
         let output_frame = output_frame.unwrap();
@@ -339,8 +803,13 @@ pub fn render<T: PixelType, F, F2>(stab: Arc<StabilizationManager<T>>, progress:
         macro_rules! create_planes_proc {
             ($planes:ident, $(($t:tt, $in_frame:expr, $out_frame:expr, $ind:expr, $yuvi:expr, $max_val:expr), )*) => {
                 $({
-                    let in_size  = ($in_frame .plane_width($ind) as usize, $in_frame .plane_height($ind) as usize, $in_frame .stride($ind) as usize);
-                    let out_size = ($out_frame.plane_width($ind) as usize, $out_frame.plane_height($ind) as usize, $out_frame.stride($ind) as usize);
+                    let in_size  = ffmpeg_hw::plane_size(&$in_frame, $ind);
+                    let mut out_size = ffmpeg_hw::plane_size(&$out_frame, $ind);
+                    if comparison.is_enabled() {
+                        // Stabilize into a separate buffer with the size of a single view
+                        let view_size = comparison.view_size(out_size.0, out_size.1);
+                        out_size = (view_size.0, view_size.1, view_size.0 * <$t as PixelType>::COUNT * <$t as PixelType>::SCALAR_BYTES);
+                    }
                     let bg = {
                         let mut params = stab.params.write();
                         params.size        = (in_size.0,  in_size.1);
@@ -350,21 +819,53 @@ pub fn render<T: PixelType, F, F2>(stab: Arc<StabilizationManager<T>>, progress:
                         params.background
                     };
                     let mut plane = Stabilization::<$t>::default();
+                    plane.set_task(gyroflow_core::gpu::context_manager::GpuTask::Export);
+                    plane.downscaled_level = stab.stabilization.read().downscaled_level.clone();
                     plane.interpolation = Interpolation::Lanczos4;
+                    plane.set_kernel_precision(stab.params.read().kernel_precision);
 
-                    // Workaround for a bug in prores videotoolbox encoder
-                    if $in_frame.format() == ffmpeg_next::format::Pixel::NV12 && is_prores_videotoolbox {
-                        plane.kernel_flags.set(KernelParamsFlags::FIX_COLOR_RANGE, true);
+                    plane.color_range_conversion = color_range_conversion(&$in_frame, &$out_frame, &$yuvi, $max_val);
+                    if plane.color_range_conversion.is_some() {
+                        ::log::debug!("Plane {}: converting color range {:?} -> {:?}", $ind, $in_frame.color_range(), $out_frame.color_range());
                     }
 
-                    plane.init_size(<$t as PixelType>::from_rgb_color(bg, &$yuvi, $max_val), in_size, out_size);
+                    let mut bg = <$t as PixelType>::from_rgb_color(bg, &$yuvi, $max_val);
+                    let yuvi: &[usize] = &$yuvi;
+                    if yuvi.is_empty() {
+                        bg *= $max_val / 255.0; // Packed RGB, the color is 0-255
+                        if let Some((scale, offset, luma)) = plane.color_range_conversion {
+                            // The background isn't converted in the kernel, so it's scaled for the range of the output here
+                            let l = luma[0] * (luma[1] * bg[0] + luma[2] * bg[1] + luma[3] * bg[2]);
+                            let alpha = bg[3];
+                            bg = bg * scale + nalgebra::Vector4::repeat(offset + l);
+                            bg[3] = alpha;
+                        }
+                    }
+                    if !ffmpeg_video_converter::is_full_range(ffmpeg_hw::sw_format(&$out_frame), $out_frame.color_range()) {
+                        // The background isn't converted in the kernel, so it has to be in the output range already
+                        if let Some((scale, offset)) = full_to_limited_range(&$yuvi, $max_val) {
+                            bg[0] = bg[0] * scale + offset;
+                            bg[1] = bg[1] * scale + offset;
+                        }
+                    }
+                    plane.init_size(bg, in_size, out_size);
                     plane.set_compute_params(ComputeParams::from_manager(&stab, false));
-                    $planes.push(Box::new(move |timestamp_us: i64, in_frame_data: &mut Video, out_frame_data: &mut Video, plane_index: usize, fill_with_background: bool| {
-                        let input_size  = ( in_frame_data.plane_width(plane_index) as usize,  in_frame_data.plane_height(plane_index) as usize,  in_frame_data.stride(plane_index) as usize);
-                        let output_size = (out_frame_data.plane_width(plane_index) as usize, out_frame_data.plane_height(plane_index) as usize, out_frame_data.stride(plane_index) as usize);
 
-                        let (buffer, out_buffer) = (in_frame_data.data_mut(plane_index), out_frame_data.data_mut(plane_index));
+                    let mut view_buffer = Vec::new();
+                    let label_pixel = {
+                        let yuvi: &[usize] = &$yuvi;
+                        let white = nalgebra::Vector4::new(255.0, 255.0, 255.0, 255.0);
+                        let mut color = <$t as PixelType>::from_rgb_color(white, yuvi, $max_val);
+                        if yuvi.is_empty() { color *= $max_val / 255.0; }
+                        bytemuck::bytes_of(&<$t as PixelType>::from_float(color)).to_vec()
+                    };
+                    $planes.push(Box::new(move |timestamp_us: i64, in_frame_data: &mut Video, out_frame_data: &mut Video, plane_index: usize, fill_with_background: bool, duplicate_of: Option<i64>| {
+                        let input_size  = ffmpeg_hw::plane_size(in_frame_data,  plane_index);
+                        let output_size = ffmpeg_hw::plane_size(out_frame_data, plane_index);
 
+                        if let Some(original_ts) = duplicate_of {
+                            plane.set_duplicate_frame(timestamp_us, original_ts);
+                        }
                         plane.ensure_stab_data_at_timestamp(timestamp_us);
                         if fill_with_background {
                             if let Some(transform) = plane.stab_data.get_mut(&timestamp_us) {
@@ -372,15 +873,76 @@ pub fn render<T: PixelType, F, F2>(stab: Arc<StabilizationManager<T>>, progress:
                             }
                         }
                         use gyroflow_core::gpu::{ BufferDescription, BufferSource };
-                        plane.process_pixels(timestamp_us, &mut BufferDescription {
-                            input_size,
-                            output_size,
-                            buffers: BufferSource::Cpu {
-                                input: buffer,
-                                output: out_buffer
-                            },
-                            input_rect: None, output_rect: None
-                        });
+
+                        // Planes of the CUDA surfaces are only accessible on the GPU. On failure the output surface is left
+                        // uninitialized, so the render is stopped instead of encoding it
+                        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                        if in_frame_data.format() == Pixel::CUDA {
+                            if !plane.process_pixels(timestamp_us, &mut BufferDescription {
+                                input_size,
+                                output_size,
+                                buffers: ffmpeg_cuda::buffer_source(in_frame_data, out_frame_data, plane_index),
+                                input_rect: None, output_rect: None
+                            }) {
+                                ::log::error!("Failed to process the CUDA frame at {} us", timestamp_us);
+                                return Err(FFmpegError::GPUProcessingFailed(timestamp_us));
+                            }
+                            return Ok(());
+                        }
+                        #[cfg(target_os = "linux")]
+                        if in_frame_data.format() == Pixel::VAAPI {
+                            let processed = match ffmpeg_vaapi::buffer_source(in_frame_data, out_frame_data, plane_index) {
+                                Some(buffers) => plane.process_pixels(timestamp_us, &mut BufferDescription {
+                                    input_size,
+                                    output_size,
+                                    buffers,
+                                    input_rect: None, output_rect: None
+                                }),
+                                None => false
+                            };
+                            if !processed {
+                                ::log::error!("Failed to process the VAAPI frame at {} us", timestamp_us);
+                                return Err(FFmpegError::GPUProcessingFailed(timestamp_us));
+                            }
+                            return Ok(());
+                        }
+
+                        let (buffer, out_buffer) = (in_frame_data.data_mut(plane_index), out_frame_data.data_mut(plane_index));
+                        if comparison.is_enabled() {
+                            let bpp = <$t as PixelType>::COUNT * <$t as PixelType>::SCALAR_BYTES;
+                            let view_size = comparison.view_size(output_size.0, output_size.1);
+                            let view_size = (view_size.0, view_size.1, view_size.0 * bpp);
+                            view_buffer.resize(view_size.2 * view_size.1, 0u8);
+
+                            plane.process_pixels(timestamp_us, &mut BufferDescription {
+                                input_size,
+                                output_size: view_size,
+                                buffers: BufferSource::Cpu {
+                                    input: buffer,
+                                    output: &mut view_buffer
+                                },
+                                input_rect: None, output_rect: None
+                            });
+
+                            let (org_pos, stab_pos) = comparison.view_positions((view_size.0, view_size.1));
+                            frame_comparison::scale_into(buffer, input_size, out_buffer, output_size.2, (org_pos.0, org_pos.1, view_size.0, view_size.1), bpp);
+                            frame_comparison::copy_into(&view_buffer, view_size, out_buffer, output_size.2, stab_pos, bpp);
+
+                            let (scale, margin) = frame_comparison::label_geometry((view_size.0, view_size.1));
+                            frame_comparison::draw_label(out_buffer, output_size, (org_pos.0 + margin, org_pos.1 + margin), scale, "Original", &label_pixel);
+                            frame_comparison::draw_label(out_buffer, output_size, (stab_pos.0 + margin, stab_pos.1 + margin), scale, "Stabilized", &label_pixel);
+                        } else {
+                            plane.process_pixels(timestamp_us, &mut BufferDescription {
+                                input_size,
+                                output_size,
+                                buffers: BufferSource::Cpu {
+                                    input: buffer,
+                                    output: out_buffer
+                                },
+                                input_rect: None, output_rect: None
+                            });
+                        }
+                        Ok(())
                     }));
                 })*
             };
@@ -389,19 +951,26 @@ pub fn render<T: PixelType, F, F2>(stab: Arc<StabilizationManager<T>>, progress:
         if planes.is_empty() {
             // Good reference about video formats: https://source.chromium.org/chromium/chromium/src/+/master:media/base/video_frame.cc
             // https://gist.github.com/Jim-Bar/3cbba684a71d1a9d468a6711a6eddbeb
-            match input_frame.format() {
+            match ffmpeg_hw::sw_format(input_frame) {
                 Pixel::NV12 => {
                     create_planes_proc!(planes,
                         (Luma8, input_frame, output_frame, 0, [0], 255.0),
                         (UV8,   input_frame, output_frame, 1, [1,2], 255.0),
                     );
                 },
-                Pixel::NV21 => {
+                Pixel::NV21 | Pixel::NV42 => {
                     create_planes_proc!(planes,
                         (Luma8, input_frame, output_frame, 0, [0], 255.0),
                         (UV8,   input_frame, output_frame, 1, [2,1], 255.0),
                     );
                 },
+                // Semi-planar 4:2:2 and 4:4:4, the chroma plane size comes from the frame
+                Pixel::NV16 | Pixel::NV24 => {
+                    create_planes_proc!(planes,
+                        (Luma8, input_frame, output_frame, 0, [0], 255.0),
+                        (UV8,   input_frame, output_frame, 1, [1,2], 255.0),
+                    );
+                },
                 Pixel::P010LE | Pixel::P016LE |
                 Pixel::P210LE | Pixel::P216LE |
                 Pixel::P410LE | Pixel::P416LE => {
@@ -415,13 +984,33 @@ pub fn render<T: PixelType, F, F2>(stab: Arc<StabilizationManager<T>>, progress:
                         (UV16,   input_frame, output_frame, 1, [1,2], max_val),
                     );
                 },
-                Pixel::YUV420P | Pixel::YUVJ420P => {
+                Pixel::YUV420P | Pixel::YUVJ420P |
+                Pixel::YUV422P | Pixel::YUVJ422P |
+                Pixel::YUV444P | Pixel::YUVJ444P |
+                Pixel::YUV440P | Pixel::YUVJ440P => {
                     create_planes_proc!(planes,
                         (Luma8, input_frame, output_frame, 0, [0], 255.0),
                         (Luma8, input_frame, output_frame, 1, [1], 255.0),
                         (Luma8, input_frame, output_frame, 2, [2], 255.0),
                     );
                 },
+                Pixel::YUVA420P | Pixel::YUVA422P | Pixel::YUVA444P => {
+                    create_planes_proc!(planes,
+                        (Luma8, input_frame, output_frame, 0, [0], 255.0),
+                        (Luma8, input_frame, output_frame, 1, [1], 255.0),
+                        (Luma8, input_frame, output_frame, 2, [2], 255.0),
+                        (Luma8, input_frame, output_frame, 3, [3], 255.0),
+                    );
+                },
+                Pixel::GRAY8 => { create_planes_proc!(planes, (Luma8, input_frame, output_frame, 0, [0], 255.0), ); },
+                Pixel::GRAY10LE | Pixel::GRAY12LE | Pixel::GRAY16LE => {
+                    let max_val = match input_frame.format() {
+                        Pixel::GRAY10LE => 1023.0,
+                        Pixel::GRAY12LE => 4095.0,
+                        _ => 65535.0
+                    };
+                    create_planes_proc!(planes, (Luma16, input_frame, output_frame, 0, [0], max_val), );
+                },
                 Pixel::YUV420P10LE | Pixel::YUV420P12LE | Pixel::YUV420P14LE | Pixel::YUV420P16LE |
                 Pixel::YUV422P10LE | Pixel::YUV422P12LE | Pixel::YUV422P14LE | Pixel::YUV422P16LE |
                 Pixel::YUV444P10LE | Pixel::YUV444P12LE | Pixel::YUV444P14LE | Pixel::YUV444P16LE => {
@@ -437,10 +1026,12 @@ pub fn render<T: PixelType, F, F2>(stab: Arc<StabilizationManager<T>>, progress:
                         (Luma16, input_frame, output_frame, 2, [2], max_val),
                     );
                 },
+                Pixel::YUVA420P10LE | Pixel::YUVA420P16LE |
+                Pixel::YUVA422P10LE | Pixel::YUVA422P12LE | Pixel::YUVA422P16LE |
                 Pixel::YUVA444P10LE | Pixel::YUVA444P12LE | Pixel::YUVA444P16LE => {
                     let max_val = match input_frame.format() {
-                        Pixel::YUVA444P10LE => 1023.0,
-                        Pixel::YUVA444P12LE => 4095.0,
+                        Pixel::YUVA420P10LE | Pixel::YUVA422P10LE | Pixel::YUVA444P10LE => 1023.0,
+                        Pixel::YUVA422P12LE | Pixel::YUVA444P12LE => 4095.0,
                         _ => 65535.0
                     };
                     create_planes_proc!(planes,
@@ -455,6 +1046,23 @@ pub fn render<T: PixelType, F, F2>(stab: Arc<StabilizationManager<T>>, progress:
                 Pixel::RGBA     => { create_planes_proc!(planes, (RGBA8,  input_frame, output_frame, 0, [], 255.0), ); },
                 Pixel::RGB48BE  => { create_planes_proc!(planes, (RGB16,  input_frame, output_frame, 0, [], 65535.0), ); },
                 Pixel::RGBA64BE => { create_planes_proc!(planes, (RGBA16, input_frame, output_frame, 0, [], 65535.0), ); },
+                Pixel::RGBA64LE => { create_planes_proc!(planes, (RGBA16, input_frame, output_frame, 0, [], 65535.0), ); },
+                // EXR and other float sources, 0.0-1.0 but not clamped, so the highlights are kept
+                Pixel::GBRPF32LE => {
+                    create_planes_proc!(planes,
+                        (Lumaf, input_frame, output_frame, 0, [1], 1.0),
+                        (Lumaf, input_frame, output_frame, 1, [2], 1.0),
+                        (Lumaf, input_frame, output_frame, 2, [0], 1.0),
+                    );
+                },
+                Pixel::GBRAPF32LE => {
+                    create_planes_proc!(planes,
+                        (Lumaf, input_frame, output_frame, 0, [1], 1.0),
+                        (Lumaf, input_frame, output_frame, 1, [2], 1.0),
+                        (Lumaf, input_frame, output_frame, 2, [0], 1.0),
+                        (Lumaf, input_frame, output_frame, 3, [3], 1.0),
+                    );
+                },
                 format => { // All other convert to YUV444P16LE
                     ::log::info!("Unknown format {:?}, converting to YUV444P16LE", format);
                     // Go through 4:4:4 because of even plane dimensions
@@ -472,29 +1080,63 @@ pub fn render<T: PixelType, F, F2>(stab: Arc<StabilizationManager<T>>, progress:
             return Err(FFmpegError::UnknownPixelFormat(input_frame.format()));
         }
 
-        let mut undistort_frame = |frame: &mut Video, out_frame: &mut Video| {
+        let mut undistort_frame = |frame: &mut Video, out_frame: &mut Video| -> Result<(), FFmpegError> {
             for (i, cb) in planes.iter_mut().enumerate() {
-                (*cb)(timestamp_us, frame, out_frame, i, fill_with_background);
+                if cancel_flag2.load(std::sync::atomic::Ordering::Relaxed) { return Ok(()); }
+                (*cb)(timestamp_us, frame, out_frame, i, fill_with_background, duplicate_of)?;
             }
             progress2((process_frame as f64 / render_frame_count as f64, process_frame, render_frame_count, false));
+            Ok(())
         };
 
-        match input_frame.format() {
-            Pixel::NV12 | Pixel::NV21 | Pixel::YUV420P | Pixel::YUVJ420P |
+        match ffmpeg_hw::sw_format(input_frame) {
+            Pixel::NV12 | Pixel::NV21 | Pixel::NV16 | Pixel::NV24 | Pixel::NV42 |
+            Pixel::YUV420P | Pixel::YUVJ420P | Pixel::YUV422P | Pixel::YUVJ422P | Pixel::YUV444P | Pixel::YUVJ444P | Pixel::YUV440P | Pixel::YUVJ440P |
+            Pixel::YUVA420P | Pixel::YUVA422P | Pixel::YUVA444P |
+            Pixel::GRAY8 | Pixel::GRAY10LE | Pixel::GRAY12LE | Pixel::GRAY16LE |
             Pixel::P010LE | Pixel::P016LE | Pixel::P210LE | Pixel::P216LE | Pixel::P410LE | Pixel::P416LE |
             Pixel::YUV420P10LE | Pixel::YUV420P12LE | Pixel::YUV420P14LE | Pixel::YUV420P16LE |
             Pixel::YUV422P10LE | Pixel::YUV422P12LE | Pixel::YUV422P14LE | Pixel::YUV422P16LE |
             Pixel::YUV444P10LE | Pixel::YUV444P12LE | Pixel::YUV444P14LE | Pixel::YUV444P16LE |
+            Pixel::YUVA420P10LE | Pixel::YUVA420P16LE | Pixel::YUVA422P10LE | Pixel::YUVA422P12LE | Pixel::YUVA422P16LE |
             Pixel::YUVA444P10LE | Pixel::YUVA444P12LE | Pixel::YUVA444P16LE |
             Pixel::AYUV64LE |
-            Pixel::RGB24 | Pixel::RGBA | Pixel::RGB48BE | Pixel::RGBA64BE => {
-                undistort_frame(input_frame, output_frame)
+            Pixel::RGB24 | Pixel::RGBA | Pixel::RGB48BE | Pixel::RGBA64BE | Pixel::RGBA64LE |
+            Pixel::GBRPF32LE | Pixel::GBRAPF32LE => {
+                undistort_frame(input_frame, output_frame)?
             },
             _ => {
+                let mut result = Ok(());
                 converter.convert_pixel_format(input_frame, output_frame, Pixel::YUV444P16LE, |converted_frame, converted_output| {
-                    undistort_frame(converted_frame, converted_output);
+                    result = undistort_frame(converted_frame, converted_output);
                 })?;
+                result?;
+            }
+        }
+
+        if blend_frames {
+            let current_planes: Vec<Vec<u8>> = (0..output_frame.planes()).map(|i| output_frame.data(i).to_vec()).collect();
+            if let Some(weight) = blend_weight {
+                if prev_output_planes.len() == current_planes.len() {
+                    match pixel_format_layout(output_frame.format()) {
+                        Some((bytes, big_endian)) => {
+                            for (i, prev) in prev_output_planes.iter().enumerate() {
+                                blend_into(output_frame.data_mut(i), prev, weight, bytes, big_endian);
+                            }
+                        },
+                        None => { log::warn!("Frame blending is not supported for {:?}", output_frame.format()); }
+                    }
+                }
             }
+            prev_output_planes = current_planes;
+        }
+
+        if let Some(cropped) = &cropped_output2 {
+            cropped.borrow_mut().write(output_frame, timestamp_us, rate_control.out_timestamp_us, rate_control.repeat_times, rate_control.repeat_interval)?;
+        }
+
+        if verify_frames.contains(&process_frame) {
+            frame_hashed((process_frame, frame_checksum(output_frame)));
         }
 
         process_frame += 1;
@@ -503,14 +1145,39 @@ pub fn render<T: PixelType, F, F2>(stab: Arc<StabilizationManager<T>>, progress:
         Ok(())
     });
 
-    if let Some(parent_dir) = std::path::Path::new(&render_options.output_path).parent() {
+    let in_memory = render_options.memory_output.is_some();
+    if let Some(parent_dir) = std::path::Path::new(&render_options.output_path).parent().filter(|_| !in_memory) {
         let _ = std::fs::create_dir_all(parent_dir);
     }
 
-    proc.render(&render_options.output_path, (render_options.output_width as u32, render_options.output_height as u32), if render_options.bitrate > 0.0 { Some(render_options.bitrate) } else { None }, cancel_flag, pause_flag)?;
+    let output_size = comparison.output_size(render_options.output_width, render_options.output_height);
+
+    // Content URIs (Android SAF) are rendered to a temporary file first, because the muxer needs a seekable path
+    let local_output_path = crate::core::filesystem::local_output_path(&render_options.output_path);
+
+    let result = proc.render(&local_output_path, (output_size.0 as u32, output_size.1 as u32), if render_options.bitrate > 0.0 { Some(render_options.bitrate) } else { None }, cancel_flag, pause_flag);
+    ::log::debug!("Pipeline stats: {}", serde_json::to_string(&gyroflow_core::pipeline::stats()).unwrap_or_default());
+    // The partial cropped output is removed when it's dropped
+    if let Err(FFmpegError::Cancelled(_)) = result {
+        if chapter_split {
+            // Finished segments are complete files
+            return Err(FFmpegError::Cancelled(PartialOutput::Kept));
+        }
+        if in_memory {
+            return Err(FFmpegError::Cancelled(PartialOutput::Removed));
+        }
+        return Err(FFmpegError::Cancelled(remove_partial_output(&local_output_path, &render_options.output_path)));
+    }
+    result?;
+
+    crate::core::filesystem::finish_local_output(&local_output_path, &render_options.output_path)?;
+    if let Some(cropped) = &cropped_output {
+        // The audio is copied from the finished main output, when it's a single video file
+        let audio_source = Some(render_options.output_path.as_str()).filter(|_| render_options.audio && !chapter_split);
+        cropped.borrow_mut().finish(audio_source)?;
+    }
 
-    let re = regex::Regex::new(r#"%[0-9]+d"#).unwrap();
-    if re.is_match(&render_options.output_path) {
+    if is_image_sequence {
         ::log::debug!("Removing {}", render_options.output_path);
         let _ = std::fs::remove_file(&render_options.output_path);
     }
@@ -519,6 +1186,21 @@ pub fn render<T: PixelType, F, F2>(stab: Arc<StabilizationManager<T>>, progress:
     Ok(())
 }
 
+// Incomplete video file can't be played, so it's removed. Frames of an image sequence are complete and are kept
+fn remove_partial_output(local_output_path: &str, output_path: &str) -> PartialOutput {
+    if regex::Regex::new(r#"%[0-9]+d"#).unwrap().is_match(output_path) {
+        return PartialOutput::Kept;
+    }
+    match std::fs::remove_file(local_output_path) {
+        Ok(_) => PartialOutput::Removed,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => PartialOutput::Removed,
+        Err(e) => {
+            ::log::warn!("Failed to remove the partial output {}: {:?}", local_output_path, e);
+            PartialOutput::RemoveFailed
+        }
+    }
+}
+
 pub fn init() -> Result<(), Error> {
 	unsafe {
         ffi::av_log_set_level(ffi::AV_LOG_INFO);
@@ -528,6 +1210,125 @@ pub fn init() -> Result<(), Error> {
     Ok(())
 }
 
+// Checksum of the visible pixels only, padding at the end of each line can contain anything
+pub fn frame_checksum(frame: &Video) -> String {
+    let format: ffi::AVPixelFormat = frame.format().into();
+    gyroflow_core::util::checksum_rows((0..frame.planes()).flat_map(|i| {
+        let line_bytes = unsafe { ffi::av_image_get_linesize(format, frame.width() as c_int, i as c_int) }.max(0) as usize;
+        let stride = frame.stride(i).max(1);
+        frame.data(i).chunks(stride).map(move |row| &row[..line_bytes.min(row.len())])
+    }))
+}
+
+// (scale, offset) converting the values of a YUV plane from full to limited range. `yuvi` are the YUV components of the plane.
+// None for RGB and alpha planes. Limited range is 16-235 for luma and 16-240 for chroma at 8 bits, scaled for higher bit depths
+fn full_to_limited_range(yuvi: &[usize], max_val: f32) -> Option<(f32, f32)> {
+    let unit = (max_val + 1.0) / 256.0;
+    match yuvi {
+        [0] => Some((219.0 * unit / max_val, 16.0 * unit)),
+        [1] | [2] | [1, 2] | [2, 1] => {
+            let scale = 224.0 * unit / max_val;
+            Some((scale, 128.0 * unit * (1.0 - scale))) // Keep the neutral chroma value
+        },
+        _ => None
+    }
+}
+// Conversion of the input plane values done in the warp kernel, when the input and output ranges differ
+fn color_range_conversion(in_frame: &Video, out_frame: &Video, yuvi: &[usize], max_val: f32) -> Option<(f32, f32, [f32; 4])> {
+    if yuvi.is_empty() {
+        // Packed RGB tagged as limited range is encoded to YUV as full range afterwards, so it's scaled here to give limited range YUV.
+        // Chroma is scaled by 224 and luma by 219, the difference is applied through the luma of the pixel
+        if !ffmpeg_video_converter::is_packed_rgb(out_frame.format()) || out_frame.color_range() != ffmpeg_next::util::color::Range::MPEG {
+            return None;
+        }
+        let unit = (max_val + 1.0) / 256.0;
+        let (kr, kg, kb) = ffmpeg_video_converter::luma_coefficients(out_frame);
+        let scale = 224.0 * unit / max_val;
+        return Some((scale, 16.0 * unit, [219.0 * unit / max_val - scale, kr, kg, kb]));
+    }
+    let in_full  = ffmpeg_video_converter::is_full_range(ffmpeg_hw::sw_format(in_frame),  in_frame.color_range());
+    let out_full = ffmpeg_video_converter::is_full_range(ffmpeg_hw::sw_format(out_frame), out_frame.color_range());
+    let (scale, offset) = full_to_limited_range(yuvi, max_val)?;
+    match (in_full, out_full) {
+        (true, false) => Some((scale, offset, [0.0; 4])),
+        (false, true) => Some((1.0 / scale, -offset / scale, [0.0; 4])),
+        _ => None
+    }
+}
+
+// Hash of the first plane (luma or packed pixels), using the most significant byte of every sample
+fn input_frame_hash(frame: &Video) -> FrameHash {
+    // Bit-packed formats are hashed byte by byte
+    let (bytes, big_endian) = pixel_format_layout(frame.format()).unwrap_or((1, false));
+    let row_bytes = unsafe { ffi::av_image_get_linesize(frame.format().into(), frame.plane_width(0) as c_int, 0) };
+    if row_bytes <= 0 {
+        return FrameHash::from_plane(&[], 0, 0, 0, 1, 0);
+    }
+    FrameHash::from_plane(frame.data(0), row_bytes as usize, frame.plane_height(0) as usize, frame.stride(0), bytes, if big_endian { 0 } else { bytes - 1 })
+}
+
+fn pixel_format_has_alpha(format: Pixel) -> bool {
+    unsafe {
+        let desc = ffi::av_pix_fmt_desc_get(format.into());
+        !desc.is_null() && ((*desc).flags & ffi::AV_PIX_FMT_FLAG_ALPHA as u64) != 0
+    }
+}
+
+// Bytes per component and endianness of the pixel format. None if the components don't take whole bytes (e.g. RGB565, X2RGB10) or are 16-bit floats
+fn pixel_format_layout(format: Pixel) -> Option<(usize, bool)> {
+    fn gcd(a: i32, b: i32) -> i32 { if b == 0 { a } else { gcd(b, a % b) } }
+    unsafe {
+        let desc = ffi::av_pix_fmt_desc_get(format.into());
+        if desc.is_null() { return Some((1, false)); }
+        let flags = (*desc).flags;
+        if (flags & ffi::AV_PIX_FMT_FLAG_BITSTREAM as u64) != 0 { return None; }
+        let comps = &(*desc).comp[..(*desc).nb_components as usize];
+
+        // The component size is the largest one which both the step and the offset within the pixel are multiples of,
+        // e.g. 1 for RGB24 (step 3, offsets 0, 1, 2) and 2 for RGB48 (step 6, offsets 0, 2, 4)
+        let size = comps.iter().map(|c| gcd(c.step, c.offset)).min().filter(|x| *x > 0)?;
+        if !matches!(size, 1 | 2 | 4) || comps.iter().any(|c| c.shift + c.depth > size * 8) { return None; }
+        if size == 2 && (flags & ffi::AV_PIX_FMT_FLAG_FLOAT as u64) != 0 { return None; }
+
+        Some((size as usize, (flags & ffi::AV_PIX_FMT_FLAG_BE as u64) != 0))
+    }
+}
+
+// dst = prev * (1 - weight) + dst * weight
+fn blend_into(dst: &mut [u8], prev: &[u8], weight: f64, bytes: usize, big_endian: bool) {
+    let len = dst.len().min(prev.len());
+    match bytes {
+        2 => {
+            for (d, p) in dst[..len].chunks_exact_mut(2).zip(prev[..len].chunks_exact(2)) {
+                let (a, b) = if big_endian {
+                    (u16::from_be_bytes([p[0], p[1]]), u16::from_be_bytes([d[0], d[1]]))
+                } else {
+                    (u16::from_le_bytes([p[0], p[1]]), u16::from_le_bytes([d[0], d[1]]))
+                };
+                let v = (a as f64 * (1.0 - weight) + b as f64 * weight).round() as u16;
+                d.copy_from_slice(&if big_endian { v.to_be_bytes() } else { v.to_le_bytes() });
+            }
+        },
+        4 => {
+            // 32-bit formats are float
+            for (d, p) in dst[..len].chunks_exact_mut(4).zip(prev[..len].chunks_exact(4)) {
+                let (a, b) = if big_endian {
+                    (f32::from_be_bytes([p[0], p[1], p[2], p[3]]), f32::from_be_bytes([d[0], d[1], d[2], d[3]]))
+                } else {
+                    (f32::from_le_bytes([p[0], p[1], p[2], p[3]]), f32::from_le_bytes([d[0], d[1], d[2], d[3]]))
+                };
+                let v = a * (1.0 - weight as f32) + b * weight as f32;
+                d.copy_from_slice(&if big_endian { v.to_be_bytes() } else { v.to_le_bytes() });
+            }
+        },
+        _ => {
+            for (d, p) in dst[..len].iter_mut().zip(prev[..len].iter()) {
+                *d = (*p as f64 * (1.0 - weight) + *d as f64 * weight).round() as u8;
+            }
+        }
+    }
+}
+
 pub fn fps_to_rational(fps: f64) -> ffmpeg_next::Rational {
     if fps.fract() > 0.1 {
         ffmpeg_next::Rational::new((fps * 1001.0).round() as i32, 1001)
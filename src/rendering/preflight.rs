@@ -0,0 +1,110 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2022 Adrian <adrian.eddy at gmail>
+
+// Checks done before the render starts: estimated output size vs free disk space and existing files at the output path.
+// The size estimate is approximate, for intra-frame codecs it's based on the nominal data rates of the profiles at 1080p30.
+
+use std::path::Path;
+use super::render_queue::RenderOptions;
+
+#[derive(Default, Clone, Debug, serde::Serialize)]
+pub struct PreflightResult {
+    pub output_path: String,
+    pub output_exists: bool,
+    pub overwrites_input: bool, // Output path is the same as the source video
+    pub estimated_size: u64, // bytes
+    pub available_space: Option<u64>, // bytes, None if it couldn't be determined (eg. content URIs)
+    pub enough_space: bool,
+}
+impl PreflightResult {
+    pub fn can_render(&self) -> bool {
+        !self.overwrites_input
+    }
+}
+
+const MB: f64 = 1024.0 * 1024.0;
+const REFERENCE_PIXEL_RATE: f64 = 1920.0 * 1080.0 * 29.97;
+// Free space to keep on the disk on top of the estimated size
+const SPACE_MARGIN: f64 = 1.05;
+
+// Nominal video data rate in Mbps at 1080p30
+fn intra_codec_mbps(codec: &str, codec_options: &str) -> Option<f64> {
+    match (codec, codec_options) {
+        // In the order of `PRORES_PROFILES`, Standard if not set
+        ("ProRes", x)           => Some([45.0, 102.0, 147.0, 220.0, 330.0, 500.0][super::prores_profile(x).unwrap_or(2)]),
+        ("DNxHD", "DNxHR LB")   => Some(45.0),
+        ("DNxHD", "DNxHR HQ")   => Some(220.0),
+        ("DNxHD", "DNxHR HQX")  => Some(220.0),
+        ("DNxHD", "DNxHR 444")  => Some(440.0),
+        ("DNxHD", _)            => Some(145.0),
+        ("CineForm", "Low")     => Some(60.0),
+        ("CineForm", "Medium")  => Some(110.0),
+        ("CineForm", "Film Scan")   => Some(240.0),
+        ("CineForm", "Film Scan 2") => Some(330.0),
+        ("CineForm", "Film Scan 3") => Some(440.0),
+        ("CineForm", _)         => Some(160.0),
+        _ => None
+    }
+}
+
+/// Approximate size of the rendered output in bytes
+pub fn estimate_output_size(render_options: &RenderOptions, duration_ms: f64, fps: f64) -> u64 {
+    let fps = if render_options.output_fps > 0.0 { render_options.output_fps } else { fps };
+    let duration_s = duration_ms / 1000.0;
+    let frames = (duration_s * fps).ceil().max(0.0);
+    let pixels = (render_options.output_width * render_options.output_height) as f64;
+
+    let video_bytes = match render_options.codec.as_str() {
+        // Lossless compression, usually around half of the uncompressed size
+        "EXR Sequence" => pixels * 4.0 * 2.0 * 0.5 * frames, // RGBA half float
+        "PNG Sequence" => pixels * 4.0 * if render_options.codec_options == "16-bit" { 2.0 } else { 1.0 } * 0.5 * frames,
+        codec => {
+            let mbps = match intra_codec_mbps(codec, &render_options.codec_options) {
+                Some(mbps) => mbps * (pixels * fps) / REFERENCE_PIXEL_RATE,
+                None => render_options.bitrate
+            };
+            mbps * MB / 8.0 * duration_s
+        }
+    };
+    let audio_bytes = if render_options.audio && !render_options.codec.contains("Sequence") { 320.0 * 1024.0 / 8.0 * duration_s } else { 0.0 };
+
+    (video_bytes + audio_bytes).round() as u64
+}
+
+// The output directory can be created by the render, so use the nearest existing parent
+fn available_space(path: &str) -> Option<u64> {
+    if crate::core::filesystem::is_content_uri(path) { return None; }
+    let dir = Path::new(path).ancestors().skip(1).find(|x| x.is_dir())?;
+    fs2::available_space(dir).map_err(|e| {
+        log::warn!("Failed to get free space of {:?}: {:?}", dir, e);
+    }).ok()
+}
+
+fn same_file(a: &str, b: &str) -> bool {
+    if a.is_empty() || b.is_empty() { return false; }
+    match (std::fs::canonicalize(a), std::fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b
+    }
+}
+
+pub fn check(render_options: &RenderOptions, input_path: &str, duration_ms: f64, fps: f64) -> PreflightResult {
+    let output_path = &render_options.output_path;
+    let is_sequence = output_path.contains('%');
+
+    let estimated_size = estimate_output_size(render_options, duration_ms, fps);
+    let available_space = available_space(output_path);
+    let enough_space = available_space.map_or(true, |x| x as f64 >= estimated_size as f64 * SPACE_MARGIN);
+    if !enough_space {
+        log::warn!("Not enough disk space for {}: estimated {:.1} MB, available {:.1} MB", output_path, estimated_size as f64 / MB, available_space.unwrap_or_default() as f64 / MB);
+    }
+
+    PreflightResult {
+        output_path: output_path.clone(),
+        output_exists: !is_sequence && render_options.output_exists(output_path),
+        overwrites_input: !is_sequence && same_file(output_path, input_path),
+        estimated_size,
+        available_space,
+        enough_space,
+    }
+}
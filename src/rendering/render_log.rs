@@ -0,0 +1,85 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// Sidecar file written next to the rendered video, with everything needed to reproduce the render:
+// app version, GPU, encoder and its settings, effective smoothing parameters and the thin project data
+
+use std::time::{ Instant, SystemTime, UNIX_EPOCH };
+use std::sync::{ Arc, atomic::{ AtomicU32, Ordering::SeqCst } };
+use parking_lot::RwLock;
+use serde_json::json;
+use super::render_queue::RenderOptions;
+use crate::core::{ StabilizationManager, stabilization::PixelType };
+
+pub struct RenderLog {
+    path: String,
+    data: serde_json::Value,
+    started: Instant,
+    downscaled_level: Arc<AtomicU32>, // Of the render's planes, see `Stabilization::downscaled_level`
+    pub encoder: RwLock<String>,
+}
+
+fn unix_timestamp_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|x| x.as_millis() as u64).unwrap_or_default()
+}
+
+impl RenderLog {
+    /// `<output name>.render.json`, image sequences use the name without the frame number
+    pub fn path_for(output_path: &str) -> String {
+        let path = output_path.replace("_%05d", "");
+        let ext_len = std::path::Path::new(&path).extension().map(|x| x.len() + 1).unwrap_or_default();
+        format!("{}.render.json", &path[..path.len() - ext_len])
+    }
+
+    pub fn new<T: PixelType>(stab: &StabilizationManager<T>, render_options: &RenderOptions) -> Self {
+        let (smoothing_name, smoothing_params, horizon_lock) = {
+            let smoothing_lock = stab.smoothing.read();
+            let smoothing = smoothing_lock.current();
+            (smoothing.get_name(), smoothing.get_parameters_json(), smoothing_lock.horizon_lock.clone())
+        };
+        let params = stab.params.read();
+        let data = json!({
+            "app_version": env!("CARGO_PKG_VERSION"),
+            "started": unix_timestamp_ms(),
+            "input": stab.input_file.read().path,
+            "output": render_options.output_path,
+            "gpu": *super::GPU_NAME.read(),
+            "render_options": render_options,
+            "settings": render_options.settings_string(params.get_scaled_fps()),
+            "smoothing": {
+                "method": smoothing_name,
+                "parameters": smoothing_params,
+                "horizon_lock_amount": horizon_lock.lock_enabled.then(|| horizon_lock.horizonlockpercent),
+                "horizon_lock_roll":   horizon_lock.lock_enabled.then(|| horizon_lock.horizonroll),
+                "horizon_lock_pitch_amount": horizon_lock.pitch_lock_enabled().then(|| horizon_lock.horizonlockpitch),
+            },
+            "fov": params.fov,
+            "min_fov": params.min_fov,
+            "project": stab.export_gyroflow_data(true, false, false, String::new()).ok()
+                .and_then(|x| serde_json::from_str::<serde_json::Value>(&x).ok()),
+        });
+        Self {
+            path: Self::path_for(&render_options.output_path),
+            data,
+            started: Instant::now(),
+            downscaled_level: stab.stabilization.read().downscaled_level.clone(),
+            encoder: RwLock::new(String::new()),
+        }
+    }
+
+    /// Writes the log with the result of the render, `error` is `None` when it succeeded
+    pub fn finish(&self, rendered_frames: usize, gpu_decoder_index: i32, error: Option<String>) -> std::io::Result<()> {
+        let mut data = self.data.clone();
+        if let serde_json::Value::Object(ref mut obj) = data {
+            obj.insert("finished".into(), json!(unix_timestamp_ms()));
+            obj.insert("duration_s".into(), json!(self.started.elapsed().as_secs_f64()));
+            obj.insert("encoder".into(), json!(*self.encoder.read()));
+            obj.insert("gpu_decoder_index".into(), json!(gpu_decoder_index));
+            obj.insert("gpu_processing_downscale".into(), json!(1 << self.downscaled_level.load(SeqCst)));
+            obj.insert("rendered_frames".into(), json!(rendered_frames));
+            obj.insert("pipeline".into(), json!(crate::core::pipeline::stats()));
+            obj.insert("result".into(), json!(if error.is_some() { "error" } else { "ok" }));
+            obj.insert("error".into(), json!(error));
+        }
+        crate::core::filesystem::write(&self.path, serde_json::to_string_pretty(&data)?.as_bytes())
+    }
+}
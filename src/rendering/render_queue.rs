@@ -5,9 +5,11 @@ use qmetaobject::*;
 
 use crate::{ core, rendering, util };
 use crate::core::{ stabilization, StabilizationManager };
+use crate::core::error::{ GyroflowError, ErrorKind };
+use crate::core::output_template::{ self, CollisionPolicy, TemplateVars };
 use std::sync::{ Arc, atomic::{ AtomicBool, AtomicUsize, Ordering::SeqCst } };
 use std::cell::RefCell;
-use std::collections::{ HashMap, HashSet };
+use std::collections::{ BTreeMap, HashMap, HashSet };
 use parking_lot::RwLock;
 use regex::Regex;
 
@@ -23,12 +25,19 @@ pub struct RenderQueueItem {
     pub start_timestamp: u64,
     pub end_timestamp: u64,
     pub error_string: QString,
+    pub notice: QString, // Serialized `JobNotice`, empty if there's none
     pub processing_progress: f64,
+    pub priority: i32, // Jobs with higher priority are rendered first, then in the queue order
+    pub held: bool,    // Not started by the queue until released
 
     status: JobStatus
 }
 impl RenderQueueItem {
     pub fn get_status(&self) -> &JobStatus { &self.status }
+
+    fn set_notice(&mut self, notice: Option<JobNotice>) {
+        self.notice = notice.map(|x| QString::from(serde_json::to_string(&x).unwrap_or_default())).unwrap_or_default();
+    }
 }
 
 #[derive(Default, Clone, Debug, PartialEq)]
@@ -37,8 +46,21 @@ pub enum JobStatus {
     Queued,
     Rendering,
     Finished,
+    Skipped, // Output file already exists and the collision policy is `Skip`
     Error
 }
+
+// Information or a question shown with the job in the queue, the UI builds the message from the `type`
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JobNotice {
+    FileExists { path: String },
+    OutputSkipped { path: String },
+    ConvertFormat { format: String, supported: Vec<String> },
+    UsesCpu,
+    GpuDownscaled { factor: u32 },
+    Cancelled { partial_output: &'static str },
+}
 struct Job {
     queue_index: usize,
     render_options: RenderOptions,
@@ -53,6 +75,8 @@ pub struct RenderOptions {
     pub codec: String,
     pub codec_options: String,
     pub output_path: String,
+    pub output_template: String, // See `core::output_template`, empty means the default "{basename}{suffix}"
+    pub collision_policy: CollisionPolicy,
     pub output_width: usize,
     pub output_height: usize,
     pub bitrate: f64,
@@ -64,20 +88,63 @@ pub struct RenderOptions {
     pub encoder_options: String,
     pub keyframe_distance: f64,
     pub preserve_other_tracks: bool,
+    pub gpmf_passthrough: bool, // Copy the GoPro metadata track (with the face, scene and wind streams) to the output
     pub pad_with_black: bool,
+    pub frame_comparison: i32, // 0 - disabled, 1 - side by side, 2 - stacked
+    pub output_fps: f64, // 0 - same as source
+    pub fps_interpolation: i32, // 0 - nearest frame, 1 - blend neighboring frames
+    pub duplicate_frames: i32, // 0 - keep, 1 - drop, 2 - interpolate with the gyro data, see `core::duplicate_frames`
+    pub deinterlace: i32, // 0 - auto (when the video is flagged as interlaced), 1 - disabled, 2 - always
+    pub color_range: i32, // 0 - same as source, 1 - limited, 2 - full
+    pub max_memory_mb: usize, // Limit for the frames queued for the encoder, shared by all renders. 0 - unlimited
+    pub decoder_threads: usize, // 0 - default for the codec, see `DecoderTuning::resolve`
+    pub decoder_queue_depth: usize,
+    pub decoder_hw_surfaces: usize,
+    pub metadata: BTreeMap<String, String>, // Written to the output container, eg. copyright, artist, comment, make, model
+    pub cropped_output: bool, // Also write `<name>_cropped` with the frames cropped to the area without borders, see `rendering::cropped_output`
+    pub split_duration_s: f64, // Split the output into `<name>_001`, `<name>_002`... of this length, 0 - disabled, see `rendering::chapter_split`
+    pub split_at_gaps: bool, // Also start a new segment where the recording has a gap in the frame timestamps
+    pub cuda_pipeline: bool, // Keep the frames on the NVIDIA GPU from NVDEC to NVENC when possible, see `rendering::ffmpeg_cuda`
+    pub vaapi_pipeline: bool, // Keep the frames on the GPU from the VAAPI decoder to the VAAPI encoder on Linux, see `rendering::ffmpeg_vaapi`
+
+    // Render verification
+    pub verify_frames: Vec<usize>,
+    pub frame_hashes: BTreeMap<usize, String>, // frame index -> expected checksum
+
+    // Muxed output is kept in this buffer, `output_path` only selects the container, see `rendering::memory_output`
+    #[serde(skip)]
+    pub memory_output: Option<rendering::memory_output::MemoryBuffer>,
 }
 impl RenderOptions {
     pub fn settings_string(&self, fps: f64) -> String {
         let codec_info = match self.codec.as_ref() {
             "H.264/AVC" | "H.265/HEVC" => format!("{} {:.0} Mbps", self.codec, self.bitrate),
             "DNxHD" => self.codec_options.clone(),
+            "CineForm" => format!("{} {}", self.codec, self.codec_options),
             "ProRes" => format!("{} {}", self.codec, self.codec_options),
             _ => self.codec.clone()
         };
 
+        let fps = if self.output_fps > 0.0 { self.output_fps } else { fps };
         format!("{}x{} {:.3}fps | {}", self.output_width, self.output_height, fps, codec_info)
     }
 
+    pub fn output_color_range(&self) -> Option<ffmpeg_next::util::color::Range> {
+        match self.color_range {
+            1 => Some(ffmpeg_next::util::color::Range::MPEG),
+            2 => Some(ffmpeg_next::util::color::Range::JPEG),
+            _ => None
+        }
+    }
+
+    pub fn decoder_tuning(&self) -> rendering::ffmpeg_processor::DecoderTuning {
+        rendering::ffmpeg_processor::DecoderTuning {
+            threads:     self.decoder_threads,
+            queue_depth: self.decoder_queue_depth,
+            hw_surfaces: self.decoder_hw_surfaces,
+        }
+    }
+
     pub fn get_encoder_options_dict(&self) -> ffmpeg_next::Dictionary {
         let re = Regex::new(r#"-([^\s"]+)\s+("[^"]+"|[^\s"]+)"#).unwrap();
 
@@ -93,6 +160,27 @@ impl RenderOptions {
         }
         options
     }
+
+    /// First file written by the render. When the output is split, `output_path` itself isn't created, only the numbered segments
+    pub fn first_output_path(&self) -> String {
+        Self::first_file_of(&self.output_path, self.is_split())
+    }
+    /// Whether rendering to `path` with these options would overwrite an existing file
+    pub fn output_exists(&self, path: &str) -> bool {
+        std::path::Path::new(&Self::first_file_of(path, self.is_split())).exists()
+    }
+    fn is_split(&self) -> bool {
+        self.split_duration_s > 0.0 || self.split_at_gaps
+    }
+    // Same condition as in `rendering::render`, segment files can't be created next to a content URI
+    fn first_file_of(path: &str, split: bool) -> String {
+        if split && !core::filesystem::is_content_uri(path) {
+            rendering::chapter_split::ChapterSplit::segment_path(path, "001")
+        } else {
+            path.to_string()
+        }
+    }
+
     pub fn update_from_json(&mut self, obj: &serde_json::Value) {
         if let serde_json::Value::Object(obj) = obj {
             if let Some(v) = obj.get("codec")          .and_then(|x| x.as_str())  { self.codec = v.to_string(); }
@@ -108,7 +196,27 @@ impl RenderOptions {
             if let Some(v) = obj.get("encoder_options")      .and_then(|x| x.as_str())  { self.encoder_options = v.to_string(); }
             if let Some(v)  = obj.get("keyframe_distance")    .and_then(|x| x.as_f64())  { self.keyframe_distance = v; }
             if let Some(v) = obj.get("preserve_other_tracks").and_then(|x| x.as_bool()) { self.preserve_other_tracks = v; }
+            if let Some(v) = obj.get("gpmf_passthrough")     .and_then(|x| x.as_bool()) { self.gpmf_passthrough = v; }
             if let Some(v) = obj.get("pad_with_black")       .and_then(|x| x.as_bool()) { self.pad_with_black = v; }
+            if let Some(v)  = obj.get("frame_comparison")     .and_then(|x| x.as_i64())  { self.frame_comparison = v as i32; }
+            if let Some(v)  = obj.get("output_fps")           .and_then(|x| x.as_f64())  { self.output_fps = v; }
+            if let Some(v)  = obj.get("fps_interpolation")    .and_then(|x| x.as_i64())  { self.fps_interpolation = v as i32; }
+            if let Some(v)  = obj.get("duplicate_frames")     .and_then(|x| x.as_i64())  { self.duplicate_frames = v as i32; }
+            if let Some(v)  = obj.get("deinterlace")          .and_then(|x| x.as_i64())  { self.deinterlace = v as i32; }
+            if let Some(v)  = obj.get("color_range")          .and_then(|x| x.as_i64())  { self.color_range = v as i32; }
+            if let Some(v)  = obj.get("max_memory_mb")        .and_then(|x| x.as_u64())  { self.max_memory_mb = v as usize; }
+            if let Some(v)  = obj.get("decoder_threads")      .and_then(|x| x.as_u64())  { self.decoder_threads = v as usize; }
+            if let Some(v)  = obj.get("decoder_queue_depth")  .and_then(|x| x.as_u64())  { self.decoder_queue_depth = v as usize; }
+            if let Some(v)  = obj.get("decoder_hw_surfaces")  .and_then(|x| x.as_u64())  { self.decoder_hw_surfaces = v as usize; }
+            if let Some(v) = obj.get("verify_frames")        .and_then(|x| x.as_array()) { self.verify_frames = v.iter().filter_map(|x| x.as_u64()).map(|x| x as usize).collect(); }
+            if let Some(v) = obj.get("output_template")      .and_then(|x| x.as_str())  { self.output_template = v.to_string(); }
+            if let Some(v) = obj.get("collision_policy")     .and_then(|x| serde_json::from_value(x.clone()).ok()) { self.collision_policy = v; }
+            if let Some(v) = obj.get("metadata")             .and_then(|x| serde_json::from_value(x.clone()).ok()) { self.metadata = v; }
+            if let Some(v) = obj.get("cropped_output")       .and_then(|x| x.as_bool()) { self.cropped_output = v; }
+            if let Some(v)  = obj.get("split_duration_s")     .and_then(|x| x.as_f64())  { self.split_duration_s = v; }
+            if let Some(v) = obj.get("split_at_gaps")        .and_then(|x| x.as_bool()) { self.split_at_gaps = v; }
+            if let Some(v) = obj.get("cuda_pipeline")        .and_then(|x| x.as_bool()) { self.cuda_pipeline = v; }
+            if let Some(v) = obj.get("vaapi_pipeline")       .and_then(|x| x.as_bool()) { self.vaapi_pipeline = v; }
 
             if let Some(v) = obj.get("output_path").and_then(|x| x.as_str()) {
                 let cur_path = std::path::Path::new(&self.output_path);
@@ -131,6 +239,9 @@ pub struct RenderQueue {
 
     add: qt_method!(fn(&mut self, additional_data: String, thumbnail_url: QString) -> u32),
     remove: qt_method!(fn(&mut self, job_id: u32)),
+    move_job: qt_method!(fn(&mut self, job_id: u32, index: i32)),
+    set_job_priority: qt_method!(fn(&mut self, job_id: u32, priority: i32)),
+    set_job_held: qt_method!(fn(&mut self, job_id: u32, held: bool)),
 
     start: qt_method!(fn(&mut self)),
     pause: qt_method!(fn(&mut self)),
@@ -144,6 +255,7 @@ pub struct RenderQueue {
     add_file: qt_method!(fn(&mut self, path: String, additional_data: String) -> u32),
 
     get_job_output_path: qt_method!(fn(&self, job_id: u32) -> QString),
+    get_job_rendered_file: qt_method!(fn(&self, job_id: u32) -> QString),
     set_job_output_path: qt_method!(fn(&mut self, job_id: u32, new_path: String)),
 
     set_pixel_format: qt_method!(fn(&mut self, job_id: u32, format: String)),
@@ -169,9 +281,13 @@ pub struct RenderQueue {
 
     pub render_progress: qt_signal!(job_id: u32, progress: f64, current_frame: usize, total_frames: usize, finished: bool),
     pub encoder_initialized: qt_signal!(job_id: u32, encoder_name: String),
+    pub frame_hash_mismatch: qt_signal!(job_id: u32, frame: usize, expected: QString, actual: QString),
+    pub frame_hashes_recorded: qt_signal!(job_id: u32, hashes: QString),
+    pub memory_usage: qt_signal!(used_mb: f64, peak_mb: f64, limit_mb: f64, waits: usize),
 
     pub convert_format: qt_signal!(job_id: u32, format: QString, supported: QString),
-    pub error: qt_signal!(job_id: u32, text: QString, arg: QString, callback: QString),
+    pub encoder_unsupported: qt_signal!(job_id: u32, issues_json: QString),
+    pub error: qt_signal!(job_id: u32, error_json: QString),
     pub added: qt_signal!(job_id: u32),
     pub processing_done: qt_signal!(job_id: u32, by_preset: bool),
     pub processing_progress: qt_signal!(job_id: u32, progress: f64),
@@ -181,7 +297,14 @@ pub struct RenderQueue {
 
     apply_to_all: qt_method!(fn(&mut self, data: String, additional_data: String)),
 
+    render_preview: qt_method!(fn(&mut self, additional_data: String, from_ratio: f64, to_ratio: f64)),
+    cancel_preview: qt_method!(fn(&mut self)),
+    pub preview_progress: qt_signal!(progress: f64, current_frame: usize, total_frames: usize),
+    pub preview_ready: qt_signal!(url: QString),
+    pub preview_error: qt_signal!(error_json: QString),
+
     pause_flag: Arc<AtomicBool>,
+    preview_cancel_flag: Arc<AtomicBool>,
 
     pub default_suffix: qt_property!(QString),
 
@@ -196,6 +319,8 @@ pub struct RenderQueue {
     pub jobs_added: HashSet<u32>,
 
     paused_timestamp: Option<u64>,
+    waiting_for_held: bool, // The queue finished with only held jobs left, releasing one of them starts the queue again
+    restored_queue_state: HashMap<u32, (i32, bool)>, // Priority and hold of the jobs from `restore_render_queue` which are still loading
 
     stabilizer: Arc<StabilizationManager<stabilization::RGBA8>>,
 }
@@ -248,6 +373,7 @@ impl RenderQueue {
         }
         update_model!(self, job_id, itm {
             itm.error_string = QString::default();
+            itm.set_notice(None);
             itm.status = JobStatus::Queued;
         });
         if self.status.to_string() != "active" {
@@ -262,6 +388,7 @@ impl RenderQueue {
         update_model!(self, job_id, itm {
             itm.output_path = QString::from(new_path);
             itm.error_string = QString::default();
+            itm.set_notice(None);
             itm.status = JobStatus::Queued;
         });
         if self.status.to_string() != "active" {
@@ -272,6 +399,7 @@ impl RenderQueue {
     pub fn set_error_string(&mut self, job_id: u32, err: QString) {
         update_model!(self, job_id, itm {
             itm.error_string = err;
+            itm.set_notice(None);
             itm.status = JobStatus::Error;
         });
     }
@@ -307,6 +435,7 @@ impl RenderQueue {
         let video_path = stab.input_file.read().path.clone();
 
         let editing = self.jobs.contains_key(&job_id);
+        let (priority, held) = self.restored_queue_state.remove(&job_id).unwrap_or_default();
 
         if editing {
             update_model!(self, job_id, itm {
@@ -318,6 +447,7 @@ impl RenderQueue {
                 itm.start_timestamp = 0;
                 itm.end_timestamp = 0;
                 itm.error_string = QString::default();
+                itm.set_notice(None);
                 itm.status = JobStatus::Queued;
             });
         } else {
@@ -334,6 +464,9 @@ impl RenderQueue {
                 end_timestamp: 0,
                 processing_progress: 0.0,
                 error_string: QString::default(),
+                notice: QString::default(),
+                priority,
+                held,
                 status: JobStatus::Queued,
             });
         }
@@ -351,6 +484,11 @@ impl RenderQueue {
         self.added(job_id);
     }
 
+    /// File to open after the render, the first segment when the output is split
+    pub fn get_job_rendered_file(&self, job_id: u32) -> QString {
+        self.jobs.get(&job_id).map(|job| QString::from(job.render_options.first_output_path())).unwrap_or_default()
+    }
+
     pub fn get_job_output_path(&self, job_id: u32) -> QString {
         let q = self.queue.borrow();
         if let Some(job) = self.jobs.get(&job_id) {
@@ -372,6 +510,36 @@ impl RenderQueue {
         self.jobs.remove(&job_id);
         self.update_queue_indices();
     }
+    /// Moves the job to `index` in the queue, clamped to the queue length
+    pub fn move_job(&mut self, job_id: u32, index: i32) {
+        if let Some(job) = self.jobs.get(&job_id) {
+            let from = job.queue_index;
+            {
+                let mut q = self.queue.borrow_mut();
+                let count = q.row_count() as usize;
+                if from >= count { return; }
+                let to = (index.max(0) as usize).min(count - 1);
+                if to == from { return; }
+                let itm = q[from].clone();
+                q.remove(from);
+                q.insert(to, itm);
+            }
+            self.update_queue_indices();
+            self.queue_changed();
+        }
+    }
+    pub fn set_job_priority(&mut self, job_id: u32, priority: i32) {
+        update_model!(self, job_id, itm { itm.priority = priority; });
+        self.queue_changed();
+    }
+    /// Held jobs stay in the queue, but aren't started until released. Doesn't affect a job which is already rendering
+    pub fn set_job_held(&mut self, job_id: u32, held: bool) {
+        update_model!(self, job_id, itm { itm.held = held; });
+        self.queue_changed();
+        if !held && (self.status.to_string() == "active" || self.waiting_for_held) {
+            self.start();
+        }
+    }
     fn update_queue_indices(&mut self) {
         for (i, v) in self.queue.borrow().iter().enumerate() {
             if let Some(job) = self.jobs.get_mut(&v.job_id) {
@@ -394,6 +562,7 @@ impl RenderQueue {
             job.cancel_flag.store(false, SeqCst);
         }
         self.pause_flag.store(false, SeqCst);
+        self.waiting_for_held = false;
 
         self.status = QString::from("active");
         self.status_changed();
@@ -422,13 +591,11 @@ impl RenderQueue {
                     break;
                 }
 
-                let mut job_id = None;
-                for v in self.queue.borrow().iter() {
-                    if v.current_frame == 0 && v.total_frames > 0 && v.status == JobStatus::Queued && (v.processing_progress == 0.0 || v.processing_progress == 1.0) {
-                        job_id = Some(v.job_id);
-                        break;
-                    }
-                }
+                // Highest priority first, the queue order for the same priority
+                let job_id = self.queue.borrow().iter()
+                    .filter(|v| v.current_frame == 0 && v.total_frames > 0 && v.status == JobStatus::Queued && !v.held && (v.processing_progress == 0.0 || v.processing_progress == 1.0))
+                    .fold(None, |best: Option<&RenderQueueItem>, v| if best.map_or(true, |b| v.priority > b.priority) { Some(v) } else { best })
+                    .map(|v| v.job_id);
                 if let Some(job_id) = job_id {
                     self.render_job(job_id, false);
                 } else {
@@ -439,6 +606,8 @@ impl RenderQueue {
                         self.start_timestamp = 0;
                         self.progress_changed();
 
+                        // Held jobs are still pending, releasing one of them starts the queue again
+                        self.waiting_for_held = self.queue.borrow().iter().any(|v| v.held && v.status == JobStatus::Queued);
                         self.status = QString::from("stopped");
                         self.status_changed();
                     }
@@ -456,6 +625,7 @@ impl RenderQueue {
     }
     pub fn stop(&mut self) {
         self.pause_flag.store(false, SeqCst);
+        self.waiting_for_held = false;
         for (_id, job) in self.jobs.iter() {
             job.cancel_flag.store(true, SeqCst);
         }
@@ -518,6 +688,80 @@ impl RenderQueue {
         }
     }
 
+    // Runs the whole export pipeline (including the encoder) for a short range of the current file, so the encoder settings
+    // and quality can be checked in the player before the full render. The output is kept in memory and passed to the player as a data URL.
+    pub fn render_preview(&mut self, additional_data: String, from_ratio: f64, to_ratio: f64) {
+        self.cancel_preview();
+
+        let mut render_options = match serde_json::from_str::<serde_json::Value>(&additional_data).ok().and_then(|obj| serde_json::from_value::<RenderOptions>(obj.get("output")?.clone()).ok()) {
+            Some(x) => x,
+            None => return
+        };
+        if render_options.codec.contains("PNG") || render_options.codec.contains("EXR") {
+            self.preview_error(QString::from(GyroflowError::generic(core::i18n::Message::new("error.preview_image_sequence").to_string()).to_json()));
+            return;
+        }
+
+        let ext = std::path::Path::new(&render_options.output_path).extension().map(|x| x.to_string_lossy().to_string()).unwrap_or_else(|| "mp4".into());
+        let mime = if ext.eq_ignore_ascii_case("mov") { "video/quicktime".to_string() } else { format!("video/{}", ext.to_ascii_lowercase()) };
+        let output = Arc::new(parking_lot::Mutex::new(Vec::new()));
+        render_options.output_path = format!("preview.{}", ext);
+        render_options.memory_output = Some(output.clone());
+        render_options.pad_with_black = false;
+        render_options.preserve_other_tracks = false;
+        render_options.cropped_output = false;
+        render_options.split_duration_s = 0.0;
+        render_options.split_at_gaps = false;
+        render_options.frame_hashes.clear();
+
+        let stab = Arc::new(self.stabilizer.get_cloned());
+        let size = stab.params.read().video_size;
+        stab.set_render_params(size, (render_options.output_width, render_options.output_height));
+        {
+            let mut params = stab.params.write();
+            params.trim_start = from_ratio.clamp(0.0, 1.0);
+            params.trim_end = to_ratio.clamp(params.trim_start, 1.0);
+        }
+        let input_file = stab.input_file.read().clone();
+
+        let cancel_flag = Arc::new(AtomicBool::new(false));
+        self.preview_cancel_flag = cancel_flag.clone();
+
+        // Results are queued to the UI thread, so the cancel flag is checked again there
+        let cancel_flag2 = cancel_flag.clone();
+        let progress = util::qt_queued_callback_mut(self, move |this, (progress, current_frame, total_frames, _finished): (f64, usize, usize, bool)| {
+            if !cancel_flag2.load(SeqCst) {
+                this.preview_progress(progress, current_frame, total_frames);
+            }
+        });
+        let cancel_flag2 = cancel_flag.clone();
+        let ready = util::qt_queued_callback_mut(self, move |this, data: Vec<u8>| {
+            if !cancel_flag2.load(SeqCst) {
+                this.preview_ready(util::data_to_url(&mime, &data));
+            }
+        });
+        let cancel_flag2 = cancel_flag.clone();
+        let err = util::qt_queued_callback_mut(self, move |this, e: GyroflowError| {
+            if !cancel_flag2.load(SeqCst) {
+                this.preview_error(QString::from(e.append_context(&rendering::get_log()).to_json()));
+            }
+        });
+
+        rendering::clear_log();
+
+        core::run_threaded(move || {
+            let result = rendering::render(stab, progress, &input_file, &render_options, 0, cancel_flag.clone(), Default::default(), Default::default(), |_| { }, |_| { });
+            match result {
+                Err(rendering::FFmpegError::Cancelled(_)) => { }
+                Err(e) => err(GyroflowError::from_message(e.to_string())),
+                Ok(_) => ready(std::mem::take(&mut *output.lock()))
+            }
+        });
+    }
+    pub fn cancel_preview(&mut self) {
+        self.preview_cancel_flag.store(true, SeqCst);
+    }
+
     pub fn cancel_job(&self, job_id: u32) {
         if let Some(job) = self.jobs.get(&job_id) {
             job.cancel_flag.store(true, SeqCst);
@@ -529,6 +773,7 @@ impl RenderQueue {
         }
         update_model!(self, job_id, itm {
             itm.error_string = QString::default();
+            itm.set_notice(None);
             itm.current_frame = 0;
             itm.status = JobStatus::Queued;
         });
@@ -550,7 +795,10 @@ impl RenderQueue {
         let mut all = Vec::new();
         for v in self.queue.borrow().iter() {
             if v.total_frames > 0 && v.status != JobStatus::Finished {
-                if let Ok(data) = serde_json::from_str(&self.get_gyroflow_data(v.job_id).to_string()) as serde_json::Result<serde_json::Value> {
+                if let Ok(mut data) = serde_json::from_str(&self.get_gyroflow_data(v.job_id).to_string()) as serde_json::Result<serde_json::Value> {
+                    if let serde_json::Value::Object(ref mut obj) = data {
+                        obj.insert("render_queue".into(), serde_json::json!({ "priority": v.priority, "held": v.held }));
+                    }
                     all.push(data);
                 }
             }
@@ -561,8 +809,15 @@ impl RenderQueue {
     pub fn restore_render_queue(&mut self, json: String, additional_data: String) {
         if let Ok(serde_json::Value::Array(val)) = serde_json::from_str(&json) as serde_json::Result<serde_json::Value> {
             for x in val {
+                let state = x.get("render_queue").map(|q| (
+                    q.get("priority").and_then(|v| v.as_i64()).unwrap_or_default() as i32,
+                    q.get("held").and_then(|v| v.as_bool()).unwrap_or_default()
+                ));
                 if let Ok(data) = serde_json::to_string(&x) {
-                    self.add_file(data, additional_data.clone());
+                    let job_id = self.add_file(data, additional_data.clone());
+                    if let Some(state) = state {
+                        self.restored_queue_state.insert(job_id, state);
+                    }
                 }
             }
         }
@@ -577,7 +832,7 @@ impl RenderQueue {
                 }
                 additional_data = serde_json::to_string(&obj).unwrap_or_default();
             }
-            if let Ok(data) = job.stab.export_gyroflow_data(true, false, additional_data) {
+            if let Ok(data) = job.stab.export_gyroflow_data(true, false, false, additional_data) {
                 return QString::from(data);
             }
         }
@@ -611,9 +866,15 @@ impl RenderQueue {
             let stab = job.stab.clone();
 
             rendering::clear_log();
+            let downscaled_level = stab.stabilization.read().downscaled_level.clone();
+            downscaled_level.store(0, SeqCst);
 
             let rendered_frames = Arc::new(AtomicUsize::new(0));
             let rendered_frames2 = rendered_frames.clone();
+            let hashes_recorded = Arc::new(AtomicBool::new(false));
+            let hashes_recorded2 = hashes_recorded.clone();
+            let memory_budget = Arc::new(rendering::frame_pool::MemoryBudget::default());
+            let memory_budget2 = memory_budget.clone();
             let progress = util::qt_queued_callback_mut(self, move |this, (progress, current_frame, total_frames, finished): (f64, usize, usize, bool)| {
                 rendered_frames2.store(current_frame, SeqCst);
 
@@ -627,12 +888,27 @@ impl RenderQueue {
                     if finished {
                         itm.status = JobStatus::Finished;
                     }
+                    // Frame was too large for the GPU and is processed at a lower resolution
+                    let mip_level = downscaled_level.load(SeqCst);
+                    if mip_level > 0 && itm.notice.is_empty() {
+                        itm.set_notice(Some(JobNotice::GpuDownscaled { factor: 1 << mip_level }));
+                    }
                 });
 
                 this.end_timestamp = Self::current_timestamp();
+                if finished && hashes_recorded2.swap(false, SeqCst) {
+                    // New reference checksums, so they can be saved with the project
+                    if let Some(job) = this.jobs.get(&job_id) {
+                        let hashes = serde_json::to_string(&job.render_options.frame_hashes).unwrap_or_default();
+                        this.frame_hashes_recorded(job_id, QString::from(hashes));
+                    }
+                }
                 this.render_progress(job_id, progress, current_frame, total_frames, finished);
                 this.progress_changed();
 
+                let mem = memory_budget2.stats();
+                this.memory_usage(mem.used_mb, mem.peak_mb, mem.limit_mb, mem.waits);
+
                 if finished {
                     if !single {
                         // Start the next one
@@ -647,23 +923,39 @@ impl RenderQueue {
                 if let Some(job) = this.jobs.get(&job_id) {
                     if job.render_options.use_gpu && (encoder_name == "libx264" || encoder_name == "libx265" || encoder_name == "prores_ks") {
                         update_model!(this, job_id, itm {
-                            itm.error_string = QString::from("uses_cpu");
+                            itm.set_notice(Some(JobNotice::UsesCpu));
                         });
                     }
                 }
                 this.encoder_initialized(job_id, encoder_name);
             });
+            let frame_hashed = util::qt_queued_callback_mut(self, move |this, (frame, hash): (usize, String)| {
+                let mut expected = None;
+                if let Some(job) = this.jobs.get_mut(&job_id) {
+                    expected = job.render_options.frame_hashes.get(&frame).cloned();
+                    if expected.is_none() {
+                        // First render stores the reference checksum
+                        job.render_options.frame_hashes.insert(frame, hash.clone());
+                        hashes_recorded.store(true, SeqCst);
+                    }
+                }
+                if let Some(expected) = expected {
+                    if expected != hash {
+                        ::log::warn!("Frame {} checksum mismatch, expected: {}, got: {}", frame, expected, hash);
+                        this.frame_hash_mismatch(job_id, frame, QString::from(expected), QString::from(hash));
+                    }
+                }
+            });
 
-            let err = util::qt_queued_callback_mut(self, move |this, (msg, mut arg): (String, String)| {
-                arg.push_str("\n\n");
-                arg.push_str(&rendering::get_log());
+            let err = util::qt_queued_callback_mut(self, move |this, e: GyroflowError| {
+                let e = e.append_context(&rendering::get_log());
 
                 update_model!(this, job_id, itm {
-                    itm.error_string = QString::from(arg.clone());
+                    itm.error_string = QString::from(e.context.clone());
                     itm.status = JobStatus::Error;
                 });
 
-                this.error(job_id, QString::from(msg), QString::from(arg), QString::default());
+                this.error(job_id, QString::from(e.to_json()));
                 this.render_progress(job_id, 1.0, 0, 0, true);
 
                 if !single {
@@ -681,7 +973,7 @@ impl RenderQueue {
                     .join(",");
 
                 update_model!(this, job_id, itm {
-                    itm.error_string = QString::from(format!("convert_format:{};{}", format, supported));
+                    itm.set_notice(Some(JobNotice::ConvertFormat { format: format.clone(), supported: supported.split(',').map(str::to_string).collect() }));
                     itm.status = JobStatus::Error;
                 });
 
@@ -694,9 +986,47 @@ impl RenderQueue {
                 }
                 this.update_status();
             });
+            let encoder_unsupported = util::qt_queued_callback_mut(self, move |this, issues: Vec<rendering::EncoderProbeIssue>| {
+                let message = issues.iter().map(|x| x.message.as_str()).collect::<Vec<_>>().join("\n");
+                update_model!(this, job_id, itm {
+                    itm.error_string = QString::from(message);
+                    itm.status = JobStatus::Error;
+                });
+
+                this.encoder_unsupported(job_id, QString::from(serde_json::to_string(&issues).unwrap_or_default()));
+                this.render_progress(job_id, 1.0, 0, 0, true);
+
+                if !single {
+                    // Start the next one
+                    this.start();
+                }
+                this.update_status();
+            });
+            let cancelled = util::qt_queued_callback_mut(self, move |this, partial_output: rendering::PartialOutput| {
+                let partial_output = match partial_output {
+                    rendering::PartialOutput::Kept         => "kept",
+                    rendering::PartialOutput::Removed      => "removed",
+                    rendering::PartialOutput::RemoveFailed => "remove_failed",
+                };
+                update_model!(this, job_id, itm {
+                    // The job could have been reset in the meantime
+                    if itm.status == JobStatus::Rendering {
+                        itm.set_notice(Some(JobNotice::Cancelled { partial_output }));
+                        itm.status = JobStatus::Error;
+                    }
+                });
+
+                this.render_progress(job_id, 1.0, 0, 0, true);
+
+                // Cancelling stops the queue, `start` would clear the cancel flags of the other jobs
+                this.update_status();
+            });
             let params = stab.params.read();
             let trim_ratio = params.trim_end - params.trim_start;
             let total_frame_count = params.frame_count;
+            let fps = params.fps;
+            let (duration_ms, scaled_fps) = (params.get_scaled_duration_ms() * trim_ratio, params.get_scaled_fps());
+            let has_alpha = params.background[3] < 255.0;
             drop(params);
             let input_file = stab.input_file.read().clone();
             let render_options = job.render_options.clone();
@@ -715,26 +1045,73 @@ impl RenderQueue {
                     }
                     additional_data = serde_json::to_string(&obj).unwrap_or_default();
                 }
-                let path = std::path::Path::new(&render_options.output_path.replace(&self.default_suffix.to_string(), "")).with_extension("gyroflow");
+                let path = std::path::Path::new(&render_options.output_path.replace(&self.default_suffix.to_string(), "")).with_extension("gyroflow").to_string_lossy().to_string();
                 let result = match self.export_project {
-                    1 => job.stab.export_gyroflow_file(&path, true, false, additional_data),
-                    2 => job.stab.export_gyroflow_file(&path, false, false, additional_data),
-                    3 => job.stab.export_gyroflow_file(&path, false, true, additional_data),
+                    1 => job.stab.export_gyroflow_file(&path, true,  false, false, additional_data),
+                    2 => job.stab.export_gyroflow_file(&path, false, false, false, additional_data),
+                    3 => job.stab.export_gyroflow_file(&path, false, true,  false, additional_data),
                     _ => { Err(std::io::Error::new(std::io::ErrorKind::Other, "Unknown option")) }
                 };
                 if let Err(e) = result {
-                    err((e.to_string(), String::new()));
+                    err(GyroflowError::from(e));
                 } else {
                     progress((1.0, 1, 1, true));
                 }
                 return;
             }
 
+            // Registered in the main stabilizer, so the interactive computations can yield to the render
+            let render_job = self.stabilizer.jobs.start(core::job_manager::JobKind::Render);
+
             core::run_threaded(move || {
+                let _render_job = render_job;
+
+                // The UI asks about overwriting and low disk space before adding the job, here only refuse to destroy the source
+                let preflight = rendering::preflight::check(&render_options, &input_file.path, duration_ms, scaled_fps);
+                if !preflight.can_render() {
+                    err(GyroflowError::from_message(format!("Output file {} is the same as the input video.", preflight.output_path)));
+                    return;
+                }
+
+                // Pre-flight check, so unsupported encoder settings are reported before decoding anything
+                match rendering::probe_encoder(&input_file, &render_options, fps, has_alpha) {
+                    Err(rendering::FFmpegError::PixelFormatNotSupported((fmt, supported))) => {
+                        convert_format((format!("{:?}", fmt), supported.into_iter().map(|v| format!("{:?}", v)).collect::<Vec<String>>().join(",")));
+                        return;
+                    }
+                    Err(rendering::FFmpegError::EncoderUnsupported(issues)) => {
+                        encoder_unsupported(issues);
+                        return;
+                    }
+                    Err(e) => { ::log::warn!("Encoder probe failed: {}", e); }
+                    Ok(_) => { }
+                }
+
+                // Sidecar `.render.json` with the effective parameters, see `rendering::render_log`
+                let render_log = Arc::new(rendering::render_log::RenderLog::new(&*stab, &render_options));
+                let encoder_initialized = {
+                    let render_log = render_log.clone();
+                    move |encoder_name: String| {
+                        *render_log.encoder.write() = encoder_name.clone();
+                        encoder_initialized(encoder_name);
+                    }
+                };
+                let write_render_log = |i: i32, error: Option<String>| {
+                    if let Err(e) = render_log.finish(rendered_frames.load(SeqCst), i, error) {
+                        ::log::warn!("Failed to write the render log: {:?}", e);
+                    }
+                };
+
                 let mut i = 0;
                 loop {
-                    let result = rendering::render(stab.clone(), progress.clone(), &input_file, &render_options, i, cancel_flag.clone(), pause_flag.clone(), encoder_initialized.clone());
+                    let result = rendering::render(stab.clone(), progress.clone(), &input_file, &render_options, i, cancel_flag.clone(), pause_flag.clone(), memory_budget.clone(), encoder_initialized.clone(), frame_hashed.clone());
                     if let Err(e) = result {
+                        if let rendering::FFmpegError::Cancelled(partial_output) = e {
+                            ::log::info!("Render cancelled after {} frames, partial output: {:?}", rendered_frames.load(SeqCst), partial_output);
+                            write_render_log(i, Some(e.to_string()));
+                            cancelled(partial_output);
+                            break;
+                        }
                         if let rendering::FFmpegError::PixelFormatNotSupported((fmt, supported)) = e {
                             convert_format((format!("{:?}", fmt), supported.into_iter().map(|v| format!("{:?}", v)).collect::<Vec<String>>().join(",")));
                             break;
@@ -751,10 +1128,12 @@ impl RenderQueue {
                                 continue;
                             }
                         }
-                        err(("An error occured: %1".to_string(), e.to_string()));
+                        write_render_log(i, Some(e.to_string()));
+                        err(GyroflowError::from_message(e.to_string()));
                         break;
                     } else {
                         // Render ok
+                        write_render_log(i, None);
                         break;
                     }
                 }
@@ -762,9 +1141,11 @@ impl RenderQueue {
         }
     }
 
-    fn get_output_path(suffix: &str, path: &str, codec: &str, ui_output_path: &str) -> String {
+    // `is_taken` is used to find the value of `{counter}` in the filename template
+    fn get_output_path(suffix: &str, path: &str, render_options: &RenderOptions, mut vars: TemplateVars, is_taken: impl Fn(&str) -> bool) -> String {
         use std::path::Path;
 
+        let ui_output_path = render_options.output_path.as_str();
         let mut path = Path::new(path).with_extension("");
 
         if !ui_output_path.is_empty() {
@@ -778,31 +1159,64 @@ impl RenderQueue {
             }
         }
 
-        let ext = match codec {
+        let ext = match render_options.codec.as_str() {
             "ProRes"        => ".mov",
             "DNxHD"         => ".mov",
+            "CineForm"      => ".mov",
             "EXR Sequence"  => "_%05d.exr",
             "PNG Sequence"  => "_%05d.png",
             _ => ".mp4"
         };
 
-        path.set_file_name(format!("{}{}{}", path.file_name().map(|v| v.to_string_lossy()).unwrap_or_default(), suffix, ext));
+        vars.insert("basename", path.file_name().map(|v| v.to_string_lossy().to_string()).unwrap_or_default());
+        vars.insert("suffix",   suffix.to_string());
+        vars.insert("codec",    render_options.codec.clone());
+        vars.insert("w",        render_options.output_width.to_string());
+        vars.insert("h",        render_options.output_height.to_string());
+
+        let name = output_template::evaluate(&render_options.output_template, &vars, |name| {
+            is_taken(&path.with_file_name(format!("{}{}", name, ext)).to_string_lossy().replace('\\', "/"))
+        });
+        path.set_file_name(format!("{}{}", name, ext));
 
         path.to_string_lossy().replace('\\', "/")
     }
 
+    fn taken_output_paths(&self) -> HashSet<String> {
+        self.jobs.values().map(|job| job.render_options.output_path.clone()).collect()
+    }
+
+    // Applies the collision policy if the output file already exists or is used by another job in the queue.
+    // Returns the notice for the queue item if the job can't be rendered as is
+    fn resolve_output_collision(render_options: &mut RenderOptions, taken: &HashSet<String>) -> Option<JobNotice> {
+        let split = render_options.is_split();
+        let is_taken = |p: &str| taken.contains(p) || std::path::Path::new(&RenderOptions::first_file_of(p, split)).exists();
+        if !is_taken(&render_options.output_path) {
+            return None;
+        }
+        match render_options.collision_policy {
+            CollisionPolicy::Ask       => Some(JobNotice::FileExists { path: render_options.output_path.clone() }),
+            CollisionPolicy::Overwrite => None,
+            CollisionPolicy::Rename    => {
+                render_options.output_path = output_template::unique_path(&render_options.output_path, is_taken);
+                None
+            },
+            CollisionPolicy::Skip      => Some(JobNotice::OutputSkipped { path: render_options.output_path.clone() })
+        }
+    }
+
     pub fn add_file(&mut self, path: String, additional_data: String) -> u32 {
         let job_id = fastrand::u32(1..);
 
         let is_gf_data = path.starts_with('{');
 
-        let err = util::qt_queued_callback_mut(self, move |this, (msg, arg): (String, String)| {
-            ::log::warn!("[add_file]: {}", arg);
+        let err = util::qt_queued_callback_mut(self, move |this, e: GyroflowError| {
+            ::log::warn!("[add_file]: {}", e);
             update_model!(this, job_id, itm {
-                itm.error_string = QString::from(arg.clone());
+                itm.error_string = QString::from(e.context.clone());
                 itm.status = JobStatus::Error;
             });
-            this.error(job_id, QString::from(msg), QString::from(arg), QString::default());
+            this.error(job_id, QString::from(e.to_json()));
         });
         let processing = util::qt_queued_callback_mut(self, move |this, progress: f64| {
             update_model!(this, job_id, itm {
@@ -814,8 +1228,6 @@ impl RenderQueue {
             this.processing_done(job_id, false);
         });
 
-        let suffix = self.default_suffix.to_string();
-
         let stabilizer = self.stabilizer.clone();
 
         let additional_data2 = additional_data.clone();
@@ -845,7 +1257,7 @@ impl RenderQueue {
                             background_margin_feather: params.background_margin_feather,
                             ..Default::default()
                         })),
-                        input_file: Arc::new(RwLock::new(gyroflow_core::InputFile { path: path.clone(), image_sequence_start: 0, image_sequence_fps: 0.0 })),
+                        input_file: Arc::new(RwLock::new(gyroflow_core::InputFile { path: path.clone(), image_sequence_start: 0, image_sequence_fps: 0.0, video_filter: String::new() })),
                         lens_profile_db: stabilizer.lens_profile_db.clone(),
                         ..Default::default()
                     };
@@ -873,17 +1285,28 @@ impl RenderQueue {
                     let stab = Arc::new(stab);
 
                     let stab2 = stab.clone();
-                    let loaded = util::qt_queued_callback_mut(self, move |this, (render_options, ask_path): (RenderOptions, bool)| {
+                    let path2 = path.clone();
+                    let loaded = util::qt_queued_callback_mut(self, move |this, (mut render_options, generate_path): (RenderOptions, bool)| {
+                        let taken = this.taken_output_paths();
+                        if generate_path {
+                            // Generated here and not in the loading thread, so the counters of a batch see each other's paths
+                            render_options.output_path = Self::get_output_path(&this.default_suffix.to_string(), &path2, &render_options, stab2.output_template_vars(), |p| {
+                                taken.contains(p) || render_options.output_exists(p)
+                            });
+                        }
+                        let collision = Self::resolve_output_collision(&mut render_options, &taken);
                         let out_path = render_options.output_path.clone();
                         this.add_internal(job_id, stab2.clone(), render_options, additional_data2.clone(), QString::default());
 
-                        if ask_path && std::path::Path::new(&out_path).exists() {
-                            let msg = QString::from(format!("file_exists:{}", out_path));
+                        if let Some(notice) = collision {
+                            let ask = matches!(notice, JobNotice::FileExists { .. });
                             update_model!(this, job_id, itm {
-                                itm.error_string = msg.clone();
-                                itm.status = JobStatus::Error;
+                                itm.status = if ask { JobStatus::Error } else { JobStatus::Skipped };
+                                itm.set_notice(Some(notice));
                             });
-                            this.error(job_id, msg, QString::default(), QString::default());
+                            if ask {
+                                this.error(job_id, QString::from(GyroflowError::new(ErrorKind::OutputExists, out_path).to_json()));
+                            }
                         }
                     });
                     let thumb_fetched = util::qt_queued_callback_mut(self, move |this, thumb: QString| {
@@ -898,6 +1321,14 @@ impl RenderQueue {
                         let fetch_thumb = |video_path: &str, ratio: f64| -> Result<(), rendering::FFmpegError> {
                             let mut fetched = false;
                             if !crate::cli::will_run_in_console() { // Don't fetch thumbs in the CLI
+                                let input_file = core::InputFile { path: video_path.to_string(), ..Default::default() };
+                                if let Some(server) = rendering::frame_server::shared(&input_file) {
+                                    return server?.lock().frame_at(0, |_timestamp_us, input_frame, converter| {
+                                        let sf = converter.scale(input_frame, ffmpeg_next::format::Pixel::RGBA, (50.0 * ratio).round() as u32, 50)?;
+                                        thumb_fetched(util::image_data_to_base64(sf.plane_width(0), sf.plane_height(0), sf.stride(0) as u32, sf.data(0)));
+                                        Ok(())
+                                    });
+                                }
                                 let mut proc = rendering::VideoProcessor::from_file(video_path, false, 0, None)?;
                                 proc.on_frame(move |_timestamp_us, input_frame, _output_frame, converter, _rate_control| {
                                     let sf = converter.scale(input_frame, ffmpeg_next::format::Pixel::RGBA, (50.0 * ratio).round() as u32, 50)?;
@@ -917,14 +1348,14 @@ impl RenderQueue {
                         if is_gf_data || path.ends_with(".gyroflow") {
                             if !is_gf_data {
                                 let video_path = || -> Option<String> {
-                                    let data = std::fs::read(&path).ok()?;
+                                    let data = core::filesystem::read(&path).ok()?;
                                     let obj: serde_json::Value = serde_json::from_slice(&data).ok()?;
                                     Some(obj.get("videofile")?.as_str()?.to_string())
                                 }().unwrap_or_default();
 
                                 if video_path.is_empty() {
                                     // It's a preset
-                                    if let Ok(data) = std::fs::read_to_string(&path) {
+                                    if let Ok(data) = core::filesystem::read_to_string(&path) {
                                         apply_preset(data);
                                     }
                                     return;
@@ -941,7 +1372,7 @@ impl RenderQueue {
                                 Ok(obj) => {
                                     if let Some(out) = obj.get("output") {
                                         if let Ok(render_options2) = serde_json::from_value(out.clone()) as serde_json::Result<RenderOptions> {
-                                            loaded((render_options2, true));
+                                            loaded((render_options2, false));
                                         }
                                     }
                                     if let Some(out) = obj.get("videofile").and_then(|x| x.as_str()) {
@@ -951,51 +1382,64 @@ impl RenderQueue {
                                         };
 
                                         if let Err(e) = fetch_thumb(out, ratio) {
-                                            err(("An error occured: %1".to_string(), e.to_string()));
+                                            err(GyroflowError::from_message(e.to_string()));
                                         }
                                     }
                                     processing_done(());
                                 },
                                 Err(e) => {
-                                    err(("An error occured: %1".to_string(), format!("Error loading {}: {:?}", path, e)));
+                                    err(GyroflowError::new(ErrorKind::InvalidProject, core::i18n::Message::new("error.loading_file").arg(&path).arg(format!("{:?}", e)).to_string()));
                                 }
                             }
                         } else if let Ok(info) = rendering::FfmpegProcessor::get_video_info(&path) {
                             ::log::info!("Loaded {:?}", &info);
 
+                            stab.set_pixel_aspect_ratio(info.pixel_aspect_ratio);
+                            let display_size = {
+                                let mut params = stab.params.write();
+                                params.video_size = (info.width as usize, info.height as usize);
+                                params.get_display_size()
+                            };
+
                             render_options.bitrate = render_options.bitrate.max(info.bitrate);
-                            render_options.output_width = info.width as usize;
-                            render_options.output_height = info.height as usize;
-                            render_options.output_path = Self::get_output_path(&suffix, &path, &render_options.codec, &render_options.output_path);
+                            render_options.output_width = display_size.0;
+                            render_options.output_height = display_size.1;
 
-                            let ratio = info.width as f64 / info.height as f64;
+                            let ratio = display_size.0 as f64 / display_size.1 as f64;
 
                             if info.duration_ms > 0.0 && info.fps > 0.0 {
 
                                 let video_size = (info.width as usize, info.height as usize);
 
                                 if let Err(e) = stab.init_from_video_data(&path, info.duration_ms, info.fps, info.frame_count, video_size) {
-                                    err(("An error occured: %1".to_string(), e.to_string()));
+                                    err(GyroflowError::from_message(e.to_string()));
                                     return;
                                 }
                                 let _ = stab.load_gyro_data(&path, |_|(), Arc::new(AtomicBool::new(false)));
                                 let camera_id = stab.camera_id.read();
 
-                                let id_str = camera_id.as_ref().map(|v| v.identifier.clone()).unwrap_or_default();
-                                if !id_str.is_empty() {
-                                    let db = stab.lens_profile_db.read();
-                                    if db.contains_id(&id_str) {
-                                        match stab.load_lens_profile(&id_str) {
-                                            Ok(_) => {
-                                                if let Some(fr) = stab.lens.read().frame_readout_time {
-                                                    stab.params.write().frame_readout_time = fr;
-                                                }
-                                            }
-                                            Err(e) => {
-                                                err(("An error occured: %1".to_string(), e.to_string()));
-                                                return;
+                                // DJI clips can also use the closest profile variant for the lens and FOV mode, see `LensProfileDatabase::find_best_match`
+                                let matched = camera_id.as_ref().and_then(|id| {
+                                    match stab.lens_profile_db.read().find_best_match(id)? {
+                                        core::lens_profile_database::ProfileMatch::Exact(key) => Some(key),
+                                        core::lens_profile_database::ProfileMatch::Closest(key) if id.brand == "DJI" => {
+                                            ::log::warn!("No exact lens profile for {} (lens: {}, gimbal mode: {}), using the closest one: {}", path, id.lens_info, id.gimbal_mode, key);
+                                            Some(key)
+                                        },
+                                        _ => None
+                                    }
+                                });
+                                if let Some(id_str) = matched {
+                                    match stab.load_lens_profile(&id_str) {
+                                        Ok(_) => {
+                                            if let Some(fr) = stab.lens.read().frame_readout_time {
+                                                stab.params.write().frame_readout_time = fr;
                                             }
                                         }
+                                        Err(e) => {
+                                            err(GyroflowError::from_message(e.to_string()));
+                                            return;
+                                        }
                                     }
                                 }
                                 if let Some(output_dim) = stab.lens.read().output_dimension.clone() {
@@ -1013,7 +1457,7 @@ impl RenderQueue {
                                 loaded((render_options, true));
 
                                 if let Err(e) = fetch_thumb(&path, ratio) {
-                                    err(("An error occured: %1".to_string(), e.to_string()));
+                                    err(GyroflowError::from_message(e.to_string()));
                                 }
 
                                 Self::do_autosync(&path, info.duration_ms, &video_size, stab.clone(), processing, err.clone(), sync_options);
@@ -1021,7 +1465,7 @@ impl RenderQueue {
                                 processing_done(());
                             }
                         } else {
-                            err(("An error occured: %1".to_string(), "Unable to read the video file.".to_string()));
+                            err(GyroflowError::new(ErrorKind::UnsupportedVideo, core::i18n::Message::new("error.unable_to_read_video").to_string()));
                         }
                     });
                 }
@@ -1032,7 +1476,7 @@ impl RenderQueue {
         job_id
     }
 
-    fn do_autosync<F: Fn(f64) + Send + Sync + Clone + 'static, F2: Fn((String, String)) + Send + Sync + Clone + 'static>(path: &str, duration_ms: f64, video_size: &(usize, usize), stab: Arc<StabilizationManager<stabilization::RGBA8>>, processing_cb: F, err: F2, sync_options: serde_json::Value) {
+    fn do_autosync<F: Fn(f64) + Send + Sync + Clone + 'static, F2: Fn(GyroflowError) + Send + Sync + Clone + 'static>(path: &str, duration_ms: f64, video_size: &(usize, usize), stab: Arc<StabilizationManager<stabilization::RGBA8>>, processing_cb: F, err: F2, sync_options: serde_json::Value) {
         let (has_gyro, has_sync_points) = {
             let gyro = stab.gyro.read();
             (!gyro.quaternions.is_empty(), !gyro.get_offsets().is_empty())
@@ -1115,7 +1559,7 @@ impl RenderQueue {
                                                         sync2.feed_frame(timestamp_us, frame_no, width, height, stride, pixels);
                                                     },
                                                     Err(e) => {
-                                                        err2(("An error occured: %1".to_string(), e.to_string()))
+                                                        err2(GyroflowError::from_message(e.to_string()))
                                                     }
                                                 }
                                                 frame_no += 1;
@@ -1124,17 +1568,17 @@ impl RenderQueue {
                                             Ok(())
                                         });
                                         if let Err(e) = proc.start_decoder_only(sync.get_ranges(), cancel_flag) {
-                                            err(("An error occured: %1".to_string(), e.to_string()));
+                                            err(GyroflowError::from_message(e.to_string()));
                                         }
                                         sync.finished_feeding_frames();
                                     }
                                     Err(error) => {
                                         dbg!(&error.to_string());
-                                        err(("An error occured: %1".to_string(), error.to_string()));
+                                        err(GyroflowError::from_message(error.to_string()));
                                     }
                                 }
                             } else {
-                                err(("An error occured: %1".to_string(), "Invalid parameters".to_string()));
+                                err(GyroflowError::new(ErrorKind::InvalidParameters, core::i18n::Message::new("error.invalid_parameters").to_string()));
                             }
 
                             stab.set_size(video_size.0, video_size.1);
@@ -1166,12 +1610,17 @@ impl RenderQueue {
         let processing_done = util::qt_queued_callback_mut(self, |this, job_id: u32| {
             this.processing_done(job_id, true);
         });
-        let err = util::qt_queued_callback_mut(self, move |this, (job_id, msg): (u32, String)| {
-            this.error(job_id, QString::from(msg), QString::default(), QString::default());
+        let err = util::qt_queued_callback_mut(self, move |this, (job_id, e): (u32, GyroflowError)| {
+            this.error(job_id, QString::from(e.to_json()));
         });
         ::log::debug!("new_output_options: {:?}", &new_output_options);
         let data = data.as_bytes();
         let mut q = self.queue.borrow_mut();
+        // Paths of the jobs which keep their output, the other ones are added as they are generated
+        let mut taken: HashSet<String> = self.jobs.values()
+            .filter(|job| job.queue_index >= q.row_count() as usize || q[job.queue_index].status != JobStatus::Queued)
+            .map(|job| job.render_options.output_path.clone())
+            .collect();
         for (job_id, job) in self.jobs.iter_mut() {
             if job.queue_index < q.row_count() as usize {
                 let mut itm = q[job.queue_index].clone();
@@ -1188,14 +1637,20 @@ impl RenderQueue {
                     let job_id = *job_id;
                     if let Some(ref new_output_options) = new_output_options {
                         job.render_options.update_from_json(new_output_options);
-                        job.render_options.output_path = Self::get_output_path(&self.default_suffix.to_string(), &itm.input_file.to_string(), &job.render_options.codec, &job.render_options.output_path);
+                        job.render_options.output_path = Self::get_output_path(&self.default_suffix.to_string(), &itm.input_file.to_string(), &job.render_options, job.stab.output_template_vars(), |p| {
+                            taken.contains(p) || job.render_options.output_exists(p)
+                        });
+                        let collision = Self::resolve_output_collision(&mut job.render_options, &taken);
+                        taken.insert(job.render_options.output_path.clone());
                         itm.export_settings = QString::from(job.render_options.settings_string(job.stab.params.read().fps));
                         itm.output_path = QString::from(job.render_options.output_path.as_str());
-                        if std::path::Path::new(&job.render_options.output_path).exists() {
-                            let msg = QString::from(format!("file_exists:{}", job.render_options.output_path));
-                            itm.error_string = msg.clone();
-                            itm.status = JobStatus::Error;
-                            err((job_id, msg.to_string()));
+                        if let Some(notice) = collision {
+                            let ask = matches!(notice, JobNotice::FileExists { .. });
+                            itm.status = if ask { JobStatus::Error } else { JobStatus::Skipped };
+                            itm.set_notice(Some(notice));
+                            if ask {
+                                err((job_id, GyroflowError::new(ErrorKind::OutputExists, job.render_options.output_path.as_str())));
+                            }
                         }
                     }
                     let processing_done = processing_done.clone();
@@ -1235,3 +1690,10 @@ impl RenderQueue {
         rendering::get_encoder_options(&encoder)
     }
 }
+
+impl Drop for RenderQueue {
+    fn drop(&mut self) {
+        // Stops the render of the last preview
+        self.cancel_preview();
+    }
+}
@@ -0,0 +1,65 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2022 Adrian <adrian.eddy at gmail>
+
+// Source timecode and reel name, carried over to the rendered file so the NLE can conform it with the original clip.
+// The mov muxer creates the `tmcd` track from the `timecode` tag, and writes the reel name from the video stream metadata.
+
+use ffmpeg_next::{ ffi, format, media, Rational };
+use std::ffi::{ CStr, CString };
+
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct SourceTimecode {
+    pub timecode: Option<String>, // HH:MM:SS:FF, or HH:MM:SS;FF for drop frame
+    pub reel_name: Option<String>,
+}
+
+// Tags used by the cameras and NLEs for the reel name
+const REEL_NAME_TAGS: &[&str] = &["reel_name", "reel", "com.apple.proapps.reel", "tape_name"];
+
+impl SourceTimecode {
+    /// Looks in the container metadata first, then in the video and timecode (data) streams
+    pub fn from_input(ictx: &format::context::Input) -> Self {
+        let mut dicts = vec![ictx.metadata()];
+        dicts.extend(ictx.streams()
+            .filter(|x| matches!(x.parameters().medium(), media::Type::Video | media::Type::Data))
+            .map(|x| x.metadata()));
+
+        let find = |keys: &[&str]| -> Option<String> {
+            dicts.iter().find_map(|d| keys.iter().find_map(|k| d.get(k).map(str::trim).filter(|x| !x.is_empty()).map(str::to_string)))
+        };
+        Self {
+            timecode: find(&["timecode"]),
+            reel_name: find(REEL_NAME_TAGS),
+        }
+    }
+
+    /// Timecode of the first output frame, after skipping `offset_s` of the source (the trim start) and converting to the output frame rate
+    pub fn shifted(&self, input_rate: Rational, output_rate: Rational, offset_s: f64) -> Option<String> {
+        let timecode = CString::new(self.timecode.as_ref()?.as_str()).ok()?;
+        if input_rate.numerator() <= 0 || output_rate.numerator() <= 0 { return None; }
+
+        unsafe {
+            let mut tc: ffi::AVTimecode = std::mem::zeroed();
+            if ffi::av_timecode_init_from_string(&mut tc, input_rate.into(), timecode.as_ptr(), std::ptr::null_mut()) < 0 {
+                log::warn!("Unsupported source timecode: {:?} at {}", self.timecode, input_rate);
+                return None;
+            }
+            let seconds = tc.start as f64 * f64::from(input_rate.invert()) + offset_s.max(0.0);
+            let start = (seconds * f64::from(output_rate)).round() as i32;
+
+            // Drop frame is only valid for the NTSC rates, use non-drop if the output rate doesn't allow it
+            let drop_frame = tc.flags & ffi::AVTimecodeFlag::AV_TIMECODE_FLAG_DROPFRAME as u32 != 0;
+            let mut out: ffi::AVTimecode = std::mem::zeroed();
+            if !drop_frame || ffi::av_timecode_init(&mut out, output_rate.into(), ffi::AVTimecodeFlag::AV_TIMECODE_FLAG_DROPFRAME as i32, start, std::ptr::null_mut()) < 0 {
+                if ffi::av_timecode_init(&mut out, output_rate.into(), 0, start, std::ptr::null_mut()) < 0 {
+                    return None;
+                }
+            }
+
+            let mut buf = [0 as std::os::raw::c_char; ffi::AV_TIMECODE_STR_SIZE as usize];
+            let ptr = ffi::av_timecode_make_string(&out, buf.as_mut_ptr(), 0);
+            if ptr.is_null() { return None; }
+            Some(CStr::from_ptr(ptr).to_string_lossy().into_owned())
+        }
+    }
+}
@@ -23,6 +23,21 @@ impl<'a> VideoProcessor<'a> {
             Ok(Self { inner: Processor::Ffmpeg(FfmpegProcessor::from_file(path, gpu_decoding, gpu_decoder_index, decoder_options)?) })
         }
     }
+    pub fn from_file_with_tuning(path: &str, gpu_decoding: bool, gpu_decoder_index: usize, decoder_options: Option<Dictionary>, tuning: ffmpeg_processor::DecoderTuning) -> Result<Self, FFmpegError> {
+        if path.to_lowercase().ends_with(".braw") {
+            Ok(Self { inner: Processor::Mdk(MDKProcessor::from_file(path)) })
+        } else {
+            Ok(Self { inner: Processor::Ffmpeg(FfmpegProcessor::from_file_with_tuning(path, gpu_decoding, gpu_decoder_index, decoder_options, tuning)?) })
+        }
+    }
+
+    // Custom ffmpeg filtergraph applied to the decoded frames, eg. "hqdn3d=4"
+    pub fn set_video_filter(&mut self, filter: &str) {
+        match &mut self.inner {
+            Processor::Ffmpeg(x) => x.video.video_filter = filter.to_string(),
+            Processor::Mdk(_) => if !filter.trim().is_empty() { log::warn!("Video filters are not supported for this file format"); }
+        }
+    }
 
     pub fn on_frame<F>(&mut self, cb: F) where F: FnMut(i64, &mut frame::Video, Option<&mut frame::Video>, &mut ffmpeg_video_converter::Converter, &mut RateControl) -> Result<(), FFmpegError> + 'static {
         match &mut self.inner {
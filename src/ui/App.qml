@@ -194,6 +194,7 @@ Rectangle {
                     property bool allowFile: false;
                     property bool allowLens: false;
                     property bool allowSync: false;
+                    property bool allowSpace: false;
                     enabled: false;
 
                     property bool enabled2: window.videoArea.vid.loaded && exportSettings.canExport && !videoArea.videoLoader.active;
@@ -206,7 +207,10 @@ Rectangle {
                         QT_TRANSLATE_NOOP("Popup", "Apply selected settings to all items in the render queue"),
                         QT_TRANSLATE_NOOP("Popup", "Export project file (including processed gyro data)"),
                         QT_TRANSLATE_NOOP("Popup", "Export project file (including gyro data)"),
-                        QT_TRANSLATE_NOOP("Popup", "Export project file")
+                        QT_TRANSLATE_NOOP("Popup", "Export project file"),
+                        QT_TRANSLATE_NOOP("Popup", "Render preview (5 seconds from the current position)"),
+                        QT_TRANSLATE_NOOP("Popup", "Save as project template"),
+                        QT_TRANSLATE_NOOP("Popup", "Apply project template")
                     ];
 
                     function render() {
@@ -233,7 +237,23 @@ Rectangle {
                             ]);
                             return;
                         }
-                        if ((controller.file_exists(outputFile.text) || render_queue.file_exists(outputFile.text)) && !allowFile) {
+                        const preflight = JSON.parse(controller.render_preflight(JSON.stringify(Object.assign(exportSettings.getExportOptions(), { output_path: outputFile.text }))));
+                        if (preflight.overwrites_input) {
+                            messageBox(Modal.Error, qsTr("Output file is the same as the input video. Choose a different output path."), [
+                                { text: qsTr("Rename"), clicked: () => { outputFile.text = window.renameOutput(outputFile.text); render(); } },
+                                { text: qsTr("Ok"), accent: true },
+                            ]);
+                            return;
+                        }
+                        if (!preflight.enough_space && !allowSpace) {
+                            const toGB = (v) => (v / 1024 / 1024 / 1024).toFixed(2);
+                            messageBox(Modal.Warning, qsTr("There may not be enough free disk space for the output file.\nEstimated size: %1 GB, available: %2 GB. Do you want to render anyway?").arg(toGB(preflight.estimated_size)).arg(toGB(preflight.available_space)), [
+                                { text: qsTr("Yes"), clicked: () => { allowSpace = true; renderBtn.render(); } },
+                                { text: qsTr("No"), accent: true },
+                            ]);
+                            return;
+                        }
+                        if ((preflight.output_exists || render_queue.file_exists(outputFile.text)) && !allowFile) {
                             messageBox(Modal.Question, qsTr("Output file already exists, do you want to overwrite it?"), [
                                 { text: qsTr("Yes"), clicked: () => { allowFile = true; renderBtn.render(); } },
                                 { text: qsTr("Rename"), clicked: () => { outputFile.text = window.renameOutput(outputFile.text); render(); } },
@@ -258,6 +278,7 @@ Rectangle {
                         allowFile = false;
                         allowLens = false;
                         allowSync = false;
+                        allowSpace = false;
                         window.videoArea.vid.pause();
                         render();
                     }
@@ -295,6 +316,45 @@ Rectangle {
                             case 5: // Export project file
                                 controller.export_gyroflow_file(/*thin*/index == 5, /*ext*/index == 3, window.getAdditionalProjectData(), "", false);
                             break;
+                            case 6: // Render preview
+                                const from = videoArea.timeline.position;
+                                const to = Math.min(1.0, from + 5000 / Math.max(1, videoArea.timeline.orgDurationMs));
+                                const preview = Qt.createComponent("RenderPreview.qml").createObject(window, { fromRatio: from, toRatio: to });
+                                preview.opened = true;
+                            break;
+                            case 7: { // Save as project template
+                                const dlg = messageBox(Modal.Info, qsTr("Template name:"), [
+                                    { text: qsTr("Save"), accent: true, clicked: function() {
+                                        const name = dlg.mainColumn.children[1].text.trim();
+                                        if (!name) return false;
+                                        controller.save_project_template(name, window.getAdditionalProjectData());
+                                    } },
+                                    { text: qsTr("Cancel") },
+                                ]);
+                                const tf = Qt.createComponent("components/TextField.qml").createObject(dlg.mainColumn, { width: 300 * dpiScale });
+                                tf.anchors.horizontalCenter = dlg.mainColumn.horizontalCenter;
+                            break;
+                            }
+                            case 8: { // Apply project template
+                                const templates = controller.get_project_templates();
+                                if (!templates.length) {
+                                    messageBox(Modal.Info, qsTr("There are no saved project templates."), [ { text: qsTr("Ok"), accent: true } ]);
+                                    break;
+                                }
+                                const dlg = messageBox(Modal.Info, qsTr("Apply the settings of the template to the current video. The whole clip will be used and synchronized again."), [
+                                    { text: qsTr("Apply"), accent: true, clicked: function() {
+                                        const obj = controller.instantiate_project_template(dlg.mainColumn.children[1].currentText, videoArea.loadedFileUrl);
+                                        if (Object.keys(obj).length > 0) videoArea.loadGyroflowData(obj);
+                                    } },
+                                    { text: qsTr("Delete"), clicked: function() {
+                                        controller.delete_project_template(dlg.mainColumn.children[1].currentText);
+                                    } },
+                                    { text: qsTr("Cancel") },
+                                ]);
+                                const cb = Qt.createComponent("components/ComboBox.qml").createObject(dlg.mainColumn, { model: templates, width: 300 * dpiScale });
+                                cb.anchors.horizontalCenter = dlg.mainColumn.horizontalCenter;
+                            break;
+                            }
                         }
                     }
                 }
@@ -373,10 +433,8 @@ Rectangle {
 
     Connections {
         target: controller;
-        function onError(text: string, arg: string, callback: string) {
-            text = getReadableError(qsTr(text).arg(arg));
-            if (text)
-                messageBox(Modal.Error, text, [ { text: qsTr("Ok"), clicked: window[callback] } ]);
+        function onError(error_json: string) {
+            showError(error_json);
         }
         function onMessage(text: string, arg: string, callback: string) {
             messageBox(Modal.Info, qsTr(text).arg(arg), [ { text: qsTr("Ok"), clicked: window[callback] } ]);
@@ -384,6 +442,9 @@ Rectangle {
         function onRequest_recompute() {
             Qt.callLater(controller.recompute_threaded);
         }
+        function onGpu_fallback(failed: string, used: string) {
+            messageBox(Modal.Warning, qsTr("Processing device %1 failed, the frames are processed with %2 instead.").arg("<b>" + failed + "</b>").arg("<b>" + used + "</b>"), [ { text: qsTr("Ok") } ]);
+        }
         function onUpdates_available(version: string, changelog: string) {
             const heading = "<p align=\"center\">" + qsTr("There's a newer version available: %1.").arg("<b>" + version + "</b>") + "</p>\n\n";
             const el = messageBox(Modal.Info, heading + changelog, [ { text: qsTr("Download"),accent: true, clicked: () => Qt.openUrlExternally("https://github.com/gyroflow/gyroflow/releases") },{ text: qsTr("Close") }], undefined, Text.MarkdownText);
@@ -444,34 +505,136 @@ Rectangle {
 
         QT_TRANSLATE_NOOP("App", "An error occured: %1");
         QT_TRANSLATE_NOOP("App", "Gyroflow file exported to %1.");
+        QT_TRANSLATE_NOOP("App", "Vibration frequencies exported to %1.");
+        QT_TRANSLATE_NOOP("App", "Frame saved to %1.");
+        QT_TRANSLATE_NOOP("App", "The video file %1 has changed since the project was saved (it was re-encoded or replaced).\nSynchronization offsets and lens calibration may no longer apply.");
         QT_TRANSLATE_NOOP("App", "--REPLACE_WITH_NATIVE_NAME_OF_YOUR_LANGUAGE_IN_YOUR_LANGUAGE--", "Translate this to the native name of your language");
         QT_TRANSLATE_NOOP("App", "Gyroflow will shut down the computer in 60 seconds because all tasks have been completed.");
         QT_TRANSLATE_NOOP("App", "Gyroflow will reboot the computer in 60 seconds because all tasks have been completed.");
 
+        // Messages generated in core (gyroflow_core::i18n)
+        QT_TRANSLATE_NOOP("Core", "Gyroflow file");
+        QT_TRANSLATE_NOOP("Core", "Invalid parameters");
+        QT_TRANSLATE_NOOP("Core", "Unable to read the video file.");
+        QT_TRANSLATE_NOOP("Core", "Unable to open %1");
+        QT_TRANSLATE_NOOP("Core", "JSON parse error: %1");
+        QT_TRANSLATE_NOOP("Core", "Error loading %1: %2");
+        QT_TRANSLATE_NOOP("Core", "Preview is not available for image sequences.");
+        QT_TRANSLATE_NOOP("Core", "Unable to read any of the calibration images.");
+        QT_TRANSLATE_NOOP("Core", "Calibrate this camera first.");
+        QT_TRANSLATE_NOOP("Core", "Camera brand is missing.");
+        QT_TRANSLATE_NOOP("Core", "Camera model is missing.");
+        QT_TRANSLATE_NOOP("Core", "Calibration dimensions are missing.");
+        QT_TRANSLATE_NOOP("Core", "Frame rate is missing.");
+        QT_TRANSLATE_NOOP("Core", "Not enough calibration frames (%1, at least %2 required).");
+        QT_TRANSLATE_NOOP("Core", "Lens is not calibrated.");
+        QT_TRANSLATE_NOOP("Core", "Calibration coefficients are invalid.");
+        QT_TRANSLATE_NOOP("Core", "Reprojection error is missing.");
+        QT_TRANSLATE_NOOP("Core", "Reprojection error is too high (%1, should be less than %2).");
+        QT_TRANSLATE_NOOP("Core", "Output file already exists.");
+        QT_TRANSLATE_NOOP("Core", "FOV");
+        QT_TRANSLATE_NOOP("Core", "Video rotation");
+        QT_TRANSLATE_NOOP("Core", "Zooming speed");
+        QT_TRANSLATE_NOOP("Core", "Zooming center offset X");
+        QT_TRANSLATE_NOOP("Core", "Zooming center offset Y");
+        QT_TRANSLATE_NOOP("Core", "Max zoom");
+        QT_TRANSLATE_NOOP("Core", "Border tolerance");
+        QT_TRANSLATE_NOOP("Core", "Background margin");
+        QT_TRANSLATE_NOOP("Core", "Background feather");
+        QT_TRANSLATE_NOOP("Core", "Horizon lock amount");
+        QT_TRANSLATE_NOOP("Core", "Horizon lock roll correction");
+        QT_TRANSLATE_NOOP("Core", "Lens correction strength");
+        QT_TRANSLATE_NOOP("Core", "Stabilization amount");
+        QT_TRANSLATE_NOOP("Core", "Lens look strength");
+        QT_TRANSLATE_NOOP("Core", "Max smoothness");
+        QT_TRANSLATE_NOOP("Core", "Max smoothness at high velocity");
+        QT_TRANSLATE_NOOP("Core", "Smoothness");
+        QT_TRANSLATE_NOOP("Core", "Smoothness pitch");
+        QT_TRANSLATE_NOOP("Core", "Smoothness roll");
+        QT_TRANSLATE_NOOP("Core", "Smoothness yaw");
+        QT_TRANSLATE_NOOP("Core", "Video speed");
+
         if (!isLandscape) {
             isLandscapeChanged();
         }
     }
 
+    function askToInstallSdk(sdk: string) {
+        if (videoArea.externalSdkModal !== null) return;
+
+        const licenseUrl = "https://code.videolan.org/videolan/x264/-/raw/master/COPYING";
+        // const licenseUrl = "https://bitbucket.org/multicoreware/x265_git/raw/master/COPYING";
+        const dlg = messageBox(Modal.Info, qsTr("This encoder requires an external library licensed as GPL.\nDo you agree with the [GPL license] and want to download the additional codec?").replace(/\[(.*?)\]/, '<a href="' + licenseUrl + '"><font color="' + styleTextColor + '">$1</font></a>'), [
+            { text: qsTr("Yes, I agree"), accent: true, clicked: function() {
+                dlg.btnsRow.children[0].enabled = false;
+                controller.install_external_sdk(sdk);
+                return false;
+            } },
+            { text: qsTr("Cancel"), clicked: function() {
+                videoArea.externalSdkModal = null;
+            } },
+        ]);
+        videoArea.externalSdkModal = dlg;
+        dlg.addLoader();
+    }
+
+    // `err` is a serialized `GyroflowError`: { kind, context, action: { type, arg } }
+    function getErrorText(err: var): string {
+        switch (err.kind) {
+            case "invalid_parameters":   return qsTr("Invalid parameters: %1").arg(err.context);
+            case "file_not_found":       return qsTr("File not found: %1").arg(err.context);
+            case "permission_denied":    return qsTr("Permission denied. Unable to create or write file.\nChange the output path or run the program as administrator.\nMake sure you have write permissions to the target directory and make sure target file is not used by any other application.");
+            case "invalid_project":      return qsTr("Unable to load the project file: %1").arg(err.context);
+            case "invalid_lens_profile": return qsTr("Invalid lens profile: %1").arg(err.context);
+            case "unsupported_video":    return qsTr("Unable to read the video file.") + "\n\n" + err.context;
+            case "no_motion_data":       return qsTr("No motion data found: %1").arg(err.context);
+            case "calibration":          return qsTr("Lens calibration failed: %1").arg(err.context);
+            case "encoder_not_found":    return qsTr("Encoder not found: %1").arg(err.context);
+            case "output_exists":        return qsTr("Output file already exists, do you want to overwrite it?");
+        }
+        return getReadableError(qsTr("An error occured: %1").arg(err.context));
+    }
+    function getErrorActions(err: var): list<var> {
+        switch (err.action.type) {
+            case "pick_lens_profile":      return [ { text: qsTr("Select lens profile"),  accent: true, clicked: () => lensProfile.searchField.forceActiveFocus() } ];
+            case "load_motion_data":       return [ { text: qsTr("Load motion data"),     accent: true, clicked: () => motionData.fileDialog.open() } ];
+            case "choose_output_location": return [ { text: qsTr("Change output path"),   accent: true, clicked: () => outputFileDialog.open() } ];
+        }
+        return [];
+    }
+    function showError(errorJson: string) {
+        const err = JSON.parse(errorJson);
+        if (err.action.type == "install_sdk" && controller.check_external_sdk(err.action.arg)) {
+            askToInstallSdk(err.action.arg);
+            return;
+        }
+        const text = getErrorText(err);
+        if (text)
+            messageBox(Modal.Error, text, getErrorActions(err).concat([ { text: qsTr("Ok") } ]));
+    }
+
+    // `notice` is a serialized `JobNotice` of the render queue item: { type, ...fields }
+    function getNoticeText(notice: var): string {
+        switch (notice.type) {
+            case "convert_format": return qsTr("GPU accelerated encoder doesn't support this pixel format (%1).\nDo you want to convert to a different supported pixel format or keep the original one and render on the CPU?").arg("<b>" + notice.format + "</b>");
+            case "file_exists":    return qsTr("Output file already exists, do you want to overwrite it?");
+            case "output_skipped": return qsTr("Output file already exists, the file was skipped.");
+            case "uses_cpu":       return qsTr("GPU encoder failed to initialize and rendering is done on the CPU, which is much slower.\nIf you have a modern device, latest GPU drivers and you think this shouldn't happen, report this on GitHub including gyroflow.log file.");
+            case "gpu_downscaled": return qsTr("The frame is larger than your GPU can process, so it's stabilized at 1/%1 of the resolution and upscaled back.\nThe output will be less sharp. Render on a GPU with more memory or reduce the output size for full quality.").arg(notice.factor);
+            case "cancelled":
+                switch (notice.partial_output) {
+                    case "removed":       return qsTr("Rendering was cancelled, the incomplete output file was removed.");
+                    case "remove_failed": return qsTr("Rendering was cancelled, but the incomplete output file couldn't be removed.");
+                    default:              return qsTr("Rendering was cancelled, the already rendered frames were kept.");
+                }
+        }
+        return "";
+    }
+
     function getReadableError(text: string): string {
         if (text.includes("ffmpeg")) {
             if (text.includes("Encoder not found") && text.includes("libx26") && controller.check_external_sdk("ffmpeg_gpl")) {
-                if (videoArea.externalSdkModal === null) {
-                    const licenseUrl = "https://code.videolan.org/videolan/x264/-/raw/master/COPYING";
-                    // const licenseUrl = "https://bitbucket.org/multicoreware/x265_git/raw/master/COPYING";
-                    const dlg = messageBox(Modal.Info, qsTr("This encoder requires an external library licensed as GPL.\nDo you agree with the [GPL license] and want to download the additional codec?").replace(/\[(.*?)\]/, '<a href="' + licenseUrl + '"><font color="' + styleTextColor + '">$1</font></a>'), [
-                        { text: qsTr("Yes, I agree"), accent: true, clicked: function() {
-                            dlg.btnsRow.children[0].enabled = false;
-                            controller.install_external_sdk("ffmpeg_gpl");
-                            return false;
-                        } },
-                        { text: qsTr("Cancel"), clicked: function() {
-                            videoArea.externalSdkModal = null;
-                        } },
-                    ]);
-                    videoArea.externalSdkModal = dlg;
-                    dlg.addLoader();
-                }
+                askToInstallSdk("ffmpeg_gpl");
                 return "";
             }
 
@@ -483,16 +646,6 @@ Rectangle {
             // Remove duplicate lines
             text = [...new Set(text.split(/\r\n|\n\r|\n|\r/g))].join("\n");
         }
-        if (text.startsWith("convert_format:")) {
-            const format = text.split(":")[1].split(";")[0];
-            return qsTr("GPU accelerated encoder doesn't support this pixel format (%1).\nDo you want to convert to a different supported pixel format or keep the original one and render on the CPU?").arg("<b>" + format + "</b>");
-        }
-        if (text.startsWith("file_exists:")) {
-            return qsTr("Output file already exists, do you want to overwrite it?");
-        }
-        if (text.startsWith("uses_cpu")) {
-            return qsTr("GPU encoder failed to initialize and rendering is done on the CPU, which is much slower.\nIf you have a modern device, latest GPU drivers and you think this shouldn't happen, report this on GitHub including gyroflow.log file.");
-        }
         if (text.includes("hevc") && text.includes("-12912")) {
             return qsTr("Your GPU doesn't support H.265/HEVC encoding, try to use H.264/AVC or disable GPU encoding in Export settings.");
         }
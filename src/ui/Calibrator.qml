@@ -42,8 +42,9 @@ Window {
 
     Connections {
         target: controller;
-        function onError(text: string, arg: string, callback: string) {
-            messageBox(Modal.Error, qsTr(text).arg(arg), [ { "text": qsTr("Ok"), clicked: window[callback] } ]);
+        function onError(error_json: string) {
+            const err = JSON.parse(error_json);
+            messageBox(Modal.Error, window.getErrorText(err), [ { "text": qsTr("Ok") } ]);
         }
         function onRequest_recompute() {
             Qt.callLater(controller.recompute_threaded);
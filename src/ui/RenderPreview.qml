@@ -0,0 +1,70 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2022 Adrian <adrian.eddy at gmail>
+
+import QtQuick
+import MDKVideo
+
+import "components/"
+
+// Short range rendered with the current export settings, played back without the stabilization preview
+Modal {
+    id: root;
+    isWide: true;
+    widthRatio: 0.7;
+    iconType: Modal.NoIcon;
+    text: qsTr("Rendering preview...");
+
+    property real fromRatio: 0;
+    property real toRatio: 1;
+
+    buttons: [ qsTr("Close") ];
+    onClicked: {
+        vid.pause();
+        render_queue.cancel_preview();
+        root.close();
+    }
+
+    Component.onCompleted: {
+        const l = root.addLoader();
+        l.active = true;
+        l.visible = true;
+        l.text = qsTr("Rendering %1...");
+        render_queue.render_preview(window.getAdditionalProjectDataJson(), fromRatio, toRatio);
+    }
+
+    Connections {
+        target: render_queue;
+        function onPreview_progress(progress: real, current_frame: int, total_frames: int) {
+            if (!root.loader) return;
+            root.loader.currentFrame = current_frame;
+            root.loader.totalFrames = total_frames;
+            root.loader.progress = progress;
+        }
+        function onPreview_ready(url: string) {
+            root.loader.active = false;
+            root.loader.visible = false;
+            root.text = qsTr("Rendered preview");
+            vid.url = url;
+            vid.play();
+        }
+        function onPreview_error(error_json: string) {
+            root.close();
+            showError(error_json);
+        }
+    }
+
+    Item {
+        width: parent.width;
+        height: vid.visible? width * Math.max(1, vid.videoHeight) / Math.max(1, vid.videoWidth) : 0;
+        MDKVideo {
+            id: vid;
+            anchors.fill: parent;
+            visible: duration > 0;
+            onPlayingChanged: if (!playing && duration > 0 && timestamp >= duration - 100) { currentFrame = 0; play(); } // Loop
+        }
+        MouseArea {
+            anchors.fill: parent;
+            onClicked: if (vid.playing) vid.pause(); else vid.play();
+        }
+    }
+}
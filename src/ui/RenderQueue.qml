@@ -89,6 +89,7 @@ Item {
                 property string remaining: "---";
                 property real fps: 0;
                 property string fpsText: topCol.progress > 0? qsTr(" @ %1fps").arg(fps.toFixed(1)) : "";
+                property string memoryText: "";
                 BasicText {
                     leftPadding: 0;
                     text: qsTr("Elapsed: %1").arg("<b>" + totalTime.elapsed + "</b>");
@@ -97,7 +98,7 @@ Item {
                     leftPadding: 0;
                     anchors.horizontalCenter: parent.horizontalCenter;
                     textFormat: Text.RichText;
-                    text: `<b>${(topCol.progress*100).toFixed(2)}%</b> <small>(${render_queue.current_frame}/${render_queue.total_frames}${totalTime.fpsText})</small>`;
+                    text: `<b>${(topCol.progress*100).toFixed(2)}%</b> <small>(${render_queue.current_frame}/${render_queue.total_frames}${totalTime.fpsText})${totalTime.memoryText}</small>`;
                 }
                 BasicText {
                     leftPadding: 0;
@@ -117,16 +118,19 @@ Item {
                 delete loader.pendingJobs[job_id];
                 loader.updateStatus();
             }
-            function onError(job_id: real, text: string, arg: string, callback: string) {
+            function onError(job_id: real, error_json: string) {
                 if (job_id == render_queue.main_job_id || loader.pendingJobs[job_id]) {
-                    text = getReadableError(qsTr(text).arg(arg));
-                    if (text)
-                        messageBox(Modal.Error, text, [ { "text": qsTr("Ok"), clicked: window[callback] } ]);
+                    showError(error_json);
                 }
                 delete loader.pendingJobs[job_id];
                 loader.updateStatus();
             }
             function onRender_progress(job_id: real, progress: real, frame: int, total_frames: int, finished: bool) {
+                if (finished && loader.hashMismatches[job_id]) {
+                    const frames = loader.hashMismatches[job_id];
+                    delete loader.hashMismatches[job_id];
+                    messageBox(Modal.Warning, qsTr("Rendered frames differ from the reference render: %1.").arg("<b>" + frames.join(", ") + "</b>"), [ { text: qsTr("Ok") } ]);
+                }
                 if (job_id == render_queue.main_job_id) {
                     window.videoArea.videoLoader.active = !finished;
                     window.videoArea.videoLoader.currentFrame = frame;
@@ -138,7 +142,7 @@ Item {
 
                     if (total_frames > 0 && finished) {
                         render_queue.main_job_id = 0;
-                        const path = render_queue.get_job_output_path(job_id);
+                        const path = render_queue.get_job_rendered_file(job_id);
                         messageBox(Modal.Success, qsTr("Rendering completed. The file was written to: %1.").arg("<br><b>" + path + "</b>"), [
                             { text: qsTr("Open rendered file"), clicked: () => controller.open_file_externally(path) },
                             { text: qsTr("Open file location"), clicked: () => controller.open_file_externally(Util.getFolder(path)) },
@@ -171,6 +175,39 @@ Item {
                 delete loader.pendingJobs[job_id];
                 loader.updateStatus();
             }
+            function onEncoder_unsupported(job_id: real, issues_json: string) {
+                if (job_id == render_queue.main_job_id || loader.pendingJobs[job_id]) {
+                    const issues = JSON.parse(issues_json).map(x => {
+                        switch (x.feature) {
+                            case "resolution":   return qsTr("Resolution %1 is not supported.").arg("<b>" + x.requested + "</b>");
+                            case "pixel_format": return qsTr("Pixel format %1 is not supported.").arg("<b>" + x.requested + "</b>");
+                            case "frame_rate":   return qsTr("Frame rate %1 fps is not supported.").arg("<b>" + x.requested + "</b>");
+                            default:             return x.message;
+                        }
+                    });
+                    const encoder = JSON.parse(issues_json).map(x => x.encoder).find(x => !!x) || "";
+                    messageBox(Modal.Error, qsTr("Encoder %1 can't render with the selected settings:").arg("<b>" + encoder + "</b>") + "<br><br>" + issues.join("<br>"), [ { text: qsTr("Ok") } ]);
+                }
+                delete loader.pendingJobs[job_id];
+                loader.updateStatus();
+            }
+            function onMemory_usage(used_mb: real, peak_mb: real, limit_mb: real, waits: int) {
+                if (used_mb > 0 || limit_mb > 0) {
+                    const limit = limit_mb > 0? " / " + limit_mb.toFixed(0) : "";
+                    totalTime.memoryText = " | " + qsTr("Frame memory: %1 MB").arg(used_mb.toFixed(0) + limit);
+                } else {
+                    totalTime.memoryText = "";
+                }
+            }
+            function onFrame_hash_mismatch(job_id: real, frame: int, expected: string, actual: string) {
+                if (!loader.hashMismatches[job_id]) loader.hashMismatches[job_id] = [];
+                loader.hashMismatches[job_id].push(frame);
+            }
+            function onFrame_hashes_recorded(job_id: real, hashes: string) {
+                if (job_id == render_queue.main_job_id) {
+                    window.exportSettings.frameHashes = JSON.parse(hashes);
+                }
+            }
             function onEncoder_initialized(job_id: real, encoder_name: string) {
 
             }
@@ -251,12 +288,12 @@ Item {
             id: dlg;
             property real progress: current_frame / total_frames;
             property bool isFinished: current_frame >= total_frames && total_frames > 0;
+            property var noticeData: notice? JSON.parse(notice) : null;
             property bool isError: error_string.length > 0 && !isQuestion && !isInfo;
-            property bool isInfo: error_string == "uses_cpu";
-            property bool isQuestion: error_string.startsWith("convert_format:") || error_string.startsWith("file_exists:");
+            property bool isInfo: !!noticeData && ["uses_cpu", "gpu_downscaled", "cancelled", "output_skipped"].includes(noticeData.type);
+            property bool isQuestion: !!noticeData && ["convert_format", "file_exists"].includes(noticeData.type);
             property bool isInProgress: (!isFinished && !isError && !isQuestion && total_frames > 0) && (current_frame > 0 || isProcessing);
             property bool isProcessing: processing_progress > 0.0 && processing_progress < 1.0;
-            property string errorString: error_string;
             onProgressChanged: {
                 const times = Util.calculateTimesAndFps(progress, current_frame, start_timestamp);
                 if (times !== false) {
@@ -267,10 +304,10 @@ Item {
                     time.elapsed = "";
                 }
             }
-            onErrorStringChanged: {
-                if (job_id == render_queue.main_job_id && error_string == "uses_cpu") {
+            onNoticeDataChanged: {
+                if (job_id == render_queue.main_job_id && isInfo) {
                     window.videoArea.videoLoader.infoMessage.type = InfoMessage.Warning;
-                    window.videoArea.videoLoader.infoMessage.text = window.getReadableError(error_string);
+                    window.videoArea.videoLoader.infoMessage.text = window.getNoticeText(noticeData);
                     window.videoArea.videoLoader.infoMessage.show = true;
                 }
             }
@@ -313,6 +350,29 @@ Item {
                     enabled: isError || isFinished || isQuestion || isInProgress;
                     onTriggered: render_queue.reset_job(job_id);
                 }
+                Action {
+                    iconName: held? "play" : "pause";
+                    text: held? qsTr("Release") : qsTr("Hold");
+                    enabled: !isFinished && !isInProgress;
+                    onTriggered: render_queue.set_job_held(job_id, !held);
+                }
+                Action {
+                    text: qsTr("Move up");
+                    enabled: index > 0;
+                    onTriggered: render_queue.move_job(job_id, index - 1);
+                }
+                Action {
+                    text: qsTr("Move down");
+                    enabled: index < lv.count - 1;
+                    onTriggered: render_queue.move_job(job_id, index + 1);
+                }
+                Action {
+                    iconName: "star";
+                    text: qsTr("High priority");
+                    checkable: true;
+                    checked: priority > 0;
+                    onTriggered: render_queue.set_job_priority(job_id, priority > 0? 0 : 1);
+                }
             }
 
             Rectangle {
@@ -357,14 +417,15 @@ Item {
                             spacing: 5 * dpiScale;
                             width: parent.width;
                             property string errorString: error_string;
-                            onErrorStringChanged: {
-                                const text = window.getReadableError(errorString).replace(/\n/g, "<br>");
+                            property var noticeData: dlg.noticeData;
+                            onErrorStringChanged: updateMessage();
+                            onNoticeDataChanged: updateMessage();
+                            function updateMessage(): void {
+                                const text = (noticeData? window.getNoticeText(noticeData) : window.getReadableError(errorString)).replace(/\n/g, "<br>");
                                 messageAreaText.text = text? text : qsTr("Missing required components.");
 
-                                if (errorString.startsWith("convert_format:")) {
-                                    const params = errorString.split(":")[1].split(";");
-                                    const supported = params[1].split(",");
-                                    let buttons = supported.map(f => ({
+                                if (noticeData && noticeData.type == "convert_format") {
+                                    let buttons = noticeData.supported.map(f => ({
                                         text: f,
                                         clicked: () => { render_queue.set_pixel_format(job_id, f); }
                                     }));
@@ -374,8 +435,8 @@ Item {
                                         clicked: () => { render_queue.set_pixel_format(job_id, "cpu"); }
                                     });
                                     btns.model = buttons;
-                                } else if (errorString.startsWith("file_exists:")) {
-                                    const path = errorString.substring(12);
+                                } else if (noticeData && noticeData.type == "file_exists") {
+                                    const path = noticeData.path;
                                     btns.model = [
                                         { text: qsTr("Yes"),    clicked: () => { render_queue.reset_job(job_id); }, accent: true },
                                         { text: qsTr("Rename"), clicked: () => { render_queue.set_job_output_path(job_id, window.renameOutput(path)); } },
@@ -418,6 +479,7 @@ Item {
             }
             Item {
                 id: innerItm;
+                opacity: held? 0.5 : 1;
                 x: 5 * dpiScale;
                 width: parent.width - 2*x;
                 height: 70 * dpiScale;
@@ -515,13 +577,13 @@ Item {
                             icon.width: 25 * dpiScale;
                             icon.height: 25 * dpiScale;
                             tooltip: qsTr("Open rendered file");
-                            onClicked: controller.open_file_externally(output_path);
+                            onClicked: controller.open_file_externally(render_queue.get_job_rendered_file(job_id));
                         }
                         IconButton {
                             visible: dlg.isFinished;
                             iconName: "folder";
                             tooltip: qsTr("Open file location");
-                            onClicked: controller.open_file_externally(Util.getFolder(output_path));
+                            onClicked: controller.open_file_externally(Util.getFolder(render_queue.get_job_rendered_file(job_id)));
                         }
                         IconButton {
                             tooltip: qsTr("Remove");
@@ -624,6 +686,7 @@ Item {
         id: loader;
         active: false;
         property var pendingJobs: ({});
+        property var hashMismatches: ({}); // job_id -> frames which differ from the reference checksums
         function updateStatus() { active = Object.keys(pendingJobs).length > 0; }
     }
 }
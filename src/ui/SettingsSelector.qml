@@ -22,6 +22,7 @@ Modal {
         "Video|video_info": {
             "Rotation":   ["rotation"],
             "Frame rate": ["fps_scale", "vfr_fps", "vfr_duration_ms"],
+            "Input crop": ["input_crop"],
         },
         "Lens profile": ["calibration_data"],
         "Motion data|gyro_source": {
@@ -46,10 +47,12 @@ Modal {
         "Stabilization|stabilization": {
             "FOV":                        ["fov"],
             "Smoothing params":           ["method", "smoothing_params"],
-            "Horizon lock":               ["horizon_lock_amount", "horizon_lock_roll", "use_gravity_vectors"],
+            "Stabilization amount":       ["stabilization_amount"],
+            "Horizon lock":               ["horizon_lock_amount", "horizon_lock_roll", "horizon_lock_pitch_amount", "use_gravity_vectors"],
             "Rolling shutter correction": ["frame_readout_time"],
             "Zooming":                    ["adaptive_zoom_window", "adaptive_zoom_center_offset"],
             "Lens correction strength":   ["lens_correction_amount"],
+            "Lens look":                  ["output_projection", "output_projection_strength"],
             "Video speed":                ["video_speed", "video_speed_affects_smoothing", "video_speed_affects_zooming"],
         },
         "Export settings|output": {
@@ -57,7 +60,7 @@ Modal {
             "Audio":       ["audio"],
             "Output size": ["output_width", "output_height"],
             "Output path": ["output_path"],
-            "Advanced": ["encoder_options", "keyframe_distance", "preserve_other_tracks", "pad_with_black"],
+            "Advanced": ["encoder_options", "keyframe_distance", "preserve_other_tracks", "pad_with_black", "frame_comparison"],
         },
         "Advanced": {
             "Background":           ["background_color", "background_mode", "background_margin", "background_margin_feather"],
@@ -90,6 +93,7 @@ Modal {
         QT_TR_NOOP("Video");
             QT_TR_NOOP("Rotation");
             QT_TR_NOOP("Frame rate");
+            QT_TR_NOOP("Input crop");
         QT_TR_NOOP("Lens profile");
         QT_TR_NOOP("Motion data");
             QT_TR_NOOP("Low pass filter");
@@ -109,10 +113,12 @@ Modal {
         QT_TR_NOOP("Stabilization");
             QT_TR_NOOP("FOV");
             QT_TR_NOOP("Smoothing params");
+            QT_TR_NOOP("Stabilization amount");
             QT_TR_NOOP("Horizon lock");
             QT_TR_NOOP("Rolling shutter correction");
             QT_TR_NOOP("Zooming");
             QT_TR_NOOP("Lens correction strength");
+            QT_TR_NOOP("Lens look");
             QT_TR_NOOP("Video speed");
         QT_TR_NOOP("Export settings");
             QT_TR_NOOP("Codec");
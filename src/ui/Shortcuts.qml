@@ -70,7 +70,7 @@ Item {
         sequences: ["i", "["];
         onActivated: {
             videoArea.timeline.focus = true;
-            videoArea.timeline.setTrim(videoArea.timeline.position, videoArea.timeline.trimEnd);
+            videoArea.timeline.editTrim(videoArea.timeline.position, videoArea.timeline.trimEnd);
         }
     }
     // Set trim end here
@@ -78,7 +78,7 @@ Item {
         sequences: ["o", "]"];
         onActivated: {
             videoArea.timeline.focus = true;
-            videoArea.timeline.setTrim(videoArea.timeline.trimStart, videoArea.timeline.position);
+            videoArea.timeline.editTrim(videoArea.timeline.trimStart, videoArea.timeline.position);
         }
     }
     // Mute on/off
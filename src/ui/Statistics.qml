@@ -173,6 +173,23 @@ Item {
         text: qsTr("Logarithmic value axis");
         checked: true;
     }
+    LinkButton {
+        anchors.horizontalCenter: parent.horizontalCenter;
+        anchors.bottom: parent.bottom;
+        anchors.bottomMargin: 5 * dpiScale;
+        text: qsTr("Export vibration frequencies");
+        tooltip: qsTr("Save the dominant vibration frequencies for every second of the video (CSV or JSON),\nso the matching noise (eg. props) can be removed from the audio with notch filters.");
+        enabled: controller.gyro_loaded;
+        onClicked: vibrationFileDialog.open();
+    }
+    FileDialog {
+        id: vibrationFileDialog;
+        fileMode: FileDialog.SaveFile;
+        title: qsTr("Select file destination");
+        nameFilters: ["*.csv", "*.json"];
+        type: "output-vibration";
+        onAccepted: controller.export_vibration_bands(selectedFile);
+    }
     Label {
         width: 200 * dpiScale;
         position: Label.LeftPosition;
@@ -32,6 +32,7 @@ Item {
 
     property bool safeArea: false;
     property var pendingGyroflowData: null;
+    readonly property bool isProjectLoading: !!pendingGyroflowData; // The video and motion data are loaded for a project, which has its own settings
     property url loadedFileUrl;
 
     property bool fullScreen: false;
@@ -88,6 +89,13 @@ Item {
     }
     Connections {
         target: controller;
+        function onGyroflow_file_damaged(recovered_data: string, dropped_sections: string, error: string) {
+            const dropped = dropped_sections? "<b>" + dropped_sections + "</b>" : qsTr("none, the end of the file is missing");
+            messageBox(Modal.Warning, qsTr("The project file is damaged (%1).\nSections which couldn't be recovered: %2.\n\nDo you want to continue with the partial data?").arg(error).arg(dropped), [
+                { text: qsTr("Continue"), accent: true, clicked: () => loadGyroflowData(JSON.parse(recovered_data)) },
+                { text: qsTr("Cancel") },
+            ]);
+        }
         function onGyroflow_file_loaded(obj) {
             if (obj && +obj.version > 0) {
                 const info = obj.video_info || { };
@@ -98,6 +106,12 @@ Item {
                     if (info.hasOwnProperty("rotation") && Math.abs(+info.rotation) > 0) {
                         vidInfo.updateEntryWithTrigger("Rotation", +info.rotation);
                     }
+                    if (info.hasOwnProperty("input_crop")) {
+                        vidInfo.setInputCrop(info.input_crop);
+                    }
+                }
+                if (obj.hasOwnProperty("video_filter")) {
+                    vidInfo.setVideoFilter(obj.video_filter || "");
                 }
 
                 for (const ts in obj.offsets) {
@@ -205,6 +219,7 @@ Item {
         }
 
         root.loadedFileUrl = url;
+        window.exportSettings.frameHashes = ({}); // Reference checksums are loaded with the project
         if (!skip_detection) {
             let newUrl;
             if (newUrl = detectImageSequence(url)) {
@@ -463,6 +478,7 @@ Item {
                     }
                 }
 
+                onPlayingChanged: controller.set_preview_playing(playing, vid);
                 onCurrentFrameChanged: {
                     fovChanged();
                     controller.update_keyframe_values(timestamp);
@@ -545,11 +561,28 @@ Item {
                 }
             }
 
-            InfoMessage {
+            Column {
                 width: vid.width;
-                type: InfoMessage.Warning;
-                visible: vid.loaded && !controller.lens_loaded && !isCalibrator;
-                text: qsTr("Lens profile is not loaded, the results will not look correct. Please load a lens profile for your camera.");
+                InfoMessage {
+                    width: parent.width;
+                    type: InfoMessage.Warning;
+                    visible: vid.loaded && !controller.lens_loaded && !isCalibrator;
+                    text: qsTr("Lens profile is not loaded, the results will not look correct. Please load a lens profile for your camera.");
+                }
+                InfoMessage {
+                    id: autoOrientationInfo;
+                    width: parent.width;
+                    type: InfoMessage.Info;
+                    visible: vid.loaded && text.length > 0;
+                    Timer { id: autoOrientationTimer; interval: 10000; onTriggered: autoOrientationInfo.text = ""; }
+                    Connections {
+                        target: controller;
+                        function onOrientation_auto_applied(video_rotation: real, camera_orientation: real) {
+                            autoOrientationInfo.text = qsTr("Camera was recorded rotated by %1°, video rotation was set to %2° automatically.").arg(camera_orientation).arg(video_rotation);
+                            autoOrientationTimer.restart();
+                        }
+                    }
+                }
             }
             MouseArea {
                 anchors.fill: parent;
@@ -650,6 +683,9 @@ Item {
             function onCompute_progress(id: real, progress: real) {
                 videoLoader.active = progress < 1;
                 videoLoader.cancelable = false;
+                if (progress >= 1 && timeline.showZoomCurve) {
+                    Qt.callLater(controller.update_zoom_curve, timeline.getChart());
+                }
             }
             function onSync_progress(progress: real, ready: int, total: int) {
                 videoLoader.active = progress < 1;
@@ -702,6 +738,13 @@ Item {
                         font.pixelSize: 11 * dpiScale;
                         anchors.verticalCenter: parent.verticalCenter;
                     }
+                    BasicText {
+                        visible: !!window && window.exportSettings.videoSpeedChanged;
+                        text: visible? qsTr("Output: %1").arg(timeline.outputTimeAtPosition((vid.currentFrame + 1) / Math.max(1, vid.frameCount))) : "";
+                        leftPadding: 5 * dpiScale;
+                        font.pixelSize: 11 * dpiScale;
+                        anchors.verticalCenter: parent.verticalCenter;
+                    }
                 }
                 BasicText {
                     id: currentFovText;
@@ -714,7 +757,7 @@ Item {
                 anchors.centerIn: parent;
                 spacing: 5 * dpiScale;
                 enabled: vid.loaded;
-                Button { text: "["; font.bold: true; onClicked: timeline.setTrim(timeline.position, timeline.trimEnd); tooltip: qsTr("Trim start"); }
+                Button { text: "["; font.bold: true; onClicked: timeline.editTrim(timeline.position, timeline.trimEnd); tooltip: qsTr("Trim start"); }
                 Button { iconName: "chevron-left"; tooltip: qsTr("Previous frame"); onClicked: vid.currentFrame -= 1; }
                 Button {
                     onClicked: if (vid.playing) vid.pause(); else vid.play();
@@ -722,7 +765,7 @@ Item {
                     iconName: vid.playing? "pause" : "play";
                 }
                 Button { iconName: "chevron-right"; tooltip: qsTr("Next frame"); onClicked: vid.currentFrame += 1; }
-                Button { text: "]"; font.bold: true; onClicked: timeline.setTrim(timeline.trimStart, timeline.position); tooltip: qsTr("Trim end"); }
+                Button { text: "]"; font.bold: true; onClicked: timeline.editTrim(timeline.trimStart, timeline.position); tooltip: qsTr("Trim end"); }
             }
             Row {
                 enabled: vid.loaded;
@@ -752,6 +795,38 @@ Item {
                     tooltip: qsTr("Toggle stabilization");
                 }
 
+                LinkButton {
+                    height: Math.round(parent.height);
+                    anchors.verticalCenter: parent.verticalCenter;
+                    iconName: "save";
+                    opacity: 0.5;
+                    leftPadding: 6 * dpiScale;
+                    rightPadding: 6 * dpiScale;
+                    topPadding: 8 * dpiScale;
+                    bottomPadding: 8 * dpiScale;
+                    tooltip: qsTr("Save current frame");
+                    onClicked: {
+                        vid.pause();
+                        frameFileDialog.timestamp_us = Math.round(vid.timestamp * 1000);
+                        frameFileDialog.open();
+                    }
+                    FileDialog {
+                        id: frameFileDialog;
+                        fileMode: FileDialog.SaveFile;
+                        title: qsTr("Select file destination");
+                        nameFilters: ["*.png", "*.jpg", "*.tif"];
+                        type: "output-frame";
+                        property real timestamp_us: 0;
+                        onAccepted: {
+                            const url = selectedFile;
+                            messageBox(Modal.Question, qsTr("Do you want to burn in the file name, timestamp and stabilization settings?"), [
+                                { text: qsTr("Yes"), clicked: () => controller.export_frame(url, timestamp_us, 0, 0, true) },
+                                { text: qsTr("No"), accent: true, clicked: () => controller.export_frame(url, timestamp_us, 0, 0, false) },
+                            ]);
+                        }
+                    }
+                }
+
                 SmallLinkButton {
                     iconName: checked? "sound" : "sound-mute";
                     onClicked: vid.muted = !vid.muted;
@@ -802,6 +877,8 @@ Item {
                 anchors.fill: parent;
                 fullScreen: root.fullScreen;
 
+                // Only user edits apply the policies, loading a project or a video just sets the range
+                onTrimEdited: (start, end) => controller.set_trim_range(start, end, trimKeyframesPolicy, trimOffsetsPolicy);
                 onTrimStartChanged: {
                     controller.set_trim_start(trimStart);
                     vid.setPlaybackRange(trimStart * vid.duration, trimEnd * vid.duration);
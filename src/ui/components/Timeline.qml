@@ -28,6 +28,19 @@ Item {
     property alias inner: inner;
 
     property bool fullScreen: false;
+    property bool showShakeHeatmap: true;
+    property bool showZoomCurve: false;
+    property bool chartPhysicalUnits: false;
+    property bool chartUnfilteredOverlay: false;
+    onChartPhysicalUnitsChanged: Qt.callLater(controller.set_chart_physical_units, chart, chartPhysicalUnits);
+    onChartUnfilteredOverlayChanged: Qt.callLater(controller.set_chart_unfiltered_overlay, chart, chartUnfilteredOverlay);
+    // What happens to keyframes and sync points outside of the new range when trimming: "keep", "delete" or "rescale"
+    property string trimKeyframesPolicy: "keep";
+    property string trimOffsetsPolicy: "keep";
+    onShowZoomCurveChanged: {
+        chart.setAxisVisible(8, showZoomCurve);
+        if (showZoomCurve) Qt.callLater(controller.update_zoom_curve, chart);
+    }
 
     property real value: 0;
     readonly property real position: vid.timestamp / root.orgDurationMs;
@@ -53,6 +66,13 @@ Item {
         const time = Math.max(0, durationMs * pos);
         return new Date(time).toISOString().substring(11, 11+8);
     }
+    // Time in the rendered video, which differs from the source time when the video speed is changed
+    function outputTimeAtPosition(pos: real): string {
+        const time = Math.max(0, controller.source_to_output_timestamp(durationMs * pos));
+        return new Date(time).toISOString().substring(11, 11+8);
+    }
+
+    signal trimEdited(real start, real end);
 
     function setTrim(start: real, end: real) {
         if (start >= end) {
@@ -62,6 +82,13 @@ Item {
             trimEnd   = end;
         }
     }
+    // Trim changed by the user, the keyframes and sync points policies are applied once, before the range changes
+    function editTrim(start: real, end: real) {
+        if (start >= end) { start = 0; end = 1.0; }
+        if (start == trimStart && end == trimEnd) return;
+        trimEdited(start, end);
+        setTrim(start, end);
+    }
 
     function resetTrim() {
         root.trimStart = 0;
@@ -130,8 +157,30 @@ Item {
         });
     }
 
+    Connections {
+        target: controller;
+        function onTrim_suggested(trim_start: real, trim_end: real, start_handling_ms: real, end_handling_ms: real) {
+            if (start_handling_ms <= 0 && end_handling_ms <= 0) {
+                messageBox(Modal.Info, qsTr("No handling was detected at the start or end of the clip."), [ { text: qsTr("Ok") } ]);
+                return;
+            }
+            const text = qsTr("Handling of the camera was detected in the first %1 s and the last %2 s of the clip.\nDo you want to trim it?")
+                            .arg((start_handling_ms / 1000).toFixed(1)).arg((end_handling_ms / 1000).toFixed(1));
+            messageBox(Modal.Question, text, [
+                { text: qsTr("Apply"), accent: true, clicked: () => root.editTrim(trim_start, trim_end) },
+                { text: qsTr("Cancel") },
+            ]);
+        }
+    }
+
     Settings {
         property alias timelineChart: chart.viewMode;
+        property alias showShakeHeatmap: root.showShakeHeatmap;
+        property alias showZoomCurve: root.showZoomCurve;
+        property alias chartPhysicalUnits: root.chartPhysicalUnits;
+        property alias chartUnfilteredOverlay: root.chartUnfilteredOverlay;
+        property alias trimKeyframesPolicy: root.trimKeyframesPolicy;
+        property alias trimOffsetsPolicy: root.trimOffsetsPolicy;
     }
 
     focus: true;
@@ -192,6 +241,42 @@ Item {
                     a6.checked = chart.getAxisVisible(6);
                     a7.checked = chart.getAxisVisible(7);
                 }
+                Component.onCompleted: {
+                    controller.set_chart_physical_units(chart, root.chartPhysicalUnits);
+                    controller.set_chart_unfiltered_overlay(chart, root.chartUnfilteredOverlay);
+                }
+            }
+            // Value at the top of the chart
+            BasicText {
+                visible: chart.unitName.length > 0 && !root.fullScreen;
+                x: 3 * dpiScale;
+                y: 3 * dpiScale;
+                leftPadding: 0;
+                font.pixelSize: 10 * dpiScale;
+                opacity: 0.6;
+                text: "±" + +(chart.unitScale / chart.vscale).toPrecision(3) + " " + chart.unitName;
+            }
+            // Shake severity per second, from green (steady) to red (severe)
+            Item {
+                id: shakeHeatmap;
+                anchors.left: parent.left;
+                anchors.right: parent.right;
+                anchors.bottom: parent.bottom;
+                height: 4 * dpiScale;
+                clip: true;
+                visible: root.showShakeHeatmap && !root.fullScreen;
+                Repeater {
+                    model: controller.shake_model;
+                    Rectangle {
+                        readonly property real start: timestamp_us / (root.durationMs * 1000.0);
+                        readonly property real end: (timestamp_us + duration_us) / (root.durationMs * 1000.0);
+                        x: root.mapToVisibleArea(start) * shakeHeatmap.width;
+                        width: (root.mapToVisibleArea(end) - root.mapToVisibleArea(start)) * shakeHeatmap.width;
+                        height: parent.height;
+                        color: Qt.hsla((1.0 - level) * 0.33, 0.9, 0.5, 1.0);
+                        opacity: level < 0.05? 0 : 0.4 + level * 0.5;
+                    }
+                }
             }
             TimelineKeyframesView {
                 id: keyframes;
@@ -497,6 +582,18 @@ Item {
                 text: qsTr("Estimate gyro bias here");
                 onTriggered: controller.estimate_bias(root.position);
             }
+            Action {
+                iconName: "bias";
+                text: qsTr("Estimate gyro bias from still parts");
+                onTriggered: {
+                    // Evenly spaced 400 ms segments within the trim range, the moving ones are skipped in the estimation
+                    const count = Math.max(1, Math.min(50, Math.floor((root.trimEnd - root.trimStart) * root.durationMs / 400)));
+                    const step = (root.trimEnd - root.trimStart) / count;
+                    let positions = [];
+                    for (let i = 0; i < count; ++i) positions.push(root.trimStart + step * (i + 0.5));
+                    controller.estimate_bias(positions.join(";"));
+                }
+            }
             Action {
                 iconName: "bin;#f67575";
                 text: qsTr("Delete all sync points");
@@ -514,6 +611,49 @@ Item {
                 Action { checkable: true; checked: chart.viewMode === 2; text: qsTr("Magnetometer");  onTriggered: root.setDisplayMode(2); }
                 Action { checkable: true; checked: chart.viewMode === 3; text: qsTr("Quaternions");   onTriggered: root.setDisplayMode(3); }
             }
+            Action {
+                checkable: true;
+                checked: root.showShakeHeatmap;
+                text: qsTr("Show shake heatmap");
+                onTriggered: root.showShakeHeatmap = checked;
+            }
+            Action {
+                checkable: true;
+                checked: root.showZoomCurve;
+                text: qsTr("Show zoom curve");
+                onTriggered: root.showZoomCurve = checked;
+            }
+            Action {
+                checkable: true;
+                checked: root.chartPhysicalUnits;
+                text: qsTr("Show values in physical units");
+                onTriggered: root.chartPhysicalUnits = checked;
+            }
+            Action {
+                checkable: true;
+                checked: root.chartUnfilteredOverlay;
+                text: qsTr("Show signal before low pass filter");
+                onTriggered: root.chartUnfilteredOverlay = checked;
+            }
+            Action {
+                iconName: "search";
+                text: qsTr("Suggest trim from motion data");
+                onTriggered: controller.suggest_trim();
+            }
+            Menu {
+                font.pixelSize: 11.5 * dpiScale;
+                title: qsTr("When trimming, keyframes outside of the range are")
+                Action { checkable: true; checked: root.trimKeyframesPolicy === "keep";    text: qsTr("Kept");                          onTriggered: root.trimKeyframesPolicy = "keep"; }
+                Action { checkable: true; checked: root.trimKeyframesPolicy === "delete";  text: qsTr("Deleted");                       onTriggered: root.trimKeyframesPolicy = "delete"; }
+                Action { checkable: true; checked: root.trimKeyframesPolicy === "rescale"; text: qsTr("Rescaled to fit the new range"); onTriggered: root.trimKeyframesPolicy = "rescale"; }
+            }
+            Menu {
+                font.pixelSize: 11.5 * dpiScale;
+                title: qsTr("When trimming, sync points outside of the range are")
+                Action { checkable: true; checked: root.trimOffsetsPolicy === "keep";    text: qsTr("Kept");                          onTriggered: root.trimOffsetsPolicy = "keep"; }
+                Action { checkable: true; checked: root.trimOffsetsPolicy === "delete";  text: qsTr("Deleted");                       onTriggered: root.trimOffsetsPolicy = "delete"; }
+                Action { checkable: true; checked: root.trimOffsetsPolicy === "rescale"; text: qsTr("Rescaled to fit the new range"); onTriggered: root.trimOffsetsPolicy = "rescale"; }
+            }
             Component.onCompleted: {
                 if (!isCalibrator) {
                     timelineContextMenu.removeAction(addCalibAction);
@@ -547,9 +687,9 @@ Item {
                     if (!vid.playing) root.setPosition(dragPos);
                 }
                 visible: root.trimActive;
-                onChangeTrimStart: (val) => root.setTrim(val, root.trimEnd);
-                onChangeTrimEnd: (val) => root.setTrim(root.trimStart, val);
-                onReset: root.resetTrim();
+                onChangeTrimStart: (val) => root.editTrim(val, root.trimEnd);
+                onChangeTrimEnd: (val) => root.editTrim(root.trimStart, val);
+                onReset: root.editTrim(0, 1.0);
             }
         }
 
@@ -7,6 +7,8 @@ use std::collections::BTreeMap;
 
 use qmetaobject::*;
 use crate::core::gyro_source::{ GyroSource, TimeIMU, TimeQuat };
+use crate::core::chart_pyramid::MinMaxPyramid;
+use crate::core::zooming::ZoomCurve;
 use crate::util;
 
 #[derive(Default, Debug, Clone)]
@@ -17,7 +19,7 @@ pub struct ChartData {
 
 #[derive(Default)]
 struct Series {
-    data: BTreeMap<i64, f64>, // timestamp, value
+    data: MinMaxPyramid, // timestamp, value
     lines: Vec<Vec<QLineF>>,
     is_optflow: bool,
     visible: bool,
@@ -30,6 +32,11 @@ struct Series {
 // viewMode 2: Magn only
 // viewMode 3: Quaternions
 // viewMode 3: Quaternions + smoothed quaternions
+// Series 8 is the adaptive zoom curve, drawn on top of any view mode
+// Series 9-11 are the X/Y/Z of the signal without the low pass filter, drawn under the gyro or accelerometer
+
+// With `physical_units` the gyro is scaled in deg/s and the accelerometer in m/s², the top of the chart
+// at vscale 1 is `unitScale`, rounded up to 1, 2 or 5 times power of 10 so it can be used for a readable label
 
 #[derive(Default, QObject)]
 pub struct TimelineGyroChart {
@@ -37,7 +44,8 @@ pub struct TimelineGyroChart {
 
     visibleAreaLeft: qt_property!(f64; WRITE setVisibleAreaLeft),
     visibleAreaRight: qt_property!(f64; WRITE setVisibleAreaRight),
-    vscale: qt_property!(f64; WRITE setVScale),
+    vscale: qt_property!(f64; WRITE setVScale NOTIFY vscaleChanged),
+    vscaleChanged: qt_signal!(),
 
     setDurationMs: qt_method!(fn(&mut self, v: f64)),
     setVScaleToVisibleArea: qt_method!(fn(&mut self)),
@@ -48,7 +56,11 @@ pub struct TimelineGyroChart {
 
     viewMode: qt_property!(u32; WRITE setViewMode NOTIFY viewModeChanged),
 
-    series: [Series; 4+4],
+    unitName: qt_property!(QString; READ getUnitName NOTIFY unitsChanged),
+    unitScale: qt_property!(f64; READ getUnitScale NOTIFY unitsChanged),
+    unitsChanged: qt_signal!(),
+
+    series: [Series; 4+4+1+3],
 
     gyro: Vec<ChartData>,
     accl: Vec<ChartData>,
@@ -59,8 +71,14 @@ pub struct TimelineGyroChart {
     org_sync_results: Vec<ChartData>,
     sync_quats: Vec<ChartData>,
     org_sync_quats: Vec<ChartData>,
+    zoom_curve: Vec<ChartData>,
+    unfiltered: Vec<ChartData>,
+
+    pub physical_units: bool,
+    pub show_unfiltered: bool,
 
     gyro_max: Option<f64>,
+    data_max: Option<f64>, // Normalization factor of the current view mode
     duration_ms: f64,
 }
 
@@ -70,9 +88,19 @@ impl TimelineGyroChart {
     fn setVisibleAreaRight(&mut self, v: f64) { self.visibleAreaRight = v; self.update(); }
     fn setAxisVisible     (&mut self, a: usize, v: bool) { self.series[a].visible = v; self.update(); self.axisVisibleChanged(); }
     fn getAxisVisible     (&self, a: usize) -> bool { self.series[a].visible }
-    fn setVScale          (&mut self, v: f64) { self.vscale = v.max(0.1); self.update(); }
+    fn setVScale          (&mut self, v: f64) { self.vscale = v.max(0.1); self.update(); self.vscaleChanged(); }
     fn setViewMode        (&mut self, v: u32) { self.viewMode = v; self.update_data(); self.viewModeChanged(); }
 
+    fn getUnitName(&self) -> QString {
+        if !self.physical_units || self.data_max.is_none() { return QString::default(); }
+        match self.viewMode {
+            0 => QString::from("°/s"),
+            1 => QString::from("m/s²"),
+            _ => QString::default()
+        }
+    }
+    fn getUnitScale(&self) -> f64 { self.data_max.unwrap_or(1.0) }
+
     pub fn setVScaleToVisibleArea(&mut self) {
         let rect = (self as &dyn QQuickItem).bounding_rect();
         let mut min_height = f64::MAX;
@@ -99,6 +127,7 @@ impl TimelineGyroChart {
         self.vscale = 0.9 / min_element.abs().max(max_element.abs());
 
         self.update();
+        self.vscaleChanged();
     }
 
     pub fn update(&mut self) {
@@ -122,36 +151,31 @@ impl TimelineGyroChart {
                 let mut to_timestamp = ((self.visibleAreaRight + 0.01) * duration_us).ceil() as i64;
                 if from_timestamp >= to_timestamp { to_timestamp = from_timestamp + 1; }
 
-                let resolution = rect.width * 10.0;
-                let mut range = serie.data.range(from_timestamp..=to_timestamp);
-                let num_samples = range.clone().count();
+                // Min and max for every pixel
+                let points_per_pixel = if crate::core::low_memory::is_enabled() { crate::core::low_memory::CHART_POINTS_PER_PIXEL } else { 2.0 };
+                let points = serie.data.query(from_timestamp, to_timestamp, (rect.width * points_per_pixel).ceil() as usize);
 
                 serie.lines.clear();
-                if num_samples > 1 {
-                    if let Some(first_item) = range.next() {
-                        let mut line = Vec::new();
-                        let mut prev_point = (*first_item.0, QPointF {
-                            x: map_to_visible_area(*first_item.0 as f64 / duration_us) * rect.width,
-                            y: (1.0 - *first_item.1 * self.vscale) * half_height
-                        });
-                        let step = (num_samples / resolution as usize).max(1);
-                        for data in range.step_by(step) {
-                            let point = QPointF {
-                                x: map_to_visible_area(*data.0 as f64 / duration_us) * rect.width,
-                                y: (1.0 - *data.1 * self.vscale) * half_height
-                            };
-
-                            let new_line = serie.is_optflow && *data.0 - prev_point.0 > 100_000;
-                            if new_line {
-                                serie.lines.push(line);
-                                line = Vec::new();
-                            } else {
-                                line.push(QLineF { pt1: prev_point.1, pt2: point });
-                            }
-                            prev_point = (*data.0, point);
+                if points.len() > 1 {
+                    let to_point = |(ts, v): (i64, f64)| QPointF {
+                        x: map_to_visible_area(ts as f64 / duration_us) * rect.width,
+                        y: (1.0 - v * self.vscale) * half_height
+                    };
+                    let mut line = Vec::new();
+                    let mut prev_point = (points[0].0, to_point(points[0]));
+                    for &data in &points[1..] {
+                        let point = to_point(data);
+
+                        let new_line = serie.is_optflow && data.0 - prev_point.0 > 100_000;
+                        if new_line {
+                            serie.lines.push(line);
+                            line = Vec::new();
+                        } else {
+                            line.push(QLineF { pt1: prev_point.1, pt2: point });
                         }
-                        serie.lines.push(line);
+                        prev_point = (data.0, point);
                     }
+                    serie.lines.push(line);
                 }
             } else {
                 serie.lines.clear();
@@ -213,6 +237,20 @@ impl TimelineGyroChart {
         self.magn = Vec::with_capacity(gyro.raw_imu.len());
         self.quats = Vec::with_capacity(gyro.quaternions.len());
         self.smoothed_quats = Vec::with_capacity(gyro.smoothed_quaternions.len());
+        self.unfiltered = Vec::new();
+
+        let has_lpf = gyro.imu_lpf > 0.0 || gyro.acc_lpf.map_or(false, |x| x > 0.0);
+        if self.show_unfiltered && has_lpf && (self.viewMode == 0 || self.viewMode == 1) {
+            for x in &gyro.unfiltered_imu() {
+                let v = if self.viewMode == 0 { x.gyro.as_ref() } else { x.accl.as_ref() };
+                if let Some(v) = v {
+                    self.unfiltered.push(ChartData {
+                        timestamp_us: ((x.timestamp_ms + gyro.offset_at_gyro_timestamp(x.timestamp_ms)) * 1000.0) as i64,
+                        values: [v[0], v[1], v[2], 0.0]
+                    });
+                }
+            }
+        }
 
         for x in &gyro.raw_imu {
             if self.viewMode == 0 {
@@ -259,32 +297,56 @@ impl TimelineGyroChart {
             add_quats(&gyro.org_smoothed_quaternions, &mut self.smoothed_quats);
         }
 
-        match self.viewMode {
-            0 => { self.gyro_max = Self::normalize_height(&mut self.gyro, None); },
-            1 => { Self::normalize_height(&mut self.accl, None); },
-            2 => { Self::normalize_height(&mut self.magn, None); },
+        let physical = self.physical_units && (self.viewMode == 0 || self.viewMode == 1);
+        let unit_max = |data: &[ChartData]| -> Option<f64> {
+            let max = Self::max_value(data);
+            if physical && max > 0.0 { Some(Self::nice_scale(max)) } else { None }
+        };
+        self.data_max = match self.viewMode {
+            0 => {
+                let max = unit_max(&self.gyro);
+                self.gyro_max = Self::normalize_height(&mut self.gyro, max);
+                self.gyro_max
+            },
+            1 => {
+                let max = unit_max(&self.accl);
+                Self::normalize_height(&mut self.accl, max)
+            },
+            2 => { Self::normalize_height(&mut self.magn, None) },
             3 => {
                 let qmax = Self::normalize_height(&mut self.quats, None);
                 Self::normalize_height(&mut self.smoothed_quats, qmax);
+                qmax
             },
-            _ => { }
-        }
+            _ => None
+        };
+        // Same scale as the filtered signal, so the difference is visible
+        Self::normalize_height(&mut self.unfiltered, self.data_max);
+        self.unitsChanged();
 
         self.sync_results = self.org_sync_results.clone();
         Self::normalize_height(&mut self.sync_results, self.gyro_max);
 
         self.update_data();
     }
-    fn get_serie_vector(vec: &[ChartData], i: usize) -> BTreeMap<i64, f64> {
-        let mut ret = BTreeMap::new();
-        for x in vec {
-            ret.insert(x.timestamp_us, x.values[i]);
-        }
-        ret
+    pub fn setZoomCurve(&mut self, curve: &ZoomCurve) {
+        // Normalized to the zoom range of the clip, so the spikes stand out regardless of the absolute zoom
+        let (min, max) = curve.samples().fold((f64::MAX, f64::MIN), |(min, max), (_, z)| (min.min(z), max.max(z)));
+        let range = max - min;
+        self.zoom_curve = curve.samples().map(|(ts, z)| ChartData {
+            timestamp_us: ts,
+            values: [if range > 0.0001 { (z - min) / range * 1.8 - 0.9 } else { 0.0 }, 0.0, 0.0, 0.0]
+        }).collect();
+
+        self.update_data();
+    }
+
+    fn get_serie_vector(vec: &[ChartData], i: usize) -> MinMaxPyramid {
+        MinMaxPyramid::new(vec.iter().map(|x| (x.timestamp_us, x.values[i])))
     }
     pub fn update_data(&mut self) {
         for s in &mut self.series {
-            s.data.clear();
+            s.data = MinMaxPyramid::default();
         }
         match self.viewMode {
             0 => {  // Gyroscope
@@ -330,20 +392,32 @@ impl TimelineGyroChart {
             }
             _ => panic!("Invalid view mode")
         }
+        self.series[8].data = Self::get_serie_vector(&self.zoom_curve, 0);
+        // Empty unless the unfiltered data applies to the view mode, see `setFromGyroSource`
+        self.series[9].data  = Self::get_serie_vector(&self.unfiltered, 0);
+        self.series[10].data = Self::get_serie_vector(&self.unfiltered, 1);
+        self.series[11].data = Self::get_serie_vector(&self.unfiltered, 2);
 
         self.update();
     }
 
-    fn normalize_height(data: &mut [ChartData], max: Option<f64>) -> Option<f64> {
-        let max = max.unwrap_or_else(|| {
-            let mut max = 0.0;
-            for x in data.iter() {
-                for i in 0..4 {
-                    if x.values[i].abs() > max { max = x.values[i].abs(); }
-                }
+    fn max_value(data: &[ChartData]) -> f64 {
+        let mut max = 0.0;
+        for x in data.iter() {
+            for i in 0..4 {
+                if x.values[i].abs() > max { max = x.values[i].abs(); }
             }
-            max
-        });
+        }
+        max
+    }
+    // Smallest 1, 2 or 5 * 10^n which is >= v
+    fn nice_scale(v: f64) -> f64 {
+        let pow = 10.0f64.powf(v.log10().floor());
+        [1.0, 2.0, 5.0, 10.0].into_iter().map(|x| x * pow).find(|x| *x >= v).unwrap_or(v)
+    }
+
+    fn normalize_height(data: &mut [ChartData], max: Option<f64>) -> Option<f64> {
+        let max = max.unwrap_or_else(|| Self::max_value(data));
         if max > 0.0 {
             for x in data.iter_mut() {
                 for i in 0..4 {
@@ -368,6 +442,10 @@ impl QQuickItem for TimelineGyroChart {
         self.series[4].visible = true;
         self.series[5].visible = true;
         self.series[6].visible = true;
+
+        self.series[9].visible = true;
+        self.series[10].visible = true;
+        self.series[11].visible = true;
     }
 
     fn geometry_changed(&mut self, _new: QRectF, _old: QRectF) {
@@ -379,6 +457,11 @@ impl QQuickPaintedItem for TimelineGyroChart {
     fn paint(&mut self, p: &mut QPainter) {
         p.set_render_hint(QPainterRenderHint::Antialiasing, true);
 
+        // Unfiltered signal under the filtered one, follows the visibility of its axis
+        if self.series[0].visible { self.drawAxis(p, 9,  "#608f4c4c"); } // Unfiltered X
+        if self.series[1].visible { self.drawAxis(p, 10, "#604c8f4d"); } // Unfiltered Y
+        if self.series[2].visible { self.drawAxis(p, 11, "#604c7c8f"); } // Unfiltered Z
+
         if self.series[0].visible { self.drawAxis(p, 0, "#8f4c4c"); } // X
         if self.series[1].visible { self.drawAxis(p, 1, "#4c8f4d"); } // Y
         if self.series[2].visible { self.drawAxis(p, 2, "#4c7c8f"); } // Z
@@ -389,5 +472,7 @@ impl QQuickPaintedItem for TimelineGyroChart {
         if self.series[6].visible { self.drawAxis(p, 6, "#88deff"); } // Sync Z
         if self.series[7].visible { self.drawAxis(p, 7, "#ff88ff"); } // Sync Angle
 
+        if self.series[8].visible { self.drawAxis(p, 8, "#e8c547"); } // Zoom
+
     }
 }
@@ -57,7 +57,7 @@ impl TimelineKeyframesView {
             for pt in &v.points {
                 if x >= pt.point.x - 8.0 && x <= pt.point.x + 8.0 &&
                    y >= pt.point.y - 8.0 && y <= pt.point.y + 8.0 {
-                    return QJSValue::from(QString::from(format!("{:?}:{}:{}:{}", kf, pt.timestamp, keyframe_text(kf), keyframe_format_value(kf, pt.value))));
+                    return QJSValue::from(QString::from(format!("{:?}:{}:{}:{}", kf, pt.timestamp, keyframe_message(kf), keyframe_format_value(kf, pt.value))));
                 }
             }
         }
@@ -67,13 +67,13 @@ impl TimelineKeyframesView {
 
     fn nextKeyframe(&self, typ: String) -> QJSValue {
         if let Some(res) = self.mgr.next_keyframe((self.videoTimestamp * 1000.0) as i64, KeyframeType::from_str(&typ).ok()) {
-            return QJSValue::from(QString::from(format!("{:?}:{}:{}:{}", res.0, res.1, keyframe_text(&res.0), keyframe_format_value(&res.0, res.2.value))));
+            return QJSValue::from(QString::from(format!("{:?}:{}:{}:{}", res.0, res.1, keyframe_message(&res.0), keyframe_format_value(&res.0, res.2.value))));
         }
         QJSValue::default()
     }
     fn prevKeyframe(&self, typ: String) -> QJSValue {
         if let Some(res) = self.mgr.prev_keyframe((self.videoTimestamp * 1000.0) as i64, KeyframeType::from_str(&typ).ok()) {
-            return QJSValue::from(QString::from(format!("{:?}:{}:{}:{}", res.0, res.1, keyframe_text(&res.0), keyframe_format_value(&res.0, res.2.value))));
+            return QJSValue::from(QString::from(format!("{:?}:{}:{}:{}", res.0, res.1, keyframe_message(&res.0), keyframe_format_value(&res.0, res.2.value))));
         }
         QJSValue::default()
     }
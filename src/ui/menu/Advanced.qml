@@ -20,6 +20,9 @@ MenuItem {
         property alias uiScaling: uiScaling.currentIndex;
         property alias safeAreaGuide: safeAreaGuide.checked;
         property alias gpudecode: gpudecode.checked;
+        property alias offline: offlineMode.checked;
+        property alias embedGyroFile: embedGyroFile.checked;
+        property alias deterministicCompute: deterministicCompute.checked;
         property alias backgroundMode: backgroundMode.currentIndex;
         property alias marginPixels: marginPixels.value;
         property alias featherPixels: featherPixels.value;
@@ -40,7 +43,7 @@ MenuItem {
 
         ComboBox {
             id: previewResolution;
-            model: [QT_TRANSLATE_NOOP("Popup", "Full"), "4k", "1080p", "720p", "480p"];
+            model: [QT_TRANSLATE_NOOP("Popup", "Full"), "4k", "1080p", "720p", "480p", QT_TRANSLATE_NOOP("Popup", "Auto")];
             font.pixelSize: 12 * dpiScale;
             width: parent.width;
             currentIndex: 2;
@@ -51,6 +54,7 @@ MenuItem {
                     case 2: target_height = 1080; break;
                     case 3: target_height = 720; break;
                     case 4: target_height = 480; break;
+                    case 5: target_height = 0; break; // Auto
                 }
 
                 controller.set_preview_resolution(target_height, window.videoArea.vid);
@@ -208,6 +212,68 @@ MenuItem {
         checked: false;
         onCheckedChanged: controller.set_zero_copy(window.videoArea.vid, checked);
     }
+    Label {
+        position: Label.LeftPosition;
+        text: qsTr("HDR preview");
+        ComboBox {
+            id: previewHdr;
+            model: [QT_TRANSLATE_NOOP("Popup", "Off"), "PQ", "HLG"];
+            font.pixelSize: 12 * dpiScale;
+            width: parent.width;
+            currentIndex: +settings.value("previewHdr", 0);
+            Component.onCompleted: controller.set_preview_hdr(window.videoArea.vid, currentIndex);
+            tooltip: qsTr("Output the zero-copy preview as 10-bit HDR when the display supports it.\nRequires restart of the application.");
+            onCurrentIndexChanged: {
+                settings.setValue("previewHdr", currentIndex);
+                controller.set_preview_hdr(window.videoArea.vid, currentIndex);
+            }
+        }
+    }
+    Label {
+        position: Label.LeftPosition;
+        text: qsTr("Preview during render");
+        ComboBox {
+            id: computePolicy;
+            model: [QT_TRANSLATE_NOOP("Popup", "Normal"), QT_TRANSLATE_NOOP("Popup", "Throttle"), QT_TRANSLATE_NOOP("Popup", "Pause")];
+            font.pixelSize: 12 * dpiScale;
+            width: parent.width;
+            currentIndex: +settings.value("computePolicy", 0);
+            Component.onCompleted: controller.set_compute_policy(currentIndex);
+            tooltip: qsTr("What to do with the preview calculations and synchronization while a video is rendering.\nThrottling or pausing them leaves more GPU time for the render.");
+            onCurrentIndexChanged: {
+                settings.setValue("computePolicy", currentIndex);
+                controller.set_compute_policy(currentIndex);
+            }
+        }
+    }
+    CheckBox {
+        id: lowMemory;
+        text: qsTr("Low memory mode");
+        tooltip: qsTr("Use less memory at the cost of speed: smaller preview cache and undistortion tables, coarser charts, shallower decoder queues and limited memory for rendering.\nUse it if Gyroflow runs out of memory with long or high resolution videos.\nRequires restart of the application.");
+        checked: controller.is_low_memory();
+        onCheckedChanged: settings.setValue("lowMemory", checked);
+    }
+    CheckBox {
+        id: offlineMode;
+        text: qsTr("Offline mode");
+        tooltip: qsTr("Don't make any network requests (update check, lens profile database sync, ratings and uploads).\nPending actions will be performed when this option is disabled.");
+        checked: false;
+        onCheckedChanged: controller.offline = checked;
+    }
+    CheckBox {
+        id: embedGyroFile;
+        text: qsTr("Embed motion data file in project");
+        tooltip: qsTr("Store a compressed copy of the external gyro log (eg. CSV or blackbox) in the exported .gyroflow file.\nIf the original file is missing when the project is opened, it will be extracted next to the project file.");
+        checked: false;
+        onCheckedChanged: controller.embed_gyro_file = checked;
+    }
+    CheckBox {
+        id: deterministicCompute;
+        text: qsTr("Deterministic computation");
+        tooltip: qsTr("Always recompute the smoothing and calculate the zooming on the CPU, so the same input always gives bit-identical results.\nUseful for comparing renders by their hashes, but makes the calculations slower.");
+        checked: false;
+        onCheckedChanged: controller.set_deterministic_compute(checked);
+    }
     CheckBox {
         id: gpudecode;
         text: qsTr("Use GPU decoding");
@@ -259,6 +325,36 @@ MenuItem {
                 }
                 const text = currentIndex == model.length - 1? "cpu" : currentText;
                 settings.setValue("processingDevice", text);
+                kernelPrecision.loadForDevice(text);
+            }
+        }
+    }
+    Label {
+        position: Label.LeftPosition;
+        text: qsTr("Processing precision");
+        visible: processingDevice.visible && processingDevice.currentIndex != processingDevice.model.length - 1;
+        ComboBox {
+            id: kernelPrecision;
+            property bool fp16Supported: true; // Not available with wgpu
+            model: fp16Supported? [QT_TRANSLATE_NOOP("Popup", "Auto"), QT_TRANSLATE_NOOP("Popup", "Full (fp32)"), QT_TRANSLATE_NOOP("Popup", "Half (fp16)")]
+                                : [QT_TRANSLATE_NOOP("Popup", "Auto"), QT_TRANSLATE_NOOP("Popup", "Full (fp32)")];
+            font.pixelSize: 12 * dpiScale;
+            width: parent.width;
+            currentIndex: 0;
+            tooltip: qsTr("Half precision is faster on mobile and Apple GPUs, but can be less accurate.\nIt's used only for 8-bit videos and only with OpenCL devices.");
+            property string device: "";
+            // Precision is saved separately for every device
+            function loadForDevice(name) {
+                device = ""; // Don't overwrite the saved value if it's not available for this device
+                fp16Supported = controller.fp16_supported(processingDevice.currentIndex);
+                currentIndex = Math.min(+settings.value("kernelPrecision/" + name, 0), model.length - 1);
+                device = name;
+                controller.set_kernel_precision(currentIndex);
+            }
+            onCurrentIndexChanged: {
+                if (!device) return;
+                settings.setValue("kernelPrecision/" + device, currentIndex);
+                controller.set_kernel_precision(currentIndex);
             }
         }
     }
@@ -274,6 +370,20 @@ MenuItem {
         }
     }
     Item { width: 1; height: 10 * dpiScale; }
+    LinkButton {
+        text: qsTr("Export diagnostics");
+        tooltip: qsTr("Save the app info, current project and the history of parameter changes to a file, which can be attached to a bug report.");
+        anchors.horizontalCenter: parent.horizontalCenter;
+        onClicked: diagnosticsFileDialog.open();
+    }
+    FileDialog {
+        id: diagnosticsFileDialog;
+        fileMode: FileDialog.SaveFile;
+        title: qsTr("Select file destination");
+        nameFilters: ["*.json"];
+        type: "output-diagnostics";
+        onAccepted: controller.export_diagnostics(selectedFile);
+    }
     LinkButton {
         text: qsTr("Reset all settings to default");
         textColor: "#f67575"
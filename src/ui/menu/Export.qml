@@ -20,6 +20,7 @@ MenuItem {
     }
 
     property bool isOsx: Qt.platform.os == "osx";
+    property bool videoSpeedChanged: videoSpeed.value != 1.0 || videoSpeed.isKeyframed;
 
     // If changing, make sure it's in sync with render_queue.rs:get_output_path
     property var exportFormats: [
@@ -29,6 +30,7 @@ MenuItem {
         { "name": "DNxHD",         "max_size": [8192, 4320], "extension": ".mov",      "gpu": false, "audio": true,  "variants": [/*"DNxHD", */"DNxHR LB", "DNxHR SQ", "DNxHR HQ", "DNxHR HQX", "DNxHR 444"] },
         { "name": "EXR Sequence",  "max_size": false,        "extension": "_%05d.exr", "gpu": false, "audio": false, "variants": [] },
         { "name": "PNG Sequence",  "max_size": false,        "extension": "_%05d.png", "gpu": false, "audio": false, "variants": ["8-bit", "16-bit"] },
+        { "name": "CineForm",      "max_size": [8192, 4320], "extension": ".mov",      "gpu": false, "audio": true,  "variants": ["Low", "Medium", "High", "Film Scan", "Film Scan 2", "Film Scan 3"] },
     ];
 
     Settings {
@@ -36,8 +38,19 @@ MenuItem {
         property alias defaultCodec: codec.currentIndex;
         property alias exportAudio: audio.checked;
         property alias keyframeDistance: keyframeDistance.value;
+        property alias maxMemory: maxMemory.value;
         property alias preserveOtherTracks: preserveOtherTracks.checked;
+        property alias gpmfPassthrough: gpmfPassthrough.checked;
         property alias padWithBlack: padWithBlack.checked;
+        property alias croppedOutput: croppedOutput.checked;
+        property alias splitDuration: splitDuration.value;
+        property alias splitAtGaps: splitAtGaps.checked;
+        property alias cudaPipeline: cudaPipeline.checked;
+        property alias vaapiPipeline: vaapiPipeline.checked;
+        property alias outputTemplate: outputTemplate.text;
+        property alias collisionPolicy: collisionPolicy.currentIndex;
+        property alias metadataArtist: metadataArtist.text;
+        property alias metadataCopyright: metadataCopyright.text;
         property alias videoSpeedAffectsSmoothing: videoSpeedAffectsSmoothing.checked;
         property alias videoSpeedAffectsZooming: videoSpeedAffectsZooming.checked;
     }
@@ -56,6 +69,7 @@ MenuItem {
     property string outCodecOptions: "";
     property real originalWidth: outWidth;
     property real originalHeight: outHeight;
+    property var frameHashes: ({}); // Reference checksums of the verified frames, frame index -> checksum
 
     property bool canExport: !resolutionWarning.visible && !resolutionWarning2.visible;
 
@@ -75,7 +89,33 @@ MenuItem {
             encoder_options:       encoderOptions.text,
             keyframe_distance:     keyframeDistance.value,
             preserve_other_tracks: preserveOtherTracks.checked,
+            gpmf_passthrough:      gpmfPassthrough.checked,
             pad_with_black:        padWithBlack.checked,
+            cropped_output:        croppedOutput.checked,
+            split_duration_s:      splitDuration.value * 60,
+            split_at_gaps:         splitAtGaps.checked,
+            cuda_pipeline:         cudaPipeline.checked,
+            vaapi_pipeline:        vaapiPipeline.checked,
+            frame_comparison:      frameComparison.currentIndex,
+            verify_frames:         verifyFrames.text.split(/[\s,;]+/).filter(x => x !== "" && !isNaN(x)).map(x => Math.max(0, Math.round(+x))),
+            frame_hashes:          root.frameHashes,
+            output_fps:            outputFps.currentIndex > 0? +outputFps.currentValue : 0,
+            fps_interpolation:     fpsInterpolation.currentIndex,
+            duplicate_frames:      duplicateFrames.currentIndex,
+            deinterlace:           deinterlace.currentIndex,
+            color_range:           colorRange.currentIndex,
+            max_memory_mb:         maxMemory.value,
+            decoder_threads:       decoderThreads.value,
+            decoder_queue_depth:   decoderQueueDepth.value,
+            decoder_hw_surfaces:   decoderSurfaces.value,
+            output_template:       outputTemplate.text,
+            collision_policy:      collisionPolicy.policies[collisionPolicy.currentIndex],
+            metadata: {
+                artist:    metadataArtist.text,
+                copyright: metadataCopyright.text,
+                model:     metadataCamera.text,
+                comment:   metadataComment.text,
+            },
         };
     }
 
@@ -113,6 +153,12 @@ MenuItem {
 
         codec.updateGpuStatus();
     }
+    function videoSizeChanged(w: real, h: real) {
+        setDefaultSize(w, h);
+        root.originalWidth = w;
+        root.originalHeight = h;
+        Qt.callLater(notifySizeChanged);
+    }
     function lensProfileLoaded(w: real, h: real) {
         setDefaultSize(w, h);
         Qt.callLater(notifySizeChanged);
@@ -144,7 +190,33 @@ MenuItem {
             if (output.hasOwnProperty("encoder_options"))       encoderOptions.text         = output.encoder_options;
             if (output.hasOwnProperty("keyframe_distance"))     keyframeDistance.value      = +output.keyframe_distance;
             if (output.hasOwnProperty("preserve_other_tracks")) preserveOtherTracks.checked = output.preserve_other_tracks;
+            if (output.hasOwnProperty("gpmf_passthrough"))      gpmfPassthrough.checked     = output.gpmf_passthrough;
             if (output.hasOwnProperty("pad_with_black"))        padWithBlack.checked        = output.pad_with_black;
+            if (output.hasOwnProperty("cropped_output"))        croppedOutput.checked       = output.cropped_output;
+            if (output.hasOwnProperty("split_duration_s"))      splitDuration.value         = +output.split_duration_s / 60;
+            if (output.hasOwnProperty("split_at_gaps"))         splitAtGaps.checked         = output.split_at_gaps;
+            if (output.hasOwnProperty("cuda_pipeline"))         cudaPipeline.checked        = output.cuda_pipeline;
+            if (output.hasOwnProperty("vaapi_pipeline"))        vaapiPipeline.checked       = output.vaapi_pipeline;
+            if (output.hasOwnProperty("frame_comparison"))      frameComparison.currentIndex = +output.frame_comparison;
+            if (output.hasOwnProperty("verify_frames"))         verifyFrames.text           = output.verify_frames.join(", ");
+            root.frameHashes = output.frame_hashes || { };
+            if (output.hasOwnProperty("output_fps"))            outputFps.currentIndex      = Math.max(0, outputFps.model.findIndex(x => Math.abs(+x - +output.output_fps) < 0.001));
+            if (output.hasOwnProperty("fps_interpolation"))     fpsInterpolation.currentIndex = +output.fps_interpolation;
+            if (output.hasOwnProperty("duplicate_frames"))      duplicateFrames.currentIndex = +output.duplicate_frames;
+            if (output.hasOwnProperty("deinterlace"))           deinterlace.currentIndex    = +output.deinterlace;
+            if (output.hasOwnProperty("color_range"))           colorRange.currentIndex     = +output.color_range;
+            if (output.hasOwnProperty("max_memory_mb"))         maxMemory.value             = +output.max_memory_mb;
+            if (output.hasOwnProperty("decoder_threads"))       decoderThreads.value        = +output.decoder_threads;
+            if (output.hasOwnProperty("decoder_queue_depth"))   decoderQueueDepth.value     = +output.decoder_queue_depth;
+            if (output.hasOwnProperty("decoder_hw_surfaces"))   decoderSurfaces.value       = +output.decoder_hw_surfaces;
+            if (output.hasOwnProperty("output_template"))       outputTemplate.text         = output.output_template;
+            if (output.hasOwnProperty("collision_policy"))      collisionPolicy.currentIndex = Math.max(0, collisionPolicy.policies.indexOf(output.collision_policy));
+            if (output.metadata) {
+                metadataArtist.text    = output.metadata.artist    || "";
+                metadataCopyright.text = output.metadata.copyright || "";
+                metadataCamera.text    = output.metadata.model     || "";
+                metadataComment.text   = output.metadata.comment   || "";
+            }
         }
 
         const stab = obj.stabilization || { };
@@ -469,6 +541,13 @@ MenuItem {
             tooltip: qsTr("This disables trim range and you need to use the .mov output file extension");
             onCheckedChanged: if (checked) codec.updateExtension(".mov");
         }
+        CheckBox {
+            id: gpmfPassthrough;
+            text: qsTr("Keep GoPro metadata");
+            checked: false;
+            visible: !preserveOtherTracks.checked;
+            tooltip: qsTr("Copy the GoPro metadata track (face detection, scene classification, wind) to the output, adjusted for the trim range. Requires .mp4 or .mov output");
+        }
         CheckBox {
             id: padWithBlack;
             text: qsTr("Use black frames outside trim range and keep original file duration");
@@ -476,6 +555,242 @@ MenuItem {
             width: parent.width;
             Component.onCompleted: contentItem.wrapMode = Text.WordWrap;
         }
+        CheckBox {
+            id: croppedOutput;
+            text: qsTr("Also export cropped version");
+            checked: false;
+            visible: frameComparison.currentIndex == 0;
+            tooltip: qsTr("Write a second file (_cropped) with the frames cropped to the area without borders, using the same stabilized frames. Useful together with transparent background and no zoom in the main output");
+        }
+        Label {
+            position: Label.LeftPosition;
+            text: qsTr("Split into parts of");
+
+            NumberField {
+                id: splitDuration;
+                width: parent.width;
+                height: 25 * dpiScale;
+                value: 0;
+                from: 0;
+                precision: 1;
+                unit: qsTr("min");
+                tooltip: qsTr("Write the output as numbered files (_001, _002...) of this length. 0 - disabled");
+            }
+        }
+        CheckBox {
+            id: splitAtGaps;
+            text: qsTr("Split at recording gaps");
+            checked: false;
+            tooltip: qsTr("Start a new numbered file where frames are missing in the recording, eg. between the chapters of a camera which drops frames when starting a new file");
+        }
+        CheckBox {
+            id: cudaPipeline;
+            text: qsTr("Keep frames on the NVIDIA GPU");
+            checked: false;
+            visible: gpu.checked && !isOsx;
+            tooltip: qsTr("Decode, stabilize and encode without copying the frames to the system memory. Requires an NVIDIA GPU with GPU decoding and encoding enabled, and is not used with comparison, cropped version, frame blending, deinterlacing or duplicate frame detection");
+        }
+        CheckBox {
+            id: vaapiPipeline;
+            text: qsTr("Keep frames on the GPU (VAAPI)");
+            checked: false;
+            visible: gpu.checked && Qt.platform.os == "linux";
+            tooltip: qsTr("Decode, stabilize and encode without copying the frames to the system memory. Requires a VAAPI encoder, GPU decoding enabled and a Vulkan processing device, and is not used with comparison, cropped version, frame blending, deinterlacing or duplicate frame detection");
+        }
+        Label {
+            position: Label.LeftPosition;
+            text: qsTr("Comparison");
+
+            ComboBox {
+                id: frameComparison;
+                model: [QT_TRANSLATE_NOOP("Popup", "Disabled"), QT_TRANSLATE_NOOP("Popup", "Side by side"), QT_TRANSLATE_NOOP("Popup", "Stacked")];
+                font.pixelSize: 12 * dpiScale;
+                width: parent.width;
+                tooltip: qsTr("Render the original and stabilized video next to each other");
+            }
+        }
+        Label {
+            position: Label.TopPosition;
+            text: qsTr("Verify frames");
+
+            TextField {
+                id: verifyFrames;
+                width: parent.width;
+                placeholderText: qsTr("eg. 0, 100, 500");
+                validator: RegularExpressionValidator { regularExpression: /[\d\s,;]*/ }
+                tooltip: qsTr("Checksums of these frames are stored with the project on the first render and compared on the next ones, to check that the output didn't change");
+            }
+            LinkButton {
+                height: parent.height;
+                iconName: "close";
+                leftPadding: 3 * dpiScale;
+                rightPadding: 3 * dpiScale;
+                y: -verifyFrames.height;
+                anchors.right: parent.right;
+                display: QQC.Button.IconOnly;
+                visible: Object.keys(root.frameHashes).length > 0;
+                tooltip: qsTr("Clear the reference checksums, the next render stores new ones");
+                onClicked: root.frameHashes = ({});
+            }
+        }
+        Label {
+            position: Label.LeftPosition;
+            text: qsTr("Output frame rate");
+
+            ComboBox {
+                id: outputFps;
+                model: [QT_TRANSLATE_NOOP("Popup", "Same as source"), "23.976", "24", "25", "29.97", "30", "50", "59.94", "60"];
+                font.pixelSize: 12 * dpiScale;
+                width: parent.width;
+                tooltip: qsTr("Convert the output to a different constant frame rate");
+            }
+        }
+        Label {
+            position: Label.LeftPosition;
+            text: qsTr("Frame rate conversion");
+            visible: outputFps.currentIndex > 0;
+
+            ComboBox {
+                id: fpsInterpolation;
+                model: [QT_TRANSLATE_NOOP("Popup", "Nearest frame"), QT_TRANSLATE_NOOP("Popup", "Blend frames")];
+                font.pixelSize: 12 * dpiScale;
+                width: parent.width;
+                tooltip: qsTr("Blending neighboring frames gives smoother motion when reducing the frame rate, but requires stabilizing all source frames");
+            }
+        }
+        Label {
+            position: Label.LeftPosition;
+            text: qsTr("Duplicated frames");
+
+            ComboBox {
+                id: duplicateFrames;
+                model: [QT_TRANSLATE_NOOP("Popup", "Keep"), QT_TRANSLATE_NOOP("Popup", "Drop"), QT_TRANSLATE_NOOP("Popup", "Interpolate")];
+                font.pixelSize: 12 * dpiScale;
+                width: parent.width;
+                tooltip: qsTr("Some cameras repeat frames when overheating, which makes the stabilized video stutter.\nDuplicates can be dropped, or rendered with the camera motion since the original frame.");
+            }
+        }
+        Label {
+            position: Label.LeftPosition;
+            text: qsTr("Deinterlace");
+
+            ComboBox {
+                id: deinterlace;
+                model: [QT_TRANSLATE_NOOP("Popup", "Auto"), QT_TRANSLATE_NOOP("Popup", "Disabled"), QT_TRANSLATE_NOOP("Popup", "Always")];
+                font.pixelSize: 12 * dpiScale;
+                width: parent.width;
+                tooltip: qsTr("Interlaced video is stabilized per field and rendered at double the frame rate");
+            }
+        }
+        Label {
+            position: Label.LeftPosition;
+            text: qsTr("Color range");
+
+            ComboBox {
+                id: colorRange;
+                model: [QT_TRANSLATE_NOOP("Popup", "Same as source"), QT_TRANSLATE_NOOP("Popup", "Limited (16-235)"), QT_TRANSLATE_NOOP("Popup", "Full (0-255)")];
+                font.pixelSize: 12 * dpiScale;
+                width: parent.width;
+                tooltip: qsTr("Range of the YUV values in the output file. The source range is detected from the video and converted when they differ.");
+            }
+        }
+        Label {
+            position: Label.LeftPosition;
+            text: qsTr("Memory limit");
+
+            NumberField {
+                id: maxMemory;
+                width: parent.width;
+                height: 25 * dpiScale;
+                value: 0;
+                from: 0;
+                precision: 0;
+                intNoThousandSep: true;
+                unit: qsTr("MB");
+                tooltip: qsTr("Maximum memory used by the frames waiting for the encoder, shared by all renders. Rendering slows down instead of using more memory. 0 means no limit.");
+            }
+        }
+        Label {
+            position: Label.LeftPosition;
+            text: qsTr("Decoder threads");
+
+            NumberField {
+                id: decoderThreads;
+                width: parent.width;
+                height: 25 * dpiScale;
+                value: 0;
+                from: 0;
+                to: 64;
+                precision: 0;
+                tooltip: qsTr("Number of frames decoded in parallel. 0 means automatic, based on the codec and resolution.");
+            }
+        }
+        Label {
+            position: Label.LeftPosition;
+            text: qsTr("Decoder queue depth");
+
+            NumberField {
+                id: decoderQueueDepth;
+                width: parent.width;
+                height: 25 * dpiScale;
+                value: 0;
+                from: 0;
+                to: 64;
+                precision: 0;
+                tooltip: qsTr("Number of frames queued in asynchronous hardware decoders. 0 means automatic.");
+            }
+        }
+        Label {
+            position: Label.LeftPosition;
+            text: qsTr("GPU decoder surfaces");
+
+            NumberField {
+                id: decoderSurfaces;
+                width: parent.width;
+                height: 25 * dpiScale;
+                value: 0;
+                from: 0;
+                to: 64;
+                precision: 0;
+                tooltip: qsTr("Additional frame buffers for GPU decoding. Increase if GPU decoding of long-GOP videos fails or stalls. 0 means automatic.");
+            }
+        }
+        Label {
+            position: Label.TopPosition;
+            text: qsTr("Output file name template (render queue)");
+
+            TextField {
+                id: outputTemplate;
+                width: parent.width;
+                placeholderText: "{basename}{suffix}";
+                tooltip: qsTr("Available variables: %1").arg("{basename}, {suffix}, {date}, {time}, {method}, {smoothness}, {codec}, {w}, {h}, {fps}, {counter}, {counter:3}");
+            }
+        }
+        Label {
+            position: Label.LeftPosition;
+            text: qsTr("If the file exists");
+
+            ComboBox {
+                id: collisionPolicy;
+                property var policies: ["ask", "overwrite", "rename", "skip"];
+                model: [QT_TRANSLATE_NOOP("Popup", "Ask"), QT_TRANSLATE_NOOP("Popup", "Overwrite"), QT_TRANSLATE_NOOP("Popup", "Rename"), QT_TRANSLATE_NOOP("Popup", "Skip")];
+                font.pixelSize: 12 * dpiScale;
+                width: parent.width;
+            }
+        }
+        Label {
+            position: Label.TopPosition;
+            text: qsTr("Metadata");
+
+            Column {
+                width: parent.width;
+                spacing: 5 * dpiScale;
+                TextField { id: metadataArtist;    width: parent.width; placeholderText: qsTr("Creator"); }
+                TextField { id: metadataCopyright; width: parent.width; placeholderText: qsTr("Copyright"); }
+                TextField { id: metadataCamera;    width: parent.width; placeholderText: qsTr("Camera"); }
+                TextField { id: metadataComment;   width: parent.width; placeholderText: qsTr("Notes"); }
+            }
+        }
         Label {
             position: Label.TopPosition;
             text: qsTr("Device for rendering");
@@ -509,6 +824,8 @@ MenuItem {
                         }
                         if (saved != defaultInitializedDevice) {
                             Qt.callLater(renderingDevice.updateController);
+                        } else if (list.length > 0) {
+                            controller.set_export_device(list[renderingDevice.currentIndex]);
                         }
                         renderingDevice.preventChange = false;
                     }
@@ -519,6 +836,7 @@ MenuItem {
                 }
                 function updateController() {
                     controller.set_rendering_gpu_type_from_name(renderingDevice.currentText);
+                    controller.set_export_device(renderingDevice.orgList[renderingDevice.currentIndex]);
                     settings.setValue("renderingDevice", renderingDevice.orgList[renderingDevice.currentIndex]);
                 }
             }
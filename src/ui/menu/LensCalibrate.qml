@@ -111,6 +111,23 @@ MenuItem {
             shutterCb.checked = Math.abs(rolling_shutter) > 0;
             bottomToTop.checked = rolling_shutter < 0;
         }
+        function onLens_profile_upload_status(status: string, message: string) {
+            switch (status) {
+                case "invalid":   messageBox(Modal.Error,   qsTr("Lens profile was saved, but it can't be submitted to the database:") + "\n" + message, [ { text: qsTr("Ok") } ]); break;
+                case "duplicate": messageBox(Modal.Warning, qsTr("This lens profile already exists in the database: %1").arg("<b>" + message + "</b>"), [ { text: qsTr("Ok") } ]); break;
+                case "uploaded":  messageBox(Modal.Success, qsTr("Lens profile submitted successfully. Thank you!"), [ { text: qsTr("Ok") } ]); break;
+                case "failed":    messageBox(Modal.Error,   qsTr("Failed to submit the lens profile: %1").arg(message), [ { text: qsTr("Ok") } ]); break;
+            }
+        }
+        function onRig_calibrated(result: string) {
+            const rig = JSON.parse(result);
+            const angles = rig.euler_deg.map(x => x.toLocaleString(Qt.locale(), "f", 3));
+            messageBox(Modal.Success, qsTr("Rotation to the second camera: pitch %1°, roll %2°, yaw %3°.\nMean error: %4° from %5 frame pairs.")
+                .arg(angles[0]).arg(angles[1]).arg(angles[2]).arg(rig.error_deg.toLocaleString(Qt.locale(), "f", 3)).arg(rig.pairs), [
+                { text: qsTr("Save"), accent: true, clicked: () => rigFileDialog.open2() },
+                { text: qsTr("Close") }
+            ]);
+        }
     }
 
     Settings {
@@ -171,6 +188,22 @@ MenuItem {
             controller.start_autocalibrate(maxPoints.value, everyNthFrame.value, iterations.value, maxSharpness.value, -1, noMarker.checked);
         }
     }
+    LinkButton {
+        text: qsTr("Calibrate from images...");
+        anchors.horizontalCenter: parent.horizontalCenter;
+        onClicked: imagesDialog.open();
+        ToolTip { visible: parent.hovered; text: qsTr("Use a set of photos of the calibration pattern instead of a video.\nAll images must have the same resolution.") }
+    }
+    FileDialog {
+        id: imagesDialog;
+        fileMode: FileDialog.OpenFiles;
+        title: qsTr("Select calibration images");
+        nameFilters: [qsTr("Images") + " (*.jpg *.jpeg *.png *.tif *.tiff *.bmp *.dng)"];
+        type: "calibration-images";
+        onAccepted: {
+            controller.start_autocalibrate_images(selectedFiles.map(x => x.toString()), maxPoints.value, iterations.value, maxSharpness.value, noMarker.checked);
+        }
+    }
 
     Label {
         position: Label.LeftPosition;
@@ -464,5 +497,48 @@ MenuItem {
             width: parent.width;
             Component.onCompleted: contentItem.wrapMode = Text.WordWrap;
         }
+        Label {
+            position: Label.LeftPosition;
+            text: qsTr("Rig time offset");
+
+            NumberField {
+                id: rigOffset;
+                width: parent.width;
+                height: 25 * dpiScale;
+                precision: 1;
+                value: 0;
+                unit: qsTr("ms");
+                tooltip: qsTr("Time of the board in the second video, relative to this one");
+            }
+        }
+        LinkButton {
+            text: qsTr("Calibrate camera rig...");
+            anchors.horizontalCenter: parent.horizontalCenter;
+            enabled: rms.value > 0 && calibrator_window.videoArea.vid.loaded;
+            onClicked: rigVideoDialog.open();
+            ToolTip { visible: parent.hovered; text: qsTr("Find the rotation between this camera and a second camera mounted on the same rig, from a synchronized video of the same calibration pattern.") }
+        }
+        FileDialog {
+            id: rigVideoDialog;
+            title: qsTr("Select the video from the second camera");
+            type: "video";
+            onAccepted: rigLensDialog.open();
+        }
+        FileDialog {
+            id: rigLensDialog;
+            title: qsTr("Select the lens profile of the second camera");
+            nameFilters: [qsTr("Lens profiles") + " (*.json)"];
+            type: "lens";
+            onAccepted: controller.start_rig_calibration(rigVideoDialog.selectedFile, selectedFile, rigOffset.value);
+        }
+        FileDialog {
+            id: rigFileDialog;
+            fileMode: FileDialog.SaveFile;
+            defaultSuffix: "json";
+            title: qsTr("Export rig calibration");
+            nameFilters: [qsTr("Rig calibration") + " (*.json)"];
+            type: "output-preset";
+            onAccepted: controller.export_rig_calibration(selectedFile);
+        }
     }
 }
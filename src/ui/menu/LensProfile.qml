@@ -22,6 +22,7 @@ MenuItem {
     property var distortionCoeffs: [];
     property string profileName;
     property string profileOriginalJson;
+    property alias searchField: search;
 
     FileDialog {
         id: fileDialog;
@@ -42,6 +43,7 @@ MenuItem {
     Component.onCompleted: {
         controller.fetch_profiles_from_github();
         controller.load_profiles(true);
+        controller.watch_lens_profiles();
 
         QT_TRANSLATE_NOOP("TableList", "Camera");
         QT_TRANSLATE_NOOP("TableList", "Lens");
@@ -73,6 +75,29 @@ MenuItem {
             profilesUpdateTimer.fromDisk = fromDisk;
             profilesUpdateTimer.start();
         }
+        function onLens_profile_updates_available(updates_json: string) {
+            // Each item is [relative path, [{ field, local, upstream }]]
+            root.profileUpdates = JSON.parse(updates_json) || [];
+        }
+        function onLens_profile_ambiguous(candidates_json: string, lens_info: string, gimbal_mode: string) {
+            // Each item is [key, display name]
+            const candidates = JSON.parse(candidates_json) || [];
+            let details = [];
+            if (lens_info)   details.push(qsTr("lens: %1").arg(lens_info));
+            if (gimbal_mode) details.push(qsTr("gimbal mode: %1").arg(gimbal_mode));
+            let buttons = candidates.slice(0, 4).map(x => ({ text: x[1], clicked: () => controller.load_lens_profile(x[0]) }));
+            buttons.push({ text: qsTr("Choose a different profile"), clicked: () => search.forceActiveFocus() });
+            messageBox(Modal.Question, qsTr("Several lens profiles match the camera settings of this clip (%1).\nSelect the one which was used for recording.").arg(details.join(", ")), buttons);
+        }
+        function onLens_profile_closest(name: string, lens_info: string, gimbal_mode: string) {
+            let details = [];
+            if (lens_info)   details.push(qsTr("lens: %1").arg(lens_info));
+            if (gimbal_mode) details.push(qsTr("gimbal mode: %1").arg(gimbal_mode));
+            messageBox(Modal.Info, qsTr("No lens profile matches the camera settings of this clip exactly (%1).\nThe closest one was loaded: %2. Make sure it's the one which was used for recording.").arg(details.join(", ")).arg(name), [
+                { text: qsTr("Ok"), accent: true },
+                { text: qsTr("Choose a different profile"), clicked: () => search.forceActiveFocus() },
+            ]);
+        }
         function onLens_profile_loaded(json_str: string, filepath: string) {
             if (json_str) {
                 const obj = JSON.parse(json_str);
@@ -130,6 +155,33 @@ MenuItem {
 
     property int currentVideoAspectRatio: Math.round((root.videoWidth / Math.max(1, root.videoHeight)) * 1000);
 
+    property var profileUpdates: [];
+    function reviewProfileUpdate() {
+        if (!profileUpdates.length) return;
+        const path = profileUpdates[0][0];
+        const diffs = profileUpdates[0][1];
+        const valueText = (v) => { const str = v === null? "-" : JSON.stringify(v); return str.length > 40? str.substring(0, 40) + "..." : str; };
+        let checkboxes = [];
+        const next = function() {
+            root.profileUpdates = root.profileUpdates.slice(1);
+            Qt.callLater(root.reviewProfileUpdate);
+        };
+        const dlg = messageBox(Modal.Question, qsTr("Official lens profile %1 has been updated, but your local copy is modified.\nSelect the fields to update, the other ones will keep your values:").arg("<b>" + path + "</b>"), [
+            { text: qsTr("Update selected"), accent: true, clicked: function() {
+                controller.apply_lens_profile_update(path, diffs.filter((_, i) => checkboxes[i].checked).map(x => x.field));
+                next();
+            } },
+            { text: qsTr("Keep my version"), clicked: function() {
+                controller.apply_lens_profile_update(path, []);
+                next();
+            } },
+            { text: qsTr("Later") },
+        ]);
+        for (const d of diffs) {
+            checkboxes.push(Qt.createComponent("../components/CheckBox.qml").createObject(dlg.mainColumn, { text: d.field + ": " + valueText(d.local) + " → " + valueText(d.upstream), checked: true }));
+        }
+    }
+
     property var favorites: ({});
     function loadFavorites() {
         const list = window.settings.value("lensProfileFavorites") || "";
@@ -223,6 +275,23 @@ MenuItem {
         }
     }
 
+    InfoMessageSmall {
+        id: updatesInfo;
+        type: InfoMessage.Info;
+        show: root.profileUpdates.length > 0;
+        text: qsTr("Updated versions of %n modified lens profile(s) are available. [Review changes]", "", root.profileUpdates.length).replace(/\[(.*?)\]/, "<a href=\"#review\">$1</a>");
+
+        MouseArea {
+            anchors.fill: parent;
+            cursorShape: parent.t.hoveredLink? Qt.PointingHandCursor : Qt.ArrowCursor;
+            acceptedButtons: Qt.NoButton;
+        }
+        Connections {
+            target: updatesInfo.t;
+            function onLinkActivated(link: url) { root.reviewProfileUpdate(); }
+        }
+    }
+
     InfoMessageSmall {
         type: lensRatio != videoRatio? InfoMessage.Error : InfoMessage.Warning;
         show: root.calibWidth > 0 && root.videoWidth > 0 && (root.calibWidth != root.videoWidth || root.calibHeight != root.videoHeight);
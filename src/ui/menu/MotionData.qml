@@ -17,6 +17,7 @@ MenuItem {
     property alias integrationMethod: integrator.currentIndex;
     property alias orientationIndicator: orientationIndicator;
     property string filename: "";
+    property alias fileDialog: fileDialog;
 
     FileDialog {
         id: fileDialog;
@@ -54,7 +55,15 @@ MenuItem {
                 arot.checked = Math.abs(ap.value) > 0 || Math.abs(ar.value) > 0 || Math.abs(ay.value) > 0;
                 arot_action.checked = arot.checked;
             }
+            if (gyro.extrinsic_rotation && gyro.extrinsic_rotation.length == 3) {
+                ep.value = gyro.extrinsic_rotation[0];
+                er.value = gyro.extrinsic_rotation[1];
+                ey.value = gyro.extrinsic_rotation[2];
+                erot.checked = Math.abs(ep.value) > 0 || Math.abs(er.value) > 0 || Math.abs(ey.value) > 0;
+            }
             if (gyro.imu_orientation) orientation.text = gyro.imu_orientation;
+            if (+gyro.quat_fusion_crossover > 0) fusionCrossover.value = +gyro.quat_fusion_crossover;
+            logStart.update();
             if (gyro.hasOwnProperty("integration_method")) {
                 const index = +gyro.integration_method;
                 integrator.currentIndex = integrator.hasQuaternions? index : index - 1;
@@ -63,6 +72,21 @@ MenuItem {
                 lpf.value = +gyro.lpf;
                 lpfcb.checked = lpf.value > 0;
             }
+            if (gyro.lpf_type) lpfType.currentIndex = Math.max(0, ["butterworth", "bessel", "moving_average"].indexOf(gyro.lpf_type));
+            if (+gyro.lpf_order > 0) lpfOrder.value = +gyro.lpf_order;
+            if ((gyro.acc_lpf !== null && gyro.acc_lpf !== undefined) || gyro.acc_bias || gyro.acc_scale) {
+                alpf.value = +gyro.acc_lpf || 0;
+                if (gyro.acc_bias  && gyro.acc_bias.length  == 3) { abx.value = gyro.acc_bias[0];  aby.value = gyro.acc_bias[1];  abz.value = gyro.acc_bias[2]; }
+                if (gyro.acc_scale && gyro.acc_scale.length == 3) { asx.value = gyro.acc_scale[0]; asy.value = gyro.acc_scale[1]; asz.value = gyro.acc_scale[2]; }
+                aproc.checked = true;
+                aproc_action.checked = true;
+            }
+            if (gyro.lpf_axes && gyro.lpf_axes.length == 3) {
+                lpfX.value = gyro.lpf_axes[0];
+                lpfY.value = gyro.lpf_axes[1];
+                lpfZ.value = gyro.lpf_axes[2];
+                lpfPerAxis.checked = true;
+            }
         }
     }
     function setGyroLpf(v: real) {
@@ -77,6 +101,7 @@ MenuItem {
             info.updateEntry("File name", filename || "---");
             info.updateEntry("Detected format", camera || "---");
             orientation.text = imu_orientation;
+            logStart.update();
 
             // Twice to trigger change signal
             integrator.hasQuaternions = !contains_quats;
@@ -89,15 +114,22 @@ MenuItem {
             controller.set_imu_lpf(lpfcb.checked? lpf.value : 0);
             controller.set_imu_rotation(rot.checked? p.value : 0, rot.checked? r.value : 0, rot.checked? y.value : 0);
             controller.set_acc_rotation(arot.checked? ap.value : 0, arot.checked? ar.value : 0, arot.checked? ay.value : 0);
+            controller.set_extrinsic_rotation(erot.checked? ep.value : 0, erot.checked? er.value : 0, erot.checked? ey.value : 0);
+            controller.set_acc_lpf(aproc.checked? alpf.value : -1);
             Qt.callLater(controller.recompute_gyro);
 
             window.videoArea.timeline.updateDurations();
         }
-        function onBias_estimated(biasX: real, biasY: real, biasZ: real) {
+        function onBias_estimated(biasX: real, biasY: real, biasZ: real, quality: real, still_segments: int, total_segments: int) {
             gyrobias.checked = true;
             bx.value = biasX;
             by.value = biasY;
             bz.value = biasZ;
+            if (still_segments == 0) {
+                messageBox(Modal.Warning, qsTr("The camera doesn't seem to be still in the selected part of the video, the estimated bias is probably wrong."), [ { text: qsTr("Ok") } ]);
+            } else if (quality < 0.5) {
+                messageBox(Modal.Info, qsTr("Gyro bias estimated from %1 of %2 segments, quality: %3%.").arg(still_segments).arg(total_segments).arg(Math.round(quality * 100)), [ { text: qsTr("Ok") } ]);
+            }
         }
         function onOrientation_guessed(value: string) {
              orientation.text = value;
@@ -144,6 +176,82 @@ MenuItem {
                 Qt.callLater(controller.recompute_gyro);
             }
         }
+        Label {
+            position: Label.LeftPosition;
+            text: qsTr("Filter type");
+
+            ComboBox {
+                id: lpfType;
+                model: [QT_TRANSLATE_NOOP("Popup", "Butterworth"), QT_TRANSLATE_NOOP("Popup", "Bessel"), QT_TRANSLATE_NOOP("Popup", "Moving average")];
+                font.pixelSize: 12 * dpiScale;
+                width: parent.width;
+                currentIndex: 0;
+                property var tooltips: ([
+                    qsTr("Flat response in the pass band, sharper roll-off with higher order."),
+                    qsTr("Smooth roll-off with the least overshoot and phase distortion."),
+                    qsTr("Simple averaging of the neighboring samples, the order is the number of passes.")
+                ]);
+                tooltip: tooltips[currentIndex];
+                onCurrentIndexChanged: lpfOrder.update();
+            }
+        }
+        Label {
+            position: Label.LeftPosition;
+            text: qsTr("Filter order");
+
+            NumberField {
+                id: lpfOrder;
+                precision: 0;
+                value: 2;
+                defaultValue: 2;
+                from: lpfType.currentIndex == 1? 2 : 1;
+                to: lpfType.currentIndex == 1? 4 : 8;
+                width: parent.width;
+                function update() {
+                    controller.set_imu_lpf_type(lpfType.currentIndex, value);
+                    Qt.callLater(controller.recompute_gyro);
+                }
+                onValueChanged: update();
+            }
+        }
+        CheckBoxWithContent {
+            id: lpfPerAxis;
+            text: qsTr("Per axis cutoff");
+            function update() {
+                controller.set_imu_lpf_axes(checked? lpfX.value : 0, checked? lpfY.value : 0, checked? lpfZ.value : 0);
+                Qt.callLater(controller.recompute_gyro);
+            }
+            onCheckedChanged: update();
+
+            Flow {
+                width: parent.width;
+                spacing: 5 * dpiScale;
+                Label {
+                    position: Label.LeftPosition;
+                    text: "X";
+                    width: undefined;
+                    inner.width: 60 * dpiScale;
+                    spacing: 5 * dpiScale;
+                    NumberField { id: lpfX; unit: qsTr("Hz"); precision: 1; value: 50; from: 0; width: 60 * dpiScale; onValueChanged: lpfPerAxis.update(); }
+                }
+                Label {
+                    position: Label.LeftPosition;
+                    text: "Y";
+                    width: undefined;
+                    inner.width: 60 * dpiScale;
+                    spacing: 5 * dpiScale;
+                    NumberField { id: lpfY; unit: qsTr("Hz"); precision: 1; value: 50; from: 0; width: 60 * dpiScale; onValueChanged: lpfPerAxis.update(); }
+                }
+                Label {
+                    position: Label.LeftPosition;
+                    text: "Z";
+                    width: undefined;
+                    inner.width: 60 * dpiScale;
+                    spacing: 5 * dpiScale;
+                    NumberField { id: lpfZ; unit: qsTr("Hz"); precision: 1; value: 50; from: 0; width: 60 * dpiScale; onValueChanged: lpfPerAxis.update(); }
+                }
+            }
+        }
     }
     Item {
         width: parent.width;
@@ -222,6 +330,12 @@ MenuItem {
                 text: qsTr("Separate accelerometer rotation");
                 checkable: true;
             }
+            Action {
+                id: aproc_action;
+                iconName: "chart";
+                text: qsTr("Accelerometer processing");
+                checkable: true;
+            }
         }
     }
     CheckBoxWithContent {
@@ -263,6 +377,77 @@ MenuItem {
             }
         }
     }
+    CheckBoxWithContent {
+        id: erot;
+        text: qsTr("Extrinsic rotation");
+        cb.tooltip: qsTr("Rotation between the device which recorded the motion data and the camera, when they are mounted on the same rig.\nUnlike the rotation above, it's also applied to the quaternions recorded by the device.");
+        onCheckedChanged: update_rotation();
+        function update_rotation() {
+            controller.set_extrinsic_rotation(erot.checked? ep.value : 0, erot.checked? er.value : 0, erot.checked? ey.value : 0);
+            Qt.callLater(controller.recompute_gyro);
+        }
+
+        Flow {
+            width: parent.width;
+            spacing: 5 * dpiScale;
+            Label { position: Label.LeftPosition; text: qsTr("Pitch"); width: undefined; inner.width: 50 * dpiScale; spacing: 5 * dpiScale; NumberField { id: ep; unit: "°"; precision: 3; from: -360; to: 360; width: 50 * dpiScale; onValueChanged: erot.update_rotation(); } }
+            Label { position: Label.LeftPosition; text: qsTr("Roll");  width: undefined; inner.width: 50 * dpiScale; spacing: 5 * dpiScale; NumberField { id: er; unit: "°"; precision: 3; from: -360; to: 360; width: 50 * dpiScale; onValueChanged: erot.update_rotation(); } }
+            Label { position: Label.LeftPosition; text: qsTr("Yaw");   width: undefined; inner.width: 50 * dpiScale; spacing: 5 * dpiScale; NumberField { id: ey; unit: "°"; precision: 3; from: -360; to: 360; width: 50 * dpiScale; onValueChanged: erot.update_rotation(); } }
+        }
+        LinkButton {
+            text: qsTr("Load rig calibration...");
+            anchors.horizontalCenter: parent.horizontalCenter;
+            onClicked: rigFileDialog.open();
+        }
+        FileDialog {
+            id: rigFileDialog;
+            title: qsTr("Select the rig calibration");
+            nameFilters: [qsTr("Rig calibration or lens profile") + " (*.json)"];
+            type: "output-preset";
+            onAccepted: {
+                const angles = controller.load_rig_calibration(selectedFile);
+                if (angles.length == 3) {
+                    ep.value = angles[0];
+                    er.value = angles[1];
+                    ey.value = angles[2];
+                    erot.checked = true;
+                    erot.update_rotation();
+                }
+            }
+        }
+    }
+    CheckBoxWithContent {
+        id: aproc;
+        visible: aproc_action.checked;
+        text: qsTr("Accelerometer processing");
+        onCheckedChanged: update_processing();
+        function update_processing() {
+            controller.set_acc_lpf(aproc.checked? alpf.value : -1);
+            controller.set_acc_bias(aproc.checked? abx.value : 0, aproc.checked? aby.value : 0, aproc.checked? abz.value : 0);
+            controller.set_acc_scale(aproc.checked? asx.value : 1, aproc.checked? asy.value : 1, aproc.checked? asz.value : 1);
+            Qt.callLater(controller.recompute_gyro);
+        }
+
+        Label {
+            position: Label.LeftPosition;
+            text: qsTr("Low pass filter");
+            NumberField { id: alpf; unit: qsTr("Hz"); precision: 2; value: 0; from: 0; width: parent.width; onValueChanged: aproc.update_processing(); tooltip: qsTr("Accelerometer is usually noisier than the gyro, lower cutoff frequency gives more stable gravity vectors.\n0 means no filtering."); }
+        }
+        Flow {
+            width: parent.width;
+            spacing: 5 * dpiScale;
+            Label { position: Label.LeftPosition; text: qsTr("Bias") + " X"; width: undefined; inner.width: 50 * dpiScale; spacing: 5 * dpiScale; NumberField { id: abx; precision: 3; width: 50 * dpiScale; onValueChanged: aproc.update_processing(); } }
+            Label { position: Label.LeftPosition; text: "Y"; width: undefined; inner.width: 50 * dpiScale; spacing: 5 * dpiScale; NumberField { id: aby; precision: 3; width: 50 * dpiScale; onValueChanged: aproc.update_processing(); } }
+            Label { position: Label.LeftPosition; text: "Z"; width: undefined; inner.width: 50 * dpiScale; spacing: 5 * dpiScale; NumberField { id: abz; precision: 3; width: 50 * dpiScale; onValueChanged: aproc.update_processing(); } }
+        }
+        Flow {
+            width: parent.width;
+            spacing: 5 * dpiScale;
+            Label { position: Label.LeftPosition; text: qsTr("Scale") + " X"; width: undefined; inner.width: 50 * dpiScale; spacing: 5 * dpiScale; NumberField { id: asx; precision: 3; value: 1; defaultValue: 1; width: 50 * dpiScale; onValueChanged: aproc.update_processing(); } }
+            Label { position: Label.LeftPosition; text: "Y"; width: undefined; inner.width: 50 * dpiScale; spacing: 5 * dpiScale; NumberField { id: asy; precision: 3; value: 1; defaultValue: 1; width: 50 * dpiScale; onValueChanged: aproc.update_processing(); } }
+            Label { position: Label.LeftPosition; text: "Z"; width: undefined; inner.width: 50 * dpiScale; spacing: 5 * dpiScale; NumberField { id: asz; precision: 3; value: 1; defaultValue: 1; width: 50 * dpiScale; onValueChanged: aproc.update_processing(); } }
+        }
+    }
     CheckBoxWithContent {
         id: gyrobias;
         text: qsTr("Gyro bias");
@@ -321,10 +506,11 @@ MenuItem {
         ComboBox {
             id: integrator;
             property bool hasQuaternions: false;
-            model: hasQuaternions? [QT_TRANSLATE_NOOP("Popup", "None"), "Complementary", "VQF", "Simple gyro", "Simple gyro + accel", "Mahony", "Madgwick" ] :  ["Complementary", "VQF", "Simple gyro", "Simple gyro + accel", "Mahony", "Madgwick"];
+            model: hasQuaternions? [QT_TRANSLATE_NOOP("Popup", "None"), "Complementary", "VQF", "Simple gyro", "Simple gyro + accel", "Mahony", "Madgwick", QT_TRANSLATE_NOOP("Popup", "Quaternions + gyro") ] :  ["Complementary", "VQF", "Simple gyro", "Simple gyro + accel", "Mahony", "Madgwick"];
             font.pixelSize: 12 * dpiScale;
             width: parent.width;
-            tooltip: hasQuaternions && currentIndex === 0? qsTr("Use built-in quaternions instead of IMU data") : qsTr("IMU integration method for calculating motion data");
+            tooltip: hasQuaternions && currentIndex === 0? qsTr("Use built-in quaternions instead of IMU data") :
+                     hasQuaternions && currentIndex === 7? qsTr("Use built-in quaternions for the slow motion and IMU data for the fast motion") : qsTr("IMU integration method for calculating motion data");
             function setMethod() {
                 controller.set_integration_method(hasQuaternions? currentIndex : currentIndex + 1);
             }
@@ -337,6 +523,51 @@ MenuItem {
             }
         }
     }
+    Label {
+        position: Label.LeftPosition;
+        text: qsTr("Crossover frequency");
+        visible: integrator.hasQuaternions && integrator.currentIndex === 7;
+
+        NumberField {
+            id: fusionCrossover;
+            unit: qsTr("Hz");
+            precision: 2;
+            value: 1.0;
+            from: 0.01;
+            width: parent.width;
+            tooltip: qsTr("Motion below this frequency comes from the built-in quaternions, above it from the gyro.");
+            onValueChanged: {
+                controller.set_quat_fusion_crossover(value);
+                Qt.callLater(controller.recompute_gyro);
+            }
+        }
+    }
+    Label {
+        id: logStart;
+        position: Label.LeftPosition;
+        text: qsTr("Video start in the log");
+        visible: false;
+        property bool updating: false;
+        function update() {
+            const slice = controller.get_log_slice();
+            visible = slice.hasOwnProperty("start_ms");
+            if (visible) {
+                updating = true;
+                logStartField.value = slice.start_ms / 1000.0;
+                updating = false;
+            }
+        }
+
+        NumberField {
+            id: logStartField;
+            unit: qsTr("s");
+            precision: 3;
+            live: false;
+            width: parent.width;
+            tooltip: qsTr("The log covers multiple videos. Set where this video starts if it wasn't detected correctly, the other videos of this log will be adjusted by the same difference.");
+            onValueChanged: if (!logStart.updating) controller.set_log_anchor(value);
+        }
+    }
 
     CheckBoxWithContent {
         id: orientationCheckbox;
@@ -23,6 +23,7 @@ MenuItem {
         property alias adaptiveZoom: adaptiveZoom.value;
         property alias correctionAmount: correctionAmount.value;
         property alias useGravityVectors: useGravityVectors.checked;
+        property alias autoSuggestSmoothing: autoSuggestSmoothing.checked;
     }
 
     function loadGyroflow(obj) {
@@ -50,6 +51,9 @@ MenuItem {
             if (typeof stab.lens_correction_amount !== "undefined") {
                 correctionAmount.value = +stab.lens_correction_amount;
             }
+            if (stab.hasOwnProperty("stabilization_amount")) stabilizationAmount.value = +stab.stabilization_amount;
+            if (stab.hasOwnProperty("output_projection")) outputProjection.currentIndex = +stab.output_projection;
+            if (stab.hasOwnProperty("output_projection_strength")) outputProjectionStrength.value = +stab.output_projection_strength;
 
             const az = +stab.adaptive_zoom_window;
             if (az < -0.9) {
@@ -60,6 +64,11 @@ MenuItem {
             } else {
                 croppingMode.currentIndex = 0; // No cropping
             }
+            if (stab.hasOwnProperty("max_zoom")) {
+                limitZoom.checked = +stab.max_zoom > 0;
+                if (+stab.max_zoom > 0) maxZoom.value = +stab.max_zoom;
+            }
+            if (stab.hasOwnProperty("zoom_border_tolerance")) borderTolerance.value = +stab.zoom_border_tolerance;
             if (stab.hasOwnProperty("adaptive_zoom_center_offset")) {
                 zoomingCenterX.value = stab.adaptive_zoom_center_offset[0];
                 zoomingCenterY.value = stab.adaptive_zoom_center_offset[1];
@@ -68,9 +77,10 @@ MenuItem {
                 useGravityVectors.checked = !!stab.use_gravity_vectors;
             }
 
-            horizonCb.checked = (+stab.horizon_lock_amount || 0) > 0;
+            horizonCb.checked = (+stab.horizon_lock_amount || 0) > 0 || (+stab.horizon_lock_pitch_amount || 0) > 0;
             horizonSlider.value = horizonCb.checked? +stab.horizon_lock_amount : 100;
             horizonRollSlider.value = horizonCb.checked? +stab.horizon_lock_roll : 0;
+            horizonPitchSlider.value = horizonCb.checked? (+stab.horizon_lock_pitch_amount || 0) : 0;
             Qt.callLater(updateHorizonLock);
         }
     }
@@ -181,6 +191,55 @@ MenuItem {
             } else {
                 controller.frame_readout_time = shutter.value;
             }
+            // Only for a newly loaded video, a project keeps its saved smoothing parameters
+            if (autoSuggestSmoothing.checked && (contains_gyro || contains_quats) && !window.videoArea.isProjectLoading) {
+                controller.suggest_smoothing();
+            }
+        }
+        function onSmoothing_suggested(smoothness: real, adaptive_zoom_window: real) {
+            const defaultIndex = smoothingAlgorithms.indexOf("Default");
+            if (defaultIndex > -1) smoothingMethod.currentIndex = defaultIndex;
+            Qt.callLater(function() {
+                const el = root.getParamElement("smoothness");
+                if (el) el.value = smoothness;
+            });
+            if (adaptive_zoom_window < -0.9) {
+                croppingMode.currentIndex = 2; // Static crop
+            } else {
+                croppingMode.currentIndex = 1; // Dynamic cropping
+                adaptiveZoom.value = adaptive_zoom_window;
+            }
+        }
+        function onHorizon_lock_stability(report) {
+            if (!report || report.fallback_applied) return;
+            if (report.is_stable) {
+                // Remove the keyframes of a previous fallback, which no longer applies
+                if (report.has_fallback) controller.check_horizon_lock(true);
+                return;
+            }
+            if (horizonAutoFallback.checked) {
+                controller.check_horizon_lock(true);
+                return;
+            }
+            const ranges = report.drifting.map(x => (x.start_ms / 1000).toFixed(1) + "s - " + (x.end_ms / 1000).toFixed(1) + "s").join(", ");
+            messageBox(Modal.Warning, qsTr("The horizon lock with gravity vectors is unreliable in %1 part(s) of the video (%2), the accelerometer data drifts from the camera motion.").arg(report.drifting.length).arg(ranges), [
+                { text: qsTr("Reduce the horizon lock there"), accent: true, clicked: () => controller.check_horizon_lock(true) },
+                { text: qsTr("Ignore") },
+            ]);
+        }
+        function onSmoothness_search_progress(progress: real) {
+            if (searchSmoothness.progress >= 0) searchSmoothness.progress = progress;
+        }
+        function onSmoothness_search_finished(result) {
+            searchSmoothness.progress = -1;
+            if (!result || !result.parameter) return;
+            if (result.reached) {
+                const el = root.getParamElement(result.parameter);
+                if (el) el.value = result.value;
+                messageBox(Modal.Info, qsTr("Found %1: %2, the crop is at most %3%.").arg(result.parameter).arg(result.value.toFixed(3)).arg(result.max_crop.toFixed(1)), [ { text: qsTr("Ok"), accent: true } ]);
+            } else {
+                messageBox(Modal.Warning, qsTr("Even the lowest smoothness needs to crop %1%, which is more than the requested %2%.").arg(result.max_crop.toFixed(1)).arg(result.target_crop.toFixed(0)), [ { text: qsTr("Ok"), accent: true } ]);
+            }
         }
         function onRolling_shutter_estimated(rolling_shutter: real) {
             root.setFrameReadoutTime(rolling_shutter);
@@ -212,6 +271,7 @@ MenuItem {
         const lockAmount = horizonCb.checked? horizonSlider.value : 0.0;
         const roll = horizonCb.checked? horizonRollSlider.value : 0.0;
         controller.set_horizon_lock(lockAmount, roll);
+        controller.set_horizon_pitch_lock(horizonCb.checked? horizonPitchSlider.value : 0.0);
         controller.set_use_gravity_vectors(useGravityVectors.checked);
     }
 
@@ -293,6 +353,41 @@ MenuItem {
         visible: children.length > 0;
     }
 
+    LinkButton {
+        text: qsTr("Suggest settings for this clip");
+        anchors.horizontalCenter: parent.horizontalCenter;
+        enabled: controller.gyro_loaded;
+        tooltip: qsTr("Set the smoothness and zooming mode based on how shaky the footage is and how much the lens allows to crop.");
+        onClicked: controller.suggest_smoothing();
+    }
+    Label {
+        position: Label.LeftPosition;
+        text: qsTr("Max crop");
+        NumberField {
+            id: targetCrop;
+            width: parent.width;
+            unit: "%";
+            precision: 0;
+            value: 20;
+            from: 1;
+            to: 90;
+        }
+    }
+    LinkButton {
+        id: searchSmoothness;
+        property real progress: -1;
+        text: progress >= 0? qsTr("Searching... %1%").arg(Math.round(progress * 100)) : qsTr("Find the smoothness for this crop");
+        anchors.horizontalCenter: parent.horizontalCenter;
+        enabled: controller.gyro_loaded && progress < 0;
+        tooltip: qsTr("Find the highest smoothness for which the stabilized video never needs to crop more than the specified amount.");
+        onClicked: { progress = 0; controller.search_smoothness(targetCrop.value); }
+    }
+    CheckBox {
+        id: autoSuggestSmoothing;
+        text: qsTr("Suggest settings when motion data is loaded");
+        checked: false;
+    }
+
     Column {
         id: smoothingStatus;
         x: 5 * dpiScale;
@@ -300,6 +395,24 @@ MenuItem {
         visible: children.length > 0;
     }
 
+    Label {
+        text: qsTr("Stabilization amount");
+        SliderWithField {
+            id: stabilizationAmount;
+            from: 0.0;
+            to: 100.0;
+            value: 1.0;
+            unit: "%";
+            defaultValue: 100.0;
+            precision: 0;
+            width: parent.width;
+            keyframe: "StabilizationAmount";
+            scaler: 100.0;
+            field.tooltip: qsTr("Lower values keep some of the natural camera motion");
+            onValueChanged: Qt.callLater(() => { controller.stabilization_amount = value; });
+        }
+    }
+
     Column {
         id: horizonLock;
         x: 5 * dpiScale;
@@ -347,6 +460,23 @@ MenuItem {
                 onValueChanged: Qt.callLater(updateHorizonLock);
             }
         }
+        Label {
+            text: qsTr("Pitch lock amount");
+            width: parent.width;
+            spacing: 2 * dpiScale;
+            SliderWithField {
+                id: horizonPitchSlider;
+                defaultValue: 0;
+                to: 100;
+                width: parent.width;
+                unit: qsTr("%");
+                precision: 0;
+                value: 0;
+                keyframe: "LockHorizonPitchAmount";
+                field.tooltip: qsTr("Keeps the view direction level. Usually undesirable for FPV, where the camera is tilted on purpose");
+                onValueChanged: Qt.callLater(updateHorizonLock);
+            }
+        }
         CheckBox {
             id: useGravityVectors;
             text: qsTr("Use gravity vectors");
@@ -354,6 +484,13 @@ MenuItem {
             visible: controller.has_gravity_vectors;
             onCheckedChanged: Qt.callLater(updateHorizonLock);
         }
+        CheckBox {
+            id: horizonAutoFallback;
+            text: qsTr("Reduce the lock where gravity drifts");
+            checked: false;
+            visible: useGravityVectors.visible && useGravityVectors.checked;
+            tooltip: qsTr("After synchronization, the gravity vectors are compared with the camera motion detected by optical flow. Where they drift apart, the horizon lock amount is reduced automatically.");
+        }
 
         BasicText {
             width: parent.width;
@@ -422,6 +559,42 @@ MenuItem {
             onValueChanged: controller.adaptive_zoom = value;
         }
     }
+    CheckBoxWithContent {
+        id: limitZoom;
+        text: qsTr("Limit zoom");
+        visible: croppingMode.currentIndex == 1;
+        cb.onCheckedChanged: controller.max_zoom = cb.checked? maxZoom.value : 0.0;
+
+        SliderWithField {
+            id: maxZoom;
+            value: 2.0;
+            defaultValue: 2.0;
+            from: 1.0;
+            to: 5.0;
+            precision: 2;
+            unit: qsTr("x");
+            width: parent.width;
+            keyframe: "MaxZoom";
+            onValueChanged: controller.max_zoom = limitZoom.checked? value : 0.0;
+        }
+    }
+    Label {
+        text: qsTr("Border tolerance");
+        visible: croppingMode.currentIndex == 1;
+        SliderWithField {
+            id: borderTolerance;
+            value: 0;
+            defaultValue: 0;
+            from: 0;
+            to: 10;
+            precision: 1;
+            unit: qsTr("%");
+            width: parent.width;
+            keyframe: "ZoomBorderTolerance";
+            field.tooltip: qsTr("Allow small borders in the corners instead of zooming in more");
+            onValueChanged: controller.zoom_border_tolerance = value;
+        }
+    }
 
     Label {
         text: qsTr("Zooming center offset");
@@ -514,4 +687,83 @@ MenuItem {
         }
     }
 
+    Label {
+        position: Label.LeftPosition;
+        text: qsTr("Lens look");
+        ComboBox {
+            id: outputProjection;
+            model: [QT_TRANSLATE_NOOP("Popup", "Rectilinear"), QT_TRANSLATE_NOOP("Popup", "Fisheye"), QT_TRANSLATE_NOOP("Popup", "Superview")];
+            font.pixelSize: 12 * dpiScale;
+            width: parent.width;
+            currentIndex: 0;
+            onCurrentIndexChanged: controller.output_projection = currentIndex;
+        }
+    }
+    Label {
+        text: qsTr("Lens look strength");
+        visible: outputProjection.currentIndex > 0;
+        SliderWithField {
+            id: outputProjectionStrength;
+            from: 0.0;
+            to: 100.0;
+            value: 1.0;
+            unit: "%";
+            defaultValue: 100.0;
+            precision: 0;
+            width: parent.width;
+            keyframe: "OutputProjectionStrength";
+            scaler: 100.0;
+            onValueChanged: Qt.callLater(() => { controller.output_projection_strength = value; });
+        }
+    }
+
+    Button {
+        id: analyzeClip;
+        text: qsTr("Analyze clip");
+        iconName: "spinner";
+        anchors.horizontalCenter: parent.horizontalCenter;
+        enabled: controller.gyro_loaded && !analyzing;
+        property bool analyzing: false;
+        tooltip: qsTr("Calculate the required zoom, frames with visible borders and the camera motion statistics for the trimmed range, without rendering.");
+        onClicked: {
+            analyzing = true;
+            controller.analyze_clip();
+        }
+    }
+    Connections {
+        target: controller;
+        function onClip_analyzed(report) {
+            analyzeClip.analyzing = false;
+            if (!report || !report.hasOwnProperty("analyzed_frames")) return;
+
+            const deg = (v) => (+v).toFixed(1) + " " + qsTr("°/s");
+            let borders = qsTr("None");
+            if (report.border_frame_count > 0) {
+                const ranges = report.border_frames.slice(0, 5).map(r => r[0] == r[1]? r[0] : (r[0] + "-" + r[1]));
+                borders = qsTr("%1 frames").arg(report.border_frame_count) + " (" + ranges.join(", ") + (report.border_frames.length > 5? ", ..." : "") + ")";
+            }
+            analysisReport.model = {
+                "Analyzed frames":      "" + report.analyzed_frames,
+                "Max required zoom":    (report.max_required_zoom * 100).toFixed(1) + "% (" + qsTr("frame %1").arg(report.max_required_zoom_frame) + ")",
+                "Visible borders":      borders,
+                "Avg angular velocity": deg(report.avg_angular_velocity),
+                "Max angular velocity": deg(report.max_angular_velocity),
+                "Vibration (X/Y/Z)":    report.vibration? report.vibration.map(x => (+x).toFixed(2)).join(" / ") + " " + qsTr("°/s") : "---"
+            };
+            analysisReport.visible = true;
+        }
+    }
+    TableList {
+        id: analysisReport;
+        visible: false;
+
+        Component.onCompleted: {
+            QT_TRANSLATE_NOOP("TableList", "Analyzed frames"),
+            QT_TRANSLATE_NOOP("TableList", "Max required zoom"),
+            QT_TRANSLATE_NOOP("TableList", "Visible borders"),
+            QT_TRANSLATE_NOOP("TableList", "Avg angular velocity"),
+            QT_TRANSLATE_NOOP("TableList", "Max angular velocity"),
+            QT_TRANSLATE_NOOP("TableList", "Vibration (X/Y/Z)")
+        }
+    }
 }
@@ -22,6 +22,9 @@ MenuItem {
         property alias sync_lpf: lpf.value;
         property alias checkNegativeInitialOffset: checkNegativeInitialOffset.checked;
         property alias experimentalAutoSyncPoints: experimentalAutoSyncPoints.checked;
+        property alias syncAfterLoad: syncAfterLoad.checked;
+        property alias rejectOutliers: rejectOutliers.checked;
+        property alias fitLinear: fitLinear.checked;
         // property alias syncMethod: syncMethod.currentIndex;
         // property alias offsetMethod: offsetMethod.currentIndex;
         property alias showFeatures: showFeatures.checked;
@@ -47,6 +50,8 @@ MenuItem {
             if (o.hasOwnProperty("of_method"))          syncMethod.currentIndex             = +o.of_method;
             if (o.hasOwnProperty("offset_method"))      offsetMethod.currentIndex           = +o.offset_method;
             if (o.hasOwnProperty("custom_sync_timestamps")) sync.customSyncTimestamps       = o.custom_sync_timestamps;
+            if (o.hasOwnProperty("reject_outliers"))    rejectOutliers.checked              = !!o.reject_outliers;
+            if (o.hasOwnProperty("fit_linear"))         fitLinear.checked                   = !!o.fit_linear;
             if (o.hasOwnProperty("auto_sync_points")) experimentalAutoSyncPoints.checked    = !!o.experimental_auto_sync_points;
             if (o.hasOwnProperty("do_autosync") && o.do_autosync) autosyncTimer.doRun = true;
         }
@@ -55,11 +60,39 @@ MenuItem {
         id: autosyncTimer;
         interval: 200;
         property bool doRun: false;
+        property bool staged: false;
         running: controller.lens_loaded && controller.gyro_loaded && !window.isDialogOpened && doRun && render_queue.editing_job_id == 0;
         onTriggered: {
             doRun = false;
-            if (controller.offsets_model.rowCount() == 0)
-                autosync.doSync();
+            const stagedRun = staged;
+            staged = false;
+            if (controller.offsets_model.rowCount() == 0) {
+                if (stagedRun)
+                    autosync.doSync("synchronize_staged", true);
+                else
+                    autosync.doSync();
+            }
+        }
+    }
+    Connections {
+        target: controller;
+        function onTelemetry_loaded(is_main_video: bool, filename: string, camera: string, imu_orientation: string, contains_gyro: bool, contains_raw_gyro: bool, contains_quats: bool, frame_readout_time: real, camera_id_json: string, sample_rate: real) {
+            if (is_main_video && contains_gyro && syncAfterLoad.checked && !autosyncTimer.doRun) {
+                autosyncTimer.staged = true;
+                autosyncTimer.doRun = true;
+            }
+        }
+        function onSync_points_rejected(count: int, rejected_json: string) {
+            rejectedInfo.text = count > 0? qsTr("%n sync point(s) deviated too much from the others and were not applied:", "", count) + "\n" + JSON.parse(rejected_json).map(x => qsTr("%1: %2 ms").arg(window.videoArea.timeline.timeAtPosition(x.timestamp / window.videoArea.timeline.durationMs)).arg(x.offset.toFixed(3))).join("\n") : "";
+        }
+        function onSync_results_staged(count: int, offsets_json: string) {
+            if (count == 0) return controller.discard_staged_sync();
+
+            const list = JSON.parse(offsets_json).map(x => qsTr("%1: %2 ms").arg(window.videoArea.timeline.timeAtPosition(x.timestamp / window.videoArea.timeline.durationMs)).arg(x.offset.toFixed(3)));
+            messageBox(Modal.Question, qsTr("Automatic synchronization found %n sync point(s):", "", count) + "\n" + list.join("\n") + "\n\n" + qsTr("Do you want to apply them?"), [
+                { text: qsTr("Apply"), accent: true, clicked: () => controller.apply_staged_sync() },
+                { text: qsTr("Discard"), clicked: () => controller.discard_staged_sync() },
+            ]);
         }
     }
     function getSettings() {
@@ -74,6 +107,8 @@ MenuItem {
             "of_method":          syncMethod.currentIndex,
             "offset_method":      offsetMethod.currentIndex,
             "auto_sync_points":   experimentalAutoSyncPoints.checked,
+            "reject_outliers":    rejectOutliers.checked,
+            "fit_linear":         fitLinear.checked,
         };
     }
     function getSettingsJson() { return JSON.stringify(getSettings()); }
@@ -85,11 +120,11 @@ MenuItem {
         anchors.horizontalCenter: parent.horizontalCenter;
         enabled: controller.gyro_loaded;
         tooltip: !enabled? qsTr("No motion data loaded, cannot sync.") : "";
-        function doSync() {
+        function doSync(mode, optimalPoints) {
             const maxPoints = maxSyncPoints.value;
             let sync_points = null;
 
-            if (experimentalAutoSyncPoints.checked) {
+            if (experimentalAutoSyncPoints.checked || optimalPoints) {
                 sync_points = controller.get_optimal_sync_points(maxPoints);
             }
             if (!sync_points) {
@@ -102,13 +137,13 @@ MenuItem {
                     const pos = start + (i*chunks);
                     ranges.push(pos);
                 }
-                if (sync.customSyncTimestamps.length > 0) {
+                if (sync.customSyncTimestamps.length > 0 && !optimalPoints) {
                     const duration = window.videoArea.timeline.durationMs;
                     ranges = sync.customSyncTimestamps.filter(v => v <= duration).map(v => v / duration);
                 }
                 sync_points = ranges.join(";");
             }
-            controller.start_autosync(sync_points, sync.getSettingsJson(), "synchronize");
+            controller.start_autosync(sync_points, sync.getSettingsJson(), mode || "synchronize");
         }
         onClicked: {
             if (!controller.lens_loaded) {
@@ -134,6 +169,12 @@ MenuItem {
         }
     }
 
+    InfoMessageSmall {
+        id: rejectedInfo;
+        type: InfoMessage.Warning;
+        show: text.length > 0;
+    }
+
     InfoMessageSmall {
         property bool usesQuats: window.motionData.hasQuaternions && window.motionData.integrationMethod === 0 && window.motionData.filename == window.vidInfo.filename;
         show: usesQuats && controller.offsets_model.rowCount() > 0;
@@ -205,6 +246,25 @@ MenuItem {
     }
 
     AdvancedSection {
+        CheckBox {
+            id: syncAfterLoad;
+            text: qsTr("Sync automatically after loading motion data");
+            checked: false;
+            tooltip: qsTr("Find the best sync points within the trim range and synchronize right after the motion data is loaded.\nThe results are applied only after you confirm them.");
+        }
+        CheckBox {
+            id: rejectOutliers;
+            text: qsTr("Reject outlier sync points");
+            checked: true;
+            tooltip: qsTr("Don't apply sync points whose offset is very different from the other points.");
+        }
+        CheckBox {
+            id: fitLinear;
+            text: qsTr("Allow linear drift of the offset");
+            checked: false;
+            enabled: rejectOutliers.checked;
+            tooltip: qsTr("Compare the sync points to a linear trend instead of a constant offset.\nUse when the camera clock drifts over time.");
+        }
         Label {
             position: Label.LeftPosition;
             text: qsTr("Analyze every n-th frame");
@@ -18,6 +18,10 @@ MenuItem {
     property string filename: "";
     property bool isCalibrator: false;
     property string pixelFormat: "";
+    property int videoWidth: 0;
+    property int videoHeight: 0;
+    property int filteredWidth: 0; // Size after the video filter
+    property int filteredHeight: 0;
 
     Component.onCompleted: {
         const fields = [
@@ -43,6 +47,9 @@ MenuItem {
         const framerate = +md["stream.video[0].codec.frame_rate"] || 0;
         const w = md["stream.video[0].codec.width"] || 0;
         const h = md["stream.video[0].codec.height"] || 0;
+        const par = controller.get_pixel_aspect_ratio();
+        const hasPar = Math.abs(par - 1.0) > 0.0001;
+        const displayW = hasPar? Math.round(w * par / 2) * 2 : w; // Output size with square pixels
         const bitrate = +md["stream.video[0].codec.bit_rate"]? ((+md["stream.video[0].codec.bit_rate"] / 1024 / 1024)) : 200;
 
         if (window) {
@@ -56,10 +63,20 @@ MenuItem {
         }
 
         root.pixelFormat = getPixelFormat(md) || "---";
+        root.videoWidth = w;
+        root.videoHeight = h;
+        // Detected in the background after the video is loaded, unless the project being loaded has its own
+        const project = window.videoArea.pendingGyroflowData;
+        if (!project || !project.video_info || !project.video_info.hasOwnProperty("input_crop")) {
+            root.setInputCrop(null);
+        }
+        root.filteredWidth = 0;
+        root.filteredHeight = 0;
+        root.setVideoFilter("");
 
         root.videoRotation = (360 - (md["stream.video[0].rotation"] || 0)) % 360; // Constrain to 0-360
 
-        list.model["Dimensions"]   = w && h? w + "x" + h : "---";
+        list.model["Dimensions"]   = w && h? w + "x" + h + (hasPar? " (" + qsTr("pixel aspect ratio %1").arg(par.toFixed(3)) + ")" : "") : "---";
         list.model["Duration"]     = getDuration(md) || "---";
         list.model["Frame rate"]   = framerate? framerate.toFixed(3) + " fps" : "---";
         list.model["Codec"]        = getCodec(md) || "---";
@@ -73,7 +90,37 @@ MenuItem {
 
         controller.set_video_rotation(root.videoRotation)
 
-        Qt.callLater(window.exportSettings.videoInfoLoaded, w, h, bitrate);
+        Qt.callLater(window.exportSettings.videoInfoLoaded, displayW, h, bitrate);
+    }
+    // `crop` is normalized [x, y, width, height] of the image area, or null
+    function setInputCrop(crop) {
+        inputCrop.ignoreUpdate = true;
+        if (crop && crop.length == 4 && root.videoWidth > 0 && root.videoHeight > 0) {
+            cropLeft.value   = Math.round(crop[0] * root.videoWidth);
+            cropTop.value    = Math.round(crop[1] * root.videoHeight);
+            cropRight.value  = Math.round((1.0 - crop[0] - crop[2]) * root.videoWidth);
+            cropBottom.value = Math.round((1.0 - crop[1] - crop[3]) * root.videoHeight);
+            inputCrop.checked = true;
+        } else {
+            inputCrop.checked = false;
+        }
+        inputCrop.ignoreUpdate = false;
+        inputCrop.update();
+    }
+    function setVideoFilter(filter: string) {
+        videoFilter.text = filter;
+        applyVideoFilter(filter);
+    }
+    // Filters can change the frame size, which is then the size of the video
+    function applyVideoFilter(filter: string) {
+        const size = controller.set_video_filter(filter, window.videoArea.vid);
+        const prevWidth  = root.filteredWidth  || root.videoWidth;
+        const prevHeight = root.filteredHeight || root.videoHeight;
+        if (size.length == 2 && size[0] > 0 && size[1] > 0) {
+            root.filteredWidth = size[0];
+            root.filteredHeight = size[1];
+            if (size[0] != prevWidth || size[1] != prevHeight) window.exportSettings.videoSizeChanged(size[0], size[1]);
+        }
     }
     function updateEntry(key: string, value: string) {
         if (key == "File name") root.filename = value;
@@ -121,6 +168,17 @@ MenuItem {
         function onRealtime_fps_loaded(fps: real) {
             root.updateEntryWithTrigger("Frame rate", fps);
         }
+        function onOrientation_auto_applied(video_rotation: real, camera_orientation: real) {
+            root.videoRotation = video_rotation;
+            root.updateEntry("Rotation", root.videoRotation + " °");
+        }
+        function onInput_crop_detected(x: real, y: real, w: real, h: real) {
+            // Don't override the crop set by the user or loaded from a project file
+            if (!inputCrop.checked) {
+                root.setInputCrop([x, y, w, h]);
+                messageBox(Modal.Info, qsTr("Black bars were detected in the video, lens correction will only use the image area.\nYou can adjust it in the \"Input crop\" section."), [ { text: qsTr("Ok") } ]);
+            }
+        }
     }
 
     Button {
@@ -165,6 +223,80 @@ MenuItem {
         });
     }
 
+    CheckBoxWithContent {
+        id: inputCrop;
+        visible: root.videoWidth > 0;
+        text: qsTr("Input crop");
+        cb.tooltip: qsTr("Use when the video contains black bars, so the lens correction only uses the image area");
+        property bool ignoreUpdate: false;
+
+        function update() {
+            if (ignoreUpdate) return;
+            const w = root.videoWidth, h = root.videoHeight;
+            if (checked && w > 0 && h > 0) {
+                const cw = w - cropLeft.value - cropRight.value;
+                const ch = h - cropTop.value - cropBottom.value;
+                if (cw > 0 && ch > 0) {
+                    controller.set_input_crop(cropLeft.value / w, cropTop.value / h, cw / w, ch / h);
+                    return;
+                }
+            }
+            controller.set_input_crop(0, 0, 0, 0);
+        }
+        cb.onCheckedChanged: update();
+
+        Flow {
+            width: parent.width;
+            spacing: 5 * dpiScale;
+            Label {
+                position: Label.LeftPosition;
+                text: qsTr("Left");
+                width: undefined;
+                inner.width: 70 * dpiScale;
+                spacing: 5 * dpiScale;
+                NumberField { id: cropLeft; unit: qsTr("px"); precision: 0; value: 0; from: 0; intNoThousandSep: true; width: 70 * dpiScale; onValueChanged: inputCrop.update(); }
+            }
+            Label {
+                position: Label.LeftPosition;
+                text: qsTr("Right");
+                width: undefined;
+                inner.width: 70 * dpiScale;
+                spacing: 5 * dpiScale;
+                NumberField { id: cropRight; unit: qsTr("px"); precision: 0; value: 0; from: 0; intNoThousandSep: true; width: 70 * dpiScale; onValueChanged: inputCrop.update(); }
+            }
+            Label {
+                position: Label.LeftPosition;
+                text: qsTr("Top");
+                width: undefined;
+                inner.width: 70 * dpiScale;
+                spacing: 5 * dpiScale;
+                NumberField { id: cropTop; unit: qsTr("px"); precision: 0; value: 0; from: 0; intNoThousandSep: true; width: 70 * dpiScale; onValueChanged: inputCrop.update(); }
+            }
+            Label {
+                position: Label.LeftPosition;
+                text: qsTr("Bottom");
+                width: undefined;
+                inner.width: 70 * dpiScale;
+                spacing: 5 * dpiScale;
+                NumberField { id: cropBottom; unit: qsTr("px"); precision: 0; value: 0; from: 0; intNoThousandSep: true; width: 70 * dpiScale; onValueChanged: inputCrop.update(); }
+            }
+        }
+    }
+
+    Label {
+        position: Label.TopPosition;
+        text: qsTr("Video filter (ffmpeg)");
+        visible: root.videoWidth > 0 && !isCalibrator;
+
+        TextField {
+            id: videoFilter;
+            width: parent.width;
+            placeholderText: "hqdn3d=4,eq=gamma=1.1";
+            tooltip: qsTr("ffmpeg filters applied to the video before stabilization, also in the preview and synchronization. Sizes in pixels refer to the original video.");
+            onEditingFinished: root.applyVideoFilter(text);
+        }
+    }
+
     DropTarget {
         parent: root.innerItem;
         color: styleBackground2;
@@ -50,9 +50,16 @@ pub fn path_to_url(path: QString) -> QUrl {
 }
 pub fn url_to_path(url: QUrl) -> String {
     let path = cpp!(unsafe [url as "QUrl"] -> QString as "QString" {
+        // Content URIs (Android SAF) are passed as is and opened through `core::filesystem`
+        if (url.scheme() == "content") {
+            return url.toString();
+        }
         return url.toLocalFile();
     });
-    path.to_string()
+    let path = path.to_string();
+    #[cfg(any(target_os = "ios", target_os = "macos"))]
+    store_security_bookmark(&path);
+    path
 }
 
 pub fn qt_queued_callback<T: QObject + 'static, T2: Send, F: FnMut(&T, T2) + 'static>(qobj: &T, mut cb: F) -> impl Fn(T2) + Send + Sync + Clone {
@@ -78,18 +85,18 @@ pub fn qt_queued_callback_mut<T: QObject + 'static, T2: Send, F: FnMut(&mut T, T
 macro_rules! wrap_simple_method {
     ($name:ident, $($param:ident:$type:ty),*) => {
         fn $name(&self, $($param:$type,)*) {
-            self.stabilizer.$name($($param,)*);
+            self.stabilizer.record_change(stringify!($name), &[$(format!("{:?}", $param)),*], || self.stabilizer.$name($($param,)*));
         }
     };
     ($name:ident, $($param:ident:$type:ty),*; recompute) => {
         fn $name(&self, $($param:$type,)*) {
-            self.stabilizer.$name($($param,)*);
+            self.stabilizer.record_change(stringify!($name), &[$(format!("{:?}", $param)),*], || self.stabilizer.$name($($param,)*));
             self.request_recompute();
         }
     };
     ($name:ident, $($param:ident:$type:ty),*; recompute$(; $extra_call:ident)*) => {
         fn $name(&mut self, $($param:$type,)*) {
-            self.stabilizer.$name($($param,)*);
+            self.stabilizer.record_change(stringify!($name), &[$(format!("{:?}", $param)),*], || self.stabilizer.$name($($param,)*));
             self.request_recompute();
             $( self.$extra_call(); )*
         }
@@ -99,11 +106,23 @@ macro_rules! wrap_simple_method {
 cpp! {{
     #ifdef Q_OS_ANDROID
     #   include <QJniObject>
+    #   include <QJniEnvironment>
+    #endif
+    #if defined(Q_OS_IOS) || defined(Q_OS_MACOS)
+    #   include <CoreFoundation/CoreFoundation.h>
+    #endif
+    #ifdef Q_OS_MACOS
+    #   define BOOKMARK_CREATION_OPTIONS   kCFURLBookmarkCreationWithSecurityScope
+    #   define BOOKMARK_RESOLUTION_OPTIONS kCFURLBookmarkResolutionWithSecurityScope
+    #else
+    #   define BOOKMARK_CREATION_OPTIONS   0
+    #   define BOOKMARK_RESOLUTION_OPTIONS 0
     #endif
     #include <QDesktopServices>
     #include <QStandardPaths>
     #include <QBuffer>
     #include <QImage>
+    #include <QPainter>
     #include <QSettings>
 }}
 pub fn resolve_android_url(url: QString) -> QString {
@@ -130,6 +149,114 @@ pub fn resolve_android_url(url: QString) -> QString {
     })
 }
 
+// Opens a content:// URI through the ContentResolver and returns a detached file descriptor, which is then owned by the caller
+pub fn open_android_content_uri(uri: &str, mode: &str) -> Option<i32> {
+    let uri = QString::from(uri);
+    let mode = QString::from(mode);
+    let fd = cpp!(unsafe [uri as "QString", mode as "QString"] -> i32 as "int" {
+        #ifdef Q_OS_ANDROID
+            return QNativeInterface::QAndroidApplication::runOnAndroidMainThread([uri, mode] {
+                QJniEnvironment env;
+                QJniObject jniUri = QJniObject::callStaticObjectMethod("android/net/Uri", "parse", "(Ljava/lang/String;)Landroid/net/Uri;", QJniObject::fromString(uri).object());
+
+                QJniObject activity(QNativeInterface::QAndroidApplication::context());
+                QJniObject resolver = activity.callObjectMethod("getContentResolver", "()Landroid/content/ContentResolver;");
+                QJniObject pfd = resolver.callObjectMethod("openFileDescriptor",
+                    "(Landroid/net/Uri;Ljava/lang/String;)Landroid/os/ParcelFileDescriptor;",
+                    jniUri.object(), QJniObject::fromString(mode).object()
+                );
+                if (env.checkAndClearExceptions() || !pfd.isValid()) {
+                    return QVariant::fromValue(-1);
+                }
+                return QVariant::fromValue(pfd.callMethod<jint>("detachFd", "()I"));
+            }).result().toInt();
+        #else
+            return -1;
+        #endif
+    });
+    if fd >= 0 { Some(fd) } else { None }
+}
+
+// Saves a security-scoped bookmark of a file or folder picked by the user, so it can still be accessed after a restart (iOS and sandboxed macOS).
+// It has to be created while the app has the access granted by the file dialog
+pub fn store_security_bookmark(path: &str) {
+    let path = QString::from(path);
+    cpp!(unsafe [path as "QString"] {
+        #if defined(Q_OS_IOS) || defined(Q_OS_MACOS)
+            if (path.isEmpty()) return;
+            QSettings settings;
+            QVariantMap bookmarks = settings.value("securityBookmarks").toMap();
+            if (bookmarks.contains(path)) {
+                // Only replace the saved bookmark when it no longer resolves or is stale
+                CFDataRef saved = bookmarks.value(path).toByteArray().toCFData();
+                Boolean stale = false;
+                CFURLRef resolved = CFURLCreateByResolvingBookmarkData(kCFAllocatorDefault, saved, BOOKMARK_RESOLUTION_OPTIONS, NULL, NULL, &stale, NULL);
+                CFRelease(saved);
+                if (resolved) CFRelease(resolved);
+                if (resolved && !stale) return;
+            }
+
+            QByteArray bytes = path.toUtf8();
+            CFURLRef url = CFURLCreateFromFileSystemRepresentation(kCFAllocatorDefault, (const UInt8 *)bytes.constData(), bytes.size(), false);
+            if (!url) return;
+            CFDataRef data = CFURLCreateBookmarkData(kCFAllocatorDefault, url, BOOKMARK_CREATION_OPTIONS, NULL, NULL, NULL);
+            CFRelease(url);
+            if (!data) return;
+            bookmarks.insert(path, QByteArray::fromCFData(data));
+            CFRelease(data);
+            settings.setValue("securityBookmarks", bookmarks);
+        #endif
+    });
+}
+
+// Starts or stops accessing a security-scoped file or folder (iOS and sandboxed macOS).
+// The scope comes from the saved bookmark of the file or of the closest folder containing it, see `store_security_bookmark`
+pub fn security_scoped_access(path: &str, start: bool) -> bool {
+    let path = QString::from(path);
+    cpp!(unsafe [path as "QString", start as "bool"] -> bool as "bool" {
+        #if defined(Q_OS_IOS) || defined(Q_OS_MACOS)
+            CFURLRef url = NULL;
+            QVariantMap bookmarks = QSettings().value("securityBookmarks").toMap();
+            QString key = path;
+            Boolean stale = false;
+            while (!key.isEmpty() && !bookmarks.contains(key)) {
+                int i = key.lastIndexOf('/');
+                key = i > 0 ? key.left(i) : QString();
+            }
+            if (!key.isEmpty()) {
+                CFDataRef data = bookmarks.value(key).toByteArray().toCFData();
+                url = CFURLCreateByResolvingBookmarkData(kCFAllocatorDefault, data, BOOKMARK_RESOLUTION_OPTIONS, NULL, NULL, &stale, NULL);
+                CFRelease(data);
+            }
+            bool refresh = url && stale && start;
+            if (!url) {
+                // No bookmark, the access can still be granted for the current session
+                QByteArray bytes = path.toUtf8();
+                url = CFURLCreateFromFileSystemRepresentation(kCFAllocatorDefault, (const UInt8 *)bytes.constData(), bytes.size(), false);
+            }
+            if (!url) return false;
+            bool ok = true;
+            if (start) {
+                ok = CFURLStartAccessingSecurityScopedResource(url);
+                if (ok && refresh) {
+                    // The file was moved or the bookmark is outdated, save a new one while the access is granted
+                    if (CFDataRef fresh = CFURLCreateBookmarkData(kCFAllocatorDefault, url, BOOKMARK_CREATION_OPTIONS, NULL, NULL, NULL)) {
+                        bookmarks.insert(key, QByteArray::fromCFData(fresh));
+                        QSettings().setValue("securityBookmarks", bookmarks);
+                        CFRelease(fresh);
+                    }
+                }
+            } else {
+                CFURLStopAccessingSecurityScopedResource(url);
+            }
+            CFRelease(url);
+            return ok;
+        #else
+            return false;
+        #endif
+    })
+}
+
 pub fn open_file_externally(path: QString) {
     cpp!(unsafe [path as "QString"] { QDesktopServices::openUrl(QUrl::fromLocalFile(path)); });
 }
@@ -192,11 +319,10 @@ pub fn init_logging() {
 }
 
 pub fn install_crash_handler() -> std::io::Result<()> {
-    let cur_dir = std::env::current_dir()?;
-    let os_str = cur_dir.as_os_str();
-
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     {
+        let cur_dir = std::env::current_dir()?;
+        let os_str = cur_dir.as_os_str();
         let path: Vec<breakpad_sys::PathChar> = {
             #[cfg(windows)]
             {
@@ -240,7 +366,13 @@ pub fn install_crash_handler() -> std::io::Result<()> {
         }
     }
 
-    // Upload crash dumps
+    Ok(())
+}
+
+// Dumps are kept on disk in offline mode and uploaded on the next start when online
+pub fn upload_crash_dumps() {
+    if is_offline() { return; }
+    let cur_dir = match std::env::current_dir() { Ok(x) => x, Err(_) => return };
     crate::core::run_threaded(move || {
         if let Ok(files) = std::fs::read_dir(cur_dir) {
             for path in files.flatten() {
@@ -256,7 +388,27 @@ pub fn install_crash_handler() -> std::io::Result<()> {
             }
         }
     });
-    Ok(())
+}
+
+pub fn is_offline() -> bool {
+    get_setting("offline") == "true"
+}
+
+// Compiled features, installed SDKs, GPU backends and codecs. Also printed by the `--capabilities` command line switch
+pub fn get_capabilities() -> serde_json::Value {
+    serde_json::json!({
+        "app_version": get_version(),
+        "features": crate::core::compiled_features(),
+        "sdks": crate::external_sdk::installed(),
+        "gpu_backends": crate::core::gpu::available_backends(),
+        "low_memory": crate::core::low_memory::is_enabled(),
+        "rendering": crate::rendering::get_capabilities(),
+        "platform": {
+            "os": std::env::consts::OS,
+            "arch": std::env::consts::ARCH,
+            "mobile": cfg!(any(target_os = "android", target_os = "ios")),
+        },
+    })
 }
 
 #[cfg(target_os = "android")]
@@ -310,6 +462,19 @@ pub fn get_version() -> String {
 pub fn clear_settings() {
     cpp!(unsafe [] { QSettings().clear(); })
 }
+pub fn translate(context: &str, text: &str) -> String {
+    let context = QString::from(context);
+    let text = QString::from(text);
+    cpp!(unsafe [context as "QString", text as "QString"] -> QString as "QString" {
+        return QCoreApplication::translate(qUtf8Printable(context), qUtf8Printable(text));
+    }).to_string()
+}
+pub fn get_setting(key: &str) -> String {
+    let key = QString::from(key);
+    cpp!(unsafe [key as "QString"] -> QString as "QString" {
+        return QSettings().value(key).toString();
+    }).to_string()
+}
 
 pub fn image_data_to_base64(w: u32, h: u32, s: u32, data: &[u8]) -> QString {
     let ptr = data.as_ptr();
@@ -325,6 +490,58 @@ pub fn image_data_to_base64(w: u32, h: u32, s: u32, data: &[u8]) -> QString {
     })
 }
 
+// `data:` URL with the base64 encoded contents, eg. for playing a file kept in memory
+pub fn data_to_url(mime: &str, data: &[u8]) -> QString {
+    let mime = QString::from(mime);
+    let ptr = data.as_ptr();
+    let len = data.len();
+    cpp!(unsafe [mime as "QString", ptr as "const char *", len as "size_t"] -> QString as "QString" {
+        QString url = "data:" + mime + ";base64,";
+        url.append(QString::fromLatin1(QByteArray::fromRawData(ptr, len).toBase64()));
+        return url;
+    })
+}
+
+pub fn image_from_rgba(w: u32, h: u32, s: u32, data: &[u8]) -> QImage {
+    let ptr = data.as_ptr();
+    cpp!(unsafe [w as "uint32_t", h as "uint32_t", s as "uint32_t", ptr as "const uint8_t *"] -> QImage as "QImage" {
+        // Deep copy, because `data` is owned by Rust
+        return QImage(ptr, w, h, s, QImage::Format_RGBA8888).copy();
+    })
+}
+
+// Encodes RGBA pixels (8 or 16 bits per channel, native endian) to `format` ("png", "jpg", "tiff"...).
+// Non-empty `text` is burned in at the bottom of the image
+pub fn encode_image(w: u32, h: u32, s: u32, data: &[u8], is_16bit: bool, format: &str, text: &str) -> Vec<u8> {
+    let ptr = data.as_ptr();
+    let format = QString::from(format);
+    let text = QString::from(text);
+    let bytes = cpp!(unsafe [w as "uint32_t", h as "uint32_t", s as "uint32_t", ptr as "const uint8_t *", is_16bit as "bool", format as "QString", text as "QString"] -> QByteArray as "QByteArray" {
+        QImage img = QImage(ptr, w, h, s, is_16bit? QImage::Format_RGBA64 : QImage::Format_RGBA8888).copy();
+        if (!text.isEmpty()) {
+            QPainter p(&img);
+            QFont font = p.font();
+            font.setPixelSize(qMax(10, int(h / 40)));
+            p.setFont(font);
+            const int margin = font.pixelSize() / 2;
+            QRect rect = p.fontMetrics().boundingRect(QRect(0, 0, w - margin * 4, h), Qt::TextWordWrap, text);
+            rect.moveTo(margin, h - rect.height() - margin * 3);
+            p.fillRect(rect.adjusted(-margin, -margin, margin, margin), QColor(0, 0, 0, 160));
+            p.setPen(Qt::white);
+            p.drawText(rect, Qt::TextWordWrap, text);
+        }
+        if (format == "jpg" || format == "jpeg") {
+            img = img.convertToFormat(QImage::Format_RGB888);
+        }
+        QByteArray byteArray;
+        QBuffer buffer(&byteArray);
+        buffer.open(QIODevice::WriteOnly);
+        img.save(&buffer, qUtf8Printable(format), 95);
+        return byteArray;
+    });
+    bytes.to_slice().to_vec()
+}
+
 pub fn image_to_b64(img: QImage) -> QString {
     cpp!(unsafe [img as "QImage"] -> QString as "QString" {
         QByteArray byteArray;